@@ -3,13 +3,13 @@
 use polling::{Event, PollMode};
 use socket2::{SockAddr, Socket};
 use std::error::Error;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::io::Write;
 use std::os::fd::AsRawFd;
-use std::str::FromStr;
+use utils::network;
 
 use crate::clearingconnection::ClearingConnection;
 
-use super::genericclearingprotocol::{GenericClearingProtocol, ProcessError};
+use super::genericclearingprotocol::{GenericClearingProtocol, MarketUpdate, ProcessError};
 
 // an implementation of the clear clearing protocol
 pub struct ClearClearingConnection {
@@ -24,16 +24,13 @@ impl ClearingConnection for ClearClearingConnection {
         Self {
             connection: None,
             address: String::from(addr),
-            port: port,
+            port,
             protocol: proto,
         }
     }
 
     fn connect(&mut self) -> Result<(), Box<dyn Error>> {
-        let clearing_addr = &SockAddr::from(SocketAddr::V4(SocketAddrV4::new(
-            Ipv4Addr::from_str(self.address.as_str()).unwrap(),
-            self.port,
-        )));
+        let clearing_addr = &network::socket_address(&self.address, self.port)?;
         self.connection = Some(Socket::new(
             clearing_addr.domain(),
             socket2::Type::STREAM,
@@ -44,15 +41,26 @@ impl ClearingConnection for ClearClearingConnection {
             .as_ref()
             .expect("Connect: missing socket")
             .connect(clearing_addr)?;
+        let logon = self.protocol.as_ref().unwrap().prepare_logon();
+        if !logon.is_empty() {
+            self.connection.as_ref().unwrap().write_all(&logon)?;
+        }
 
         Ok(())
     }
 
+    fn reconnect(&mut self, poller: &polling::Poller) -> Result<(), Box<dyn Error>> {
+        if let Some(connection) = self.connection.take() {
+            // not registered if the previous attempt failed
+            let _ = poller.delete(&connection);
+        }
+        self.connect()?;
+        self.register_with_poller(poller)?;
+        Ok(())
+    }
+
     fn listen(&mut self) -> Result<(), Box<dyn Error>> {
-        let clearing_addr = &SockAddr::from(SocketAddr::V4(SocketAddrV4::new(
-            Ipv4Addr::from_str(self.address.as_str()).unwrap(),
-            self.port,
-        )));
+        let clearing_addr = &network::socket_address(&self.address, self.port)?;
         self.connection = Some(Socket::new(
             clearing_addr.domain(),
             socket2::Type::STREAM,
@@ -63,7 +71,7 @@ impl ClearingConnection for ClearClearingConnection {
         self.connection
             .as_ref()
             .expect("Listen: missing socket")
-            .bind(&clearing_addr)?;
+            .bind(clearing_addr)?;
         self.connection
             .as_ref()
             .expect("Listen: missing socket")
@@ -77,10 +85,10 @@ impl ClearingConnection for ClearClearingConnection {
     }
 
     fn register_with_poller(&mut self, poller: &polling::Poller) -> std::io::Result<()> {
-        if self.connection.is_some() {
+        if let Some(connection) = &self.connection {
             unsafe {
                 poller.add_with_mode(
-                    self.connection.as_ref().expect("Poller: missing socket"),
+                    connection,
                     Event::readable(self.get_socket_key()),
                     PollMode::Level,
                 )
@@ -109,6 +117,15 @@ impl ClearingConnection for ClearClearingConnection {
         }
     }
 
+    fn request_instrument(&self, instrument_id: u64) -> Result<usize, Box<dyn Error>> {
+        let message = self
+            .protocol
+            .as_ref()
+            .unwrap()
+            .prepare_instrument_request(instrument_id);
+        Ok(self.connection.as_ref().unwrap().send(&message)?)
+    }
+
     fn process(
         &mut self,
         buffer: &[u8],
@@ -125,15 +142,14 @@ impl ClearingConnection for ClearClearingConnection {
                 Ok((response, bytes)) => {
                     // if any, sends the response back on the provided socket
                     // otherwise use the generic connection
-                    if response.len() > 0 {
-                        if match response_socket {
+                    if !response.is_empty()
+                        && match response_socket {
                             Some(socket) => socket.send(&response),
                             None => self.connection.as_ref().unwrap().send(&response),
                         }
                         .is_err()
-                        {
-                            return Err(ProcessError::new("Error sending response"));
-                        }
+                    {
+                        return Err(ProcessError::new("Error sending response"));
                     }
                     if bytes == 0 {
                         // break on non-progress
@@ -150,9 +166,74 @@ impl ClearingConnection for ClearClearingConnection {
         &self.protocol
     }
 
+    fn set_session(&mut self, engine_id: Option<u8>) {
+        self.protocol.as_mut().unwrap().set_session(engine_id);
+    }
+
+    fn get_session(&self) -> Option<u8> {
+        self.protocol.as_ref().unwrap().get_session()
+    }
+
     fn add_instrument(&mut self, i: instruments::instrument::Instrument) {
         self.protocol.as_mut().unwrap().add_instrument(i);
     }
+
+    fn remove_instrument(&mut self, instrument_id: u64) {
+        self.protocol
+            .as_mut()
+            .unwrap()
+            .remove_instrument(instrument_id);
+    }
+
+    fn take_eod_summaries(&mut self) -> Vec<oep::eodsummary::EodSummary> {
+        self.protocol.as_mut().unwrap().take_eod_summaries()
+    }
+
+    fn take_trade_captures(&mut self) -> Vec<oep::tradecapture::TradeCapture> {
+        self.protocol.as_mut().unwrap().take_trade_captures()
+    }
+
+    fn take_market_updates(&mut self) -> Vec<MarketUpdate> {
+        self.protocol.as_mut().unwrap().take_market_updates()
+    }
+
+    fn take_snapshot_request(&mut self) -> bool {
+        self.protocol.as_mut().unwrap().take_snapshot_request()
+    }
+
+    fn send_trade_capture(
+        &mut self,
+        capture: oep::tradecapture::TradeCapture,
+    ) -> Result<(), Box<dyn Error>> {
+        let protocol = self.protocol.as_mut().unwrap();
+        let capture = protocol.sequence_trade_capture(capture);
+        let message = protocol.prepare_trade_capture(&capture);
+        self.connection
+            .as_ref()
+            .expect("Send: missing socket")
+            .write_all(&message)?;
+        Ok(())
+    }
+
+    fn resend_trade_captures(&mut self, timestamp: u64) -> Result<usize, Box<dyn Error>> {
+        let protocol = self.protocol.as_ref().unwrap();
+        let captures: Vec<_> = protocol
+            .unacked_trade_captures()
+            .into_iter()
+            .filter(|capture| capture.timestamp < timestamp)
+            .collect();
+        let message: Vec<u8> = captures
+            .iter()
+            .flat_map(|capture| protocol.prepare_trade_capture(capture))
+            .collect();
+        if !message.is_empty() {
+            self.connection
+                .as_ref()
+                .expect("Send: missing socket")
+                .write_all(&message)?;
+        }
+        Ok(captures.len())
+    }
 }
 
 impl std::io::Read for ClearClearingConnection {
@@ -1,13 +1,18 @@
 use instruments::instrument::Instrument;
+use oep::eodsummary::EodSummary;
+use oep::tradecapture::TradeCapture;
 use socket2::{SockAddr, Socket};
 use std::error::Error;
 use std::io;
 
-use super::genericclearingprotocol::{GenericClearingProtocol, ProcessError};
+use super::genericclearingprotocol::{GenericClearingProtocol, MarketUpdate, ProcessError};
 
 pub trait ClearingConnection: std::io::Read + std::io::Write {
     fn new(addr: &str, port: u16, proto: Option<Box<dyn GenericClearingProtocol>>) -> Self;
+    // connects and logs on, the logon going first
     fn connect(&mut self) -> Result<(), Box<dyn Error>>;
+    // drops the current connection, if any, and connects again
+    fn reconnect(&mut self, poller: &polling::Poller) -> Result<(), Box<dyn Error>>;
     fn listen(&mut self) -> Result<(), Box<dyn Error>>;
     fn accept(&self) -> io::Result<(Socket, SockAddr)>;
     fn register_with_poller(&mut self, poller: &polling::Poller) -> std::io::Result<()>;
@@ -20,6 +25,25 @@ pub trait ClearingConnection: std::io::Read + std::io::Write {
 
     // returns number of instruments added
     fn request_instruments(&self) -> Result<usize, Box<dyn Error>>;
+    // asks for the instrument @instrument_id alone, the clearing answering
+    // with a not found if it doesn't know it
+    fn request_instrument(&self, instrument_id: u64) -> Result<usize, Box<dyn Error>>;
     fn add_instrument(&mut self, i: Instrument);
+    fn remove_instrument(&mut self, instrument_id: u64);
+    fn take_eod_summaries(&mut self) -> Vec<EodSummary>;
+    fn take_trade_captures(&mut self) -> Vec<TradeCapture>;
+    // the updates left by a forwarding protocol for the markets living elsewhere
+    fn take_market_updates(&mut self) -> Vec<MarketUpdate>;
+    // whether the clearing asked for a snapshot since the last call
+    fn take_snapshot_request(&mut self) -> bool;
+    // sends @capture with the next sequence, keeping it until the clearing acks it
+    fn send_trade_capture(&mut self, capture: TradeCapture) -> Result<(), Box<dyn Error>>;
+    // sends again the unacked captures of the trades before @timestamp,
+    // returns how many were sent
+    fn resend_trade_captures(&mut self, timestamp: u64) -> Result<usize, Box<dyn Error>>;
     fn get_protocol(&self) -> &Option<Box<dyn GenericClearingProtocol>>;
+    // on the server side, the engine logged on the connection processed next
+    fn set_session(&mut self, engine_id: Option<u8>);
+    // on the server side, the engine logged on the connection processed last
+    fn get_session(&self) -> Option<u8>;
 }
@@ -1,37 +1,101 @@
 // The implementation of the "Clear" Protocol
 
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
 
-use crate::genericclearingprotocol::{GenericClearingProtocol, ProtocolSide};
+use crate::genericclearingprotocol::{GenericClearingProtocol, MarketUpdate, ProtocolSide};
+use crate::logon::{EngineCredentials, EngineSecrets};
 use disseminator::disseminator::Disseminator;
 use instruments::genericinstrumentlist::GenericInstrumentList;
-use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
-use market::Market;
+use instruments::instrument::{Instrument, InstrumentState, InstrumentType, INSTRUMENT_FIXED_SIZE};
+use instruments::partition::Partition;
+use instruments::price::MAX_PRICE_DECIMALS;
+use market::{
+    orderid::OrderIdGenerator, publish_segment_state, volatility::VolatilityConfig, Market,
+};
+use oep::decoder::Decoder;
+use oep::eodsummary::{EodSummary, EODSUMMARY_SIZE};
+use oep::statechange::StateChangeReason;
+use oep::tradecapture::{TradeCapture, TRADECAPTURE_SIZE};
+use order::Side;
 
 use super::genericclearingprotocol::ProcessError;
 
 // the clear clearing protocol
-const CLEAR_PROTOCOL_VERSION: u8 = 1;
+const CLEAR_PROTOCOL_VERSION: u8 = 9;
 
 const CLEAR_TYPE_HEARTBEAT: u16 = 0;
 const CLEAR_TYPE_INSTRUMENT_UPDATE: u16 = 1;
 const CLEAR_TYPE_INSTRUMENT_REQUEST: u16 = 2;
 const CLEAR_TYPE_ALL_INSTRUMENTS_REQUEST: u16 = 3;
+const CLEAR_TYPE_EOD_SUMMARY: u16 = 4;
+const CLEAR_TYPE_TRADE_CAPTURE: u16 = 5;
+const CLEAR_TYPE_EXPOSURE_UPDATE: u16 = 6;
+const CLEAR_TYPE_TRADE_CAPTURE_ACK: u16 = 7;
+const CLEAR_TYPE_SNAPSHOT_REQUEST: u16 = 8;
+const CLEAR_TYPE_INSTRUMENT_DELETION: u16 = 9;
+const CLEAR_TYPE_TRADE_BUST: u16 = 10;
+const CLEAR_TYPE_SEGMENT_STATE: u16 = 11;
+const CLEAR_TYPE_INSTRUMENT_NOT_FOUND: u16 = 12;
+const CLEAR_TYPE_LOGON: u16 = 13;
+
+// participant, book id and blocked side
+const EXPOSURE_UPDATE_SIZE: usize = 8 + 8 + 1;
+const NO_BLOCKED_SIDE: u8 = 2;
+// sequence of the last capture accounted for
+const TRADE_CAPTURE_ACK_SIZE: usize = 8;
+// instrument id
+const INSTRUMENT_DELETION_SIZE: usize = 8;
+const INSTRUMENT_NOT_FOUND_SIZE: usize = 8;
+// the engine id, followed by the secret
+const LOGON_FIXED_SIZE: usize = 1;
+// book id and trade id
+const TRADE_BUST_SIZE: usize = 8 + 8;
+// segment and state
+const SEGMENT_STATE_SIZE: usize = 2 + 1;
 
 // for now this is hashmap. I want to change that
 // key is instrument ID
-type MarketCollection = Rc<RefCell<HashMap<u64, Market>>>;
+type MarketCollection = Arc<Mutex<HashMap<u64, Market>>>;
+
+// where the instrument and exposure updates of the clearing end up
+enum Markets {
+    // applied to the markets of the engine, created here for the new instruments
+    Local {
+        markets: MarketCollection,
+        disseminator: Arc<Mutex<dyn Disseminator>>,
+        order_ids: Arc<Mutex<OrderIdGenerator>>,
+    },
+    // kept for take_market_updates
+    Forwarded(Vec<MarketUpdate>),
+}
 
-pub struct ClearProtocol<T: GenericInstrumentList<Item = Rc<RefCell<Instrument>>>> {
+pub struct ClearProtocol<T: GenericInstrumentList<Item = Arc<RwLock<Instrument>>>> {
     instrument_list: T,
     protocol_side: ProtocolSide,
-    markets: MarketCollection,
-    disseminator: Rc<RefCell<dyn Disseminator>>,
+    markets: Markets,
+    // applied to the markets created for the new instruments
+    volatility: VolatilityConfig,
+    // only the books of the partition get a market
+    partition: Partition,
+    eod_summaries: Vec<EodSummary>,
+    // received by the server side
+    trade_captures: Vec<TradeCapture>,
+    // sent by the client side, waiting for the ack of the server
+    unacked_captures: VecDeque<TradeCapture>,
+    // sequence of the last capture sent
+    capture_seq: u64,
+    // the clearing asked for a snapshot of the state of the engine
+    snapshot_requested: bool,
+    // what the client side logs on with
+    credentials: Option<EngineCredentials>,
+    // the engines the server side lets log on
+    engine_secrets: EngineSecrets,
+    // the engine logged on the connection being processed by the server side
+    session: Option<u8>,
 }
 
-impl<T: GenericInstrumentList<Item = Rc<RefCell<Instrument>>>> ClearProtocol<T> {
+impl<T: GenericInstrumentList<Item = Arc<RwLock<Instrument>>>> ClearProtocol<T> {
     /// Initializes a struct with a `None` value for the
     /// `instrument_update_upcall` field.
     ///
@@ -42,16 +106,66 @@ impl<T: GenericInstrumentList<Item = Rc<RefCell<Instrument>>>> ClearProtocol<T>
     pub fn new(
         instrument_list: T,
         markets: MarketCollection,
-        disseminator: Rc<RefCell<dyn Disseminator>>,
+        disseminator: Arc<Mutex<dyn Disseminator>>,
+        order_ids: Arc<Mutex<OrderIdGenerator>>,
     ) -> Self {
+        Self::with_markets(
+            instrument_list,
+            Markets::Local {
+                markets,
+                disseminator,
+                order_ids,
+            },
+        )
+    }
+
+    /// A protocol that doesn't keep any market: the updates of the instruments
+    /// of the partition and the exposure updates are left for
+    /// `take_market_updates`, e.g. to be applied by the shards of the engine
+    pub fn forwarding(instrument_list: T) -> Self {
+        Self::with_markets(instrument_list, Markets::Forwarded(vec![]))
+    }
+
+    fn with_markets(instrument_list: T, markets: Markets) -> Self {
         Self {
-            instrument_list: instrument_list,
+            instrument_list,
             protocol_side: ProtocolSide::Client,
-            markets: markets,
-            disseminator: disseminator,
+            markets,
+            volatility: VolatilityConfig::default(),
+            partition: Partition::default(),
+            eod_summaries: vec![],
+            trade_captures: vec![],
+            unacked_captures: VecDeque::new(),
+            capture_seq: 0,
+            snapshot_requested: false,
+            credentials: None,
+            engine_secrets: EngineSecrets::default(),
+            session: None,
         }
     }
 
+    /// The volatility interruption settings of the markets created from now on
+    pub fn set_volatility_config(&mut self, config: VolatilityConfig) {
+        self.volatility = config;
+    }
+
+    /// Limits the markets to the books of @partition, the other instruments
+    /// are only kept in the instrument list
+    pub fn set_partition(&mut self, partition: Partition) {
+        self.partition = partition;
+    }
+
+    /// What the client side logs on with, see `prepare_logon`
+    pub fn set_credentials(&mut self, credentials: EngineCredentials) {
+        self.credentials = Some(credentials);
+    }
+
+    /// The engines the server side lets log on, the other connections being
+    /// refused everything but the heartbeats
+    pub fn set_engine_secrets(&mut self, secrets: EngineSecrets) {
+        self.engine_secrets = secrets;
+    }
+
     /// The function `process_one_data_entry` processes a data entry in a buffer and
     /// returns the number of bytes processed or an error.
     ///
@@ -62,103 +176,368 @@ impl<T: GenericInstrumentList<Item = Rc<RefCell<Instrument>>>> ClearProtocol<T>
     /// Returns:
     ///
     /// The function `process_one_data_entry` returns a `Result<usize, ProcessError>`.
+    /// Nothing is processed while the entry is incomplete, and an entry shorter
+    /// than its type requires is an error.
     fn process_one_data_entry(&mut self, buffer: &[u8]) -> Result<(Vec<u8>, usize), ProcessError> {
-        let data_type =
-            u16::from_le_bytes(buffer[0..2].try_into().expect("Invalid data type slice"));
-        let data_len = u16::from_le_bytes(buffer[2..4].try_into().expect("Invalid data len slice"));
+        let Some(header) = buffer.get(0..4) else {
+            return Ok((vec![], 0));
+        };
+        let data_type = u16::from_le_bytes([header[0], header[1]]);
+        let data_len = u16::from_le_bytes([header[2], header[3]]) as usize;
         let processed: usize = 4;
-        if usize::from(data_len) + processed > buffer.len() {
+        let Some(entry) = buffer.get(processed..processed + data_len) else {
+            // too short, will process later
             return Ok((vec![], 0));
+        };
+        let entry_len = processed + data_len;
+        if self.protocol_side == ProtocolSide::Server
+            && self.session.is_none()
+            && data_type != CLEAR_TYPE_HEARTBEAT
+            && data_type != CLEAR_TYPE_LOGON
+        {
+            return Err(ProcessError::new("Not logged on"));
         }
         match data_type {
-            CLEAR_TYPE_HEARTBEAT => Ok((vec![], processed)),
+            CLEAR_TYPE_HEARTBEAT => Ok((vec![], entry_len)),
+            CLEAR_TYPE_LOGON => {
+                let fixed = fixed_size::<LOGON_FIXED_SIZE>(entry, "logon")?;
+                if self.protocol_side == ProtocolSide::Server {
+                    if self.session.is_some() {
+                        return Err(ProcessError::new("Already logged on"));
+                    }
+                    let engine_id = fixed[0];
+                    if !self
+                        .engine_secrets
+                        .check(engine_id, &entry[LOGON_FIXED_SIZE..])
+                    {
+                        return Err(ProcessError::new(&format!(
+                            "Invalid logon of engine {engine_id}"
+                        )));
+                    }
+                    self.session = Some(engine_id);
+                }
+                Ok((vec![], entry_len))
+            }
             CLEAR_TYPE_INSTRUMENT_UPDATE => {
-                if processed + data_len as usize > buffer.len() {
-                    Ok((vec![], 0)) // too short, will process later
-                } else {
-                    let instrument_id = u64::from_le_bytes(
-                        buffer[4..12].try_into().expect("Invalid instrument ID"),
+                let fixed = fixed_size::<INSTRUMENT_FIXED_SIZE>(entry, "instrument update")?;
+                let instrument_id = read_u64(&fixed[0..8]);
+                let instrument_type = match fixed[8] {
+                    0 => InstrumentType::Share,
+                    1 => InstrumentType::OptionCall,
+                    2 => InstrumentType::OptionPut,
+                    3 => InstrumentType::Future,
+                    4 => InstrumentType::Warrant,
+                    _ => InstrumentType::Share,
+                };
+                let instrument_state = match fixed[9] {
+                    0 => InstrumentState::Trading,
+                    1 => InstrumentState::Closed,
+                    2 => InstrumentState::Auction,
+                    3 => InstrumentState::Halted,
+                    4 => InstrumentState::PreOpen,
+                    _ => InstrumentState::Closed,
+                };
+
+                if self.protocol_side == ProtocolSide::Client {
+                    // update the specific instrument
+                    let percentage_bands = fixed[10];
+                    let percentage_variation_allowed = fixed[11];
+                    // a zero tick or lot means no constraint
+                    let tick_size = read_u64(&fixed[12..20]).max(1);
+                    let round_lot = read_u64(&fixed[20..28]).max(1);
+                    let previous_close = read_u64(&fixed[28..36]);
+                    let price_decimals = fixed[36].min(MAX_PRICE_DECIMALS);
+                    let segment = u16::from_le_bytes([fixed[37], fixed[38]]);
+                    let expiry = read_u64(&fixed[39..47]);
+                    let strike = read_u64(&fixed[47..55]);
+                    let underlying = read_u64(&fixed[55..63]);
+                    //extract the name
+                    let name = String::from_utf8(entry[INSTRUMENT_FIXED_SIZE..].to_vec())
+                        .map_err(|_| ProcessError::new("Invalid instrument name"))?;
+                    let mut instrument = Instrument::new(
+                        instrument_id,
+                        &name,
+                        instrument_type,
+                        instrument_state,
+                        percentage_bands,
+                        percentage_variation_allowed,
                     );
-                    let instrument_type = match buffer[12].to_le() {
-                        0 => InstrumentType::Share,
-                        1 => InstrumentType::OptionCall,
-                        2 => InstrumentType::OptionPut,
-                        3 => InstrumentType::Future,
-                        4 => InstrumentType::Warrant,
-                        _ => InstrumentType::Share,
-                    };
-                    let instrument_state = match buffer[13].to_le() {
-                        0 => InstrumentState::Trading,
-                        1 => InstrumentState::Closed,
-                        2 => InstrumentState::Auction,
-                        _ => InstrumentState::Closed,
-                    };
+                    instrument.set_tick_size(tick_size);
+                    instrument.set_round_lot(round_lot);
+                    instrument.set_previous_close(previous_close);
+                    instrument.set_price_decimals(price_decimals);
+                    instrument.set_segment(segment);
+                    instrument.set_expiry(expiry);
+                    instrument.set_strike(strike);
+                    instrument.set_underlying(underlying);
+                    let inserted_instrument = self.instrument_list.add_instrument(instrument);
 
-                    if self.protocol_side == ProtocolSide::Client {
-                        // update the specific instrument
-                        let percentage_bands = buffer[14].to_le();
-                        let percentage_variation_allowed = buffer[15].to_le();
-                        //extract the name
-                        let mut v = buffer[16..].to_vec();
-                        v.truncate(data_len as usize - 12);
-                        let name = String::from_utf8(v).unwrap();
-                        let instrument = Instrument::new(
-                            instrument_id,
-                            &name,
-                            instrument_type,
-                            instrument_state,
-                            percentage_bands,
-                            percentage_variation_allowed,
-                        );
-                        let inserted_instrument = self.instrument_list.add_instrument(instrument);
-
-                        if let Some(m) = self.markets.borrow().get(&instrument_id) {
-                            m.instrument_updated();
-                            return Ok((vec![], processed + data_len as usize)); // we do this just to drop the borrow
+                    let (markets, disseminator, order_ids) = match &mut self.markets {
+                        Markets::Local {
+                            markets,
+                            disseminator,
+                            order_ids,
+                        } => (markets.clone(), disseminator.clone(), order_ids.clone()),
+                        Markets::Forwarded(updates) => {
+                            if self.partition.contains(instrument_id) {
+                                updates.push(MarketUpdate::Instrument(
+                                    inserted_instrument.read().unwrap().clone(),
+                                ));
+                            }
+                            return Ok((vec![], entry_len));
                         }
-                        self.markets.borrow_mut().insert(
-                            instrument_id,
-                            Market::new(inserted_instrument, self.disseminator.clone()),
-                        );
+                    };
+                    if let Some(m) = markets.lock().unwrap().get_mut(&instrument_id) {
+                        // a market closing reports its day back to the clearing
+                        let response = match m.instrument_updated() {
+                            Some(summary) => self.prepare_eod_summary(&summary),
+                            None => vec![],
+                        };
+                        return Ok((response, entry_len));
+                        // we do this just to drop the borrow
                     }
-                    Ok((vec![], processed + data_len as usize))
+                    if !self.partition.contains(instrument_id) {
+                        return Ok((vec![], entry_len));
+                    }
+                    let mut market = Market::new(inserted_instrument, disseminator, order_ids);
+                    market.set_volatility_config(self.volatility);
+                    markets.lock().unwrap().insert(instrument_id, market);
                 }
+                Ok((vec![], entry_len))
             }
             CLEAR_TYPE_INSTRUMENT_REQUEST => {
-                if processed + 8 > buffer.len() {
-                    Ok((vec![], 0))
-                } else {
-                    let instrument_id = u64::from_le_bytes(
-                        buffer[4..12].try_into().expect("Invalid instrument ID"),
-                    );
-                    match self.instrument_list.get(instrument_id) {
-                        Some(instrument) => {
-                            let response =
-                                self.prepare_instrument_update_response(&instrument.borrow());
-                            Ok((response, processed + 8))
-                        }
-                        None => Ok((vec![], processed + 8)),
+                let fixed = fixed_size::<8>(entry, "instrument request")?;
+                match self.instrument_list.get(read_u64(fixed)) {
+                    Some(instrument) => {
+                        let response =
+                            self.prepare_instrument_update_response(&instrument.read().unwrap());
+                        Ok((response, entry_len))
                     }
+                    None => Ok((
+                        self.prepare_instrument_not_found(read_u64(fixed)),
+                        entry_len,
+                    )),
                 }
             }
             CLEAR_TYPE_ALL_INSTRUMENTS_REQUEST => {
                 let response = self
                     .instrument_list
                     .clone()
-                    .map(|i| self.prepare_instrument_update_response(&i.borrow()))
+                    .map(|i| self.prepare_instrument_update_response(&i.read().unwrap()))
                     .reduce(|mut acc, mut i| {
-                        (&mut acc).append(&mut i);
+                        acc.append(&mut i);
                         acc
                     })
                     .unwrap_or_default(); // default in case there is no instrument
-                Ok((response, processed))
+                Ok((response, entry_len))
+            }
+            CLEAR_TYPE_EOD_SUMMARY => {
+                let summary_buffer = fixed_size::<EODSUMMARY_SIZE>(entry, "EOD summary")?;
+                let summary = EodSummary::decode(*summary_buffer)
+                    .map_err(|_| ProcessError::new("Invalid EOD summary"))?;
+                if self.protocol_side == ProtocolSide::Server {
+                    self.eod_summaries.push(summary);
+                }
+                Ok((vec![], entry_len))
+            }
+            CLEAR_TYPE_TRADE_CAPTURE => {
+                let capture_buffer = fixed_size::<TRADECAPTURE_SIZE>(entry, "trade capture")?;
+                let capture = TradeCapture::decode(*capture_buffer)
+                    .map_err(|_| ProcessError::new("Invalid trade capture"))?;
+                if self.protocol_side == ProtocolSide::Server {
+                    self.trade_captures.push(capture);
+                    // the ack only says the capture arrived, the duplicates
+                    // are for the receiver of take_trade_captures to drop
+                    Ok((self.prepare_trade_capture_ack(capture.seq), entry_len))
+                } else {
+                    Ok((vec![], entry_len))
+                }
+            }
+            CLEAR_TYPE_EXPOSURE_UPDATE => {
+                let fixed = fixed_size::<EXPOSURE_UPDATE_SIZE>(entry, "exposure update")?;
+                let participant = read_u64(&fixed[0..8]);
+                let book_id = read_u64(&fixed[8..16]);
+                let blocked_side = match fixed[16] {
+                    NO_BLOCKED_SIDE => None,
+                    side => Some(Side::from(side)),
+                };
+                if self.protocol_side == ProtocolSide::Client {
+                    match &mut self.markets {
+                        Markets::Local { markets, .. } => {
+                            if let Some(m) = markets.lock().unwrap().get_mut(&book_id) {
+                                m.set_exposure_block(participant, blocked_side);
+                            }
+                        }
+                        Markets::Forwarded(updates) => updates.push(MarketUpdate::Exposure {
+                            participant,
+                            book_id,
+                            blocked_side,
+                        }),
+                    }
+                }
+                Ok((vec![], entry_len))
+            }
+            CLEAR_TYPE_TRADE_CAPTURE_ACK => {
+                let seq = read_u64(fixed_size::<TRADE_CAPTURE_ACK_SIZE>(
+                    entry,
+                    "trade capture ack",
+                )?);
+                if self.protocol_side == ProtocolSide::Client {
+                    // the acks are cumulative
+                    while self
+                        .unacked_captures
+                        .front()
+                        .is_some_and(|capture| capture.seq <= seq)
+                    {
+                        self.unacked_captures.pop_front();
+                    }
+                }
+                Ok((vec![], entry_len))
+            }
+            CLEAR_TYPE_INSTRUMENT_DELETION => {
+                let instrument_id = read_u64(fixed_size::<INSTRUMENT_DELETION_SIZE>(
+                    entry,
+                    "instrument deletion",
+                )?);
+                if self.protocol_side == ProtocolSide::Client {
+                    return Ok((self.delete_instrument(instrument_id), entry_len));
+                }
+                Ok((vec![], entry_len))
+            }
+            CLEAR_TYPE_INSTRUMENT_NOT_FOUND => {
+                let instrument_id = read_u64(fixed_size::<INSTRUMENT_NOT_FOUND_SIZE>(
+                    entry,
+                    "instrument not found",
+                )?);
+                // whatever the engine had of it is gone as well
+                if self.protocol_side == ProtocolSide::Client {
+                    return Ok((self.delete_instrument(instrument_id), entry_len));
+                }
+                Ok((vec![], entry_len))
+            }
+            CLEAR_TYPE_SNAPSHOT_REQUEST => {
+                if self.protocol_side == ProtocolSide::Client {
+                    self.snapshot_requested = true;
+                }
+                Ok((vec![], entry_len))
+            }
+            CLEAR_TYPE_TRADE_BUST => {
+                let fixed = fixed_size::<TRADE_BUST_SIZE>(entry, "trade bust")?;
+                let book_id = read_u64(&fixed[0..8]);
+                let trade_id = read_u64(&fixed[8..16]);
+                if self.protocol_side == ProtocolSide::Client {
+                    match &mut self.markets {
+                        Markets::Local { markets, .. } => {
+                            // the execution reports are not sent here
+                            if let Some(m) = markets.lock().unwrap().get_mut(&book_id) {
+                                m.bust_trade(trade_id);
+                            }
+                        }
+                        Markets::Forwarded(updates) => {
+                            if self.partition.contains(book_id) {
+                                updates.push(MarketUpdate::TradeBust { book_id, trade_id });
+                            }
+                        }
+                    }
+                }
+                Ok((vec![], entry_len))
+            }
+            CLEAR_TYPE_SEGMENT_STATE => {
+                let fixed = fixed_size::<SEGMENT_STATE_SIZE>(entry, "segment state")?;
+                let segment = u16::from_le_bytes([fixed[0], fixed[1]]);
+                let state = InstrumentState::from(fixed[2]);
+                if self.protocol_side == ProtocolSide::Client {
+                    // the instruments of the other partitions too
+                    for instrument in self.instrument_list.clone() {
+                        let mut instrument = instrument.write().unwrap();
+                        if instrument.get_segment() == segment {
+                            instrument.set_state(state);
+                        }
+                    }
+                    let (markets, disseminator) = match &mut self.markets {
+                        Markets::Local {
+                            markets,
+                            disseminator,
+                            ..
+                        } => (markets.clone(), disseminator.clone()),
+                        Markets::Forwarded(updates) => {
+                            updates.push(MarketUpdate::Segment { segment, state });
+                            return Ok((vec![], entry_len));
+                        }
+                    };
+                    // the markets share the instruments of the list, their
+                    // state is changed already
+                    let mut response = vec![];
+                    for m in markets
+                        .lock()
+                        .unwrap()
+                        .values_mut()
+                        .filter(|m| m.get_segment() == segment)
+                    {
+                        if let Some(summary) = m.instrument_updated() {
+                            response.append(&mut self.prepare_eod_summary(&summary));
+                        }
+                    }
+                    if publish_segment_state(
+                        &disseminator,
+                        segment,
+                        state,
+                        StateChangeReason::Clearing,
+                    )
+                    .is_err()
+                    {
+                        eprintln!("Error publishing the state of segment {segment}");
+                    }
+                    return Ok((response, entry_len));
+                }
+                Ok((vec![], entry_len))
             }
             _ => Err(ProcessError::new("Invalid type")),
         }
     }
 }
 
-impl<T: GenericInstrumentList<Item = Rc<RefCell<Instrument>>>> GenericClearingProtocol
+impl<T: GenericInstrumentList<Item = Arc<RwLock<Instrument>>>> ClearProtocol<T> {
+    /// Drops @instrument_id and closes its market for good, on the client side
+    ///
+    /// Returns: the end of day summary for the clearing if the local market
+    /// was still open, nothing otherwise
+    fn delete_instrument(&mut self, instrument_id: u64) -> Vec<u8> {
+        self.instrument_list.remove(instrument_id);
+        match &mut self.markets {
+            Markets::Local { markets, .. } => {
+                let market = markets.lock().unwrap().remove(&instrument_id);
+                // the orders of the market are not reported here
+                if let Some((Some(summary), _)) = market.map(|mut m| m.delete()) {
+                    return self.prepare_eod_summary(&summary);
+                }
+            }
+            Markets::Forwarded(updates) => {
+                if self.partition.contains(instrument_id) {
+                    updates.push(MarketUpdate::Deleted(instrument_id));
+                }
+            }
+        }
+        vec![]
+    }
+}
+
+/// The first @S bytes of @entry, an error naming @what if the entry is shorter
+fn fixed_size<'a, const S: usize>(
+    entry: &'a [u8],
+    what: &str,
+) -> Result<&'a [u8; S], ProcessError> {
+    entry
+        .get(..S)
+        .and_then(|fixed| fixed.try_into().ok())
+        .ok_or_else(|| ProcessError::new(&format!("Invalid {what} length {}", entry.len())))
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap_or_default())
+}
+
+impl<T: GenericInstrumentList<Item = Arc<RwLock<Instrument>>>> GenericClearingProtocol
     for ClearProtocol<T>
 {
     /// Takes a buffer of bytes, checks for a valid header,
@@ -210,8 +589,23 @@ impl<T: GenericInstrumentList<Item = Rc<RefCell<Instrument>>>> GenericClearingPr
         vec![b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, 3, 0, 0, 0]
     }
 
+    fn prepare_instrument_request(&self, instrument_id: u64) -> Vec<u8> {
+        let mut r = vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_INSTRUMENT_REQUEST.to_le_bytes()[0],
+            CLEAR_TYPE_INSTRUMENT_REQUEST.to_le_bytes()[1],
+            8,
+            0,
+        ];
+        r.extend_from_slice(&instrument_id.to_le_bytes());
+        r
+    }
+
     fn prepare_instrument_update_response(&self, instrument: &Instrument) -> Vec<u8> {
-        let length: u16 = (12 + instrument.get_name().len())
+        let length: u16 = (INSTRUMENT_FIXED_SIZE + instrument.get_name().len())
             .try_into()
             .unwrap_or_default();
         if length == 0 {
@@ -237,10 +631,191 @@ impl<T: GenericInstrumentList<Item = Rc<RefCell<Instrument>>>> GenericClearingPr
         r
     }
 
+    fn prepare_eod_summary(&self, summary: &EodSummary) -> Vec<u8> {
+        let length = EODSUMMARY_SIZE as u16;
+        let mut r = vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_EOD_SUMMARY.to_le_bytes()[0],
+            CLEAR_TYPE_EOD_SUMMARY.to_le_bytes()[1],
+            length.to_le_bytes()[0],
+            length.to_le_bytes()[1],
+        ];
+        r.extend_from_slice(&summary.encode());
+        r
+    }
+
+    fn prepare_trade_capture(&self, capture: &TradeCapture) -> Vec<u8> {
+        let length = TRADECAPTURE_SIZE as u16;
+        let mut r = vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_TRADE_CAPTURE.to_le_bytes()[0],
+            CLEAR_TYPE_TRADE_CAPTURE.to_le_bytes()[1],
+            length.to_le_bytes()[0],
+            length.to_le_bytes()[1],
+        ];
+        r.extend_from_slice(&capture.encode());
+        r
+    }
+
+    fn prepare_trade_capture_ack(&self, seq: u64) -> Vec<u8> {
+        let length = TRADE_CAPTURE_ACK_SIZE as u16;
+        let mut r = vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_TRADE_CAPTURE_ACK.to_le_bytes()[0],
+            CLEAR_TYPE_TRADE_CAPTURE_ACK.to_le_bytes()[1],
+            length.to_le_bytes()[0],
+            length.to_le_bytes()[1],
+        ];
+        r.extend_from_slice(&seq.to_le_bytes());
+        r
+    }
+
+    fn sequence_trade_capture(&mut self, mut capture: TradeCapture) -> TradeCapture {
+        self.capture_seq += 1;
+        capture.seq = self.capture_seq;
+        self.unacked_captures.push_back(capture);
+        capture
+    }
+
+    fn unacked_trade_captures(&self) -> Vec<TradeCapture> {
+        self.unacked_captures.iter().copied().collect()
+    }
+
+    fn prepare_exposure_update(
+        &self,
+        participant: u64,
+        book_id: u64,
+        blocked_side: Option<Side>,
+    ) -> Vec<u8> {
+        let length = EXPOSURE_UPDATE_SIZE as u16;
+        let mut r = vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_EXPOSURE_UPDATE.to_le_bytes()[0],
+            CLEAR_TYPE_EXPOSURE_UPDATE.to_le_bytes()[1],
+            length.to_le_bytes()[0],
+            length.to_le_bytes()[1],
+        ];
+        r.extend_from_slice(&participant.to_le_bytes());
+        r.extend_from_slice(&book_id.to_le_bytes());
+        r.push(blocked_side.map_or(NO_BLOCKED_SIDE, |side| side.into()));
+        r
+    }
+
+    fn prepare_instrument_deletion(&self, instrument_id: u64) -> Vec<u8> {
+        let length = INSTRUMENT_DELETION_SIZE as u16;
+        let mut r = vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_INSTRUMENT_DELETION.to_le_bytes()[0],
+            CLEAR_TYPE_INSTRUMENT_DELETION.to_le_bytes()[1],
+            length.to_le_bytes()[0],
+            length.to_le_bytes()[1],
+        ];
+        r.extend_from_slice(&instrument_id.to_le_bytes());
+        r
+    }
+
+    fn prepare_instrument_not_found(&self, instrument_id: u64) -> Vec<u8> {
+        let length = INSTRUMENT_NOT_FOUND_SIZE as u16;
+        let mut r = vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_INSTRUMENT_NOT_FOUND.to_le_bytes()[0],
+            CLEAR_TYPE_INSTRUMENT_NOT_FOUND.to_le_bytes()[1],
+            length.to_le_bytes()[0],
+            length.to_le_bytes()[1],
+        ];
+        r.extend_from_slice(&instrument_id.to_le_bytes());
+        r
+    }
+
+    fn prepare_logon(&self) -> Vec<u8> {
+        let Some(credentials) = &self.credentials else {
+            return vec![];
+        };
+        let length = (LOGON_FIXED_SIZE + credentials.secret.len()) as u16;
+        let mut r = vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_LOGON.to_le_bytes()[0],
+            CLEAR_TYPE_LOGON.to_le_bytes()[1],
+            length.to_le_bytes()[0],
+            length.to_le_bytes()[1],
+        ];
+        r.extend_from_slice(&credentials.engine_id.to_le_bytes());
+        r.extend_from_slice(credentials.secret.as_bytes());
+        r
+    }
+
+    fn prepare_trade_bust(&self, book_id: u64, trade_id: u64) -> Vec<u8> {
+        let length = TRADE_BUST_SIZE as u16;
+        let mut r = vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_TRADE_BUST.to_le_bytes()[0],
+            CLEAR_TYPE_TRADE_BUST.to_le_bytes()[1],
+            length.to_le_bytes()[0],
+            length.to_le_bytes()[1],
+        ];
+        r.extend_from_slice(&book_id.to_le_bytes());
+        r.extend_from_slice(&trade_id.to_le_bytes());
+        r
+    }
+
+    fn prepare_segment_state(&self, segment: u16, state: InstrumentState) -> Vec<u8> {
+        let length = SEGMENT_STATE_SIZE as u16;
+        let mut r = vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_SEGMENT_STATE.to_le_bytes()[0],
+            CLEAR_TYPE_SEGMENT_STATE.to_le_bytes()[1],
+            length.to_le_bytes()[0],
+            length.to_le_bytes()[1],
+        ];
+        r.extend_from_slice(&segment.to_le_bytes());
+        r.push(state.into());
+        r
+    }
+
+    fn prepare_snapshot_request(&self) -> Vec<u8> {
+        vec![
+            b'C',
+            b'P',
+            CLEAR_PROTOCOL_VERSION,
+            1,
+            CLEAR_TYPE_SNAPSHOT_REQUEST.to_le_bytes()[0],
+            CLEAR_TYPE_SNAPSHOT_REQUEST.to_le_bytes()[1],
+            0,
+            0,
+        ]
+    }
+
     fn clone_instrument_list(&self) -> Vec<Instrument> {
         self.instrument_list
             .clone()
-            .map(|x| x.borrow().clone())
+            .map(|x| x.read().unwrap().clone())
             .collect()
     }
 
@@ -248,43 +823,85 @@ impl<T: GenericInstrumentList<Item = Rc<RefCell<Instrument>>>> GenericClearingPr
         self.instrument_list.add_instrument(i);
     }
 
+    fn remove_instrument(&mut self, instrument_id: u64) {
+        self.instrument_list.remove(instrument_id);
+    }
+
+    fn take_eod_summaries(&mut self) -> Vec<EodSummary> {
+        std::mem::take(&mut self.eod_summaries)
+    }
+
+    fn take_trade_captures(&mut self) -> Vec<TradeCapture> {
+        std::mem::take(&mut self.trade_captures)
+    }
+
+    fn take_market_updates(&mut self) -> Vec<MarketUpdate> {
+        match &mut self.markets {
+            Markets::Local { .. } => vec![],
+            Markets::Forwarded(updates) => std::mem::take(updates),
+        }
+    }
+
+    fn take_snapshot_request(&mut self) -> bool {
+        std::mem::take(&mut self.snapshot_requested)
+    }
+
     fn set_protocol_side(&mut self, side: ProtocolSide) {
         self.protocol_side = side;
     }
+
+    fn set_session(&mut self, engine_id: Option<u8>) {
+        self.session = engine_id;
+    }
+
+    fn get_session(&self) -> Option<u8> {
+        self.session
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::cell::RefCell;
     use std::collections::HashMap;
-    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
 
     use disseminator::mockdisseminator::MockDisseminator;
     use instruments::genericinstrumentlist::GenericInstrumentList;
     use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
     use instruments::instrumentlist::InstrumentList;
     use instruments::mockinstrumentlist::MockInstrumentList;
-    use market::Market;
+    use instruments::partition::Partition;
+    use market::{orderid::OrderIdGenerator, Market};
 
     use super::ClearProtocol;
     use super::CLEAR_PROTOCOL_VERSION;
-    use crate::clearprotocol::{CLEAR_TYPE_ALL_INSTRUMENTS_REQUEST, CLEAR_TYPE_INSTRUMENT_UPDATE};
-    use crate::genericclearingprotocol::GenericClearingProtocol;
+    use crate::clearprotocol::{
+        CLEAR_TYPE_ALL_INSTRUMENTS_REQUEST, CLEAR_TYPE_EOD_SUMMARY, CLEAR_TYPE_INSTRUMENT_UPDATE,
+        CLEAR_TYPE_TRADE_CAPTURE_ACK, SEGMENT_STATE_SIZE, TRADE_BUST_SIZE,
+    };
+    use crate::genericclearingprotocol::{GenericClearingProtocol, MarketUpdate, ProtocolSide};
+    use crate::logon::{EngineCredentials, EngineSecrets};
+    use oep::eodsummary::{EodSummary, EODSUMMARY_SIZE};
+    use oep::tradecapture::{TradeCapture, TRADECAPTURE_SIZE};
+    use order::Side;
 
     #[test]
     fn instrument_update_no_upcall() {
-        let markets = Rc::new(RefCell::new(HashMap::<u64, Market>::new()));
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
         let mut target = ClearProtocol::new(
             MockInstrumentList::new(),
             markets,
-            Rc::new(RefCell::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
 
         #[rustfmt::skip]
         let packet = [
             b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, // technical header
-            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 12, 0, // Instrument update, Len: 12
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
             8, 7, 6, 5, 4, 3, 2, 1, 0, 2, 20, 25,
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // previous close, price decimals, segment
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // expiry, strike, underlying
         ];
 
         let v = target.process(&packet);
@@ -294,18 +911,23 @@ mod test {
 
     #[test]
     fn one_instrument_update() {
-        let markets = Rc::new(RefCell::new(HashMap::<u64, Market>::new()));
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
         let mut target = ClearProtocol::new(
             MockInstrumentList::new(),
             markets,
-            Rc::new(RefCell::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
 
         #[rustfmt::skip]
         let packet = [
             b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, // technical header
-            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 12 + 3, 0, // Instrument update
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63 + 3, 0, // Instrument update
             8, 7, 6, 5, 4, 3, 2, 1, 0, 2, 20, 25,
+            5, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0xe2, 0x04, 0, 0, 0, 0, 0, 0, 2, 3, 0, // previous close, price decimals, segment
+            0x88, 0x13, 0, 0, 0, 0, 0, 0, 0x28, 0x23, 0, 0, 0, 0, 0, 0, // expiry, strike
+            42, 0, 0, 0, 0, 0, 0, 0, // underlying
             b'A', b'B', b'C'
         ];
 
@@ -322,25 +944,40 @@ mod test {
         assert_eq!(ins.get_state(), InstrumentState::Auction);
         assert_eq!(ins.get_percentage_bands(), 20);
         assert_eq!(ins.get_percentage_variation_allowed(), 25);
+        assert_eq!(ins.get_tick_size(), 5);
+        assert_eq!(ins.get_round_lot(), 100);
+        assert_eq!(ins.get_previous_close(), 1250);
+        assert_eq!(ins.get_price_decimals(), 2);
+        assert_eq!(ins.get_segment(), 3);
+        assert_eq!(ins.get_expiry(), 5000);
+        assert_eq!(ins.get_strike(), 9000);
+        assert_eq!(ins.get_underlying(), 42);
         assert_eq!("ABC", ins.get_name());
     }
 
     #[test]
     fn two_instrument_updates() {
-        let markets = Rc::new(RefCell::new(HashMap::<u64, Market>::new()));
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
         let mut target = ClearProtocol::new(
             MockInstrumentList::new(),
             markets,
-            Rc::new(RefCell::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
 
         #[rustfmt::skip]
         let packet = [
             b'C', b'P', CLEAR_PROTOCOL_VERSION, 2, // technical header
-            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 12, 0, // Instrument update, Len: 12
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
             8, 7, 6, 5, 4, 3, 2, 1, 0, 2, 20, 25, // first instrument
-            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 12, 0, // Instrument update, Len: 12
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // previous close, price decimals, segment
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // expiry, strike, underlying
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
             9, 8, 6, 5, 4, 3, 2, 1, 0, 2, 20, 25, // second instrument
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // previous close, price decimals, segment
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // expiry, strike, underlying
         ];
 
         let v = target.process(&packet);
@@ -366,19 +1003,96 @@ mod test {
         assert_eq!(ins.get_percentage_variation_allowed(), 25);
     }
 
+    #[test]
+    fn markets_only_for_the_partition() {
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
+        let mut target = ClearProtocol::new(
+            MockInstrumentList::new(),
+            markets.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_partition(Partition::parse(1, "1-10").unwrap());
+
+        #[rustfmt::skip]
+        let packet = [
+            b'C', b'P', CLEAR_PROTOCOL_VERSION, 2, // technical header
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
+            5, 0, 0, 0, 0, 0, 0, 0, 0, 2, 20, 25, // in the partition
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // previous close, price decimals, segment
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // expiry, strike, underlying
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
+            11, 0, 0, 0, 0, 0, 0, 0, 0, 2, 20, 25, // outside of it
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // previous close, price decimals, segment
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // expiry, strike, underlying
+        ];
+
+        let v = target.process(&packet);
+        assert!(v.is_ok());
+        assert_eq!(v.unwrap().1, packet.len());
+        assert_eq!(2, target.clone_instrument_list().len());
+        assert_eq!(
+            vec![5],
+            markets.lock().unwrap().keys().copied().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn forwarding_keeps_no_market() {
+        let mut target = ClearProtocol::forwarding(MockInstrumentList::new());
+        target.set_partition(Partition::parse(1, "1-10").unwrap());
+
+        #[rustfmt::skip]
+        let packet = [
+            b'C', b'P', CLEAR_PROTOCOL_VERSION, 2, // technical header
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
+            5, 0, 0, 0, 0, 0, 0, 0, 0, 2, 20, 25, // in the partition
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // previous close, price decimals, segment
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // expiry, strike, underlying
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
+            11, 0, 0, 0, 0, 0, 0, 0, 0, 2, 20, 25, // outside of it
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // previous close, price decimals, segment
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // expiry, strike, underlying
+        ];
+        let (response, processed) = target.process(&packet).unwrap();
+        assert!(response.is_empty());
+        assert_eq!(packet.len(), processed);
+        assert_eq!(2, target.clone_instrument_list().len());
+
+        let packet = target.prepare_exposure_update(11, 5, Some(Side::Ask));
+        target.process(&packet).unwrap();
+        let updates = target.take_market_updates();
+        assert_eq!(2, updates.len());
+        assert!(matches!(&updates[0], MarketUpdate::Instrument(i) if i.get_id() == 5));
+        assert_eq!(
+            MarketUpdate::Exposure {
+                participant: 11,
+                book_id: 5,
+                blocked_side: Some(Side::Ask),
+            },
+            updates[1]
+        );
+        assert!(target.take_market_updates().is_empty());
+    }
+
     #[test]
     fn one_incomplete_instrument_update() {
-        let markets = Rc::new(RefCell::new(HashMap::<u64, Market>::new()));
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
         let mut target = ClearProtocol::new(
             MockInstrumentList::new(),
             markets,
-            Rc::new(RefCell::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
 
         #[rustfmt::skip]
         let packet = [
             b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, // technical header
-            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 12, 0, // Instrument update, Len: 12
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
             8, 7, 6, 5, 4, 3, 2, 1, 0, 2,
         ];
 
@@ -390,25 +1104,29 @@ mod test {
 
     #[test]
     fn one_complete_one_incomplete_instrument_update() {
-        let markets = Rc::new(RefCell::new(HashMap::<u64, Market>::new()));
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
         let mut target = ClearProtocol::new(
             MockInstrumentList::new(),
             markets,
-            Rc::new(RefCell::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
 
         #[rustfmt::skip]
         let packet = [
             b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, // technical header
-            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 12, 0, // Instrument update, Len: 12
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
             8, 7, 6, 5, 4, 3, 2, 1, 0, 2, 20, 25, // first instrument
-            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 12, 0, // Instrument update, Len: 12
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // previous close, price decimals, segment
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // expiry, strike, underlying
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
             9, 8, 6, 5, 4, 3, 2, 1, 0, 2, // second incomplete instrument
         ];
 
         let v = target.process(&packet);
         assert!(v.is_ok());
-        assert_eq!(v.unwrap().1, 4 + 4 + 12);
+        assert_eq!(v.unwrap().1, 4 + 4 + 63);
 
         let i_list = target.clone_instrument_list();
         assert_eq!(1, i_list.len());
@@ -429,26 +1147,31 @@ mod test {
         let instrument = Instrument::new_fast(0x0102030405060708, InstrumentType::OptionPut);
         assert_eq!(InstrumentState::Closed, instrument.get_state());
 
-        let markets = Rc::new(RefCell::new(HashMap::<u64, Market>::new()));
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
         let mut target = ClearProtocol::new(
             InstrumentList::new(),
             markets.clone(),
-            Rc::new(RefCell::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
         let instrument_ref = target.instrument_list.add_instrument(instrument);
-        markets.borrow_mut().insert(
-            instrument_ref.borrow().get_id(),
+        markets.lock().unwrap().insert(
+            instrument_ref.read().unwrap().get_id(),
             Market::new(
                 instrument_ref.clone(),
-                Rc::new(RefCell::new(MockDisseminator::new())),
+                Arc::new(Mutex::new(MockDisseminator::new())),
+                Arc::new(Mutex::new(OrderIdGenerator::new(0))),
             ),
         );
 
         #[rustfmt::skip]
         let packet = [
             b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, // technical header
-            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 12, 0, // Instrument update, Len: 12
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
             8, 7, 6, 5, 4, 3, 2, 1, 2, 0, 20, 25, // update the instrument to trading
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // previous close, price decimals, segment
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // expiry, strike, underlying
         ];
 
         let v = target.process(&packet);
@@ -458,12 +1181,469 @@ mod test {
 
         assert_eq!(
             InstrumentState::Trading,
-            instrument_ref.borrow().get_state()
+            instrument_ref.read().unwrap().get_state()
         );
         assert_eq!(
             InstrumentState::Trading,
-            markets.borrow()[&instrument_ref.borrow().get_id()].get_state()
+            markets.lock().unwrap()[&instrument_ref.read().unwrap().get_id()].get_state()
+        );
+    }
+
+    #[test]
+    fn closing_instrument_reports_eod_summary() {
+        let mut instrument = Instrument::new_fast(0x0102030405060708, InstrumentType::Share);
+        instrument.set_state(InstrumentState::Trading);
+
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = ClearProtocol::new(
+            InstrumentList::new(),
+            markets.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        let instrument_ref = target.instrument_list.add_instrument(instrument);
+        markets.lock().unwrap().insert(
+            instrument_ref.read().unwrap().get_id(),
+            Market::new(
+                instrument_ref.clone(),
+                disseminator.clone(),
+                Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+            ),
+        );
+
+        #[rustfmt::skip]
+        let packet = [
+            b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, // technical header
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 63, 0, // Instrument update, Len: 63
+            8, 7, 6, 5, 4, 3, 2, 1, 0, 1, 20, 25, // close the instrument
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // tick size, round lot
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // previous close, price decimals, segment
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // expiry, strike, underlying
+        ];
+
+        let v = target.process(&packet);
+        assert!(v.is_ok());
+        let (response, processed) = v.unwrap();
+        assert_eq!(packet.len(), processed);
+        assert_eq!(8 + EODSUMMARY_SIZE, response.len());
+        assert_eq!(CLEAR_TYPE_EOD_SUMMARY as u8, response[4]);
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1], response[8..16]);
+        assert_eq!(1, disseminator.lock().unwrap().eod_summaries.borrow().len());
+
+        // the same update a second time doesn't close the market again
+        let v = target.process(&packet);
+        assert!(v.unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn instrument_deletion() {
+        let mut instrument = Instrument::new_fast(500, InstrumentType::Share);
+        instrument.set_state(InstrumentState::Trading);
+
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = ClearProtocol::new(
+            InstrumentList::new(),
+            markets.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        let instrument_ref = target.instrument_list.add_instrument(instrument);
+        markets.lock().unwrap().insert(
+            500,
+            Market::new(
+                instrument_ref,
+                disseminator.clone(),
+                Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+            ),
+        );
+
+        let packet = target.prepare_instrument_deletion(500);
+        assert_eq!(16, packet.len());
+        let (response, processed) = target.process(&packet).unwrap();
+        assert_eq!(packet.len(), processed);
+        // the market was trading, so it's closed first
+        assert_eq!(CLEAR_TYPE_EOD_SUMMARY as u8, response[4]);
+        assert!(markets.lock().unwrap().is_empty());
+        assert!(target.clone_instrument_list().is_empty());
+
+        // unknown instruments are ignored
+        assert!(target.process(&packet).unwrap().0.is_empty());
+
+        let mut target = ClearProtocol::forwarding(MockInstrumentList::new());
+        target.set_partition(Partition::parse(1, "1-10").unwrap());
+        for id in [5, 11] {
+            target
+                .process(&target.prepare_instrument_deletion(id))
+                .unwrap();
+        }
+        assert_eq!(vec![MarketUpdate::Deleted(5)], target.take_market_updates());
+    }
+
+    #[test]
+    fn segment_state() {
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = ClearProtocol::new(
+            InstrumentList::new(),
+            markets.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        for (id, segment) in [(5, 2), (6, 2), (7, 1)] {
+            let mut instrument = Instrument::new_fast(id, InstrumentType::Share);
+            instrument.set_state(InstrumentState::Trading);
+            instrument.set_segment(segment);
+            let packet = target.prepare_instrument_update_response(&instrument);
+            target.process(&packet).unwrap();
+        }
+        assert_eq!(3, markets.lock().unwrap().len());
+
+        let packet = target.prepare_segment_state(2, InstrumentState::Closed);
+        assert_eq!(8 + SEGMENT_STATE_SIZE, packet.len());
+        let (response, processed) = target.process(&packet).unwrap();
+        assert_eq!(packet.len(), processed);
+        // the end of day summaries of the two markets closed
+        assert_eq!(2 * (8 + EODSUMMARY_SIZE), response.len());
+        let mut states: Vec<(u64, InstrumentState)> = target
+            .clone_instrument_list()
+            .iter()
+            .map(|i| (i.get_id(), i.get_state()))
+            .collect();
+        states.sort_by_key(|(id, _)| *id);
+        assert_eq!(
+            vec![
+                (5, InstrumentState::Closed),
+                (6, InstrumentState::Closed),
+                (7, InstrumentState::Trading)
+            ],
+            states
+        );
+        let published = disseminator.lock().unwrap().segment_states.borrow().clone();
+        assert_eq!(1, published.len());
+        assert_eq!((2, 1), ({ published[0].segment }, published[0].state));
+
+        // forwarded to the shards as it is
+        let mut target = ClearProtocol::forwarding(MockInstrumentList::new());
+        target
+            .process(&target.prepare_segment_state(2, InstrumentState::Halted))
+            .unwrap();
+        assert_eq!(
+            vec![MarketUpdate::Segment {
+                segment: 2,
+                state: InstrumentState::Halted
+            }],
+            target.take_market_updates()
+        );
+    }
+
+    #[test]
+    fn trade_bust() {
+        let mut target = ClearProtocol::forwarding(MockInstrumentList::new());
+        target.set_partition(Partition::parse(1, "1-10").unwrap());
+        for book_id in [5, 11] {
+            let packet = target.prepare_trade_bust(book_id, 3);
+            assert_eq!(8 + TRADE_BUST_SIZE, packet.len());
+            let (response, processed) = target.process(&packet).unwrap();
+            assert!(response.is_empty());
+            assert_eq!(packet.len(), processed);
+        }
+        // the books of the partition only
+        assert_eq!(
+            vec![MarketUpdate::TradeBust {
+                book_id: 5,
+                trade_id: 3
+            }],
+            target.take_market_updates()
+        );
+        // too short
+        let packet = target.prepare_trade_bust(5, 3);
+        let mut short = packet[..packet.len() - 8].to_vec();
+        short[6] = 8;
+        assert!(target.process(&short).is_err());
+    }
+
+    #[test]
+    fn server_collects_eod_summaries() {
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
+        let mut target = ClearProtocol::new(
+            InstrumentList::new(),
+            markets,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_protocol_side(ProtocolSide::Server);
+        target.set_session(Some(1));
+
+        let packet = target.prepare_eod_summary(&EodSummary {
+            book_id: 500,
+            closing_price: 1000,
+            volume: 200,
+            trade_count: 2,
+        });
+        let v = target.process(&packet);
+        assert!(v.is_ok());
+        assert_eq!(packet.len(), v.unwrap().1);
+
+        let summaries = target.take_eod_summaries();
+        assert_eq!(1, summaries.len());
+        assert_eq!(500, { summaries[0].book_id });
+        assert_eq!(1000, { summaries[0].closing_price });
+        assert!(target.take_eod_summaries().is_empty());
+    }
+
+    fn trade_capture(trade_id: u64) -> TradeCapture {
+        TradeCapture {
+            seq: 0,
+            book_id: 500,
+            trade_id,
+            price: 1000,
+            quantity: 200,
+            buyer: 11,
+            seller: 12,
+            timestamp: 1700000000000000000,
+        }
+    }
+
+    #[test]
+    fn server_collects_and_acks_trade_captures() {
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
+        let mut target = ClearProtocol::new(
+            InstrumentList::new(),
+            markets,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
+        target.set_protocol_side(ProtocolSide::Server);
+        target.set_session(Some(1));
+
+        let packet = target.prepare_trade_capture(&TradeCapture {
+            seq: 3,
+            ..trade_capture(1)
+        });
+        assert_eq!(8 + TRADECAPTURE_SIZE, packet.len());
+        let (response, processed) = target.process(&packet).unwrap();
+        assert_eq!(packet.len(), processed);
+        assert_eq!(target.prepare_trade_capture_ack(3), response);
+        #[rustfmt::skip]
+        assert_eq!(
+            vec![
+                b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, // technical header
+                CLEAR_TYPE_TRADE_CAPTURE_ACK as u8, 0, // type
+                8, 0, // length
+                3, 0, 0, 0, 0, 0, 0, 0, // sequence
+            ],
+            response
+        );
+
+        let captures = target.take_trade_captures();
+        assert_eq!(1, captures.len());
+        assert_eq!(3, { captures[0].seq });
+        assert_eq!(500, { captures[0].book_id });
+        assert_eq!(11, { captures[0].buyer });
+        assert_eq!(12, { captures[0].seller });
+        assert_eq!(1700000000000000000, { captures[0].timestamp });
+        assert!(target.take_trade_captures().is_empty());
+    }
+
+    #[test]
+    fn client_keeps_the_unacked_trade_captures() {
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
+        let mut target = ClearProtocol::new(
+            InstrumentList::new(),
+            markets,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+
+        let sequenced: Vec<u64> = (1..=3)
+            .map(|trade_id| target.sequence_trade_capture(trade_capture(trade_id)).seq)
+            .collect();
+        assert_eq!(vec![1, 2, 3], sequenced);
+        assert_eq!(3, target.unacked_trade_captures().len());
+
+        // the acks are cumulative
+        let packet = target.prepare_trade_capture_ack(2);
+        let (response, processed) = target.process(&packet).unwrap();
+        assert!(response.is_empty());
+        assert_eq!(packet.len(), processed);
+        let unacked = target.unacked_trade_captures();
+        assert_eq!(1, unacked.len());
+        assert_eq!(3, { unacked[0].seq });
+        assert_eq!(3, { unacked[0].trade_id });
+
+        // a late ack changes nothing
+        target
+            .process(&target.prepare_trade_capture_ack(1))
+            .unwrap();
+        assert_eq!(1, target.unacked_trade_captures().len());
+        target
+            .process(&target.prepare_trade_capture_ack(3))
+            .unwrap();
+        assert!(target.unacked_trade_captures().is_empty());
+        // the client doesn't collect the captures it gets
+        let packet = target.prepare_trade_capture(&trade_capture(4));
+        assert_eq!((vec![], packet.len()), target.process(&packet).unwrap());
+        assert!(target.take_trade_captures().is_empty());
+    }
+
+    #[test]
+    fn incomplete_trade_capture_ack() {
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
+        let mut target = ClearProtocol::new(
+            InstrumentList::new(),
+            markets,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.sequence_trade_capture(trade_capture(1));
+        let packet = target.prepare_trade_capture_ack(1);
+        assert_eq!(4, target.process(&packet[..packet.len() - 1]).unwrap().1);
+        assert_eq!(1, target.unacked_trade_captures().len());
+    }
+
+    #[test]
+    fn snapshot_request() {
+        let mut target = ClearProtocol::forwarding(InstrumentList::new());
+        let packet = target.prepare_snapshot_request();
+        assert_eq!((vec![], packet.len()), target.process(&packet).unwrap());
+        assert!(target.take_snapshot_request());
+        assert!(!target.take_snapshot_request());
+
+        // for the engines only
+        target.set_protocol_side(ProtocolSide::Server);
+        target.set_session(Some(1));
+        target.process(&packet).unwrap();
+        assert!(!target.take_snapshot_request());
+    }
+
+    #[test]
+    fn exposure_update_blocks_the_participant() {
+        let instrument = Instrument::new_fast(500, InstrumentType::Share);
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
+        let mut target = ClearProtocol::new(
+            InstrumentList::new(),
+            markets.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        let instrument_ref = target.instrument_list.add_instrument(instrument);
+        markets.lock().unwrap().insert(
+            500,
+            Market::new(
+                instrument_ref,
+                Arc::new(Mutex::new(MockDisseminator::new())),
+                Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+            ),
+        );
+
+        let packet = target.prepare_exposure_update(11, 500, Some(Side::Bid));
+        let v = target.process(&packet);
+        assert_eq!(packet.len(), v.unwrap().1);
+        assert!(markets.lock().unwrap()[&500].is_exposure_blocked(11, Side::Bid));
+        assert!(!markets.lock().unwrap()[&500].is_exposure_blocked(11, Side::Ask));
+
+        let packet = target.prepare_exposure_update(11, 500, None);
+        assert!(target.process(&packet).is_ok());
+        assert!(!markets.lock().unwrap()[&500].is_exposure_blocked(11, Side::Bid));
+
+        // unknown books are ignored
+        let packet = target.prepare_exposure_update(11, 501, Some(Side::Ask));
+        assert_eq!(packet.len(), target.process(&packet).unwrap().1);
+    }
+
+    #[test]
+    fn request_one_instrument() {
+        let mut target = ClearProtocol::forwarding(InstrumentList::new());
+        let mut instrument = Instrument::new_fast(7, InstrumentType::Future);
+        instrument.set_expiry(5000);
+        let _ = target.instrument_list.add_instrument(instrument.clone());
+
+        let (response, processed) = target
+            .process(&target.prepare_instrument_request(7))
+            .unwrap();
+        assert_eq!(8 + 8, processed);
+        assert_eq!(
+            target.prepare_instrument_update_response(&instrument),
+            response
+        );
+        // not found for an unknown one
+        let (response, _) = target
+            .process(&target.prepare_instrument_request(8))
+            .unwrap();
+        assert_eq!(target.prepare_instrument_not_found(8), response);
+    }
+
+    #[test]
+    fn logon() {
+        let mut client = ClearProtocol::forwarding(MockInstrumentList::new());
+        assert!(client.prepare_logon().is_empty());
+        client.set_credentials(EngineCredentials {
+            engine_id: 3,
+            secret: String::from("secret"),
+        });
+        let logon = client.prepare_logon();
+        assert_eq!(8 + 1 + 6, logon.len());
+
+        let mut secrets = EngineSecrets::default();
+        secrets.insert(3, "secret");
+        let mut target = ClearProtocol::forwarding(MockInstrumentList::new());
+        target.set_protocol_side(ProtocolSide::Server);
+        target.set_engine_secrets(secrets.clone());
+        let _ = target
+            .instrument_list
+            .add_instrument(Instrument::new_fast(7, InstrumentType::Share));
+
+        // nothing but the heartbeats before the logon
+        let request = client.prepare_all_instrument_request();
+        assert!(target.process(&request).is_err());
+        assert_eq!(
+            (vec![], 8),
+            target.process(&client.prepare_heartbeat()).unwrap()
+        );
+        assert_eq!(None, target.get_session());
+
+        assert_eq!((vec![], logon.len()), target.process(&logon).unwrap());
+        assert_eq!(Some(3), target.get_session());
+        let (response, _) = target.process(&request).unwrap();
+        assert!(!response.is_empty());
+        // only once
+        assert!(target.process(&logon).is_err());
+
+        // another connection, with the wrong secret
+        target.set_session(None);
+        client.set_credentials(EngineCredentials {
+            engine_id: 3,
+            secret: String::from("secreT"),
+        });
+        assert!(target.process(&client.prepare_logon()).is_err());
+        assert_eq!(None, target.get_session());
+        client.set_credentials(EngineCredentials {
+            engine_id: 4,
+            secret: String::from("secret"),
+        });
+        assert!(target.process(&client.prepare_logon()).is_err());
+    }
+
+    #[test]
+    fn instrument_not_found() {
+        let mut target = ClearProtocol::forwarding(MockInstrumentList::new());
+        target.set_partition(Partition::parse(1, "1-10").unwrap());
+        let packet = target.prepare_instrument_not_found(5);
+        assert_eq!(16, packet.len());
+        let (response, processed) = target.process(&packet).unwrap();
+        assert_eq!(packet.len(), processed);
+        assert!(response.is_empty());
+        // the held orders of the book are let go, to be rejected
+        assert_eq!(vec![MarketUpdate::Deleted(5)], target.take_market_updates());
+
+        // nothing on the server side
+        let mut target = ClearProtocol::forwarding(MockInstrumentList::new());
+        target.set_protocol_side(ProtocolSide::Server);
+        target.set_session(Some(1));
+        target.process(&packet).unwrap();
+        assert!(target.take_market_updates().is_empty());
     }
 
     #[test]
@@ -473,19 +1653,21 @@ mod test {
         assert_eq!(InstrumentState::Closed, instrument1.get_state());
         assert_eq!(InstrumentState::Closed, instrument2.get_state());
 
-        let markets = Rc::new(RefCell::new(HashMap::<u64, Market>::new()));
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
         let mut target = ClearProtocol::new(
             InstrumentList::new(),
             markets.clone(),
-            Rc::new(RefCell::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
         let _ = target.instrument_list.add_instrument(instrument1);
         let instrument_ref = target.instrument_list.add_instrument(instrument2);
-        markets.borrow_mut().insert(
-            instrument_ref.borrow().get_id(),
+        markets.lock().unwrap().insert(
+            instrument_ref.read().unwrap().get_id(),
             Market::new(
                 instrument_ref.clone(),
-                Rc::new(RefCell::new(MockDisseminator::new())),
+                Arc::new(Mutex::new(MockDisseminator::new())),
+                Arc::new(Mutex::new(OrderIdGenerator::new(0))),
             ),
         );
 
@@ -501,18 +1683,19 @@ mod test {
         assert_eq!(v.as_ref().unwrap().1, packet.len());
 
         assert_eq!(
-            (8 + 12) * target.instrument_list.len(), // 8 header + 12 data
+            (8 + 63) * target.instrument_list.len(), // 8 header + 63 data
             v.as_ref().unwrap().0.len()
         );
     }
 
     #[test]
     fn request_all_instruments_but_no_instruments_resuts_in_empty_response() {
-        let markets = Rc::new(RefCell::new(HashMap::<u64, Market>::new()));
+        let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
         let mut target = ClearProtocol::new(
             InstrumentList::new(),
             markets.clone(),
-            Rc::new(RefCell::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
 
         #[rustfmt::skip]
@@ -528,4 +1711,111 @@ mod test {
 
         assert_eq!(0, v.as_ref().unwrap().0.len());
     }
+
+    #[test]
+    fn short_entries_are_errors() {
+        let mut target = ClearProtocol::forwarding(InstrumentList::new());
+        // the fixed fields of an instrument update are 63 bytes
+        #[rustfmt::skip]
+        let packet = [
+            b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, // technical header
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 10, 0, // Instrument update, Len: 10
+            8, 7, 6, 5, 4, 3, 2, 1, 0, 2,
+        ];
+        let e = target.process(&packet).unwrap_err();
+        assert_eq!("Invalid instrument update length 10", e.to_string());
+        assert!(target.take_market_updates().is_empty());
+
+        // not a valid name
+        #[rustfmt::skip]
+        let packet = [
+            b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, // technical header
+            CLEAR_TYPE_INSTRUMENT_UPDATE as u8, 0, 64, 0, // Instrument update, Len: 64
+            8, 7, 6, 5, 4, 3, 2, 1, 0, 2, 20, 25,
+            1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff,
+        ];
+        assert!(target.process(&packet).is_err());
+
+        target.set_protocol_side(ProtocolSide::Server);
+        target.set_session(Some(1));
+        #[rustfmt::skip]
+        let packet = [
+            b'C', b'P', CLEAR_PROTOCOL_VERSION, 1, // technical header
+            CLEAR_TYPE_EOD_SUMMARY as u8, 0, 4, 0, // EOD summary, Len: 4
+            1, 2, 3, 4,
+        ];
+        assert!(target.process(&packet).is_err());
+        assert!(target.take_eod_summaries().is_empty());
+    }
+}
+
+/// Whatever the other side sends, the processing fails instead of panicking
+#[cfg(test)]
+mod properties {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use disseminator::mockdisseminator::MockDisseminator;
+    use instruments::genericinstrumentlist::GenericInstrumentList;
+    use instruments::instrumentlist::InstrumentList;
+    use market::{orderid::OrderIdGenerator, Market};
+    use proptest::prelude::*;
+
+    use super::{ClearProtocol, CLEAR_PROTOCOL_VERSION};
+    use crate::genericclearingprotocol::{GenericClearingProtocol, ProtocolSide};
+
+    /// An entry of any type up to the known ones, its length right or not
+    fn entry() -> impl Strategy<Value = Vec<u8>> {
+        (
+            0u16..13,
+            prop::collection::vec(any::<u8>(), 0..80),
+            prop_oneof![Just(None), any::<u16>().prop_map(Some)],
+        )
+            .prop_map(|(data_type, data, len)| {
+                let len = len.unwrap_or(data.len() as u16);
+                let mut r = data_type.to_le_bytes().to_vec();
+                r.extend_from_slice(&len.to_le_bytes());
+                r.extend(data);
+                r
+            })
+    }
+
+    /// A packet of @entries, announcing their count or any other
+    fn packet() -> impl Strategy<Value = Vec<u8>> {
+        (
+            prop::collection::vec(entry(), 0..5),
+            prop::option::of(any::<u8>()),
+        )
+            .prop_map(|(entries, count)| {
+                let count = count.unwrap_or(entries.len() as u8);
+                let mut r = vec![b'C', b'P', CLEAR_PROTOCOL_VERSION, count];
+                r.extend(entries.concat());
+                r
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn process_any_packet(
+            buffer in prop_oneof![packet(), prop::collection::vec(any::<u8>(), 0..100)],
+            side in prop_oneof![Just(ProtocolSide::Client), Just(ProtocolSide::Server)],
+        ) {
+            let mut local = ClearProtocol::new(
+                InstrumentList::new(),
+                Arc::new(Mutex::new(HashMap::<u64, Market>::new())),
+                Arc::new(Mutex::new(MockDisseminator::new())),
+                Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+            );
+            let mut forwarding = ClearProtocol::forwarding(InstrumentList::new());
+            local.set_protocol_side(side);
+            forwarding.set_protocol_side(side);
+            for target in [&mut local as &mut dyn GenericClearingProtocol, &mut forwarding] {
+                if let Ok((_, processed)) = target.process(&buffer) {
+                    prop_assert!(processed <= buffer.len());
+                }
+            }
+        }
+    }
 }
@@ -1,7 +1,10 @@
 use core::fmt;
 use std::{error::Error, str};
 
-use instruments::instrument::Instrument;
+use instruments::instrument::{Instrument, InstrumentState};
+use oep::eodsummary::EodSummary;
+use oep::tradecapture::TradeCapture;
+use order::Side;
 
 #[derive(Debug)]
 pub struct ProcessError {
@@ -30,14 +33,82 @@ pub enum ProtocolSide {
     Server,
 }
 
+/// A change to a market, for the engines whose markets are not kept by the
+/// protocol, e.g. spread over shards
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarketUpdate {
+    // created or updated by the clearing
+    Instrument(Instrument),
+    // @blocked_side is the side @participant can't add risk on, None lifts the block
+    Exposure {
+        participant: u64,
+        book_id: u64,
+        blocked_side: Option<Side>,
+    },
+    // the instrument was deleted by the clearing, its market goes
+    Deleted(u64),
+    // the trade @trade_id of the book is cancelled
+    TradeBust {
+        book_id: u64,
+        trade_id: u64,
+    },
+    // all the instruments of @segment go to @state
+    Segment {
+        segment: u16,
+        state: InstrumentState,
+    },
+}
+
 pub trait GenericClearingProtocol {
     fn process(&mut self, buffer: &[u8]) -> Result<(Vec<u8>, usize), ProcessError>;
     fn clone_instrument_list(&self) -> Vec<Instrument>;
     fn add_instrument(&mut self, i: Instrument);
+    fn remove_instrument(&mut self, instrument_id: u64);
+    // end of day summaries received so far, emptying the internal list
+    fn take_eod_summaries(&mut self) -> Vec<EodSummary>;
+    // trade captures received so far, emptying the internal list
+    fn take_trade_captures(&mut self) -> Vec<TradeCapture>;
+    // market updates not applied by the protocol itself, emptying the internal list
+    fn take_market_updates(&mut self) -> Vec<MarketUpdate>;
+    // stamps @capture with the next sequence and keeps it until the server acks it
+    fn sequence_trade_capture(&mut self, capture: TradeCapture) -> TradeCapture;
+    // the captures sent but not acked yet, oldest first
+    fn unacked_trade_captures(&self) -> Vec<TradeCapture>;
+    // whether a snapshot was requested since the last call
+    fn take_snapshot_request(&mut self) -> bool;
 
     // Generic messages
     fn prepare_heartbeat(&self) -> Vec<u8>;
     fn prepare_all_instrument_request(&self) -> Vec<u8>;
+    fn prepare_instrument_request(&self, instrument_id: u64) -> Vec<u8>;
     fn prepare_instrument_update_response(&self, instrument: &Instrument) -> Vec<u8>;
+    fn prepare_eod_summary(&self, summary: &EodSummary) -> Vec<u8>;
+    fn prepare_trade_capture(&self, capture: &TradeCapture) -> Vec<u8>;
+    // acks all the captures up to @seq
+    fn prepare_trade_capture_ack(&self, seq: u64) -> Vec<u8>;
+    // @blocked_side is the side @participant can't add risk on, None lifts the block
+    fn prepare_exposure_update(
+        &self,
+        participant: u64,
+        book_id: u64,
+        blocked_side: Option<Side>,
+    ) -> Vec<u8>;
+    // tells the engines the instrument is gone, and its market with it
+    fn prepare_instrument_deletion(&self, instrument_id: u64) -> Vec<u8>;
+    // answers an instrument request for an id the clearing doesn't have
+    fn prepare_instrument_not_found(&self, instrument_id: u64) -> Vec<u8>;
+    // asks the engines to save the state of their markets right away
+    fn prepare_snapshot_request(&self) -> Vec<u8>;
+    // tells the engines to cancel the trade @trade_id of @book_id
+    fn prepare_trade_bust(&self, book_id: u64, trade_id: u64) -> Vec<u8>;
+    // tells the engines to put all the instruments of @segment in @state
+    fn prepare_segment_state(&self, segment: u16, state: InstrumentState) -> Vec<u8>;
     fn set_protocol_side(&mut self, side: ProtocolSide);
+    // the first message of an engine, nothing without credentials
+    fn prepare_logon(&self) -> Vec<u8>;
+    // the engine logged on the connection the server side processes next,
+    // None before its logon
+    fn set_session(&mut self, engine_id: Option<u8>);
+    // the engine logged on the connection processed last
+    fn get_session(&self) -> Option<u8>;
 }
@@ -2,6 +2,8 @@ pub mod clearclearingconnection;
 pub mod clearingconnection;
 pub mod clearprotocol;
 pub mod genericclearingprotocol;
+pub mod liveness;
+pub mod logon;
 
 #[cfg(test)]
 pub mod mockclearingconnection;
@@ -0,0 +1,159 @@
+//! Supervision of a Clear connection: both sides send heartbeats when they have
+//! nothing else to say, and give up on a peer that stays silent for too long.
+
+use std::{
+    collections::HashMap,
+    num::ParseIntError,
+    time::{Duration, Instant},
+};
+
+use utils::config::get_optional_config_string;
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LivenessConfig {
+    // how often a heartbeat is sent
+    pub heartbeat_interval: Duration,
+    // the peer is considered gone if nothing came from it for this long
+    pub timeout: Duration,
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_millis(1000),
+            timeout: Duration::from_millis(5000),
+        }
+    }
+}
+
+impl LivenessConfig {
+    /// Reads the heartbeat_interval_ms and heartbeat_timeout_ms keys of @section
+    pub fn from_config(config_map: &ConfigMap, section: &str) -> Result<Self, ParseIntError> {
+        let default = Self::default();
+        let optional = |key: &str| get_optional_config_string(config_map, section, key);
+        Ok(Self {
+            heartbeat_interval: match optional("heartbeat_interval_ms") {
+                Some(v) => Duration::from_millis(v.parse::<u64>()?),
+                None => default.heartbeat_interval,
+            },
+            timeout: match optional("heartbeat_timeout_ms") {
+                Some(v) => Duration::from_millis(v.parse::<u64>()?),
+                None => default.timeout,
+            },
+        })
+    }
+}
+
+/// The heartbeat timers of one connection
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    config: LivenessConfig,
+    // None once the peer closed the connection
+    last_seen: Option<Instant>,
+    last_heartbeat: Instant,
+}
+
+impl Liveness {
+    /// Starts the timers of a connection established at @now
+    pub fn new(config: LivenessConfig, now: Instant) -> Self {
+        Self {
+            config,
+            last_seen: Some(now),
+            last_heartbeat: now,
+        }
+    }
+
+    /// Anything received from the peer proves it alive
+    pub fn on_received(&mut self, now: Instant) {
+        self.last_seen = Some(now);
+    }
+
+    /// The peer is gone, without waiting for the timeout
+    pub fn on_closed(&mut self) {
+        self.last_seen = None;
+    }
+
+    /// Whether a heartbeat has to be sent at @now, in which case it is
+    /// accounted for as sent
+    pub fn heartbeat_due(&mut self, now: Instant) -> bool {
+        if now.saturating_duration_since(self.last_heartbeat) < self.config.heartbeat_interval {
+            return false;
+        }
+        self.last_heartbeat = now;
+        true
+    }
+
+    pub fn is_silent(&self, now: Instant) -> bool {
+        self.last_seen
+            .is_none_or(|seen| now.saturating_duration_since(seen) > self.config.timeout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use configparser::ini::Ini;
+
+    use super::{Liveness, LivenessConfig};
+
+    fn config() -> LivenessConfig {
+        LivenessConfig {
+            heartbeat_interval: Duration::from_millis(100),
+            timeout: Duration::from_millis(300),
+        }
+    }
+
+    #[test]
+    fn config_defaults() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[clearing]
+                heartbeat_timeout_ms=2000",
+            ))
+            .unwrap();
+        let target = LivenessConfig::from_config(&config_map, "clearing").unwrap();
+        assert_eq!(
+            LivenessConfig::default().heartbeat_interval,
+            target.heartbeat_interval
+        );
+        assert_eq!(Duration::from_millis(2000), target.timeout);
+
+        let config_map = Ini::new()
+            .read(String::from(
+                "[clearing]
+                heartbeat_interval_ms=often",
+            ))
+            .unwrap();
+        assert!(LivenessConfig::from_config(&config_map, "clearing").is_err());
+    }
+
+    #[test]
+    fn heartbeats() {
+        let start = Instant::now();
+        let mut target = Liveness::new(config(), start);
+        assert!(!target.heartbeat_due(start));
+        assert!(!target.heartbeat_due(start + Duration::from_millis(99)));
+        assert!(target.heartbeat_due(start + Duration::from_millis(100)));
+        // just sent
+        assert!(!target.heartbeat_due(start + Duration::from_millis(150)));
+        assert!(target.heartbeat_due(start + Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn silence() {
+        let start = Instant::now();
+        let mut target = Liveness::new(config(), start);
+        assert!(!target.is_silent(start + Duration::from_millis(300)));
+        assert!(target.is_silent(start + Duration::from_millis(301)));
+
+        target.on_received(start + Duration::from_millis(200));
+        assert!(!target.is_silent(start + Duration::from_millis(400)));
+        assert!(target.is_silent(start + Duration::from_millis(501)));
+
+        target.on_closed();
+        assert!(target.is_silent(start + Duration::from_millis(200)));
+    }
+}
@@ -0,0 +1,105 @@
+//! The logon of the matching engines to the clearing
+//!
+//! An engine opens its Clear connection with a logon carrying its engine id
+//! and the secret it shares with the clearing. The clearing only serves the
+//! connections logged on this way, the heartbeats aside.
+
+use std::{collections::HashMap, num::ParseIntError};
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+/// What an engine logs on with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineCredentials {
+    pub engine_id: u8,
+    pub secret: String,
+}
+
+/// The engines allowed to log on to the clearing, with their secrets
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineSecrets {
+    // engine id -> its secret
+    secrets: HashMap<u8, String>,
+}
+
+impl EngineSecrets {
+    /// Loads the [engines] section, each key an engine id and its value the
+    /// secret of the engine. Without it, no engine can log on
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self, ParseIntError> {
+        let mut r = Self::default();
+        let Some(section) = config_map.get("engines") else {
+            return Ok(r);
+        };
+        for (key, value) in section {
+            let secret = value.as_deref().unwrap_or_default();
+            // an empty secret would let anyone in
+            if !secret.is_empty() {
+                r.secrets.insert(key.parse::<u8>()?, secret.to_string());
+            }
+        }
+        Ok(r)
+    }
+
+    pub fn insert(&mut self, engine_id: u8, secret: &str) {
+        self.secrets.insert(engine_id, secret.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+
+    /// Whether @secret is the one of @engine_id, compared in constant time
+    pub fn check(&self, engine_id: u8, secret: &[u8]) -> bool {
+        let Some(expected) = self.secrets.get(&engine_id) else {
+            return false;
+        };
+        let expected = expected.as_bytes();
+        expected.len() == secret.len()
+            && expected
+                .iter()
+                .zip(secret)
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use configparser::ini::Ini;
+
+    use super::EngineSecrets;
+
+    #[test]
+    fn secrets() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[engines]
+                1=first
+                2=second
+                3=",
+            ))
+            .unwrap();
+        let target = EngineSecrets::from_config(&config_map).unwrap();
+        assert!(target.check(1, b"first"));
+        assert!(target.check(2, b"second"));
+        assert!(!target.check(1, b"second"));
+        assert!(!target.check(1, b"firs"));
+        assert!(!target.check(1, b"first "));
+        // no secret, no logon
+        assert!(!target.check(3, b""));
+        assert!(!target.check(4, b"first"));
+
+        assert!(
+            EngineSecrets::from_config(&Ini::new().read(String::new()).unwrap())
+                .unwrap()
+                .is_empty()
+        );
+        let config_map = Ini::new()
+            .read(String::from(
+                "[engines]
+                first=1",
+            ))
+            .unwrap();
+        assert!(EngineSecrets::from_config(&config_map).is_err());
+    }
+}
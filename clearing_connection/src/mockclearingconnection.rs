@@ -5,7 +5,7 @@ use socket2::Socket;
 
 use crate::clearingconnection::ClearingConnection;
 
-use super::genericclearingprotocol::GenericClearingProtocol;
+use super::genericclearingprotocol::{GenericClearingProtocol, MarketUpdate};
 
 pub struct MockClearingConnection {}
 
@@ -18,6 +18,10 @@ impl ClearingConnection for MockClearingConnection {
         Ok(())
     }
 
+    fn reconnect(&mut self, _poller: &polling::Poller) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
     fn listen(&mut self) -> Result<(), Box<dyn Error>> {
         todo!()
     }
@@ -28,6 +32,10 @@ impl ClearingConnection for MockClearingConnection {
         // Ok(1)
     }
 
+    fn request_instrument(&self, _instrument_id: u64) -> Result<usize, Box<dyn Error>> {
+        todo!()
+    }
+
     fn register_with_poller(&mut self, _poller: &polling::Poller) -> io::Result<()> {
         Ok(())
     }
@@ -48,6 +56,14 @@ impl ClearingConnection for MockClearingConnection {
         todo!()
     }
 
+    fn set_session(&mut self, _engine_id: Option<u8>) {
+        todo!()
+    }
+
+    fn get_session(&self) -> Option<u8> {
+        todo!()
+    }
+
     fn accept(&self) -> io::Result<(socket2::Socket, socket2::SockAddr)> {
         todo!()
     }
@@ -55,6 +71,37 @@ impl ClearingConnection for MockClearingConnection {
     fn add_instrument(&mut self, _i: Instrument) {
         todo!()
     }
+
+    fn remove_instrument(&mut self, _instrument_id: u64) {
+        todo!()
+    }
+
+    fn take_eod_summaries(&mut self) -> Vec<oep::eodsummary::EodSummary> {
+        vec![]
+    }
+
+    fn take_trade_captures(&mut self) -> Vec<oep::tradecapture::TradeCapture> {
+        vec![]
+    }
+
+    fn take_market_updates(&mut self) -> Vec<MarketUpdate> {
+        vec![]
+    }
+
+    fn take_snapshot_request(&mut self) -> bool {
+        false
+    }
+
+    fn send_trade_capture(
+        &mut self,
+        _capture: oep::tradecapture::TradeCapture,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn resend_trade_captures(&mut self, _timestamp: u64) -> Result<usize, Box<dyn Error>> {
+        Ok(0)
+    }
 }
 
 impl std::io::Read for MockClearingConnection {
@@ -0,0 +1,1061 @@
+//! The admin API of the clearing, over HTTP
+//!
+//! The instruments are created and changed in the database, then pushed to the
+//! matching engines as Clear protocol instrument updates right away, instead
+//! of waiting for the next instrument refresh. The engines can also be asked
+//! to save a snapshot of their markets. See doc/clear_protocol.md.
+//!
+//! The users logging in the gateways are created there too, their passwords
+//! stored hashed, see dbhook::password.
+//!
+//! The requests are read by a thread of their own and handed over to the main
+//! loop of the clearing, which owns the database and the engine connections.
+
+use std::{
+    collections::HashMap,
+    net::TcpListener,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use dbhook::genericdb::{GenericDB, NewUser};
+use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+use polling::Poller;
+use tracing::{error, info};
+use utils::{
+    config::get_optional_config_string,
+    json::{escape, field},
+};
+
+use crate::http::{self, Request, Response};
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+// for a client that doesn't send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the admin API listens, given by the optional [admin] section
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminConfig {
+    pub address: String,
+    pub port: u16,
+}
+
+impl AdminConfig {
+    /// None without a port, the admin API being off
+    pub fn from_config(config_map: &ConfigMap) -> Result<Option<Self>> {
+        let Some(port) = get_optional_config_string(config_map, "admin", "port") else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            address: get_optional_config_string(config_map, "admin", "address")
+                .unwrap_or(String::from("127.0.0.1")),
+            port: port.parse()?,
+        }))
+    }
+}
+
+/// The fields of an instrument to change, the others being left alone
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstrumentChanges {
+    pub name: Option<String>,
+    pub i_type: Option<InstrumentType>,
+    pub state: Option<InstrumentState>,
+    pub percentage_bands: Option<u8>,
+    pub percentage_variation: Option<u8>,
+    pub segment: Option<u16>,
+    pub expiry: Option<u64>,
+    pub strike: Option<u64>,
+    pub underlying: Option<u64>,
+}
+
+impl InstrumentChanges {
+    /// The fields given by the JSON object @body
+    pub fn from_json(body: &str) -> Result<Self> {
+        let percentage = |key: &str| {
+            field(body, key)
+                .map(|value| {
+                    value
+                        .parse::<u8>()
+                        .map_err(|_| anyhow!("Invalid {key} {value}"))
+                })
+                .transpose()
+        };
+        let number = |key: &str| {
+            field(body, key)
+                .map(|value| {
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| anyhow!("Invalid {key} {value}"))
+                })
+                .transpose()
+        };
+        Ok(Self {
+            name: field(body, "name").map(String::from),
+            i_type: field(body, "type").map(parse_type).transpose()?,
+            state: field(body, "state").map(parse_state).transpose()?,
+            percentage_bands: percentage("percentage_bands")?,
+            percentage_variation: percentage("percentage_variation")?,
+            segment: field(body, "segment")
+                .map(|value| {
+                    value
+                        .parse::<u16>()
+                        .map_err(|_| anyhow!("Invalid segment {value}"))
+                })
+                .transpose()?,
+            expiry: number("expiry")?,
+            strike: number("strike")?,
+            underlying: number("underlying")?,
+        })
+    }
+
+    pub fn apply(&self, instrument: &Instrument) -> Instrument {
+        let mut r = Instrument::new(
+            instrument.get_id(),
+            self.name.as_deref().unwrap_or(instrument.get_name()),
+            self.i_type.unwrap_or(instrument.get_type()),
+            self.state.unwrap_or(instrument.get_state()),
+            self.percentage_bands
+                .unwrap_or(instrument.get_percentage_bands()),
+            self.percentage_variation
+                .unwrap_or(instrument.get_percentage_variation_allowed()),
+        );
+        r.set_tick_size(instrument.get_tick_size());
+        r.set_round_lot(instrument.get_round_lot());
+        r.set_previous_close(instrument.get_previous_close());
+        r.set_price_decimals(instrument.get_price_decimals());
+        r.set_segment(self.segment.unwrap_or(instrument.get_segment()));
+        r.set_expiry(self.expiry.unwrap_or(instrument.get_expiry()));
+        r.set_strike(self.strike.unwrap_or(instrument.get_strike()));
+        r.set_underlying(self.underlying.unwrap_or(instrument.get_underlying()));
+        r
+    }
+}
+
+fn parse_type(value: &str) -> Result<InstrumentType> {
+    match value.to_lowercase().as_str() {
+        "share" => Ok(InstrumentType::Share),
+        "option_call" => Ok(InstrumentType::OptionCall),
+        "option_put" => Ok(InstrumentType::OptionPut),
+        "future" => Ok(InstrumentType::Future),
+        "warrant" => Ok(InstrumentType::Warrant),
+        _ => Err(anyhow!("Invalid type {value}")),
+    }
+}
+
+fn type_name(i_type: InstrumentType) -> &'static str {
+    match i_type {
+        InstrumentType::Share => "share",
+        InstrumentType::OptionCall => "option_call",
+        InstrumentType::OptionPut => "option_put",
+        InstrumentType::Future => "future",
+        InstrumentType::Warrant => "warrant",
+    }
+}
+
+fn parse_state(value: &str) -> Result<InstrumentState> {
+    match value.to_lowercase().as_str() {
+        "trading" => Ok(InstrumentState::Trading),
+        "closed" => Ok(InstrumentState::Closed),
+        "auction" => Ok(InstrumentState::Auction),
+        "halted" => Ok(InstrumentState::Halted),
+        "pre_open" => Ok(InstrumentState::PreOpen),
+        _ => Err(anyhow!("Invalid state {value}")),
+    }
+}
+
+fn state_name(state: InstrumentState) -> &'static str {
+    match state {
+        InstrumentState::Trading => "trading",
+        InstrumentState::Closed => "closed",
+        InstrumentState::Auction => "auction",
+        InstrumentState::Halted => "halted",
+        InstrumentState::PreOpen => "pre_open",
+    }
+}
+
+fn instrument_json(instrument: &Instrument) -> String {
+    format!(
+        "{{\"id\":{},\"name\":\"{}\",\"type\":\"{}\",\"state\":\"{}\",\
+         \"percentage_bands\":{},\"percentage_variation\":{},\"segment\":{},\
+         \"expiry\":{},\"strike\":{},\"underlying\":{}}}",
+        instrument.get_id(),
+        escape(instrument.get_name()),
+        type_name(instrument.get_type()),
+        state_name(instrument.get_state()),
+        instrument.get_percentage_bands(),
+        instrument.get_percentage_variation_allowed(),
+        instrument.get_segment(),
+        instrument.get_expiry(),
+        instrument.get_strike(),
+        instrument.get_underlying()
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminCommand {
+    ListInstruments,
+    CreateInstrument(Instrument),
+    UpdateInstrument(u64, InstrumentChanges),
+    DeleteInstrument(u64),
+    // of the state of the markets, by the matching engines
+    Snapshot,
+    // closes all the instruments still open
+    EndOfDay,
+    // puts all the instruments of the segment in the state
+    SetSegmentState(u16, InstrumentState),
+    // book id, trade id
+    BustTrade(u64, u64),
+    CreateUser(NewUser),
+    // username, session id, new password
+    SetPassword(String, u32, String),
+}
+
+impl AdminCommand {
+    /// The command asked by @request, or the response to an invalid one
+    pub fn route(request: &Request) -> Result<Self, Response> {
+        let segments: Vec<&str> = request
+            .path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+        let bad_request = |e: anyhow::Error| Response::error(400, &e.to_string());
+        let book_id = |id: &str| {
+            id.parse::<u64>()
+                .map_err(|_| Response::error(404, &format!("Invalid instrument {id}")))
+        };
+        // 0 is no segment
+        let segment = |segment: &str| {
+            segment
+                .parse::<u16>()
+                .ok()
+                .filter(|segment| *segment != 0)
+                .ok_or(Response::error(404, &format!("Invalid segment {segment}")))
+        };
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["instruments"]) => Ok(Self::ListInstruments),
+            ("POST", ["instruments"]) => {
+                let id = field(&request.body, "id")
+                    .and_then(|id| id.parse::<u64>().ok())
+                    .ok_or(Response::error(400, "Missing or invalid id"))?;
+                let (Some(name), Some(i_type)) =
+                    (field(&request.body, "name"), field(&request.body, "type"))
+                else {
+                    return Err(Response::error(400, "Missing name or type"));
+                };
+                let instrument = Instrument::new(
+                    id,
+                    name,
+                    parse_type(i_type).map_err(bad_request)?,
+                    InstrumentState::Closed,
+                    0,
+                    0,
+                );
+                let changes = InstrumentChanges::from_json(&request.body).map_err(bad_request)?;
+                Ok(Self::CreateInstrument(changes.apply(&instrument)))
+            }
+            ("PATCH", ["instruments", id]) => Ok(Self::UpdateInstrument(
+                book_id(id)?,
+                InstrumentChanges::from_json(&request.body).map_err(bad_request)?,
+            )),
+            ("DELETE", ["instruments", id]) => Ok(Self::DeleteInstrument(book_id(id)?)),
+            ("POST", ["instruments", id, action @ ("halt" | "resume")]) => {
+                Ok(Self::UpdateInstrument(
+                    book_id(id)?,
+                    InstrumentChanges {
+                        state: Some(match *action {
+                            "halt" => InstrumentState::Halted,
+                            _ => InstrumentState::Trading,
+                        }),
+                        ..Default::default()
+                    },
+                ))
+            }
+            ("PUT", ["instruments", id, "bands"]) => {
+                let changes = InstrumentChanges::from_json(&request.body).map_err(bad_request)?;
+                if changes.percentage_bands.is_none() && changes.percentage_variation.is_none() {
+                    return Err(Response::error(
+                        400,
+                        "Missing percentage_bands or percentage_variation",
+                    ));
+                }
+                Ok(Self::UpdateInstrument(
+                    book_id(id)?,
+                    InstrumentChanges {
+                        percentage_bands: changes.percentage_bands,
+                        percentage_variation: changes.percentage_variation,
+                        ..Default::default()
+                    },
+                ))
+            }
+            ("POST", ["instruments", id, "trades", trade_id, "bust"]) => {
+                let trade_id = trade_id
+                    .parse::<u64>()
+                    .map_err(|_| Response::error(404, &format!("Invalid trade {trade_id}")))?;
+                Ok(Self::BustTrade(book_id(id)?, trade_id))
+            }
+            ("POST", ["segments", id, action @ ("halt" | "resume")]) => Ok(Self::SetSegmentState(
+                segment(id)?,
+                match *action {
+                    "halt" => InstrumentState::Halted,
+                    _ => InstrumentState::Trading,
+                },
+            )),
+            ("PUT", ["segments", id, "state"]) => {
+                let state =
+                    field(&request.body, "state").ok_or(Response::error(400, "Missing state"))?;
+                Ok(Self::SetSegmentState(
+                    segment(id)?,
+                    parse_state(state).map_err(bad_request)?,
+                ))
+            }
+            ("POST", ["snapshot"]) => Ok(Self::Snapshot),
+            ("POST", ["eod"]) => Ok(Self::EndOfDay),
+            ("POST", ["users"]) => {
+                let number = |key: &str| {
+                    field(&request.body, key)
+                        .and_then(|value| value.parse::<u64>().ok())
+                        .ok_or(Response::error(400, &format!("Missing or invalid {key}")))
+                };
+                let (Some(username), Some(password)) = (
+                    field(&request.body, "username"),
+                    field(&request.body, "password"),
+                ) else {
+                    return Err(Response::error(400, "Missing username or password"));
+                };
+                let session_id = u32::try_from(number("session_id")?)
+                    .map_err(|_| Response::error(400, "Missing or invalid session_id"))?;
+                Ok(Self::CreateUser(NewUser {
+                    username: String::from(username),
+                    password: String::from(password),
+                    session_id,
+                    participant: number("participant")?,
+                }))
+            }
+            ("PUT", ["users", username, session_id, "password"]) => {
+                let session_id = session_id
+                    .parse::<u32>()
+                    .map_err(|_| Response::error(404, &format!("Invalid session {session_id}")))?;
+                let password = field(&request.body, "password")
+                    .ok_or(Response::error(400, "Missing password"))?;
+                Ok(Self::SetPassword(
+                    String::from(*username),
+                    session_id,
+                    String::from(password),
+                ))
+            }
+            (
+                _,
+                ["instruments"]
+                | ["instruments", _]
+                | ["instruments", _, "halt" | "resume" | "bands"]
+                | ["instruments", _, "trades", _, "bust"]
+                | ["segments", _, "halt" | "resume" | "state"]
+                | ["snapshot"]
+                | ["eod"]
+                | ["users"]
+                | ["users", _, _, "password"],
+            ) => Err(Response::error(405, "Method not allowed")),
+            _ => Err(Response::error(404, "Not found")),
+        }
+    }
+}
+
+/// What the matching engines are sent once a command succeeded
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnginePush {
+    Instrument(Instrument),
+    // the id of the instrument deleted
+    Deleted(u64),
+    SnapshotRequest,
+    // the instruments closed at the end of the day
+    Closed(Vec<Instrument>),
+    // book id, trade id
+    TradeBust(u64, u64),
+    // the segment, its new state and its instruments in that state
+    Segment(u16, InstrumentState, Vec<Instrument>),
+}
+
+/// Runs @command against @db, returning the response of the admin and what
+/// the engines have to be sent
+pub fn execute(command: AdminCommand, db: &mut dyn GenericDB) -> (Response, Option<EnginePush>) {
+    let stored = |db: &mut dyn GenericDB, instrument: Instrument, status: u16| match db
+        .store_instrument(&instrument)
+    {
+        Ok(()) => (
+            Response::new(status, instrument_json(&instrument)),
+            Some(EnginePush::Instrument(instrument)),
+        ),
+        Err(e) => (Response::error(500, &e.to_string()), None),
+    };
+    match command {
+        AdminCommand::ListInstruments => {
+            let mut instruments = db.get_instruments();
+            instruments.sort_by_key(|i| i.get_id());
+            let instruments: Vec<String> = instruments.iter().map(instrument_json).collect();
+            (
+                Response::new(
+                    200,
+                    format!("{{\"instruments\":[{}]}}", instruments.join(",")),
+                ),
+                None,
+            )
+        }
+        AdminCommand::CreateInstrument(instrument) => {
+            let id = instrument.get_id();
+            if db.get_instruments().iter().any(|i| i.get_id() == id) {
+                return (
+                    Response::error(409, &format!("The instrument {id} already exists")),
+                    None,
+                );
+            }
+            stored(db, instrument, 201)
+        }
+        AdminCommand::UpdateInstrument(id, changes) => {
+            match db.get_instruments().into_iter().find(|i| i.get_id() == id) {
+                Some(instrument) => stored(db, changes.apply(&instrument), 200),
+                None => (Response::error(404, &format!("No instrument {id}")), None),
+            }
+        }
+        AdminCommand::DeleteInstrument(id) => {
+            if !db.get_instruments().iter().any(|i| i.get_id() == id) {
+                return (Response::error(404, &format!("No instrument {id}")), None);
+            }
+            match db.delete_instrument(id) {
+                Ok(()) => (
+                    Response::new(200, format!("{{\"deleted\":{id}}}")),
+                    Some(EnginePush::Deleted(id)),
+                ),
+                Err(e) => (Response::error(500, &e.to_string()), None),
+            }
+        }
+        AdminCommand::Snapshot => (
+            Response::new(202, String::from("{\"snapshot\":\"requested\"}")),
+            Some(EnginePush::SnapshotRequest),
+        ),
+        AdminCommand::EndOfDay => {
+            let mut instruments = db.get_instruments();
+            instruments.sort_by_key(|i| i.get_id());
+            let mut closed = vec![];
+            for mut instrument in instruments {
+                if instrument.get_state() == InstrumentState::Closed {
+                    continue;
+                }
+                instrument.set_state(InstrumentState::Closed);
+                if let Err(e) = db.store_instrument(&instrument) {
+                    // the ones stored already are closed all the same
+                    let push = (!closed.is_empty()).then_some(EnginePush::Closed(closed));
+                    return (Response::error(500, &e.to_string()), push);
+                }
+                closed.push(instrument);
+            }
+            let ids: Vec<String> = closed.iter().map(|i| i.get_id().to_string()).collect();
+            (
+                Response::new(200, format!("{{\"closed\":[{}]}}", ids.join(","))),
+                (!closed.is_empty()).then_some(EnginePush::Closed(closed)),
+            )
+        }
+        AdminCommand::SetSegmentState(segment, state) => {
+            let mut instruments: Vec<Instrument> = db
+                .get_instruments()
+                .into_iter()
+                .filter(|i| i.get_segment() == segment)
+                .collect();
+            if instruments.is_empty() {
+                return (
+                    Response::error(404, &format!("No instrument in segment {segment}")),
+                    None,
+                );
+            }
+            instruments.sort_by_key(|i| i.get_id());
+            for instrument in instruments.iter_mut() {
+                instrument.set_state(state);
+                if let Err(e) = db.store_instrument(instrument) {
+                    // the ones stored already reach the engines with the next refresh
+                    return (Response::error(500, &e.to_string()), None);
+                }
+            }
+            let ids: Vec<String> = instruments.iter().map(|i| i.get_id().to_string()).collect();
+            (
+                Response::new(
+                    200,
+                    format!(
+                        "{{\"segment\":{segment},\"state\":\"{}\",\"instruments\":[{}]}}",
+                        state_name(state),
+                        ids.join(",")
+                    ),
+                ),
+                Some(EnginePush::Segment(segment, state, instruments)),
+            )
+        }
+        AdminCommand::BustTrade(book_id, trade_id) => {
+            if !db.get_instruments().iter().any(|i| i.get_id() == book_id) {
+                return (
+                    Response::error(404, &format!("No instrument {book_id}")),
+                    None,
+                );
+            }
+            // whether the trade is still there to bust is up to the engine
+            (
+                Response::new(
+                    202,
+                    format!(
+                        "{{\"book_id\":{book_id},\"trade_id\":{trade_id},\"bust\":\"requested\"}}"
+                    ),
+                ),
+                Some(EnginePush::TradeBust(book_id, trade_id)),
+            )
+        }
+        AdminCommand::CreateUser(user) => match db.create_user(&user) {
+            Ok(true) => (
+                Response::new(
+                    201,
+                    format!(
+                        "{{\"username\":\"{}\",\"session_id\":{},\"participant\":{}}}",
+                        escape(&user.username),
+                        user.session_id,
+                        user.participant
+                    ),
+                ),
+                None,
+            ),
+            Ok(false) => (
+                Response::error(
+                    409,
+                    &format!(
+                        "The user {} already exists on session {}",
+                        user.username, user.session_id
+                    ),
+                ),
+                None,
+            ),
+            Err(e) => (Response::error(500, &e.to_string()), None),
+        },
+        AdminCommand::SetPassword(username, session_id, password) => {
+            match db.set_password(&username, session_id, &password) {
+                Ok(true) => (
+                    Response::new(
+                        200,
+                        format!(
+                            "{{\"username\":\"{}\",\"session_id\":{session_id}}}",
+                            escape(&username)
+                        ),
+                    ),
+                    None,
+                ),
+                Ok(false) => (
+                    Response::error(404, &format!("No user {username} on session {session_id}")),
+                    None,
+                ),
+                Err(e) => (Response::error(500, &e.to_string()), None),
+            }
+        }
+    }
+}
+
+/// A command and where its response goes
+pub type AdminRequest = (AdminCommand, Sender<Response>);
+
+/// Serves the admin API on a thread of its own, handing the commands over
+/// to the receiver returned and waking up @poller for each
+pub fn spawn(config: &AdminConfig, poller: Arc<Poller>) -> Result<Receiver<AdminRequest>> {
+    let listener = TcpListener::bind((config.address.as_str(), config.port))?;
+    info!(address = %listener.local_addr()?, "Serving the admin API");
+    let (requests, received) = mpsc::channel();
+    thread::spawn(move || {
+        // one at a time, there's no hurry for the admin
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!(error = %e, "Error accepting an admin connection");
+                    continue;
+                }
+            };
+            let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+            let response = match http::read_request(&mut stream) {
+                Ok(request) => match AdminCommand::route(&request) {
+                    Ok(command) => {
+                        let (reply, response) = mpsc::channel();
+                        if requests.send((command, reply)).is_err() {
+                            // the clearing is going down
+                            return;
+                        }
+                        let _ = poller.notify();
+                        response
+                            .recv()
+                            .unwrap_or(Response::error(503, "The clearing is going down"))
+                    }
+                    Err(response) => response,
+                },
+                Err(e) => Response::error(400, &e.to_string()),
+            };
+            if let Err(e) = http::write_response(&mut stream, &response) {
+                error!(error = %e, "Error answering the admin");
+            }
+        }
+    });
+    Ok(received)
+}
+
+#[cfg(test)]
+mod test {
+    use configparser::ini::Ini;
+    use dbhook::{
+        genericdb::{GenericDB, NewUser},
+        mockdb::MockDB,
+    };
+    use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+    use oep::login::Login;
+
+    use super::{execute, AdminCommand, AdminConfig, EnginePush, InstrumentChanges};
+    use crate::http::Request;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: String::from(method),
+            path: String::from(path),
+            body: String::from(body),
+        }
+    }
+
+    /// The status of the response to @request, and what the engines got
+    fn run(db: &mut MockDB, request: Request) -> (u16, Option<EnginePush>) {
+        match AdminCommand::route(&request) {
+            Ok(command) => {
+                let (response, push) = execute(command, db);
+                (response.status, push)
+            }
+            Err(response) => (response.status, None),
+        }
+    }
+
+    #[test]
+    fn config() {
+        let mut ini = Ini::new();
+        let config_map = ini.read(String::from("[admin]\nport=8081")).unwrap();
+        assert_eq!(
+            Some(AdminConfig {
+                address: String::from("127.0.0.1"),
+                port: 8081
+            }),
+            AdminConfig::from_config(&config_map).unwrap()
+        );
+        let config_map = ini.read(String::from("[admin]\nport=x")).unwrap();
+        assert!(AdminConfig::from_config(&config_map).is_err());
+        let config_map = ini.read(String::from("[clearing]\nport=1")).unwrap();
+        assert_eq!(None, AdminConfig::from_config(&config_map).unwrap());
+    }
+
+    #[test]
+    fn routes() {
+        assert_eq!(
+            Ok(AdminCommand::UpdateInstrument(
+                7,
+                InstrumentChanges {
+                    state: Some(InstrumentState::Halted),
+                    ..Default::default()
+                }
+            )),
+            AdminCommand::route(&request("POST", "/instruments/7/halt", ""))
+        );
+        assert_eq!(
+            Ok(AdminCommand::UpdateInstrument(
+                7,
+                InstrumentChanges {
+                    percentage_bands: Some(5),
+                    ..Default::default()
+                }
+            )),
+            AdminCommand::route(&request(
+                "PUT",
+                "/instruments/7/bands",
+                r#"{"percentage_bands": 5, "name": "ignored"}"#
+            ))
+        );
+        assert_eq!(
+            Ok(AdminCommand::Snapshot),
+            AdminCommand::route(&request("POST", "/snapshot/", ""))
+        );
+        for (status, method, path, body) in [
+            (400, "POST", "/instruments", r#"{"id": 7, "name": "ACME"}"#),
+            (
+                400,
+                "POST",
+                "/instruments",
+                r#"{"id": 7, "name": "ACME", "type": "bond"}"#,
+            ),
+            (
+                400,
+                "PATCH",
+                "/instruments/7",
+                r#"{"percentage_bands": 300}"#,
+            ),
+            (400, "PUT", "/instruments/7/bands", "{}"),
+            (400, "PATCH", "/instruments/7", r#"{"expiry": -1}"#),
+            (404, "POST", "/instruments/x/halt", ""),
+            (404, "POST", "/instruments/7/close", ""),
+            (404, "DELETE", "/instruments/x", ""),
+            (405, "PUT", "/instruments/7", ""),
+        ] {
+            assert_eq!(
+                status,
+                AdminCommand::route(&request(method, path, body))
+                    .unwrap_err()
+                    .status,
+                "{method} {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn instruments() {
+        let mut db = MockDB::default();
+        let (status, push) = run(
+            &mut db,
+            request(
+                "POST",
+                "/instruments",
+                r#"{"id": 7, "name": "ACME", "type": "share", "percentage_bands": 10}"#,
+            ),
+        );
+        assert_eq!(201, status);
+        let expected = Instrument::new(
+            7,
+            "ACME",
+            InstrumentType::Share,
+            InstrumentState::Closed,
+            10,
+            0,
+        );
+        assert_eq!(Some(EnginePush::Instrument(expected.clone())), push);
+        assert_eq!(vec![expected], db.get_instruments());
+        // only once
+        let (status, push) = run(
+            &mut db,
+            request(
+                "POST",
+                "/instruments",
+                r#"{"id": 7, "name": "ACME", "type": "share"}"#,
+            ),
+        );
+        assert_eq!((409, None), (status, push));
+
+        let (status, push) = run(&mut db, request("POST", "/instruments/7/resume", ""));
+        assert_eq!(200, status);
+        let Some(EnginePush::Instrument(updated)) = push else {
+            panic!("No instrument update for the engines");
+        };
+        assert_eq!(InstrumentState::Trading, updated.get_state());
+        assert_eq!(10, updated.get_percentage_bands());
+        assert_eq!(
+            InstrumentState::Trading,
+            db.get_instruments()[0].get_state()
+        );
+
+        run(
+            &mut db,
+            request(
+                "PUT",
+                "/instruments/7/bands",
+                r#"{"percentage_variation": 20}"#,
+            ),
+        );
+        let stored = &db.get_instruments()[0];
+        assert_eq!(
+            (InstrumentState::Trading, 10, 20),
+            (
+                stored.get_state(),
+                stored.get_percentage_bands(),
+                stored.get_percentage_variation_allowed()
+            )
+        );
+
+        let (response, _) = execute(AdminCommand::ListInstruments, &mut db);
+        assert_eq!(
+            r#"{"instruments":[{"id":7,"name":"ACME","type":"share","state":"trading","percentage_bands":10,"percentage_variation":20,"segment":0,"expiry":0,"strike":0,"underlying":0}]}"#,
+            response.body
+        );
+        // the contract of a derivative
+        run(
+            &mut db,
+            request(
+                "PATCH",
+                "/instruments/7",
+                r#"{"type": "option_call", "expiry": 1800000000, "strike": 9000, "underlying": 3}"#,
+            ),
+        );
+        let stored = &db.get_instruments()[0];
+        assert_eq!(
+            (InstrumentType::OptionCall, 1_800_000_000, 9000, 3),
+            (
+                stored.get_type(),
+                stored.get_expiry(),
+                stored.get_strike(),
+                stored.get_underlying()
+            )
+        );
+        assert_eq!(
+            (404, None),
+            run(&mut db, request("POST", "/instruments/8/halt", ""))
+        );
+        assert_eq!(
+            (200, Some(EnginePush::Deleted(7))),
+            run(&mut db, request("DELETE", "/instruments/7", ""))
+        );
+        assert!(db.get_instruments().is_empty());
+        assert_eq!(
+            (404, None),
+            run(&mut db, request("DELETE", "/instruments/7", ""))
+        );
+        assert_eq!(
+            (202, Some(EnginePush::SnapshotRequest)),
+            run(&mut db, request("POST", "/snapshot", ""))
+        );
+    }
+
+    #[test]
+    fn end_of_day() {
+        let mut db = MockDB::default();
+        for (id, state) in [
+            (9, InstrumentState::Trading),
+            (7, InstrumentState::Halted),
+            (8, InstrumentState::Closed),
+        ] {
+            db.store_instrument(&Instrument::new(
+                id,
+                "ACME",
+                InstrumentType::Share,
+                state,
+                0,
+                0,
+            ))
+            .unwrap();
+        }
+        let (response, push) = execute(AdminCommand::EndOfDay, &mut db);
+        assert_eq!(
+            (200, r#"{"closed":[7,9]}"#),
+            (response.status, response.body.as_str())
+        );
+        let Some(EnginePush::Closed(closed)) = push else {
+            panic!("No instrument updates for the engines");
+        };
+        assert_eq!(
+            vec![7, 9],
+            closed.iter().map(|i| i.get_id()).collect::<Vec<_>>()
+        );
+        assert!(closed
+            .iter()
+            .all(|i| i.get_state() == InstrumentState::Closed));
+        assert!(db
+            .get_instruments()
+            .iter()
+            .all(|i| i.get_state() == InstrumentState::Closed));
+
+        // nothing left to close
+        assert_eq!((200, None), run(&mut db, request("POST", "/eod", "")));
+        assert_eq!(
+            405,
+            AdminCommand::route(&request("GET", "/eod", ""))
+                .unwrap_err()
+                .status
+        );
+    }
+
+    #[test]
+    fn segments() {
+        let mut db = MockDB::default();
+        for (id, segment) in [(9, 2), (7, 2), (8, 1)] {
+            let (status, _) = run(
+                &mut db,
+                request(
+                    "POST",
+                    "/instruments",
+                    &format!(
+                        r#"{{"id": {id}, "name": "ACME", "type": "share", "state": "trading", "segment": {segment}}}"#
+                    ),
+                ),
+            );
+            assert_eq!(201, status);
+        }
+        let (response, push) = execute(
+            AdminCommand::route(&request("POST", "/segments/2/halt", "")).unwrap(),
+            &mut db,
+        );
+        assert_eq!(
+            (200, r#"{"segment":2,"state":"halted","instruments":[7,9]}"#),
+            (response.status, response.body.as_str())
+        );
+        let Some(EnginePush::Segment(2, InstrumentState::Halted, halted)) = push else {
+            panic!("No segment state for the engines");
+        };
+        assert_eq!(
+            vec![7, 9],
+            halted.iter().map(|i| i.get_id()).collect::<Vec<_>>()
+        );
+        let mut stored = db.get_instruments();
+        stored.sort_by_key(|i| i.get_id());
+        assert_eq!(
+            vec![
+                (7, InstrumentState::Halted, 2),
+                (8, InstrumentState::Trading, 1),
+                (9, InstrumentState::Halted, 2)
+            ],
+            stored
+                .iter()
+                .map(|i| (i.get_id(), i.get_state(), i.get_segment()))
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            Ok(AdminCommand::SetSegmentState(1, InstrumentState::Auction)),
+            AdminCommand::route(&request(
+                "PUT",
+                "/segments/1/state",
+                r#"{"state": "auction"}"#
+            ))
+        );
+        // moving an instrument to another segment keeps the rest
+        run(
+            &mut db,
+            request("PATCH", "/instruments/8", r#"{"segment": 3}"#),
+        );
+        assert_eq!(
+            (404, None),
+            run(&mut db, request("POST", "/segments/1/resume", ""))
+        );
+        assert_eq!(
+            InstrumentState::Trading,
+            db.get_instruments()
+                .iter()
+                .find(|i| i.get_id() == 8)
+                .unwrap()
+                .get_state()
+        );
+        for (status, method, path, body) in [
+            (400, "PUT", "/segments/1/state", "{}"),
+            (400, "PUT", "/segments/1/state", r#"{"state": "open"}"#),
+            (404, "POST", "/segments/0/halt", ""),
+            (404, "POST", "/segments/x/halt", ""),
+            (405, "GET", "/segments/1/state", ""),
+            (400, "PATCH", "/instruments/8", r#"{"segment": -1}"#),
+        ] {
+            assert_eq!(
+                status,
+                AdminCommand::route(&request(method, path, body))
+                    .unwrap_err()
+                    .status,
+                "{method} {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn bust_trade() {
+        let mut db = MockDB::default();
+        db.store_instrument(&Instrument::new(
+            5,
+            "ACME",
+            InstrumentType::Share,
+            InstrumentState::Trading,
+            0,
+            0,
+        ))
+        .unwrap();
+        let command =
+            AdminCommand::route(&request("POST", "/instruments/5/trades/12/bust", "")).unwrap();
+        assert_eq!(AdminCommand::BustTrade(5, 12), command);
+        let (response, push) = execute(command, &mut db);
+        assert_eq!(
+            (202, r#"{"book_id":5,"trade_id":12,"bust":"requested"}"#),
+            (response.status, response.body.as_str())
+        );
+        assert!(matches!(push, Some(EnginePush::TradeBust(5, 12))));
+
+        assert_eq!(
+            (404, None),
+            run(
+                &mut db,
+                request("POST", "/instruments/6/trades/12/bust", "")
+            )
+        );
+        for (method, path, status) in [
+            ("POST", "/instruments/5/trades/x/bust", 404),
+            ("GET", "/instruments/5/trades/12/bust", 405),
+        ] {
+            assert_eq!(
+                status,
+                AdminCommand::route(&request(method, path, ""))
+                    .unwrap_err()
+                    .status
+            );
+        }
+    }
+
+    #[test]
+    fn users() {
+        let mut db = MockDB::default();
+        let body =
+            r#"{"username": "trader", "password": "secret", "session_id": 5, "participant": 7}"#;
+        assert_eq!(
+            Ok(AdminCommand::CreateUser(NewUser {
+                username: String::from("trader"),
+                password: String::from("secret"),
+                session_id: 5,
+                participant: 7,
+            })),
+            AdminCommand::route(&request("POST", "/users", body))
+        );
+        assert_eq!((201, None), run(&mut db, request("POST", "/users", body)));
+        assert_eq!((409, None), run(&mut db, request("POST", "/users", body)));
+        assert_eq!(
+            7,
+            db.check_login("trader", &Login::free_text_hash("secret"), 5)
+                .unwrap()
+        );
+
+        let change = r#"{"password": "other"}"#;
+        assert_eq!(
+            (200, None),
+            run(&mut db, request("PUT", "/users/trader/5/password", change))
+        );
+        assert!(db
+            .check_login("trader", &Login::free_text_hash("secret"), 5)
+            .is_err());
+        assert_eq!(
+            (404, None),
+            run(&mut db, request("PUT", "/users/trader/6/password", change))
+        );
+
+        for (status, method, path, body) in [
+            (
+                400,
+                "POST",
+                "/users",
+                r#"{"username": "trader", "session_id": 5, "participant": 7}"#,
+            ),
+            (
+                400,
+                "POST",
+                "/users",
+                r#"{"username": "trader", "password": "x", "session_id": -1, "participant": 7}"#,
+            ),
+            (400, "PUT", "/users/trader/5/password", "{}"),
+            (404, "PUT", "/users/trader/x/password", change),
+            (405, "GET", "/users", ""),
+        ] {
+            assert_eq!(
+                status,
+                AdminCommand::route(&request(method, path, body))
+                    .unwrap_err()
+                    .status,
+                "{method} {path}"
+            );
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! The typed [clearing] and [database] sections of clearing.ini
+//!
+//! The engines, admin, exposure, heartbeat, metrics and log settings are
+//! loaded by their own from_config.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use utils::config::{self, ConfigError, ConfigMap, DatabaseConfig};
+
+#[derive(Debug, Deserialize)]
+struct ClearingSection {
+    address: String,
+    port: u16,
+    max_packet_size: u16,
+}
+
+// the [database] key of the clearing only
+#[derive(Debug, Deserialize)]
+struct RefreshSection {
+    instrument_refresh: u64,
+}
+
+/// Where the clearing listens for the engines, and the database of the instruments
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClearingConfig {
+    pub address: String,
+    pub port: u16,
+    pub max_packet_size: usize,
+    pub database: DatabaseConfig,
+    // how often the changed instruments are downloaded
+    pub instrument_refresh: Duration,
+}
+
+impl ClearingConfig {
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self, ConfigError> {
+        let clearing: ClearingSection = config::section(config_map, "clearing")?;
+        let refresh: RefreshSection = config::section(config_map, "database")?;
+        if refresh.instrument_refresh == 0 {
+            return Err(ConfigError::new(
+                "database",
+                Some("instrument_refresh"),
+                "must be a positive number of seconds",
+            ));
+        }
+        Ok(Self {
+            address: clearing.address,
+            port: clearing.port,
+            max_packet_size: clearing.max_packet_size as usize,
+            database: DatabaseConfig::from_config(config_map)?,
+            instrument_refresh: Duration::from_secs(refresh.instrument_refresh),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use configparser::ini::Ini;
+
+    use super::ClearingConfig;
+
+    #[test]
+    fn from_config() {
+        let load = |refresh: &str| {
+            let config_map = Ini::new()
+                .read(format!(
+                    "[clearing]
+                    address=127.0.0.1
+                    port=10001
+                    max_packet_size=10000
+
+                    [database]
+                    type=mock
+                    address=127.0.0.1
+                    port=5432
+                    username=test
+                    password=test
+                    name=trading
+                    {refresh}"
+                ))
+                .unwrap();
+            ClearingConfig::from_config(&config_map).map_err(|e| e.to_string())
+        };
+        let target = load("instrument_refresh=60").unwrap();
+        assert_eq!(target.port, 10001);
+        assert_eq!(target.max_packet_size, 10000);
+        assert_eq!(target.database.db_type, "mock");
+        assert_eq!(target.instrument_refresh, Duration::from_secs(60));
+
+        assert_eq!(
+            load("").unwrap_err(),
+            "instrument_refresh in the [database] section: missing, please set it"
+        );
+        assert!(load("instrument_refresh=0").is_err());
+        assert!(load("instrument_refresh=-1").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! The little of HTTP/1.1 the admin API needs: one request per connection,
+//! with its body sized by Content-Length, and a JSON response
+
+use std::io::{self, Read, Write};
+
+// the longest request line and headers accepted
+const MAX_HEAD_SIZE: usize = 8192;
+// the longest body accepted, the instruments being tiny
+const MAX_BODY_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    // without the query string
+    pub path: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    // JSON
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: u16, body: String) -> Self {
+        Self { status, body }
+    }
+
+    /// @status, with {"error": @message} as the body
+    pub fn error(status: u16, message: &str) -> Self {
+        Self::new(
+            status,
+            format!("{{\"error\":\"{}\"}}", utils::json::escape(message)),
+        )
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            500 => "Internal Server Error",
+            503 => "Service Unavailable",
+            _ => "",
+        }
+    }
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+/// Reads a request from @stream
+pub fn read_request(stream: &mut impl Read) -> io::Result<Request> {
+    // one byte at a time, not to read past the head
+    let mut head = vec![];
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_SIZE {
+            return Err(invalid("request head too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).map_err(|_| invalid("request head not UTF-8"))?;
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(invalid("invalid request line"));
+    };
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .map(|(_, value)| value.trim().parse::<usize>())
+        .transpose()
+        .map_err(|_| invalid("invalid Content-Length"))?
+        .unwrap_or_default();
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("request body too long"));
+    }
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body)?;
+    Ok(Request {
+        method: method.to_uppercase(),
+        path: String::from(target.split('?').next().unwrap_or_default()),
+        body: String::from_utf8(body).map_err(|_| invalid("request body not UTF-8"))?,
+    })
+}
+
+pub fn write_response(stream: &mut impl Write, response: &Response) -> io::Result<()> {
+    stream.write_all(
+        format!(
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            response.status,
+            response.reason(),
+            response.body.len(),
+            response.body
+        )
+        .as_bytes(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::{read_request, write_response, Request, Response};
+
+    #[test]
+    fn requests() {
+        let mut input = Cursor::new(
+            b"PATCH /instruments/7?pretty HTTP/1.1\r\nHost: localhost\r\ncontent-length: 13\r\n\r\n{\"state\":\"x\"}"
+                .to_vec(),
+        );
+        assert_eq!(
+            Request {
+                method: String::from("PATCH"),
+                path: String::from("/instruments/7"),
+                body: String::from("{\"state\":\"x\"}"),
+            },
+            read_request(&mut input).unwrap()
+        );
+
+        let mut input = Cursor::new(b"GET /instruments HTTP/1.1\r\n\r\n".to_vec());
+        assert_eq!("", read_request(&mut input).unwrap().body);
+
+        for invalid in [
+            b"GET /instruments HTTP/1.1\r\n".to_vec(),
+            b"\r\n\r\n".to_vec(),
+            b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n".to_vec(),
+            b"POST / HTTP/1.1\r\nContent-Length: 100000\r\n\r\n".to_vec(),
+            // cut short
+            b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}".to_vec(),
+        ] {
+            assert!(read_request(&mut Cursor::new(invalid)).is_err());
+        }
+    }
+
+    #[test]
+    fn responses() {
+        let mut output = vec![];
+        write_response(&mut output, &Response::error(404, "No instrument 7")).unwrap();
+        assert_eq!(
+            "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\nContent-Length: 27\r\n\
+             Connection: close\r\n\r\n{\"error\":\"No instrument 7\"}",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
@@ -1,87 +1,166 @@
 /// For now the clearing engine is not doing too much except
 /// downloading some instruments from the database and distributing them
-/// to the matching engine
+/// to the matching engine, storing the end of day summaries that the
+/// matching engine reports back, keeping the positions of the participants,
+/// recording the trades for the audit and serving the admin API
 use clearing_connection::genericclearingprotocol::ProtocolSide;
-use configparser::ini::Ini;
 use disseminator::mockdisseminator::MockDisseminator;
 use polling::{Event, Events, PollMode, Poller};
 use socket2::Socket;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::{collections::BTreeMap, error::Error, io::Read, os::fd::AsRawFd};
 
+use admin::{AdminConfig, EnginePush};
 use clearing_connection::genericclearingprotocol::GenericClearingProtocol;
+use clearing_connection::liveness::{Liveness, LivenessConfig};
+use clearing_connection::logon::EngineSecrets;
 use clearing_connection::{
     clearclearingconnection::ClearClearingConnection, clearingconnection::ClearingConnection,
     clearprotocol::ClearProtocol,
 };
+use config::ClearingConfig;
 use instruments::{genericinstrumentlist::GenericInstrumentList, instrumentlist::InstrumentList};
-use market::Market;
-use utils::config;
+use market::{orderid::OrderIdGenerator, Market};
+use metrics::ClearingMetrics;
+use positions::{ExposureLimits, ExposureUpdate, PositionKeeper};
+use tracing::{debug_span, error, info, warn};
+use utils::logging::{self, LogConfig};
+use utils::metrics::MetricsConfig;
+
+mod admin;
+mod config;
+mod http;
+mod metrics;
+mod positions;
+
+/// Sends @updates to the matching engines connected on @sockets
+fn send_exposure_updates(
+    connection: &ClearClearingConnection,
+    sockets: &[&Socket],
+    updates: &[ExposureUpdate],
+) {
+    let Some(protocol) = connection.get_protocol().as_ref() else {
+        return;
+    };
+    for update in updates {
+        let message = protocol.prepare_exposure_update(
+            update.participant,
+            update.book_id,
+            update.blocked_side,
+        );
+        for socket in sockets {
+            if let Err(e) = socket.send(&message) {
+                error!(error = %e, "Error sending an exposure update");
+            }
+        }
+    }
+}
+
+/// The sockets of @clients whose engine logged on, as kept in @sessions
+fn logged_on<'a>(
+    clients: &'a BTreeMap<usize, Socket>,
+    sessions: &HashMap<usize, u8>,
+) -> Vec<&'a Socket> {
+    clients
+        .iter()
+        .filter(|(k, _)| sessions.contains_key(k))
+        .map(|(_, socket)| socket)
+        .collect()
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-    println!("Loading configuration file");
-    let mut config = Ini::new();
-    let config_map = config
-        .load("clearing.ini")
-        .expect("Unable to load the configuration file");
-    let clearing_addr = config::get_config_string(&config_map, "clearing", "address");
-    let clearing_port = config::get_config_string(&config_map, "clearing", "port")
-        .parse::<u16>()
-        .expect("Clearing port must be an u16");
-    let max_packet_size = config::get_config_string(&config_map, "clearing", "max_packet_size")
-        .parse::<u16>()
-        .expect("max_packet_size must be an u16") as usize;
+    let config_map = utils::config::load("clearing.ini")?;
+    let log_config = LogConfig::from_config(&config_map, "clearing")
+        .expect("Invalid log settings in the clearing section");
+    logging::init(&log_config).expect("Unable to start logging");
+    info!("Loaded the configuration file");
+    let clearing_config = ClearingConfig::from_config(&config_map)?;
+    let max_packet_size = clearing_config.max_packet_size;
+    let liveness_config = LivenessConfig::from_config(&config_map, "clearing")
+        .expect("The heartbeat settings must be integers");
+    let admin_config =
+        AdminConfig::from_config(&config_map).expect("The admin port must be an u16");
+    let metrics_config = MetricsConfig::from_config(&config_map, "clearing")
+        .expect("The metrics port must be an u16");
+    let engine_secrets =
+        EngineSecrets::from_config(&config_map).expect("The engine ids must be u8");
+    if engine_secrets.is_empty() {
+        warn!("No engines configured, none of them can log on");
+    }
 
-    println!("Starting the clearing server");
-    let poller = Poller::new()?;
+    info!("Starting the clearing server");
+    let metrics = ClearingMetrics::new(utils::metrics::registry());
+    if let Some(metrics_config) = &metrics_config {
+        metrics_config.serve()?;
+    }
+    let poller = Arc::new(Poller::new()?);
     let mut poll_events = Events::new();
 
     let mut instrument_list = InstrumentList::new();
 
     // Load the instruments
-    eprintln!("Connecting to DB");
-    let db_type = config::get_config_string(&config_map, "database", "type");
-    let db_addr = config::get_config_string(&config_map, "database", "address");
-    let db_port = config::get_config_string(&config_map, "database", "port")
-        .parse::<u16>()
-        .expect("Database port must be an u16");
-    let db_user = config::get_config_string(&config_map, "database", "username");
-    let db_pass = config::get_config_string(&config_map, "database", "password");
-    let db_name = config::get_config_string(&config_map, "database", "name");
-    let instrument_refresh =
-        config::get_config_string(&config_map, "database", "instrument_refresh")
-            .parse::<u64>()
-            .expect("instrument_refresh must be a positive integer");
-
-    let mut db_client = dbhook::factory::build(&db_type);
-    db_client.connect(&db_addr, db_port, &db_user, &db_pass, &db_name)?;
-    eprintln!("Downloading instruments");
-    let instruments = db_client.get_instruments();
-    eprintln!("Downloaded {} instruments", instruments.len());
-    instruments.into_iter().for_each(|i| {
+    info!("Connecting to DB");
+    let database = &clearing_config.database;
+    let mut db_client = dbhook::factory::build(&database.db_type);
+    db_client.connect(
+        &database.address,
+        database.port,
+        &database.username,
+        &database.password,
+        &database.name,
+    )?;
+    info!("Downloading instruments");
+    let instruments = db_client.get_changed_instruments(None)?;
+    info!(
+        instruments = instruments.updated.len(),
+        "Downloaded the instruments"
+    );
+    instruments.updated.into_iter().for_each(|i| {
         instrument_list.add_instrument(i);
     });
-    let last_update = Instant::now();
+    // the engines are only sent the instruments changed after this
+    let mut instruments_since = instruments.latest;
+    let mut last_update = Instant::now();
+
+    let mut positions = PositionKeeper::new(
+        ExposureLimits::from_config(&config_map).expect("Exposure limits must be integers"),
+    );
 
-    let markets = Rc::new(RefCell::new(HashMap::<u64, Market>::new()));
+    let markets = Arc::new(Mutex::new(HashMap::<u64, Market>::new()));
     let mut protocol = Box::new(ClearProtocol::<InstrumentList>::new(
         instrument_list,
         markets,
-        Rc::new(RefCell::new(MockDisseminator::new())), // we don't need a real one here
+        Arc::new(Mutex::new(MockDisseminator::new())), // we don't need a real one here
+        Arc::new(Mutex::new(OrderIdGenerator::new(0))), // nor order ids, no markets are created
     ));
     protocol.set_protocol_side(ProtocolSide::Server);
-    let mut connection =
-        ClearClearingConnection::new(&clearing_addr, clearing_port, Some(protocol));
+    protocol.set_engine_secrets(engine_secrets);
+    let mut connection = ClearClearingConnection::new(
+        &clearing_config.address,
+        clearing_config.port,
+        Some(protocol),
+    );
     connection.listen()?;
     connection.register_with_poller(&poller)?;
     let clearing_socket_fd = connection.get_socket_key();
     let mut clients: BTreeMap<usize, Socket> = BTreeMap::new();
 
     let mut remaining = HashMap::<usize, Vec<u8>>::new();
-    println!("Listening for incoming connections");
+    // the engines that didn't get the exposure blocks in place yet
+    let mut unsynced = HashSet::<usize>::new();
+    // engine -> sequence of the last trade capture accounted for
+    let mut capture_seqs = HashMap::<usize, u64>::new();
+    let mut liveness = HashMap::<usize, Liveness>::new();
+    // connection -> the engine logged on it, served only once there
+    let mut sessions = HashMap::<usize, u8>::new();
+    // the admin wakes the poller up for its commands to be run here
+    let admin_requests = match &admin_config {
+        Some(admin_config) => Some(admin::spawn(admin_config, poller.clone())?),
+        None => None,
+    };
+    info!("Listening for incoming connections");
     loop {
         poll_events.clear();
         poller.wait(&mut poll_events, Some(Duration::from_secs(1)))?;
@@ -90,13 +169,13 @@ fn main() -> Result<(), Box<dyn Error>> {
                 k if k == clearing_socket_fd => {
                     // accept
                     let (socket, sockaddr) = connection.accept()?;
-                    println!(
-                        "Accepted incoming connection from {}",
-                        sockaddr.as_socket_ipv4().unwrap().ip() // TODO: IPv6
+                    info!(
+                        address = %sockaddr.as_socket().unwrap().ip(),
+                        "Accepted incoming connection"
                     );
                     socket.set_nonblocking(true)?;
                     socket.set_nodelay(true)?;
-                    let socket_key = (&socket).as_raw_fd() as usize;
+                    let socket_key = socket.as_raw_fd() as usize;
                     unsafe {
                         poller.add_with_mode(
                             &socket,
@@ -105,7 +184,10 @@ fn main() -> Result<(), Box<dyn Error>> {
                         )?;
                     }
                     clients.insert(socket_key, socket);
+                    metrics.connected_engines.set(clients.len() as i64);
                     remaining.insert(socket_key, vec![]);
+                    unsynced.insert(socket_key);
+                    liveness.insert(socket_key, Liveness::new(liveness_config, Instant::now()));
                 }
                 k if k != clearing_socket_fd => {
                     let mut socket = clients.get(&k).expect("Invalid socket in poll");
@@ -113,8 +195,13 @@ fn main() -> Result<(), Box<dyn Error>> {
                         () => {
                             poller.delete(socket)?;
                             clients.remove(&k);
+                            metrics.connected_engines.set(clients.len() as i64);
                             remaining.remove(&k);
-                            println!("Disconnected one client");
+                            unsynced.remove(&k);
+                            capture_seqs.remove(&k);
+                            liveness.remove(&k);
+                            sessions.remove(&k);
+                            info!(engine = k, "Disconnected one engine");
                             continue;
                         };
                     }
@@ -124,6 +211,9 @@ fn main() -> Result<(), Box<dyn Error>> {
                     let mut buffer = Vec::with_capacity(max_packet_size);
                     buffer.resize_with(max_packet_size, Default::default);
                     match socket.read(&mut buffer) {
+                        Ok(0) => {
+                            clean_socket!();
+                        }
                         Ok(r) => remaining
                             .get_mut(&k)
                             .unwrap()
@@ -132,7 +222,17 @@ fn main() -> Result<(), Box<dyn Error>> {
                             clean_socket!();
                         }
                     }
-                    match connection.process(&remaining.get(&k).unwrap(), Some(socket)) {
+                    if let Some(l) = liveness.get_mut(&k) {
+                        l.on_received(Instant::now());
+                    }
+                    connection.set_session(sessions.get(&k).copied());
+                    let processed = connection.process(remaining.get(&k).unwrap(), Some(socket));
+                    if let Some(engine_id) = connection.get_session() {
+                        if sessions.insert(k, engine_id).is_none() {
+                            info!(engine = k, engine_id, "Engine logged on");
+                        }
+                    }
+                    match processed {
                         Ok(bytes) => {
                             let r = remaining.len();
                             if bytes < r {
@@ -140,10 +240,70 @@ fn main() -> Result<(), Box<dyn Error>> {
                             }
                         }
                         Err(e) => {
-                            println!("Error {e} reading on socket {:#?}", socket);
+                            error!(engine = k, error = %e, "Error reading from an engine");
                             clean_socket!();
                         }
                     }
+                    for summary in connection.take_eod_summaries() {
+                        let book_id = summary.book_id;
+                        if let Err(e) = db_client.store_eod_summary(&summary) {
+                            error!(book_id, error = %e, "Error storing the EOD summary");
+                        }
+                        // the reference of the price bands the next day
+                        let closing_price = summary.closing_price;
+                        let instrument = connection
+                            .get_protocol()
+                            .as_ref()
+                            .unwrap()
+                            .clone_instrument_list()
+                            .into_iter()
+                            .find(|i| i.get_id() == book_id);
+                        if let Some(mut instrument) = instrument.filter(|_| closing_price > 0) {
+                            instrument.set_previous_close(closing_price);
+                            connection.add_instrument(instrument);
+                        }
+                    }
+                    // after its instrument request, so that the engine has the markets to block
+                    if sessions.contains_key(&k) && unsynced.remove(&k) {
+                        let blocks = positions.get_blocks();
+                        send_exposure_updates(&connection, &[socket], &blocks);
+                    }
+                    for capture in connection.take_trade_captures() {
+                        // resent by the engine, as it didn't get the ack in time
+                        let last_seq = capture_seqs.entry(k).or_default();
+                        if capture.seq <= *last_seq {
+                            continue;
+                        }
+                        *last_seq = capture.seq;
+                        let _span = debug_span!(
+                            "trade_capture",
+                            engine = k,
+                            seq = { capture.seq },
+                            book_id = { capture.book_id },
+                            trade_id = { capture.trade_id },
+                        )
+                        .entered();
+                        metrics.trade_captures.inc();
+                        if let Err(e) = db_client.store_trade(&capture) {
+                            error!(error = %e, "Error storing the trade");
+                        }
+                        let updates = positions.add_trade(&capture);
+                        for update in &updates {
+                            info!(
+                                participant = update.participant,
+                                book_id = update.book_id,
+                                position =
+                                    positions.get_position(update.participant, update.book_id),
+                                blocked_side = ?update.blocked_side,
+                                "Exposure of a participant changed"
+                            );
+                        }
+                        send_exposure_updates(
+                            &connection,
+                            &logged_on(&clients, &sessions),
+                            &updates,
+                        );
+                    }
                 }
                 _ => {
                     panic!("Got poll event on invalid socket")
@@ -151,30 +311,128 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         }
 
-        // every X seconds redownload the instruments and serve them on all the connections
-        // TODO: in the end, we need to find a better way of doing this operation:
-        // 1. don't send updates for instruments that haven't been updated in the database
-        // 2. send updates when an instrument is changed instead of every X seconds
-        if Instant::now().duration_since(last_update) > Duration::from_secs(instrument_refresh) {
-            let instruments = db_client.get_instruments();
-            let response = instruments
-                .iter()
-                .map(|x| {
-                    connection.add_instrument(x.clone());
-                    connection
-                        .get_protocol()
-                        .as_ref()
-                        .unwrap()
-                        .prepare_instrument_update_response(&x)
-                })
-                .reduce(|mut acc, mut i| {
-                    (&mut acc).append(&mut i);
-                    acc
-                });
-            if response.is_some() {
-                clients.iter().for_each(|(_, socket)| {
-                    socket.send(response.as_ref().unwrap().as_slice()).unwrap();
-                });
+        // the changes of the admin reach the engines right away
+        for (command, reply) in admin_requests.iter().flat_map(|r| r.try_iter()) {
+            let (response, push) = admin::execute(command, db_client.as_mut());
+            let protocol = connection.get_protocol().as_ref().unwrap();
+            let message = match push {
+                Some(EnginePush::Instrument(instrument)) => {
+                    let message = protocol.prepare_instrument_update_response(&instrument);
+                    connection.add_instrument(instrument);
+                    metrics.instrument_updates.inc();
+                    message
+                }
+                Some(EnginePush::Deleted(id)) => {
+                    let message = protocol.prepare_instrument_deletion(id);
+                    connection.remove_instrument(id);
+                    metrics.instrument_updates.inc();
+                    message
+                }
+                Some(EnginePush::SnapshotRequest) => protocol.prepare_snapshot_request(),
+                Some(EnginePush::Closed(instruments)) => {
+                    let message = instruments
+                        .iter()
+                        .flat_map(|i| protocol.prepare_instrument_update_response(i))
+                        .collect();
+                    metrics.instrument_updates.add(instruments.len() as u64);
+                    instruments
+                        .into_iter()
+                        .for_each(|i| connection.add_instrument(i));
+                    message
+                }
+                Some(EnginePush::TradeBust(book_id, trade_id)) => {
+                    protocol.prepare_trade_bust(book_id, trade_id)
+                }
+                Some(EnginePush::Segment(segment, state, instruments)) => {
+                    let message = protocol.prepare_segment_state(segment, state);
+                    instruments
+                        .into_iter()
+                        .for_each(|i| connection.add_instrument(i));
+                    message
+                }
+                None => vec![],
+            };
+            if !message.is_empty() {
+                for socket in logged_on(&clients, &sessions) {
+                    if let Err(e) = socket.send(&message) {
+                        error!(error = %e, "Error sending the change of the admin");
+                    }
+                }
+            }
+            // the admin may be gone
+            let _ = reply.send(response);
+        }
+
+        // heartbeat the engines, and let go of the silent ones
+        let now = Instant::now();
+        let heartbeat = connection
+            .get_protocol()
+            .as_ref()
+            .unwrap()
+            .prepare_heartbeat();
+        let silent: Vec<usize> = liveness
+            .iter_mut()
+            .filter_map(|(k, l)| {
+                if l.is_silent(now) {
+                    return Some(*k);
+                }
+                if l.heartbeat_due(now) {
+                    // a dead connection shows up as silence
+                    let _ = clients[k].send(&heartbeat);
+                }
+                None
+            })
+            .collect();
+        for k in silent {
+            if let Some(socket) = clients.remove(&k) {
+                poller.delete(&socket)?;
+            }
+            remaining.remove(&k);
+            unsynced.remove(&k);
+            capture_seqs.remove(&k);
+            liveness.remove(&k);
+            sessions.remove(&k);
+            info!(engine = k, "Disconnected one silent engine");
+        }
+        metrics.connected_engines.set(clients.len() as i64);
+
+        // every X seconds send all the connections the instruments changed or deleted in the
+        // database since the last time, for the changes made behind the back of the admin API
+        if now.duration_since(last_update) > clearing_config.instrument_refresh {
+            last_update = now;
+            let changes = match db_client.get_changed_instruments(instruments_since) {
+                Ok(changes) => changes,
+                Err(e) => {
+                    error!(error = %e, "Error downloading the changed instruments");
+                    continue;
+                }
+            };
+            instruments_since = changes.latest.or(instruments_since);
+            let protocol = connection.get_protocol().as_ref().unwrap();
+            let mut message = vec![];
+            for instrument in &changes.updated {
+                message.append(&mut protocol.prepare_instrument_update_response(instrument));
+            }
+            for id in &changes.deleted {
+                message.append(&mut protocol.prepare_instrument_deletion(*id));
+            }
+            metrics
+                .instrument_updates
+                .add((changes.updated.len() + changes.deleted.len()) as u64);
+            changes
+                .updated
+                .into_iter()
+                .for_each(|i| connection.add_instrument(i));
+            changes
+                .deleted
+                .into_iter()
+                .for_each(|id| connection.remove_instrument(id));
+            if !message.is_empty() {
+                for socket in logged_on(&clients, &sessions) {
+                    if let Err(e) = socket.send(&message) {
+                        error!(error = %e, "Error sending the changed instruments");
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,37 @@
+//! What the clearing counts, for its metrics endpoint
+
+use std::sync::Arc;
+
+use utils::metrics::{Counter, Gauge, Registry};
+
+/// The handles of the metrics of the clearing, in the registry of the process
+#[derive(Debug)]
+pub struct ClearingMetrics {
+    // instruments changed or deleted, by the admin or in the database, sent to the engines
+    pub instrument_updates: Arc<Counter>,
+    pub connected_engines: Arc<Gauge>,
+    // accounted for in the positions, without the ones resent by the engines
+    pub trade_captures: Arc<Counter>,
+}
+
+impl ClearingMetrics {
+    pub fn new(registry: &Registry) -> Self {
+        Self {
+            instrument_updates: registry.counter(
+                "clearing_instrument_updates_total",
+                "Instrument changes and deletions sent to the matching engines",
+                &[],
+            ),
+            connected_engines: registry.gauge(
+                "clearing_connected_engines",
+                "Matching engines connected",
+                &[],
+            ),
+            trade_captures: registry.counter(
+                "clearing_trade_captures_total",
+                "Trade captures accounted for in the positions",
+                &[],
+            ),
+        }
+    }
+}
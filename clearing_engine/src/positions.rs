@@ -0,0 +1,236 @@
+//! Net positions of the participants, kept from the trade captures of the
+//! matching engines, and the exposure limits applied to them
+//!
+//! A participant whose position in an instrument reaches its limit, long or
+//! short, is blocked from adding to it: the matching engines reject its orders
+//! on that side of the book until the position goes back under the limit.
+
+use std::{collections::HashMap, num::ParseIntError};
+
+use oep::tradecapture::TradeCapture;
+use order::Side;
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+/// Maximum net position, in absolute value, of a participant in any instrument
+/// 0 means unlimited
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExposureLimits {
+    default: u64,
+    // participant -> its own limit
+    participants: HashMap<u64, u64>,
+}
+
+impl ExposureLimits {
+    /// Loads the [exposure] section: the `default` key applies to all the
+    /// participants, while a key named after a participant overrides it
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self, ParseIntError> {
+        let mut r = Self::default();
+        let Some(section) = config_map.get("exposure") else {
+            return Ok(r);
+        };
+        for (key, value) in section {
+            let limit = value.as_deref().unwrap_or_default().parse::<u64>()?;
+            match key.as_str() {
+                "default" => r.default = limit,
+                participant => {
+                    r.participants.insert(participant.parse::<u64>()?, limit);
+                }
+            }
+        }
+        Ok(r)
+    }
+
+    pub fn get(&self, participant: u64) -> u64 {
+        *self.participants.get(&participant).unwrap_or(&self.default)
+    }
+}
+
+/// A change of the side a participant can't add risk on, to be sent to the
+/// matching engines
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExposureUpdate {
+    pub participant: u64,
+    pub book_id: u64,
+    // None once the block is lifted
+    pub blocked_side: Option<Side>,
+}
+
+#[derive(Debug, Default)]
+pub struct PositionKeeper {
+    limits: ExposureLimits,
+    // (participant, book id) -> net position, positive when long
+    positions: HashMap<(u64, u64), i128>,
+    // (participant, book id) -> the side that would increase the position
+    blocks: HashMap<(u64, u64), Side>,
+}
+
+impl PositionKeeper {
+    pub fn new(limits: ExposureLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_position(&self, participant: u64, book_id: u64) -> i128 {
+        *self.positions.get(&(participant, book_id)).unwrap_or(&0)
+    }
+
+    /// Accounts for the trade of @capture on both its counterparties
+    ///
+    /// Returns: the blocks set or lifted by the trade
+    pub fn add_trade(&mut self, capture: &TradeCapture) -> Vec<ExposureUpdate> {
+        let quantity = capture.quantity as i128;
+        [(capture.buyer, quantity), (capture.seller, -quantity)]
+            .into_iter()
+            .filter_map(|(participant, change)| {
+                let position = self
+                    .positions
+                    .entry((participant, capture.book_id))
+                    .or_default();
+                *position += change;
+                let position = *position;
+                self.update_block(participant, capture.book_id, position)
+            })
+            .collect()
+    }
+
+    /// The blocks in place, e.g. for a matching engine that just connected
+    pub fn get_blocks(&self) -> Vec<ExposureUpdate> {
+        self.blocks
+            .iter()
+            .map(|(&(participant, book_id), &side)| ExposureUpdate {
+                participant,
+                book_id,
+                blocked_side: Some(side),
+            })
+            .collect()
+    }
+
+    fn update_block(
+        &mut self,
+        participant: u64,
+        book_id: u64,
+        position: i128,
+    ) -> Option<ExposureUpdate> {
+        let limit = self.limits.get(participant) as i128;
+        let blocked_side = match position {
+            _ if limit == 0 => None,
+            p if p >= limit => Some(Side::Bid),
+            p if p <= -limit => Some(Side::Ask),
+            _ => None,
+        };
+        let key = (participant, book_id);
+        if self.blocks.get(&key).copied() == blocked_side {
+            return None;
+        }
+        match blocked_side {
+            Some(side) => self.blocks.insert(key, side),
+            None => self.blocks.remove(&key),
+        };
+        Some(ExposureUpdate {
+            participant,
+            book_id,
+            blocked_side,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use configparser::ini::Ini;
+    use oep::tradecapture::TradeCapture;
+    use order::Side;
+
+    use super::{ExposureLimits, ExposureUpdate, PositionKeeper};
+
+    fn trade(buyer: u64, seller: u64, quantity: u64) -> TradeCapture {
+        TradeCapture {
+            seq: 1,
+            book_id: 500,
+            trade_id: 1,
+            price: 1000,
+            quantity,
+            buyer,
+            seller,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn limits_from_config() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[exposure]
+                default=1000
+                11=50",
+            ))
+            .unwrap();
+        let limits = ExposureLimits::from_config(&config_map).unwrap();
+        assert_eq!(50, limits.get(11));
+        assert_eq!(1000, limits.get(12));
+
+        let config_map = Ini::new().read(String::from("[clearing]")).unwrap();
+        assert_eq!(0, ExposureLimits::from_config(&config_map).unwrap().get(11));
+
+        let config_map = Ini::new()
+            .read(String::from(
+                "[exposure]
+                someone=1000",
+            ))
+            .unwrap();
+        assert!(ExposureLimits::from_config(&config_map).is_err());
+    }
+
+    #[test]
+    fn net_positions() {
+        let mut target = PositionKeeper::default();
+        assert!(target.add_trade(&trade(11, 12, 100)).is_empty());
+        assert!(target.add_trade(&trade(12, 11, 30)).is_empty());
+        assert_eq!(70, target.get_position(11, 500));
+        assert_eq!(-70, target.get_position(12, 500));
+        assert_eq!(0, target.get_position(11, 501));
+        // trading with itself doesn't move the position
+        target.add_trade(&trade(11, 11, 1000));
+        assert_eq!(70, target.get_position(11, 500));
+    }
+
+    #[test]
+    fn blocks_at_the_limit() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[exposure]
+                default=100",
+            ))
+            .unwrap();
+        let mut target = PositionKeeper::new(ExposureLimits::from_config(&config_map).unwrap());
+
+        assert!(target.add_trade(&trade(11, 12, 60)).is_empty());
+        let updates = target.add_trade(&trade(11, 12, 40));
+        assert_eq!(
+            vec![
+                ExposureUpdate {
+                    participant: 11,
+                    book_id: 500,
+                    blocked_side: Some(Side::Bid),
+                },
+                ExposureUpdate {
+                    participant: 12,
+                    book_id: 500,
+                    blocked_side: Some(Side::Ask),
+                },
+            ],
+            updates
+        );
+        assert_eq!(2, target.get_blocks().len());
+        // still over the limit, nothing changes
+        assert!(target.add_trade(&trade(11, 12, 10)).is_empty());
+
+        // back under the limit
+        let updates = target.add_trade(&trade(12, 11, 20));
+        assert_eq!(2, updates.len());
+        assert!(updates.iter().all(|u| u.blocked_side.is_none()));
+        assert!(target.get_blocks().is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    io::BufRead,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use instruments::price::parse_price;
+use oep::{
+    cancel::Cancel,
+    connection::{Connection, MessageTypes},
+    execution_report::ExecutionReport,
+    modify::Modify,
+    neworder::NewOrder,
+};
+use order::{OrderState, OrderType};
+use tracing::warn;
+
+/// How long the reports are waited for once all the commands are sent
+const REPORT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The ids put in the messages of the session
+#[derive(Debug, Clone, Copy)]
+pub struct SessionIds {
+    pub participant: u64,
+    pub gateway_id: u8,
+    pub session_id: u32,
+}
+
+fn side(s: &str) -> Result<u8> {
+    match s {
+        "bid" => Ok(0),
+        "ask" => Ok(1),
+        _ => bail!("Unknown side {s}"),
+    }
+}
+
+/// The message of the command @line, one of
+///     new_order,book_id,bid|ask,quantity,price[,day|ioc]
+///     modify,order_id,book_id,bid|ask,quantity,price
+///     cancel,order_id,book_id,bid|ask
+///     cancel_client,client_order_id,book_id,bid|ask
+/// None for the blank lines and the comments, starting with #. The new
+/// orders are given @client_order_id, and their prices may have up to
+/// @price_decimals decimals, sent scaled
+pub fn parse_command(
+    line: &str,
+    ids: SessionIds,
+    client_order_id: u64,
+    price_decimals: u8,
+) -> Result<Option<MessageTypes>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let number = |i: usize| -> Result<u64> {
+        let field = fields.get(i).ok_or(anyhow!("Missing field {i}"))?;
+        field
+            .parse::<u64>()
+            .with_context(|| format!("Invalid number {field}"))
+    };
+    let price = |i: usize| -> Result<u64> {
+        let field = fields.get(i).ok_or(anyhow!("Missing field {i}"))?;
+        parse_price(field, price_decimals).ok_or(anyhow!("Invalid price {field}"))
+    };
+    let side_at = |i: usize| side(fields.get(i).ok_or(anyhow!("Missing side"))?);
+    let message = match fields[0] {
+        "new_order" if (5..=6).contains(&fields.len()) => MessageTypes::NewOrder(NewOrder {
+            client_order_id,
+            participant: ids.participant,
+            book_id: number(1)?,
+            quantity: number(3)?,
+            price: price(4)?,
+            order_type: match fields.get(5).copied().unwrap_or("day") {
+                "day" => OrderType::Day.into(),
+                "ioc" => OrderType::FillAndKill.into(),
+                other => bail!("Unknown order type {other}"),
+            },
+            side: side_at(2)?,
+            gateway_id: ids.gateway_id,
+            session_id: ids.session_id,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        }),
+        "modify" if fields.len() == 6 => MessageTypes::Modify(Modify {
+            participant: ids.participant,
+            order_id: number(1)?,
+            book_id: number(2)?,
+            quantity: number(4)?,
+            price: price(5)?,
+            gateway_id: ids.gateway_id,
+            session_id: ids.session_id,
+            side: side_at(3)?,
+            orig_client_order_id: 0,
+        }),
+        "cancel" if fields.len() == 4 => MessageTypes::Cancel(Cancel {
+            participant: ids.participant,
+            order_id: number(1)?,
+            book_id: number(2)?,
+            gateway_id: ids.gateway_id,
+            session_id: ids.session_id,
+            side: side_at(3)?,
+            orig_client_order_id: 0,
+        }),
+        // the order known by the client order id of its new order only
+        "cancel_client" if fields.len() == 4 => MessageTypes::Cancel(Cancel {
+            participant: ids.participant,
+            order_id: 0,
+            book_id: number(2)?,
+            gateway_id: ids.gateway_id,
+            session_id: ids.session_id,
+            side: side_at(3)?,
+            orig_client_order_id: number(1)?,
+        }),
+        _ => bail!("Invalid command {line}"),
+    };
+    Ok(Some(message))
+}
+
+/// The CSV line printed for @report
+///     report,order_id,submitted_order_id,book_id,side,state,price,quantity,filled,leaves,reject_reason
+pub fn format_report(report: &ExecutionReport) -> String {
+    format!(
+        "report,{},{},{},{},{:?},{},{},{},{},{:?}",
+        report.get_order_id(),
+        report.get_submitted_order_id(),
+        report.get_book(),
+        if report.side == 0 { "bid" } else { "ask" },
+        OrderState::from(report.state),
+        report.get_price(),
+        report.get_quantity(),
+        report.get_filled_quantity(),
+        report.get_leaves_quantity(),
+        report.get_reject_reason(),
+    )
+}
+
+fn print_reports(messages: Vec<MessageTypes>) {
+    for message in messages {
+        if let MessageTypes::ExecutionReport(report) = message {
+            println!("{}", format_report(&report));
+        }
+    }
+}
+
+/// Sends the commands read from @input, one per line, printing the execution
+/// reports as they come back, then the ones coming up to REPORT_TIMEOUT after
+/// the last command. A line that can't be parsed is reported on stderr and
+/// skipped
+pub fn run(
+    connection: &Connection,
+    ids: SessionIds,
+    price_decimals: u8,
+    input: impl BufRead,
+) -> Result<()> {
+    let mut next_client_order_id = 1;
+    for (number, line) in input.lines().enumerate() {
+        match parse_command(&line?, ids, next_client_order_id, price_decimals) {
+            Ok(Some(message)) => {
+                if let MessageTypes::NewOrder(_) = message {
+                    next_client_order_id += 1;
+                }
+                connection.send_message(message)?;
+            }
+            Ok(None) => (),
+            Err(e) => warn!(line = number + 1, error = %e, "Skipping an invalid command"),
+        }
+        print_reports(connection.poll_messages()?);
+    }
+    let deadline = Instant::now() + REPORT_TIMEOUT;
+    while let Some(message) =
+        connection.recv_message(deadline.saturating_duration_since(Instant::now()))
+    {
+        print_reports(vec![message]);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use oep::{connection::MessageTypes, execution_report::ExecutionReport};
+    use order::OrderType;
+
+    use super::{format_report, parse_command, SessionIds};
+
+    const IDS: SessionIds = SessionIds {
+        participant: 666,
+        gateway_id: 1,
+        session_id: 1000,
+    };
+
+    #[test]
+    fn commands() {
+        let Some(MessageTypes::NewOrder(order)) =
+            parse_command("new_order,42,ask,100,1234,ioc", IDS, 7, 0).unwrap()
+        else {
+            panic!("Not a new order");
+        };
+        assert_eq!(
+            (7, 666, 42, 100, 1234, 1),
+            (
+                { order.client_order_id },
+                { order.participant },
+                { order.book_id },
+                { order.quantity },
+                { order.price },
+                order.side
+            )
+        );
+        assert_eq!(OrderType::FillAndKill, { order.order_type }.into());
+        let Some(MessageTypes::NewOrder(order)) =
+            parse_command(" new_order, 42, bid, 100, 1234 ", IDS, 8, 0).unwrap()
+        else {
+            panic!("Not a new order");
+        };
+        assert_eq!(OrderType::Day, { order.order_type }.into());
+
+        let Some(MessageTypes::Modify(modify)) =
+            parse_command("modify,5,42,bid,50,1235", IDS, 9, 0).unwrap()
+        else {
+            panic!("Not a modify");
+        };
+        assert_eq!(
+            (5, 42, 50, 1235, 0),
+            (
+                { modify.order_id },
+                { modify.book_id },
+                { modify.quantity },
+                { modify.price },
+                modify.side
+            )
+        );
+        let Some(MessageTypes::Cancel(cancel)) =
+            parse_command("cancel,5,42,ask", IDS, 9, 0).unwrap()
+        else {
+            panic!("Not a cancel");
+        };
+        assert_eq!(
+            (5, 42, 1),
+            ({ cancel.order_id }, { cancel.book_id }, cancel.side)
+        );
+        let Some(MessageTypes::Cancel(cancel)) =
+            parse_command("cancel_client,3,42,bid", IDS, 9, 0).unwrap()
+        else {
+            panic!("Not a cancel");
+        };
+        assert_eq!(
+            (0, 3, 0),
+            (
+                { cancel.order_id },
+                { cancel.orig_client_order_id },
+                cancel.side
+            )
+        );
+
+        assert!(parse_command("", IDS, 9, 0).unwrap().is_none());
+        assert!(parse_command("# a comment", IDS, 9, 0).unwrap().is_none());
+        for invalid in [
+            "new_order,42,ask,100",
+            "new_order,42,sell,100,1234",
+            "new_order,42,ask,100,1234,gtc",
+            "modify,x,42,bid,50,1235",
+            "cancel,5,42",
+            "replace,5,42,ask",
+        ] {
+            assert!(parse_command(invalid, IDS, 9, 0).is_err(), "{invalid}");
+        }
+
+        // decimal prices, scaled
+        let Some(MessageTypes::NewOrder(order)) =
+            parse_command("new_order,42,ask,100,12.34", IDS, 10, 2).unwrap()
+        else {
+            panic!("Not a new order");
+        };
+        assert_eq!(1234, { order.price });
+        assert!(parse_command("new_order,42,ask,100,12.345", IDS, 10, 2).is_err());
+        assert!(parse_command("new_order,42,ask,100,12.34", IDS, 10, 0).is_err());
+    }
+
+    #[test]
+    fn report() {
+        let report = ExecutionReport {
+            participant: 666,
+            order_id: 10,
+            submitted_order_id: 7,
+            book: 42,
+            quantity: 100,
+            price: 1234,
+            flags: 0,
+            side: 1,
+            state: 5,
+            session_id: 1000,
+            gateway_id: 1,
+            filled_quantity: 40,
+            leaves_quantity: 60,
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
+        };
+        assert_eq!(
+            "report,10,7,42,ask,PartiallyTraded,1234,100,40,60,None",
+            format_report(&report)
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! The typed sections of client.ini
+
+use serde::Deserialize;
+use utils::config::{self, ConfigError, ConfigMap};
+
+/// The [gateway] section, the session the client logs in with
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct GatewayLogin {
+    pub address: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub participant: u64,
+    pub session_id: u32,
+    pub gateway_id: u8,
+}
+
+// the keys of the [client] section read here, the logs aside
+#[derive(Debug, Default, Deserialize)]
+struct ClientSection {
+    #[serde(default)]
+    price_decimals: u8,
+}
+
+/// What the client needs, the feed only being joined when interactive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    pub gateway: GatewayLogin,
+    // the decimals of the prices of the commands given from a file, there
+    // being no feed to tell them
+    pub price_decimals: u8,
+}
+
+impl ClientConfig {
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self, ConfigError> {
+        let client: ClientSection = config::section(config_map, "client")?;
+        Ok(Self {
+            gateway: config::section(config_map, "gateway")?,
+            price_decimals: client.price_decimals,
+        })
+    }
+}
+
+/// The [feed] section, the channels the instruments are learnt from
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FeedGroups {
+    pub group: String,
+    pub port: u16,
+    pub snapshot_group: String,
+    pub snapshot_port: u16,
+}
+
+impl FeedGroups {
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self, ConfigError> {
+        config::section(config_map, "feed")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use configparser::ini::Ini;
+
+    use super::{ClientConfig, FeedGroups};
+
+    #[test]
+    fn from_config() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                address=127.0.0.1
+                port=10000
+                username=abc
+                password=pass
+                participant=666
+                session_id=1000
+                gateway_id=1",
+            ))
+            .unwrap();
+        let target = ClientConfig::from_config(&config_map).unwrap();
+        assert_eq!(target.gateway.participant, 666);
+        assert_eq!(target.gateway.session_id, 1000);
+        assert_eq!(target.price_decimals, 0);
+        // only needed when interactive
+        assert_eq!(
+            FeedGroups::from_config(&config_map)
+                .unwrap_err()
+                .to_string(),
+            "group in the [feed] section: missing, please set it"
+        );
+
+        let config_map = Ini::new()
+            .read(String::from(
+                "[client]
+                price_decimals=2
+                [gateway]
+                address=127.0.0.1
+                port=10000
+                username=abc
+                password=pass
+                participant=-1
+                session_id=1000
+                gateway_id=1",
+            ))
+            .unwrap();
+        assert_eq!(
+            ClientConfig::from_config(&config_map).unwrap_err().key(),
+            Some("participant")
+        );
+    }
+}
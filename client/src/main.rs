@@ -1,7 +1,6 @@
 use std::{
-    io::Read,
-    net::{Ipv4Addr, SocketAddrV4},
-    str::FromStr,
+    fs::File,
+    io::{BufReader, Read},
     sync::{Arc, Mutex},
     thread,
     time::Duration,
@@ -9,14 +8,24 @@ use std::{
 
 use anyhow::Result;
 use dialoguer::{theme::ColorfulTheme, Completion, FuzzySelect, Input};
-use instruments::instrument::Instrument;
-use oep::{cancel::Cancel, connection::MessageTypes, modify::Modify, neworder::NewOrder};
+use disseminator::batch::split;
+use instruments::{
+    instrument::Instrument,
+    price::{format_price, parse_price},
+};
+use oep::{
+    cancel::Cancel, connection::MessageTypes, modify::Modify, neworder::NewOrder,
+    ordertracker::OrderTracker,
+};
 
-use configparser::ini::Ini;
+use config::{ClientConfig, FeedGroups};
 use order::OrderType;
-use socket2::SockAddr;
-use utils::config;
-use utils::network;
+use tracing::{debug, info};
+use utils::logging::{self, LogConfig};
+use utils::network::{self, MulticastConfig};
+
+mod batch;
+mod config;
 
 struct InstrumentCompletion {
     options: Vec<String>,
@@ -50,43 +59,31 @@ impl Completion for InstrumentCompletion {
     }
 }
 
-fn main() -> Result<()> {
-    //read configuration file
-    println!("Loading configuration file");
-    let mut config = Ini::new();
-    let config_map = config
-        .load("client.ini")
-        .expect("Unable to load the configuration file");
-
-    let instruments: Arc<Mutex<Vec<Instrument>>> = Arc::new(Mutex::new(vec![]));
-
-    let feed_group = config::get_config_string(&config_map, "feed", "group");
-    let feed_port = config::get_config_string(&config_map, "feed", "port")
-        .parse::<u16>()
-        .expect("Feed port not an u16");
-
-    let instrument_list = instruments.clone();
-    thread::spawn(move || {
-        let mut listener = network::join_multicast_group(&SockAddr::from(
-            std::net::SocketAddr::V4(SocketAddrV4::new(
-                Ipv4Addr::from_str(&feed_group).expect("Invalid feed group address"),
-                feed_port,
-            )),
-        ))
-        .expect("Couldn't create the listener");
-        let mut buffer: [u8; 2000] = [0; 2000];
-        loop {
-            let r = listener
-                .read(&mut buffer)
-                .expect("read error from the feed socket");
-            if r > 8 && buffer[8] == 1 {
+/// Keeps @instrument_list up to date with the instrument messages
+/// received on the @group:@port multicast, joined as set in @multicast
+fn listen_for_instruments(
+    group: &str,
+    port: u16,
+    multicast: &MulticastConfig,
+    instrument_list: Arc<Mutex<Vec<Instrument>>>,
+) {
+    let mut listener = network::join_multicast_group(
+        &network::socket_address(group, port).expect("Invalid feed group address"),
+        multicast,
+    )
+    .expect("Couldn't create the listener");
+    let mut buffer: [u8; 2000] = [0; 2000];
+    loop {
+        let r = listener
+            .read(&mut buffer)
+            .expect("read error from the feed socket");
+        let Some((_, messages)) = split(&buffer[..r]) else {
+            continue;
+        };
+        for message in messages {
+            if message.len() > 1 && message[0] == 1 {
                 // we got ourselves an instrument update
-                let instrument_slice = &buffer[9..r];
-                let instrument = Instrument::decode(
-                    instrument_slice
-                        .try_into()
-                        .expect("Instrument decoding error"),
-                );
+                let instrument = Instrument::decode(&message[1..]);
                 // let's try figuring out if we already have the instrument or if it's a new one
                 let mut ilist = instrument_list.lock().expect("ilist lock");
                 let mut found_at = ilist.len();
@@ -96,7 +93,7 @@ fn main() -> Result<()> {
                         break;
                     }
                 }
-                eprintln!("Received instrument {:#?}", instrument);
+                debug!(instrument = ?instrument, "Received instrument");
                 if found_at == ilist.len() {
                     ilist.push(instrument);
                 } else {
@@ -104,36 +101,82 @@ fn main() -> Result<()> {
                 }
             }
         }
-    });
+    }
+}
+
+/// Interactive, unless given a file of commands to send, - for the standard
+/// input (see batch::parse_command)
+fn main() -> Result<()> {
+    let batch_input = std::env::args().nth(1);
+
+    //read configuration file
+    let config_map = utils::config::load("client.ini")?;
+    let log_config = LogConfig::from_config(&config_map, "client")
+        .expect("Invalid log settings in the client section");
+    logging::init(&log_config).expect("Unable to start logging");
+
+    let instruments: Arc<Mutex<Vec<Instrument>>> = Arc::new(Mutex::new(vec![]));
+
+    let client_config = ClientConfig::from_config(&config_map)?;
+    // the instruments come with the snapshots, and on the feed when their state changes
+    let feeds = match batch_input {
+        Some(_) => vec![],
+        None => {
+            let groups = FeedGroups::from_config(&config_map)?;
+            vec![
+                (groups.group, groups.port),
+                (groups.snapshot_group, groups.snapshot_port),
+            ]
+        }
+    };
+    let multicast = MulticastConfig::from_config(&config_map, "feed")
+        .expect("Invalid multicast settings in the feed section");
+    for (feed_group, feed_port) in feeds {
+        let instrument_list = instruments.clone();
+        let multicast = multicast.clone();
+        thread::spawn(move || {
+            listen_for_instruments(&feed_group, feed_port, &multicast, instrument_list)
+        });
+    }
 
     // Gateway section
-    eprintln!("Connecting to GW");
-    let gw_addr = config::get_config_string(&config_map, "gateway", "address");
-    let gw_port = config::get_config_string(&config_map, "gateway", "port")
-        .parse::<u16>()
-        .expect("Gateway port must be an u16");
-    let gw_user = config::get_config_string(&config_map, "gateway", "username");
-    let gw_pass = config::get_config_string(&config_map, "gateway", "password");
-    let gw_participant = config::get_config_string(&config_map, "gateway", "participant")
-        .parse::<u64>()
-        .expect("Gateway participant must be an u64");
-    let gw_session_id = config::get_config_string(&config_map, "gateway", "session_id")
-        .parse::<u32>()
-        .expect("Gateway session_id must be an u32");
-    let gw_gateway_id = config::get_config_string(&config_map, "gateway", "gateway_id")
-        .parse::<u8>()
-        .expect("Gateway gateway_id must be an u8");
+    info!("Connecting to GW");
+    let gateway = &client_config.gateway;
 
     let mut connection = oep::connection::Connection::default();
-    connection.connect(&gw_addr, gw_port)?;
+    connection.connect(&gateway.address, gateway.port)?;
     connection.login(
-        gw_participant,
-        gw_session_id,
-        gw_gateway_id,
-        &gw_user,
-        &gw_pass,
+        gateway.participant,
+        gateway.session_id,
+        gateway.gateway_id,
+        &gateway.username,
+        &gateway.password,
     )?;
     connection.wait_for_login(Some(5000))?;
+    // the prompts below can keep the session quiet for a long time
+    connection.start_heartbeats(Duration::from_secs(1))?;
+    if let Some(input) = batch_input {
+        let ids = batch::SessionIds {
+            participant: gateway.participant,
+            gateway_id: gateway.gateway_id,
+            session_id: gateway.session_id,
+        };
+        // there is no feed to tell the decimals of the instruments
+        let price_decimals = client_config.price_decimals;
+        return match input.as_str() {
+            "-" => batch::run(&connection, ids, price_decimals, std::io::stdin().lock()),
+            path => batch::run(
+                &connection,
+                ids,
+                price_decimals,
+                BufReader::new(File::open(path)?),
+            ),
+        };
+    }
+    // the execution reports are applied to the orders as they come
+    let tracker = Arc::new(Mutex::new(OrderTracker::new()));
+    connection.track_orders(tracker.clone())?;
+    let mut next_client_order_id = 1;
 
     macro_rules! get_instrument_id {
         () => {{
@@ -155,19 +198,33 @@ fn main() -> Result<()> {
                 })
                 .interact_text()
                 .unwrap();
-            let instrument_id = instruments
+            let (instrument_id, price_decimals) = instruments
                 .lock()
                 .unwrap()
                 .iter()
                 .find(|x| x.get_name() == instrument)
-                .unwrap()
-                .get_id();
-            (instrument, instrument_id)
+                .map(|x| (x.get_id(), x.get_price_decimals()))
+                .unwrap();
+            (instrument, instrument_id, price_decimals)
         }};
     }
 
+    // in decimals, sent scaled to those of the instrument
+    let get_price = |price_decimals: u8| {
+        let price = Input::<String>::with_theme(&ColorfulTheme::default())
+            .with_prompt("Price")
+            .validate_with(|p: &String| -> Result<(), &str> {
+                parse_price(p, price_decimals)
+                    .map(|_| ())
+                    .ok_or("Not a price of the instrument")
+            })
+            .interact_text()
+            .unwrap();
+        parse_price(&price, price_decimals).unwrap()
+    };
+
     loop {
-        let choices = ["new_order", "modify", "cancel", "quit"];
+        let choices = ["new_order", "modify", "cancel", "orders", "quit"];
 
         let selection = FuzzySelect::new()
             .with_prompt("Message type")
@@ -184,7 +241,7 @@ fn main() -> Result<()> {
                     .interact()
                     .unwrap();
                 let order_type = order_type[selection];
-                let (instrument, instrument_id) = get_instrument_id!();
+                let (instrument, instrument_id, price_decimals) = get_instrument_id!();
                 let side = ["bid", "ask"];
                 let selection = FuzzySelect::new()
                     .with_prompt("Side")
@@ -197,18 +254,19 @@ fn main() -> Result<()> {
                     .with_prompt("Quantity")
                     .interact_text()
                     .unwrap();
-                let price = Input::<u64>::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Price")
-                    .interact_text()
-                    .unwrap();
+                let price = get_price(price_decimals);
 
                 println!(
                     "Your order: {} {} {} {}@{}",
-                    order_type, side, instrument, quantity, price
+                    order_type,
+                    side,
+                    instrument,
+                    quantity,
+                    format_price(price, price_decimals)
                 );
-                let order = MessageTypes::NewOrder(NewOrder {
-                    client_order_id: 0,
-                    participant: gw_participant,
+                let order = NewOrder {
+                    client_order_id: next_client_order_id,
+                    participant: gateway.participant,
                     book_id: instrument_id,
                     quantity: quantity,
                     price: price,
@@ -217,26 +275,28 @@ fn main() -> Result<()> {
                     } else {
                         OrderType::FillAndKill.into()
                     },
-                    side: if side == "buy" { 0 } else { 1 },
-                    gateway_id: gw_gateway_id,
-                    session_id: gw_session_id,
-                });
-                connection.send_message(order)?;
+                    side: if side == "bid" { 0 } else { 1 },
+                    gateway_id: gateway.gateway_id,
+                    session_id: gateway.session_id,
+                    expiry: 0,
+                    stop_price: 0,
+                    display_quantity: 0,
+                };
+                next_client_order_id += 1;
+                tracker.lock().unwrap().on_new_order(&order);
+                connection.send_message(MessageTypes::NewOrder(order))?;
             }
             "modify" => {
                 let order_id = Input::<u64>::with_theme(&ColorfulTheme::default())
                     .with_prompt("Order ID:")
                     .interact_text()
                     .unwrap();
-                let (_instrument, instrument_id) = get_instrument_id!();
+                let (_instrument, instrument_id, price_decimals) = get_instrument_id!();
                 let quantity = Input::<u64>::with_theme(&ColorfulTheme::default())
                     .with_prompt("Quantity")
                     .interact_text()
                     .unwrap();
-                let price = Input::<u64>::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Price")
-                    .interact_text()
-                    .unwrap();
+                let price = get_price(price_decimals);
                 let side = ["bid", "ask"];
                 let selection_side = FuzzySelect::new()
                     .with_prompt("Side")
@@ -244,14 +304,15 @@ fn main() -> Result<()> {
                     .interact()
                     .unwrap();
                 let order = MessageTypes::Modify(Modify {
-                    participant: gw_participant,
+                    participant: gateway.participant,
                     order_id: order_id,
                     book_id: instrument_id,
                     quantity: quantity,
                     price: price,
-                    gateway_id: gw_gateway_id,
-                    session_id: gw_session_id,
+                    gateway_id: gateway.gateway_id,
+                    session_id: gateway.session_id,
                     side: selection_side as u8,
+                    orig_client_order_id: 0,
                 });
                 connection.send_message(order)?;
             }
@@ -260,7 +321,7 @@ fn main() -> Result<()> {
                     .with_prompt("Order ID:")
                     .interact_text()
                     .unwrap();
-                let (_instrument, instrument_id) = get_instrument_id!();
+                let (_instrument, instrument_id, _) = get_instrument_id!();
                 let side = ["bid", "ask"];
                 let selection_side = FuzzySelect::new()
                     .with_prompt("Side")
@@ -268,22 +329,39 @@ fn main() -> Result<()> {
                     .interact()
                     .unwrap();
                 let order = MessageTypes::Cancel(Cancel {
-                    participant: gw_participant,
+                    participant: gateway.participant,
                     order_id: order_id,
                     book_id: instrument_id,
-                    gateway_id: gw_gateway_id,
-                    session_id: gw_session_id,
+                    gateway_id: gateway.gateway_id,
+                    session_id: gateway.session_id,
                     side: selection_side as u8,
+                    orig_client_order_id: 0,
                 });
                 connection.send_message(order)?;
             }
+            "orders" => {
+                let tracker = tracker.lock().unwrap();
+                for order in tracker.open_orders() {
+                    println!(
+                        "Order {} (client {}): {:?} {} {}@{}, {} filled",
+                        order.order_id,
+                        order.client_order_id,
+                        order.status,
+                        if order.side == 0 { "bid" } else { "ask" },
+                        order.leaves_quantity,
+                        order.price,
+                        order.filled_quantity
+                    );
+                }
+                for fill in tracker.fills() {
+                    println!(
+                        "Fill of order {}: {}@{} on book {}",
+                        fill.order_id, fill.quantity, fill.price, fill.book_id
+                    );
+                }
+            }
             "quit" | _ => break,
         }
-        // wait 1 second for something from gateway
-        match connection.recv_message(Duration::from_secs(1)) {
-            Some(m) => println!("{:#?}", m),
-            None => {}
-        }
     }
     Ok(())
 }
@@ -0,0 +1,334 @@
+//! The scripted cases, each of them on a session of its own
+//!
+//! The orders are FillAndKill: nothing is left in the books, and no execution
+//! report of a previous run waits for the sessions when they log in again.
+
+use std::time::Duration;
+
+use oep::{
+    decoder::Decoder,
+    execution_report::RejectReason,
+    header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+    login::{Login, LOGIN_SIZE},
+    neworder::{NewOrder, NEWORDER_SIZE},
+    oep_message::MsgType,
+    version::MIN_OEP_VERSION,
+};
+use order::{OrderType, Side};
+
+use crate::script::Step;
+
+// longer than the gateway takes, whatever the message
+const OVERSIZED_LEN: u32 = 1 << 20;
+// no message type has it
+const UNKNOWN_MSG_TYPE: u16 = 999;
+
+/// Who the cases log in as, and where they send their orders
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub participant: u64,
+    pub session_id: u32,
+    pub gateway_id: u8,
+    pub username: String,
+    pub password: String,
+    pub book_id: u64,
+}
+
+impl Session {
+    fn login_body(&self, gateway_id: u8) -> [u8; LOGIN_SIZE] {
+        let mut login = Login::new(
+            self.participant,
+            self.session_id,
+            gateway_id,
+            &self.username,
+        );
+        login.hash_text_to_password(&self.password);
+        login.encode()
+    }
+
+    /// The login of the session, in @version
+    fn login_in(&self, version: u16, gateway_id: u8) -> Vec<u8> {
+        let header = OepHeader::new(version, MsgType::Login.into(), LOGIN_SIZE as u32);
+        [header.encode().as_slice(), &self.login_body(gateway_id)].concat()
+    }
+
+    fn login(&self) -> Vec<u8> {
+        self.login_in(OEP_VERSION, self.gateway_id)
+    }
+
+    /// A new order, sent with @gateway_id, its sequence stamped when played
+    fn order_for(&self, client_order_id: u64, gateway_id: u8) -> Vec<u8> {
+        let order = NewOrder {
+            client_order_id,
+            participant: self.participant,
+            book_id: self.book_id,
+            quantity: 1,
+            price: 1,
+            order_type: OrderType::FillAndKill.into(),
+            side: Side::Bid.into(),
+            gateway_id,
+            session_id: self.session_id,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        };
+        let header = OepHeader::new(OEP_VERSION, MsgType::NewOrder.into(), NEWORDER_SIZE as u32);
+        [header.encode().as_slice(), &order.encode()].concat()
+    }
+
+    fn order(&self, client_order_id: u64) -> Vec<u8> {
+        self.order_for(client_order_id, self.gateway_id)
+    }
+}
+
+/// A script, and what it checks
+pub struct Case {
+    pub name: &'static str,
+    pub build: fn(&Session) -> Vec<Step>,
+}
+
+/// A header announcing @msg_len bytes of @msg_type, followed by @body
+fn raw(msg_type: u16, msg_len: u32, body: &[u8]) -> Vec<u8> {
+    let header = OepHeader::new(OEP_VERSION, msg_type, msg_len).encode();
+    [header.as_slice(), body].concat()
+}
+
+fn login(s: &Session) -> Vec<Step> {
+    vec![Step::Send(s.login()), Step::Expect(MsgType::Login)]
+}
+
+/// The login and the order written a few bytes at a time
+fn fragmented(s: &Session) -> Vec<Step> {
+    let mut steps = vec![];
+    for piece in s.login().chunks(7) {
+        steps.push(Step::Send(piece.to_vec()));
+        steps.push(Step::Pause(Duration::from_millis(5)));
+    }
+    steps.push(Step::Expect(MsgType::Login));
+    // the header alone first
+    let order = s.order(1);
+    steps.push(Step::SendSequenced(order[..OEP_HEADER_SIZE].to_vec(), 0));
+    steps.push(Step::ExpectSilence);
+    steps.push(Step::Send(order[OEP_HEADER_SIZE..].to_vec()));
+    steps.push(Step::Expect(MsgType::ExecutionReport));
+    steps
+}
+
+/// Two orders in a single write
+fn coalesced(s: &Session) -> Vec<Step> {
+    vec![
+        Step::Send(s.login()),
+        Step::Expect(MsgType::Login),
+        Step::SendSequenced([s.order(1), s.order(2)].concat(), 0),
+        Step::Expect(MsgType::ExecutionReport),
+        Step::Expect(MsgType::ExecutionReport),
+    ]
+}
+
+/// Half a message is waited for, not taken for garbage
+fn truncated(s: &Session) -> Vec<Step> {
+    let login = s.login();
+    let half = login.len() / 2;
+    vec![
+        Step::Send(login[..half].to_vec()),
+        Step::ExpectSilence,
+        Step::Send(login[half..].to_vec()),
+        Step::Expect(MsgType::Login),
+    ]
+}
+
+fn order(s: &Session) -> Vec<Step> {
+    let mut steps = login(s);
+    steps.push(Step::SendSequenced(s.order(1), 0));
+    steps.push(Step::Expect(MsgType::ExecutionReport));
+    steps
+}
+
+fn order_before_login(s: &Session) -> Vec<Step> {
+    vec![Step::Send(s.order(1)), Step::ExpectDisconnect]
+}
+
+/// Rejected, the session carrying on with the sequence expected
+fn out_of_sequence(s: &Session) -> Vec<Step> {
+    let mut steps = login(s);
+    steps.push(Step::SendSequenced(s.order(1), 5));
+    steps.push(Step::ExpectReject(RejectReason::OutOfSequence));
+    steps.push(Step::SendSequenced(s.order(2), 0));
+    steps.push(Step::Expect(MsgType::ExecutionReport));
+    steps
+}
+
+fn login_twice(s: &Session) -> Vec<Step> {
+    let mut steps = login(s);
+    steps.push(Step::Send(s.login()));
+    steps.push(Step::ExpectDisconnect);
+    steps
+}
+
+fn login_wrong_gateway(s: &Session) -> Vec<Step> {
+    vec![
+        Step::Send(s.login_in(OEP_VERSION, s.gateway_id.wrapping_add(1))),
+        Step::ExpectDisconnect,
+    ]
+}
+
+fn order_wrong_gateway(s: &Session) -> Vec<Step> {
+    let mut steps = login(s);
+    steps.push(Step::SendSequenced(
+        s.order_for(1, s.gateway_id.wrapping_add(1)),
+        0,
+    ));
+    steps.push(Step::ExpectDisconnect);
+    steps
+}
+
+/// Older than the gateway speaks
+fn unsupported_version(s: &Session) -> Vec<Step> {
+    vec![
+        Step::Send(s.login_in(MIN_OEP_VERSION - 1, s.gateway_id)),
+        Step::Expect(MsgType::VersionReject),
+        Step::ExpectDisconnect,
+    ]
+}
+
+/// Newer than the gateway speaks, agreed on in the version of the gateway
+fn newer_version(s: &Session) -> Vec<Step> {
+    vec![
+        Step::Send(s.login_in(OEP_VERSION + 1, s.gateway_id)),
+        Step::Expect(MsgType::Login),
+    ]
+}
+
+/// A header announcing more than any message
+fn oversized(s: &Session) -> Vec<Step> {
+    vec![
+        Step::Send(raw(
+            MsgType::Login.into(),
+            OVERSIZED_LEN,
+            &s.login_body(s.gateway_id),
+        )),
+        Step::ExpectDisconnect,
+    ]
+}
+
+/// A login shorter than a login
+fn wrong_length(s: &Session) -> Vec<Step> {
+    let body = s.login_body(s.gateway_id);
+    vec![
+        Step::Send(raw(
+            MsgType::Login.into(),
+            LOGIN_SIZE as u32 - 8,
+            &body[..LOGIN_SIZE - 8],
+        )),
+        Step::ExpectDisconnect,
+    ]
+}
+
+fn unknown_type(s: &Session) -> Vec<Step> {
+    let mut steps = login(s);
+    steps.push(Step::Send(raw(UNKNOWN_MSG_TYPE, 8, &[0; 8])));
+    steps.push(Step::ExpectDisconnect);
+    steps
+}
+
+/// All the cases, in the order they are run
+pub fn all() -> Vec<Case> {
+    vec![
+        Case {
+            name: "login",
+            build: login,
+        },
+        Case {
+            name: "fragmented",
+            build: fragmented,
+        },
+        Case {
+            name: "coalesced",
+            build: coalesced,
+        },
+        Case {
+            name: "truncated",
+            build: truncated,
+        },
+        Case {
+            name: "order",
+            build: order,
+        },
+        Case {
+            name: "order_before_login",
+            build: order_before_login,
+        },
+        Case {
+            name: "out_of_sequence",
+            build: out_of_sequence,
+        },
+        Case {
+            name: "login_twice",
+            build: login_twice,
+        },
+        Case {
+            name: "login_wrong_gateway",
+            build: login_wrong_gateway,
+        },
+        Case {
+            name: "order_wrong_gateway",
+            build: order_wrong_gateway,
+        },
+        Case {
+            name: "unsupported_version",
+            build: unsupported_version,
+        },
+        Case {
+            name: "newer_version",
+            build: newer_version,
+        },
+        Case {
+            name: "oversized",
+            build: oversized,
+        },
+        Case {
+            name: "wrong_length",
+            build: wrong_length,
+        },
+        Case {
+            name: "unknown_type",
+            build: unknown_type,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use oep::{header::OEP_HEADER_SIZE, neworder::NEWORDER_SIZE, oep_decode};
+
+    use super::{all, Session};
+    use crate::script::Step;
+
+    #[test]
+    fn well_formed() {
+        let session = Session {
+            participant: 666,
+            session_id: 2000,
+            gateway_id: 1,
+            username: String::from("abc"),
+            password: String::from("pass"),
+            book_id: 1,
+        };
+        assert!(oep_decode(&session.login()).is_ok());
+        assert_eq!(OEP_HEADER_SIZE + NEWORDER_SIZE, session.order(1).len());
+        assert!(oep_decode(&session.order(1)).is_ok());
+
+        let cases = all();
+        let mut names: Vec<_> = cases.iter().map(|c| c.name).collect();
+        names.dedup();
+        assert_eq!(cases.len(), names.len());
+        // every script ends up checking something
+        for case in cases {
+            let steps = (case.build)(&session);
+            assert!(!matches!(
+                steps.last(),
+                Some(Step::Send(_) | Step::SendSequenced(..) | Step::Pause(_)) | None
+            ));
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::{net::TcpStream, time::Duration};
+
+use anyhow::{bail, Result};
+use configparser::ini::Ini;
+use utils::config;
+
+use cases::Session;
+use script::Timeouts;
+
+mod cases;
+mod script;
+
+fn main() -> Result<()> {
+    println!("Loading configuration file");
+    let mut config = Ini::new();
+    let config_map = config
+        .load("conformance.ini")
+        .expect("Unable to load the configuration file");
+    let string = |section: &str, key: &str| config::get_config_string(&config_map, section, key);
+    let optional = |key: &str| config::get_optional_config_string(&config_map, "conformance", key);
+    let millis = |key: &str, default: u64| {
+        optional(key)
+            .map(|v| {
+                v.parse::<u64>()
+                    .unwrap_or_else(|_| panic!("{key} must be an integer"))
+            })
+            .map_or(Duration::from_millis(default), Duration::from_millis)
+    };
+
+    let address = string("gateway", "address");
+    let port = string("gateway", "port")
+        .parse::<u16>()
+        .expect("Gateway port must be an u16");
+    let session = Session {
+        participant: string("gateway", "participant")
+            .parse()
+            .expect("Gateway participant must be an u64"),
+        session_id: 0,
+        gateway_id: string("gateway", "gateway_id")
+            .parse()
+            .expect("Gateway gateway_id must be an u8"),
+        username: string("gateway", "username"),
+        password: string("gateway", "password"),
+        book_id: string("conformance", "book_id")
+            .parse()
+            .expect("book_id must be an u64"),
+    };
+    let first_session_id = string("gateway", "first_session_id")
+        .parse::<u32>()
+        .expect("Gateway first_session_id must be an u32");
+    let timeouts = Timeouts {
+        reply: millis("reply_timeout_ms", 2000),
+        silence: millis("silence_ms", 200),
+    };
+    // all of them if missing
+    let selected: Option<Vec<String>> =
+        optional("cases").map(|c| c.split(',').map(|n| n.trim().to_string()).collect());
+
+    let mut failed = 0;
+    let mut run = 0;
+    for (index, case) in cases::all().into_iter().enumerate() {
+        if selected
+            .as_ref()
+            .is_some_and(|s| !s.iter().any(|n| n == case.name))
+        {
+            continue;
+        }
+        run += 1;
+        let session = Session {
+            session_id: first_session_id + index as u32,
+            ..session.clone()
+        };
+        let result = TcpStream::connect((address.as_str(), port))
+            .map_err(anyhow::Error::from)
+            .and_then(|stream| script::run(stream, &(case.build)(&session), timeouts));
+        match result {
+            Ok(()) => println!("{} ... ok", case.name),
+            Err(e) => {
+                println!("{} ... FAILED, {e}", case.name);
+                failed += 1;
+            }
+        }
+    }
+    println!("{run} cases run, {} passed, {failed} failed", run - failed);
+    if failed > 0 {
+        bail!("{failed} of the cases failed");
+    }
+    Ok(())
+}
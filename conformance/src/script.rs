@@ -0,0 +1,292 @@
+//! Scripts of bytes sent to the gateway, and of what it is expected to do
+//! about them
+//!
+//! A script runs on a connection of its own. Its steps are played in order,
+//! the first one not holding failing the whole script.
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use oep::{
+    decoder::Decoder,
+    execution_report::{ExecutionReport, RejectReason},
+    header::{OepHeader, OEP_HEADER_SIZE},
+    messagebuffer::MessageBuffer,
+    oep_message::{MsgType, OepMessage},
+};
+
+#[derive(Debug, Clone)]
+pub enum Step {
+    // written as they are, in a single write
+    Send(Vec<u8>),
+    // the messages are stamped in turn with the sequences the gateway
+    // expects, plus the skip. They only move on when nothing is skipped
+    SendSequenced(Vec<u8>, u32),
+    Pause(Duration),
+    // the next message is of this type
+    Expect(MsgType),
+    // the next message is an execution report rejected for this reason
+    ExpectReject(RejectReason),
+    // nothing comes for a while, and the connection stays open
+    ExpectSilence,
+    // the gateway closes the connection without sending anything more
+    ExpectDisconnect,
+}
+
+/// How long the gateway is given, and how long is silent enough
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub reply: Duration,
+    pub silence: Duration,
+}
+
+/// What a message of the gateway was, as far as the steps are concerned
+enum Received {
+    Message(Box<dyn OepMessage>),
+    Closed,
+    Nothing,
+}
+
+/// The client side of a connection to the gateway
+struct Peer {
+    stream: TcpStream,
+    received: MessageBuffer,
+    // the sequence of the next order message, told by the login reply
+    next_seq: u32,
+}
+
+impl Peer {
+    /// What comes first from the gateway, waiting up to @timeout
+    fn receive(&mut self, timeout: Duration) -> Result<Received> {
+        let deadline = Instant::now() + timeout;
+        let mut chunk = [0; 4096];
+        loop {
+            if let Some(framed) = self.received.next_message() {
+                let (header, message) = framed.map_err(|e| anyhow!("Undecodable reply: {e}"))?;
+                if message.message_type() == MsgType::Login {
+                    self.next_seq = header.seq;
+                }
+                return Ok(Received::Message(message));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(Received::Nothing);
+            }
+            self.stream.set_read_timeout(Some(left))?;
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(Received::Closed),
+                Ok(r) => self.received.extend(&chunk[..r]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(Received::Nothing)
+                }
+                // closed with what was sent to it still unread
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+                    ) =>
+                {
+                    return Ok(Received::Closed)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// The next message, failing without one within @timeout
+    fn expect_message(&mut self, timeout: Duration) -> Result<Box<dyn OepMessage>> {
+        match self.receive(timeout)? {
+            Received::Message(message) => Ok(message),
+            Received::Closed => bail!("Disconnected instead"),
+            Received::Nothing => bail!("Nothing received in {timeout:?}"),
+        }
+    }
+
+    fn play(&mut self, step: &Step, timeouts: Timeouts) -> Result<()> {
+        match step {
+            Step::Send(bytes) => self.stream.write_all(bytes)?,
+            Step::SendSequenced(bytes, skip) => {
+                let mut messages = bytes.clone();
+                let stamped = stamp(&mut messages, self.next_seq + skip);
+                if *skip == 0 {
+                    self.next_seq += stamped;
+                }
+                self.stream.write_all(&messages)?;
+            }
+            Step::Pause(duration) => thread::sleep(*duration),
+            Step::Expect(msg_type) => {
+                let message = self.expect_message(timeouts.reply)?;
+                if message.message_type() != *msg_type {
+                    bail!("Received {:?}", message.message_type());
+                }
+            }
+            Step::ExpectReject(reason) => {
+                let message = self.expect_message(timeouts.reply)?;
+                let Some(ereport) = message.as_any().downcast_ref::<ExecutionReport>() else {
+                    bail!("Received {:?}", message.message_type());
+                };
+                if ereport.get_reject_reason() != *reason {
+                    bail!("Rejected for {:?}", ereport.get_reject_reason());
+                }
+            }
+            Step::ExpectSilence => match self.receive(timeouts.silence)? {
+                Received::Nothing => {}
+                Received::Closed => bail!("Disconnected"),
+                Received::Message(message) => bail!("Received {:?}", message.message_type()),
+            },
+            Step::ExpectDisconnect => match self.receive(timeouts.reply)? {
+                Received::Closed => {}
+                Received::Nothing => bail!("Still connected after {:?}", timeouts.reply),
+                Received::Message(message) => bail!("Received {:?}", message.message_type()),
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Stamps the OEP @messages, the first one with @seq, the next ones with
+/// the sequences following it. The last message may be cut after its header
+///
+/// Returns: how many were stamped
+pub fn stamp(messages: &mut [u8], seq: u32) -> u32 {
+    let mut offset = 0;
+    let mut stamped = 0;
+    while offset + OEP_HEADER_SIZE <= messages.len() {
+        let header: [u8; OEP_HEADER_SIZE] = messages[offset..offset + OEP_HEADER_SIZE]
+            .try_into()
+            .unwrap();
+        let msg_len = OepHeader::decode(header).unwrap().msg_len as usize;
+        messages[offset + OEP_HEADER_SIZE - 4..offset + OEP_HEADER_SIZE]
+            .copy_from_slice(&(seq + stamped).to_le_bytes());
+        stamped += 1;
+        offset += OEP_HEADER_SIZE + msg_len;
+    }
+    stamped
+}
+
+/// Plays @steps on @stream
+///
+/// Returns: an error telling which step didn't hold, and why
+pub fn run(stream: TcpStream, steps: &[Step], timeouts: Timeouts) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let mut peer = Peer {
+        stream,
+        received: MessageBuffer::new(),
+        next_seq: 1,
+    };
+    for (index, step) in steps.iter().enumerate() {
+        peer.play(step, timeouts)
+            .map_err(|e| anyhow!("step {} {}: {e}", index + 1, step_name(step)))?;
+    }
+    Ok(())
+}
+
+/// The step without the bytes it sends
+fn step_name(step: &Step) -> String {
+    match step {
+        Step::Send(bytes) => format!("Send({} bytes)", bytes.len()),
+        Step::SendSequenced(bytes, skip) => {
+            format!("SendSequenced({} bytes, +{skip})", bytes.len())
+        }
+        other => format!("{other:?}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    use oep::{
+        decoder::Decoder,
+        header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+        login::{Login, LOGIN_SIZE},
+        oep_message::MsgType,
+    };
+
+    use super::{run, Step, Timeouts};
+
+    const TIMEOUTS: Timeouts = Timeouts {
+        reply: Duration::from_millis(500),
+        silence: Duration::from_millis(50),
+    };
+
+    fn login_bytes(seq: u32) -> Vec<u8> {
+        let header = OepHeader::new(OEP_VERSION, MsgType::Login.into(), LOGIN_SIZE as u32)
+            .with_seq(seq)
+            .encode();
+        [header.as_slice(), &Login::new(1, 2, 1, "test").encode()].concat()
+    }
+
+    /// A gateway reading a login, then answering it in two pieces and
+    /// hanging up
+    fn fake_gateway() -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut login = vec![0; OEP_HEADER_SIZE + LOGIN_SIZE];
+            client.read_exact(&mut login).unwrap();
+            let reply = login_bytes(7);
+            client.write_all(&reply[..5]).unwrap();
+            thread::sleep(Duration::from_millis(100));
+            client.write_all(&reply[5..]).unwrap();
+            thread::sleep(Duration::from_millis(100));
+        });
+        TcpStream::connect(address).unwrap()
+    }
+
+    #[test]
+    fn steps() {
+        let steps = [
+            Step::Send(login_bytes(0)),
+            Step::ExpectSilence,
+            Step::Expect(MsgType::Login),
+            Step::ExpectDisconnect,
+        ];
+        assert!(run(fake_gateway(), &steps, TIMEOUTS).is_ok());
+
+        let steps = [
+            Step::Send(login_bytes(0)),
+            Step::Expect(MsgType::LoginReject),
+        ];
+        let e = run(fake_gateway(), &steps, TIMEOUTS).unwrap_err();
+        assert_eq!("step 2 Expect(LoginReject): Received Login", e.to_string());
+
+        // the reply is in by then
+        let steps = [
+            Step::Send(login_bytes(0)),
+            Step::Pause(Duration::from_millis(150)),
+            Step::ExpectSilence,
+        ];
+        assert!(run(fake_gateway(), &steps, TIMEOUTS).is_err());
+
+        let steps = [
+            Step::Send(login_bytes(0)),
+            Step::Pause(Duration::from_millis(150)),
+            Step::ExpectDisconnect,
+        ];
+        assert!(run(fake_gateway(), &steps, TIMEOUTS).is_err());
+    }
+
+    #[test]
+    fn stamp() {
+        let mut message = login_bytes(0);
+        assert_eq!(1, super::stamp(&mut message, 0x01020304));
+        assert_eq!(login_bytes(0x01020304), message);
+
+        let mut messages = [login_bytes(0), login_bytes(0)].concat();
+        let cut = messages.len() - LOGIN_SIZE + 1;
+        assert_eq!(2, super::stamp(&mut messages[..cut], 7));
+        assert_eq!([login_bytes(7), login_bytes(8)].concat(), messages);
+    }
+}
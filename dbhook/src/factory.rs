@@ -29,13 +29,13 @@ mod test {
             #[cfg(feature = "duckdb")]
             "inmemduckdb",
         ]
-        .map(|x| build(x));
+        .map(build);
     }
 
     #[test]
     #[should_panic]
     fn build_panics_on_unknown() {
-        let _v = ["something"].map(|x| build(x));
+        let _v = ["something"].map(build);
     }
 
     #[test]
@@ -47,7 +47,9 @@ mod test {
             #[cfg(feature = "duckdb")]
             "inmemduckdb",
         ]
-        .map(|x| build(x));
-        v.map(|mut i| i.disconnect());
+        .map(build);
+        for mut i in v {
+            i.disconnect();
+        }
     }
 }
@@ -1,5 +1,83 @@
-use anyhow::Result;
+use std::{fmt, time::SystemTime};
+
+use anyhow::{bail, Result};
 use instruments::instrument::Instrument;
+use oep::{eodsummary::EodSummary, execution_report::ExecutionReport, tradecapture::TradeCapture};
+use tracing::warn;
+
+use crate::{
+    password::{self, Cost, StoredPassword},
+    risklimits::RiskLimits,
+};
+
+/// The instruments changed in the database since some point in time
+#[derive(Debug, Clone, Default)]
+pub struct ChangedInstruments {
+    // created or updated, and still active
+    pub updated: Vec<Instrument>,
+    // the ids of the instruments no longer active
+    pub deleted: Vec<u64>,
+    // when the latest of these changes was made, None when unknown
+    pub latest: Option<SystemTime>,
+}
+
+/// A user to create, logging in a session of a participant
+#[derive(Clone, PartialEq)]
+pub struct NewUser {
+    pub username: String,
+    // in plain text, as typed by the user: stored hashed, see crate::password
+    pub password: String,
+    pub session_id: u32,
+    pub participant: u64,
+}
+
+// not to log the password
+impl fmt::Debug for NewUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NewUser")
+            .field("username", &self.username)
+            .field("session_id", &self.session_id)
+            .field("participant", &self.participant)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What the login of a user is checked against
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserLogin {
+    pub participant: u64,
+    // None for a user let in whatever the password, as by the mock
+    pub password: Option<StoredPassword>,
+    // the cost of the hash to store once a cheaper password matched, None
+    // for a database keeping them as they are
+    pub upgrade_to: Option<Cost>,
+}
+
+impl UserLogin {
+    /// Checks @login_hash, the SHA-512 sent by the client, against the
+    /// password. Takes the time of the hashing, see crate::password
+    ///
+    /// Returns: the hash to store in place of the password, if any
+    pub fn check(&self, login_hash: &[u8; 64]) -> Result<Option<String>> {
+        let Some(stored) = &self.password else {
+            return Ok(None);
+        };
+        if !password::verify(stored, login_hash)? {
+            bail!("Invalid password");
+        }
+        let Some(cost) = self.upgrade_to else {
+            return Ok(None);
+        };
+        // the login goes on with the password as it is
+        match password::rehash(stored, login_hash, cost) {
+            Ok(hash) => Ok(hash),
+            Err(e) => {
+                warn!(error = %e, "Unable to rehash the password");
+                Ok(None)
+            }
+        }
+    }
+}
 
 pub trait GenericDB {
     fn connect(
@@ -11,6 +89,50 @@ pub trait GenericDB {
         dbname: &str,
     ) -> Result<()>;
     fn disconnect(&mut self);
-    fn check_login(&mut self, username: &str, password: &[u8; 64], session_id: u32) -> Result<u64>;
+    /// What @username logs in on @session_id with, see UserLogin::check
+    fn get_login(&mut self, username: &str, session_id: u32) -> Result<UserLogin>;
+    /// Replaces the password of @username on @session_id with @hash, as
+    /// upgraded by UserLogin::check
+    fn store_password_hash(&mut self, username: &str, session_id: u32, hash: &str) -> Result<()>;
+    /// The participant logged in as by @username on @session_id, @password
+    /// being the SHA-512 sent by the client. A password still kept in plain
+    /// text is stored hashed once it matched. Takes the time of the hashing,
+    /// on the thread of the caller
+    fn check_login(&mut self, username: &str, password: &[u8; 64], session_id: u32) -> Result<u64> {
+        let login = self.get_login(username, session_id)?;
+        if let Some(hash) = login.check(password)? {
+            if let Err(e) = self.store_password_hash(username, session_id, &hash) {
+                warn!(username, session_id, error = %e, "Unable to store the rehashed password");
+            }
+        }
+        Ok(login.participant)
+    }
+    /// Creates @user, false if there's one with the same name and session
+    fn create_user(&mut self, user: &NewUser) -> Result<bool>;
+    /// Changes the password of @username on @session_id to @password, in
+    /// plain text. False if there's no such user
+    fn set_password(&mut self, username: &str, session_id: u32, password: &str) -> Result<bool>;
     fn get_instruments(&mut self) -> Vec<Instrument>;
+    /// Creates @instrument, or updates the one with its id
+    fn store_instrument(&mut self, instrument: &Instrument) -> Result<()>;
+    /// The instruments changed after @since, or all of them for None. The
+    /// latest of the changes returned is where to start from next time
+    fn get_changed_instruments(&mut self, since: Option<SystemTime>) -> Result<ChangedInstruments>;
+    /// Deactivates the instrument @id, failing if there's no active one
+    fn delete_instrument(&mut self, id: u64) -> Result<()>;
+    fn store_eod_summary(&mut self, summary: &EodSummary) -> Result<()>;
+    fn get_risk_limits(&mut self) -> Result<Vec<RiskLimits>>;
+    /// The books @participant is entitled to trade, none meaning all of them
+    fn get_entitlements(&mut self, participant: u64) -> Result<Vec<u64>>;
+    /// Keeps @message, an execution report with its OEP header, until the
+    /// session @session_id logs in again
+    fn store_pending_report(&mut self, session_id: u32, message: &[u8]) -> Result<()>;
+    /// Removes the messages kept for @session_id, returning them oldest first
+    fn take_pending_reports(&mut self, session_id: u32) -> Result<Vec<Vec<u8>>>;
+    /// Appends @ereport to the audit trail of the orders, stamped with the
+    /// time it was stored
+    fn store_order_event(&mut self, ereport: &ExecutionReport) -> Result<()>;
+    /// Appends @capture to the audit trail of the trades, the reversing
+    /// captures of the busts included
+    fn store_trade(&mut self, capture: &TradeCapture) -> Result<()>;
 }
@@ -1,4 +1,10 @@
-use crate::genericdb::GenericDB;
+use std::{path::Path, time::SystemTime};
+
+use crate::{
+    genericdb::{ChangedInstruments, GenericDB, NewUser, UserLogin},
+    password::StoredPassword,
+    risklimits::RiskLimits,
+};
 use anyhow::bail;
 use duckdb::Connection;
 use instruments::instrument::Instrument;
@@ -12,8 +18,9 @@ struct ParticipantPassword {
 /// ::connect's DBName needs to be the type of the database.
 /// E.g.: "csv", "parquet" or other
 ///
-/// The files should be called users.type and instruments.type
-/// E.g. "users.csv" and "instruments.csv"
+/// The files should be called users.type, instruments.type and risk_limits.type
+/// E.g. "users.csv", "instruments.csv" and "risk_limits.csv", with an optional
+/// entitlements.type
 ///
 /// Example:
 ///
@@ -55,12 +62,7 @@ impl GenericDB for InMemDuckDB {
 
     fn disconnect(&mut self) {}
 
-    fn check_login(
-        &mut self,
-        username: &str,
-        password_hash: &[u8; 64],
-        session_id: u32,
-    ) -> anyhow::Result<u64> {
+    fn get_login(&mut self, username: &str, session_id: u32) -> anyhow::Result<UserLogin> {
         let mut prepared_statement = self.connection.prepare(
             "SELECT participant, password from 'users.?' WHERE username=? and session_id=?",
         )?;
@@ -73,17 +75,43 @@ impl GenericDB for InMemDuckDB {
                 })
             },
         )?;
-        for m in matches {
+        // hashed or not, the users file being read only
+        if let Some(m) = matches.into_iter().next() {
             let pp = m?;
-            let hashed_password = oep::login::Login::free_text_hash(&pp.password);
-            if password_hash.eq(&hashed_password) == true {
-                return Ok(pp.participant);
-            }
-            break;
+            return Ok(UserLogin {
+                participant: pp.participant,
+                password: Some(StoredPassword::from(pp.password.as_str())),
+                upgrade_to: None,
+            });
         }
         bail!("Invalid password");
     }
 
+    fn store_password_hash(
+        &mut self,
+        _username: &str,
+        _session_id: u32,
+        _hash: &str,
+    ) -> anyhow::Result<()> {
+        bail!("The users of an in memory DuckDB are read only");
+    }
+
+    fn create_user(&mut self, user: &NewUser) -> anyhow::Result<bool> {
+        bail!(
+            "Can't create the user {}, the users file is read only",
+            user.username
+        );
+    }
+
+    fn set_password(
+        &mut self,
+        username: &str,
+        _session_id: u32,
+        _password: &str,
+    ) -> anyhow::Result<bool> {
+        bail!("Can't change the password of {username}, the users file is read only");
+    }
+
     fn get_instruments(&mut self) -> Vec<instruments::instrument::Instrument> {
         let mut prepared_statement = self
             .connection
@@ -106,4 +134,165 @@ impl GenericDB for InMemDuckDB {
             .unwrap();
         matches.map(|res| res.unwrap()).collect()
     }
+
+    fn store_instrument(&mut self, instrument: &Instrument) -> anyhow::Result<()> {
+        bail!(
+            "Can't store the instrument {}, the instrument files are read only",
+            instrument.get_id()
+        );
+    }
+
+    /// The files have no update times, so all the instruments are changed
+    fn get_changed_instruments(
+        &mut self,
+        _since: Option<SystemTime>,
+    ) -> anyhow::Result<ChangedInstruments> {
+        Ok(ChangedInstruments {
+            updated: self.get_instruments(),
+            ..Default::default()
+        })
+    }
+
+    fn delete_instrument(&mut self, id: u64) -> anyhow::Result<()> {
+        bail!("Can't delete the instrument {id}, the instrument files are read only");
+    }
+
+    /// The summaries only live as long as the in memory database
+    fn store_eod_summary(&mut self, summary: &oep::eodsummary::EodSummary) -> anyhow::Result<()> {
+        self.connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS eod_summary (instrument_id UBIGINT, trading_day DATE,
+            closing_price UBIGINT, volume UBIGINT, trade_count UBIGINT)",
+        )?;
+        self.connection.execute(
+            "INSERT INTO eod_summary VALUES (?, CURRENT_DATE, ?, ?, ?)",
+            [
+                summary.book_id,
+                summary.closing_price,
+                summary.volume,
+                summary.trade_count,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_risk_limits(&mut self) -> anyhow::Result<Vec<RiskLimits>> {
+        let mut prepared_statement = self.connection.prepare(
+            "SELECT participant, max_quantity, max_notional, max_open_orders from 'risk_limits.?'",
+        )?;
+        let matches = prepared_statement.query_map([&self.dbname], |row| {
+            Ok(RiskLimits {
+                participant: row.get(0)?,
+                max_quantity: row.get(1)?,
+                max_notional: row.get(2)?,
+                max_open_orders: row.get(3)?,
+            })
+        })?;
+        Ok(matches.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Out of entitlements.type, if there's one
+    fn get_entitlements(&mut self, participant: u64) -> anyhow::Result<Vec<u64>> {
+        if !Path::new(&format!("entitlements.{}", self.dbname)).exists() {
+            return Ok(vec![]);
+        }
+        let mut prepared_statement = self
+            .connection
+            .prepare("SELECT book_id from 'entitlements.?' WHERE participant=?")?;
+        let matches = prepared_statement
+            .query_map(duckdb::params![self.dbname, participant], |row| row.get(0))?;
+        Ok(matches.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// The reports only live as long as the in memory database
+    fn store_pending_report(&mut self, session_id: u32, message: &[u8]) -> anyhow::Result<()> {
+        self.create_pending_reports()?;
+        self.connection.execute(
+            "INSERT INTO pending_reports (session_id, message) VALUES (?, ?)",
+            duckdb::params![session_id, message],
+        )?;
+        Ok(())
+    }
+
+    fn take_pending_reports(&mut self, session_id: u32) -> anyhow::Result<Vec<Vec<u8>>> {
+        self.create_pending_reports()?;
+        let mut prepared_statement = self
+            .connection
+            .prepare("DELETE FROM pending_reports WHERE session_id=? RETURNING id, message")?;
+        let mut reports = prepared_statement
+            .query_map([session_id], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        reports.sort_by_key(|(id, _)| *id);
+        Ok(reports.into_iter().map(|(_, message)| message).collect())
+    }
+
+    /// The events only live as long as the in memory database
+    fn store_order_event(
+        &mut self,
+        ereport: &oep::execution_report::ExecutionReport,
+    ) -> anyhow::Result<()> {
+        self.connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS order_events (recorded_at TIMESTAMPTZ DEFAULT now(),
+            participant UBIGINT, order_id UBIGINT, submitted_order_id UBIGINT, book_id UBIGINT,
+            quantity UBIGINT, price UBIGINT, side UTINYINT, state UTINYINT, gateway_id UTINYINT,
+            session_id UINTEGER, filled_quantity UBIGINT, leaves_quantity UBIGINT,
+            orig_order_id UBIGINT, reject_reason UTINYINT, trade_id UBIGINT)",
+        )?;
+        self.connection.execute(
+            "INSERT INTO order_events (participant, order_id, submitted_order_id, book_id,
+            quantity, price, side, state, gateway_id, session_id, filled_quantity,
+            leaves_quantity, orig_order_id, reject_reason, trade_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            duckdb::params![
+                { ereport.participant },
+                { ereport.order_id },
+                { ereport.submitted_order_id },
+                { ereport.book },
+                { ereport.quantity },
+                { ereport.price },
+                { ereport.side },
+                { ereport.state },
+                { ereport.gateway_id },
+                { ereport.session_id },
+                { ereport.filled_quantity },
+                { ereport.leaves_quantity },
+                { ereport.orig_order_id },
+                { ereport.reject_reason },
+                { ereport.trade_id },
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The trades only live as long as the in memory database
+    fn store_trade(&mut self, capture: &oep::tradecapture::TradeCapture) -> anyhow::Result<()> {
+        self.connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS trades (book_id UBIGINT, trade_id UBIGINT,
+            price UBIGINT, quantity UBIGINT, buyer UBIGINT, seller UBIGINT, traded_at UBIGINT)",
+        )?;
+        self.connection.execute(
+            "INSERT INTO trades VALUES (?, ?, ?, ?, ?, ?, ?)",
+            [
+                capture.book_id,
+                capture.trade_id,
+                capture.price,
+                capture.quantity,
+                capture.buyer,
+                capture.seller,
+                capture.timestamp,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl InMemDuckDB {
+    fn create_pending_reports(&self) -> duckdb::Result<()> {
+        self.connection.execute_batch(
+            "CREATE SEQUENCE IF NOT EXISTS pending_report_ids;
+            CREATE TABLE IF NOT EXISTS pending_reports (
+            id BIGINT DEFAULT nextval('pending_report_ids'), session_id UINTEGER, message BLOB)",
+        )
+    }
 }
@@ -3,5 +3,7 @@ pub mod genericdb;
 #[cfg(feature = "duckdb")]
 pub mod inmemduckdb;
 pub mod mockdb;
+pub mod password;
 #[cfg(feature = "postgres")]
 pub mod pgsqldb;
+pub mod risklimits;
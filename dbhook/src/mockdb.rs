@@ -1,10 +1,73 @@
-use crate::genericdb::GenericDB;
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
 
-pub struct MockDB {}
+use anyhow::bail;
+use instruments::instrument::Instrument;
+use oep::{execution_report::ExecutionReport, tradecapture::TradeCapture};
+pub use utils::faults::{FailureMode, Faults};
 
-impl Default for MockDB {
-    fn default() -> Self {
-        Self {}
+use crate::{
+    genericdb::{ChangedInstruments, GenericDB, NewUser, UserLogin},
+    password::{self, Cost, StoredPassword},
+    risklimits::RiskLimits,
+};
+
+// the mock has no offline guesses to slow down
+const MOCK_COST: Cost = Cost {
+    memory_kib: 8,
+    passes: 1,
+    lanes: 1,
+};
+
+#[derive(Default)]
+pub struct MockDB {
+    // instrument, active, updated at
+    instruments: Vec<(Instrument, bool, SystemTime)>,
+    // seconds since the epoch, one more for every change
+    clock: u64,
+    // session id, message
+    pending_reports: Vec<(u32, Vec<u8>)>,
+    // username, session id -> password, participant
+    users: HashMap<(String, u32), (StoredPassword, u64)>,
+    // participant -> the books it's entitled to
+    entitlements: HashMap<u64, Vec<u64>>,
+    // the audit trail, oldest first
+    order_events: Vec<ExecutionReport>,
+    trades: Vec<TradeCapture>,
+    // the calls failing as told, get_instruments returning nothing as for
+    // the database
+    faults: Faults,
+}
+
+impl MockDB {
+    pub fn set_entitlements(&mut self, participant: u64, books: Vec<u64>) {
+        self.entitlements.insert(participant, books);
+    }
+
+    pub fn get_order_events(&self) -> &[ExecutionReport] {
+        &self.order_events
+    }
+
+    pub fn get_trades(&self) -> &[TradeCapture] {
+        &self.trades
+    }
+
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    fn check_faults(&self) -> anyhow::Result<()> {
+        if self.faults.on_call() {
+            bail!("Injected failure");
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) -> SystemTime {
+        self.clock += 1;
+        SystemTime::UNIX_EPOCH + Duration::from_secs(self.clock)
     }
 }
 
@@ -20,18 +83,279 @@ impl GenericDB for MockDB {
         todo!()
     }
 
-    fn check_login(
+    /// The users created are checked, anyone else logging in as participant 111
+    fn get_login(&mut self, username: &str, session_id: u32) -> anyhow::Result<UserLogin> {
+        self.check_faults()?;
+        let login = match self.users.get(&(String::from(username), session_id)) {
+            Some((stored, participant)) => UserLogin {
+                participant: *participant,
+                password: Some(stored.clone()),
+                upgrade_to: Some(MOCK_COST),
+            },
+            None => UserLogin {
+                participant: 111,
+                password: None,
+                upgrade_to: None,
+            },
+        };
+        Ok(login)
+    }
+
+    fn store_password_hash(
         &mut self,
-        _username: &str,
-        _password: &[u8; 64],
-        _session_id: u32,
-    ) -> anyhow::Result<u64> {
-        Ok(111)
+        username: &str,
+        session_id: u32,
+        hash: &str,
+    ) -> anyhow::Result<()> {
+        self.check_faults()?;
+        match self.users.get_mut(&(String::from(username), session_id)) {
+            Some((stored, _)) => *stored = StoredPassword::Hashed(String::from(hash)),
+            None => bail!("No user {username} on session {session_id}"),
+        }
+        Ok(())
+    }
+
+    fn create_user(&mut self, user: &NewUser) -> anyhow::Result<bool> {
+        self.check_faults()?;
+        let key = (user.username.clone(), user.session_id);
+        if self.users.contains_key(&key) {
+            return Ok(false);
+        }
+        let hash = password::hash_password(
+            &oep::login::Login::free_text_hash(&user.password),
+            MOCK_COST,
+        )?;
+        self.users
+            .insert(key, (StoredPassword::Hashed(hash), user.participant));
+        Ok(true)
+    }
+
+    fn set_password(
+        &mut self,
+        username: &str,
+        session_id: u32,
+        password: &str,
+    ) -> anyhow::Result<bool> {
+        self.check_faults()?;
+        let hash =
+            password::hash_password(&oep::login::Login::free_text_hash(password), MOCK_COST)?;
+        match self.users.get_mut(&(String::from(username), session_id)) {
+            Some((stored, _)) => *stored = StoredPassword::Hashed(hash),
+            None => return Ok(false),
+        }
+        Ok(true)
     }
 
     fn disconnect(&mut self) {}
 
-    fn get_instruments(&mut self) -> Vec<instruments::instrument::Instrument> {
-        todo!()
+    fn get_instruments(&mut self) -> Vec<Instrument> {
+        if self.faults.on_call() {
+            return vec![];
+        }
+        self.instruments
+            .iter()
+            .filter(|(_, active, _)| *active)
+            .map(|(i, _, _)| i.clone())
+            .collect()
+    }
+
+    fn store_instrument(&mut self, instrument: &Instrument) -> anyhow::Result<()> {
+        self.check_faults()?;
+        let now = self.tick();
+        self.instruments
+            .retain(|(i, _, _)| i.get_id() != instrument.get_id());
+        self.instruments.push((instrument.clone(), true, now));
+        Ok(())
+    }
+
+    fn get_changed_instruments(
+        &mut self,
+        since: Option<SystemTime>,
+    ) -> anyhow::Result<ChangedInstruments> {
+        self.check_faults()?;
+        let mut changes = ChangedInstruments::default();
+        for (instrument, active, updated_at) in &self.instruments {
+            if since.is_some_and(|since| *updated_at <= since) {
+                continue;
+            }
+            changes.latest = changes.latest.max(Some(*updated_at));
+            if *active {
+                changes.updated.push(instrument.clone());
+            } else {
+                changes.deleted.push(instrument.get_id());
+            }
+        }
+        Ok(changes)
+    }
+
+    fn delete_instrument(&mut self, id: u64) -> anyhow::Result<()> {
+        self.check_faults()?;
+        let now = self.tick();
+        match self
+            .instruments
+            .iter_mut()
+            .find(|(i, active, _)| *active && i.get_id() == id)
+        {
+            Some((_, active, updated_at)) => {
+                *active = false;
+                *updated_at = now;
+                Ok(())
+            }
+            None => bail!("No instrument {id}"),
+        }
+    }
+
+    /// Only the closing price is kept, as the previous close of the instrument
+    fn store_eod_summary(&mut self, summary: &oep::eodsummary::EodSummary) -> anyhow::Result<()> {
+        self.check_faults()?;
+        if summary.closing_price > 0 {
+            self.instruments
+                .iter_mut()
+                .filter(|(i, _, _)| i.get_id() == summary.book_id)
+                .for_each(|(i, _, _)| i.set_previous_close(summary.closing_price));
+        }
+        Ok(())
+    }
+
+    fn get_risk_limits(&mut self) -> anyhow::Result<Vec<RiskLimits>> {
+        self.check_faults()?;
+        Ok(vec![])
+    }
+
+    fn get_entitlements(&mut self, participant: u64) -> anyhow::Result<Vec<u64>> {
+        self.check_faults()?;
+        Ok(self
+            .entitlements
+            .get(&participant)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn store_pending_report(&mut self, session_id: u32, message: &[u8]) -> anyhow::Result<()> {
+        self.check_faults()?;
+        self.pending_reports.push((session_id, message.to_vec()));
+        Ok(())
+    }
+
+    fn take_pending_reports(&mut self, session_id: u32) -> anyhow::Result<Vec<Vec<u8>>> {
+        self.check_faults()?;
+        let (taken, kept) = std::mem::take(&mut self.pending_reports)
+            .into_iter()
+            .partition(|(s, _)| *s == session_id);
+        self.pending_reports = kept;
+        Ok(taken.into_iter().map(|(_, message)| message).collect())
+    }
+
+    fn store_order_event(&mut self, ereport: &ExecutionReport) -> anyhow::Result<()> {
+        self.check_faults()?;
+        self.order_events.push(*ereport);
+        Ok(())
+    }
+
+    fn store_trade(&mut self, capture: &TradeCapture) -> anyhow::Result<()> {
+        self.check_faults()?;
+        self.trades.push(*capture);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use instruments::instrument::{Instrument, InstrumentType};
+
+    use oep::{eodsummary::EodSummary, login::Login};
+
+    use super::{FailureMode, MockDB};
+    use crate::genericdb::{GenericDB, NewUser};
+
+    #[test]
+    fn users() {
+        let mut db = MockDB::default();
+        let user = NewUser {
+            username: String::from("trader"),
+            password: String::from("secret"),
+            session_id: 5,
+            participant: 7,
+        };
+        assert!(db.create_user(&user).unwrap());
+        assert!(!db.create_user(&user).unwrap());
+        assert!(!format!("{user:?}").contains("secret"));
+        let secret = Login::free_text_hash("secret");
+        assert_eq!(7, db.check_login("trader", &secret, 5).unwrap());
+        assert!(db
+            .check_login("trader", &Login::free_text_hash("other"), 5)
+            .is_err());
+
+        assert!(db.set_password("trader", 5, "other").unwrap());
+        assert!(db.check_login("trader", &secret, 5).is_err());
+        assert_eq!(
+            7,
+            db.check_login("trader", &Login::free_text_hash("other"), 5)
+                .unwrap()
+        );
+        assert!(!db.set_password("trader", 6, "other").unwrap());
+    }
+
+    #[test]
+    fn faults() {
+        let mut db = MockDB::default();
+        db.store_instrument(&Instrument::new_fast(1, InstrumentType::Share))
+            .unwrap();
+        db.faults().set_mode(FailureMode::Always);
+        assert!(db.get_instruments().is_empty());
+        assert!(db.store_pending_report(1, &[1]).is_err());
+        assert!(db.take_pending_reports(1).is_err());
+
+        db.faults().set_mode(FailureMode::Nth(2));
+        assert!(db.store_pending_report(1, &[1]).is_ok());
+        assert!(db.store_pending_report(1, &[2]).is_err());
+        assert_eq!(vec![vec![1]], db.take_pending_reports(1).unwrap());
+        assert_eq!((7, 4), (db.faults().calls(), db.faults().failures()));
+    }
+
+    #[test]
+    fn changed_instruments() {
+        let mut db = MockDB::default();
+        db.store_instrument(&Instrument::new_fast(1, InstrumentType::Share))
+            .unwrap();
+        db.store_instrument(&Instrument::new_fast(2, InstrumentType::Share))
+            .unwrap();
+        let changes = db.get_changed_instruments(None).unwrap();
+        assert_eq!(2, changes.updated.len());
+        let since = changes.latest;
+        assert!(since.is_some());
+        assert!(db.get_changed_instruments(since).unwrap().latest.is_none());
+
+        db.delete_instrument(1).unwrap();
+        assert!(db.delete_instrument(1).is_err());
+        let changes = db.get_changed_instruments(since).unwrap();
+        assert!(changes.updated.is_empty());
+        assert_eq!(vec![1], changes.deleted);
+        assert!(changes.latest > since);
+        assert_eq!(
+            vec![2],
+            db.get_instruments()
+                .iter()
+                .map(|i| i.get_id())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn previous_close() {
+        let mut db = MockDB::default();
+        db.store_instrument(&Instrument::new_fast(1, InstrumentType::Share))
+            .unwrap();
+        let summary = |closing_price| EodSummary {
+            book_id: 1,
+            closing_price,
+            volume: 100,
+            trade_count: 1,
+        };
+        db.store_eod_summary(&summary(1250)).unwrap();
+        assert_eq!(1250, db.get_instruments()[0].get_previous_close());
+        // nothing to close at, the one before stays
+        db.store_eod_summary(&summary(0)).unwrap();
+        assert_eq!(1250, db.get_instruments()[0].get_previous_close());
     }
 }
@@ -0,0 +1,251 @@
+//! The passwords of the users, as stored in the database
+//!
+//! The clients send the SHA-512 of the password in their login (see
+//! oep::login). What the database keeps is an Argon2id hash of that hash, as
+//! a PHC string carrying the salt and the cost it was made with:
+//!
+//! $argon2id$v=19$m=<memory, KiB>,t=<passes>,p=<lanes>$<salt>$<hash>
+//!
+//! Checking a password takes that memory and time on purpose, which is what
+//! makes the offline guesses slow: whoever serves other clients meanwhile
+//! checks the logins off its own thread. A stored hash asking for more than
+//! Cost::MAX is refused rather than checked.
+//!
+//! The users created before kept their password in plain text, in the same
+//! column. It is still accepted until their next login stores it hashed, see
+//! rehash. The same goes for the hashes cheaper than the cost asked for.
+
+use anyhow::{anyhow, bail, Result};
+use argon2::{
+    password_hash, Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier,
+    Version, ARGON2ID_IDENT,
+};
+
+/// What hashing a password costs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cost {
+    pub memory_kib: u32,
+    pub passes: u32,
+    pub lanes: u32,
+}
+
+impl Cost {
+    // the minimum recommended for Argon2id, about 20 ms a login
+    pub const DEFAULT: Cost = Cost {
+        memory_kib: 19 * 1024,
+        passes: 2,
+        lanes: 1,
+    };
+    // the most a stored hash can ask of a login
+    pub const MAX: Cost = Cost {
+        memory_kib: 256 * 1024,
+        passes: 16,
+        lanes: 8,
+    };
+
+    fn hasher(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(self.memory_kib, self.passes, self.lanes, None)
+            .map_err(|e| anyhow!("Invalid password hash cost {self:?}: {e}"))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    // whether @self asks for more of anything than @other
+    fn exceeds(&self, other: &Cost) -> bool {
+        self.memory_kib > other.memory_kib || self.passes > other.passes || self.lanes > other.lanes
+    }
+}
+
+/// The password of a user, as found in the database
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoredPassword {
+    // salted and hashed, see the module documentation
+    Hashed(String),
+    // plain text, from before the hashing
+    Legacy(String),
+}
+
+impl From<&str> for StoredPassword {
+    /// The password column, hashed only if it is a whole Argon2id PHC string:
+    /// a password in plain text merely starting like one stays what it is
+    fn from(value: &str) -> Self {
+        match PasswordHash::new(value) {
+            Ok(hash) if hash.algorithm == ARGON2ID_IDENT && hash.hash.is_some() => {
+                StoredPassword::Hashed(String::from(value))
+            }
+            _ => StoredPassword::Legacy(String::from(value)),
+        }
+    }
+}
+
+/// The hash of @login_hash, the SHA-512 sent by the client, with a random
+/// salt and @cost, for the database to keep
+pub fn hash_password(login_hash: &[u8; 64], cost: Cost) -> Result<String> {
+    let hash = cost
+        .hasher()?
+        .hash_password(login_hash)
+        .map_err(|e| anyhow!("Unable to hash the password: {e}"))?;
+    Ok(hash.to_string())
+}
+
+/// The hash of @login_hash, out of @salt and @cost
+pub fn hash_with(login_hash: &[u8], salt: &[u8], cost: Cost) -> Result<String> {
+    let hash = cost
+        .hasher()?
+        .hash_password_with_salt(login_hash, salt)
+        .map_err(|e| anyhow!("Unable to hash the password: {e}"))?;
+    Ok(hash.to_string())
+}
+
+/// Whether @login_hash, the SHA-512 sent by the client, is the one of
+/// @stored. Fails for a hash that can't be read, or costs more than Cost::MAX
+pub fn verify(stored: &StoredPassword, login_hash: &[u8; 64]) -> Result<bool> {
+    match stored {
+        StoredPassword::Legacy(password) => Ok(constant_time_eq(
+            &oep::login::Login::free_text_hash(password),
+            login_hash,
+        )),
+        StoredPassword::Hashed(stored) => {
+            let (hash, _) = parse(stored)?;
+            match Argon2::default().verify_password(login_hash, &hash) {
+                Ok(()) => Ok(true),
+                Err(password_hash::Error::PasswordInvalid) => Ok(false),
+                Err(e) => bail!("Unable to check the password: {e}"),
+            }
+        }
+    }
+}
+
+/// The hash to store instead of @stored, once the user logged in with
+/// @login_hash: for the legacy passwords, and the hashes cheaper than @cost.
+/// None if @stored can stay
+pub fn rehash(
+    stored: &StoredPassword,
+    login_hash: &[u8; 64],
+    cost: Cost,
+) -> Result<Option<String>> {
+    let outdated = match stored {
+        StoredPassword::Legacy(_) => true,
+        StoredPassword::Hashed(stored) => cost.exceeds(&parse(stored)?.1),
+    };
+    if !outdated {
+        return Ok(None);
+    }
+    Ok(Some(hash_password(login_hash, cost)?))
+}
+
+// the hash and its cost, within Cost::MAX
+fn parse(stored: &str) -> Result<(PasswordHash, Cost)> {
+    let hash = PasswordHash::new(stored).map_err(|e| anyhow!("Invalid password hash: {e}"))?;
+    if hash.algorithm != ARGON2ID_IDENT {
+        bail!("Unknown password hash format {}", hash.algorithm);
+    }
+    let params = Params::try_from(&hash).map_err(|e| anyhow!("Invalid password hash cost: {e}"))?;
+    let cost = Cost {
+        memory_kib: params.m_cost(),
+        passes: params.t_cost(),
+        lanes: params.p_cost(),
+    };
+    if cost.exceeds(&Cost::MAX) {
+        bail!("Password hash costing more than allowed: {cost:?}");
+    }
+    Ok((hash, cost))
+}
+
+// not to tell how much of the hash matched by how long it took
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |r, (a, b)| r | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use oep::login::Login;
+
+    use super::{hash_password, hash_with, rehash, verify, Cost, StoredPassword};
+
+    // cheap enough for the tests
+    const COST: Cost = Cost {
+        memory_kib: 8,
+        passes: 1,
+        lanes: 1,
+    };
+
+    #[test]
+    fn hashed() {
+        let login_hash = Login::free_text_hash("secret");
+        let stored =
+            StoredPassword::Hashed(hash_with(&login_hash, b"0123456789abcdef", COST).unwrap());
+        assert!(verify(&stored, &login_hash).unwrap());
+        assert!(!verify(&stored, &Login::free_text_hash("Secret")).unwrap());
+        assert_eq!(None, rehash(&stored, &login_hash, COST).unwrap());
+        // more passes asked for
+        let stronger = Cost { passes: 2, ..COST };
+        let stronger = rehash(&stored, &login_hash, stronger).unwrap().unwrap();
+        assert!(stronger.starts_with("$argon2id$v=19$m=8,t=2,p=1$"));
+        assert!(verify(&StoredPassword::Hashed(stronger), &login_hash).unwrap());
+
+        // the same password, salted differently
+        let other = hash_with(&login_hash, b"fedcba9876543210", COST).unwrap();
+        assert_ne!(StoredPassword::Hashed(other.clone()), stored);
+        assert!(verify(&StoredPassword::Hashed(other), &login_hash).unwrap());
+
+        assert_eq!(
+            stored,
+            StoredPassword::from(
+                hash_with(&login_hash, b"0123456789abcdef", COST)
+                    .unwrap()
+                    .as_str()
+            )
+        );
+
+        for garbage in [
+            "",
+            "secret",
+            "$argon2id$v=19$x",
+            "$argon2i$v=19$m=8,t=1,p=1$c2FsdHNhbHQ$aGFzaA",
+        ] {
+            assert!(verify(&StoredPassword::Hashed(String::from(garbage)), &login_hash).is_err());
+        }
+    }
+
+    #[test]
+    fn bounded_cost() {
+        let login_hash = Login::free_text_hash("secret");
+        let stored = hash_with(&login_hash, b"0123456789abcdef", COST).unwrap();
+        // as if the row had been tampered with, asking for 4 GiB
+        let costly = StoredPassword::Hashed(stored.replace("m=8,", "m=4194304,"));
+        assert!(verify(&costly, &login_hash)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Password hash costing more than allowed"));
+        assert!(rehash(&costly, &login_hash, COST).is_err());
+    }
+
+    #[test]
+    fn legacy() {
+        let login_hash = Login::free_text_hash("secret");
+        let stored = StoredPassword::from("secret");
+        assert_eq!(StoredPassword::Legacy(String::from("secret")), stored);
+        assert!(verify(&stored, &login_hash).unwrap());
+        assert!(!verify(&stored, &Login::free_text_hash("other")).unwrap());
+
+        let upgraded = rehash(&stored, &login_hash, COST).unwrap().unwrap();
+        assert!(upgraded.starts_with("$argon2id$v=19$m=8,t=1,p=1$"));
+        assert!(verify(&StoredPassword::Hashed(upgraded), &login_hash).unwrap());
+
+        // looking like a hash isn't enough to be taken for one
+        for password in ["$argon2id$", "$argon2id$v=19$m=8,t=1,p=1$secret"] {
+            let stored = StoredPassword::from(password);
+            assert_eq!(StoredPassword::Legacy(String::from(password)), stored);
+            assert!(verify(&stored, &Login::free_text_hash(password)).unwrap());
+        }
+    }
+
+    #[test]
+    fn random_salts() {
+        let login_hash = Login::free_text_hash("secret");
+        assert_ne!(
+            hash_password(&login_hash, COST).unwrap(),
+            hash_password(&login_hash, COST).unwrap()
+        );
+    }
+}
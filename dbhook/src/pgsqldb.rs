@@ -1,20 +1,22 @@
-use crate::genericdb::GenericDB;
+use std::time::SystemTime;
+
+use crate::{
+    genericdb::{ChangedInstruments, GenericDB, NewUser, UserLogin},
+    password::{self, Cost, StoredPassword},
+    risklimits::RiskLimits,
+};
 use anyhow::{anyhow, bail, Result};
 use instruments::instrument::Instrument;
+use oep::{eodsummary::EodSummary, execution_report::ExecutionReport, tradecapture::TradeCapture};
 use postgres::Client;
 
+#[derive(Default)]
 pub struct PGSqlDB {
     client: Option<Client>,
 }
 
 impl PGSqlDB {}
 
-impl Default for PGSqlDB {
-    fn default() -> Self {
-        Self { client: None }
-    }
-}
-
 impl GenericDB for PGSqlDB {
     fn connect(
         &mut self,
@@ -41,19 +43,14 @@ impl GenericDB for PGSqlDB {
         }
     }
 
-    fn check_login(
-        &mut self,
-        username: &str,
-        password_hash: &[u8; 64],
-        session_id: u32,
-    ) -> anyhow::Result<u64> {
+    fn get_login(&mut self, username: &str, session_id: u32) -> Result<UserLogin> {
         let s_id = session_id as i32;
         let query = self.client.as_mut().unwrap().query(
             "SELECT participant, password from users where
             username=$1 AND session_id=$2",
             &[&username, &s_id],
         )?;
-        if query.len() == 0 {
+        if query.is_empty() {
             return Err(anyhow!(format!(
                 "Invalid credentials for {username}/{s_id}"
             )));
@@ -61,17 +58,61 @@ impl GenericDB for PGSqlDB {
             return Err(anyhow!(format!("Too many matches for {username}")));
         }
         let password: String = query[0].get("password");
-        let hashed_password = oep::login::Login::free_text_hash(&password);
-        if password_hash.eq(&hashed_password) == false {
-            bail!("Invalid password");
-        }
         let participant: i64 = query[0].get("participant");
-        return Ok(participant as u64);
+        Ok(UserLogin {
+            participant: participant as u64,
+            password: Some(StoredPassword::from(password.as_str())),
+            upgrade_to: Some(Cost::DEFAULT),
+        })
+    }
+
+    fn store_password_hash(&mut self, username: &str, session_id: u32, hash: &str) -> Result<()> {
+        let s_id = session_id as i32;
+        self.client.as_mut().unwrap().execute(
+            "UPDATE users SET password=$1 WHERE username=$2 AND session_id=$3",
+            &[&hash, &username, &s_id],
+        )?;
+        Ok(())
+    }
+
+    fn create_user(&mut self, user: &NewUser) -> Result<bool> {
+        let s_id = user.session_id as i32;
+        let client = self.client.as_mut().unwrap();
+        let existing = client.query(
+            "SELECT 1 FROM users WHERE username=$1 AND session_id=$2",
+            &[&user.username, &s_id],
+        )?;
+        if !existing.is_empty() {
+            return Ok(false);
+        }
+        let hash = password::hash_password(
+            &oep::login::Login::free_text_hash(&user.password),
+            Cost::DEFAULT,
+        )?;
+        client.execute(
+            "INSERT INTO users (username, password, session_id, participant) VALUES ($1, $2, $3, $4)",
+            &[&user.username, &hash, &s_id, &(user.participant as i64)],
+        )?;
+        Ok(true)
+    }
+
+    fn set_password(&mut self, username: &str, session_id: u32, password: &str) -> Result<bool> {
+        let s_id = session_id as i32;
+        let hash =
+            password::hash_password(&oep::login::Login::free_text_hash(password), Cost::DEFAULT)?;
+        let updated = self.client.as_mut().unwrap().execute(
+            "UPDATE users SET password=$1 WHERE username=$2 AND session_id=$3",
+            &[&hash, &username, &s_id],
+        )?;
+        Ok(updated > 0)
     }
 
     fn get_instruments(&mut self) -> Vec<Instrument> {
         let query = self.client.as_mut().unwrap().query(
-            "SELECT id, name, i_type, state, percentage_bands, percentage_variation_allowed
+            "SELECT id, name, i_type, state, percentage_bands, percentage_variation_allowed,
+            (SELECT closing_price FROM eod_summary WHERE instrument_id = instrument.id
+            AND closing_price > 0 ORDER BY trading_day DESC LIMIT 1), segment,
+            EXTRACT(EPOCH FROM expiry)::bigint, strike, underlying
             from instrument where active = 1",
             &[],
         );
@@ -85,17 +126,231 @@ impl GenericDB for PGSqlDB {
                     let state: i16 = x.get(3);
                     let perc_bands: i16 = x.get(4);
                     let perc_var_allowed: i16 = x.get(5);
-                    Instrument::new(
+                    let previous_close: Option<i64> = x.get(6);
+                    let segment: i32 = x.get(7);
+                    let expiry: Option<i64> = x.get(8);
+                    let strike: Option<i64> = x.get(9);
+                    let underlying: Option<i64> = x.get(10);
+                    let mut instrument = Instrument::new(
                         id as u64,
                         &name,
                         (i_type as u8).into(),
                         (state as u8).into(),
                         perc_bands as u8,
                         perc_var_allowed as u8,
-                    )
+                    );
+                    instrument.set_previous_close(previous_close.unwrap_or_default() as u64);
+                    instrument.set_segment(segment as u16);
+                    instrument.set_expiry(expiry.unwrap_or_default() as u64);
+                    instrument.set_strike(strike.unwrap_or_default() as u64);
+                    instrument.set_underlying(underlying.unwrap_or_default() as u64);
+                    instrument
                 })
                 .collect(),
             Err(_) => vec![],
         }
     }
+
+    fn store_instrument(&mut self, instrument: &Instrument) -> Result<()> {
+        let id = instrument.get_id() as i64;
+        let i_type: u8 = instrument.get_type().into();
+        let state: u8 = instrument.get_state().into();
+        self.client.as_mut().unwrap().execute(
+            "INSERT INTO instrument (id, name, i_type, state, percentage_bands,
+            percentage_variation_allowed, segment, expiry, strike, underlying)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, to_timestamp(NULLIF($8::bigint, 0)),
+            NULLIF($9::bigint, 0), NULLIF($10::bigint, 0))
+            ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, i_type = EXCLUDED.i_type,
+            state = EXCLUDED.state, percentage_bands = EXCLUDED.percentage_bands,
+            percentage_variation_allowed = EXCLUDED.percentage_variation_allowed,
+            segment = EXCLUDED.segment, expiry = EXCLUDED.expiry, strike = EXCLUDED.strike,
+            underlying = EXCLUDED.underlying, active = 1, updated_at = now()",
+            &[
+                &id,
+                &instrument.get_name(),
+                &(i_type as i16),
+                &(state as i16),
+                &(instrument.get_percentage_bands() as i16),
+                &(instrument.get_percentage_variation_allowed() as i16),
+                &(instrument.get_segment() as i32),
+                &(instrument.get_expiry() as i64),
+                &(instrument.get_strike() as i64),
+                &(instrument.get_underlying() as i64),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_changed_instruments(&mut self, since: Option<SystemTime>) -> Result<ChangedInstruments> {
+        let query = self.client.as_mut().unwrap().query(
+            "SELECT id, name, i_type, state, percentage_bands, percentage_variation_allowed,
+            active, updated_at,
+            (SELECT closing_price FROM eod_summary WHERE instrument_id = instrument.id
+            AND closing_price > 0 ORDER BY trading_day DESC LIMIT 1), segment,
+            EXTRACT(EPOCH FROM expiry)::bigint, strike, underlying
+            from instrument where $1::timestamptz IS NULL OR updated_at > $1",
+            &[&since],
+        )?;
+        let mut changes = ChangedInstruments::default();
+        for x in query.iter() {
+            let id: i64 = x.get(0);
+            let active: i16 = x.get(6);
+            let updated_at: Option<SystemTime> = x.get(7);
+            changes.latest = changes.latest.max(updated_at);
+            if active != 1 {
+                changes.deleted.push(id as u64);
+                continue;
+            }
+            let name: String = x.get(1);
+            let i_type: i16 = x.get(2);
+            let state: i16 = x.get(3);
+            let perc_bands: i16 = x.get(4);
+            let perc_var_allowed: i16 = x.get(5);
+            let previous_close: Option<i64> = x.get(8);
+            let segment: i32 = x.get(9);
+            let expiry: Option<i64> = x.get(10);
+            let strike: Option<i64> = x.get(11);
+            let underlying: Option<i64> = x.get(12);
+            let mut instrument = Instrument::new(
+                id as u64,
+                &name,
+                (i_type as u8).into(),
+                (state as u8).into(),
+                perc_bands as u8,
+                perc_var_allowed as u8,
+            );
+            instrument.set_previous_close(previous_close.unwrap_or_default() as u64);
+            instrument.set_segment(segment as u16);
+            instrument.set_expiry(expiry.unwrap_or_default() as u64);
+            instrument.set_strike(strike.unwrap_or_default() as u64);
+            instrument.set_underlying(underlying.unwrap_or_default() as u64);
+            changes.updated.push(instrument);
+        }
+        Ok(changes)
+    }
+
+    fn delete_instrument(&mut self, id: u64) -> Result<()> {
+        let id = id as i64;
+        let deleted = self.client.as_mut().unwrap().execute(
+            "UPDATE instrument SET active = 0, updated_at = now() WHERE id = $1 AND active = 1",
+            &[&id],
+        )?;
+        if deleted == 0 {
+            bail!("No instrument {id}");
+        }
+        Ok(())
+    }
+
+    fn store_eod_summary(&mut self, summary: &EodSummary) -> Result<()> {
+        let book_id = summary.book_id as i64;
+        let closing_price = summary.closing_price as i64;
+        let volume = summary.volume as i64;
+        let trade_count = summary.trade_count as i64;
+        self.client.as_mut().unwrap().execute(
+            "INSERT INTO eod_summary (instrument_id, trading_day, closing_price, volume, trade_count)
+            VALUES ($1, CURRENT_DATE, $2, $3, $4)",
+            &[&book_id, &closing_price, &volume, &trade_count],
+        )?;
+        Ok(())
+    }
+
+    fn get_risk_limits(&mut self) -> Result<Vec<RiskLimits>> {
+        let query = self.client.as_mut().unwrap().query(
+            "SELECT participant, max_quantity, max_notional, max_open_orders from risk_limits",
+            &[],
+        )?;
+        Ok(query
+            .iter()
+            .map(|x| {
+                let participant: i64 = x.get(0);
+                let max_quantity: i64 = x.get(1);
+                let max_notional: i64 = x.get(2);
+                let max_open_orders: i32 = x.get(3);
+                RiskLimits {
+                    participant: participant as u64,
+                    max_quantity: max_quantity as u64,
+                    max_notional: max_notional as u64,
+                    max_open_orders: max_open_orders as u32,
+                }
+            })
+            .collect())
+    }
+
+    fn get_entitlements(&mut self, participant: u64) -> Result<Vec<u64>> {
+        let query = self.client.as_mut().unwrap().query(
+            "SELECT book_id from entitlements WHERE participant=$1",
+            &[&(participant as i64)],
+        )?;
+        Ok(query
+            .iter()
+            .map(|x| {
+                let book_id: i64 = x.get(0);
+                book_id as u64
+            })
+            .collect())
+    }
+
+    fn store_pending_report(&mut self, session_id: u32, message: &[u8]) -> Result<()> {
+        let s_id = session_id as i32;
+        self.client.as_mut().unwrap().execute(
+            "INSERT INTO pending_reports (session_id, message) VALUES ($1, $2)",
+            &[&s_id, &message],
+        )?;
+        Ok(())
+    }
+
+    fn take_pending_reports(&mut self, session_id: u32) -> Result<Vec<Vec<u8>>> {
+        let s_id = session_id as i32;
+        let query = self.client.as_mut().unwrap().query(
+            "DELETE FROM pending_reports WHERE session_id=$1 RETURNING id, message",
+            &[&s_id],
+        )?;
+        let mut reports: Vec<(i64, Vec<u8>)> = query.iter().map(|x| (x.get(0), x.get(1))).collect();
+        reports.sort_by_key(|(id, _)| *id);
+        Ok(reports.into_iter().map(|(_, message)| message).collect())
+    }
+
+    fn store_order_event(&mut self, ereport: &ExecutionReport) -> Result<()> {
+        self.client.as_mut().unwrap().execute(
+            "INSERT INTO order_events (participant, order_id, submitted_order_id, book_id,
+            quantity, price, side, state, gateway_id, session_id, filled_quantity,
+            leaves_quantity, orig_order_id, reject_reason, trade_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)",
+            &[
+                &(ereport.participant as i64),
+                &(ereport.order_id as i64),
+                &(ereport.submitted_order_id as i64),
+                &(ereport.book as i64),
+                &(ereport.quantity as i64),
+                &(ereport.price as i64),
+                &(ereport.side as i16),
+                &(ereport.state as i16),
+                &(ereport.gateway_id as i16),
+                &(ereport.session_id as i32),
+                &(ereport.filled_quantity as i64),
+                &(ereport.leaves_quantity as i64),
+                &(ereport.orig_order_id as i64),
+                &(ereport.reject_reason as i16),
+                &(ereport.trade_id as i64),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn store_trade(&mut self, capture: &TradeCapture) -> Result<()> {
+        self.client.as_mut().unwrap().execute(
+            "INSERT INTO trades (book_id, trade_id, price, quantity, buyer, seller, traded_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[
+                &(capture.book_id as i64),
+                &(capture.trade_id as i64),
+                &(capture.price as i64),
+                &(capture.quantity as i64),
+                &(capture.buyer as i64),
+                &(capture.seller as i64),
+                &(capture.timestamp as i64),
+            ],
+        )?;
+        Ok(())
+    }
 }
@@ -0,0 +1,11 @@
+/// Pre-trade limits of a participant, 0 meaning unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RiskLimits {
+    pub participant: u64,
+    // of a single order
+    pub max_quantity: u64,
+    // price * quantity of a single order
+    pub max_notional: u64,
+    // orders resting in the books at the same time
+    pub max_open_orders: u32,
+}
@@ -0,0 +1,171 @@
+//! Packing of several feed messages into one datagram
+//!
+//! A batch is sent as a message of its own, with the type ID 11:
+//!
+//! | Sequence (8) | 11 (1) | Count (2) | then Count times: | Length (2) | Type ID (1) | Value (Length - 1) |
+//!
+//! The messages keep the sequence of the datagram carrying them. A batch holding a
+//! single message is sent as that message, so the consumers still get plain datagrams
+//! when the feed is quiet.
+//!
+//! # Example:
+//!
+//! ```
+//! # use disseminator::batch::split;
+//! // sequence 5, a batch of a heartbeat and an auction info fragment
+//! let datagram = [5, 0, 0, 0, 0, 0, 0, 0, 11, 2, 0, 1, 0, 0, 3, 0, 9, 1, 2];
+//! let (seq, messages) = split(&datagram).unwrap();
+//! assert_eq!(5, seq);
+//! assert_eq!(vec![&[0][..], &[9, 1, 2][..]], messages);
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+pub const BATCH_TYPE_ID: u8 = 11;
+// sequence, type ID and count
+const BATCH_HEADER_SIZE: usize = 8 + 1 + 2;
+
+/// The messages waiting to be sent in one datagram of at most @mtu bytes
+#[derive(Debug)]
+pub(crate) struct Batch {
+    mtu: usize,
+    // Length + Type ID + Value of each message
+    buffer: RefCell<Vec<u8>>,
+    count: Cell<u16>,
+    // when the first message held back was added
+    started: Cell<Option<Instant>>,
+}
+
+impl Batch {
+    pub(crate) fn new(mtu: usize) -> Self {
+        Self {
+            mtu,
+            buffer: RefCell::new(Vec::with_capacity(mtu)),
+            count: Cell::new(0),
+            started: Cell::new(None),
+        }
+    }
+
+    /// Whether @message (Type ID + Value) still fits in the datagram
+    pub(crate) fn fits(&self, message: &[u8]) -> bool {
+        self.count.get() < u16::MAX
+            && BATCH_HEADER_SIZE + self.buffer.borrow().len() + 2 + message.len() <= self.mtu
+    }
+
+    pub(crate) fn push(&self, message: &[u8]) {
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.extend_from_slice(&(message.len() as u16).to_le_bytes());
+        buffer.extend_from_slice(message);
+        self.count.set(self.count.get() + 1);
+        if self.started.get().is_none() {
+            self.started.set(Some(Instant::now()));
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.count.get() == 0
+    }
+
+    /// Whether the oldest message held back waited more than @max_delay
+    pub(crate) fn expired(&self, max_delay: Duration) -> bool {
+        self.started
+            .get()
+            .is_some_and(|started| started.elapsed() >= max_delay)
+    }
+
+    /// Empties the batch, returning what has to follow the sequence in the datagram
+    pub(crate) fn take(&self) -> Vec<u8> {
+        let mut buffer = self.buffer.borrow_mut();
+        let r = match self.count.get() {
+            // skip the Length of the single message
+            1 => buffer[2..].to_vec(),
+            count => [&[BATCH_TYPE_ID][..], &count.to_le_bytes(), &buffer].concat(),
+        };
+        buffer.clear();
+        self.count.set(0);
+        self.started.set(None);
+        r
+    }
+}
+
+/// The sequence of a feed datagram and its messages (Type ID + Value),
+/// None if the datagram is malformed
+pub fn split(datagram: &[u8]) -> Option<(u64, Vec<&[u8]>)> {
+    let seq = u64::from_le_bytes(datagram.get(0..8)?.try_into().ok()?);
+    if *datagram.get(8)? != BATCH_TYPE_ID {
+        return Some((seq, vec![&datagram[8..]]));
+    }
+    let count = u16::from_le_bytes(datagram.get(9..11)?.try_into().ok()?);
+    let mut processed = BATCH_HEADER_SIZE;
+    let mut messages = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let len =
+            u16::from_le_bytes(datagram.get(processed..processed + 2)?.try_into().ok()?) as usize;
+        processed += 2;
+        messages.push(datagram.get(processed..processed + len)?);
+        processed += len;
+    }
+    Some((seq, messages))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{split, Batch, BATCH_TYPE_ID};
+
+    #[test]
+    fn fill_up_to_the_mtu() {
+        // room for the header and two messages of 3 bytes
+        let target = Batch::new(8 + 1 + 2 + 2 * (2 + 3));
+        assert!(target.is_empty());
+        assert!(target.fits(&[1, 2, 3]));
+        target.push(&[1, 2, 3]);
+        assert!(!target.fits(&[4, 5, 6, 7]));
+        assert!(target.fits(&[4, 5, 6]));
+        target.push(&[4, 5, 6]);
+        assert!(!target.fits(&[7]));
+
+        let taken = target.take();
+        assert_eq!(
+            vec![BATCH_TYPE_ID, 2, 0, 3, 0, 1, 2, 3, 3, 0, 4, 5, 6],
+            taken
+        );
+        assert!(target.is_empty());
+        assert!(target.fits(&[7]));
+    }
+
+    #[test]
+    fn single_message_is_sent_plain() {
+        let target = Batch::new(100);
+        target.push(&[7, 1, 2]);
+        assert_eq!(vec![7, 1, 2], target.take());
+    }
+
+    #[test]
+    fn expires() {
+        let target = Batch::new(100);
+        assert!(!target.expired(Duration::ZERO));
+        target.push(&[7]);
+        assert!(target.expired(Duration::ZERO));
+        assert!(!target.expired(Duration::from_secs(60)));
+        target.take();
+        assert!(!target.expired(Duration::ZERO));
+    }
+
+    #[test]
+    fn split_datagrams() {
+        let target = Batch::new(100);
+        target.push(&[3, 1]);
+        target.push(&[4, 2, 2]);
+        let datagram = [&7u64.to_le_bytes()[..], &target.take()].concat();
+        assert_eq!(Some((7, vec![&[3, 1][..], &[4, 2, 2]])), split(&datagram));
+        // not a batch
+        let plain = [&7u64.to_le_bytes()[..], &[3, 1]].concat();
+        assert_eq!(Some((7, vec![&[3, 1][..]])), split(&plain));
+        // truncated
+        assert_eq!(None, split(&datagram[..datagram.len() - 1]));
+        assert_eq!(None, split(&datagram[..8]));
+    }
+}
@@ -0,0 +1,159 @@
+//! Book checksums are published periodically on the feed, so that the consumers
+//! can detect if the books they reconstruct from the incremental messages silently
+//! diverged from the ones held by the matching engine.
+//!
+//! The checksum is a FNV-1a hash over the top `depth` price levels of each side,
+//! bids first and then asks, both best price first. Each level contributes its
+//! price and its aggregated quantity as little-endian u64 values. The number of
+//! levels on each side is hashed as well.
+//!
+//! # Example:
+//!
+//! ```
+//! # use disseminator::checksum::{aggregate_levels, BookChecksum, BOOK_CHECKSUM_DEPTH};
+//! let bids = aggregate_levels([(101, 10), (101, 5), (100, 20)]);
+//! let asks = aggregate_levels([(102, 7)]);
+//! let checksum = BookChecksum::new(1000, &bids, &asks, BOOK_CHECKSUM_DEPTH);
+//!
+//! // what a feed consumer would do after decoding the checksum message
+//! let received = BookChecksum::decode(&checksum.encode()).unwrap();
+//! assert!(received.verify(&bids, &asks));
+//! ```
+
+use oep::decoder::DecodeError;
+
+pub const BOOK_CHECKSUM_DEPTH: usize = 10;
+pub const BOOKCHECKSUM_SIZE: usize = 8 + 1 + 8;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookChecksum {
+    pub book_id: u64,
+    pub depth: u8,
+    pub checksum: u64,
+}
+
+impl BookChecksum {
+    /// Computes the checksum for a book. @bids and @asks are (price, quantity) levels,
+    /// best price first, as returned by aggregate_levels
+    pub fn new(book_id: u64, bids: &[(u64, u64)], asks: &[(u64, u64)], depth: usize) -> Self {
+        let depth = std::cmp::min(depth, u8::MAX as usize);
+        Self {
+            book_id,
+            depth: depth as u8,
+            checksum: compute(bids, asks, depth),
+        }
+    }
+
+    /// Checks a locally reconstructed book against this checksum
+    pub fn verify(&self, bids: &[(u64, u64)], asks: &[(u64, u64)]) -> bool {
+        compute(bids, asks, self.depth as usize) == self.checksum
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut r = Vec::with_capacity(BOOKCHECKSUM_SIZE);
+        r.extend_from_slice(&self.book_id.to_le_bytes());
+        r.push(self.depth);
+        r.extend_from_slice(&self.checksum.to_le_bytes());
+        r
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() < BOOKCHECKSUM_SIZE {
+            return Err(DecodeError);
+        }
+        Ok(Self {
+            book_id: u64::from_le_bytes(buf[0..8].try_into().map_err(|_| DecodeError)?),
+            depth: buf[8],
+            checksum: u64::from_le_bytes(buf[9..17].try_into().map_err(|_| DecodeError)?),
+        })
+    }
+}
+
+/// Merges consecutive (price, quantity) entries sharing the same price into levels.
+/// The input is expected to be already sorted in the side priority order.
+pub fn aggregate_levels<I>(orders: I) -> Vec<(u64, u64)>
+where
+    I: IntoIterator<Item = (u64, u64)>,
+{
+    let mut levels: Vec<(u64, u64)> = vec![];
+    for (price, quantity) in orders {
+        match levels.last_mut() {
+            Some(level) if level.0 == price => level.1 = level.1.saturating_add(quantity),
+            _ => levels.push((price, quantity)),
+        }
+    }
+    levels
+}
+
+/// FNV-1a over the top @depth levels of each side
+pub fn compute(bids: &[(u64, u64)], asks: &[(u64, u64)], depth: usize) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut feed = |bytes: &[u8]| {
+        for b in bytes {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    };
+    for side in [bids, asks] {
+        let levels = &side[..std::cmp::min(depth, side.len())];
+        feed(&(levels.len() as u64).to_le_bytes());
+        for (price, quantity) in levels {
+            feed(&price.to_le_bytes());
+            feed(&quantity.to_le_bytes());
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::{aggregate_levels, compute, BookChecksum, BOOKCHECKSUM_SIZE};
+
+    #[test]
+    fn aggregates_same_price() {
+        let levels = aggregate_levels([(100, 1), (100, 2), (99, 3), (98, 4), (98, 5)]);
+        assert_eq!(vec![(100, 3), (99, 3), (98, 9)], levels);
+    }
+
+    #[test]
+    fn empty_book_is_stable() {
+        assert_eq!(compute(&[], &[], 10), compute(&[], &[], 10));
+        assert_ne!(compute(&[], &[], 10), compute(&[(100, 1)], &[], 10));
+    }
+
+    #[test]
+    fn side_matters() {
+        assert_ne!(compute(&[(100, 1)], &[], 10), compute(&[], &[(100, 1)], 10));
+    }
+
+    #[test]
+    fn only_top_levels_count() {
+        let bids = [(100, 1), (99, 1), (98, 1)];
+        let other_bids = [(100, 1), (99, 1), (50, 1000)];
+        assert_eq!(compute(&bids, &[], 2), compute(&other_bids, &[], 2));
+        assert_ne!(compute(&bids, &[], 3), compute(&other_bids, &[], 3));
+    }
+
+    #[test]
+    fn verify_detects_divergence() {
+        let bids = [(100, 10), (99, 20)];
+        let asks = [(101, 5)];
+        let checksum = BookChecksum::new(5, &bids, &asks, 10);
+        assert!(checksum.verify(&bids, &asks));
+        assert!(!checksum.verify(&bids, &[(101, 4)]));
+        assert!(!checksum.verify(&[(100, 10)], &asks));
+    }
+
+    #[test]
+    fn encode_decode() {
+        let checksum = BookChecksum::new(0x0102030405060708, &[(100, 10)], &[(101, 5)], 10);
+        let encoded = checksum.encode();
+        assert_eq!(BOOKCHECKSUM_SIZE, encoded.len());
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1, 10], encoded[0..9]);
+        assert_eq!(checksum, BookChecksum::decode(&encoded).unwrap());
+        assert!(BookChecksum::decode(&encoded[..BOOKCHECKSUM_SIZE - 1]).is_err());
+    }
+}
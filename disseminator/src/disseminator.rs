@@ -1,15 +1,71 @@
+use crate::checksum::BookChecksum;
+use crate::error::DisseminateError;
+use crate::recovery::RecoveryCache;
+use crate::snapshot::SnapshotHeader;
 use instruments::instrument::Instrument;
+use oep::auctioninfo::AuctionInfo;
+use oep::delisting::InstrumentDelisted;
+use oep::eodsummary::EodSummary;
+use oep::segmentstate::SegmentState;
+use oep::statechange::InstrumentStateChange;
+use oep::statistics::Statistics;
 use oep::trade::Trade;
+use oep::tradebust::TradeBust;
 use order::Order;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-pub trait Disseminator: std::fmt::Debug {
-    fn send_cancel_order(&self, order: &Order) -> Result<usize, std::io::Error>;
-    fn send_new_order(&self, order: &Order) -> Result<usize, std::io::Error>;
-    fn send_modify_order(&self, order: &Order) -> Result<usize, std::io::Error>;
-    fn send_trade(&self, trade: &Trade) -> Result<usize, std::io::Error>;
+pub trait Disseminator: std::fmt::Debug + Send {
+    fn send_cancel_order(&self, order: &Order) -> Result<usize, DisseminateError>;
+    fn send_new_order(&self, order: &Order) -> Result<usize, DisseminateError>;
+    fn send_modify_order(&self, order: &Order) -> Result<usize, DisseminateError>;
+    fn send_trade(&self, trade: &Trade) -> Result<usize, DisseminateError>;
+    // a trade cancelled by the exchange after the fact
+    fn send_trade_bust(&self, bust: &TradeBust) -> Result<usize, DisseminateError>;
 
     // instruments and snapshots
-    fn send_instrument_info(&self, instruments: &Instrument) -> Result<usize, std::io::Error>;
+    fn send_instrument_info(&self, instruments: &Instrument) -> Result<usize, DisseminateError>;
     // sends market update, order by order
-    fn send_market_order(&self, order: &Order) -> Result<usize, std::io::Error>;
+    fn send_market_order(&self, order: &Order) -> Result<usize, DisseminateError>;
+    // checksum of the top levels of a book, for the consumers to verify their books
+    fn send_book_checksum(&self, checksum: &BookChecksum) -> Result<usize, DisseminateError>;
+    // closing price and daily statistics of an instrument
+    fn send_eod_summary(&self, summary: &EodSummary) -> Result<usize, DisseminateError>;
+    // indicative price and volume of an ongoing auction
+    fn send_auction_info(&self, info: &AuctionInfo) -> Result<usize, DisseminateError>;
+    // open, high, low, last, volume and VWAP of the session
+    fn send_statistics(&self, statistics: &Statistics) -> Result<usize, DisseminateError>;
+    // a market opening, halting, going into auction or closing
+    fn send_state_change(&self, change: &InstrumentStateChange) -> Result<usize, DisseminateError>;
+    // all the instruments of a segment put in a new state at once
+    fn send_segment_state(&self, state: &SegmentState) -> Result<usize, DisseminateError>;
+    // an instrument gone for good, its book to be dropped
+    fn send_delisting(&self, delisting: &InstrumentDelisted) -> Result<usize, DisseminateError>;
+    // announces the instrument and market messages of a snapshot
+    fn send_snapshot_header(&self, header: &SnapshotHeader) -> Result<usize, DisseminateError>;
+    // sequence of the next message to be sent
+    fn get_sequence(&self) -> u64;
+    // sends the messages held back for batching, if any
+    fn flush(&self) -> Result<usize, DisseminateError>;
+}
+
+/// A disseminator publishing on a multicast channel, whatever its format
+pub trait FeedDisseminator: Disseminator {
+    // keeps the sent packets in @cache, to be retransmitted on request
+    fn set_recovery_cache(&mut self, cache: Arc<Mutex<RecoveryCache>>);
+    // drops the messages while @muted, without using sequence numbers, e.g.
+    // while the engine goes again through what the consumers already got
+    fn set_muted(&mut self, muted: bool);
+    // numbers the next datagram with @seq, e.g. for the feed to go on after a
+    // restart where it stopped
+    fn set_sequence(&mut self, seq: u64);
+    // packs the messages in datagrams of up to @mtu bytes, instead of sending
+    // each of them right away
+    fn set_mtu(&mut self, mtu: usize);
+    // sends every datagram to @addr:@port as well, with the same sequence,
+    // for the consumers to fill the gaps of one feed with the other
+    fn set_b_feed(&mut self, addr: &str, port: u16);
+    // sends the messages held back for batching for longer than @max_delay,
+    // and retries the ones held back because of backpressure
+    fn flush_expired(&self, max_delay: Duration) -> Result<usize, DisseminateError>;
 }
@@ -0,0 +1,56 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::io;
+
+/// Why a message didn't make it to the feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisseminateError {
+    /// The socket can't take more for now and the retry buffer is full
+    WouldBlock,
+    /// The socket is unusable, the message is lost
+    Disconnected,
+    /// The message doesn't fit in a datagram
+    Encode,
+}
+
+impl Display for DisseminateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WouldBlock => write!(f, "Disseminator would block"),
+            Self::Disconnected => write!(f, "Disseminator disconnected"),
+            Self::Encode => write!(f, "Message too big for the feed"),
+        }
+    }
+}
+
+impl Error for DisseminateError {}
+
+impl From<io::Error> for DisseminateError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::TimedOut => {
+                Self::WouldBlock
+            }
+            _ => Self::Disconnected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::DisseminateError;
+
+    #[test]
+    fn from_io_error() {
+        assert_eq!(
+            DisseminateError::WouldBlock,
+            io::Error::from(io::ErrorKind::WouldBlock).into()
+        );
+        assert_eq!(
+            DisseminateError::Disconnected,
+            io::Error::from(io::ErrorKind::ConnectionRefused).into()
+        );
+    }
+}
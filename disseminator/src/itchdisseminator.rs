@@ -0,0 +1,630 @@
+//! The market data in a NASDAQ ITCH like format, for the standard feed handlers
+//!
+//! The messages go out in the datagrams of the MBO feed, sequence, batching
+//! and recovery included, but their Type ID is the ASCII code of the ITCH
+//! message type and their fields are big endian, as in ITCH:
+//!
+//! | Type | Message | Fields
+//! --- | --- | ---
+//! | S | system event | Timestamp, Book ID, Event code
+//! | R | directory | Timestamp, Book ID, Instrument type, Tick size, Round lot, Name (8)
+//! | A | add order | Timestamp, Order ref, Book ID, Side, Shares, Price
+//! | E | order executed | Timestamp, Order ref, Book ID, Executed shares, Match number
+//! | X | order cancel | Timestamp, Order ref, Book ID, Cancelled shares
+//! | D | order delete | Timestamp, Order ref, Book ID
+//! | P | trade | Timestamp, Book ID, Aggressor side, Shares, Price, Match number
+//! | B | broken trade | Timestamp, Book ID, Match number
+//! | I | imbalance | Timestamp, Book ID, Paired shares, Reference price
+//! | G | snapshot header | Timestamp, Book ID, Next incremental sequence, Order count
+//!
+//! All the numbers take 8 bytes, except the order count (4), the sides and
+//! the codes (1). See doc/feed_protocol.md for their meaning.
+//!
+//! # Example:
+//!
+//! ```
+//! # use disseminator::batch::split;
+//! // sequence 3, the delete of the order 7 of the book 1000
+//! let datagram = [
+//!     [3, 0, 0, 0, 0, 0, 0, 0, b'D'].as_slice(),
+//!     &1_700_000_000_000_000_000u64.to_be_bytes(),
+//!     &7u64.to_be_bytes(),
+//!     &1000u64.to_be_bytes(),
+//! ]
+//! .concat();
+//! let (seq, messages) = split(&datagram).unwrap();
+//! assert_eq!(3, seq);
+//! assert_eq!(b'D', messages[0][0]);
+//! assert_eq!(7, u64::from_be_bytes(messages[0][9..17].try_into().unwrap()));
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use instruments::instrument::{Instrument, InstrumentState};
+use oep::{
+    auctioninfo::AuctionInfo,
+    delisting::InstrumentDelisted,
+    eodsummary::EodSummary,
+    segmentstate::SegmentState,
+    statechange::InstrumentStateChange,
+    statistics::Statistics,
+    trade::{Trade, NO_AGGRESSOR},
+    tradebust::TradeBust,
+};
+use order::{Order, Side};
+#[cfg(not(test))]
+use utils::network::MulticastConfig;
+
+use crate::checksum::BookChecksum;
+use crate::disseminator::{Disseminator, FeedDisseminator};
+use crate::error::DisseminateError;
+use crate::mbooepdisseminator::MBOOepDisseminator;
+use crate::recovery::RecoveryCache;
+use crate::snapshot::SnapshotHeader;
+
+pub const SYSTEM_EVENT: u8 = b'S';
+pub const DIRECTORY: u8 = b'R';
+pub const ADD_ORDER: u8 = b'A';
+pub const ORDER_EXECUTED: u8 = b'E';
+pub const ORDER_CANCEL: u8 = b'X';
+pub const ORDER_DELETE: u8 = b'D';
+pub const TRADE: u8 = b'P';
+pub const BROKEN_TRADE: u8 = b'B';
+pub const IMBALANCE: u8 = b'I';
+pub const SNAPSHOT_HEADER: u8 = b'G';
+
+// the instrument names are padded with spaces, or cut, to that many bytes
+const NAME_SIZE: usize = 8;
+
+#[derive(Debug)]
+pub struct ItchDisseminator {
+    // the datagrams carrying the messages
+    feed: MBOOepDisseminator,
+    // order id -> shown quantity of the orders added to the books, for the
+    // modifications to be told as the cancel of a part of an order
+    orders: RefCell<HashMap<u64, u64>>,
+}
+
+/// The exchange time, nanoseconds since the unix epoch
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+fn side_code(side: Side) -> u8 {
+    match side {
+        Side::Bid => b'B',
+        Side::Ask => b'S',
+    }
+}
+
+fn event_code(state: InstrumentState) -> u8 {
+    match state {
+        InstrumentState::Trading => b'T',
+        InstrumentState::Closed => b'C',
+        InstrumentState::Auction => b'A',
+        InstrumentState::Halted => b'H',
+        InstrumentState::PreOpen => b'P',
+    }
+}
+
+impl ItchDisseminator {
+    #[cfg(not(test))]
+    pub fn new(addr: &str, port: u16, multicast: MulticastConfig) -> Self {
+        Self::with_feed(MBOOepDisseminator::new(addr, port, multicast))
+    }
+
+    fn with_feed(feed: MBOOepDisseminator) -> Self {
+        Self {
+            feed,
+            orders: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn add_order(&self, order: &Order) -> Result<usize, DisseminateError> {
+        let m = [
+            now_nanos().to_be_bytes().as_slice(),
+            &order.get_id().to_be_bytes(),
+            &order.instrument.read().unwrap().get_id().to_be_bytes(),
+            &[side_code(order.side)],
+            &order.quantity.to_be_bytes(),
+            &order.price.to_be_bytes(),
+        ]
+        .concat();
+        self.feed.send_with_header(&[ADD_ORDER], &m)
+    }
+
+    fn delete_order(&self, order: &Order) -> Result<usize, DisseminateError> {
+        let m = [
+            now_nanos().to_be_bytes().as_slice(),
+            &order.get_id().to_be_bytes(),
+            &order.instrument.read().unwrap().get_id().to_be_bytes(),
+        ]
+        .concat();
+        self.feed.send_with_header(&[ORDER_DELETE], &m)
+    }
+
+    fn execute_order(&self, order_id: u64, trade: &Trade) -> Result<usize, DisseminateError> {
+        let mut orders = self.orders.borrow_mut();
+        if let Some(shown) = orders.get_mut(&order_id) {
+            *shown = shown.saturating_sub(trade.quantity);
+            if *shown == 0 {
+                orders.remove(&order_id);
+            }
+        }
+        let m = [
+            { trade.timestamp }.to_be_bytes().as_slice(),
+            &order_id.to_be_bytes(),
+            &{ trade.book_id }.to_be_bytes(),
+            &{ trade.quantity }.to_be_bytes(),
+            &{ trade.trade_id }.to_be_bytes(),
+        ]
+        .concat();
+        self.feed.send_with_header(&[ORDER_EXECUTED], &m)
+    }
+}
+
+impl FeedDisseminator for ItchDisseminator {
+    fn set_recovery_cache(&mut self, cache: Arc<Mutex<RecoveryCache>>) {
+        self.feed.set_recovery_cache(cache);
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.feed.set_muted(muted);
+    }
+
+    fn set_sequence(&mut self, seq: u64) {
+        self.feed.set_sequence(seq);
+    }
+
+    fn set_mtu(&mut self, mtu: usize) {
+        self.feed.set_mtu(mtu);
+    }
+
+    fn set_b_feed(&mut self, addr: &str, port: u16) {
+        self.feed.set_b_feed(addr, port);
+    }
+
+    fn flush_expired(&self, max_delay: Duration) -> Result<usize, DisseminateError> {
+        self.feed.flush_expired(max_delay)
+    }
+}
+
+impl Disseminator for ItchDisseminator {
+    fn send_cancel_order(&self, order: &Order) -> Result<usize, DisseminateError> {
+        self.orders.borrow_mut().remove(&order.get_id());
+        self.delete_order(order)
+    }
+
+    fn send_new_order(&self, order: &Order) -> Result<usize, DisseminateError> {
+        self.orders
+            .borrow_mut()
+            .insert(order.get_id(), order.quantity);
+        self.add_order(order)
+    }
+
+    fn send_modify_order(&self, order: &Order) -> Result<usize, DisseminateError> {
+        let shown = self.orders.borrow().get(&order.get_id()).copied();
+        match shown {
+            // a decrease keeps the queue position
+            Some(shown) if order.quantity <= shown => {
+                if order.quantity == shown {
+                    return Ok(0);
+                }
+                self.orders
+                    .borrow_mut()
+                    .insert(order.get_id(), order.quantity);
+                let m = [
+                    now_nanos().to_be_bytes().as_slice(),
+                    &order.get_id().to_be_bytes(),
+                    &order.instrument.read().unwrap().get_id().to_be_bytes(),
+                    &(shown - order.quantity).to_be_bytes(),
+                ]
+                .concat();
+                self.feed.send_with_header(&[ORDER_CANCEL], &m)
+            }
+            // an increase sends the order to the back of its price level, and
+            // so does the modify of an order added before a restart
+            _ => Ok(self.delete_order(order)? + self.send_new_order(order)?),
+        }
+    }
+
+    fn send_trade(&self, trade: &Trade) -> Result<usize, DisseminateError> {
+        let aggressor = match trade.aggressor_side {
+            NO_AGGRESSOR => None,
+            side => Some(Side::from(side)),
+        };
+        let mut r = 0;
+        // the resting orders, both of them in an auction uncross
+        if aggressor != Some(Side::Ask) {
+            r += self.execute_order(trade.ask_order_id, trade)?;
+        }
+        if aggressor != Some(Side::Bid) {
+            r += self.execute_order(trade.bid_order_id, trade)?;
+        }
+        let m = [
+            { trade.timestamp }.to_be_bytes().as_slice(),
+            &{ trade.book_id }.to_be_bytes(),
+            &[aggressor.map_or(b' ', side_code)],
+            &{ trade.quantity }.to_be_bytes(),
+            &{ trade.price }.to_be_bytes(),
+            &{ trade.trade_id }.to_be_bytes(),
+        ]
+        .concat();
+        r += self.feed.send_with_header(&[TRADE], &m)?;
+        // the trades are not held back
+        self.feed.flush()?;
+        Ok(r)
+    }
+
+    fn send_trade_bust(&self, bust: &TradeBust) -> Result<usize, DisseminateError> {
+        let m = [
+            { bust.timestamp }.to_be_bytes().as_slice(),
+            &{ bust.book_id }.to_be_bytes(),
+            &{ bust.trade_id }.to_be_bytes(),
+        ]
+        .concat();
+        self.feed.send_with_header(&[BROKEN_TRADE], &m)
+    }
+
+    fn send_instrument_info(&self, instrument: &Instrument) -> Result<usize, DisseminateError> {
+        let mut name = instrument.get_name().as_bytes().to_vec();
+        name.resize(NAME_SIZE, b' ');
+        let timestamp = now_nanos().to_be_bytes();
+        let book_id = instrument.get_id().to_be_bytes();
+        let directory = [
+            timestamp.as_slice(),
+            &book_id,
+            &[instrument.get_type().into()],
+            &instrument.get_tick_size().to_be_bytes(),
+            &instrument.get_round_lot().to_be_bytes(),
+            &name,
+        ]
+        .concat();
+        let event = [
+            timestamp.as_slice(),
+            &book_id,
+            &[event_code(instrument.get_state())],
+        ]
+        .concat();
+        Ok(self.feed.send_with_header(&[DIRECTORY], &directory)?
+            + self.feed.send_with_header(&[SYSTEM_EVENT], &event)?)
+    }
+
+    fn send_market_order(&self, order: &Order) -> Result<usize, DisseminateError> {
+        // on the snapshot channel, the book is told order by order
+        self.add_order(order)
+    }
+
+    fn send_book_checksum(&self, _checksum: &BookChecksum) -> Result<usize, DisseminateError> {
+        // no ITCH counterpart
+        Ok(0)
+    }
+
+    fn send_eod_summary(&self, _summary: &EodSummary) -> Result<usize, DisseminateError> {
+        // no ITCH counterpart, the close being told by the system event
+        Ok(0)
+    }
+
+    fn send_auction_info(&self, info: &AuctionInfo) -> Result<usize, DisseminateError> {
+        let m = [
+            now_nanos().to_be_bytes().as_slice(),
+            &{ info.book_id }.to_be_bytes(),
+            &{ info.volume }.to_be_bytes(),
+            &{ info.price }.to_be_bytes(),
+        ]
+        .concat();
+        self.feed.send_with_header(&[IMBALANCE], &m)
+    }
+
+    fn send_statistics(&self, _statistics: &Statistics) -> Result<usize, DisseminateError> {
+        // no ITCH counterpart, the consumers add up the trades
+        Ok(0)
+    }
+
+    fn send_state_change(
+        &self,
+        _change: &InstrumentStateChange,
+    ) -> Result<usize, DisseminateError> {
+        // told by the system event following the directory
+        Ok(0)
+    }
+
+    fn send_segment_state(&self, _state: &SegmentState) -> Result<usize, DisseminateError> {
+        // no such message, the system events of the instruments tell it
+        Ok(0)
+    }
+
+    fn send_delisting(&self, _delisting: &InstrumentDelisted) -> Result<usize, DisseminateError> {
+        // no ITCH counterpart
+        Ok(0)
+    }
+
+    fn send_snapshot_header(&self, header: &SnapshotHeader) -> Result<usize, DisseminateError> {
+        let m = [
+            now_nanos().to_be_bytes().as_slice(),
+            &header.book_id.to_be_bytes(),
+            &header.next_seq.to_be_bytes(),
+            &header.order_count.to_be_bytes(),
+        ]
+        .concat();
+        self.feed.send_with_header(&[SNAPSHOT_HEADER], &m)
+    }
+
+    fn get_sequence(&self) -> u64 {
+        self.feed.get_sequence()
+    }
+
+    fn flush(&self) -> Result<usize, DisseminateError> {
+        self.feed.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::RwLock;
+
+    use instruments::instrument::InstrumentType;
+    use order::OrderType;
+
+    use crate::batch::split;
+
+    use super::*;
+
+    const BOOK_ID: u64 = 444;
+
+    fn new_target() -> ItchDisseminator {
+        ItchDisseminator::with_feed(MBOOepDisseminator::mock())
+    }
+
+    fn new_order(id: u64, side: Side, price: u64, quantity: u64) -> Order {
+        let instrument = Instrument::new_fast(BOOK_ID, InstrumentType::Share);
+        let mut order = Order::new(
+            1001,
+            Arc::new(RwLock::new(instrument)),
+            price,
+            quantity,
+            side,
+            OrderType::Day,
+        )
+        .with_session(1, 100);
+        order.set_id(id);
+        order
+    }
+
+    fn u64_at(message: &[u8], offset: usize) -> u64 {
+        u64::from_be_bytes(message[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// The messages sent since the last call, along with their sequence
+    fn sent(target: &ItchDisseminator) -> Vec<(u64, Vec<u8>)> {
+        let buffer = target.feed.take_sent();
+        let mut r = vec![];
+        let mut datagrams = buffer.as_slice();
+        // unbatched, every datagram is the sequence and one message
+        while !datagrams.is_empty() {
+            let size = 8
+                + 1
+                + match datagrams[8] {
+                    ADD_ORDER => 41,
+                    ORDER_EXECUTED => 40,
+                    ORDER_CANCEL => 32,
+                    ORDER_DELETE => 24,
+                    TRADE => 41,
+                    BROKEN_TRADE => 24,
+                    SYSTEM_EVENT => 17,
+                    DIRECTORY => 41,
+                    IMBALANCE => 32,
+                    SNAPSHOT_HEADER => 28,
+                    t => panic!("Unexpected message type {t}"),
+                };
+            let (seq, messages) = split(&datagrams[..size]).unwrap();
+            r.push((seq, messages[0].to_vec()));
+            datagrams = &datagrams[size..];
+        }
+        r
+    }
+
+    #[test]
+    fn add_and_delete() {
+        let target = new_target();
+        let order = new_order(7, Side::Bid, 100, 50);
+
+        assert!(target.send_new_order(&order).is_ok());
+        assert!(target.send_cancel_order(&order).is_ok());
+
+        let sent = sent(&target);
+        assert_eq!(2, sent.len());
+        let (seq, add) = &sent[0];
+        assert_eq!(0, *seq);
+        assert_eq!(ADD_ORDER, add[0]);
+        assert_eq!(7, u64_at(add, 9));
+        assert_eq!(BOOK_ID, u64_at(add, 17));
+        assert_eq!(b'B', add[25]);
+        assert_eq!(50, u64_at(add, 26));
+        assert_eq!(100, u64_at(add, 34));
+
+        let (seq, delete) = &sent[1];
+        assert_eq!(1, *seq);
+        assert_eq!(ORDER_DELETE, delete[0]);
+        assert_eq!(7, u64_at(delete, 9));
+        assert_eq!(BOOK_ID, u64_at(delete, 17));
+        assert!(target.orders.borrow().is_empty());
+    }
+
+    #[test]
+    fn modify() {
+        let target = new_target();
+        let mut order = new_order(7, Side::Ask, 100, 50);
+        target.send_new_order(&order).unwrap();
+        sent(&target);
+
+        // a decrease is the cancel of a part of the order
+        order.quantity = 30;
+        target.send_modify_order(&order).unwrap();
+        let sent_messages = sent(&target);
+        assert_eq!(1, sent_messages.len());
+        let cancel = &sent_messages[0].1;
+        assert_eq!(ORDER_CANCEL, cancel[0]);
+        assert_eq!(7, u64_at(cancel, 9));
+        assert_eq!(20, u64_at(cancel, 25));
+
+        // an increase loses the queue position
+        order.quantity = 60;
+        target.send_modify_order(&order).unwrap();
+        let types: Vec<u8> = sent(&target).iter().map(|(_, m)| m[0]).collect();
+        assert_eq!(vec![ORDER_DELETE, ADD_ORDER], types);
+        assert_eq!(Some(&60), target.orders.borrow().get(&7));
+
+        // nothing to tell without a change of the shown quantity
+        assert_eq!(0, target.send_modify_order(&order).unwrap());
+    }
+
+    #[test]
+    fn trades() {
+        let target = new_target();
+        let resting = new_order(7, Side::Ask, 100, 50);
+        target.send_new_order(&resting).unwrap();
+        sent(&target);
+
+        let mut trade = Trade {
+            bid_order_id: 8,
+            ask_order_id: 7,
+            price: 100,
+            quantity: 20,
+            book_id: BOOK_ID,
+            trade_id: 3,
+            timestamp: 1234,
+            aggressor_side: Side::Bid.into(),
+        };
+        target.send_trade(&trade).unwrap();
+        let sent_messages = sent(&target);
+        assert_eq!(2, sent_messages.len());
+        let executed = &sent_messages[0].1;
+        assert_eq!(ORDER_EXECUTED, executed[0]);
+        assert_eq!(1234, u64_at(executed, 1));
+        assert_eq!(7, u64_at(executed, 9));
+        assert_eq!(BOOK_ID, u64_at(executed, 17));
+        assert_eq!(20, u64_at(executed, 25));
+        assert_eq!(3, u64_at(executed, 33));
+        let print = &sent_messages[1].1;
+        assert_eq!(TRADE, print[0]);
+        assert_eq!(BOOK_ID, u64_at(print, 9));
+        assert_eq!(b'B', print[17]);
+        assert_eq!(20, u64_at(print, 18));
+        assert_eq!(100, u64_at(print, 26));
+        assert_eq!(3, u64_at(print, 34));
+        assert_eq!(Some(&30), target.orders.borrow().get(&7));
+
+        // an uncross executes both orders
+        trade.quantity = 30;
+        trade.aggressor_side = NO_AGGRESSOR;
+        target.send_trade(&trade).unwrap();
+        let sent_messages = sent(&target);
+        let executed: Vec<u64> = sent_messages
+            .iter()
+            .filter(|(_, m)| m[0] == ORDER_EXECUTED)
+            .map(|(_, m)| u64_at(m, 9))
+            .collect();
+        assert_eq!(vec![7, 8], executed);
+        assert_eq!(b' ', sent_messages[2].1[17]);
+        // fully executed
+        assert!(target.orders.borrow().is_empty());
+    }
+
+    #[test]
+    fn instrument_and_snapshot() {
+        let target = new_target();
+        let mut instrument = Instrument::new(
+            BOOK_ID,
+            "ACME",
+            InstrumentType::Share,
+            InstrumentState::Halted,
+            0,
+            30,
+        );
+        instrument.set_tick_size(5);
+        target.send_instrument_info(&instrument).unwrap();
+        let sent_messages = sent(&target);
+        let directory = &sent_messages[0].1;
+        assert_eq!(DIRECTORY, directory[0]);
+        assert_eq!(BOOK_ID, u64_at(directory, 9));
+        assert_eq!(5, u64_at(directory, 18));
+        assert_eq!(b"ACME    ", &directory[34..42]);
+        let event = &sent_messages[1].1;
+        assert_eq!(SYSTEM_EVENT, event[0]);
+        assert_eq!(BOOK_ID, u64_at(event, 9));
+        assert_eq!(b'H', event[17]);
+
+        let header = SnapshotHeader {
+            book_id: BOOK_ID,
+            next_seq: 42,
+            order_count: 1,
+        };
+        target.send_snapshot_header(&header).unwrap();
+        target
+            .send_market_order(&new_order(7, Side::Bid, 100, 50))
+            .unwrap();
+        let sent_messages = sent(&target);
+        let header = &sent_messages[0].1;
+        assert_eq!(SNAPSHOT_HEADER, header[0]);
+        assert_eq!(42, u64_at(header, 17));
+        assert_eq!(1, u32::from_be_bytes(header[25..29].try_into().unwrap()));
+        assert_eq!(ADD_ORDER, sent_messages[1].1[0]);
+        // the snapshots don't track the orders
+        assert!(target.orders.borrow().is_empty());
+
+        target
+            .send_auction_info(&AuctionInfo {
+                book_id: BOOK_ID,
+                price: 100,
+                volume: 25,
+            })
+            .unwrap();
+        let imbalance = &sent(&target)[0].1;
+        assert_eq!(IMBALANCE, imbalance[0]);
+        assert_eq!(25, u64_at(imbalance, 17));
+        assert_eq!(100, u64_at(imbalance, 25));
+
+        target
+            .send_trade_bust(&TradeBust {
+                book_id: BOOK_ID,
+                trade_id: 3,
+                price: 100,
+                quantity: 25,
+                timestamp: 1000,
+            })
+            .unwrap();
+        let broken = &sent(&target)[0].1;
+        assert_eq!(BROKEN_TRADE, broken[0]);
+        assert_eq!(1000, u64_at(broken, 1));
+        assert_eq!(BOOK_ID, u64_at(broken, 9));
+        assert_eq!(3, u64_at(broken, 17));
+    }
+
+    #[test]
+    fn batched() {
+        let mut target = new_target();
+        target.set_mtu(1400);
+        target
+            .send_new_order(&new_order(7, Side::Bid, 100, 50))
+            .unwrap();
+        target
+            .send_new_order(&new_order(8, Side::Bid, 100, 50))
+            .unwrap();
+        assert!(target.feed.take_sent().is_empty());
+
+        target.flush().unwrap();
+        let buffer = target.feed.take_sent();
+        let (seq, messages) = split(&buffer).unwrap();
+        assert_eq!(0, seq);
+        assert_eq!(2, messages.len());
+        assert_eq!(8, u64_at(messages[1], 9));
+        assert_eq!(1, target.get_sequence());
+    }
+}
@@ -1,3 +1,9 @@
+pub mod batch;
+pub mod checksum;
 pub mod disseminator;
+pub mod error;
+pub mod itchdisseminator;
 pub mod mbooepdisseminator;
 pub mod mockdisseminator;
+pub mod recovery;
+pub mod snapshot;
@@ -2,34 +2,61 @@ use instruments::instrument::Instrument;
 /// I do grotesque things in this file just for the sake of testing
 ///
 ///
-use oep::{cancel::Cancel, decoder::Decoder, modify::Modify, neworder::NewOrder, trade::Trade};
+use oep::{
+    auctioninfo::AuctionInfo, cancel::Cancel, decoder::Decoder, delisting::InstrumentDelisted,
+    eodsummary::EodSummary, modify::Modify, neworder::NewOrder, segmentstate::SegmentState,
+    statechange::InstrumentStateChange, statistics::Statistics, trade::Trade, tradebust::TradeBust,
+};
 use order::Order;
 #[cfg(not(test))]
-use socket2::{Domain, Protocol, SockAddr, Socket, Type};
-use std::cell::Cell;
-#[cfg(test)]
-use std::cell::RefCell;
+use socket2::Socket;
+use std::cell::{Cell, RefCell};
+use std::sync::{Arc, Mutex};
 #[cfg(not(test))]
-use std::net::{Ipv4Addr, SocketAddrV4};
+use utils::network::{self, MulticastConfig};
+
+use std::collections::VecDeque;
+use std::time::Duration;
 
-use crate::disseminator::Disseminator;
+use crate::batch::Batch;
+use crate::checksum::BookChecksum;
+use crate::disseminator::{Disseminator, FeedDisseminator};
+use crate::error::DisseminateError;
+use crate::recovery::RecoveryCache;
+use crate::snapshot::SnapshotHeader;
 
 #[cfg(not(test))]
 #[derive(Debug)]
 pub struct MBOOepDisseminator {
     socket: Socket,
+    // the redundant B feed, carrying the same datagrams, see set_b_feed
+    b_socket: Option<Socket>,
     seq: Cell<u64>,
+    // what was sent, for retransmission
+    recovery: Option<Arc<Mutex<RecoveryCache>>>,
+    // the messages held back, to be sent in one datagram
+    batch: Option<Batch>,
+    // the datagrams the socket couldn't take yet, oldest first
+    pending: RefCell<VecDeque<Vec<u8>>>,
+    // the messages are dropped, see set_muted
+    muted: bool,
+    // the interface, ttl and loopback of both feeds
+    multicast: MulticastConfig,
 }
 
 #[cfg(test)]
 #[derive(Debug)]
 struct MockSocket {
     pub buffer: RefCell<Vec<u8>>,
+    pub would_block: Cell<bool>,
 }
 
 #[cfg(test)]
 impl MockSocket {
     pub fn send(&self, bytes: &[u8]) -> Result<usize, std::io::Error> {
+        if self.would_block.get() {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
         self.buffer.borrow_mut().append(&mut bytes.to_vec());
         Ok(bytes.len())
     }
@@ -39,126 +66,356 @@ impl MockSocket {
 #[derive(Debug)]
 pub struct MBOOepDisseminator {
     socket: MockSocket,
+    b_socket: Option<MockSocket>,
     seq: Cell<u64>,
+    recovery: Option<Arc<Mutex<RecoveryCache>>>,
+    batch: Option<Batch>,
+    pending: RefCell<VecDeque<Vec<u8>>>,
+    muted: bool,
 }
 
+/// Largest UDP payload over IPv4
+const MAX_DATAGRAM_SIZE: usize = 65507;
+/// Upper limit of the datagrams kept while the socket is full
+pub const MAX_PENDING_DATAGRAMS: usize = 10000;
+
 impl MBOOepDisseminator {
     #[cfg(not(test))]
-    pub fn new(addr: &str, port: u16) -> Self {
-        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
-        socket
-            .connect(&SockAddr::from(SocketAddrV4::new(
-                addr.parse::<Ipv4Addr>().unwrap(),
-                port,
-            )))
-            .expect("Error connecting the disseminator");
-        socket
-            .set_multicast_loop_v4(true)
-            .expect("set_multicast_loop_v4");
+    pub fn new(addr: &str, port: u16, multicast: MulticastConfig) -> Self {
         Self {
-            socket: socket,
+            socket: Self::connect(addr, port, &multicast),
+            b_socket: None,
             seq: Cell::new(0),
+            recovery: None,
+            batch: None,
+            pending: RefCell::new(VecDeque::new()),
+            muted: false,
+            multicast,
         }
     }
 
-    fn send(&self, bytes: &[u8]) -> Result<usize, std::io::Error> {
+    #[cfg(not(test))]
+    fn connect(addr: &str, port: u16, multicast: &MulticastConfig) -> Socket {
+        let address = network::socket_address(addr, port).expect("Invalid feed address");
+        let socket =
+            network::udp_sender(&address, multicast).expect("Error connecting the disseminator");
+        // a full socket buffer must not stall the engine
+        socket.set_nonblocking(true).expect("set_nonblocking");
+        socket
+    }
+
+    /// Sends a datagram, or keeps it for later if the socket can't take it yet.
+    /// Fails with WouldBlock, without using a sequence number, once
+    /// MAX_PENDING_DATAGRAMS are waiting
+    fn send(&self, bytes: &[u8]) -> Result<usize, DisseminateError> {
+        if 8 + bytes.len() > MAX_DATAGRAM_SIZE {
+            return Err(DisseminateError::Encode);
+        }
+        // the older datagrams go first, to keep the sequence in order
+        self.retry_pending()?;
+        if self.pending.borrow().len() >= MAX_PENDING_DATAGRAMS {
+            return Err(DisseminateError::WouldBlock);
+        }
+
         let old_seq = self.seq.get();
         self.seq.set(old_seq + 1);
-        self.socket
-            .send([&old_seq.to_le_bytes(), bytes].concat().as_slice())
+        let packet = [&old_seq.to_le_bytes(), bytes].concat();
+        if let Some(cache) = &self.recovery {
+            cache.lock().unwrap().push(old_seq, packet.clone());
+        }
+        // best effort, the consumers arbitrate between the two feeds
+        if let Some(b_socket) = &self.b_socket {
+            let _ = b_socket.send(packet.as_slice());
+        }
+        if !self.pending.borrow().is_empty() {
+            let len = packet.len();
+            self.pending.borrow_mut().push_back(packet);
+            return Ok(len);
+        }
+        match self.socket.send(packet.as_slice()) {
+            Ok(r) => Ok(r),
+            Err(e) => match DisseminateError::from(e) {
+                DisseminateError::WouldBlock => {
+                    let len = packet.len();
+                    self.pending.borrow_mut().push_back(packet);
+                    Ok(len)
+                }
+                e => Err(e),
+            },
+        }
     }
 
-    fn send_with_header(&self, header_bytes: &[u8], bytes: &[u8]) -> Result<usize, std::io::Error> {
-        self.send([header_bytes, bytes].concat().as_slice())
+    /// Sends the datagrams kept because of backpressure, as long as the socket takes them
+    fn retry_pending(&self) -> Result<usize, DisseminateError> {
+        let mut pending = self.pending.borrow_mut();
+        let mut sent = 0;
+        while let Some(packet) = pending.front() {
+            match self.socket.send(packet) {
+                Ok(r) => {
+                    sent += r;
+                    pending.pop_front();
+                }
+                Err(e) => match DisseminateError::from(e) {
+                    DisseminateError::WouldBlock => break,
+                    e => return Err(e),
+                },
+            }
+        }
+        Ok(sent)
+    }
+
+    /// A disseminator writing to a MockSocket, for the tests of the formats
+    /// sharing its datagrams
+    #[cfg(test)]
+    pub(crate) fn mock() -> Self {
+        Self {
+            socket: MockSocket {
+                buffer: RefCell::new(vec![]),
+                would_block: Cell::new(false),
+            },
+            b_socket: None,
+            seq: Cell::new(0),
+            recovery: None,
+            batch: None,
+            pending: RefCell::new(VecDeque::new()),
+            muted: false,
+        }
+    }
+
+    /// What went out on the MockSocket so far, emptying it
+    #[cfg(test)]
+    pub(crate) fn take_sent(&self) -> Vec<u8> {
+        self.socket.buffer.take()
+    }
+
+    /// Sends the Type ID @header_bytes followed by @bytes, as a datagram of
+    /// its own or in the current batch
+    pub(crate) fn send_with_header(
+        &self,
+        header_bytes: &[u8],
+        bytes: &[u8],
+    ) -> Result<usize, DisseminateError> {
+        if self.muted {
+            return Ok(0);
+        }
+        let message = [header_bytes, bytes].concat();
+        let Some(batch) = &self.batch else {
+            return self.send(message.as_slice());
+        };
+        if !batch.fits(&message) {
+            self.flush()?;
+            // too big even for an empty batch
+            if !batch.fits(&message) {
+                return self.send(message.as_slice());
+            }
+        }
+        batch.push(&message);
+        Ok(message.len())
+    }
+}
+
+impl FeedDisseminator for MBOOepDisseminator {
+    fn set_recovery_cache(&mut self, cache: Arc<Mutex<RecoveryCache>>) {
+        self.recovery = Some(cache);
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    fn set_sequence(&mut self, seq: u64) {
+        self.seq.set(seq);
+    }
+
+    fn set_mtu(&mut self, mtu: usize) {
+        self.batch = Some(Batch::new(mtu));
+    }
+
+    #[cfg(not(test))]
+    fn set_b_feed(&mut self, addr: &str, port: u16) {
+        self.b_socket = Some(Self::connect(addr, port, &self.multicast));
+    }
+
+    #[cfg(test)]
+    fn set_b_feed(&mut self, _addr: &str, _port: u16) {
+        self.b_socket = Some(MockSocket {
+            buffer: RefCell::new(vec![]),
+            would_block: Cell::new(false),
+        });
+    }
+
+    fn flush_expired(&self, max_delay: Duration) -> Result<usize, DisseminateError> {
+        match &self.batch {
+            Some(batch) if batch.expired(max_delay) => self.flush(),
+            _ => self.retry_pending(),
+        }
     }
 }
 
 impl Disseminator for MBOOepDisseminator {
-    fn send_cancel_order(&self, order: &Order) -> Result<usize, std::io::Error> {
+    fn send_cancel_order(&self, order: &Order) -> Result<usize, DisseminateError> {
         let cancel_order_header = [6];
         let m = Cancel {
             participant: order.participant,
             order_id: order.get_id(),
-            book_id: order.instrument.borrow().get_id(),
+            book_id: order.instrument.read().unwrap().get_id(),
             gateway_id: 0,
             session_id: 0,
             side: order.side.into(),
+            orig_client_order_id: 0,
         };
         self.send_with_header(&cancel_order_header, &m.encode())
     }
 
-    fn send_new_order(&self, order: &Order) -> Result<usize, std::io::Error> {
+    fn send_new_order(&self, order: &Order) -> Result<usize, DisseminateError> {
         let new_order_header = [4];
         let m = NewOrder {
             client_order_id: order.get_id(),
             participant: order.participant,
-            book_id: order.instrument.borrow().get_id(),
+            book_id: order.instrument.read().unwrap().get_id(),
             quantity: order.quantity,
             price: order.price,
             order_type: order.order_type.into(),
             side: order.side.into(),
             gateway_id: 0,
             session_id: 0,
+            expiry: order.expiry,
+            stop_price: order.stop_price,
+            // the hidden part of an iceberg order is not disclosed
+            display_quantity: 0,
         };
         self.send_with_header(&new_order_header, &m.encode())
     }
 
-    fn send_modify_order(&self, order: &Order) -> Result<usize, std::io::Error> {
+    fn send_modify_order(&self, order: &Order) -> Result<usize, DisseminateError> {
         let modify_header = [5];
         let m = Modify {
             participant: order.participant,
             order_id: order.get_id(),
-            book_id: order.instrument.borrow().get_id(),
+            book_id: order.instrument.read().unwrap().get_id(),
             quantity: order.quantity,
             price: order.price,
             gateway_id: 0,
             session_id: 0,
             side: order.side.into(),
+            orig_client_order_id: 0,
         };
         self.send_with_header(&modify_header, &m.encode())
     }
 
-    fn send_trade(&self, trade: &Trade) -> Result<usize, std::io::Error> {
+    fn send_trade(&self, trade: &Trade) -> Result<usize, DisseminateError> {
         let trade_header = [3];
-        self.send_with_header(&trade_header, &trade.encode())
+        // the trades are not held back
+        let r = self.send_with_header(&trade_header, &trade.encode())?;
+        self.flush()?;
+        Ok(r)
+    }
+
+    fn send_trade_bust(&self, bust: &TradeBust) -> Result<usize, DisseminateError> {
+        let trade_bust_header = [14];
+        self.send_with_header(&trade_bust_header, &bust.encode())
     }
 
-    fn send_instrument_info(&self, instrument: &Instrument) -> Result<usize, std::io::Error> {
+    fn send_instrument_info(&self, instrument: &Instrument) -> Result<usize, DisseminateError> {
         let instrument_header = [1];
         self.send_with_header(&instrument_header, &instrument.encode())
     }
 
-    fn send_market_order(&self, order: &Order) -> Result<usize, std::io::Error> {
+    fn send_market_order(&self, order: &Order) -> Result<usize, DisseminateError> {
         let market_header = [2];
         let m = NewOrder {
             client_order_id: order.get_id(),
             participant: order.participant,
-            book_id: order.instrument.borrow().get_id(),
+            book_id: order.instrument.read().unwrap().get_id(),
             quantity: order.quantity,
             price: order.price,
             order_type: order.order_type.into(),
             side: order.side.into(),
             gateway_id: 0,
             session_id: 0,
+            expiry: order.expiry,
+            stop_price: order.stop_price,
+            // the hidden part of an iceberg order is not disclosed
+            display_quantity: 0,
         };
         self.send_with_header(&market_header, &m.encode())
     }
+
+    fn send_book_checksum(&self, checksum: &BookChecksum) -> Result<usize, DisseminateError> {
+        let checksum_header = [7];
+        self.send_with_header(&checksum_header, &checksum.encode())
+    }
+
+    fn send_eod_summary(&self, summary: &EodSummary) -> Result<usize, DisseminateError> {
+        let eod_summary_header = [8];
+        self.send_with_header(&eod_summary_header, &summary.encode())
+    }
+
+    fn send_auction_info(&self, info: &AuctionInfo) -> Result<usize, DisseminateError> {
+        let auction_info_header = [9];
+        self.send_with_header(&auction_info_header, &info.encode())
+    }
+
+    fn send_statistics(&self, statistics: &Statistics) -> Result<usize, DisseminateError> {
+        let statistics_header = [12];
+        self.send_with_header(&statistics_header, &statistics.encode())
+    }
+
+    fn send_state_change(&self, change: &InstrumentStateChange) -> Result<usize, DisseminateError> {
+        let state_change_header = [13];
+        self.send_with_header(&state_change_header, &change.encode())
+    }
+
+    fn send_segment_state(&self, state: &SegmentState) -> Result<usize, DisseminateError> {
+        let segment_state_header = [15];
+        self.send_with_header(&segment_state_header, &state.encode())
+    }
+
+    fn send_delisting(&self, delisting: &InstrumentDelisted) -> Result<usize, DisseminateError> {
+        let delisting_header = [16];
+        self.send_with_header(&delisting_header, &delisting.encode())
+    }
+
+    fn send_snapshot_header(&self, header: &SnapshotHeader) -> Result<usize, DisseminateError> {
+        let snapshot_header = [10];
+        self.send_with_header(&snapshot_header, &header.encode())
+    }
+
+    fn get_sequence(&self) -> u64 {
+        self.seq.get()
+    }
+
+    fn flush(&self) -> Result<usize, DisseminateError> {
+        match &self.batch {
+            Some(batch) if !batch.is_empty() => self.send(&batch.take()),
+            _ => self.retry_pending(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::{
-        cell::{Cell, RefCell},
-        rc::Rc,
+        sync::{Arc, Mutex, RwLock},
+        time::Duration,
     };
 
     use instruments::instrument::Instrument;
     use oep::decoder::Decoder;
     use order::{Order, Side};
 
-    use crate::disseminator::Disseminator;
+    use crate::batch::split;
+    use crate::checksum::{BookChecksum, BOOKCHECKSUM_SIZE};
+    use crate::disseminator::{Disseminator, FeedDisseminator};
+    use crate::error::DisseminateError;
+    use crate::recovery::RecoveryCache;
+    use crate::snapshot::{SnapshotHeader, SNAPSHOT_HEADER_SIZE};
+
+    use super::{MBOOepDisseminator, MAX_PENDING_DATAGRAMS};
 
-    use super::MBOOepDisseminator;
+    fn new_target() -> MBOOepDisseminator {
+        MBOOepDisseminator::mock()
+    }
 
     #[test]
     pub fn send_new_order() {
@@ -167,20 +424,14 @@ mod test {
             Instrument::new_fast(BOOK_ID, instruments::instrument::InstrumentType::Share);
         let order = Order::new(
             1001,
-            Rc::new(RefCell::new(instrument)),
+            Arc::new(RwLock::new(instrument)),
             123,
             100,
             order::Side::Bid,
             order::OrderType::Day,
-            100,
-            1001,
-        );
-        let target = MBOOepDisseminator {
-            socket: super::MockSocket {
-                buffer: RefCell::new(vec![]),
-            },
-            seq: Cell::new(0),
-        };
+        )
+        .with_session(100, 1001);
+        let target = new_target();
 
         let v = target.send_new_order(&order);
         assert!(v.is_ok());
@@ -195,6 +446,9 @@ mod test {
             side: order.side.into(),
             gateway_id: 0,
             session_id: 0,
+            expiry: order.expiry,
+            stop_price: order.stop_price,
+            display_quantity: 0,
         }
         .encode();
         let buf = [[0, 0, 0, 0, 0, 0, 0, 0, 4].as_slice(), buf.as_slice()].concat();
@@ -209,20 +463,14 @@ mod test {
             Instrument::new_fast(BOOK_ID, instruments::instrument::InstrumentType::Share);
         let order = Order::new(
             1001,
-            Rc::new(RefCell::new(instrument)),
+            Arc::new(RwLock::new(instrument)),
             123,
             100,
             order::Side::Bid,
             order::OrderType::Day,
-            100,
-            10001,
-        );
-        let target = MBOOepDisseminator {
-            socket: super::MockSocket {
-                buffer: RefCell::new(vec![]),
-            },
-            seq: Cell::new(0),
-        };
+        )
+        .with_session(100, 10001);
+        let target = new_target();
 
         let v = target.send_cancel_order(&order);
         assert!(v.is_ok());
@@ -230,10 +478,11 @@ mod test {
         let buf = oep::cancel::Cancel {
             participant: order.participant,
             order_id: order.get_id(),
-            book_id: order.instrument.borrow().get_id(),
+            book_id: order.instrument.read().unwrap().get_id(),
             side: Side::Bid.into(),
             gateway_id: 0,
             session_id: 0,
+            orig_client_order_id: 0,
         }
         .encode();
         let buf = [[0, 0, 0, 0, 0, 0, 0, 0, 6].as_slice(), buf.as_slice()].concat();
@@ -248,20 +497,14 @@ mod test {
             Instrument::new_fast(BOOK_ID, instruments::instrument::InstrumentType::Share);
         let order = Order::new(
             1001,
-            Rc::new(RefCell::new(instrument)),
+            Arc::new(RwLock::new(instrument)),
             123,
             100,
             order::Side::Bid,
             order::OrderType::Day,
-            100,
-            1001,
-        );
-        let target = MBOOepDisseminator {
-            socket: super::MockSocket {
-                buffer: RefCell::new(vec![]),
-            },
-            seq: Cell::new(0),
-        };
+        )
+        .with_session(100, 1001);
+        let target = new_target();
 
         let v = target.send_modify_order(&order);
         assert!(v.is_ok());
@@ -269,12 +512,13 @@ mod test {
         let buf = oep::modify::Modify {
             participant: order.participant,
             order_id: order.get_id(),
-            book_id: order.instrument.borrow().get_id(),
+            book_id: order.instrument.read().unwrap().get_id(),
             side: Side::Bid.into(),
             gateway_id: 0,
             session_id: 0,
             quantity: order.quantity,
             price: order.price,
+            orig_client_order_id: 0,
         }
         .encode();
         let buf = [[0, 0, 0, 0, 0, 0, 0, 0, 5].as_slice(), buf.as_slice()].concat();
@@ -289,20 +533,14 @@ mod test {
             Instrument::new_fast(BOOK_ID, instruments::instrument::InstrumentType::Share);
         let order = Order::new(
             1001,
-            Rc::new(RefCell::new(instrument)),
+            Arc::new(RwLock::new(instrument)),
             123,
             100,
             order::Side::Bid,
             order::OrderType::Day,
-            100,
-            1001,
-        );
-        let target = MBOOepDisseminator {
-            socket: super::MockSocket {
-                buffer: RefCell::new(vec![]),
-            },
-            seq: Cell::new(0),
-        };
+        )
+        .with_session(100, 1001);
+        let target = new_target();
 
         for s in 0..10 {
             assert!(target.send_modify_order(&order).is_ok());
@@ -313,6 +551,35 @@ mod test {
         }
     }
 
+    #[test]
+    fn muted_and_resumed() {
+        let order = Order::new(
+            1001,
+            Arc::new(RwLock::new(Instrument::new_fast(
+                444,
+                instruments::instrument::InstrumentType::Share,
+            ))),
+            123,
+            100,
+            order::Side::Bid,
+            order::OrderType::Day,
+        )
+        .with_session(100, 1001);
+        let mut target = new_target();
+        target.set_muted(true);
+        assert!(target.send_new_order(&order).is_ok());
+        assert!(target.socket.buffer.borrow().is_empty());
+        assert_eq!(0, target.get_sequence());
+
+        // going on where a previous run stopped
+        target.set_muted(false);
+        target.set_sequence(40);
+        assert!(target.send_new_order(&order).is_ok());
+        let v = target.socket.buffer.borrow().clone();
+        assert_eq!(40, u64::from_le_bytes(v[0..8].try_into().unwrap()));
+        assert_eq!(41, target.get_sequence());
+    }
+
     #[test]
     fn send_instrument() {
         let instrument = Instrument::new(
@@ -324,17 +591,12 @@ mod test {
             20,
         );
 
-        let target = MBOOepDisseminator {
-            socket: super::MockSocket {
-                buffer: RefCell::new(vec![]),
-            },
-            seq: Cell::new(0),
-        };
+        let target = new_target();
         assert!(target.send_instrument_info(&instrument).is_ok());
-        assert_eq!((8 + 1 + (12 + 3)) * 1, target.socket.buffer.borrow().len());
+        assert_eq!(8 + 1 + (63 + 3), target.socket.buffer.borrow().len());
 
         let decoded_instrument = Instrument::decode(
-            target.socket.buffer.borrow().clone()[9..24]
+            target.socket.buffer.borrow().clone()[9..75]
                 .try_into()
                 .expect("cannot convert"),
         );
@@ -351,4 +613,331 @@ mod test {
         assert_eq!(20, decoded_instrument.get_percentage_variation_allowed());
         assert_eq!("XYZ", decoded_instrument.get_name());
     }
+
+    #[test]
+    fn send_book_checksum() {
+        let checksum = BookChecksum::new(444, &[(100, 10)], &[(101, 20)], 10);
+        let target = new_target();
+        assert!(target.send_book_checksum(&checksum).is_ok());
+
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(8 + 1 + BOOKCHECKSUM_SIZE, buf.len());
+        assert_eq!(7, buf[8]);
+        assert_eq!(checksum, BookChecksum::decode(&buf[9..]).unwrap());
+    }
+
+    #[test]
+    fn send_eod_summary() {
+        let summary = oep::eodsummary::EodSummary {
+            book_id: 444,
+            closing_price: 1000,
+            volume: 500,
+            trade_count: 3,
+        };
+        let target = new_target();
+        assert!(target.send_eod_summary(&summary).is_ok());
+
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(8 + 1 + oep::eodsummary::EODSUMMARY_SIZE, buf.len());
+        assert_eq!(8, buf[8]);
+        assert_eq!(summary.encode().as_slice(), &buf[9..]);
+    }
+
+    #[test]
+    fn send_auction_info() {
+        let info = oep::auctioninfo::AuctionInfo {
+            book_id: 444,
+            price: 1000,
+            volume: 500,
+        };
+        let target = new_target();
+        assert!(target.send_auction_info(&info).is_ok());
+
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(8 + 1 + oep::auctioninfo::AUCTIONINFO_SIZE, buf.len());
+        assert_eq!(9, buf[8]);
+        assert_eq!(info.encode().as_slice(), &buf[9..]);
+    }
+
+    #[test]
+    fn sent_packets_are_cached() {
+        let info = oep::auctioninfo::AuctionInfo {
+            book_id: 444,
+            price: 1000,
+            volume: 500,
+        };
+        let cache = Arc::new(Mutex::new(RecoveryCache::new(10)));
+        let mut target = new_target();
+        target.set_recovery_cache(cache.clone());
+        assert!(target.send_auction_info(&info).is_ok());
+        assert!(target.send_auction_info(&info).is_ok());
+
+        let request = utils::recovery::RecoveryRequest {
+            from_seq: 1,
+            count: 1,
+        };
+        let cache = cache.lock().unwrap();
+        let cached: Vec<&[u8]> = cache.get(&request).collect();
+        assert_eq!(1, cached.len());
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(&buf[buf.len() / 2..], cached[0]);
+        assert_eq!(Some(1), utils::recovery::packet_seq(cached[0]));
+    }
+
+    #[test]
+    fn send_snapshot_header() {
+        let header = SnapshotHeader {
+            book_id: 444,
+            next_seq: 12,
+            order_count: 3,
+        };
+        let target = new_target();
+        target.seq.set(7);
+        assert_eq!(7, target.get_sequence());
+        assert!(target.send_snapshot_header(&header).is_ok());
+        assert_eq!(8, target.get_sequence());
+
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(8 + 1 + SNAPSHOT_HEADER_SIZE, buf.len());
+        assert_eq!(10, buf[8]);
+        assert_eq!(header, SnapshotHeader::decode(&buf[9..]).unwrap());
+    }
+
+    #[test]
+    fn batches_up_to_the_mtu() {
+        let info = oep::auctioninfo::AuctionInfo {
+            book_id: 444,
+            price: 1000,
+            volume: 500,
+        };
+        let message_len = 1 + oep::auctioninfo::AUCTIONINFO_SIZE;
+        let mut target = new_target();
+        // room for two auction infos
+        target.set_mtu(8 + 1 + 2 + 2 * (2 + message_len));
+        for _ in 0..3 {
+            assert_eq!(message_len, target.send_auction_info(&info).unwrap());
+        }
+        // the first two went out together, the third one is still held back
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(1, target.get_sequence());
+        let (seq, messages) = split(&buf).unwrap();
+        assert_eq!(0, seq);
+        assert_eq!(2, messages.len());
+        assert_eq!(9, messages[1][0]);
+        assert_eq!(info.encode().as_slice(), &messages[1][1..]);
+
+        assert!(target.flush_expired(Duration::from_secs(60)).is_ok());
+        assert_eq!(1, target.get_sequence());
+        assert!(target.flush_expired(Duration::ZERO).is_ok());
+        assert_eq!(2, target.get_sequence());
+        // alone in its datagram, so sent as is
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(9, buf[buf.len() - message_len]);
+        assert_eq!(0, target.flush().unwrap());
+    }
+
+    #[test]
+    fn trades_are_flushed() {
+        let mut target = new_target();
+        target.set_mtu(1400);
+        let instrument = Instrument::new_fast(1, instruments::instrument::InstrumentType::Share);
+        let order = Order::new(
+            1001,
+            Arc::new(RwLock::new(instrument)),
+            100,
+            10,
+            Side::Bid,
+            order::OrderType::Day,
+        )
+        .with_session(100, 1001);
+        assert!(target.send_new_order(&order).is_ok());
+        assert!(target.socket.buffer.borrow().is_empty());
+
+        let trade = oep::trade::Trade {
+            bid_order_id: 1,
+            ask_order_id: 2,
+            price: 100,
+            quantity: 10,
+            book_id: 1,
+            trade_id: 1,
+            timestamp: 0,
+            aggressor_side: 0,
+        };
+        assert!(target.send_trade(&trade).is_ok());
+        let buf = target.socket.buffer.borrow().clone();
+        let (_, messages) = split(&buf).unwrap();
+        assert_eq!(
+            vec![4, 3],
+            messages.iter().map(|m| m[0]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn retries_on_backpressure() {
+        let info = oep::auctioninfo::AuctionInfo {
+            book_id: 444,
+            price: 1000,
+            volume: 500,
+        };
+        let datagram_len = 8 + 1 + oep::auctioninfo::AUCTIONINFO_SIZE;
+        let target = new_target();
+        target.socket.would_block.set(true);
+        for _ in 0..MAX_PENDING_DATAGRAMS {
+            assert_eq!(datagram_len, target.send_auction_info(&info).unwrap());
+        }
+        // the retry buffer is full, the message is refused without using a sequence
+        assert_eq!(
+            Err(DisseminateError::WouldBlock),
+            target.send_auction_info(&info)
+        );
+        assert_eq!(MAX_PENDING_DATAGRAMS as u64, target.get_sequence());
+        assert!(target.socket.buffer.borrow().is_empty());
+
+        target.socket.would_block.set(false);
+        assert_eq!(
+            MAX_PENDING_DATAGRAMS * datagram_len,
+            target.flush_expired(Duration::from_secs(60)).unwrap()
+        );
+        assert!(target.send_auction_info(&info).is_ok());
+        // all sent, in order
+        let buf = target.socket.buffer.borrow().clone();
+        for (seq, datagram) in buf.chunks(datagram_len).enumerate() {
+            assert_eq!(Some(seq as u64), utils::recovery::packet_seq(datagram));
+        }
+    }
+
+    #[test]
+    fn b_feed() {
+        let info = oep::auctioninfo::AuctionInfo {
+            book_id: 444,
+            price: 1000,
+            volume: 500,
+        };
+        let mut target = new_target();
+        target.set_mtu(1400);
+        target.set_b_feed("127.0.0.1", 25001);
+        assert!(target.send_auction_info(&info).is_ok());
+        assert!(target.flush().is_ok());
+        assert!(target.send_auction_info(&info).is_ok());
+        assert!(target.flush().is_ok());
+        // same datagrams, same sequence
+        let b_socket = target.b_socket.as_ref().unwrap();
+        assert_eq!(*target.socket.buffer.borrow(), *b_socket.buffer.borrow());
+        assert_eq!(2, target.get_sequence());
+
+        // a B feed falling behind doesn't hold the A feed back
+        b_socket.would_block.set(true);
+        assert!(target.send_auction_info(&info).is_ok());
+        assert!(target.flush().is_ok());
+        assert!(target.socket.buffer.borrow().len() > b_socket.buffer.borrow().len());
+        assert!(target.pending.borrow().is_empty());
+    }
+
+    #[test]
+    fn too_big_for_a_datagram() {
+        let instrument = Instrument::new(
+            1,
+            &"X".repeat(super::MAX_DATAGRAM_SIZE),
+            instruments::instrument::InstrumentType::Share,
+            instruments::instrument::InstrumentState::Trading,
+            10,
+            20,
+        );
+        let target = new_target();
+        assert_eq!(
+            Err(DisseminateError::Encode),
+            target.send_instrument_info(&instrument)
+        );
+        assert_eq!(0, target.get_sequence());
+    }
+
+    #[test]
+    fn send_statistics() {
+        let statistics = oep::statistics::Statistics {
+            book_id: 444,
+            last_price: 1010,
+            open: 1000,
+            high: 1020,
+            low: 990,
+            close: 0,
+            volume: 300,
+            vwap: 1005,
+            trade_count: 4,
+        };
+        let target = new_target();
+        assert!(target.send_statistics(&statistics).is_ok());
+
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(8 + 1 + oep::statistics::STATISTICS_SIZE, buf.len());
+        assert_eq!(12, buf[8]);
+        assert_eq!(statistics.encode().as_slice(), &buf[9..]);
+    }
+
+    #[test]
+    fn send_state_change() {
+        let change = oep::statechange::InstrumentStateChange {
+            book_id: 444,
+            timestamp: 1000,
+            previous_state: 0,
+            state: 2,
+            reason: oep::statechange::StateChangeReason::PriceVariation.into(),
+        };
+        let target = new_target();
+        assert!(target.send_state_change(&change).is_ok());
+
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(8 + 1 + oep::statechange::STATECHANGE_SIZE, buf.len());
+        assert_eq!(13, buf[8]);
+        assert_eq!(change.encode().as_slice(), &buf[9..]);
+    }
+
+    #[test]
+    fn send_segment_state() {
+        let state = oep::segmentstate::SegmentState {
+            segment: 2,
+            timestamp: 1000,
+            state: 3,
+            reason: oep::statechange::StateChangeReason::Clearing.into(),
+        };
+        let target = new_target();
+        assert!(target.send_segment_state(&state).is_ok());
+
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(8 + 1 + oep::segmentstate::SEGMENTSTATE_SIZE, buf.len());
+        assert_eq!(15, buf[8]);
+        assert_eq!(state.encode().as_slice(), &buf[9..]);
+    }
+
+    #[test]
+    fn send_delisting() {
+        let delisting = oep::delisting::InstrumentDelisted {
+            book_id: 444,
+            timestamp: 1000,
+        };
+        let target = new_target();
+        assert!(target.send_delisting(&delisting).is_ok());
+
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(8 + 1 + oep::delisting::DELISTING_SIZE, buf.len());
+        assert_eq!(16, buf[8]);
+        assert_eq!(delisting.encode().as_slice(), &buf[9..]);
+    }
+
+    #[test]
+    fn send_trade_bust() {
+        let bust = oep::tradebust::TradeBust {
+            book_id: 444,
+            trade_id: 7,
+            price: 1000,
+            quantity: 100,
+            timestamp: 2000,
+        };
+        let target = new_target();
+        assert!(target.send_trade_bust(&bust).is_ok());
+
+        let buf = target.socket.buffer.borrow().clone();
+        assert_eq!(8 + 1 + oep::tradebust::TRADEBUST_SIZE, buf.len());
+        assert_eq!(14, buf[8]);
+        assert_eq!(bust.encode().as_slice(), &buf[9..]);
+    }
 }
@@ -1,10 +1,21 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 
+use oep::auctioninfo::AuctionInfo;
+use oep::delisting::InstrumentDelisted;
+use oep::eodsummary::EodSummary;
+use oep::segmentstate::SegmentState;
+use oep::statechange::InstrumentStateChange;
+use oep::statistics::Statistics;
 use oep::trade::Trade;
+use oep::tradebust::TradeBust;
 use order::Order;
 
+use crate::checksum::BookChecksum;
 use crate::disseminator::Disseminator;
+use crate::error::DisseminateError;
+use crate::snapshot::SnapshotHeader;
 use instruments::instrument::Instrument;
+pub use utils::faults::{FailureMode, Faults};
 
 /// MockDisseminator used for the market unit tests
 #[derive(Debug)]
@@ -13,9 +24,31 @@ pub struct MockDisseminator {
     pub new_orders: RefCell<Vec<Order>>,
     pub modifies: RefCell<Vec<Order>>,
     pub trades: RefCell<Vec<Trade>>,
+    pub trade_busts: RefCell<Vec<TradeBust>>,
     pub instrument_info: RefCell<Vec<Instrument>>,
     // not really "market orders" but orders that can be used to reconstruct a market
     pub market_orders: RefCell<Vec<Order>>,
+    pub checksums: RefCell<Vec<BookChecksum>>,
+    pub eod_summaries: RefCell<Vec<EodSummary>>,
+    pub auction_infos: RefCell<Vec<AuctionInfo>>,
+    pub statistics: RefCell<Vec<Statistics>>,
+    pub state_changes: RefCell<Vec<InstrumentStateChange>>,
+    pub segment_states: RefCell<Vec<SegmentState>>,
+    pub delistings: RefCell<Vec<InstrumentDelisted>>,
+    pub snapshot_headers: RefCell<Vec<SnapshotHeader>>,
+    // returned by get_sequence, set by the tests
+    pub sequence: Cell<u64>,
+    // when set, the order and trade messages fail with it
+    pub failure: Cell<Option<DisseminateError>>,
+    // the sends failing as told, with Disconnected unless failure is set,
+    // and the sends counted, the failed ones included
+    pub faults: Faults,
+}
+
+impl Default for MockDisseminator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MockDisseminator {
@@ -25,40 +58,135 @@ impl MockDisseminator {
             new_orders: RefCell::new(vec![]),
             modifies: RefCell::new(vec![]),
             trades: RefCell::new(vec![]),
+            trade_busts: RefCell::new(vec![]),
             instrument_info: RefCell::new(vec![]),
             market_orders: RefCell::new(vec![]),
+            checksums: RefCell::new(vec![]),
+            eod_summaries: RefCell::new(vec![]),
+            auction_infos: RefCell::new(vec![]),
+            statistics: RefCell::new(vec![]),
+            state_changes: RefCell::new(vec![]),
+            segment_states: RefCell::new(vec![]),
+            delistings: RefCell::new(vec![]),
+            snapshot_headers: RefCell::new(vec![]),
+            sequence: Cell::new(0),
+            failure: Cell::new(None),
+            faults: Faults::default(),
+        }
+    }
+
+    fn check_failure(&self) -> Result<(), DisseminateError> {
+        self.check_faults()?;
+        match self.failure.get() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn check_faults(&self) -> Result<(), DisseminateError> {
+        match self.faults.on_call() {
+            true => Err(self.failure.get().unwrap_or(DisseminateError::Disconnected)),
+            false => Ok(()),
         }
     }
 }
 
 impl Disseminator for MockDisseminator {
-    fn send_cancel_order(&self, order: &order::Order) -> Result<usize, std::io::Error> {
+    fn send_cancel_order(&self, order: &order::Order) -> Result<usize, DisseminateError> {
+        self.check_failure()?;
         self.cancels.borrow_mut().push(order.clone());
         Ok(1)
     }
 
-    fn send_new_order(&self, order: &order::Order) -> Result<usize, std::io::Error> {
+    fn send_new_order(&self, order: &order::Order) -> Result<usize, DisseminateError> {
+        self.check_failure()?;
         self.new_orders.borrow_mut().push(order.clone());
         Ok(1)
     }
 
-    fn send_trade(&self, trade: &Trade) -> Result<usize, std::io::Error> {
-        self.trades.borrow_mut().push(trade.clone());
+    fn send_trade(&self, trade: &Trade) -> Result<usize, DisseminateError> {
+        self.check_failure()?;
+        self.trades.borrow_mut().push(*trade);
+        Ok(1)
+    }
+
+    fn send_trade_bust(&self, bust: &TradeBust) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
+        self.trade_busts.borrow_mut().push(*bust);
         Ok(1)
     }
 
-    fn send_modify_order(&self, order: &Order) -> Result<usize, std::io::Error> {
+    fn send_modify_order(&self, order: &Order) -> Result<usize, DisseminateError> {
+        self.check_failure()?;
         self.modifies.borrow_mut().push(order.clone());
         Ok(1)
     }
 
-    fn send_instrument_info(&self, instrument: &Instrument) -> Result<usize, std::io::Error> {
+    fn send_instrument_info(&self, instrument: &Instrument) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
         self.instrument_info.borrow_mut().push(instrument.clone());
         Ok(1)
     }
 
-    fn send_market_order(&self, order: &Order) -> Result<usize, std::io::Error> {
+    fn send_market_order(&self, order: &Order) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
         self.market_orders.borrow_mut().push(order.clone());
         Ok(1)
     }
+
+    fn send_book_checksum(&self, checksum: &BookChecksum) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
+        self.checksums.borrow_mut().push(*checksum);
+        Ok(1)
+    }
+
+    fn send_eod_summary(&self, summary: &EodSummary) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
+        self.eod_summaries.borrow_mut().push(*summary);
+        Ok(1)
+    }
+
+    fn send_auction_info(&self, info: &AuctionInfo) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
+        self.auction_infos.borrow_mut().push(*info);
+        Ok(1)
+    }
+
+    fn send_statistics(&self, statistics: &Statistics) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
+        self.statistics.borrow_mut().push(*statistics);
+        Ok(1)
+    }
+
+    fn send_state_change(&self, change: &InstrumentStateChange) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
+        self.state_changes.borrow_mut().push(*change);
+        Ok(1)
+    }
+
+    fn send_segment_state(&self, state: &SegmentState) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
+        self.segment_states.borrow_mut().push(*state);
+        Ok(1)
+    }
+
+    fn send_delisting(&self, delisting: &InstrumentDelisted) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
+        self.delistings.borrow_mut().push(*delisting);
+        Ok(1)
+    }
+
+    fn send_snapshot_header(&self, header: &SnapshotHeader) -> Result<usize, DisseminateError> {
+        self.check_faults()?;
+        self.snapshot_headers.borrow_mut().push(*header);
+        Ok(1)
+    }
+
+    fn get_sequence(&self) -> u64 {
+        self.sequence.get()
+    }
+
+    fn flush(&self) -> Result<usize, DisseminateError> {
+        Ok(0)
+    }
 }
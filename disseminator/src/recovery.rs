@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use utils::recovery::{encode_response, RecoveryRequest, RECOVERY_REQUEST_SIZE};
+
+/// Upper limit of the packets sent back for a single request
+pub const MAX_RECOVERY_COUNT: u32 = 1000;
+
+/// The last packets sent on the feed, kept around for retransmission
+#[derive(Debug)]
+pub struct RecoveryCache {
+    capacity: usize,
+    // (sequence, packet as sent), the sequences are consecutive
+    packets: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl RecoveryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            packets: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, seq: u64, packet: Vec<u8>) {
+        if self.packets.len() == self.capacity {
+            self.packets.pop_front();
+        }
+        self.packets.push_back((seq, packet));
+    }
+
+    /// The cached packets of @request, starting with the first one still cached
+    pub fn get(&self, request: &RecoveryRequest) -> impl ExactSizeIterator<Item = &[u8]> + '_ {
+        let first = self.packets.front().map(|p| p.0).unwrap_or_default();
+        let end = request
+            .from_seq
+            .saturating_add(request.count.min(MAX_RECOVERY_COUNT) as u64);
+        let skip = request.from_seq.saturating_sub(first) as usize;
+        let take = end.saturating_sub(first.max(request.from_seq)) as usize;
+        self.packets
+            .range(skip.min(self.packets.len())..)
+            .take(take)
+            .map(|p| p.1.as_slice())
+    }
+}
+
+/// Serves the retransmission requests of the feed consumers, over TCP
+///
+/// Nothing blocks: @poll is meant to be called from the main loop of the engine
+#[derive(Debug)]
+pub struct RecoveryServer {
+    listener: TcpListener,
+    cache: Arc<Mutex<RecoveryCache>>,
+    // the consumers, with the bytes of their incomplete requests
+    clients: Vec<(TcpStream, Vec<u8>)>,
+}
+
+impl RecoveryServer {
+    pub fn new(addr: &str, port: u16, cache: Arc<Mutex<RecoveryCache>>) -> io::Result<Self> {
+        let listener = TcpListener::bind((addr, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            cache,
+            clients: vec![],
+        })
+    }
+
+    pub fn local_port(&self) -> io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    /// Accepts the new consumers and answers their pending requests.
+    /// The consumers are disconnected on errors
+    pub fn poll(&mut self) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push((stream, vec![]));
+            }
+        }
+
+        let cache = self.cache.lock().unwrap();
+        self.clients.retain_mut(|(stream, buffer)| {
+            let mut chunk = [0; 1024];
+            loop {
+                match stream.read(&mut chunk) {
+                    Ok(0) => return false,
+                    Ok(r) => buffer.extend_from_slice(&chunk[..r]),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(_) => return false,
+                }
+            }
+            while buffer.len() >= RECOVERY_REQUEST_SIZE {
+                let request = RecoveryRequest::decode(buffer).unwrap();
+                buffer.drain(..RECOVERY_REQUEST_SIZE);
+                // the responses are small enough for the socket buffer
+                if stream
+                    .write_all(&encode_response(cache.get(&request)))
+                    .is_err()
+                {
+                    return false;
+                }
+            }
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use utils::recovery::{request_retransmission, RecoveryRequest};
+
+    use super::{RecoveryCache, RecoveryServer};
+
+    fn request(from_seq: u64, count: u32) -> RecoveryRequest {
+        RecoveryRequest { from_seq, count }
+    }
+
+    #[test]
+    fn cache_keeps_the_last_packets() {
+        let mut target = RecoveryCache::new(3);
+        for seq in 0..5 {
+            target.push(seq, vec![seq as u8]);
+        }
+        let get = |r| target.get(&r).map(|p| p.to_vec()).collect::<Vec<_>>();
+        assert_eq!(vec![vec![2], vec![3], vec![4]], get(request(0, 10)));
+        assert_eq!(vec![vec![3]], get(request(3, 1)));
+        assert_eq!(vec![vec![2]], get(request(1, 2)));
+        assert!(get(request(0, 2)).is_empty());
+        assert!(get(request(5, 2)).is_empty());
+    }
+
+    #[test]
+    fn serve_requests() {
+        let cache = Arc::new(Mutex::new(RecoveryCache::new(10)));
+        for seq in 0..5 {
+            cache.lock().unwrap().push(seq, vec![seq as u8; 3]);
+        }
+        let mut target = RecoveryServer::new("127.0.0.1", 0, cache).unwrap();
+        let addr = format!("127.0.0.1:{}", target.local_port().unwrap());
+
+        let client = thread::spawn(move || request_retransmission(&addr, request(2, 2)));
+        while !client.is_finished() {
+            target.poll();
+        }
+        assert_eq!(
+            vec![vec![2; 3], vec![3; 3]],
+            client.join().unwrap().unwrap()
+        );
+    }
+}
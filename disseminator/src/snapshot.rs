@@ -0,0 +1,83 @@
+//! Book snapshots are published on their own channel, apart from the incremental
+//! feed, so that a late joiner can rebuild the books without replaying the day.
+//!
+//! Every snapshot starts with a header, followed by the instrument message and by
+//! `order_count` market messages. The header carries the sequence of the next
+//! incremental message, the first one not reflected in the snapshot, so that a
+//! consumer knows which of the buffered incrementals to apply on top of it.
+//!
+//! # Example:
+//!
+//! ```
+//! # use disseminator::snapshot::SnapshotHeader;
+//! let header = SnapshotHeader {
+//!     book_id: 1000,
+//!     next_seq: 42,
+//!     order_count: 2,
+//! };
+//! let received = SnapshotHeader::decode(&header.encode()).unwrap();
+//!
+//! // the incrementals buffered before the snapshot are dropped or applied
+//! assert!(!received.includes(41));
+//! assert!(received.includes(42));
+//! ```
+
+use oep::decoder::DecodeError;
+
+pub const SNAPSHOT_HEADER_SIZE: usize = 8 + 8 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub book_id: u64,
+    // sequence of the first incremental message not reflected in the snapshot
+    pub next_seq: u64,
+    // number of market messages following the instrument message
+    pub order_count: u32,
+}
+
+impl SnapshotHeader {
+    /// Whether the incremental message with @seq has to be applied on top of
+    /// the snapshot. The older ones are already part of it
+    pub fn includes(&self, seq: u64) -> bool {
+        seq >= self.next_seq
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut r = Vec::with_capacity(SNAPSHOT_HEADER_SIZE);
+        r.extend_from_slice(&self.book_id.to_le_bytes());
+        r.extend_from_slice(&self.next_seq.to_le_bytes());
+        r.extend_from_slice(&self.order_count.to_le_bytes());
+        r
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() < SNAPSHOT_HEADER_SIZE {
+            return Err(DecodeError);
+        }
+        Ok(Self {
+            book_id: u64::from_le_bytes(buf[0..8].try_into().map_err(|_| DecodeError)?),
+            next_seq: u64::from_le_bytes(buf[8..16].try_into().map_err(|_| DecodeError)?),
+            order_count: u32::from_le_bytes(buf[16..20].try_into().map_err(|_| DecodeError)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SnapshotHeader, SNAPSHOT_HEADER_SIZE};
+
+    #[test]
+    fn encode_decode() {
+        let header = SnapshotHeader {
+            book_id: 0x0102030405060708,
+            next_seq: 300,
+            order_count: 5,
+        };
+        let encoded = header.encode();
+        assert_eq!(SNAPSHOT_HEADER_SIZE, encoded.len());
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1, 44, 1], encoded[0..10]);
+        assert_eq!([5, 0, 0, 0], encoded[16..20]);
+        assert_eq!(header, SnapshotHeader::decode(&encoded).unwrap());
+        assert!(SnapshotHeader::decode(&encoded[..SNAPSHOT_HEADER_SIZE - 1]).is_err());
+    }
+}
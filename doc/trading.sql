@@ -31,19 +31,122 @@ CREATE TABLE public.instrument (
     state smallint,
     percentage_bands smallint,
     percentage_variation_allowed smallint,
-    active smallint DEFAULT 1
+    segment integer DEFAULT 0 NOT NULL,
+    expiry timestamp with time zone,
+    strike bigint,
+    underlying bigint,
+    active smallint DEFAULT 1,
+    updated_at timestamp with time zone DEFAULT now()
 );
 
 
 ALTER TABLE public.instrument OWNER TO postgres;
 
+--
+-- Name: eod_summary; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.eod_summary (
+    instrument_id bigint,
+    trading_day date,
+    closing_price bigint,
+    volume bigint,
+    trade_count bigint
+);
+
+
+ALTER TABLE public.eod_summary OWNER TO postgres;
+
+--
+-- Name: risk_limits; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.risk_limits (
+    participant bigint,
+    max_quantity bigint DEFAULT 0,
+    max_notional bigint DEFAULT 0,
+    max_open_orders integer DEFAULT 0
+);
+
+
+ALTER TABLE public.risk_limits OWNER TO postgres;
+
+--
+-- Name: entitlements; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.entitlements (
+    participant bigint,
+    book_id bigint
+);
+
+
+ALTER TABLE public.entitlements OWNER TO postgres;
+
+--
+-- Name: pending_reports; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.pending_reports (
+    id bigserial,
+    session_id integer,
+    message bytea
+);
+
+
+ALTER TABLE public.pending_reports OWNER TO postgres;
+
+--
+-- Name: order_events; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.order_events (
+    id bigserial,
+    recorded_at timestamp with time zone DEFAULT now(),
+    participant bigint,
+    order_id bigint,
+    submitted_order_id bigint,
+    book_id bigint,
+    quantity bigint,
+    price bigint,
+    side smallint,
+    state smallint,
+    gateway_id smallint,
+    session_id integer,
+    filled_quantity bigint,
+    leaves_quantity bigint,
+    orig_order_id bigint,
+    reject_reason smallint,
+    trade_id bigint
+);
+
+
+ALTER TABLE public.order_events OWNER TO postgres;
+
+--
+-- Name: trades; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.trades (
+    book_id bigint,
+    trade_id bigint,
+    price bigint,
+    quantity bigint,
+    buyer bigint,
+    seller bigint,
+    traded_at bigint
+);
+
+
+ALTER TABLE public.trades OWNER TO postgres;
+
 --
 -- Name: users; Type: TABLE; Schema: public; Owner: postgres
 --
 
 CREATE TABLE public.users (
     username character varying(64),
-    password character varying(64),
+    password character varying(256),
     session_id integer,
     participant bigint,
     userttype integer
@@ -64,7 +167,7 @@ ALTER TABLE ONLY public.instrument
 -- Name: TABLE instrument; Type: ACL; Schema: public; Owner: postgres
 --
 
-GRANT SELECT ON TABLE public.instrument TO test;
+GRANT SELECT, INSERT, UPDATE ON TABLE public.instrument TO test;
 
 
 --
@@ -74,6 +177,43 @@ GRANT SELECT ON TABLE public.instrument TO test;
 GRANT SELECT ON TABLE public.users TO test;
 
 
+--
+-- Name: TABLE eod_summary; Type: ACL; Schema: public; Owner: postgres
+--
+
+GRANT SELECT, INSERT ON TABLE public.eod_summary TO test;
+
+
+--
+-- Name: TABLE risk_limits; Type: ACL; Schema: public; Owner: postgres
+--
+
+GRANT SELECT ON TABLE public.risk_limits TO test;
+
+
+--
+-- Name: TABLE pending_reports; Type: ACL; Schema: public; Owner: postgres
+--
+
+GRANT SELECT, INSERT, DELETE ON TABLE public.pending_reports TO test;
+GRANT USAGE ON SEQUENCE public.pending_reports_id_seq TO test;
+
+
+--
+-- Name: TABLE order_events; Type: ACL; Schema: public; Owner: postgres
+--
+
+GRANT SELECT, INSERT ON TABLE public.order_events TO test;
+GRANT USAGE ON SEQUENCE public.order_events_id_seq TO test;
+
+
+--
+-- Name: TABLE trades; Type: ACL; Schema: public; Owner: postgres
+--
+
+GRANT SELECT, INSERT ON TABLE public.trades TO test;
+
+
 --
 -- PostgreSQL database dump complete
 --
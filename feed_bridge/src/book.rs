@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, HashMap};
+
+use instruments::instrument::Instrument;
+use order::Side;
+
+use crate::message::FeedMessage;
+
+/// What changed in a book, as told to its subscribers
+#[derive(Debug, Clone, PartialEq)]
+pub enum BookEvent {
+    // the quantity now resting at @price, 0 once the level is gone
+    Level {
+        side: Side,
+        price: u64,
+        quantity: u64,
+    },
+    Trade {
+        price: u64,
+        quantity: u64,
+        aggressor_side: u8,
+    },
+    // the name or the state of the instrument
+    Instrument,
+    Auction {
+        price: u64,
+        volume: u64,
+    },
+    // the book was rebuilt from a snapshot
+    Synced,
+    // the book can't be trusted any more, until the next snapshot
+    Stale,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Resting {
+    side: Side,
+    price: u64,
+    quantity: u64,
+}
+
+/// The book of an instrument, rebuilt order by order from the feed
+#[derive(Debug, Clone)]
+pub struct Book {
+    instrument: Instrument,
+    orders: HashMap<u64, Resting>,
+    // price -> aggregated quantity
+    bids: BTreeMap<u64, u64>,
+    asks: BTreeMap<u64, u64>,
+}
+
+impl Book {
+    pub fn new(instrument: Instrument) -> Self {
+        Self {
+            instrument,
+            orders: HashMap::new(),
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        }
+    }
+
+    pub fn instrument(&self) -> &Instrument {
+        &self.instrument
+    }
+
+    /// The price levels of @side, the best one first
+    pub fn levels(&self, side: Side) -> Vec<(u64, u64)> {
+        match side {
+            Side::Bid => self.bids.iter().rev().map(|(p, q)| (*p, *q)).collect(),
+            Side::Ask => self.asks.iter().map(|(p, q)| (*p, *q)).collect(),
+        }
+    }
+
+    /// Applies @message to the book, returning what changed
+    pub fn apply(&mut self, message: &FeedMessage) -> Vec<BookEvent> {
+        match message {
+            FeedMessage::Instrument(instrument) => {
+                self.instrument = instrument.clone();
+                vec![BookEvent::Instrument]
+            }
+            FeedMessage::Order(order) => {
+                let mut r = self.remove(order.client_order_id);
+                r.push(self.add(
+                    order.client_order_id,
+                    Resting {
+                        side: order.side.into(),
+                        price: order.price,
+                        quantity: order.quantity,
+                    },
+                ));
+                r
+            }
+            FeedMessage::Modify(modify) => {
+                let mut r = self.remove(modify.order_id);
+                r.push(self.add(
+                    modify.order_id,
+                    Resting {
+                        side: modify.side.into(),
+                        price: modify.price,
+                        quantity: modify.quantity,
+                    },
+                ));
+                r
+            }
+            FeedMessage::Cancel(cancel) => self.remove(cancel.order_id),
+            FeedMessage::Trade(trade) => {
+                let mut r = vec![];
+                for order_id in [trade.bid_order_id, trade.ask_order_id] {
+                    if let Some(event) = self.execute(order_id, trade.quantity) {
+                        r.push(event);
+                    }
+                }
+                r.push(BookEvent::Trade {
+                    price: trade.price,
+                    quantity: trade.quantity,
+                    aggressor_side: trade.aggressor_side,
+                });
+                r
+            }
+            FeedMessage::Checksum(checksum) => {
+                match checksum.verify(&self.levels(Side::Bid), &self.levels(Side::Ask)) {
+                    true => vec![],
+                    false => vec![BookEvent::Stale],
+                }
+            }
+            FeedMessage::AuctionInfo(info) => vec![BookEvent::Auction {
+                price: info.price,
+                volume: info.volume,
+            }],
+            FeedMessage::SnapshotHeader(_) => vec![],
+        }
+    }
+
+    fn levels_of(&mut self, side: Side) -> &mut BTreeMap<u64, u64> {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+
+    /// Adds @quantity (possibly negative) to a level, returning what it became
+    fn change_level(&mut self, side: Side, price: u64, quantity: i128) -> BookEvent {
+        let levels = self.levels_of(side);
+        let level = levels.entry(price).or_default();
+        *level = (*level as i128 + quantity).max(0) as u64;
+        let quantity = *level;
+        if quantity == 0 {
+            levels.remove(&price);
+        }
+        BookEvent::Level {
+            side,
+            price,
+            quantity,
+        }
+    }
+
+    fn add(&mut self, order_id: u64, order: Resting) -> BookEvent {
+        self.orders.insert(order_id, order);
+        self.change_level(order.side, order.price, order.quantity as i128)
+    }
+
+    fn remove(&mut self, order_id: u64) -> Vec<BookEvent> {
+        match self.orders.remove(&order_id) {
+            Some(order) => {
+                vec![self.change_level(order.side, order.price, -(order.quantity as i128))]
+            }
+            None => vec![],
+        }
+    }
+
+    /// Takes @quantity off a resting order, None if it's not in the book
+    fn execute(&mut self, order_id: u64, quantity: u64) -> Option<BookEvent> {
+        let order = self.orders.get_mut(&order_id)?;
+        let executed = quantity.min(order.quantity);
+        order.quantity -= executed;
+        let order = *order;
+        if order.quantity == 0 {
+            self.orders.remove(&order_id);
+        }
+        Some(self.change_level(order.side, order.price, -(executed as i128)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use disseminator::checksum::{BookChecksum, BOOK_CHECKSUM_DEPTH};
+    use instruments::instrument::{Instrument, InstrumentType};
+    use oep::{cancel::Cancel, modify::Modify, neworder::NewOrder, trade::Trade};
+    use order::{OrderType, Side};
+
+    use super::{Book, BookEvent};
+    use crate::message::FeedMessage;
+
+    const BOOK_ID: u64 = 1000;
+
+    fn new_order(order_id: u64, side: Side, price: u64, quantity: u64) -> FeedMessage {
+        FeedMessage::Order(NewOrder {
+            client_order_id: order_id,
+            participant: 0,
+            book_id: BOOK_ID,
+            quantity,
+            price,
+            order_type: OrderType::Day.into(),
+            side: side.into(),
+            gateway_id: 0,
+            session_id: 0,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        })
+    }
+
+    fn level(side: Side, price: u64, quantity: u64) -> BookEvent {
+        BookEvent::Level {
+            side,
+            price,
+            quantity,
+        }
+    }
+
+    #[test]
+    fn orders() {
+        let mut target = Book::new(Instrument::new_fast(BOOK_ID, InstrumentType::Share));
+        assert_eq!(
+            vec![level(Side::Bid, 100, 10)],
+            target.apply(&new_order(1, Side::Bid, 100, 10))
+        );
+        target.apply(&new_order(2, Side::Bid, 100, 5));
+        target.apply(&new_order(3, Side::Bid, 101, 7));
+        target.apply(&new_order(4, Side::Ask, 103, 8));
+        assert_eq!(vec![(101, 7), (100, 15)], target.levels(Side::Bid));
+        assert_eq!(vec![(103, 8)], target.levels(Side::Ask));
+
+        // a price change is a cancel followed by a new order, a quantity
+        // change a modify
+        let modify = FeedMessage::Modify(Modify {
+            participant: 0,
+            order_id: 2,
+            book_id: BOOK_ID,
+            quantity: 3,
+            price: 100,
+            side: Side::Bid.into(),
+            gateway_id: 0,
+            session_id: 0,
+            orig_client_order_id: 0,
+        });
+        assert_eq!(
+            vec![level(Side::Bid, 100, 10), level(Side::Bid, 100, 13)],
+            target.apply(&modify)
+        );
+        let cancel = FeedMessage::Cancel(Cancel {
+            participant: 0,
+            order_id: 3,
+            book_id: BOOK_ID,
+            side: Side::Bid.into(),
+            gateway_id: 0,
+            session_id: 0,
+            orig_client_order_id: 0,
+        });
+        assert_eq!(vec![level(Side::Bid, 101, 0)], target.apply(&cancel));
+        assert_eq!(vec![(100, 13)], target.levels(Side::Bid));
+        // already gone
+        assert!(target.apply(&cancel).is_empty());
+    }
+
+    #[test]
+    fn trades() {
+        let mut target = Book::new(Instrument::new_fast(BOOK_ID, InstrumentType::Share));
+        target.apply(&new_order(1, Side::Ask, 100, 10));
+        // the aggressor, not in the book, traded 4 against the order 1
+        let trade = FeedMessage::Trade(Trade {
+            bid_order_id: 2,
+            ask_order_id: 1,
+            price: 100,
+            quantity: 4,
+            book_id: BOOK_ID,
+            trade_id: 1,
+            timestamp: 0,
+            aggressor_side: Side::Bid.into(),
+        });
+        assert_eq!(
+            vec![
+                level(Side::Ask, 100, 6),
+                BookEvent::Trade {
+                    price: 100,
+                    quantity: 4,
+                    aggressor_side: Side::Bid.into()
+                }
+            ],
+            target.apply(&trade)
+        );
+        target.apply(&trade);
+        assert_eq!(level(Side::Ask, 100, 0), target.apply(&trade)[0]);
+        assert!(target.levels(Side::Ask).is_empty());
+    }
+
+    #[test]
+    fn checksums() {
+        let mut target = Book::new(Instrument::new_fast(BOOK_ID, InstrumentType::Share));
+        target.apply(&new_order(1, Side::Bid, 100, 10));
+        let mut reference = target.clone();
+        let checksum = |book: &Book| {
+            FeedMessage::Checksum(BookChecksum::new(
+                BOOK_ID,
+                &book.levels(Side::Bid),
+                &book.levels(Side::Ask),
+                BOOK_CHECKSUM_DEPTH,
+            ))
+        };
+        assert!(target.apply(&checksum(&reference)).is_empty());
+
+        // the engine saw an order the bridge missed
+        reference.apply(&new_order(2, Side::Ask, 101, 10));
+        assert_eq!(vec![BookEvent::Stale], target.apply(&checksum(&reference)));
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::{HashMap, VecDeque};
+
+use disseminator::{batch::split, snapshot::SnapshotHeader};
+
+use crate::{
+    book::{Book, BookEvent},
+    message::FeedMessage,
+};
+
+/// Upper limit of the incrementals kept for the books waiting for a snapshot
+pub const MAX_BUFFERED_MESSAGES: usize = 100000;
+
+/// Something that changed in the book @book_id, with the sequence of the
+/// incremental datagram behind it (0 for the snapshots)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub book_id: u64,
+    pub seq: u64,
+    pub event: BookEvent,
+}
+
+#[derive(Debug)]
+struct PendingSnapshot {
+    header: SnapshotHeader,
+    // created by the instrument message following the header
+    book: Option<Book>,
+    // the market messages still expected
+    remaining: u32,
+}
+
+/// The books of all the instruments, synchronized from the snapshot channel
+/// and kept up to date by the incremental feed, as described in
+/// doc/feed_protocol.md
+///
+/// The incrementals of a book are buffered until a snapshot of the book is
+/// complete, then the ones it doesn't reflect are applied on top of it. A gap
+/// in the incremental sequence or a wrong book checksum sends the books back
+/// to waiting for a snapshot.
+#[derive(Debug, Default)]
+pub struct Books {
+    // the books in sync
+    books: HashMap<u64, Book>,
+    snapshot: Option<PendingSnapshot>,
+    // (seq, message) of the books waiting for a snapshot, oldest first
+    buffered: VecDeque<(u64, FeedMessage)>,
+    // the oldest sequence the buffer still goes back to, None before the
+    // first incremental
+    buffered_from: Option<u64>,
+    // the same, for the books found diverging since
+    diverged_from: HashMap<u64, u64>,
+    // the incremental sequence expected next
+    next_seq: Option<u64>,
+}
+
+impl Books {
+    pub fn get(&self, book_id: u64) -> Option<&Book> {
+        self.books.get(&book_id)
+    }
+
+    /// The books in sync, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &Book> {
+        self.books.values()
+    }
+
+    /// Applies a datagram of the incremental feed
+    pub fn on_incremental(&mut self, datagram: &[u8]) -> Vec<Update> {
+        let Some((seq, messages)) = split(datagram) else {
+            return vec![];
+        };
+        let mut r = vec![];
+        match self.next_seq {
+            // late or duplicated
+            Some(next) if seq < next => return r,
+            Some(next) if seq > next => {
+                eprintln!("Lost the incrementals {next} to {}", seq - 1);
+                for book_id in self.books.keys() {
+                    r.push(Update {
+                        book_id: *book_id,
+                        seq,
+                        event: BookEvent::Stale,
+                    });
+                }
+                self.books.clear();
+                self.buffered.clear();
+                self.buffered_from = Some(seq);
+                self.diverged_from.clear();
+            }
+            None => self.buffered_from = Some(seq),
+            _ => {}
+        }
+        self.next_seq = Some(seq + 1);
+
+        for message in messages.iter().filter_map(|m| FeedMessage::decode(m)) {
+            let book_id = message.book_id();
+            let Some(book) = self.books.get_mut(&book_id) else {
+                self.buffer(seq, message);
+                continue;
+            };
+            for event in book.apply(&message) {
+                if event == BookEvent::Stale {
+                    eprintln!("Book {book_id} diverged from the one of the engine");
+                    self.books.remove(&book_id);
+                    // what came before the checksum in the datagram is lost
+                    self.diverged_from.insert(book_id, seq + 1);
+                    r.push(Update {
+                        book_id,
+                        seq,
+                        event,
+                    });
+                    break;
+                }
+                r.push(Update {
+                    book_id,
+                    seq,
+                    event,
+                });
+            }
+        }
+        r
+    }
+
+    /// Applies a datagram of the snapshot channel
+    pub fn on_snapshot(&mut self, datagram: &[u8]) -> Vec<Update> {
+        let Some((_, messages)) = split(datagram) else {
+            return vec![];
+        };
+        let mut r = vec![];
+        for message in messages.iter().filter_map(|m| FeedMessage::decode(m)) {
+            match (&message, &mut self.snapshot) {
+                (FeedMessage::SnapshotHeader(header), _) => {
+                    // the books in sync don't need it
+                    self.snapshot =
+                        (!self.books.contains_key(&header.book_id)).then_some(PendingSnapshot {
+                            header: *header,
+                            book: None,
+                            remaining: header.order_count,
+                        });
+                }
+                (FeedMessage::Instrument(instrument), Some(pending))
+                    if pending.header.book_id == instrument.get_id() && pending.book.is_none() =>
+                {
+                    pending.book = Some(Book::new(instrument.clone()));
+                }
+                (FeedMessage::Order(order), Some(pending))
+                    if pending.header.book_id == order.book_id && pending.remaining > 0 =>
+                {
+                    if let Some(book) = &mut pending.book {
+                        book.apply(&message);
+                        pending.remaining -= 1;
+                    }
+                }
+                _ => continue,
+            }
+            if let Some(update) = self.complete_snapshot() {
+                r.push(update);
+            }
+        }
+        r
+    }
+
+    /// Moves the snapshot being received to the books in sync, once complete
+    fn complete_snapshot(&mut self) -> Option<Update> {
+        let pending = self.snapshot.as_ref()?;
+        if pending.book.is_none() || pending.remaining > 0 {
+            return None;
+        }
+        let pending = self.snapshot.take()?;
+        let header = pending.header;
+        let mut book = pending.book?;
+        // the incrementals between the snapshot and the buffer are gone
+        let from = self
+            .buffered_from
+            .max(self.diverged_from.get(&header.book_id).copied());
+        if from.is_some_and(|from| from > header.next_seq) {
+            return None;
+        }
+        for (seq, message) in &self.buffered {
+            if message.book_id() == header.book_id && header.includes(*seq) {
+                book.apply(message);
+            }
+        }
+        self.buffered
+            .retain(|(_, message)| message.book_id() != header.book_id);
+        self.diverged_from.remove(&header.book_id);
+        self.books.insert(header.book_id, book);
+        Some(Update {
+            book_id: header.book_id,
+            seq: 0,
+            event: BookEvent::Synced,
+        })
+    }
+
+    fn buffer(&mut self, seq: u64, message: FeedMessage) {
+        if self.buffered.len() >= MAX_BUFFERED_MESSAGES {
+            if let Some((dropped, _)) = self.buffered.pop_front() {
+                self.buffered_from = Some(dropped + 1);
+            }
+        }
+        self.buffered.push_back((seq, message));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use disseminator::{
+        checksum::{BookChecksum, BOOK_CHECKSUM_DEPTH},
+        snapshot::SnapshotHeader,
+    };
+    use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+    use oep::{cancel::Cancel, decoder::Decoder, neworder::NewOrder};
+    use order::{OrderType, Side};
+
+    use super::{Books, Update};
+    use crate::book::BookEvent;
+
+    const BOOK_ID: u64 = 1000;
+
+    fn datagram(seq: u64, type_id: u8, value: &[u8]) -> Vec<u8> {
+        [seq.to_le_bytes().as_slice(), &[type_id], value].concat()
+    }
+
+    fn order(order_id: u64, price: u64) -> Vec<u8> {
+        NewOrder {
+            client_order_id: order_id,
+            participant: 0,
+            book_id: BOOK_ID,
+            quantity: 10,
+            price,
+            order_type: OrderType::Day.into(),
+            side: Side::Bid.into(),
+            gateway_id: 0,
+            session_id: 0,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        }
+        .encode()
+        .to_vec()
+    }
+
+    fn cancel(order_id: u64) -> Vec<u8> {
+        Cancel {
+            participant: 0,
+            order_id,
+            book_id: BOOK_ID,
+            side: Side::Bid.into(),
+            gateway_id: 0,
+            session_id: 0,
+            orig_client_order_id: 0,
+        }
+        .encode()
+        .to_vec()
+    }
+
+    /// Sends the snapshot of a book holding the order 1, taken before @next_seq
+    fn snapshot(target: &mut Books, next_seq: u64) -> Vec<Update> {
+        let header = SnapshotHeader {
+            book_id: BOOK_ID,
+            next_seq,
+            order_count: 1,
+        };
+        let instrument = Instrument::new(
+            BOOK_ID,
+            "ACME",
+            InstrumentType::Share,
+            InstrumentState::Trading,
+            0,
+            30,
+        );
+        let mut r = target.on_snapshot(&datagram(0, 10, &header.encode()));
+        r.extend(target.on_snapshot(&datagram(1, 1, &instrument.encode())));
+        r.extend(target.on_snapshot(&datagram(2, 2, &order(1, 100))));
+        r
+    }
+
+    fn synced() -> Vec<Update> {
+        vec![Update {
+            book_id: BOOK_ID,
+            seq: 0,
+            event: BookEvent::Synced,
+        }]
+    }
+
+    #[test]
+    fn synchronize() {
+        let mut target = Books::default();
+        // buffered until the snapshot, the first being reflected in it
+        assert!(target
+            .on_incremental(&datagram(5, 4, &order(1, 100)))
+            .is_empty());
+        assert!(target
+            .on_incremental(&datagram(6, 4, &order(2, 101)))
+            .is_empty());
+        assert!(target.get(BOOK_ID).is_none());
+
+        assert_eq!(synced(), snapshot(&mut target, 6));
+        let book = target.get(BOOK_ID).unwrap();
+        assert_eq!("ACME", book.instrument().get_name());
+        assert_eq!(vec![(101, 10), (100, 10)], book.levels(Side::Bid));
+
+        // then kept up to date
+        assert_eq!(
+            vec![Update {
+                book_id: BOOK_ID,
+                seq: 7,
+                event: BookEvent::Level {
+                    side: Side::Bid,
+                    price: 101,
+                    quantity: 0
+                }
+            }],
+            target.on_incremental(&datagram(7, 6, &cancel(2)))
+        );
+        // late
+        assert!(target
+            .on_incremental(&datagram(6, 6, &cancel(1)))
+            .is_empty());
+        // a snapshot of a book in sync is not needed
+        assert!(snapshot(&mut target, 8).is_empty());
+    }
+
+    #[test]
+    fn lost_incrementals() {
+        let mut target = Books::default();
+        target.on_incremental(&datagram(5, 4, &order(2, 101)));
+        // the buffer doesn't go back to the snapshot
+        assert!(snapshot(&mut target, 4).is_empty());
+        assert_eq!(synced(), snapshot(&mut target, 5));
+
+        // the books wait for the next snapshot after a gap
+        assert_eq!(
+            vec![Update {
+                book_id: BOOK_ID,
+                seq: 8,
+                event: BookEvent::Stale
+            }],
+            target.on_incremental(&datagram(8, 6, &cancel(2)))
+        );
+        assert!(target.get(BOOK_ID).is_none());
+        assert!(snapshot(&mut target, 7).is_empty());
+        assert_eq!(synced(), snapshot(&mut target, 8));
+        assert_eq!(
+            vec![(100, 10)],
+            target.get(BOOK_ID).unwrap().levels(Side::Bid)
+        );
+    }
+
+    #[test]
+    fn diverged() {
+        let mut target = Books::default();
+        assert_eq!(synced(), snapshot(&mut target, 0));
+        // the engine has no order
+        let checksum = BookChecksum::new(BOOK_ID, &[], &[], BOOK_CHECKSUM_DEPTH);
+        let updates = target.on_incremental(&datagram(0, 7, &checksum.encode()));
+        assert_eq!(BookEvent::Stale, updates[0].event);
+        assert!(target.get(BOOK_ID).is_none());
+
+        // the incrementals before the checksum are not buffered
+        assert!(snapshot(&mut target, 0).is_empty());
+        assert_eq!(synced(), snapshot(&mut target, 1));
+    }
+}
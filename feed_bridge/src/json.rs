@@ -0,0 +1,313 @@
+//! The JSON messages exchanged with the WebSocket clients
+//!
+//! The clients send flat JSON objects:
+//!
+//! ```text
+//! {"action": "list"}
+//! {"action": "subscribe", "book_id": 1000}
+//! {"action": "unsubscribe", "book_id": 1000}
+//! ```
+//!
+//! and get back objects telling their `type`, see doc/feed_bridge.md.
+//!
+//! # Example:
+//!
+//! ```
+//! # use feed_bridge::json::Command;
+//! let command = Command::parse(r#"{"action": "subscribe", "book_id": 1000}"#);
+//! assert_eq!(Some(Command::Subscribe(1000)), command);
+//! ```
+
+use instruments::instrument::{Instrument, InstrumentState};
+use oep::trade::NO_AGGRESSOR;
+use order::Side;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    book::{Book, BookEvent},
+    books::Update,
+};
+
+/// What a client asks for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    // the books in sync
+    List,
+    Subscribe(u64),
+    Unsubscribe(u64),
+}
+
+// a command as sent, the book id going with the subscriptions
+#[derive(Deserialize)]
+struct Request {
+    action: String,
+    book_id: Option<u64>,
+}
+
+impl Command {
+    /// None if @text is not a command
+    pub fn parse(text: &str) -> Option<Self> {
+        let request: Request = serde_json::from_str(text).ok()?;
+        match request.action.as_str() {
+            "list" => Some(Self::List),
+            "subscribe" => Some(Self::Subscribe(request.book_id?)),
+            "unsubscribe" => Some(Self::Unsubscribe(request.book_id?)),
+            _ => None,
+        }
+    }
+}
+
+// the fields telling an instrument, in the snapshots and the book lists
+#[derive(Serialize)]
+struct InstrumentFields<'a> {
+    book_id: u64,
+    name: &'a str,
+    state: &'static str,
+}
+
+impl<'a> From<&'a Instrument> for InstrumentFields<'a> {
+    fn from(instrument: &'a Instrument) -> Self {
+        Self {
+            book_id: instrument.get_id(),
+            name: instrument.get_name(),
+            state: state_name(instrument.get_state()),
+        }
+    }
+}
+
+/// What is sent to the clients, by its type
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message<'a> {
+    Snapshot {
+        #[serde(flatten)]
+        instrument: InstrumentFields<'a>,
+        // price, quantity
+        bids: Vec<(u64, u64)>,
+        asks: Vec<(u64, u64)>,
+    },
+    Books {
+        books: Vec<InstrumentFields<'a>>,
+    },
+    Error {
+        message: &'a str,
+    },
+    Level {
+        book_id: u64,
+        seq: u64,
+        side: &'static str,
+        price: u64,
+        quantity: u64,
+    },
+    Trade {
+        book_id: u64,
+        seq: u64,
+        price: u64,
+        quantity: u64,
+        aggressor: &'static str,
+    },
+    Instrument {
+        book_id: u64,
+        seq: u64,
+        name: &'a str,
+        state: &'static str,
+    },
+    Auction {
+        book_id: u64,
+        seq: u64,
+        price: u64,
+        volume: u64,
+    },
+    Stale {
+        book_id: u64,
+        seq: u64,
+    },
+}
+
+impl Message<'_> {
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("the messages serialize to JSON")
+    }
+}
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Bid => "bid",
+        Side::Ask => "ask",
+    }
+}
+
+fn state_name(state: InstrumentState) -> &'static str {
+    match state {
+        InstrumentState::Trading => "trading",
+        InstrumentState::Closed => "closed",
+        InstrumentState::Auction => "auction",
+        InstrumentState::Halted => "halted",
+        InstrumentState::PreOpen => "pre_open",
+    }
+}
+
+/// The whole of @book, sent on a subscription and when the book is rebuilt
+pub fn snapshot(book: &Book) -> String {
+    Message::Snapshot {
+        instrument: book.instrument().into(),
+        bids: book.levels(Side::Bid),
+        asks: book.levels(Side::Ask),
+    }
+    .to_json()
+}
+
+/// The answer to a List command
+pub fn list<'a>(books: impl Iterator<Item = &'a Book>) -> String {
+    let mut books: Vec<&Instrument> = books.map(|b| b.instrument()).collect();
+    books.sort_by_key(|i| i.get_id());
+    Message::Books {
+        books: books.into_iter().map(InstrumentFields::from).collect(),
+    }
+    .to_json()
+}
+
+pub fn error(message: &str) -> String {
+    Message::Error { message }.to_json()
+}
+
+/// @update, as sent to the subscribers of its book, None for the Synced ones
+/// which get a snapshot instead
+pub fn update(update: &Update, book: Option<&Book>) -> Option<String> {
+    let (book_id, seq) = (update.book_id, update.seq);
+    let message = match &update.event {
+        BookEvent::Level {
+            side,
+            price,
+            quantity,
+        } => Message::Level {
+            book_id,
+            seq,
+            side: side_name(*side),
+            price: *price,
+            quantity: *quantity,
+        },
+        BookEvent::Trade {
+            price,
+            quantity,
+            aggressor_side,
+        } => Message::Trade {
+            book_id,
+            seq,
+            price: *price,
+            quantity: *quantity,
+            aggressor: match *aggressor_side {
+                NO_AGGRESSOR => "none",
+                side => side_name(Side::from(side)),
+            },
+        },
+        BookEvent::Instrument => {
+            let instrument = book?.instrument();
+            Message::Instrument {
+                book_id,
+                seq,
+                name: instrument.get_name(),
+                state: state_name(instrument.get_state()),
+            }
+        }
+        BookEvent::Auction { price, volume } => Message::Auction {
+            book_id,
+            seq,
+            price: *price,
+            volume: *volume,
+        },
+        BookEvent::Stale => Message::Stale { book_id, seq },
+        BookEvent::Synced => return None,
+    };
+    Some(message.to_json())
+}
+
+#[cfg(test)]
+mod test {
+    use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+    use order::Side;
+
+    use super::{error, list, snapshot, update, Command};
+    use crate::{
+        book::{Book, BookEvent},
+        books::Update,
+    };
+
+    #[test]
+    fn commands() {
+        assert_eq!(Some(Command::List), Command::parse(r#"{"action":"list"}"#));
+        assert_eq!(
+            Some(Command::Unsubscribe(7)),
+            Command::parse(r#" { "book_id" : 7 , "action" : "unsubscribe" } "#)
+        );
+        for invalid in [
+            "",
+            "subscribe 7",
+            r#"{"action":"subscribe"}"#,
+            r#"{"action":"subscribe","book_id":"x"}"#,
+            r#"{"action":"buy","book_id":7}"#,
+        ] {
+            assert_eq!(None, Command::parse(invalid), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn render() {
+        let instrument = Instrument::new(
+            1000,
+            "AC\"ME",
+            InstrumentType::Share,
+            InstrumentState::Trading,
+            0,
+            30,
+        );
+        let book = Book::new(instrument);
+        assert_eq!(
+            r#"{"type":"snapshot","book_id":1000,"name":"AC\"ME","state":"trading","bids":[],"asks":[]}"#,
+            snapshot(&book)
+        );
+        assert_eq!(
+            r#"{"type":"books","books":[{"book_id":1000,"name":"AC\"ME","state":"trading"}]}"#,
+            list([&book].into_iter())
+        );
+        assert_eq!(
+            r#"{"type":"error","message":"Unknown command"}"#,
+            error("Unknown command")
+        );
+
+        let level = Update {
+            book_id: 1000,
+            seq: 12,
+            event: BookEvent::Level {
+                side: Side::Ask,
+                price: 101,
+                quantity: 0,
+            },
+        };
+        assert_eq!(
+            Some(String::from(
+                r#"{"type":"level","book_id":1000,"seq":12,"side":"ask","price":101,"quantity":0}"#
+            )),
+            update(&level, Some(&book))
+        );
+        let trade = Update {
+            event: BookEvent::Trade {
+                price: 101,
+                quantity: 5,
+                aggressor_side: 2,
+            },
+            ..level.clone()
+        };
+        assert_eq!(
+            Some(String::from(
+                r#"{"type":"trade","book_id":1000,"seq":12,"price":101,"quantity":5,"aggressor":"none"}"#
+            )),
+            update(&trade, Some(&book))
+        );
+        let synced = Update {
+            event: BookEvent::Synced,
+            ..level
+        };
+        assert_eq!(None, update(&synced, Some(&book)));
+    }
+}
@@ -0,0 +1,14 @@
+//! Market data for the browsers
+//!
+//! The bridge joins the multicast feed of the matching engine, rebuilds the
+//! book of every instrument from the snapshots and the incrementals, and
+//! serves them as JSON over WebSocket: a client subscribing to a book gets a
+//! snapshot of it, then the changes of its price levels and its trades as
+//! they come. See doc/feed_bridge.md.
+
+pub mod book;
+pub mod books;
+pub mod json;
+pub mod message;
+pub mod server;
+pub mod websocket;
@@ -0,0 +1,13 @@
+use anyhow::Result;
+use configparser::ini::Ini;
+use feed_bridge::server::{BridgeConfig, BridgeServer};
+
+fn main() -> Result<()> {
+    println!("Loading configuration file");
+    let mut config = Ini::new();
+    let config_map = config
+        .load("feed_bridge.ini")
+        .expect("Unable to load the configuration file");
+
+    BridgeServer::new().run(BridgeConfig::from_config(&config_map)?)
+}
@@ -0,0 +1,111 @@
+use disseminator::{checksum::BookChecksum, snapshot::SnapshotHeader};
+use instruments::instrument::{Instrument, INSTRUMENT_FIXED_SIZE};
+use oep::{
+    auctioninfo::AuctionInfo, cancel::Cancel, decoder::Decoder, modify::Modify, neworder::NewOrder,
+    trade::Trade,
+};
+
+/// The messages of the MBO feed a book is built from
+#[derive(Debug, Clone)]
+pub enum FeedMessage {
+    Instrument(Instrument),
+    // a new order on the incremental feed, or a resting order in a snapshot
+    Order(NewOrder),
+    Modify(Modify),
+    Cancel(Cancel),
+    Trade(Trade),
+    Checksum(BookChecksum),
+    AuctionInfo(AuctionInfo),
+    SnapshotHeader(SnapshotHeader),
+}
+
+impl FeedMessage {
+    /// Decodes @message, the Type ID followed by the Value, None for the
+    /// messages of no use to a book or not decoding
+    pub fn decode(message: &[u8]) -> Option<Self> {
+        let (type_id, value) = message.split_first()?;
+        Some(match type_id {
+            // Instrument::decode panics on a garbled message
+            1 if value.len() >= INSTRUMENT_FIXED_SIZE
+                && std::str::from_utf8(&value[INSTRUMENT_FIXED_SIZE..]).is_ok() =>
+            {
+                Self::Instrument(Instrument::decode(value))
+            }
+            2 | 4 => Self::Order(NewOrder::decode(value.try_into().ok()?).ok()?),
+            3 => Self::Trade(Trade::decode(value.try_into().ok()?).ok()?),
+            5 => Self::Modify(Modify::decode(value.try_into().ok()?).ok()?),
+            6 => Self::Cancel(Cancel::decode(value.try_into().ok()?).ok()?),
+            7 => Self::Checksum(BookChecksum::decode(value).ok()?),
+            9 => Self::AuctionInfo(AuctionInfo::decode(value.try_into().ok()?).ok()?),
+            10 => Self::SnapshotHeader(SnapshotHeader::decode(value).ok()?),
+            _ => return None,
+        })
+    }
+
+    pub fn book_id(&self) -> u64 {
+        match self {
+            Self::Instrument(instrument) => instrument.get_id(),
+            Self::Order(order) => order.book_id,
+            Self::Modify(modify) => modify.book_id,
+            Self::Cancel(cancel) => cancel.book_id,
+            Self::Trade(trade) => trade.book_id,
+            Self::Checksum(checksum) => checksum.book_id,
+            Self::AuctionInfo(info) => info.book_id,
+            Self::SnapshotHeader(header) => header.book_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use disseminator::snapshot::SnapshotHeader;
+    use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+    use oep::{cancel::Cancel, decoder::Decoder};
+
+    use super::FeedMessage;
+
+    #[test]
+    fn decode() {
+        let instrument = Instrument::new(
+            1000,
+            "ACME",
+            InstrumentType::Share,
+            InstrumentState::Trading,
+            0,
+            30,
+        );
+        let message = [[1].as_slice(), &instrument.encode()].concat();
+        match FeedMessage::decode(&message) {
+            Some(FeedMessage::Instrument(decoded)) => assert_eq!("ACME", decoded.get_name()),
+            other => panic!("Unexpected {other:?}"),
+        }
+        // cut short
+        assert!(FeedMessage::decode(&message[..20]).is_none());
+
+        let cancel = Cancel {
+            participant: 1,
+            order_id: 7,
+            book_id: 1000,
+            side: 0,
+            gateway_id: 0,
+            session_id: 0,
+            orig_client_order_id: 0,
+        };
+        let message = [[6].as_slice(), &cancel.encode()].concat();
+        let decoded = FeedMessage::decode(&message).unwrap();
+        assert_eq!(1000, decoded.book_id());
+        assert!(FeedMessage::decode(&message[..message.len() - 1]).is_none());
+
+        let header = SnapshotHeader {
+            book_id: 1001,
+            next_seq: 5,
+            order_count: 0,
+        };
+        let message = [[10].as_slice(), &header.encode()].concat();
+        assert_eq!(1001, FeedMessage::decode(&message).unwrap().book_id());
+
+        // statistics, not needed
+        assert!(FeedMessage::decode(&[12, 0, 0]).is_none());
+        assert!(FeedMessage::decode(&[]).is_none());
+    }
+}
@@ -0,0 +1,425 @@
+//! The threads of the bridge
+//!
+//! One thread per feed channel joins its multicast group and applies the
+//! datagrams to the books of the Hub, which hands the JSON updates over to the
+//! subscribed clients. Every WebSocket client has a reader thread, handling its
+//! commands, and a writer thread draining the channel of its frames, so that a
+//! slow client never holds the feed back. A client letting CLIENT_QUEUE frames
+//! pile up is dropped rather than buffered for without a limit.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use socket2::{SockAddr, Socket};
+use utils::{
+    config::{get_config_string, get_optional_config_string},
+    network::{join_multicast_group, socket_address, MulticastConfig},
+};
+
+use crate::{
+    book::BookEvent,
+    books::{Books, Update},
+    json::{self, Command},
+    websocket::{self, Frame, OPCODE_CLOSE, OPCODE_PONG},
+};
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+/// The frames a client can be behind by before being dropped
+pub const CLIENT_QUEUE: usize = 4096;
+// between two attempts at joining a feed group again
+const REJOIN_DELAY: Duration = Duration::from_secs(1);
+
+/// The feed channels joined and the WebSocket port
+#[derive(Debug, Clone, PartialEq)]
+pub struct BridgeConfig {
+    pub feed_group: String,
+    pub feed_port: u16,
+    pub snapshot_group: String,
+    pub snapshot_port: u16,
+    // the interface the feed groups are joined on
+    pub multicast: MulticastConfig,
+    pub address: String,
+    pub port: u16,
+}
+
+impl BridgeConfig {
+    /// Loads the [feed] and [bridge] sections
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self> {
+        let port = |section: &str, key: &str| get_config_string(config_map, section, key).parse();
+        Ok(Self {
+            feed_group: get_config_string(config_map, "feed", "group"),
+            feed_port: port("feed", "port")?,
+            snapshot_group: get_config_string(config_map, "feed", "snapshot_group"),
+            snapshot_port: port("feed", "snapshot_port")?,
+            multicast: MulticastConfig::from_config(config_map, "feed").map_err(|e| anyhow!(e))?,
+            address: get_optional_config_string(config_map, "bridge", "address")
+                .unwrap_or(String::from("0.0.0.0")),
+            port: port("bridge", "port")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Incremental,
+    Snapshot,
+}
+
+#[derive(Debug)]
+struct Client {
+    frames: SyncSender<Vec<u8>>,
+    // shut down when the client falls behind, None in the tests
+    stream: Option<TcpStream>,
+    books: HashSet<u64>,
+}
+
+impl Client {
+    /// Queues @frame, false if the client can't keep up
+    fn send(&self, frame: Vec<u8>) -> bool {
+        // a client gone is removed by its reader
+        !matches!(self.frames.try_send(frame), Err(TrySendError::Full(_)))
+    }
+}
+
+/// The books and the clients subscribed to them
+#[derive(Debug, Default)]
+pub struct Hub {
+    books: Books,
+    clients: HashMap<u64, Client>,
+    next_client_id: u64,
+}
+
+impl Hub {
+    /// Applies a datagram received on @channel, sending what changed to the subscribers
+    pub fn on_datagram(&mut self, channel: Channel, datagram: &[u8]) {
+        let updates = match channel {
+            Channel::Incremental => self.books.on_incremental(datagram),
+            Channel::Snapshot => self.books.on_snapshot(datagram),
+        };
+        for update in updates {
+            self.publish(&update);
+        }
+    }
+
+    fn publish(&mut self, update: &Update) {
+        let book = self.books.get(update.book_id);
+        let text = match (&update.event, book) {
+            (BookEvent::Synced, Some(book)) => json::snapshot(book),
+            _ => match json::update(update, book) {
+                Some(text) => text,
+                None => return,
+            },
+        };
+        let frame = websocket::text_frame(&text);
+        let lagging: Vec<u64> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.books.contains(&update.book_id))
+            .filter(|(_, client)| !client.send(frame.clone()))
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        for client_id in lagging {
+            self.drop_client(client_id);
+        }
+    }
+
+    /// Registers a client whose frames go to @frames, returning its id. Its
+    /// @stream is shut down if it falls behind
+    pub fn connect(&mut self, frames: SyncSender<Vec<u8>>, stream: Option<TcpStream>) -> u64 {
+        self.next_client_id += 1;
+        self.clients.insert(
+            self.next_client_id,
+            Client {
+                frames,
+                stream,
+                books: HashSet::new(),
+            },
+        );
+        self.next_client_id
+    }
+
+    pub fn disconnect(&mut self, client_id: u64) {
+        self.clients.remove(&client_id);
+    }
+
+    // the client is too slow to be kept: closing its socket ends its reader
+    // and its writer
+    fn drop_client(&mut self, client_id: u64) {
+        if let Some(client) = self.clients.remove(&client_id) {
+            eprintln!("Dropping the WebSocket client {client_id}, {CLIENT_QUEUE} frames behind");
+            if let Some(stream) = client.stream {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    /// Handles the message @text of a client
+    pub fn on_command(&mut self, client_id: u64, text: &str) {
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return;
+        };
+        let reply = match Command::parse(text) {
+            Some(Command::List) => Some(json::list(self.books.iter())),
+            Some(Command::Subscribe(book_id)) => {
+                client.books.insert(book_id);
+                // otherwise sent once the book is in sync
+                self.books.get(book_id).map(json::snapshot)
+            }
+            Some(Command::Unsubscribe(book_id)) => {
+                client.books.remove(&book_id);
+                None
+            }
+            None => Some(json::error(&format!("Invalid command {text}"))),
+        };
+        if let Some(reply) = reply {
+            if !client.send(websocket::text_frame(&reply)) {
+                self.drop_client(client_id);
+            }
+        }
+    }
+}
+
+pub struct BridgeServer {
+    hub: Arc<Mutex<Hub>>,
+}
+
+impl Default for BridgeServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BridgeServer {
+    pub fn new() -> Self {
+        Self {
+            hub: Arc::new(Mutex::new(Hub::default())),
+        }
+    }
+
+    pub fn hub(&self) -> Arc<Mutex<Hub>> {
+        self.hub.clone()
+    }
+
+    /// Joins the feeds and serves the WebSocket clients, forever
+    pub fn run(self, config: BridgeConfig) -> Result<()> {
+        for (group, port, channel) in [
+            (&config.feed_group, config.feed_port, Channel::Incremental),
+            (
+                &config.snapshot_group,
+                config.snapshot_port,
+                Channel::Snapshot,
+            ),
+        ] {
+            let group = socket_address(group, port)?;
+            let mut socket = join_multicast_group(&group, &config.multicast)?;
+            let multicast = config.multicast.clone();
+            let hub = self.hub.clone();
+            thread::spawn(move || {
+                let mut buffer = [0; 65536];
+                loop {
+                    match socket.read(&mut buffer) {
+                        Ok(r) => hub.lock().unwrap().on_datagram(channel, &buffer[..r]),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => {
+                            eprintln!("Error reading the {channel:?} feed, joining it again: {e}");
+                            socket = rejoin(&group, &multicast);
+                        }
+                    }
+                }
+            });
+        }
+        let listener = TcpListener::bind((config.address.as_str(), config.port))?;
+        println!(
+            "Serving the WebSocket clients on {}",
+            listener.local_addr()?
+        );
+        self.serve(listener);
+        Ok(())
+    }
+
+    /// Accepts the WebSocket clients of @listener, forever
+    pub fn serve(&self, listener: TcpListener) {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let hub = self.hub.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve_client(stream, hub) {
+                            eprintln!("WebSocket client gone: {e}");
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Error accepting a WebSocket client: {e}"),
+            }
+        }
+    }
+}
+
+// the socket of the feed @group, joined again once it failed
+fn rejoin(group: &SockAddr, multicast: &MulticastConfig) -> Socket {
+    loop {
+        thread::sleep(REJOIN_DELAY);
+        match join_multicast_group(group, multicast) {
+            Ok(socket) => return socket,
+            Err(e) => eprintln!("Unable to join the feed group again: {e}"),
+        }
+    }
+}
+
+fn serve_client(mut stream: TcpStream, hub: Arc<Mutex<Hub>>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    websocket::handshake(&mut stream)?;
+
+    let (frames, outgoing) = mpsc::sync_channel::<Vec<u8>>(CLIENT_QUEUE);
+    let mut writer = stream.try_clone()?;
+    thread::spawn(move || {
+        // until the client is disconnected, its senders being dropped
+        for frame in outgoing {
+            if writer.write_all(&frame).is_err() {
+                break;
+            }
+        }
+    });
+
+    let client_id = hub
+        .lock()
+        .unwrap()
+        .connect(frames.clone(), Some(stream.try_clone()?));
+    let r = loop {
+        match websocket::read_frame(&mut stream) {
+            Ok(Frame::Text(text)) => hub.lock().unwrap().on_command(client_id, &text),
+            Ok(Frame::Ping(payload)) => {
+                let _ = frames.try_send(websocket::encode_frame(OPCODE_PONG, &payload));
+            }
+            Ok(Frame::Close) => {
+                let _ = frames.try_send(websocket::encode_frame(OPCODE_CLOSE, &[]));
+                break Ok(());
+            }
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+    };
+    hub.lock().unwrap().disconnect(client_id);
+    r
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use disseminator::snapshot::SnapshotHeader;
+    use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+
+    use super::{Channel, Hub, CLIENT_QUEUE};
+    use crate::websocket::text_frame;
+
+    #[test]
+    fn subscriptions() {
+        let mut hub = Hub::default();
+        let (frames, received) = mpsc::sync_channel(CLIENT_QUEUE);
+        let client_id = hub.connect(frames, None);
+
+        hub.on_command(client_id, r#"{"action":"subscribe","book_id":1000}"#);
+        hub.on_command(client_id, r#"{"action":"sell"}"#);
+        assert_eq!(
+            text_frame(r#"{"type":"error","message":"Invalid command {\"action\":\"sell\"}"}"#),
+            received.try_recv().unwrap()
+        );
+
+        // the snapshot of an empty book completes with its instrument
+        let header = SnapshotHeader {
+            book_id: 1000,
+            next_seq: 0,
+            order_count: 0,
+        };
+        let instrument = Instrument::new(
+            1000,
+            "ACME",
+            InstrumentType::Share,
+            InstrumentState::Trading,
+            0,
+            30,
+        );
+        let datagram =
+            |type_id: u8, value: &[u8]| [0u64.to_le_bytes().as_slice(), &[type_id], value].concat();
+        hub.on_datagram(Channel::Snapshot, &datagram(10, &header.encode()));
+        hub.on_datagram(Channel::Snapshot, &datagram(1, &instrument.encode()));
+        let snapshot = text_frame(
+            r#"{"type":"snapshot","book_id":1000,"name":"ACME","state":"trading","bids":[],"asks":[]}"#,
+        );
+        assert_eq!(snapshot, received.try_recv().unwrap());
+
+        hub.on_command(client_id, r#"{"action":"list"}"#);
+        assert_eq!(
+            text_frame(
+                r#"{"type":"books","books":[{"book_id":1000,"name":"ACME","state":"trading"}]}"#
+            ),
+            received.try_recv().unwrap()
+        );
+        hub.on_command(client_id, r#"{"action":"unsubscribe","book_id":1000}"#);
+        hub.on_command(client_id, r#"{"action":"subscribe","book_id":1000}"#);
+        assert_eq!(snapshot, received.try_recv().unwrap());
+
+        hub.disconnect(client_id);
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn slow_clients_dropped() {
+        let mut hub = Hub::default();
+        // room for a single frame
+        let (frames, received) = mpsc::sync_channel(1);
+        let slow = hub.connect(frames, None);
+        let (frames, _received) = mpsc::sync_channel(CLIENT_QUEUE);
+        let other = hub.connect(frames, None);
+        for client_id in [slow, other] {
+            hub.on_command(client_id, r#"{"action":"subscribe","book_id":1000}"#);
+            hub.on_command(client_id, r#"{"action":"list"}"#);
+        }
+
+        // the snapshot doesn't fit behind the books message
+        let header = SnapshotHeader {
+            book_id: 1000,
+            next_seq: 0,
+            order_count: 0,
+        };
+        let instrument = Instrument::new(
+            1000,
+            "ACME",
+            InstrumentType::Share,
+            InstrumentState::Trading,
+            0,
+            30,
+        );
+        let datagram =
+            |type_id: u8, value: &[u8]| [0u64.to_le_bytes().as_slice(), &[type_id], value].concat();
+        hub.on_datagram(Channel::Snapshot, &datagram(10, &header.encode()));
+        hub.on_datagram(Channel::Snapshot, &datagram(1, &instrument.encode()));
+        assert!(!hub.clients.contains_key(&slow));
+        assert!(hub.clients.contains_key(&other));
+        assert_eq!(
+            text_frame(r#"{"type":"books","books":[]}"#),
+            received.try_recv().unwrap()
+        );
+        assert!(received.try_recv().is_err());
+
+        // the same for the answers to the commands
+        let (frames, _received) = mpsc::sync_channel(1);
+        let slow = hub.connect(frames, None);
+        hub.on_command(slow, r#"{"action":"list"}"#);
+        assert!(hub.clients.contains_key(&slow));
+        hub.on_command(slow, r#"{"action":"list"}"#);
+        assert!(!hub.clients.contains_key(&slow));
+    }
+}
@@ -0,0 +1,249 @@
+//! The little of WebSocket (RFC 6455) a server pushing JSON needs: the opening
+//! handshake and the unfragmented frames
+//!
+//! # Example:
+//!
+//! ```
+//! # use feed_bridge::websocket::accept_key;
+//! // the example of the RFC
+//! assert_eq!(
+//!     "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+//!     accept_key("dGhlIHNhbXBsZSBub25jZQ==")
+//! );
+//! ```
+
+use std::io::{self, Read, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// the longest handshake request accepted
+const MAX_REQUEST_SIZE: usize = 8192;
+// the longest message accepted from a client, the commands being tiny
+pub const MAX_PAYLOAD_SIZE: usize = 4096;
+
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xa;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+/// The Sec-WebSocket-Accept answering the Sec-WebSocket-Key @key
+pub fn accept_key(key: &str) -> String {
+    STANDARD.encode(Sha1::digest(format!("{}{GUID}", key.trim()).as_bytes()))
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+/// Reads the opening handshake of a client from @stream and answers it,
+/// failing after a 400 if it's not a WebSocket upgrade
+pub fn handshake(stream: &mut (impl Read + Write)) -> io::Result<()> {
+    // one byte at a time, not to read the frames following the request
+    let mut request = vec![];
+    let mut byte = [0];
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() >= MAX_REQUEST_SIZE {
+            return Err(invalid("handshake request too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        request.push(byte[0]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let key = request.starts_with("GET ").then(|| {
+        request.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("sec-websocket-key")
+                .then_some(value.trim())
+        })
+    });
+    let Some(Some(key)) = key else {
+        stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+        return Err(invalid("not a WebSocket handshake"));
+    };
+    stream.write_all(
+        format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        )
+        .as_bytes(),
+    )
+}
+
+/// A frame of the server, which is never masked
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut r = Vec::with_capacity(payload.len() + 10);
+    r.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => r.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            r.push(126);
+            r.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            r.push(127);
+            r.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    r.extend_from_slice(payload);
+    r
+}
+
+pub fn text_frame(text: &str) -> Vec<u8> {
+    encode_frame(OPCODE_TEXT, text.as_bytes())
+}
+
+/// Reads a frame of a client, which has to be masked and not fragmented
+pub fn read_frame(stream: &mut impl Read) -> io::Result<Frame> {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head)?;
+    if head[0] & 0x80 == 0 || head[0] & 0x0f == 0 {
+        return Err(invalid("fragmented frame"));
+    }
+    if head[1] & 0x80 == 0 {
+        return Err(invalid("unmasked frame"));
+    }
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as usize
+        }
+        127 => {
+            let mut len = [0; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len).try_into().unwrap_or(usize::MAX)
+        }
+        len => len as usize,
+    };
+    if len > MAX_PAYLOAD_SIZE {
+        return Err(invalid("frame too long"));
+    }
+    let mut mask = [0; 4];
+    stream.read_exact(&mut mask)?;
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    match head[0] & 0x0f {
+        OPCODE_TEXT => String::from_utf8(payload)
+            .map(Frame::Text)
+            .map_err(|_| invalid("text frame not UTF-8")),
+        OPCODE_BINARY => Ok(Frame::Binary(payload)),
+        OPCODE_CLOSE => Ok(Frame::Close),
+        OPCODE_PING => Ok(Frame::Ping(payload)),
+        OPCODE_PONG => Ok(Frame::Pong(payload)),
+        _ => Err(invalid("unknown opcode")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read, Write};
+
+    use super::*;
+
+    /// Reads the request from read_buffer and writes the answer to written
+    struct MockStream {
+        read_buffer: Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.read_buffer.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn masked(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut r = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        r.extend_from_slice(&mask);
+        r.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        r
+    }
+
+    #[test]
+    fn accept_keys() {
+        // the example of the RFC, the key trimmed as read from its header
+        assert_eq!(
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+            accept_key(" dGhlIHNhbXBsZSBub25jZQ== ")
+        );
+    }
+
+    #[test]
+    fn handshakes() {
+        let request = "GET /feed HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                       Connection: Upgrade\r\nsec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Version: 13\r\n\r\n";
+        let frame = masked(OPCODE_TEXT, b"hi");
+        let mut stream = MockStream {
+            read_buffer: Cursor::new([request.as_bytes(), &frame].concat()),
+            written: vec![],
+        };
+        handshake(&mut stream).unwrap();
+        let answer = String::from_utf8(stream.written.clone()).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 101"));
+        assert!(answer.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        // the frame following the request is left alone
+        assert_eq!(
+            Frame::Text(String::from("hi")),
+            read_frame(&mut stream).unwrap()
+        );
+
+        let mut stream = MockStream {
+            read_buffer: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec()),
+            written: vec![],
+        };
+        assert!(handshake(&mut stream).is_err());
+        assert!(stream.written.starts_with(b"HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn frames() {
+        assert_eq!(vec![0x81, 2, b'h', b'i'], text_frame("hi"));
+        let long = encode_frame(OPCODE_BINARY, &[0; 300]);
+        assert_eq!([0x82, 126, 1, 44], long[..4]);
+        assert_eq!(304, long.len());
+
+        let mut input = Cursor::new(
+            [
+                masked(OPCODE_PING, b"x"),
+                masked(OPCODE_CLOSE, &[]),
+                // not masked
+                text_frame("hi"),
+            ]
+            .concat(),
+        );
+        assert_eq!(Frame::Ping(vec![b'x']), read_frame(&mut input).unwrap());
+        assert_eq!(Frame::Close, read_frame(&mut input).unwrap());
+        assert!(read_frame(&mut input).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use utils::config::get_optional_config_string;
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+/// The CompID of the exchange, unless configured otherwise
+pub const DEFAULT_COMP_ID: &str = "EXCHANGE";
+
+/// The FIX side of a listener
+///
+/// Every FIX session maps to one OEP session: the client logging on as
+/// SenderCompID logs in as the session_id given by `fix_sessions`, a list of
+/// `SenderCompID:session_id` pairs. The exchange goes by `fix_comp_id`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixConfig {
+    pub comp_id: String,
+    // SenderCompID of the client -> its OEP session_id
+    pub sessions: HashMap<String, u32>,
+}
+
+impl FixConfig {
+    /// Loads the FIX settings of the listener whose section is @section
+    pub fn from_config(config_map: &ConfigMap, section: &str) -> Result<Self> {
+        let optional = |key: &str| get_optional_config_string(config_map, section, key);
+        let mut sessions = HashMap::new();
+        for pair in optional("fix_sessions")
+            .unwrap_or_default()
+            .split(',')
+            .map(|pair| pair.trim())
+            .filter(|pair| !pair.is_empty())
+        {
+            let (comp_id, session_id) = pair
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Invalid FIX session {pair}, expected CompID:session_id"))?;
+            let session_id = session_id
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| anyhow!("The session_id of FIX session {pair} must be an u32"))?;
+            if sessions
+                .insert(String::from(comp_id.trim()), session_id)
+                .is_some()
+            {
+                bail!("FIX session {} configured twice", comp_id.trim());
+            }
+        }
+        if sessions.is_empty() {
+            bail!("No FIX session configured in {section}");
+        }
+        Ok(Self {
+            comp_id: optional("fix_comp_id").unwrap_or(String::from(DEFAULT_COMP_ID)),
+            sessions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use configparser::ini::Ini;
+
+    use super::{FixConfig, DEFAULT_COMP_ID};
+
+    #[test]
+    fn load() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[listener_fix]
+                fix_sessions=BROKER1:100, Broker2:101",
+            ))
+            .unwrap();
+        let target = FixConfig::from_config(&config_map, "listener_fix").unwrap();
+        assert_eq!(DEFAULT_COMP_ID, target.comp_id);
+        assert_eq!(2, target.sessions.len());
+        assert_eq!(Some(&100), target.sessions.get("BROKER1"));
+        assert_eq!(Some(&101), target.sessions.get("Broker2"));
+
+        let config_map = Ini::new()
+            .read(String::from(
+                "[listener_fix]
+                fix_comp_id=XCHG
+                fix_sessions=BROKER1:100",
+            ))
+            .unwrap();
+        let target = FixConfig::from_config(&config_map, "listener_fix").unwrap();
+        assert_eq!("XCHG", target.comp_id);
+    }
+
+    #[test]
+    fn invalid() {
+        for sessions in ["", "BROKER1", "BROKER1:x", "BROKER1:100,BROKER1:101"] {
+            let config_map = Ini::new()
+                .read(format!("[listener_fix]\nfix_sessions={sessions}"))
+                .unwrap();
+            assert!(
+                FixConfig::from_config(&config_map, "listener_fix").is_err(),
+                "{sessions}"
+            );
+        }
+    }
+}
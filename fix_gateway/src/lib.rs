@@ -0,0 +1,16 @@
+//! FIX 4.4 front-end of the gateway
+//!
+//! Translates the FIX sessions of the clients to OEP sessions, and back. The
+//! gateway feeds a FixSession with the bytes read from a FIX client and
+//! handles the OEP messages coming out of it as it would the ones of an OEP
+//! client, while the OEP messages the gateway sends to the session go through
+//! the FixSession on their way out. Nothing here does any networking.
+//!
+//! The FIX messages spoken, the rest being refused with a Reject:
+//!  * Logon, Logout, Heartbeat, TestRequest, ResendRequest, SequenceReset
+//!  * NewOrderSingle, OrderCancelRequest, OrderCancelReplaceRequest, in
+//!  * ExecutionReport, OrderCancelReject, out
+
+pub mod config;
+pub mod message;
+pub mod session;
@@ -0,0 +1,396 @@
+//! The tag=value encoding of the FIX messages
+//!
+//! A message is a run of `tag=value` fields, each ended by SOH: BeginString(8),
+//! BodyLength(9) and MsgType(35) first, CheckSum(10) last. The body length
+//! counts the bytes from MsgType up to CheckSum, and the checksum is the sum of
+//! the bytes before it, modulo 256, on 3 digits.
+
+use std::{
+    fmt::{self, Display},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub const BEGIN_STRING: &str = "FIX.4.4";
+pub const SOH: u8 = 0x01;
+// no message handled here gets anywhere near, a longer one is garbage
+const MAX_BODY_LENGTH: usize = 4096;
+
+pub mod tag {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECKSUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const EXEC_INST: u32 = 18;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const STOP_PX: u32 = 99;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const EXPIRE_TIME: u32 = 126;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const MAX_SHOW: u32 = 210;
+    pub const REF_TAG_ID: u32 = 371;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    pub const USERNAME: u32 = 553;
+    pub const PASSWORD: u32 = 554;
+    pub const TRD_MATCH_ID: u32 = 880;
+}
+
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+}
+
+/// Why a message is refused, as told by a Reject(3)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionReject {
+    RequiredTagMissing(u32),
+    ValueIncorrect(u32),
+    IncorrectDataFormat(u32),
+    InvalidMsgType,
+}
+
+impl SessionReject {
+    /// The SessionRejectReason(373) and the RefTagID(371), if any
+    pub fn reason(&self) -> (u32, Option<u32>) {
+        match *self {
+            SessionReject::RequiredTagMissing(tag) => (1, Some(tag)),
+            SessionReject::ValueIncorrect(tag) => (5, Some(tag)),
+            SessionReject::IncorrectDataFormat(tag) => (6, Some(tag)),
+            SessionReject::InvalidMsgType => (11, None),
+        }
+    }
+}
+
+impl Display for SessionReject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionReject::RequiredTagMissing(tag) => write!(f, "Required tag {tag} missing"),
+            SessionReject::ValueIncorrect(tag) => write!(f, "Incorrect value for tag {tag}"),
+            SessionReject::IncorrectDataFormat(tag) => {
+                write!(f, "Incorrect data format for tag {tag}")
+            }
+            SessionReject::InvalidMsgType => write!(f, "Unsupported MsgType"),
+        }
+    }
+}
+
+impl std::error::Error for SessionReject {}
+
+/// A FIX message, without its BeginString, BodyLength and CheckSum
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixMessage {
+    // in the order they come on the wire, MsgType first
+    fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        Self {
+            fields: vec![(tag::MSG_TYPE, String::from(msg_type))],
+        }
+    }
+
+    /// Adds the field @tag, after the ones already there
+    pub fn with(mut self, tag: u32, value: impl Display) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tag::MSG_TYPE).unwrap_or_default()
+    }
+
+    /// The first value of @tag
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn require(&self, tag: u32) -> Result<&str, SessionReject> {
+        self.get(tag).ok_or(SessionReject::RequiredTagMissing(tag))
+    }
+
+    /// The value of @tag, an integer
+    pub fn require_u64(&self, tag: u32) -> Result<u64, SessionReject> {
+        self.require(tag)?
+            .parse()
+            .map_err(|_| SessionReject::IncorrectDataFormat(tag))
+    }
+
+    /// The value of @tag if it's there, an integer
+    pub fn optional_u64(&self, tag: u32) -> Result<Option<u64>, SessionReject> {
+        self.get(tag)
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| SessionReject::IncorrectDataFormat(tag))
+            })
+            .transpose()
+    }
+
+    /// The message as sent on the wire
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = vec![];
+        for (tag, value) in self.fields.iter() {
+            body.extend_from_slice(format!("{tag}={value}").as_bytes());
+            body.push(SOH);
+        }
+        let mut r = format!("8={BEGIN_STRING}\x019={}\x01", body.len()).into_bytes();
+        r.append(&mut body);
+        let checksum = checksum(&r);
+        r.extend_from_slice(format!("10={checksum:03}\x01").as_bytes());
+        r
+    }
+
+    /// The message at the start of @buffer, along with its length
+    ///
+    /// Returns: None while the message is incomplete, an error if @buffer is
+    /// not a FIX 4.4 stream
+    pub fn decode(buffer: &[u8]) -> anyhow::Result<Option<(Self, usize)>> {
+        let prefix = format!("8={BEGIN_STRING}\x019=");
+        let compared = prefix.len().min(buffer.len());
+        if buffer[..compared] != prefix.as_bytes()[..compared] {
+            anyhow::bail!("Not a {BEGIN_STRING} message");
+        }
+        let Some(end) = buffer[compared..].iter().position(|&b| b == SOH) else {
+            if buffer.len() - compared > 6 {
+                anyhow::bail!("Invalid BodyLength");
+            }
+            return Ok(None);
+        };
+        let body_length = std::str::from_utf8(&buffer[compared..compared + end])?
+            .parse::<usize>()
+            .ok()
+            .filter(|&len| len <= MAX_BODY_LENGTH)
+            .ok_or_else(|| anyhow::anyhow!("Invalid BodyLength"))?;
+        let body_start = compared + end + 1;
+        let checksum_start = body_start + body_length;
+        // 10=xxx and SOH
+        let len = checksum_start + 7;
+        if buffer.len() < len {
+            return Ok(None);
+        }
+        let expected = format!("10={:03}\x01", checksum(&buffer[..checksum_start]));
+        if buffer[checksum_start..len] != *expected.as_bytes() {
+            anyhow::bail!("Invalid CheckSum");
+        }
+
+        let body = std::str::from_utf8(&buffer[body_start..checksum_start])?;
+        let Some(body) = body.strip_suffix('\x01') else {
+            anyhow::bail!("Invalid BodyLength");
+        };
+        let mut fields = vec![];
+        for field in body.split('\x01') {
+            let (tag, value) = field
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid field {field}"))?;
+            let tag = tag
+                .parse::<u32>()
+                .map_err(|_| anyhow::anyhow!("Invalid tag {tag}"))?;
+            fields.push((tag, String::from(value)));
+        }
+        if fields.first().map(|(tag, _)| *tag) != Some(tag::MSG_TYPE) {
+            anyhow::bail!("MsgType has to come first");
+        }
+        Ok(Some((Self { fields }, len)))
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+// days since 1970-01-01 of a date, and back, in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// @time as a FIX UTCTimestamp, YYYYMMDD-HH:MM:SS.sss
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let seconds = seconds.rem_euclid(86400);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// The unix timestamp (seconds) of a FIX UTCTimestamp, the fraction of a
+/// second being dropped
+pub fn parse_utc_timestamp(s: &str) -> Option<u64> {
+    let (date, time) = s.split_once('-')?;
+    let time = time.split('.').next()?;
+    if date.len() != 8 || time.len() != 8 || !date.is_ascii() || !time.is_ascii() {
+        return None;
+    }
+    let number = |s: &str| s.parse::<i64>().ok();
+    let (year, month, day) = (
+        number(&date[..4])?,
+        number(&date[4..6])?,
+        number(&date[6..])?,
+    );
+    let mut clock = time.split(':').map(number);
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(seconds).ok()
+}
+
+/// The UTCTimestamp of @seconds since the epoch
+pub fn utc_timestamp_of(seconds: u64) -> String {
+    utc_timestamp(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{msg_type, parse_utc_timestamp, tag, utc_timestamp, FixMessage, SessionReject};
+
+    fn wire(s: &str) -> Vec<u8> {
+        s.replace('|', "\x01").into_bytes()
+    }
+
+    #[test]
+    fn encode_decode() {
+        let target = FixMessage::new(msg_type::HEARTBEAT)
+            .with(tag::SENDER_COMP_ID, "EXCHANGE")
+            .with(tag::MSG_SEQ_NUM, 7);
+        let encoded = target.encode();
+        assert_eq!(
+            wire("8=FIX.4.4|9=22|35=0|49=EXCHANGE|34=7|10=156|"),
+            encoded
+        );
+
+        let (decoded, len) = FixMessage::decode(&encoded).unwrap().unwrap();
+        assert_eq!(target, decoded);
+        assert_eq!(encoded.len(), len);
+        assert_eq!("0", decoded.msg_type());
+        assert_eq!(Some("EXCHANGE"), decoded.get(tag::SENDER_COMP_ID));
+        assert_eq!(Ok(7), decoded.require_u64(tag::MSG_SEQ_NUM));
+        assert_eq!(Ok(None), decoded.optional_u64(tag::PRICE));
+        assert_eq!(
+            Err(SessionReject::RequiredTagMissing(tag::PRICE)),
+            decoded.require_u64(tag::PRICE)
+        );
+        assert_eq!(
+            Err(SessionReject::IncorrectDataFormat(tag::SENDER_COMP_ID)),
+            decoded.require_u64(tag::SENDER_COMP_ID)
+        );
+
+        // a byte at a time, followed by the next one
+        for i in 0..encoded.len() {
+            assert_eq!(None, FixMessage::decode(&encoded[..i]).unwrap());
+        }
+        let two = [encoded.clone(), encoded.clone()].concat();
+        assert_eq!(encoded.len(), FixMessage::decode(&two).unwrap().unwrap().1);
+    }
+
+    #[test]
+    fn garbled() {
+        // checksum
+        assert!(FixMessage::decode(&wire("8=FIX.4.4|9=5|35=0|10=000|")).is_err());
+        // length
+        assert!(FixMessage::decode(&wire("8=FIX.4.4|9=4|35=0|10=161|")).is_err());
+        assert!(FixMessage::decode(&wire("8=FIX.4.4|9=x|35=0|10=161|")).is_err());
+        assert!(FixMessage::decode(&wire("8=FIX.4.4|9=123456789")).is_err());
+        // version
+        assert!(FixMessage::decode(&wire("8=FIX.4.2|9=5|35=0|10=163|")).is_err());
+        assert!(FixMessage::decode(b"GET / HTTP/1.1").is_err());
+        // MsgType first
+        let bad = wire("8=FIX.4.4|9=5|34=1|");
+        let checksum = bad.iter().fold(0u8, |s, &b| s.wrapping_add(b));
+        let bad = [bad, wire(&format!("10={checksum:03}|"))].concat();
+        assert!(FixMessage::decode(&bad).is_err());
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!("19700101-00:00:00.000", utc_timestamp(UNIX_EPOCH));
+        let time = UNIX_EPOCH + Duration::from_millis(1709251199123);
+        assert_eq!("20240229-23:59:59.123", utc_timestamp(time));
+        assert_eq!(
+            Some(1709251199),
+            parse_utc_timestamp("20240229-23:59:59.123")
+        );
+        assert_eq!(Some(1709251199), parse_utc_timestamp("20240229-23:59:59"));
+        assert_eq!(Some(0), parse_utc_timestamp("19700101-00:00:00"));
+        assert_eq!(None, parse_utc_timestamp("20241301-00:00:00"));
+        assert_eq!(None, parse_utc_timestamp("2024-02-29"));
+        assert_eq!(None, parse_utc_timestamp("19690101-00:00:00"));
+    }
+}
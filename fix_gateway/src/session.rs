@@ -0,0 +1,1254 @@
+//! One FIX session, translated to and from its OEP session
+//!
+//! The FIX sequences start again with every Logon, as asked by
+//! ResetSeqNumFlag(141), there is no resend: a client finding a gap logs on
+//! again, and gets the execution reports missed while away, as any OEP
+//! session would. The OEP sequences are the ones of the gateway, picked up
+//! from the login echo.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Result};
+use oep::{
+    cancel::{Cancel, CANCEL_SIZE},
+    decoder::Decoder,
+    execution_report::{ExecutionReport, RejectReason},
+    header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+    heartbeat::{Heartbeat, HEARTBEAT_SIZE},
+    login::{Login, LOGIN_SIZE},
+    loginreject::LoginReject,
+    neworder::{NewOrder, NEWORDER_SIZE},
+    oep_decode,
+    oep_message::MsgType,
+    replace::{Replace, REPLACE_SIZE},
+    version::VersionReject,
+};
+use order::{OrderState, OrderType};
+
+use crate::{
+    config::FixConfig,
+    message::{msg_type, parse_utc_timestamp, tag, utc_timestamp, FixMessage, SessionReject},
+};
+
+// for the clients not asking for a HeartBtInt
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+// the longest user name fitting in the OEP login
+const MAX_USERNAME_LENGTH: usize = 62;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    AwaitingLogon,
+    // the OEP login went out, the FIX Logon is answered once it's accepted
+    LoggingOn,
+    LoggedOn,
+    LoggedOut,
+}
+
+/// What came out of a FIX message
+#[derive(Debug, Default, PartialEq)]
+pub struct Translated {
+    // for the gateway, with their OEP headers
+    pub oep: Vec<u8>,
+    // for the client, right away
+    pub reply: Vec<u8>,
+    // whether the client has to be disconnected, after the reply
+    pub disconnect: bool,
+}
+
+impl Translated {
+    fn oep(oep: Vec<u8>) -> Self {
+        Self {
+            oep,
+            ..Default::default()
+        }
+    }
+
+    fn reply(reply: Vec<u8>) -> Self {
+        Self {
+            reply,
+            ..Default::default()
+        }
+    }
+
+    fn logout(reply: Vec<u8>) -> Self {
+        Self {
+            reply,
+            disconnect: true,
+            ..Default::default()
+        }
+    }
+}
+
+// what the execution reports of an order need to carry
+#[derive(Debug, Clone, Default)]
+struct OrderInfo {
+    cl_ord_id: String,
+    cum_qty: u64,
+    // sum of the quantities filled times their prices, for the AvgPx
+    notional: u128,
+}
+
+impl OrderInfo {
+    fn avg_px(&self) -> f64 {
+        match self.cum_qty {
+            0 => 0.0,
+            q => self.notional as f64 / q as f64,
+        }
+    }
+}
+
+// an OrderCancelRequest waiting for its execution report
+#[derive(Debug, Clone)]
+struct PendingCancel {
+    cl_ord_id: String,
+    orig_cl_ord_id: String,
+}
+
+/// Translates a FIX 4.4 session to the OEP messages of the gateway, and the
+/// OEP messages of the gateway back to FIX
+#[derive(Debug)]
+pub struct FixSession {
+    config: FixConfig,
+    gateway_id: u8,
+    state: State,
+    // as found in the Logon
+    client_comp_id: String,
+    session_id: u32,
+    participant: u64,
+    heartbeat_interval: Duration,
+    next_in: u64,
+    next_out: u64,
+    next_oep_seq: u32,
+    last_sent: Instant,
+    // the bytes not making a whole message yet
+    fix_buffer: Vec<u8>,
+    oep_buffer: Vec<u8>,
+    // exchange order id -> what its reports need
+    orders: HashMap<u64, OrderInfo>,
+    // exchange order id -> the request cancelling it
+    cancels: HashMap<u64, PendingCancel>,
+}
+
+impl FixSession {
+    pub fn new(config: FixConfig, gateway_id: u8) -> Self {
+        Self {
+            config,
+            gateway_id,
+            state: State::AwaitingLogon,
+            client_comp_id: String::new(),
+            session_id: 0,
+            participant: 0,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            next_in: 1,
+            next_out: 1,
+            next_oep_seq: 1,
+            last_sent: Instant::now(),
+            fix_buffer: vec![],
+            oep_buffer: vec![],
+            orders: HashMap::new(),
+            cancels: HashMap::new(),
+        }
+    }
+
+    pub fn is_logged_on(&self) -> bool {
+        self.state == State::LoggedOn
+    }
+
+    /// Takes in the bytes read from the client, translated by next_input
+    pub fn receive(&mut self, data: &[u8]) {
+        self.fix_buffer.extend_from_slice(data);
+    }
+
+    /// Translates the next message received from the client. One message at
+    /// a time, so that the gateway can answer the Logon before the messages
+    /// after it are translated
+    ///
+    /// Returns: None while no message is complete, an error if the client
+    /// doesn't speak FIX 4.4
+    pub fn next_input(&mut self) -> Result<Option<Translated>> {
+        if self.state == State::LoggedOut {
+            bail!("Logged out");
+        }
+        let Some((message, len)) = FixMessage::decode(&self.fix_buffer)? else {
+            return Ok(None);
+        };
+        self.fix_buffer.drain(..len);
+        Ok(Some(self.on_message(&message)))
+    }
+
+    /// Translates the OEP messages sent by the gateway, as many as @data
+    /// completes
+    ///
+    /// Returns: the FIX messages for the client
+    pub fn on_oep_data(&mut self, data: &[u8]) -> Vec<u8> {
+        self.oep_buffer.extend_from_slice(data);
+        let mut r = vec![];
+        while self.oep_buffer.len() >= OEP_HEADER_SIZE {
+            let header =
+                match OepHeader::decode(self.oep_buffer[..OEP_HEADER_SIZE].try_into().unwrap()) {
+                    Ok(header) => header,
+                    Err(_) => break,
+                };
+            let len = OEP_HEADER_SIZE + header.msg_len as usize;
+            if self.oep_buffer.len() < len {
+                break;
+            }
+            let message = oep_decode(&self.oep_buffer[..len]);
+            self.oep_buffer.drain(..len);
+            let Ok(message) = message else {
+                continue;
+            };
+            let any = message.as_any();
+            if let Some(login) = any.downcast_ref::<Login>() {
+                r.append(&mut self.on_login_accepted(login, header.seq));
+            } else if let Some(ereport) = any.downcast_ref::<ExecutionReport>() {
+                r.append(&mut self.on_execution_report(ereport, header.seq));
+            } else if any.downcast_ref::<VersionReject>().is_some() {
+                r.append(&mut self.logout("Unsupported OEP version"));
+            } else if let Some(reject) = any.downcast_ref::<LoginReject>() {
+                r.append(&mut self.logout(&format!("Logon refused: {:?}", reject.get_reason())));
+            }
+        }
+        r
+    }
+
+    /// A Heartbeat, if nothing was sent to the client for its HeartBtInt
+    pub fn heartbeat_due(&mut self, now: Instant) -> Option<Vec<u8>> {
+        if self.state != State::LoggedOn
+            || now.saturating_duration_since(self.last_sent) < self.heartbeat_interval
+        {
+            return None;
+        }
+        let message = self.header(msg_type::HEARTBEAT);
+        Some(self.encode(message))
+    }
+
+    /// Ends the session, e.g. once the gateway refused the logon
+    ///
+    /// Returns: the Logout for the client, nothing if the client never logged
+    /// on or is already gone
+    pub fn logout(&mut self, text: &str) -> Vec<u8> {
+        if matches!(self.state, State::AwaitingLogon | State::LoggedOut) {
+            return vec![];
+        }
+        let message = self.header(msg_type::LOGOUT).with(tag::TEXT, text);
+        self.state = State::LoggedOut;
+        self.encode(message)
+    }
+
+    fn on_message(&mut self, message: &FixMessage) -> Translated {
+        if message.msg_type() == msg_type::LOGON {
+            return self.on_logon(message);
+        }
+        if self.state != State::LoggedOn {
+            return Translated::logout(self.logout("Logon not completed"));
+        }
+        if message.get(tag::SENDER_COMP_ID) != Some(self.client_comp_id.as_str())
+            || message.get(tag::TARGET_COMP_ID) != Some(self.config.comp_id.as_str())
+        {
+            return Translated::logout(self.logout("Unexpected CompID"));
+        }
+
+        // a SequenceReset-Reset moves the sequence whatever its own is
+        if message.msg_type() == msg_type::SEQUENCE_RESET
+            && message.get(tag::GAP_FILL_FLAG) != Some("Y")
+        {
+            return match message.require_u64(tag::NEW_SEQ_NO) {
+                Ok(seq) if seq >= self.next_in => {
+                    self.next_in = seq;
+                    Translated::default()
+                }
+                Ok(_) => Translated::reply(
+                    self.reject(message, SessionReject::ValueIncorrect(tag::NEW_SEQ_NO)),
+                ),
+                Err(e) => Translated::reply(self.reject(message, e)),
+            };
+        }
+        let Ok(seq) = message.require_u64(tag::MSG_SEQ_NUM) else {
+            return Translated::logout(self.logout("MsgSeqNum missing"));
+        };
+        if seq < self.next_in {
+            if message.get(tag::POSS_DUP_FLAG) == Some("Y") {
+                return Translated::default();
+            }
+            let text = format!("MsgSeqNum too low, expecting {}", self.next_in);
+            return Translated::logout(self.logout(&text));
+        }
+        if seq > self.next_in {
+            // nothing is kept for a resend, the client has to log on again
+            let text = format!("MsgSeqNum too high, expecting {}", self.next_in);
+            return Translated::logout(self.logout(&text));
+        }
+        self.next_in += 1;
+
+        let translated = match message.msg_type() {
+            msg_type::HEARTBEAT => Ok(Translated::oep(self.oep_heartbeat())),
+            msg_type::TEST_REQUEST => {
+                let reply = self.header(msg_type::HEARTBEAT).with(
+                    tag::TEST_REQ_ID,
+                    message.get(tag::TEST_REQ_ID).unwrap_or_default(),
+                );
+                Ok(Translated::reply(self.encode(reply)))
+            }
+            msg_type::RESEND_REQUEST => {
+                // the messages sent so far are not sent again
+                let reply = self.header(msg_type::SEQUENCE_RESET);
+                let reply = reply.with(tag::NEW_SEQ_NO, self.next_out);
+                Ok(Translated::reply(self.encode(reply)))
+            }
+            msg_type::SEQUENCE_RESET => match message.require_u64(tag::NEW_SEQ_NO) {
+                Ok(seq) if seq >= self.next_in => {
+                    self.next_in = seq;
+                    Ok(Translated::default())
+                }
+                Ok(_) => Err(SessionReject::ValueIncorrect(tag::NEW_SEQ_NO)),
+                Err(e) => Err(e),
+            },
+            msg_type::LOGOUT => {
+                let reply = self.logout("Logout acknowledged");
+                Ok(Translated::logout(reply))
+            }
+            msg_type::NEW_ORDER_SINGLE => self.new_order(message).map(Translated::oep),
+            msg_type::ORDER_CANCEL_REQUEST => self.cancel(message).map(Translated::oep),
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => self.replace(message).map(Translated::oep),
+            _ => Err(SessionReject::InvalidMsgType),
+        };
+        translated.unwrap_or_else(|e| Translated::reply(self.reject(message, e)))
+    }
+
+    fn on_logon(&mut self, message: &FixMessage) -> Translated {
+        if self.state != State::AwaitingLogon {
+            return Translated::logout(self.logout("Already logged on"));
+        }
+        // the Logouts go to whoever was trying to log on
+        let comp_id = message.get(tag::SENDER_COMP_ID).unwrap_or_default();
+        self.client_comp_id = String::from(comp_id);
+        self.state = State::LoggingOn;
+        let Some(&session_id) = self.config.sessions.get(comp_id) else {
+            return Translated::logout(self.logout("Unknown SenderCompID"));
+        };
+        if message.get(tag::TARGET_COMP_ID) != Some(self.config.comp_id.as_str()) {
+            return Translated::logout(self.logout("Unknown TargetCompID"));
+        }
+        let Ok(seq) = message.require_u64(tag::MSG_SEQ_NUM) else {
+            return Translated::logout(self.logout("MsgSeqNum missing"));
+        };
+        let interval = match message.require_u64(tag::HEART_BT_INT) {
+            Ok(0) => DEFAULT_HEARTBEAT_INTERVAL,
+            Ok(seconds) => Duration::from_secs(seconds),
+            Err(e) => return Translated::logout(self.logout(&e.to_string())),
+        };
+        let Some(username) = message
+            .get(tag::USERNAME)
+            .filter(|u| !u.is_empty() && u.len() <= MAX_USERNAME_LENGTH && !u.contains('\0'))
+        else {
+            return Translated::logout(self.logout("Invalid Username"));
+        };
+
+        self.next_in = seq + 1;
+        self.heartbeat_interval = interval;
+        self.session_id = session_id;
+        let mut login = Login::new(0, session_id, self.gateway_id, username);
+        login.hash_text_to_password(message.get(tag::PASSWORD).unwrap_or_default());
+        Translated::oep(frame(MsgType::Login, LOGIN_SIZE, 0, &login.encode()))
+    }
+
+    // the gateway accepted the login, whose echo carries the participant
+    // and the OEP sequence expected next
+    fn on_login_accepted(&mut self, login: &Login, next_oep_seq: u32) -> Vec<u8> {
+        if self.state != State::LoggingOn {
+            return vec![];
+        }
+        self.participant = login.participant;
+        self.next_oep_seq = next_oep_seq;
+        self.state = State::LoggedOn;
+        let message = self
+            .header(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, self.heartbeat_interval.as_secs())
+            .with(tag::RESET_SEQ_NUM_FLAG, "Y");
+        self.encode(message)
+    }
+
+    fn new_order(&mut self, message: &FixMessage) -> Result<Vec<u8>, SessionReject> {
+        let terms = OrderTerms::of(message)?;
+        let order = NewOrder {
+            client_order_id: message.require_u64(tag::CL_ORD_ID)?,
+            participant: self.participant,
+            book_id: terms.book_id,
+            quantity: terms.quantity,
+            price: terms.price,
+            order_type: terms.order_type.into(),
+            side: terms.side,
+            gateway_id: self.gateway_id,
+            session_id: self.session_id,
+            expiry: terms.expiry,
+            stop_price: terms.stop_price,
+            display_quantity: terms.display_quantity,
+        };
+        Ok(self.sequenced(MsgType::NewOrder, NEWORDER_SIZE, &order.encode()))
+    }
+
+    fn cancel(&mut self, message: &FixMessage) -> Result<Vec<u8>, SessionReject> {
+        let cl_ord_id = message.require(tag::CL_ORD_ID)?;
+        let order_id = message.require_u64(tag::ORDER_ID)?;
+        let cancel = Cancel {
+            participant: self.participant,
+            order_id,
+            book_id: message.require_u64(tag::SYMBOL)?,
+            side: side_of(message)?,
+            gateway_id: self.gateway_id,
+            session_id: self.session_id,
+            orig_client_order_id: 0,
+        };
+        self.cancels.insert(
+            order_id,
+            PendingCancel {
+                cl_ord_id: String::from(cl_ord_id),
+                orig_cl_ord_id: String::from(message.get(tag::ORIG_CL_ORD_ID).unwrap_or_default()),
+            },
+        );
+        Ok(self.sequenced(MsgType::Cancel, CANCEL_SIZE, &cancel.encode()))
+    }
+
+    fn replace(&mut self, message: &FixMessage) -> Result<Vec<u8>, SessionReject> {
+        let terms = OrderTerms::of(message)?;
+        let replace = Replace {
+            orig_order_id: message.require_u64(tag::ORDER_ID)?,
+            client_order_id: message.require_u64(tag::CL_ORD_ID)?,
+            participant: self.participant,
+            book_id: terms.book_id,
+            quantity: terms.quantity,
+            price: terms.price,
+            order_type: terms.order_type.into(),
+            side: terms.side,
+            gateway_id: self.gateway_id,
+            session_id: self.session_id,
+            expiry: terms.expiry,
+            stop_price: terms.stop_price,
+            display_quantity: terms.display_quantity,
+        };
+        Ok(self.sequenced(MsgType::Replace, REPLACE_SIZE, &replace.encode()))
+    }
+
+    fn on_execution_report(&mut self, ereport: &ExecutionReport, seq: u32) -> Vec<u8> {
+        if self.state != State::LoggedOn || ereport.state > OrderState::TradeBusted.into() {
+            return vec![];
+        }
+        let (order_id, orig_order_id) = (ereport.order_id, ereport.orig_order_id);
+        let state = OrderState::from(ereport.state);
+        let replaced = orig_order_id != 0;
+
+        if state == OrderState::Cancelled && replaced && order_id == orig_order_id {
+            // the old half of a replace, reported along with the new one
+            return vec![];
+        }
+        if state == OrderState::Rejected && (replaced || self.cancels.contains_key(&order_id)) {
+            return self.cancel_reject(ereport);
+        }
+
+        // what the order carries over from the one it replaced, if any
+        let replaced_info = match replaced {
+            true => self.orders.remove(&orig_order_id),
+            false => None,
+        };
+        let submitted = ereport.submitted_order_id;
+        let info = self
+            .orders
+            .entry(order_id)
+            .or_insert_with(|| match &replaced_info {
+                Some(old) => OrderInfo {
+                    cl_ord_id: submitted.to_string(),
+                    ..old.clone()
+                },
+                None => OrderInfo {
+                    cl_ord_id: submitted.to_string(),
+                    ..Default::default()
+                },
+            });
+        let filled = ereport.filled_quantity;
+        if filled > 0 {
+            info.cum_qty += filled;
+            info.notional += filled as u128 * ereport.price as u128;
+        }
+        // the quantity of a trade busted no longer counts as filled
+        let busted = match state {
+            OrderState::TradeBusted => ereport.quantity,
+            _ => 0,
+        };
+        if busted > 0 {
+            info.cum_qty = info.cum_qty.saturating_sub(busted);
+            info.notional = info
+                .notional
+                .saturating_sub(busted as u128 * ereport.price as u128);
+        }
+        let (mut cl_ord_id, mut orig_cl_ord_id) = (info.cl_ord_id.clone(), None);
+        if let Some(old) = &replaced_info {
+            orig_cl_ord_id = Some(old.cl_ord_id.clone());
+        }
+        let (cum_qty, avg_px) = (info.cum_qty, info.avg_px());
+        let open = if cum_qty > 0 { "1" } else { "0" };
+
+        let (exec_type, ord_status) = match state {
+            OrderState::Inserted if !replaced => ("0", open),
+            OrderState::Inserted | OrderState::Modified => ("5", open),
+            OrderState::Cancelled => {
+                if let Some(cancel) = self.cancels.remove(&order_id) {
+                    orig_cl_ord_id = Some(cl_ord_id);
+                    cl_ord_id = cancel.cl_ord_id;
+                    if !cancel.orig_cl_ord_id.is_empty() {
+                        orig_cl_ord_id = Some(cancel.orig_cl_ord_id);
+                    }
+                }
+                ("4", "4")
+            }
+            OrderState::Rejected => ("8", "8"),
+            OrderState::Traded => ("F", "2"),
+            OrderState::PartiallyTraded => ("F", "1"),
+            // the quotes can't be sent over FIX
+            OrderState::QuoteAck => return vec![],
+            OrderState::TradeBusted => match (ereport.leaves_quantity, cum_qty) {
+                (0, 0) => ("H", "4"),
+                (0, _) => ("H", "2"),
+                _ => ("H", open),
+            },
+        };
+        if matches!(
+            state,
+            OrderState::Cancelled | OrderState::Rejected | OrderState::Traded
+        ) || (busted > 0 && ereport.leaves_quantity == 0)
+        {
+            self.orders.remove(&order_id);
+        }
+
+        let mut message = self
+            .header(msg_type::EXECUTION_REPORT)
+            .with(tag::ORDER_ID, order_id)
+            .with(tag::CL_ORD_ID, cl_ord_id);
+        if let Some(orig_cl_ord_id) = orig_cl_ord_id {
+            message = message.with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id);
+        }
+        message = message
+            .with(tag::EXEC_ID, seq)
+            .with(tag::EXEC_TYPE, exec_type)
+            .with(tag::ORD_STATUS, ord_status)
+            .with(tag::SYMBOL, ereport.get_book())
+            .with(tag::SIDE, fix_side(ereport.side))
+            .with(tag::ORDER_QTY, ereport.quantity)
+            .with(tag::PRICE, ereport.get_price());
+        if filled > 0 || busted > 0 {
+            message = message
+                .with(tag::LAST_QTY, filled.max(busted))
+                .with(tag::LAST_PX, ereport.get_price());
+        }
+        if ereport.trade_id != 0 {
+            message = message.with(tag::TRD_MATCH_ID, ereport.get_trade_id());
+        }
+        message = message
+            .with(tag::LEAVES_QTY, ereport.leaves_quantity)
+            .with(tag::CUM_QTY, cum_qty)
+            .with(tag::AVG_PX, avg_px);
+        if state == OrderState::Rejected {
+            message = message
+                .with(tag::ORD_REJ_REASON, 99)
+                .with(tag::TEXT, reject_text(ereport.reject_reason));
+        }
+        self.encode(message)
+    }
+
+    // the rejection of an OrderCancelRequest or an OrderCancelReplaceRequest
+    fn cancel_reject(&mut self, ereport: &ExecutionReport) -> Vec<u8> {
+        let (order_id, orig_order_id) = (ereport.order_id, ereport.orig_order_id);
+        let (order_id, cl_ord_id, response_to) = match orig_order_id {
+            0 => {
+                let cancel = self.cancels.remove(&order_id);
+                (order_id, cancel.map(|c| c.cl_ord_id).unwrap_or_default(), 1)
+            }
+            _ => (orig_order_id, { ereport.submitted_order_id }.to_string(), 2),
+        };
+        let (orig_cl_ord_id, ord_status) = match self.orders.get(&order_id) {
+            Some(info) => (
+                info.cl_ord_id.clone(),
+                if info.cum_qty > 0 { "1" } else { "0" },
+            ),
+            // gone or never there
+            None => (String::from("NONE"), "8"),
+        };
+        let message = self
+            .header(msg_type::ORDER_CANCEL_REJECT)
+            .with(tag::ORDER_ID, order_id)
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id)
+            .with(tag::ORD_STATUS, ord_status)
+            .with(tag::CXL_REJ_RESPONSE_TO, response_to)
+            .with(tag::TEXT, reject_text(ereport.reject_reason));
+        self.encode(message)
+    }
+
+    fn reject(&mut self, message: &FixMessage, reason: SessionReject) -> Vec<u8> {
+        let (code, ref_tag) = reason.reason();
+        let mut reject = self
+            .header(msg_type::REJECT)
+            .with(
+                tag::REF_SEQ_NUM,
+                message.get(tag::MSG_SEQ_NUM).unwrap_or_default(),
+            )
+            .with(tag::REF_MSG_TYPE, message.msg_type());
+        if let Some(ref_tag) = ref_tag {
+            reject = reject.with(tag::REF_TAG_ID, ref_tag);
+        }
+        let reject = reject
+            .with(tag::SESSION_REJECT_REASON, code)
+            .with(tag::TEXT, reason);
+        self.encode(reject)
+    }
+
+    fn oep_heartbeat(&self) -> Vec<u8> {
+        let heartbeat = Heartbeat::new(self.participant, self.session_id, self.gateway_id);
+        frame(MsgType::Heartbeat, HEARTBEAT_SIZE, 0, &heartbeat.encode())
+    }
+
+    fn sequenced(&mut self, msg_type: MsgType, size: usize, body: &[u8]) -> Vec<u8> {
+        let seq = self.next_oep_seq;
+        self.next_oep_seq += 1;
+        frame(msg_type, size, seq, body)
+    }
+
+    // the standard header of the next message sent to the client
+    fn header(&mut self, msg_type: &str) -> FixMessage {
+        let seq = self.next_out;
+        self.next_out += 1;
+        FixMessage::new(msg_type)
+            .with(tag::SENDER_COMP_ID, &self.config.comp_id)
+            .with(tag::TARGET_COMP_ID, &self.client_comp_id)
+            .with(tag::MSG_SEQ_NUM, seq)
+            .with(tag::SENDING_TIME, utc_timestamp(SystemTime::now()))
+    }
+
+    fn encode(&mut self, message: FixMessage) -> Vec<u8> {
+        self.last_sent = Instant::now();
+        message.encode()
+    }
+}
+
+fn frame(msg_type: MsgType, size: usize, seq: u32, body: &[u8]) -> Vec<u8> {
+    let header = OepHeader::new(OEP_VERSION, msg_type.into(), size as u32).with_seq(seq);
+    [header.encode().as_slice(), body].concat()
+}
+
+fn side_of(message: &FixMessage) -> Result<u8, SessionReject> {
+    match message.require(tag::SIDE)? {
+        "1" => Ok(0),
+        "2" => Ok(1),
+        _ => Err(SessionReject::ValueIncorrect(tag::SIDE)),
+    }
+}
+
+fn fix_side(side: u8) -> &'static str {
+    match side {
+        0 => "1",
+        _ => "2",
+    }
+}
+
+fn reject_text(reason: u8) -> String {
+    format!("{:?}", RejectReason::from(reason))
+}
+
+// the fields shared by the NewOrderSingle and the OrderCancelReplaceRequest
+struct OrderTerms {
+    book_id: u64,
+    side: u8,
+    quantity: u64,
+    order_type: OrderType,
+    price: u64,
+    expiry: u64,
+    stop_price: u64,
+    display_quantity: u64,
+}
+
+impl OrderTerms {
+    fn of(message: &FixMessage) -> Result<Self, SessionReject> {
+        let price = || message.require_u64(tag::PRICE);
+        let stop_price = || message.require_u64(tag::STOP_PX);
+        let (order_type, price, stop_price, expiry) = match message.require(tag::ORD_TYPE)? {
+            "1" => (OrderType::Market, 0, 0, 0),
+            "3" => (OrderType::StopLoss, 0, stop_price()?, 0),
+            "4" => (OrderType::StopLimit, price()?, stop_price()?, 0),
+            "2" if message.get(tag::EXEC_INST).is_some_and(|i| i.contains('6')) => {
+                (OrderType::PostOrKill, price()?, 0, 0)
+            }
+            "2" => match message.get(tag::TIME_IN_FORCE).unwrap_or("0") {
+                "0" => (OrderType::Day, price()?, 0, 0),
+                "1" => (OrderType::GoodTillCancel, price()?, 0, 0),
+                "3" => (OrderType::FillAndKill, price()?, 0, 0),
+                "4" => (OrderType::FillOrKill, price()?, 0, 0),
+                "6" => {
+                    let expiry = parse_utc_timestamp(message.require(tag::EXPIRE_TIME)?)
+                        .ok_or(SessionReject::IncorrectDataFormat(tag::EXPIRE_TIME))?;
+                    (OrderType::GoodTillDate, price()?, 0, expiry)
+                }
+                _ => return Err(SessionReject::ValueIncorrect(tag::TIME_IN_FORCE)),
+            },
+            _ => return Err(SessionReject::ValueIncorrect(tag::ORD_TYPE)),
+        };
+        Ok(Self {
+            book_id: message.require_u64(tag::SYMBOL)?,
+            side: side_of(message)?,
+            quantity: message.require_u64(tag::ORDER_QTY)?,
+            order_type,
+            price,
+            expiry,
+            stop_price,
+            display_quantity: message.optional_u64(tag::MAX_SHOW)?.unwrap_or(0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+
+    use oep::{
+        cancel::Cancel,
+        decoder::Decoder,
+        execution_report::{ExecutionReport, RejectReason, EXECUTIONREPORT_SIZE},
+        header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+        login::{Login, LOGIN_SIZE},
+        neworder::NewOrder,
+        oep_decode,
+        oep_message::{MsgType, OepMessage},
+        replace::Replace,
+    };
+    use order::{OrderState, OrderType};
+
+    use super::{frame, FixSession, Translated};
+    use crate::{
+        config::FixConfig,
+        message::{msg_type, tag, FixMessage},
+    };
+
+    const GATEWAY_ID: u8 = 1;
+    const SESSION_ID: u32 = 100;
+    const PARTICIPANT: u64 = 42;
+
+    fn new_target() -> FixSession {
+        let config = FixConfig {
+            comp_id: String::from("EXCHANGE"),
+            sessions: HashMap::from([(String::from("BROKER1"), SESSION_ID)]),
+        };
+        FixSession::new(config, GATEWAY_ID)
+    }
+
+    fn fix(msg_type: &str, seq: u64) -> FixMessage {
+        FixMessage::new(msg_type)
+            .with(tag::SENDER_COMP_ID, "BROKER1")
+            .with(tag::TARGET_COMP_ID, "EXCHANGE")
+            .with(tag::MSG_SEQ_NUM, seq)
+    }
+
+    fn new_order(seq: u64, cl_ord_id: u64) -> FixMessage {
+        fix(msg_type::NEW_ORDER_SINGLE, seq)
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::SYMBOL, 7)
+            .with(tag::SIDE, 1)
+            .with(tag::ORDER_QTY, 100)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, 1000)
+    }
+
+    fn translate(target: &mut FixSession, message: FixMessage) -> Translated {
+        target.receive(&message.encode());
+        target.next_input().unwrap().unwrap()
+    }
+
+    fn decoded(mut bytes: &[u8]) -> Vec<FixMessage> {
+        let mut r = vec![];
+        while let Some((message, len)) = FixMessage::decode(bytes).unwrap() {
+            r.push(message);
+            bytes = &bytes[len..];
+        }
+        assert!(bytes.is_empty());
+        r
+    }
+
+    fn oep(bytes: &[u8]) -> (OepHeader, Box<dyn OepMessage>) {
+        let header = OepHeader::decode(bytes[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+        (header, oep_decode(bytes).unwrap())
+    }
+
+    fn ereport(order_id: u64, submitted: u64, state: OrderState) -> ExecutionReport {
+        ExecutionReport {
+            participant: PARTICIPANT,
+            order_id,
+            submitted_order_id: submitted,
+            book: 7,
+            quantity: 100,
+            price: 1000,
+            flags: 0,
+            side: 0,
+            state: state.into(),
+            session_id: SESSION_ID,
+            gateway_id: GATEWAY_ID,
+            filled_quantity: 0,
+            leaves_quantity: 100,
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
+        }
+    }
+
+    fn report(target: &mut FixSession, seq: u32, ereport: ExecutionReport) -> Vec<FixMessage> {
+        let bytes = frame(
+            MsgType::ExecutionReport,
+            EXECUTIONREPORT_SIZE,
+            seq,
+            &ereport.encode(),
+        );
+        decoded(&target.on_oep_data(&bytes))
+    }
+
+    // logged on, the OEP sequence expected next being 5
+    fn logged_on() -> FixSession {
+        let mut target = new_target();
+        let logon = fix(msg_type::LOGON, 1)
+            .with(tag::HEART_BT_INT, 10)
+            .with(tag::USERNAME, "user1")
+            .with(tag::PASSWORD, "secret");
+        let translated = translate(&mut target, logon);
+        let (_, login) = oep(&translated.oep);
+        let mut echo = *login.as_any().downcast_ref::<Login>().unwrap();
+        echo.participant = PARTICIPANT;
+        let bytes = frame(MsgType::Login, LOGIN_SIZE, 5, &echo.encode());
+        assert_eq!(1, decoded(&target.on_oep_data(&bytes)).len());
+        target
+    }
+
+    #[test]
+    fn logon() {
+        let mut target = new_target();
+        let logon = fix(msg_type::LOGON, 1)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, 10)
+            .with(tag::USERNAME, "user1")
+            .with(tag::PASSWORD, "secret");
+        let translated = translate(&mut target, logon);
+        assert!(translated.reply.is_empty());
+        assert!(!translated.disconnect);
+        let (header, login) = oep(&translated.oep);
+        assert_eq!(OEP_VERSION, { header.oep_version });
+        let login = login.as_any().downcast_ref::<Login>().unwrap();
+        assert_eq!(SESSION_ID, { login.session_id });
+        assert_eq!(GATEWAY_ID, login.get_gateway_id());
+        assert_eq!(b"user1\0", &login.user[..6]);
+        assert_eq!(Login::free_text_hash("secret"), login.password);
+        // answered once the gateway accepts the login
+        assert!(!target.is_logged_on());
+
+        // split in two
+        let mut echo = *login;
+        echo.participant = PARTICIPANT;
+        let bytes = frame(MsgType::Login, LOGIN_SIZE, 5, &echo.encode());
+        assert!(target.on_oep_data(&bytes[..10]).is_empty());
+        let reply = decoded(&target.on_oep_data(&bytes[10..]));
+        assert!(target.is_logged_on());
+        assert_eq!(1, reply.len());
+        assert_eq!(msg_type::LOGON, reply[0].msg_type());
+        assert_eq!(Some("EXCHANGE"), reply[0].get(tag::SENDER_COMP_ID));
+        assert_eq!(Some("BROKER1"), reply[0].get(tag::TARGET_COMP_ID));
+        assert_eq!(Some("1"), reply[0].get(tag::MSG_SEQ_NUM));
+        assert_eq!(Some("10"), reply[0].get(tag::HEART_BT_INT));
+
+        // the orders carry the participant, in the OEP sequence of the gateway
+        let translated = translate(&mut target, new_order(2, 11));
+        let (header, order) = oep(&translated.oep);
+        assert_eq!(5, { header.seq });
+        assert_eq!(PARTICIPANT, order.get_participant());
+    }
+
+    #[test]
+    fn refused_logons() {
+        // unknown CompID
+        let mut target = new_target();
+        let logon = FixMessage::new(msg_type::LOGON)
+            .with(tag::SENDER_COMP_ID, "BROKER9")
+            .with(tag::TARGET_COMP_ID, "EXCHANGE")
+            .with(tag::MSG_SEQ_NUM, 1)
+            .with(tag::HEART_BT_INT, 10)
+            .with(tag::USERNAME, "user1");
+        let translated = translate(&mut target, logon);
+        assert!(translated.disconnect);
+        assert!(translated.oep.is_empty());
+        let reply = decoded(&translated.reply);
+        assert_eq!(msg_type::LOGOUT, reply[0].msg_type());
+        assert_eq!(Some("BROKER9"), reply[0].get(tag::TARGET_COMP_ID));
+        assert!(target.next_input().is_err());
+
+        // no user
+        let mut target = new_target();
+        let translated = translate(
+            &mut target,
+            fix(msg_type::LOGON, 1).with(tag::HEART_BT_INT, 10),
+        );
+        assert!(translated.disconnect);
+        assert!(translated.oep.is_empty());
+
+        // orders first
+        let mut target = new_target();
+        let translated = translate(&mut target, new_order(1, 11));
+        assert!(translated.disconnect);
+        assert!(translated.oep.is_empty());
+
+        // the gateway refusing the login
+        let mut target = new_target();
+        let logon = fix(msg_type::LOGON, 1)
+            .with(tag::HEART_BT_INT, 10)
+            .with(tag::USERNAME, "user1");
+        assert!(!translate(&mut target, logon).oep.is_empty());
+        let reply = decoded(&target.logout("Logon refused"));
+        assert_eq!(msg_type::LOGOUT, reply[0].msg_type());
+        assert_eq!(Some("Logon refused"), reply[0].get(tag::TEXT));
+        assert!(target.logout("again").is_empty());
+    }
+
+    #[test]
+    fn orders() {
+        let mut target = logged_on();
+        let order = new_order(2, 11)
+            .with(tag::TIME_IN_FORCE, 6)
+            .with(tag::EXPIRE_TIME, "20240229-23:59:59")
+            .with(tag::MAX_SHOW, 10);
+        let (header, order) = oep(&translate(&mut target, order).oep);
+        assert_eq!(5, { header.seq });
+        let order = order.as_any().downcast_ref::<NewOrder>().unwrap();
+        assert_eq!(11, { order.client_order_id });
+        assert_eq!(7, { order.book_id });
+        assert_eq!(0, order.side);
+        assert_eq!(100, { order.quantity });
+        assert_eq!(1000, { order.price });
+        assert_eq!(OrderType::GoodTillDate, { order.order_type }.into());
+        assert_eq!(1709251199, { order.expiry });
+        assert_eq!(10, { order.display_quantity });
+        assert_eq!(SESSION_ID, { order.session_id });
+
+        let stop = fix(msg_type::NEW_ORDER_SINGLE, 3)
+            .with(tag::CL_ORD_ID, 12)
+            .with(tag::SYMBOL, 7)
+            .with(tag::SIDE, 2)
+            .with(tag::ORDER_QTY, 5)
+            .with(tag::ORD_TYPE, 3)
+            .with(tag::STOP_PX, 900);
+        let (_, order) = oep(&translate(&mut target, stop).oep);
+        let order = order.as_any().downcast_ref::<NewOrder>().unwrap();
+        assert_eq!(1, order.side);
+        assert_eq!(OrderType::StopLoss, { order.order_type }.into());
+        assert_eq!(900, { order.stop_price });
+
+        let cancel = fix(msg_type::ORDER_CANCEL_REQUEST, 4)
+            .with(tag::ORIG_CL_ORD_ID, 11)
+            .with(tag::CL_ORD_ID, 13)
+            .with(tag::ORDER_ID, 1001)
+            .with(tag::SYMBOL, 7)
+            .with(tag::SIDE, 1);
+        let (header, cancel) = oep(&translate(&mut target, cancel).oep);
+        assert_eq!(7, { header.seq });
+        let cancel = cancel.as_any().downcast_ref::<Cancel>().unwrap();
+        assert_eq!(1001, { cancel.order_id });
+        assert_eq!(PARTICIPANT, { cancel.participant });
+
+        let replace = fix(msg_type::ORDER_CANCEL_REPLACE_REQUEST, 5)
+            .with(tag::ORDER_ID, 1001)
+            .with(tag::CL_ORD_ID, 14)
+            .with(tag::SYMBOL, 7)
+            .with(tag::SIDE, 1)
+            .with(tag::ORDER_QTY, 50)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, 1010)
+            .with(tag::TIME_IN_FORCE, 3);
+        let (header, replace) = oep(&translate(&mut target, replace).oep);
+        assert_eq!(8, { header.seq });
+        let replace = replace.as_any().downcast_ref::<Replace>().unwrap();
+        assert_eq!(1001, { replace.orig_order_id });
+        assert_eq!(14, { replace.client_order_id });
+        assert_eq!(50, { replace.quantity });
+        assert_eq!(OrderType::FillAndKill, { replace.order_type }.into());
+    }
+
+    #[test]
+    fn session_rejects() {
+        let mut target = logged_on();
+        // no price, nothing goes to the gateway, nor takes an OEP sequence
+        let order = fix(msg_type::NEW_ORDER_SINGLE, 2)
+            .with(tag::CL_ORD_ID, 11)
+            .with(tag::SYMBOL, 7)
+            .with(tag::SIDE, 1)
+            .with(tag::ORDER_QTY, 100)
+            .with(tag::ORD_TYPE, 2);
+        let translated = translate(&mut target, order);
+        assert!(translated.oep.is_empty());
+        assert!(!translated.disconnect);
+        let reply = decoded(&translated.reply);
+        assert_eq!(msg_type::REJECT, reply[0].msg_type());
+        assert_eq!(Some("2"), reply[0].get(tag::REF_SEQ_NUM));
+        assert_eq!(Some("D"), reply[0].get(tag::REF_MSG_TYPE));
+        assert_eq!(Some("44"), reply[0].get(tag::REF_TAG_ID));
+        assert_eq!(Some("1"), reply[0].get(tag::SESSION_REJECT_REASON));
+
+        let order = new_order(3, 11).with(tag::TIME_IN_FORCE, 9);
+        let reply = decoded(&translate(&mut target, order).reply);
+        assert_eq!(Some("59"), reply[0].get(tag::REF_TAG_ID));
+        assert_eq!(Some("5"), reply[0].get(tag::SESSION_REJECT_REASON));
+
+        let order = new_order(4, 11).with(tag::EXPIRE_TIME, "tomorrow");
+        let order = order.with(tag::TIME_IN_FORCE, 6);
+        let reply = decoded(&translate(&mut target, order).reply);
+        assert_eq!(Some("126"), reply[0].get(tag::REF_TAG_ID));
+        assert_eq!(Some("6"), reply[0].get(tag::SESSION_REJECT_REASON));
+
+        let reply = decoded(&translate(&mut target, fix("AE", 5)).reply);
+        assert_eq!(Some("11"), reply[0].get(tag::SESSION_REJECT_REASON));
+        assert_eq!(None, reply[0].get(tag::REF_TAG_ID));
+
+        let (header, _) = oep(&translate(&mut target, new_order(6, 11)).oep);
+        assert_eq!(5, { header.seq });
+    }
+
+    #[test]
+    fn session_messages() {
+        let mut target = logged_on();
+        let request = fix(msg_type::TEST_REQUEST, 2).with(tag::TEST_REQ_ID, "ping");
+        let translated = translate(&mut target, request);
+        assert!(translated.oep.is_empty());
+        let reply = decoded(&translated.reply);
+        assert_eq!(msg_type::HEARTBEAT, reply[0].msg_type());
+        assert_eq!(Some("ping"), reply[0].get(tag::TEST_REQ_ID));
+        assert_eq!(Some("2"), reply[0].get(tag::MSG_SEQ_NUM));
+
+        // keeps the OEP session alive
+        let (header, _) = oep(&translate(&mut target, fix(msg_type::HEARTBEAT, 3)).oep);
+        assert_eq!(MsgType::Heartbeat, header.message_type());
+
+        // nothing is sent again
+        let request = fix(msg_type::RESEND_REQUEST, 4)
+            .with(tag::BEGIN_SEQ_NO, 1)
+            .with(tag::END_SEQ_NO, 0);
+        let reply = decoded(&translate(&mut target, request).reply);
+        assert_eq!(msg_type::SEQUENCE_RESET, reply[0].msg_type());
+        assert_eq!(Some("3"), reply[0].get(tag::MSG_SEQ_NUM));
+        assert_eq!(Some("4"), reply[0].get(tag::NEW_SEQ_NO));
+
+        // moving the sequence forward
+        let reset = fix(msg_type::SEQUENCE_RESET, 1).with(tag::NEW_SEQ_NO, 10);
+        assert_eq!(Translated::default(), translate(&mut target, reset));
+        // a duplicate is dropped
+        let duplicate = fix(msg_type::HEARTBEAT, 9).with(tag::POSS_DUP_FLAG, "Y");
+        assert_eq!(Translated::default(), translate(&mut target, duplicate));
+        assert!(!translate(&mut target, fix(msg_type::HEARTBEAT, 10)).disconnect);
+
+        // heartbeats when nothing else goes out
+        let now = Instant::now();
+        assert_eq!(None, target.heartbeat_due(now));
+        let heartbeat = target.heartbeat_due(now + Duration::from_secs(10)).unwrap();
+        assert_eq!(msg_type::HEARTBEAT, decoded(&heartbeat)[0].msg_type());
+
+        let translated = translate(&mut target, fix(msg_type::LOGOUT, 11));
+        assert!(translated.disconnect);
+        assert_eq!(msg_type::LOGOUT, decoded(&translated.reply)[0].msg_type());
+        assert_eq!(None, target.heartbeat_due(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn sequence_gaps() {
+        let mut target = logged_on();
+        let translated = translate(&mut target, new_order(3, 11));
+        assert!(translated.disconnect);
+        assert!(translated.oep.is_empty());
+        let reply = decoded(&translated.reply);
+        assert_eq!(msg_type::LOGOUT, reply[0].msg_type());
+        assert_eq!(
+            Some("MsgSeqNum too high, expecting 2"),
+            reply[0].get(tag::TEXT)
+        );
+
+        let mut target = logged_on();
+        let translated = translate(&mut target, new_order(1, 11));
+        assert!(translated.disconnect);
+        assert!(translated.oep.is_empty());
+
+        // someone else's
+        let mut target = logged_on();
+        let order = FixMessage::new(msg_type::HEARTBEAT)
+            .with(tag::SENDER_COMP_ID, "BROKER2")
+            .with(tag::TARGET_COMP_ID, "EXCHANGE")
+            .with(tag::MSG_SEQ_NUM, 2);
+        assert!(translate(&mut target, order).disconnect);
+    }
+
+    #[test]
+    fn trade_busts() {
+        let mut target = logged_on();
+        report(&mut target, 20, ereport(1001, 11, OrderState::Inserted));
+        let fill = ExecutionReport {
+            filled_quantity: 40,
+            leaves_quantity: 60,
+            ..ereport(1001, 1001, OrderState::PartiallyTraded)
+        };
+        report(&mut target, 21, fill);
+
+        // the order is open again, as if it never traded
+        let bust = ExecutionReport {
+            quantity: 40,
+            leaves_quantity: 60,
+            ..ereport(1001, 1001, OrderState::TradeBusted)
+        };
+        let reply = report(&mut target, 22, bust);
+        assert_eq!(Some("11"), reply[0].get(tag::CL_ORD_ID));
+        assert_eq!(Some("H"), reply[0].get(tag::EXEC_TYPE));
+        assert_eq!(Some("0"), reply[0].get(tag::ORD_STATUS));
+        assert_eq!(Some("40"), reply[0].get(tag::LAST_QTY));
+        assert_eq!(Some("1000"), reply[0].get(tag::LAST_PX));
+        assert_eq!(Some("0"), reply[0].get(tag::CUM_QTY));
+
+        // traded in full, then busted: nothing was filled in the end
+        let fill = ExecutionReport {
+            filled_quantity: 60,
+            leaves_quantity: 0,
+            ..ereport(1001, 1001, OrderState::Traded)
+        };
+        report(&mut target, 23, fill);
+        let bust = ExecutionReport {
+            quantity: 60,
+            leaves_quantity: 0,
+            ..ereport(1001, 1001, OrderState::TradeBusted)
+        };
+        let reply = report(&mut target, 24, bust);
+        assert_eq!(Some("H"), reply[0].get(tag::EXEC_TYPE));
+        assert_eq!(Some("4"), reply[0].get(tag::ORD_STATUS));
+    }
+
+    #[test]
+    fn execution_reports() {
+        let mut target = logged_on();
+
+        let reply = report(&mut target, 20, ereport(1001, 11, OrderState::Inserted));
+        assert_eq!(1, reply.len());
+        assert_eq!(msg_type::EXECUTION_REPORT, reply[0].msg_type());
+        assert_eq!(Some("1001"), reply[0].get(tag::ORDER_ID));
+        assert_eq!(Some("11"), reply[0].get(tag::CL_ORD_ID));
+        assert_eq!(Some("20"), reply[0].get(tag::EXEC_ID));
+        assert_eq!(Some("0"), reply[0].get(tag::EXEC_TYPE));
+        assert_eq!(Some("0"), reply[0].get(tag::ORD_STATUS));
+        assert_eq!(Some("7"), reply[0].get(tag::SYMBOL));
+        assert_eq!(Some("1"), reply[0].get(tag::SIDE));
+        assert_eq!(Some("100"), reply[0].get(tag::LEAVES_QTY));
+        assert_eq!(Some("0"), reply[0].get(tag::CUM_QTY));
+        assert_eq!(None, reply[0].get(tag::LAST_QTY));
+        assert_eq!(None, reply[0].get(tag::TRD_MATCH_ID));
+
+        // passive fills, carrying the exchange id
+        let fill = ExecutionReport {
+            filled_quantity: 40,
+            leaves_quantity: 60,
+            price: 1000,
+            trade_id: 5,
+            ..ereport(1001, 1001, OrderState::PartiallyTraded)
+        };
+        let reply = report(&mut target, 21, fill);
+        assert_eq!(Some("11"), reply[0].get(tag::CL_ORD_ID));
+        assert_eq!(Some("5"), reply[0].get(tag::TRD_MATCH_ID));
+        assert_eq!(Some("F"), reply[0].get(tag::EXEC_TYPE));
+        assert_eq!(Some("1"), reply[0].get(tag::ORD_STATUS));
+        assert_eq!(Some("40"), reply[0].get(tag::LAST_QTY));
+        assert_eq!(Some("1000"), reply[0].get(tag::LAST_PX));
+        assert_eq!(Some("40"), reply[0].get(tag::CUM_QTY));
+        assert_eq!(Some("1000"), reply[0].get(tag::AVG_PX));
+        let fill = ExecutionReport {
+            filled_quantity: 10,
+            leaves_quantity: 50,
+            price: 1005,
+            ..ereport(1001, 1001, OrderState::PartiallyTraded)
+        };
+        let reply = report(&mut target, 22, fill);
+        assert_eq!(Some("50"), reply[0].get(tag::CUM_QTY));
+        assert_eq!(Some("1001"), reply[0].get(tag::AVG_PX));
+
+        // replaced, reported once
+        let old = ExecutionReport {
+            orig_order_id: 1001,
+            leaves_quantity: 0,
+            ..ereport(1001, 1001, OrderState::Cancelled)
+        };
+        assert!(report(&mut target, 23, old).is_empty());
+        let new = ExecutionReport {
+            orig_order_id: 1001,
+            ..ereport(1002, 14, OrderState::Inserted)
+        };
+        let reply = report(&mut target, 24, new);
+        assert_eq!(1, reply.len());
+        assert_eq!(Some("1002"), reply[0].get(tag::ORDER_ID));
+        assert_eq!(Some("14"), reply[0].get(tag::CL_ORD_ID));
+        assert_eq!(Some("11"), reply[0].get(tag::ORIG_CL_ORD_ID));
+        assert_eq!(Some("5"), reply[0].get(tag::EXEC_TYPE));
+        assert_eq!(Some("1"), reply[0].get(tag::ORD_STATUS));
+        assert_eq!(Some("50"), reply[0].get(tag::CUM_QTY));
+
+        // cancelled on request
+        let cancel = fix(msg_type::ORDER_CANCEL_REQUEST, 2)
+            .with(tag::ORIG_CL_ORD_ID, 14)
+            .with(tag::CL_ORD_ID, 15)
+            .with(tag::ORDER_ID, 1002)
+            .with(tag::SYMBOL, 7)
+            .with(tag::SIDE, 1);
+        assert!(!translate(&mut target, cancel).oep.is_empty());
+        let cancelled = ExecutionReport {
+            leaves_quantity: 0,
+            ..ereport(1002, 1002, OrderState::Cancelled)
+        };
+        let reply = report(&mut target, 25, cancelled);
+        assert_eq!(Some("15"), reply[0].get(tag::CL_ORD_ID));
+        assert_eq!(Some("14"), reply[0].get(tag::ORIG_CL_ORD_ID));
+        assert_eq!(Some("4"), reply[0].get(tag::EXEC_TYPE));
+        assert_eq!(Some("4"), reply[0].get(tag::ORD_STATUS));
+
+        // rejects
+        let rejected = ExecutionReport {
+            reject_reason: RejectReason::Throttled.into(),
+            leaves_quantity: 0,
+            ..ereport(16, 16, OrderState::Rejected)
+        };
+        let reply = report(&mut target, 26, rejected);
+        assert_eq!(msg_type::EXECUTION_REPORT, reply[0].msg_type());
+        assert_eq!(Some("16"), reply[0].get(tag::CL_ORD_ID));
+        assert_eq!(Some("8"), reply[0].get(tag::EXEC_TYPE));
+        assert_eq!(Some("8"), reply[0].get(tag::ORD_STATUS));
+        assert_eq!(Some("Throttled"), reply[0].get(tag::TEXT));
+
+        let cancel = fix(msg_type::ORDER_CANCEL_REQUEST, 3)
+            .with(tag::CL_ORD_ID, 17)
+            .with(tag::ORDER_ID, 1003)
+            .with(tag::SYMBOL, 7)
+            .with(tag::SIDE, 1);
+        assert!(!translate(&mut target, cancel).oep.is_empty());
+        let reply = report(&mut target, 27, ereport(1003, 1003, OrderState::Rejected));
+        assert_eq!(msg_type::ORDER_CANCEL_REJECT, reply[0].msg_type());
+        assert_eq!(Some("17"), reply[0].get(tag::CL_ORD_ID));
+        assert_eq!(Some("1"), reply[0].get(tag::CXL_REJ_RESPONSE_TO));
+
+        let rejected = ExecutionReport {
+            orig_order_id: 1003,
+            ..ereport(18, 18, OrderState::Rejected)
+        };
+        let reply = report(&mut target, 28, rejected);
+        assert_eq!(msg_type::ORDER_CANCEL_REJECT, reply[0].msg_type());
+        assert_eq!(Some("1003"), reply[0].get(tag::ORDER_ID));
+        assert_eq!(Some("18"), reply[0].get(tag::CL_ORD_ID));
+        assert_eq!(Some("2"), reply[0].get(tag::CXL_REJ_RESPONSE_TO));
+    }
+}
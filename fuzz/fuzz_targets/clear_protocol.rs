@@ -0,0 +1,44 @@
+#![no_main]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use clearing_connection::clearprotocol::ClearProtocol;
+use clearing_connection::genericclearingprotocol::{GenericClearingProtocol, ProtocolSide};
+use disseminator::mockdisseminator::MockDisseminator;
+use instruments::genericinstrumentlist::GenericInstrumentList;
+use instruments::instrumentlist::InstrumentList;
+use libfuzzer_sys::fuzz_target;
+use market::{orderid::OrderIdGenerator, Market};
+
+// the first byte picks the side and the markets of the protocol, and whether
+// an engine logged on already, the rest is the buffer read off the connection
+fuzz_target!(|data: &[u8]| {
+    let Some((&setup, buffer)) = data.split_first() else {
+        return;
+    };
+    let mut target: Box<dyn GenericClearingProtocol> = match setup & 1 {
+        0 => Box::new(ClearProtocol::new(
+            InstrumentList::new(),
+            Arc::new(Mutex::new(HashMap::<u64, Market>::new())),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        )),
+        _ => Box::new(ClearProtocol::forwarding(InstrumentList::new())),
+    };
+    target.set_protocol_side(match setup & 2 {
+        0 => ProtocolSide::Client,
+        _ => ProtocolSide::Server,
+    });
+    if setup & 4 != 0 {
+        target.set_session(Some(0));
+    }
+    // as the connections do, dropping what was processed
+    let mut offset = 0;
+    while let Ok((_, processed)) = target.process(&buffer[offset..]) {
+        if processed == 0 {
+            break;
+        }
+        offset += processed;
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use oep::{messagebuffer::MessageBuffer, oep_decode};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = oep_decode(data);
+    // the same bytes as a stream, read in two pieces
+    let mut buffer = MessageBuffer::new();
+    let (first, second) = data.split_at(data.len() / 2);
+    for piece in [first, second] {
+        buffer.extend(piece);
+        while buffer.next_message().is_some() {}
+    }
+});
@@ -0,0 +1,509 @@
+//! Drop copy of the execution reports, for the risk and surveillance consumers
+//!
+//! A consumer connects to the drop copy port of the gateway and logs in with
+//! an OEP login, checked against the database the same as the one of a
+//! session. The participant it logs in as has to be listed in the [dropcopy]
+//! section, along with the participants it gets to see. From then on, it gets
+//! a copy of every execution report of those participants coming from the
+//! matching engines, the fills included, whatever the gateway and the session
+//! they are for. Nothing but heartbeats is expected from it afterwards.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    ops::ControlFlow,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Result};
+use dbhook::genericdb::GenericDB;
+use oep::{
+    decoder::Decoder,
+    execution_report::{execution_report_size, ExecutionReport},
+    header::{OepHeader, OEP_VERSION},
+    login::{Login, LOGIN_SIZE},
+    loginreject::{LoginReject, LoginRejectReason, LOGINREJECT_SIZE},
+    oep_message::{MsgType, OepMessage},
+    version::{negotiate_version, VersionReject, VERSIONREJECT_SIZE},
+};
+use serde::Deserialize;
+use tracing::{info, warn};
+use utils::config;
+
+use crate::server::{next_message, ClientWriter};
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+const SECTION: &str = "dropcopy";
+// participants_<consumer participant>=<participants seen>
+const PARTICIPANTS_PREFIX: &str = "participants_";
+
+/// The participants whose execution reports a consumer gets
+#[derive(Debug, Clone, PartialEq)]
+pub enum Participants {
+    All,
+    Only(HashSet<u64>),
+}
+
+impl Participants {
+    pub fn contains(&self, participant: u64) -> bool {
+        match self {
+            Participants::All => true,
+            Participants::Only(participants) => participants.contains(&participant),
+        }
+    }
+}
+
+impl FromStr for Participants {
+    type Err = anyhow::Error;
+
+    /// * for all of them, or a comma separated list of participants
+    fn from_str(s: &str) -> Result<Self> {
+        if s.trim() == "*" {
+            return Ok(Participants::All);
+        }
+        Ok(Participants::Only(
+            s.split(',')
+                .map(|p| p.trim().parse::<u64>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| anyhow!("Invalid participant list {s}"))?,
+        ))
+    }
+}
+
+/// The keys of the [dropcopy] section, the consumers aside
+#[derive(Debug, Deserialize)]
+struct DropCopySection {
+    address: String,
+    port: u16,
+}
+
+/// The [dropcopy] section
+#[derive(Debug, Clone, PartialEq)]
+pub struct DropCopyConfig {
+    pub address: String,
+    pub port: u16,
+    // the participant a consumer logs in as -> the participants it sees
+    pub consumers: HashMap<u64, Participants>,
+}
+
+impl DropCopyConfig {
+    /// Returns: None without a [dropcopy] section
+    pub fn from_config(config_map: &ConfigMap) -> Result<Option<Self>> {
+        let Some(section) = config_map.get(SECTION) else {
+            return Ok(None);
+        };
+        let listening: DropCopySection = config::section(config_map, SECTION)?;
+        let mut consumers = HashMap::new();
+        for (key, value) in section {
+            let Some(participant) = key.strip_prefix(PARTICIPANTS_PREFIX) else {
+                continue;
+            };
+            let participant = participant
+                .parse::<u64>()
+                .map_err(|_| anyhow!("Invalid drop copy consumer {key}"))?;
+            let participants = value.as_deref().unwrap_or_default().parse()?;
+            consumers.insert(participant, participants);
+        }
+        if consumers.is_empty() {
+            bail!("No drop copy consumer configured");
+        }
+        Ok(Some(Self {
+            address: listening.address,
+            port: listening.port,
+            consumers,
+        }))
+    }
+}
+
+struct Consumer {
+    writer: ClientWriter,
+    recv_buffer: Vec<u8>,
+    // once logged in
+    participants: Option<Participants>,
+    // of the last execution report sent
+    seq: u32,
+    // negotiated at login, the same as for a session
+    oep_version: u16,
+}
+
+/// The drop copy consumers of the gateway
+pub struct DropCopy {
+    allowed: HashMap<u64, Participants>,
+    // consumer id -> consumer
+    consumers: HashMap<usize, Consumer>,
+    next_consumer_id: usize,
+}
+
+impl DropCopy {
+    pub fn new(config: &DropCopyConfig) -> Self {
+        Self {
+            allowed: config.consumers.clone(),
+            consumers: HashMap::new(),
+            next_consumer_id: 1,
+        }
+    }
+
+    /// Returns: the id the newly connected consumer is known by from now on
+    pub fn add_consumer(&mut self, writer: ClientWriter) -> usize {
+        let consumer_id = self.next_consumer_id;
+        self.next_consumer_id += 1;
+        self.consumers.insert(
+            consumer_id,
+            Consumer {
+                writer,
+                recv_buffer: vec![],
+                participants: None,
+                seq: 0,
+                oep_version: OEP_VERSION,
+            },
+        );
+        consumer_id
+    }
+
+    pub fn remove_consumer(&mut self, consumer_id: usize) {
+        self.consumers.remove(&consumer_id);
+    }
+
+    /// Handles the bytes read from @consumer_id, the login being checked in @db
+    ///
+    /// Returns: Break if the consumer has to be disconnected
+    pub fn on_consumer_data(
+        &mut self,
+        db: &mut Box<dyn GenericDB>,
+        consumer_id: usize,
+        data: &[u8],
+    ) -> ControlFlow<()> {
+        let Some(consumer) = self.consumers.get_mut(&consumer_id) else {
+            return ControlFlow::Break(());
+        };
+        consumer.recv_buffer.extend_from_slice(data);
+        loop {
+            let (message, header) = match next_message(&mut consumer.recv_buffer) {
+                Ok(Some(next)) => next,
+                Ok(None) => return ControlFlow::Continue(()),
+                Err(e) => {
+                    warn!(consumer_id, error = %e, "Drop copy consumer sent an invalid message");
+                    return ControlFlow::Break(());
+                }
+            };
+            match (message.message_type(), &consumer.participants) {
+                (MsgType::Login, None) => {
+                    let login = message
+                        .as_any()
+                        .downcast_ref::<Login>()
+                        .expect("Bad pointer conversion");
+                    match negotiate_version(header.oep_version) {
+                        Ok(version) => consumer.oep_version = version,
+                        Err(e) => {
+                            warn!(consumer_id, error = %e, "Drop copy login failed");
+                            let reject = VersionReject::new(
+                                login.participant,
+                                login.session_id,
+                                login.get_gateway_id(),
+                                header.oep_version,
+                            );
+                            let header = OepHeader::new(
+                                OEP_VERSION,
+                                MsgType::VersionReject.into(),
+                                VERSIONREJECT_SIZE as u32,
+                            );
+                            let _ = consumer.writer.write_all(
+                                &[header.encode().as_slice(), &reject.encode()].concat(),
+                            );
+                            return ControlFlow::Break(());
+                        }
+                    }
+                    match login_consumer(&self.allowed, db, login) {
+                        Ok(participants) => consumer.participants = Some(participants),
+                        Err(e) => {
+                            warn!(consumer_id, error = %e, "Drop copy login failed");
+                            let reject = LoginReject::new(
+                                login.participant,
+                                login.session_id,
+                                login.get_gateway_id(),
+                                LoginRejectReason::BadCredentials,
+                            );
+                            let header = OepHeader::new(
+                                OEP_VERSION,
+                                MsgType::LoginReject.into(),
+                                LOGINREJECT_SIZE as u32,
+                            );
+                            let _ = consumer.writer.write_all(
+                                &[header.encode().as_slice(), &reject.encode()].concat(),
+                            );
+                            return ControlFlow::Break(());
+                        }
+                    }
+                    let header = OepHeader::new(
+                        consumer.oep_version,
+                        MsgType::Login.into(),
+                        LOGIN_SIZE as u32,
+                    );
+                    let reply = [header.encode().as_slice(), &login.encode()].concat();
+                    if consumer.writer.write_all(&reply).is_err() {
+                        return ControlFlow::Break(());
+                    }
+                }
+                (MsgType::Heartbeat, Some(_)) => {}
+                (msg_type, _) => {
+                    warn!(
+                        consumer_id,
+                        ?msg_type,
+                        "Drop copy consumer sent an unexpected message"
+                    );
+                    return ControlFlow::Break(());
+                }
+            }
+        }
+    }
+
+    /// Copies @ereport to the consumers allowed to see its participant, each
+    /// consumer numbering what it gets from 1
+    pub fn on_execution_report(&mut self, ereport: &ExecutionReport) {
+        let participant = ereport.participant;
+        let encoded = ereport.encode();
+        for consumer in self.consumers.values_mut() {
+            if !consumer
+                .participants
+                .as_ref()
+                .is_some_and(|p| p.contains(participant))
+            {
+                continue;
+            }
+            consumer.seq += 1;
+            // the fields added since the version of the consumer cut off
+            let size = execution_report_size(consumer.oep_version);
+            let header = OepHeader::new(
+                consumer.oep_version,
+                MsgType::ExecutionReport.into(),
+                size as u32,
+            )
+            .with_seq(consumer.seq);
+            // a consumer gone is removed by its reader
+            let _ = consumer
+                .writer
+                .write_all(&[header.encode().as_slice(), &encoded[..size]].concat());
+        }
+    }
+}
+
+/// The participants @login gets to see, once checked in @db
+fn login_consumer(
+    allowed: &HashMap<u64, Participants>,
+    db: &mut Box<dyn GenericDB>,
+    login: &Login,
+) -> Result<Participants> {
+    let user: Vec<u8> = login.user.iter().copied().take_while(|&c| c != 0).collect();
+    let user = String::from_utf8(user)?;
+    let participant = db.check_login(&user, &login.password, login.get_session_id())?;
+    match allowed.get(&participant) {
+        Some(participants) => {
+            info!(%user, participant, "Drop copy consumer logged in");
+            Ok(participants.clone())
+        }
+        None => bail!("Participant {participant} is not a drop copy consumer"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use oep::{
+        decoder::Decoder,
+        execution_report::{ExecutionReport, EXECUTIONREPORT_SIZE, EXECUTIONREPORT_V6_SIZE},
+        header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+        heartbeat::{Heartbeat, HEARTBEAT_SIZE},
+        login::{Login, LOGIN_SIZE},
+        loginreject::{LoginReject, LoginRejectReason, LOGINREJECT_SIZE},
+        oep_decode,
+        oep_message::MsgType,
+    };
+    use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+    use super::{DropCopy, DropCopyConfig, Participants};
+    use crate::server::ClientWriter;
+
+    // as logged in by the mock database
+    const CONSUMER: u64 = 111;
+
+    fn framed(msg_type: MsgType, len: usize, payload: &[u8]) -> Vec<u8> {
+        let header = OepHeader::new(OEP_VERSION, msg_type.into(), len as u32);
+        [header.encode().as_slice(), payload].concat()
+    }
+
+    fn login() -> Vec<u8> {
+        let login = Login::new(CONSUMER, 5, 1, "surveillance");
+        framed(MsgType::Login, LOGIN_SIZE, &login.encode())
+    }
+
+    fn ereport(participant: u64, gateway_id: u8) -> ExecutionReport {
+        let mut ereport = ExecutionReport::decode([0; EXECUTIONREPORT_SIZE]).unwrap();
+        ereport.participant = participant;
+        ereport.gateway_id = gateway_id;
+        ereport
+    }
+
+    // @consumer being allowed to see @participants
+    fn target(consumer: u64, participants: Participants) -> DropCopy {
+        DropCopy::new(&DropCopyConfig {
+            address: String::from("127.0.0.1"),
+            port: 10100,
+            consumers: HashMap::from([(consumer, participants)]),
+        })
+    }
+
+    fn connect(target: &mut DropCopy) -> (usize, UnboundedReceiver<Vec<u8>>) {
+        let (outgoing, to_send) = mpsc::unbounded_channel();
+        (target.add_consumer(ClientWriter::new(outgoing)), to_send)
+    }
+
+    // the participant and the sequence of the execution reports received
+    fn received(to_send: &mut UnboundedReceiver<Vec<u8>>) -> Vec<(u64, u32)> {
+        let mut r = vec![];
+        while let Ok(buf) = to_send.try_recv() {
+            let header = OepHeader::decode(buf[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+            assert_eq!(MsgType::ExecutionReport, header.message_type());
+            let message = oep_decode(&buf).unwrap();
+            let ereport = message.as_any().downcast_ref::<ExecutionReport>().unwrap();
+            r.push((ereport.participant, header.seq));
+        }
+        r
+    }
+
+    #[test]
+    fn copies_the_participants_allowed() {
+        let mut target = target(CONSUMER, Participants::Only(HashSet::from([1, 2])));
+        let mut db = dbhook::factory::build("mock");
+        let (consumer, mut to_send) = connect(&mut target);
+        // nothing before the login
+        target.on_execution_report(&ereport(1, 1));
+        assert!(to_send.try_recv().is_err());
+
+        assert!(target
+            .on_consumer_data(&mut db, consumer, &login())
+            .is_continue());
+        let reply = to_send.try_recv().unwrap();
+        assert_eq!(MsgType::Login, {
+            OepHeader::decode(reply[..OEP_HEADER_SIZE].try_into().unwrap())
+                .unwrap()
+                .message_type()
+        });
+        // whatever the gateway
+        for (participant, gateway_id) in [(1, 1), (3, 1), (2, 7)] {
+            target.on_execution_report(&ereport(participant, gateway_id));
+        }
+        assert_eq!(vec![(1, 1), (2, 2)], received(&mut to_send));
+
+        let heartbeat = Heartbeat::new(CONSUMER, 5, 1);
+        let heartbeat = framed(MsgType::Heartbeat, HEARTBEAT_SIZE, &heartbeat.encode());
+        assert!(target
+            .on_consumer_data(&mut db, consumer, &heartbeat)
+            .is_continue());
+        // logged in already
+        assert!(target
+            .on_consumer_data(&mut db, consumer, &login())
+            .is_break());
+
+        target.remove_consumer(consumer);
+        target.on_execution_report(&ereport(1, 1));
+        assert!(received(&mut to_send).is_empty());
+    }
+
+    #[test]
+    fn copies_in_the_version_of_the_consumer() {
+        let mut target = target(CONSUMER, Participants::All);
+        let mut db = dbhook::factory::build("mock");
+        let (consumer, mut to_send) = connect(&mut target);
+        let mut login = login();
+        login[..2].copy_from_slice(&6u16.to_le_bytes());
+        assert!(target
+            .on_consumer_data(&mut db, consumer, &login)
+            .is_continue());
+        to_send.try_recv().unwrap();
+
+        let mut copied = ereport(1, 1);
+        copied.trade_id = 99;
+        target.on_execution_report(&copied);
+        let buf = to_send.try_recv().unwrap();
+        // without the trade_id
+        assert_eq!(OEP_HEADER_SIZE + EXECUTIONREPORT_V6_SIZE, buf.len());
+        let header = OepHeader::decode(buf[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(6, { header.oep_version });
+        let message = oep_decode(&buf).unwrap();
+        let ereport = message.as_any().downcast_ref::<ExecutionReport>().unwrap();
+        assert_eq!(1, { ereport.participant });
+        assert_eq!(0, ereport.get_trade_id());
+    }
+
+    #[test]
+    fn refuses_the_others() {
+        let mut db = dbhook::factory::build("mock");
+        // the consumer logs in as another participant
+        let mut other = target(CONSUMER + 1, Participants::All);
+        let (consumer, mut to_send) = connect(&mut other);
+        assert!(other
+            .on_consumer_data(&mut db, consumer, &login())
+            .is_break());
+        let reply = to_send.try_recv().unwrap();
+        assert_eq!(OEP_HEADER_SIZE + LOGINREJECT_SIZE, reply.len());
+        let reject = LoginReject::decode(reply[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(LoginRejectReason::BadCredentials, reject.get_reason());
+
+        // a login first
+        let mut target = target(CONSUMER, Participants::All);
+        let (consumer, _to_send) = connect(&mut target);
+        let heartbeat = Heartbeat::new(CONSUMER, 5, 1);
+        let heartbeat = framed(MsgType::Heartbeat, HEARTBEAT_SIZE, &heartbeat.encode());
+        assert!(target
+            .on_consumer_data(&mut db, consumer, &heartbeat)
+            .is_break());
+    }
+
+    #[test]
+    fn config_from_ini() {
+        let config_map = configparser::ini::Ini::new()
+            .read(String::from(
+                "[dropcopy]
+                address=127.0.0.1
+                port=10100
+                participants_900=*
+                participants_901=1, 2,3",
+            ))
+            .unwrap();
+        let config = DropCopyConfig::from_config(&config_map).unwrap().unwrap();
+        assert_eq!(10100, config.port);
+        assert_eq!(Some(&Participants::All), config.consumers.get(&900));
+        assert_eq!(
+            Some(&Participants::Only(HashSet::from([1, 2, 3]))),
+            config.consumers.get(&901)
+        );
+
+        let config_map = configparser::ini::Ini::new()
+            .read(String::from("[gateway]\nid=1"))
+            .unwrap();
+        assert_eq!(None, DropCopyConfig::from_config(&config_map).unwrap());
+        let config_map = configparser::ini::Ini::new()
+            .read(String::from("[dropcopy]\naddress=127.0.0.1\nport=10100"))
+            .unwrap();
+        assert!(DropCopyConfig::from_config(&config_map).is_err());
+        let config_map = configparser::ini::Ini::new()
+            .read(String::from(
+                "[dropcopy]\naddress=127.0.0.1\nport=10100\nparticipants_900=1,x",
+            ))
+            .unwrap();
+        assert!(DropCopyConfig::from_config(&config_map).is_err());
+        let config_map = configparser::ini::Ini::new()
+            .read(String::from(
+                "[dropcopy]\naddress=127.0.0.1\nport=ten\nparticipants_900=*",
+            ))
+            .unwrap();
+        assert_eq!(
+            "port in the [dropcopy] section: ten is not a valid u16",
+            DropCopyConfig::from_config(&config_map)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
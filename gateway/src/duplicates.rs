@@ -0,0 +1,151 @@
+use std::collections::{HashSet, VecDeque};
+
+use oep::{
+    execution_report::RejectReason,
+    neworder::NewOrder,
+    oep_message::{MsgType, OepMessage},
+    replace::Replace,
+};
+
+// client order ids remembered per session, unless configured otherwise
+pub const DEFAULT_CLIENT_ORDER_ID_WINDOW: usize = 10000;
+
+/// The last client order ids of the new orders and the replaces of a session
+///
+/// A client unsure its order made it, e.g. after a reconnect, may send it
+/// again under a new sequence: the gateway refuses the client order ids seen
+/// in the last messages of the session, instead of entering the order twice.
+/// Kept across the reconnects of the session, like its sequences. The 0
+/// client order id is not checked.
+#[derive(Debug)]
+pub struct RecentClientOrderIds {
+    // oldest first
+    order: VecDeque<u64>,
+    ids: HashSet<u64>,
+    window: usize,
+}
+
+impl RecentClientOrderIds {
+    /// @window - how many client order ids are remembered, 0 checking none
+    pub fn new(window: usize) -> Self {
+        Self {
+            order: VecDeque::new(),
+            ids: HashSet::new(),
+            window,
+        }
+    }
+
+    /// Checks the client order id of @message before it is relayed to the
+    /// matching engine, remembering it if it goes through
+    ///
+    /// Returns: why the message has to be rejected, if it has to
+    pub fn check(&mut self, message: &dyn OepMessage) -> Result<(), RejectReason> {
+        let client_order_id = match message.message_type() {
+            MsgType::NewOrder => {
+                message
+                    .as_any()
+                    .downcast_ref::<NewOrder>()
+                    .unwrap()
+                    .client_order_id
+            }
+            MsgType::Replace => {
+                message
+                    .as_any()
+                    .downcast_ref::<Replace>()
+                    .unwrap()
+                    .client_order_id
+            }
+            _ => return Ok(()),
+        };
+        if self.window == 0 || client_order_id == 0 {
+            return Ok(());
+        }
+        if !self.ids.insert(client_order_id) {
+            return Err(RejectReason::DuplicateClientOrderId);
+        }
+        if self.order.len() == self.window {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.order.push_back(client_order_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use oep::{
+        cancel::Cancel, execution_report::RejectReason, neworder::NewOrder, replace::Replace,
+    };
+
+    use super::RecentClientOrderIds;
+
+    fn new_order(client_order_id: u64) -> NewOrder {
+        NewOrder {
+            client_order_id,
+            participant: 3,
+            book_id: 1,
+            quantity: 10,
+            price: 100,
+            order_type: 0,
+            side: 0,
+            gateway_id: 1,
+            session_id: 1,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        }
+    }
+
+    #[test]
+    fn window() {
+        let mut target = RecentClientOrderIds::new(2);
+        assert_eq!(Ok(()), target.check(&new_order(1)));
+        assert_eq!(
+            Err(RejectReason::DuplicateClientOrderId),
+            target.check(&new_order(1))
+        );
+        // the new order of a replace counts as well
+        let replace = Replace {
+            orig_order_id: 5,
+            client_order_id: 2,
+            participant: 3,
+            book_id: 1,
+            quantity: 10,
+            price: 100,
+            order_type: 0,
+            side: 0,
+            gateway_id: 1,
+            session_id: 1,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        };
+        assert_eq!(Ok(()), target.check(&replace));
+        assert_eq!(
+            Err(RejectReason::DuplicateClientOrderId),
+            target.check(&new_order(2))
+        );
+        // 1 falls out of the window
+        assert_eq!(Ok(()), target.check(&new_order(3)));
+        assert_eq!(Ok(()), target.check(&new_order(1)));
+
+        // not checked
+        assert_eq!(Ok(()), target.check(&new_order(0)));
+        assert_eq!(Ok(()), target.check(&new_order(0)));
+        let cancel = Cancel {
+            participant: 3,
+            order_id: 0,
+            book_id: 1,
+            side: 0,
+            gateway_id: 1,
+            session_id: 1,
+            orig_client_order_id: 1,
+        };
+        assert_eq!(Ok(()), target.check(&cancel));
+        let mut target = RecentClientOrderIds::new(0);
+        assert_eq!(Ok(()), target.check(&new_order(1)));
+        assert_eq!(Ok(()), target.check(&new_order(1)));
+    }
+}
@@ -0,0 +1,298 @@
+//! The sequences of the execution reports, with a sequenced egress
+//!
+//! Every engine numbers the reports of each gateway on their own, starting
+//! with 1 on each of its runs. The gateway takes its own in that order only:
+//! a frame coming after a gap is dropped and the engine is asked with an
+//! EgressNak for everything starting with the first one missing. The reports
+//! of the other gateways, only wanted by the drop copy, are taken as they
+//! come, but for the duplicates of their retransmissions. An engine seen for
+//! the first time is taken as it is, whatever it sent before the gateway
+//! started being none of its business.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use oep::{
+    decoder::Decoder,
+    egress::{EgressHeader, EgressNak, EGRESSHEADER_SIZE},
+    ingress::IngressKind,
+};
+use tracing::{info, warn};
+
+// a gap is asked for again only after this long, the frames being on their way
+const NAK_EVERY: Duration = Duration::from_millis(100);
+
+/// What to do with a frame received from an engine
+#[derive(Debug)]
+pub enum Egress<'a> {
+    // the next execution report, header included
+    Report(&'a [u8]),
+    // some frames of the gateway were lost, the engine has to send them again
+    Gap(EgressNak),
+    // a duplicate, a heartbeat, or a gap already asked for
+    Nothing,
+}
+
+#[derive(Debug)]
+struct EngineSequence {
+    // the primary and the backups of a partition have their own sequences
+    engine_id: u8,
+    epoch: u32,
+    next_seq: u64,
+    // the last gap asked for, and when
+    last_nak: Option<(u64, Instant)>,
+}
+
+#[derive(Debug)]
+pub struct EgressTracker {
+    gateway_id: u8,
+    // (partition id, gateway id) -> the sequence of the engine of the partition
+    streams: HashMap<(u8, u8), EngineSequence>,
+}
+
+impl EgressTracker {
+    pub fn new(gateway_id: u8) -> Self {
+        Self {
+            gateway_id,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// The next sequence expected from the engine of @partition_id, for the
+    /// reports of @gateway_id, if it was heard of
+    pub fn next_seq(&self, partition_id: u8, gateway_id: u8) -> Option<u64> {
+        self.streams
+            .get(&(partition_id, gateway_id))
+            .map(|s| s.next_seq)
+    }
+
+    /// Checks the sequence of @frame, what follows the OepHeader of an
+    /// EgressFrame, received at @now
+    pub fn receive<'a>(&mut self, frame: &'a [u8], now: Instant) -> Egress<'a> {
+        if frame.len() < EGRESSHEADER_SIZE {
+            return Egress::Nothing;
+        }
+        let Ok(header) = EgressHeader::decode(frame[..EGRESSHEADER_SIZE].try_into().unwrap())
+        else {
+            warn!("Invalid egress header received");
+            return Egress::Nothing;
+        };
+        let (engine_id, partition_id, gateway_id, epoch, seq) = (
+            header.engine_id,
+            header.partition_id,
+            header.gateway_id,
+            header.epoch,
+            header.seq,
+        );
+        let stream = self
+            .streams
+            .entry((partition_id, gateway_id))
+            .or_insert_with(|| EngineSequence {
+                engine_id,
+                epoch,
+                next_seq: seq,
+                last_nak: None,
+            });
+        if (stream.engine_id, stream.epoch) != (engine_id, epoch) {
+            info!(
+                engine_id,
+                partition_id, epoch, "Engine started, expecting its sequence from 1"
+            );
+            *stream = EngineSequence {
+                engine_id,
+                epoch,
+                next_seq: 1,
+                last_nak: None,
+            };
+        }
+        let report = &frame[EGRESSHEADER_SIZE..];
+        if gateway_id != self.gateway_id {
+            return match header.get_kind() {
+                IngressKind::Message if seq >= stream.next_seq => {
+                    stream.next_seq = seq + 1;
+                    Egress::Report(report)
+                }
+                _ => Egress::Nothing,
+            };
+        }
+        match header.get_kind() {
+            IngressKind::Message if seq == stream.next_seq => {
+                stream.next_seq += 1;
+                Egress::Report(report)
+            }
+            IngressKind::Message | IngressKind::Heartbeat if seq > stream.next_seq => {
+                match stream.last_nak {
+                    Some((from_seq, at))
+                        if from_seq == stream.next_seq && now.duration_since(at) < NAK_EVERY =>
+                    {
+                        Egress::Nothing
+                    }
+                    _ => {
+                        stream.last_nak = Some((stream.next_seq, now));
+                        Egress::Gap(EgressNak::new(
+                            engine_id,
+                            partition_id,
+                            gateway_id,
+                            epoch,
+                            stream.next_seq,
+                        ))
+                    }
+                }
+            }
+            IngressKind::Reset if seq > stream.next_seq => {
+                warn!(
+                    engine_id,
+                    partition_id,
+                    lost = seq - stream.next_seq,
+                    "Lost execution reports, no longer kept by the engine"
+                );
+                stream.next_seq = seq;
+                Egress::Nothing
+            }
+            _ => Egress::Nothing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use oep::{decoder::Decoder, egress::EgressHeader, ingress::IngressKind};
+
+    use super::{Egress, EgressTracker};
+
+    fn frame(engine_id: u8, gateway_id: u8, kind: IngressKind, epoch: u32, seq: u64) -> Vec<u8> {
+        let header = EgressHeader::new(engine_id, 2, gateway_id, kind, epoch, seq).encode();
+        match kind {
+            IngressKind::Message => [header.as_slice(), &[seq as u8]].concat(),
+            _ => header.to_vec(),
+        }
+    }
+
+    fn report(seq: u64) -> Vec<u8> {
+        frame(0, 1, IngressKind::Message, 100, seq)
+    }
+
+    #[test]
+    fn in_sequence() {
+        let mut target = EgressTracker::new(1);
+        let now = Instant::now();
+        // taken as it is the first time
+        assert!(matches!(
+            target.receive(&report(5), now),
+            Egress::Report([5])
+        ));
+        assert!(matches!(
+            target.receive(&report(6), now),
+            Egress::Report([6])
+        ));
+        // duplicates
+        assert!(matches!(target.receive(&report(6), now), Egress::Nothing));
+        assert!(matches!(target.receive(&report(2), now), Egress::Nothing));
+        assert_eq!(Some(7), target.next_seq(2, 1));
+        assert_eq!(None, target.next_seq(3, 1));
+        // garbage
+        assert!(matches!(target.receive(&[1, 2], now), Egress::Nothing));
+    }
+
+    #[test]
+    fn gap() {
+        let mut target = EgressTracker::new(1);
+        let now = Instant::now();
+        target.receive(&report(1), now);
+        let Egress::Gap(nak) = target.receive(&report(3), now) else {
+            panic!("gap expected");
+        };
+        assert_eq!(0, nak.engine_id);
+        assert_eq!(2, nak.partition_id);
+        assert_eq!(1, nak.gateway_id);
+        assert_eq!(100, { nak.epoch });
+        assert_eq!(2, { nak.from_seq });
+        // already asked for
+        assert!(matches!(target.receive(&report(4), now), Egress::Nothing));
+        // but not for ever
+        let later = now + Duration::from_millis(200);
+        assert!(matches!(target.receive(&report(4), later), Egress::Gap(_)));
+
+        // the retransmission
+        assert!(matches!(
+            target.receive(&report(2), later),
+            Egress::Report([2])
+        ));
+        assert!(matches!(
+            target.receive(&report(3), later),
+            Egress::Report([3])
+        ));
+        assert_eq!(Some(4), target.next_seq(2, 1));
+    }
+
+    #[test]
+    fn heartbeat_and_reset() {
+        let mut target = EgressTracker::new(1);
+        let now = Instant::now();
+        target.receive(&report(1), now);
+        let heartbeat = frame(0, 1, IngressKind::Heartbeat, 100, 2);
+        assert!(matches!(target.receive(&heartbeat, now), Egress::Nothing));
+        // the last report was lost
+        let heartbeat = frame(0, 1, IngressKind::Heartbeat, 100, 3);
+        let Egress::Gap(nak) = target.receive(&heartbeat, now) else {
+            panic!("gap expected");
+        };
+        assert_eq!(2, { nak.from_seq });
+
+        // the engine no longer has it
+        let reset = frame(0, 1, IngressKind::Reset, 100, 3);
+        assert!(matches!(target.receive(&reset, now), Egress::Nothing));
+        assert_eq!(Some(3), target.next_seq(2, 1));
+        assert!(matches!(
+            target.receive(&report(3), now),
+            Egress::Report([3])
+        ));
+    }
+
+    #[test]
+    fn other_gateways() {
+        let mut target = EgressTracker::new(1);
+        let now = Instant::now();
+        let other = |seq| frame(0, 4, IngressKind::Message, 100, seq);
+        assert!(matches!(
+            target.receive(&other(1), now),
+            Egress::Report([1])
+        ));
+        // their gaps are not asked for
+        assert!(matches!(
+            target.receive(&other(3), now),
+            Egress::Report([3])
+        ));
+        // but their retransmissions are dropped
+        assert!(matches!(target.receive(&other(2), now), Egress::Nothing));
+        assert!(matches!(target.receive(&other(3), now), Egress::Nothing));
+        let heartbeat = frame(0, 4, IngressKind::Heartbeat, 100, 9);
+        assert!(matches!(target.receive(&heartbeat, now), Egress::Nothing));
+        assert_eq!(Some(4), target.next_seq(2, 4));
+    }
+
+    #[test]
+    fn engine_restart_and_takeover() {
+        let mut target = EgressTracker::new(1);
+        let now = Instant::now();
+        target.receive(&report(10), now);
+        // a new run starts again with 1, whose first report was lost
+        let Egress::Gap(nak) = target.receive(&frame(0, 1, IngressKind::Message, 101, 2), now)
+        else {
+            panic!("gap expected");
+        };
+        assert_eq!(101, { nak.epoch });
+        assert_eq!(1, { nak.from_seq });
+
+        // the backup taking over has a sequence of its own
+        assert!(matches!(
+            target.receive(&frame(5, 1, IngressKind::Message, 90, 1), now),
+            Egress::Report([1])
+        ));
+        assert_eq!(Some(2), target.next_seq(2, 1));
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+
+use oep::{
+    cancel::Cancel,
+    execution_report::RejectReason,
+    massquote::MassQuote,
+    modify::Modify,
+    neworder::NewOrder,
+    oep_message::{MsgType, OepMessage},
+    quote::Quote,
+    replace::Replace,
+};
+
+/// The books a session can trade, as entitled to its participant
+///
+/// Loaded from the database when the session logs in. The participants
+/// without any entitlement are not checked. The mass cancels and the quote
+/// cancels are let through, for the participants to pull the orders of the
+/// books they lost.
+#[derive(Debug, Clone, Default)]
+pub struct Entitlements {
+    // None for all of them
+    books: Option<HashSet<u64>>,
+}
+
+impl Entitlements {
+    /// The entitlements to @books, none of them meaning all the books
+    pub fn new(books: Vec<u64>) -> Self {
+        Self {
+            books: (!books.is_empty()).then(|| books.into_iter().collect()),
+        }
+    }
+
+    pub fn allows(&self, book_id: u64) -> bool {
+        self.books.as_ref().is_none_or(|b| b.contains(&book_id))
+    }
+
+    /// Checks @message before it is relayed to the matching engine
+    ///
+    /// Returns: why the message has to be rejected, if it has to
+    pub fn check(&self, message: &dyn OepMessage) -> Result<(), RejectReason> {
+        let book_id = match message.message_type() {
+            MsgType::NewOrder => message.as_any().downcast_ref::<NewOrder>().unwrap().book_id,
+            MsgType::Modify => message.as_any().downcast_ref::<Modify>().unwrap().book_id,
+            MsgType::Cancel => message.as_any().downcast_ref::<Cancel>().unwrap().book_id,
+            MsgType::Replace => message.as_any().downcast_ref::<Replace>().unwrap().book_id,
+            MsgType::Quote => message.as_any().downcast_ref::<Quote>().unwrap().book_id,
+            // all its books, or none of them
+            MsgType::MassQuote => {
+                let m = message.as_any().downcast_ref::<MassQuote>().unwrap();
+                match m.quotes().find(|q| !self.allows(q.book_id)) {
+                    Some(q) => q.book_id,
+                    None => return Ok(()),
+                }
+            }
+            _ => return Ok(()),
+        };
+        match self.allows(book_id) {
+            true => Ok(()),
+            false => Err(RejectReason::NotEntitled),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use oep::{
+        cancel::Cancel,
+        execution_report::RejectReason,
+        masscancel::{MassCancel, ANY_SIDE},
+        massquote::{MassQuote, QuoteEntry, MAX_MASS_QUOTE_ENTRIES},
+    };
+
+    use super::Entitlements;
+
+    fn cancel(book_id: u64) -> Cancel {
+        Cancel {
+            participant: 3,
+            order_id: 7,
+            book_id,
+            side: 0,
+            gateway_id: 1,
+            session_id: 1,
+            orig_client_order_id: 0,
+        }
+    }
+
+    #[test]
+    fn books() {
+        let target = Entitlements::new(vec![1, 2]);
+        assert!(target.allows(2));
+        assert!(!target.allows(3));
+        assert_eq!(Ok(()), target.check(&cancel(1)));
+        assert_eq!(Err(RejectReason::NotEntitled), target.check(&cancel(3)));
+        let mass_cancel = MassCancel {
+            participant: 3,
+            book_id: 3,
+            side: ANY_SIDE,
+            gateway_id: 1,
+            session_id: 1,
+        };
+        assert_eq!(Ok(()), target.check(&mass_cancel));
+        // all the books of a mass quote
+        let mut mass_quote = MassQuote {
+            mass_quote_id: 1,
+            participant: 3,
+            gateway_id: 1,
+            session_id: 1,
+            entry_count: 2,
+            entries: [QuoteEntry::default(); MAX_MASS_QUOTE_ENTRIES],
+        };
+        mass_quote.entries[0].book_id = 1;
+        mass_quote.entries[1].book_id = 2;
+        assert_eq!(Ok(()), target.check(&mass_quote));
+        mass_quote.entries[1].book_id = 3;
+        assert_eq!(Err(RejectReason::NotEntitled), target.check(&mass_quote));
+
+        // not restricted
+        let target = Entitlements::new(vec![]);
+        assert!(target.allows(3));
+        assert_eq!(Ok(()), target.check(&cancel(3)));
+    }
+}
@@ -0,0 +1,489 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use oep::{
+    cancel::Cancel,
+    engine_status::{EngineState, EngineStatus},
+    execution_report::{ExecutionReport, RejectReason, FLAG_THROTTLE_REJECT},
+    massquote::MassQuote,
+    modify::Modify,
+    neworder::NewOrder,
+    oep_message::{MsgType, OepMessage},
+    quote::Quote,
+    replace::Replace,
+};
+use order::{OrderState, Side};
+use tracing::{info, warn};
+use utils::config::get_optional_config_string;
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+/// Timings of the store-and-forward logic, from the [gateway] section
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailoverConfig {
+    // the engine is considered gone if it wasn't heard of for this long
+    pub engine_timeout: Duration,
+    // how long the messages are kept while waiting for an engine to become ready
+    pub buffer_timeout: Duration,
+    pub max_buffered_messages: usize,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            engine_timeout: Duration::from_millis(3000),
+            buffer_timeout: Duration::from_millis(5000),
+            max_buffered_messages: 10000,
+        }
+    }
+}
+
+impl FailoverConfig {
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self> {
+        let default = Self::default();
+        let optional = |key: &str| get_optional_config_string(config_map, "gateway", key);
+        Ok(Self {
+            engine_timeout: match optional("engine_timeout_ms") {
+                Some(v) => Duration::from_millis(v.parse::<u64>()?),
+                None => default.engine_timeout,
+            },
+            buffer_timeout: match optional("failover_timeout_ms") {
+                Some(v) => Duration::from_millis(v.parse::<u64>()?),
+                None => default.buffer_timeout,
+            },
+            max_buffered_messages: match optional("failover_max_buffered_messages") {
+                Some(v) => v.parse::<usize>()?,
+                None => default.max_buffered_messages,
+            },
+        })
+    }
+}
+
+/// A message on its way to the matching engine
+#[derive(Debug, Clone)]
+pub struct PendingMessage {
+    pub session_id: u32,
+    // already encoded for the matching engine
+    pub payload: Vec<u8>,
+    // what to send back to the client if the message can't be delivered
+    pub rejection: Option<ExecutionReport>,
+}
+
+impl PendingMessage {
+    pub fn new(session_id: u32, payload: Vec<u8>, rejection: Option<ExecutionReport>) -> Self {
+        Self {
+            session_id,
+            payload,
+            rejection,
+        }
+    }
+}
+
+/// Builds the execution report that rejects @message for @reason, for the
+/// messages that expect an answer from the matching engine
+pub fn rejection_for(message: &dyn OepMessage, reason: RejectReason) -> Option<ExecutionReport> {
+    let flags = match reason {
+        RejectReason::Throttled => FLAG_THROTTLE_REJECT,
+        _ => 0,
+    };
+    match message.message_type() {
+        MsgType::NewOrder => {
+            let m = message.as_any().downcast_ref::<NewOrder>()?;
+            Some(ExecutionReport {
+                participant: m.participant,
+                order_id: m.client_order_id,
+                submitted_order_id: m.client_order_id,
+                book: m.book_id,
+                quantity: m.quantity,
+                price: m.price,
+                flags,
+                side: m.side,
+                state: OrderState::Rejected.into(),
+                session_id: m.session_id,
+                gateway_id: m.gateway_id,
+                filled_quantity: 0,
+                leaves_quantity: 0,
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: reason.into(),
+                trade_id: 0,
+            })
+        }
+        MsgType::Replace => {
+            let m = message.as_any().downcast_ref::<Replace>()?;
+            Some(ExecutionReport {
+                participant: m.participant,
+                order_id: m.client_order_id,
+                submitted_order_id: m.client_order_id,
+                book: m.book_id,
+                quantity: m.quantity,
+                price: m.price,
+                flags,
+                side: m.side,
+                state: OrderState::Rejected.into(),
+                session_id: m.session_id,
+                gateway_id: m.gateway_id,
+                filled_quantity: 0,
+                leaves_quantity: 0,
+                orig_order_id: m.orig_order_id,
+                partition_id: 0,
+                reject_reason: reason.into(),
+                trade_id: 0,
+            })
+        }
+        MsgType::Modify => {
+            let m = message.as_any().downcast_ref::<Modify>()?;
+            Some(ExecutionReport {
+                participant: m.participant,
+                order_id: m.order_id,
+                submitted_order_id: m.order_id,
+                book: m.book_id,
+                quantity: m.quantity,
+                price: m.price,
+                flags,
+                side: m.side,
+                state: OrderState::Rejected.into(),
+                session_id: m.session_id,
+                gateway_id: m.gateway_id,
+                filled_quantity: 0,
+                leaves_quantity: 0,
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: reason.into(),
+                trade_id: 0,
+            })
+        }
+        MsgType::Cancel => {
+            let m = message.as_any().downcast_ref::<Cancel>()?;
+            Some(ExecutionReport {
+                participant: m.participant,
+                order_id: m.order_id,
+                submitted_order_id: m.order_id,
+                book: m.book_id,
+                quantity: 0,
+                price: 0,
+                flags,
+                side: m.side,
+                state: OrderState::Rejected.into(),
+                session_id: m.session_id,
+                gateway_id: m.gateway_id,
+                filled_quantity: 0,
+                leaves_quantity: 0,
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: reason.into(),
+                trade_id: 0,
+            })
+        }
+        // reported on the bid, as the matching engine does
+        MsgType::Quote => {
+            let m = message.as_any().downcast_ref::<Quote>()?;
+            Some(ExecutionReport {
+                participant: m.participant,
+                order_id: m.quote_id,
+                submitted_order_id: m.quote_id,
+                book: m.book_id,
+                quantity: m.bid_quantity,
+                price: m.bid_price,
+                flags,
+                side: Side::Bid.into(),
+                state: OrderState::Rejected.into(),
+                session_id: m.session_id,
+                gateway_id: m.gateway_id,
+                filled_quantity: 0,
+                leaves_quantity: 0,
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: reason.into(),
+                trade_id: 0,
+            })
+        }
+        // a single reject, for all the books
+        MsgType::MassQuote => {
+            let m = message.as_any().downcast_ref::<MassQuote>()?;
+            Some(ExecutionReport {
+                participant: m.participant,
+                order_id: m.mass_quote_id,
+                submitted_order_id: m.mass_quote_id,
+                book: 0,
+                quantity: 0,
+                price: 0,
+                flags,
+                side: Side::Bid.into(),
+                state: OrderState::Rejected.into(),
+                session_id: m.session_id,
+                gateway_id: m.gateway_id,
+                filled_quantity: 0,
+                leaves_quantity: 0,
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: reason.into(),
+                trade_id: 0,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelayState {
+    // no engine was heard of yet: relay blindly, like a gateway without failover
+    Unknown,
+    Relaying,
+    // waiting for an engine to become ready
+    Buffering { since: Instant },
+    // the failover took too long, reject everything until an engine is ready
+    Unavailable,
+}
+
+/// Outcome of handing a message over to the FailoverBuffer
+#[derive(Debug)]
+pub enum Relay {
+    // send it to the matching engine right away
+    Now(PendingMessage),
+    // kept until an engine is ready
+    Buffered,
+    // can't be delivered, notify the client
+    Rejected(PendingMessage),
+}
+
+/// Store-and-forward of the messages sent to the matching engine
+///
+/// The engines announce their state periodically. When the engine stops
+/// announcing itself or a new one announces it is starting (e.g. a backup
+/// taking over), the messages are kept instead of being relayed. They are
+/// flushed in order once an engine announces it is ready, or handed back for
+/// rejection if that doesn't happen within the configured timeout.
+pub struct FailoverBuffer {
+    config: FailoverConfig,
+    state: RelayState,
+    engine_id: Option<u8>,
+    last_engine_status: Instant,
+    buffered: VecDeque<PendingMessage>,
+}
+
+impl FailoverBuffer {
+    pub fn new(config: FailoverConfig) -> Self {
+        Self {
+            config,
+            state: RelayState::Unknown,
+            engine_id: None,
+            last_engine_status: Instant::now(),
+            buffered: VecDeque::new(),
+        }
+    }
+
+    pub fn get_state(&self) -> RelayState {
+        self.state
+    }
+
+    pub fn buffered_len(&self) -> usize {
+        self.buffered.len()
+    }
+
+    /// Accounts for a status message of an engine.
+    /// Returns the buffered messages that can now be relayed, in their original order
+    pub fn engine_status(&mut self, status: &EngineStatus, now: Instant) -> Vec<PendingMessage> {
+        self.last_engine_status = now;
+        match status.get_state() {
+            // a stopping engine might be followed by a backup taking over
+            EngineState::Starting | EngineState::Stopping => {
+                self.start_buffering(now);
+                vec![]
+            }
+            EngineState::Ready => {
+                if self.engine_id.is_some_and(|id| id != status.engine_id) {
+                    info!(engine_id = status.engine_id, "Engine took over");
+                }
+                self.engine_id = Some(status.engine_id);
+                self.state = RelayState::Relaying;
+                self.buffered.drain(..).collect()
+            }
+        }
+    }
+
+    /// Hands @message over, to be sent now or later to the matching engine
+    pub fn relay(&mut self, message: PendingMessage, now: Instant) -> Relay {
+        self.check_engine(now);
+        match self.state {
+            RelayState::Unknown | RelayState::Relaying => Relay::Now(message),
+            RelayState::Buffering { .. } => {
+                if self.buffered.len() >= self.config.max_buffered_messages {
+                    Relay::Rejected(message)
+                } else {
+                    self.buffered.push_back(message);
+                    Relay::Buffered
+                }
+            }
+            RelayState::Unavailable => Relay::Rejected(message),
+        }
+    }
+
+    /// Meant to be called periodically. Detects a silent engine and gives up on
+    /// the buffered messages if the failover takes too long.
+    /// Returns the messages that were given up on
+    pub fn expire(&mut self, now: Instant) -> Vec<PendingMessage> {
+        self.check_engine(now);
+        match self.state {
+            RelayState::Buffering { since }
+                if now.duration_since(since) >= self.config.buffer_timeout =>
+            {
+                warn!(
+                    timeout = ?self.config.buffer_timeout,
+                    rejected = self.buffered.len(),
+                    "No matching engine ready, rejecting the buffered messages"
+                );
+                self.state = RelayState::Unavailable;
+                self.buffered.drain(..).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    fn check_engine(&mut self, now: Instant) {
+        if self.state == RelayState::Relaying
+            && now.duration_since(self.last_engine_status) >= self.config.engine_timeout
+        {
+            warn!("Matching engine went silent, buffering messages");
+            self.start_buffering(now);
+        }
+    }
+
+    fn start_buffering(&mut self, now: Instant) {
+        if !matches!(self.state, RelayState::Buffering { .. }) {
+            self.state = RelayState::Buffering { since: now };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use oep::{
+        engine_status::{EngineState, EngineStatus},
+        execution_report::RejectReason,
+        neworder::NewOrder,
+    };
+    use order::OrderState;
+
+    use super::{rejection_for, FailoverBuffer, FailoverConfig, PendingMessage, Relay, RelayState};
+
+    fn config() -> FailoverConfig {
+        FailoverConfig {
+            engine_timeout: Duration::from_millis(100),
+            buffer_timeout: Duration::from_millis(1000),
+            max_buffered_messages: 2,
+        }
+    }
+
+    fn message(i: u8) -> PendingMessage {
+        PendingMessage::new(1, vec![i], None)
+    }
+
+    #[test]
+    fn relays_until_an_engine_is_known() {
+        let mut target = FailoverBuffer::new(config());
+        let start = Instant::now();
+        assert!(matches!(
+            target.relay(message(1), start + Duration::from_secs(10)),
+            Relay::Now(_)
+        ));
+        assert_eq!(RelayState::Unknown, target.get_state());
+    }
+
+    #[test]
+    fn buffers_during_failover() {
+        let mut target = FailoverBuffer::new(config());
+        let start = Instant::now();
+        target.engine_status(&EngineStatus::new(1, EngineState::Ready), start);
+        assert!(matches!(target.relay(message(1), start), Relay::Now(_)));
+
+        // the primary goes silent
+        let later = start + Duration::from_millis(200);
+        assert!(matches!(target.relay(message(2), later), Relay::Buffered));
+        // the backup takes over
+        target.engine_status(&EngineStatus::new(2, EngineState::Starting), later);
+        assert!(matches!(target.relay(message(3), later), Relay::Buffered));
+        // buffer is full
+        assert!(matches!(
+            target.relay(message(4), later),
+            Relay::Rejected(_)
+        ));
+        assert!(target.expire(later).is_empty());
+
+        let flushed = target.engine_status(&EngineStatus::new(2, EngineState::Ready), later);
+        assert_eq!(
+            vec![vec![2], vec![3]],
+            flushed.into_iter().map(|m| m.payload).collect::<Vec<_>>()
+        );
+        assert_eq!(RelayState::Relaying, target.get_state());
+        assert!(matches!(target.relay(message(5), later), Relay::Now(_)));
+    }
+
+    #[test]
+    fn gives_up_after_the_timeout() {
+        let mut target = FailoverBuffer::new(config());
+        let start = Instant::now();
+        target.engine_status(&EngineStatus::new(1, EngineState::Starting), start);
+        assert!(matches!(target.relay(message(1), start), Relay::Buffered));
+
+        let expired = target.expire(start + Duration::from_millis(1000));
+        assert_eq!(1, expired.len());
+        assert_eq!(0, target.buffered_len());
+        assert!(matches!(
+            target.relay(message(2), start + Duration::from_millis(1001)),
+            Relay::Rejected(_)
+        ));
+
+        // recovers as soon as an engine is ready
+        target.engine_status(
+            &EngineStatus::new(1, EngineState::Ready),
+            start + Duration::from_millis(2000),
+        );
+        assert!(matches!(
+            target.relay(message(3), start + Duration::from_millis(2000)),
+            Relay::Now(_)
+        ));
+    }
+
+    #[test]
+    fn buffers_while_stopping() {
+        let mut target = FailoverBuffer::new(config());
+        let start = Instant::now();
+        target.engine_status(&EngineStatus::new(1, EngineState::Ready), start);
+        // well before the engine would be found silent
+        target.engine_status(&EngineStatus::new(1, EngineState::Stopping), start);
+        assert!(matches!(target.relay(message(1), start), Relay::Buffered));
+    }
+
+    #[test]
+    fn rejects_new_orders() {
+        let order = NewOrder {
+            client_order_id: 7,
+            participant: 3,
+            book_id: 1,
+            quantity: 100,
+            price: 1000,
+            order_type: 0,
+            side: 1,
+            gateway_id: 1,
+            session_id: 1,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        };
+        let ereport = rejection_for(&order, RejectReason::EngineUnavailable).unwrap();
+        assert_eq!(7, { ereport.submitted_order_id });
+        assert_eq!(100, { ereport.quantity });
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
+        assert_eq!(RejectReason::EngineUnavailable, ereport.get_reject_reason());
+        assert!(!ereport.is_throttle_reject());
+
+        let ereport = rejection_for(&order, RejectReason::Throttled).unwrap();
+        assert!(ereport.is_throttle_reject());
+    }
+}
@@ -0,0 +1,156 @@
+//! Sequencing of the messages relayed to the matching engines
+//!
+//! With a sequenced ingress, the gateway numbers what it relays to the
+//! engines, starting with 1 on every run, and keeps the last frames sent. An
+//! engine noticing a gap asks for the missing frames with an IngressNak and
+//! gets them again on the same multicast group, the engines which already
+//! have them dropping them as duplicates. The heartbeats let the engines
+//! notice the loss of the last frames, when nothing else follows them.
+
+use std::collections::VecDeque;
+
+use oep::{
+    decoder::Decoder,
+    ingress::{IngressHeader, IngressKind, IngressNak, INGRESSHEADER_SIZE},
+};
+
+// frames kept for the retransmissions, unless configured otherwise
+pub const DEFAULT_MAX_RETRANSMIT: usize = 100000;
+
+#[derive(Debug)]
+pub struct SequencedIngress {
+    gateway_id: u8,
+    epoch: u32,
+    next_seq: u64,
+    // the last frames sent, header included, oldest first
+    sent: VecDeque<(u64, Vec<u8>)>,
+    max_sent: usize,
+}
+
+impl SequencedIngress {
+    /// @epoch - tells this run of the gateway apart from the previous ones
+    /// @max_sent - how many of the frames sent are kept for a retransmission
+    pub fn new(gateway_id: u8, epoch: u32, max_sent: usize) -> Self {
+        Self {
+            gateway_id,
+            epoch,
+            next_seq: 1,
+            sent: VecDeque::new(),
+            max_sent,
+        }
+    }
+
+    fn header(&self, kind: IngressKind, seq: u64) -> [u8; INGRESSHEADER_SIZE] {
+        IngressHeader::new(self.gateway_id, kind, self.epoch, seq).encode()
+    }
+
+    /// Frames @payload with the next sequence, keeping a copy of the frame
+    /// for the retransmissions
+    pub fn frame(&mut self, payload: &[u8]) -> Vec<u8> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let frame = [self.header(IngressKind::Message, seq).as_slice(), payload].concat();
+        if self.max_sent > 0 {
+            if self.sent.len() == self.max_sent {
+                self.sent.pop_front();
+            }
+            self.sent.push_back((seq, frame.clone()));
+        }
+        frame
+    }
+
+    /// Announces the next sequence, for the engines to notice what they missed
+    pub fn heartbeat(&self) -> Vec<u8> {
+        self.header(IngressKind::Heartbeat, self.next_seq).to_vec()
+    }
+
+    /// The frames to send again for @nak, the ones kept starting with its
+    /// from_seq. They come after a reset if some of those asked for are gone.
+    /// Nothing for the naks of the other gateways or of a previous run
+    pub fn retransmit(&self, nak: &IngressNak) -> Vec<Vec<u8>> {
+        if nak.gateway_id != self.gateway_id || { nak.epoch } != self.epoch {
+            return vec![];
+        }
+        let from_seq = nak.from_seq;
+        let first_kept = self.sent.front().map_or(self.next_seq, |(seq, _)| *seq);
+        let mut r = vec![];
+        if from_seq < first_kept {
+            r.push(self.header(IngressKind::Reset, first_kept).to_vec());
+        }
+        r.extend(
+            self.sent
+                .iter()
+                .filter(|(seq, _)| *seq >= from_seq)
+                .map(|(_, frame)| frame.clone()),
+        );
+        r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use oep::{
+        decoder::Decoder,
+        ingress::{IngressHeader, IngressKind, IngressNak, INGRESSHEADER_SIZE},
+    };
+
+    use super::SequencedIngress;
+
+    fn header(frame: &[u8]) -> IngressHeader {
+        IngressHeader::decode(frame[..INGRESSHEADER_SIZE].try_into().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn frame_and_heartbeat() {
+        let mut target = SequencedIngress::new(3, 100, 10);
+        let frame = target.frame(&[7, 8]);
+        let h = header(&frame);
+        assert_eq!(3, h.gateway_id);
+        assert_eq!(IngressKind::Message, h.get_kind());
+        assert_eq!(100, { h.epoch });
+        assert_eq!(1, { h.seq });
+        assert_eq!([7, 8], frame[INGRESSHEADER_SIZE..]);
+        assert_eq!(2, { header(&target.frame(&[9])).seq });
+
+        let heartbeat = target.heartbeat();
+        assert_eq!(INGRESSHEADER_SIZE, heartbeat.len());
+        assert_eq!(IngressKind::Heartbeat, header(&heartbeat).get_kind());
+        assert_eq!(3, { header(&heartbeat).seq });
+    }
+
+    #[test]
+    fn retransmit() {
+        let mut target = SequencedIngress::new(3, 100, 2);
+        for i in 0..3u8 {
+            target.frame(&[i]);
+        }
+        let resent = target.retransmit(&IngressNak::new(0, 3, 100, 3));
+        assert_eq!(1, resent.len());
+        assert_eq!(3, { header(&resent[0]).seq });
+        assert_eq!([2], resent[0][INGRESSHEADER_SIZE..]);
+
+        // the first one is gone, the engine has to skip it
+        let resent = target.retransmit(&IngressNak::new(0, 3, 100, 1));
+        assert_eq!(3, resent.len());
+        assert_eq!(IngressKind::Reset, header(&resent[0]).get_kind());
+        assert_eq!(2, { header(&resent[0]).seq });
+        assert_eq!(2, { header(&resent[1]).seq });
+        assert_eq!(3, { header(&resent[2]).seq });
+
+        // not for us
+        assert!(target.retransmit(&IngressNak::new(0, 4, 100, 1)).is_empty());
+        assert!(target.retransmit(&IngressNak::new(0, 3, 99, 1)).is_empty());
+        // nothing missed
+        assert!(target.retransmit(&IngressNak::new(0, 3, 100, 4)).is_empty());
+    }
+
+    #[test]
+    fn keeps_nothing() {
+        let mut target = SequencedIngress::new(3, 100, 0);
+        target.frame(&[1]);
+        let resent = target.retransmit(&IngressNak::new(0, 3, 100, 1));
+        assert_eq!(1, resent.len());
+        assert_eq!(IngressKind::Reset, header(&resent[0]).get_kind());
+        assert_eq!(2, { header(&resent[0]).seq });
+    }
+}
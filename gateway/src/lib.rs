@@ -1 +1,53 @@
+#[cfg(feature = "usdt")]
+usdt::dtrace_provider!("gateway_probes.d");
+
+/// Evaluates @func, firing the probe @probe with the time it took, in nanoseconds
+#[cfg(feature = "usdt")]
+#[macro_export]
+macro_rules! timeit {
+    ($probe: ident, $func: expr) => {{
+        let start = std::time::Instant::now();
+        let result = $func;
+        let duration = start.elapsed().as_nanos() as u64;
+        $crate::probes::$probe(duration);
+        result
+    }};
+}
+#[cfg(not(feature = "usdt"))]
+#[macro_export]
+macro_rules! timeit {
+    ($_probe: ident, $func: expr) => {{
+        $func
+    }};
+}
+
+/// The probes of the gateway: decoding a message of a client, checking a
+/// login in the database, sending a message to the matching engines and
+/// handing an execution report over to its session and the drop copy
+#[cfg(feature = "usdt")]
+pub mod probes {
+    macro_rules! probes {
+        ($($probe: ident),*) => {
+            $(
+                pub fn $probe(duration: u64) {
+                    crate::gateway::$probe!(|| (duration));
+                }
+            )*
+        };
+    }
+    probes!(decode, login, relay, fan_out);
+}
+
+pub mod dropcopy;
+pub mod duplicates;
+pub mod egress;
+pub mod entitlements;
+pub mod failover;
+pub mod ingress;
+pub mod listener;
 pub mod messages;
+pub mod metrics;
+pub mod outbound;
+pub mod risk;
+pub mod sequence;
+pub mod server;
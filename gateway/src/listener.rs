@@ -0,0 +1,603 @@
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use fix_gateway::config::FixConfig;
+use serde::Deserialize;
+use utils::config::{self, ConfigError};
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+/// Wire protocol spoken by the clients of a listener
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListenerProtocol {
+    Oep,
+    // FIX 4.4, translated to OEP by the fix_gateway
+    Fix,
+}
+
+impl FromStr for ListenerProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "oep" => Ok(ListenerProtocol::Oep),
+            "fix" => Ok(ListenerProtocol::Fix),
+            "tls" => bail!("Listener protocol {s} is not supported yet"),
+            _ => bail!("Unknown listener protocol {s}"),
+        }
+    }
+}
+
+/// One client facing port of the gateway
+///
+/// Every listener has its own session namespace: a session can only log in
+/// on the listener whose range contains its session_id. The rate limit
+/// applies to each session accepted on the listener, 0 meaning unlimited.
+/// The messages over the limit are rejected, and the sessions rejected more
+/// than max_throttled_per_second times in a second are disconnected.
+/// The sessions silent for more than session_timeout_ms are disconnected
+/// too, 0 meaning never. The FIX listeners map the CompIDs of their clients
+/// to sessions, see FixConfig.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerConfig {
+    pub name: String,
+    pub address: String,
+    pub port: u16,
+    pub protocol: ListenerProtocol,
+    pub session_ids: RangeInclusive<u32>,
+    pub max_messages_per_second: u32,
+    pub burst_messages: u32,
+    pub max_throttled_per_second: u32,
+    pub session_timeout_ms: u32,
+    // only for the FIX listeners
+    pub fix: Option<FixConfig>,
+}
+
+/// The keys of a [listener_<name>] section, the FIX ones aside
+#[derive(Debug, Deserialize)]
+struct ListenerSection {
+    address: String,
+    port: u16,
+    #[serde(default, deserialize_with = "config::from_str_optional")]
+    protocol: Option<ListenerProtocol>,
+    session_id_min: Option<u32>,
+    session_id_max: Option<u32>,
+    #[serde(default)]
+    max_messages_per_second: u32,
+    // max_messages_per_second by default, for both
+    burst_messages: Option<u32>,
+    max_throttled_per_second: Option<u32>,
+    #[serde(default)]
+    session_timeout_ms: u32,
+}
+
+/// The keys of the [gateway] section naming its listeners
+#[derive(Debug, Deserialize)]
+struct ListenerNames {
+    listeners: Option<Vec<String>>,
+}
+
+/// The address and the port of the single listener of a gateway without
+/// listeners, in its [gateway] section
+#[derive(Debug, Deserialize)]
+struct DefaultListener {
+    address: String,
+    port: u16,
+}
+
+impl ListenerConfig {
+    /// Loads the listener described by the [listener_@name] section
+    pub fn from_config(config_map: &ConfigMap, name: &str) -> Result<Self> {
+        let section_name = format!("listener_{name}");
+        if !config_map.contains_key(&section_name) {
+            return Err(ConfigError::new(&section_name, None, "not found").into());
+        }
+        let section: ListenerSection = config::section(config_map, &section_name)?;
+        let error = |key: &str, message: &str| ConfigError::new(&section_name, Some(key), message);
+        let session_id_min = section.session_id_min.unwrap_or(u32::MIN);
+        let session_id_max = section.session_id_max.unwrap_or(u32::MAX);
+        if session_id_min > session_id_max {
+            return Err(error("session_id_min", "leaves an empty session namespace").into());
+        }
+        let max_messages_per_second = section.max_messages_per_second;
+        let burst_messages = section.burst_messages.unwrap_or(max_messages_per_second);
+        if max_messages_per_second > 0 && burst_messages == 0 {
+            return Err(error("burst_messages", "would throttle every message").into());
+        }
+        let protocol = section.protocol.unwrap_or(ListenerProtocol::Oep);
+        let fix = match protocol {
+            ListenerProtocol::Fix => Some(FixConfig::from_config(config_map, &section_name)?),
+            ListenerProtocol::Oep => None,
+        };
+        if let Some(session_id) = fix
+            .iter()
+            .flat_map(|f| f.sessions.values())
+            .find(|session_id| !(session_id_min..=session_id_max).contains(*session_id))
+        {
+            bail!("FIX session {session_id} is outside the namespace of listener {name}");
+        }
+
+        Ok(Self {
+            name: String::from(name),
+            address: section.address,
+            port: section.port,
+            protocol,
+            session_ids: session_id_min..=session_id_max,
+            max_messages_per_second,
+            burst_messages,
+            max_throttled_per_second: section
+                .max_throttled_per_second
+                .unwrap_or(max_messages_per_second),
+            session_timeout_ms: section.session_timeout_ms,
+            fix,
+        })
+    }
+
+    /// Loads all the listeners enumerated in the "listeners" key of the gateway section.
+    /// Without such a key, the gateway address and port are used for a single OEP listener.
+    pub fn load_all(config_map: &ConfigMap) -> Result<Vec<Self>> {
+        let names: ListenerNames = config::section(config_map, "gateway")?;
+        let listeners = match names.listeners {
+            Some(names) => names
+                .iter()
+                .map(|name| Self::from_config(config_map, name))
+                .collect::<Result<Vec<Self>>>()?,
+            None => {
+                let default: DefaultListener = config::section(config_map, "gateway")?;
+                vec![Self {
+                    name: String::from("default"),
+                    address: default.address,
+                    port: default.port,
+                    protocol: ListenerProtocol::Oep,
+                    session_ids: u32::MIN..=u32::MAX,
+                    max_messages_per_second: 0,
+                    burst_messages: 0,
+                    max_throttled_per_second: 0,
+                    session_timeout_ms: 0,
+                    fix: None,
+                }]
+            }
+        };
+
+        if listeners.is_empty() {
+            bail!("No listener configured");
+        }
+        for (i, a) in listeners.iter().enumerate() {
+            for b in &listeners[i + 1..] {
+                if a.session_ids.start() <= b.session_ids.end()
+                    && b.session_ids.start() <= a.session_ids.end()
+                {
+                    bail!(
+                        "Listeners {} and {} have overlapping session namespaces",
+                        a.name,
+                        b.name
+                    );
+                }
+                if a.address == b.address && a.port == b.port {
+                    bail!("Listeners {} and {} share the same port", a.name, b.name);
+                }
+            }
+        }
+        Ok(listeners)
+    }
+
+    pub fn accepts_session(&self, session_id: u32) -> bool {
+        self.session_ids.contains(&session_id)
+    }
+
+    /// Whether a session accepted on this listener, last heard of at
+    /// @last_activity, has been silent for too long at @now
+    pub fn is_timed_out(&self, last_activity: Instant, now: Instant) -> bool {
+        self.session_timeout_ms != 0
+            && now.saturating_duration_since(last_activity)
+                > Duration::from_millis(self.session_timeout_ms as u64)
+    }
+
+    /// How long the clients of this listener can stay silent, forever if None
+    pub fn session_timeout(&self) -> Option<Duration> {
+        match self.session_timeout_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms as u64)),
+        }
+    }
+
+    /// This listener with the rate limit and the session timeout of
+    /// @reloaded, the only settings changed without a restart
+    pub fn with_limits_of(&self, reloaded: &Self) -> Self {
+        Self {
+            max_messages_per_second: reloaded.max_messages_per_second,
+            burst_messages: reloaded.burst_messages,
+            max_throttled_per_second: reloaded.max_throttled_per_second,
+            session_timeout_ms: reloaded.session_timeout_ms,
+            ..self.clone()
+        }
+    }
+
+    /// The rate limiter of a session accepted on this listener
+    pub fn rate_limiter(&self) -> RateLimiter {
+        RateLimiter::new(
+            self.max_messages_per_second,
+            self.burst_messages,
+            self.max_throttled_per_second,
+        )
+    }
+}
+
+/// What to do with a message, as decided by the RateLimiter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Throttle {
+    Allow,
+    // over the limit, reject the message
+    Reject,
+    // too many rejects, the session has to go
+    Disconnect,
+}
+
+/// Token bucket allowing @max_per_second messages every second on average,
+/// with bursts of up to @burst messages. Going over the limit more than
+/// @max_rejects_per_second times in the same second disconnects the session
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    max_per_second: u32,
+    burst: u32,
+    max_rejects_per_second: u32,
+    // in thousandths of a message, so that the refill doesn't lose the fractions
+    tokens: u64,
+    last_refill: Instant,
+    rejects_window_start: Instant,
+    rejects: u32,
+}
+
+impl RateLimiter {
+    pub fn new(max_per_second: u32, burst: u32, max_rejects_per_second: u32) -> Self {
+        let now = Instant::now();
+        Self {
+            max_per_second,
+            burst,
+            max_rejects_per_second,
+            tokens: burst as u64 * 1000,
+            last_refill: now,
+            rejects_window_start: now,
+            rejects: 0,
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(0, 0, 0)
+    }
+
+    /// Changes the limits, keeping the messages already allowed up to the new burst
+    pub fn set_limits(&mut self, max_per_second: u32, burst: u32, max_rejects_per_second: u32) {
+        if self.max_per_second == 0 {
+            // unlimited so far, the bucket starts full
+            self.tokens = burst as u64 * 1000;
+            self.last_refill = Instant::now();
+        }
+        self.max_per_second = max_per_second;
+        self.burst = burst;
+        self.max_rejects_per_second = max_rejects_per_second;
+        self.tokens = self.tokens.min(burst as u64 * 1000);
+    }
+
+    /// accounts for one more message received at @now
+    pub fn check(&mut self, now: Instant) -> Throttle {
+        if self.max_per_second == 0 {
+            return Throttle::Allow;
+        }
+        let elapsed_ms = now.saturating_duration_since(self.last_refill).as_millis() as u64;
+        if elapsed_ms > 0 {
+            self.tokens = (self.tokens + elapsed_ms * self.max_per_second as u64)
+                .min(self.burst as u64 * 1000);
+            self.last_refill += Duration::from_millis(elapsed_ms);
+        }
+        if self.tokens >= 1000 {
+            self.tokens -= 1000;
+            return Throttle::Allow;
+        }
+
+        if now.saturating_duration_since(self.rejects_window_start) >= Duration::from_secs(1) {
+            self.rejects_window_start = now;
+            self.rejects = 0;
+        }
+        self.rejects += 1;
+        if self.rejects > self.max_rejects_per_second {
+            Throttle::Disconnect
+        } else {
+            Throttle::Reject
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use configparser::ini::Ini;
+
+    use super::{ListenerConfig, ListenerProtocol, RateLimiter, Throttle};
+
+    #[test]
+    fn defaults_to_the_gateway_port() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                address=127.0.0.1
+                port=10000",
+            ))
+            .unwrap();
+        let listeners = ListenerConfig::load_all(&config_map).unwrap();
+        assert_eq!(1, listeners.len());
+        assert_eq!(10000, listeners[0].port);
+        assert_eq!(ListenerProtocol::Oep, listeners[0].protocol);
+        assert!(listeners[0].accepts_session(0));
+        assert!(listeners[0].accepts_session(u32::MAX));
+
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                address=127.0.0.1
+                port=100000",
+            ))
+            .unwrap();
+        assert!(ListenerConfig::load_all(&config_map)
+            .unwrap_err()
+            .to_string()
+            .starts_with("port in the [gateway] section"));
+    }
+
+    #[test]
+    fn typed_listener_keys() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                listeners=members
+                [listener_members]
+                address=127.0.0.1
+                port=-1",
+            ))
+            .unwrap();
+        assert!(ListenerConfig::load_all(&config_map)
+            .unwrap_err()
+            .to_string()
+            .starts_with("port in the [listener_members] section"));
+
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                listeners=members
+                [listener_members]
+                port=10000",
+            ))
+            .unwrap();
+        assert!(ListenerConfig::load_all(&config_map)
+            .unwrap_err()
+            .to_string()
+            .starts_with("address in the [listener_members] section"));
+
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                listeners=members, retail
+                [listener_members]
+                address=127.0.0.1
+                port=10000",
+            ))
+            .unwrap();
+        assert_eq!(
+            "[listener_retail] section: not found",
+            ListenerConfig::load_all(&config_map)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn loads_multiple_listeners() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                listeners=members, retail
+                [listener_members]
+                address=127.0.0.1
+                port=10000
+                session_id_max=999
+                [listener_retail]
+                address=127.0.0.1
+                port=10001
+                protocol=oep
+                session_id_min=1000
+                session_id_max=1999
+                max_messages_per_second=50
+                burst_messages=80
+                session_timeout_ms=3000",
+            ))
+            .unwrap();
+        let listeners = ListenerConfig::load_all(&config_map).unwrap();
+        assert_eq!(2, listeners.len());
+        assert_eq!("members", listeners[0].name);
+        assert_eq!(0, listeners[0].max_messages_per_second);
+        assert!(listeners[0].accepts_session(999));
+        assert!(!listeners[0].accepts_session(1000));
+        assert_eq!(10001, listeners[1].port);
+        assert_eq!(50, listeners[1].max_messages_per_second);
+        assert_eq!(80, listeners[1].burst_messages);
+        assert_eq!(50, listeners[1].max_throttled_per_second);
+        assert!(listeners[1].accepts_session(1000));
+        assert!(!listeners[1].accepts_session(2000));
+
+        // no timeout on the first one
+        let start = Instant::now();
+        let later = start + Duration::from_secs(3600);
+        assert!(!listeners[0].is_timed_out(start, later));
+        assert!(!listeners[1].is_timed_out(start, start + Duration::from_millis(3000)));
+        assert!(listeners[1].is_timed_out(start, start + Duration::from_millis(3001)));
+    }
+
+    #[test]
+    fn rejects_overlapping_namespaces() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                listeners=a,b
+                [listener_a]
+                address=127.0.0.1
+                port=10000
+                session_id_max=1000
+                [listener_b]
+                address=127.0.0.1
+                port=10001
+                session_id_min=1000",
+            ))
+            .unwrap();
+        assert!(ListenerConfig::load_all(&config_map).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_protocols() {
+        assert!("tls".parse::<ListenerProtocol>().is_err());
+        assert!("something".parse::<ListenerProtocol>().is_err());
+        assert_eq!(ListenerProtocol::Oep, "OEP".parse().unwrap());
+        assert_eq!(ListenerProtocol::Fix, "fix".parse().unwrap());
+    }
+
+    #[test]
+    fn loads_fix_listeners() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                listeners=members, fix
+                [listener_members]
+                address=127.0.0.1
+                port=10000
+                session_id_max=999
+                [listener_fix]
+                address=127.0.0.1
+                port=10001
+                protocol=fix
+                session_id_min=1000
+                fix_sessions=BROKER1:1000, BROKER2:1001",
+            ))
+            .unwrap();
+        let listeners = ListenerConfig::load_all(&config_map).unwrap();
+        assert_eq!(None, listeners[0].fix);
+        assert_eq!(ListenerProtocol::Fix, listeners[1].protocol);
+        let fix = listeners[1].fix.as_ref().unwrap();
+        assert_eq!(Some(&1001), fix.sessions.get("BROKER2"));
+
+        // without sessions
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                listeners=fix
+                [listener_fix]
+                address=127.0.0.1
+                port=10001
+                protocol=fix",
+            ))
+            .unwrap();
+        assert!(ListenerConfig::load_all(&config_map).is_err());
+
+        // outside the namespace
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                listeners=fix
+                [listener_fix]
+                address=127.0.0.1
+                port=10001
+                protocol=fix
+                session_id_min=1000
+                fix_sessions=BROKER1:999",
+            ))
+            .unwrap();
+        assert!(ListenerConfig::load_all(&config_map).is_err());
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let mut target = RateLimiter::new(2, 2, 10);
+        let start = Instant::now();
+        assert_eq!(Throttle::Allow, target.check(start));
+        assert_eq!(Throttle::Allow, target.check(start));
+        assert_eq!(Throttle::Reject, target.check(start));
+        // refilled with one message
+        assert_eq!(
+            Throttle::Allow,
+            target.check(start + Duration::from_millis(500))
+        );
+        assert_eq!(
+            Throttle::Reject,
+            target.check(start + Duration::from_millis(500))
+        );
+        // never more than the burst
+        let later = start + Duration::from_secs(60);
+        assert_eq!(Throttle::Allow, target.check(later));
+        assert_eq!(Throttle::Allow, target.check(later));
+        assert_eq!(Throttle::Reject, target.check(later));
+
+        let mut target = RateLimiter::unlimited();
+        assert!((0..1000).all(|_| target.check(start) == Throttle::Allow));
+    }
+
+    #[test]
+    fn rate_limiter_bursts() {
+        let mut target = RateLimiter::new(10, 50, 10);
+        let start = Instant::now();
+        assert!((0..50).all(|_| target.check(start) == Throttle::Allow));
+        assert_eq!(Throttle::Reject, target.check(start));
+        // back to the average rate
+        let mut now = start;
+        for _ in 0..20 {
+            now += Duration::from_millis(100);
+            assert_eq!(Throttle::Allow, target.check(now));
+            assert_eq!(Throttle::Reject, target.check(now));
+        }
+    }
+
+    #[test]
+    fn rate_limiter_disconnects_persistent_violators() {
+        let mut target = RateLimiter::new(1, 1, 2);
+        let start = Instant::now();
+        assert_eq!(Throttle::Allow, target.check(start));
+        assert_eq!(Throttle::Reject, target.check(start));
+        assert_eq!(Throttle::Reject, target.check(start));
+        assert_eq!(Throttle::Disconnect, target.check(start));
+
+        // the rejects are counted every second
+        let mut target = RateLimiter::new(1, 1, 2);
+        let mut now = Instant::now();
+        for _ in 0..10 {
+            assert_eq!(Throttle::Allow, target.check(now));
+            assert_eq!(Throttle::Reject, target.check(now));
+            assert_eq!(Throttle::Reject, target.check(now));
+            now += Duration::from_secs(1);
+        }
+    }
+
+    #[test]
+    fn rate_limiter_reloaded() {
+        let mut target = RateLimiter::new(10, 5, 10);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(Throttle::Allow, target.check(start));
+        }
+        // what is left of the burst stays, up to the new one
+        target.set_limits(10, 1, 10);
+        assert_eq!(Throttle::Allow, target.check(start));
+        assert_eq!(Throttle::Reject, target.check(start));
+        target.set_limits(0, 0, 0);
+        assert_eq!(Throttle::Allow, target.check(start));
+
+        let mut target = RateLimiter::unlimited();
+        target.set_limits(1, 2, 1);
+        let now = Instant::now();
+        assert_eq!(Throttle::Allow, target.check(now));
+        assert_eq!(Throttle::Allow, target.check(now));
+        assert_eq!(Throttle::Reject, target.check(now));
+    }
+}
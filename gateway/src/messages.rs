@@ -1,27 +1,43 @@
 use std::{
     cell::RefCell,
     ffi::CString,
-    io::{Read, Write},
-    os::fd::AsFd,
+    io::{self, Write},
     rc::Rc,
+    time::Instant,
 };
 
 use anyhow::{bail, Result};
-use dbhook::genericdb::GenericDB;
+use dbhook::genericdb::{GenericDB, UserLogin};
 use oep::{
     cancel::Cancel,
     decoder::Decoder,
-    header::{OepHeader, OEP_VERSION},
+    execution_report::execution_report_size,
+    header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
     login::Login,
+    loginreject::{LoginReject, LoginRejectReason, LOGINREJECT_SIZE},
+    masscancel::MassCancel,
+    massquote::MassQuote,
     modify::Modify,
     neworder::NewOrder,
     oep_message::{MsgType, OepMessage},
+    quote::Quote,
+    quotecancelall::QuoteCancelAll,
+    replace::Replace,
+    resendrequest::ResendRequest,
+    sessioninfo::SessionInfo,
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    entitlements::Entitlements,
+    listener::{ListenerConfig, RateLimiter},
+    sequence::{Sequencing, SessionSequence, DEFAULT_MAX_SENT},
 };
-use polling::AsSource;
 
 pub struct ConnectedSession<TSocket>
 where
-    TSocket: Read + Write + AsFd + AsSource,
+    TSocket: Write,
 {
     pub(crate) socket: Rc<RefCell<TSocket>>,
     pub(crate) session_id: u32,
@@ -31,9 +47,23 @@ where
     pub response_buffer: Vec<u8>,
     pub(crate) is_corked: bool,
     cork_buf: Vec<u8>,
+    // the listener that accepted this session, if any
+    pub(crate) listener: Option<Rc<ListenerConfig>>,
+    pub(crate) rate_limiter: RateLimiter,
+    // when the client last sent anything
+    pub(crate) last_activity: Instant,
+    // shared with the later connections of the same session
+    pub(crate) sequence: Rc<RefCell<SessionSequence>>,
+    // negotiated at login, see oep::version
+    pub(crate) oep_version: u16,
+    // asked for at login, the orders of the session being cancelled when it
+    // disconnects
+    pub(crate) cancel_on_disconnect: bool,
+    // of the participant, loaded at login
+    pub(crate) entitlements: Entitlements,
 }
 
-impl<TSocket: Read + Write + AsFd + AsSource> ConnectedSession<TSocket> {
+impl<TSocket: Write> ConnectedSession<TSocket> {
     pub fn new(socket: Rc<RefCell<TSocket>>) -> Self {
         Self {
             socket: socket.clone(),
@@ -43,9 +73,78 @@ impl<TSocket: Read + Write + AsFd + AsSource> ConnectedSession<TSocket> {
             response_buffer: Vec::with_capacity(500),
             is_corked: false,
             cork_buf: vec![],
+            listener: None,
+            rate_limiter: RateLimiter::unlimited(),
+            last_activity: Instant::now(),
+            sequence: Rc::new(RefCell::new(SessionSequence::new(DEFAULT_MAX_SENT))),
+            oep_version: OEP_VERSION,
+            cancel_on_disconnect: true,
+            entitlements: Entitlements::default(),
         }
     }
 
+    /// Binds the session to the listener that accepted it, inheriting its rate limit
+    pub fn set_listener(&mut self, listener: Rc<ListenerConfig>) {
+        self.rate_limiter = listener.rate_limiter();
+        self.listener = Some(listener);
+    }
+
+    /// Moves the session on to the @listener reloaded, with its rate limit
+    pub fn update_listener(&mut self, listener: Rc<ListenerConfig>) {
+        self.rate_limiter.set_limits(
+            listener.max_messages_per_second,
+            listener.burst_messages,
+            listener.max_throttled_per_second,
+        );
+        self.listener = Some(listener);
+    }
+
+    /// Whether the client has been silent for longer than its listener allows
+    pub fn is_timed_out(&self, now: Instant) -> bool {
+        self.listener
+            .as_ref()
+            .is_some_and(|l| l.is_timed_out(self.last_activity, now))
+    }
+
+    /// Picks up the sequences of the session, as left by its previous connections
+    pub fn set_sequence(&mut self, sequence: Rc<RefCell<SessionSequence>>) {
+        self.sequence = sequence;
+    }
+
+    /// Checks the sequence of an order message received from the client
+    pub fn check_inbound(&mut self, seq: u32) -> Sequencing {
+        self.sequence.borrow_mut().check_inbound(seq)
+    }
+
+    /// Sends the execution report @buf, with its OEP header, under the next
+    /// sequence of the session. It is kept for the resends as it is
+    pub fn send_sequenced(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        let mut message = buf.to_vec();
+        self.sequence.borrow_mut().stamp(&mut message);
+        self.send_report(&message)
+    }
+
+    /// Sends the execution report @buf, with its OEP header, as encoded by the
+    /// matching engines in OEP_VERSION: the client gets it in the version of
+    /// the session, the fields added since cut off
+    pub fn send_report(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        let size = execution_report_size(self.oep_version);
+        let (Some(header), Some(body)) = (
+            buf.get(..OEP_HEADER_SIZE),
+            buf.get(OEP_HEADER_SIZE..OEP_HEADER_SIZE + size),
+        ) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "execution report too short",
+            ));
+        };
+        let mut header = OepHeader::decode(header.try_into().unwrap())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        header.oep_version = self.oep_version;
+        header.msg_len = size as u32;
+        self.send(&[header.encode().as_slice(), body].concat())
+    }
+
     pub fn send(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
         if !self.is_corked {
             self.socket.borrow_mut().write(buf)
@@ -55,13 +154,50 @@ impl<TSocket: Read + Write + AsFd + AsSource> ConnectedSession<TSocket> {
         }
     }
 
+    /// The SessionNotification for the matching engines, once the client is
+    /// gone, for the orders of the session on the gateway @gateway_id to be
+    /// cancelled. None if it never logged in, or logged in keeping its orders
+    pub fn disconnect_notification(&self, gateway_id: u8) -> Option<Vec<u8>> {
+        if self.participant == 0 || self.session_id == 0 || !self.cancel_on_disconnect {
+            return None;
+        }
+        let mut buffer: Vec<u8> = Vec::with_capacity(32);
+        buffer.extend_from_slice(&[MsgType::SessionNotification as u8, 0, 0, 0]);
+        buffer.extend_from_slice(
+            &SessionInfo::new(self.participant, self.session_id, gateway_id).encode(),
+        );
+        Some(buffer)
+    }
+
+    /// Tells the client why its @login is refused, the connection being
+    /// closed right after
+    pub fn send_login_reject(
+        &mut self,
+        login: &dyn OepMessage,
+        reason: LoginRejectReason,
+    ) -> Result<usize, std::io::Error> {
+        let reject = LoginReject::new(
+            login.get_participant(),
+            login.get_session_id(),
+            login.get_gateway_id(),
+            reason,
+        );
+        // like the login, in the same layout whatever the version
+        let header = OepHeader::new(
+            OEP_VERSION,
+            MsgType::LoginReject.into(),
+            LOGINREJECT_SIZE as u32,
+        );
+        self.send(&[header.encode().as_slice(), &reject.encode()].concat())
+    }
+
     pub fn cork(&mut self) {
         self.is_corked = true;
     }
 
     pub fn uncork(&mut self) -> Result<usize, std::io::Error> {
         let mut sent = 0;
-        if self.cork_buf.len() > 0 {
+        if !self.cork_buf.is_empty() {
             sent = self.socket.borrow_mut().write(self.cork_buf.as_slice())?;
             self.cork_buf.clear();
         }
@@ -70,9 +206,130 @@ impl<TSocket: Read + Write + AsFd + AsSource> ConnectedSession<TSocket> {
     }
 }
 
-/// for a login message: returns an updated participant ID in case login was successful
-/// or 0 if login failed. For the rest of the messages, returns the participant ID.
-/// If message is not accepted => Err
+/// A login looked up in the database, its password still to be checked
+pub struct PendingLogin {
+    pub login: Login,
+    user: String,
+    user_login: UserLogin,
+}
+
+impl PendingLogin {
+    /// Checks the password of the login, taking the time of the hashing
+    ///
+    /// Returns: the hash to store in place of the password, if any
+    pub fn check(&self) -> Result<Option<String>> {
+        timeit!(login, self.user_login.check(&self.login.password))
+    }
+}
+
+/// Starts the login of @session with @msg, looking its user up in @db. The
+/// password is then checked with PendingLogin::check, off the thread serving
+/// the sessions, for complete_login to carry on. A login reject is sent if
+/// the user can't log in
+pub fn begin_login<TSocket: Write>(
+    db: &mut Box<dyn GenericDB>,
+    session: &mut ConnectedSession<TSocket>,
+    msg: &Login,
+) -> Result<PendingLogin> {
+    let session_id = msg.session_id;
+    if let Some(listener) = session.listener.clone() {
+        if !listener.accepts_session(session_id) {
+            let _ = session.send_login_reject(msg, LoginRejectReason::BadCredentials);
+            bail!(
+                "Session {session_id} is outside the namespace of listener {}",
+                listener.name
+            );
+        }
+    }
+    session.session_id = session_id;
+    let mut v: Vec<u8> = msg.user.into_iter().filter(|x| *x != 0).collect();
+    v.push(0);
+    let user = CString::from_vec_with_nul(v)
+        .expect("receive_message cstring::new")
+        .into_string()
+        .expect("receive_message into_string");
+    match db.get_login(&user, session_id) {
+        Ok(user_login) => Ok(PendingLogin {
+            login: *msg,
+            user,
+            user_login,
+        }),
+        Err(e) => {
+            let _ = session.send_login_reject(msg, LoginRejectReason::BadCredentials);
+            Err(e)
+        }
+    }
+}
+
+/// Completes the login of @session as @pending, @checked by
+/// PendingLogin::check: stores the upgraded password, and replies to the
+/// client with the participant logged in, or a login reject
+///
+/// Returns: the participant id
+pub fn complete_login<TSocket: Write>(
+    db: &mut Box<dyn GenericDB>,
+    session: &mut ConnectedSession<TSocket>,
+    pending: &PendingLogin,
+    checked: Result<Option<String>>,
+) -> Result<u64> {
+    let msg = &pending.login;
+    let session_id = msg.session_id;
+    let participant = pending.user_login.participant;
+    match checked {
+        Ok(Some(hash)) => {
+            // the login goes on with the password as it was
+            if let Err(e) = db.store_password_hash(&pending.user, session_id, &hash) {
+                warn!(session_id, error = %e, "Unable to store the rehashed password");
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            let _ = session.send_login_reject(msg, LoginRejectReason::BadCredentials);
+            return Err(e);
+        }
+    }
+    if participant == 0 {
+        let _ = session.send_login_reject(msg, LoginRejectReason::BadCredentials);
+        bail!("No participant for {}", pending.user);
+    }
+    let books = db.get_entitlements(participant);
+    if books.is_err() {
+        let _ = session.send_login_reject(msg, LoginRejectReason::Unspecified);
+    }
+    session.entitlements = Entitlements::new(books?);
+    session.participant = participant;
+    session.cancel_on_disconnect = msg.cancels_on_disconnect();
+    info!(
+        participant = session.participant,
+        session_id = session.session_id,
+        "Successful login"
+    );
+
+    // send the response back as the original login message with a
+    // standard header, carrying the version accepted and the
+    // sequence expected next, and the participant logged in
+    let mut reply = *msg;
+    reply.participant = session.participant;
+    let next_inbound = session.sequence.borrow().next_inbound();
+    session.cork();
+    session.send(
+        OepHeader::new(
+            session.oep_version,
+            MsgType::Login.into(),
+            oep::login::LOGIN_SIZE as u32,
+        )
+        .with_seq(next_inbound)
+        .encode()
+        .as_slice(),
+    )?;
+    session.send(&reply.encode())?;
+    session.uncork()?;
+    Ok(participant)
+}
+
+/// for a login message: returns an updated participant ID in case login was successful,
+/// the password being checked on the thread of the caller. For the rest of the messages,
+/// returns the participant ID. If message is not accepted => Err
 /// IMPORTANT: the message that needs to be relayed to the matching engine will
 /// end up in the session.response_buffer. It is up to the caller to actually
 /// send this message to the matching engine and clear the response_buffer afterwards.
@@ -90,11 +347,10 @@ impl<TSocket: Read + Write + AsFd + AsSource> ConnectedSession<TSocket> {
 ///
 /// Returns:
 ///     the participant id
-#[must_use]
-pub fn receive_and_prepare_relay_message<TSocket: Read + Write + AsFd + AsSource>(
+pub fn receive_and_prepare_relay_message<TSocket: Write>(
     db: &mut Box<dyn GenericDB>,
     session: &mut ConnectedSession<TSocket>,
-    message: &Box<dyn OepMessage>,
+    message: &dyn OepMessage,
 ) -> Result<u64> {
     macro_rules! relay_message {
         ($message: expr, $msgtype: ty, $msg_type_encoding: expr) => {
@@ -120,40 +376,14 @@ pub fn receive_and_prepare_relay_message<TSocket: Read + Write + AsFd + AsSource
     match message.message_type() {
         MsgType::Login => {
             if session.participant == 0 {
-                // we need to check the login
+                // the password checked on this thread, see begin_login
                 let msg = message
                     .as_any()
                     .downcast_ref::<Login>()
                     .expect("Bad pointer conversion");
-                let session_id = msg.session_id;
-                session.session_id = session_id;
-                // TODO: check if already logged in
-                let mut v: Vec<u8> = msg.user.to_vec().into_iter().filter(|x| *x != 0).collect();
-                v.push(0);
-                session.participant = db.check_login(
-                    &CString::from_vec_with_nul(v)
-                        .expect("receive_message cstring::new")
-                        .into_string()
-                        .expect("receive_message into_string"),
-                    &msg.password,
-                    session_id,
-                )?;
-                println!("Successful login for participant {}", session.participant);
-
-                // send the response back as the original login message with a
-                // standard header
-                session.cork();
-                session.send(
-                    OepHeader::new(
-                        OEP_VERSION,
-                        MsgType::Login.into(),
-                        oep::login::LOGIN_SIZE as u32,
-                    )
-                    .encode()
-                    .as_slice(),
-                )?;
-                session.send(&msg.encode())?;
-                session.uncork()?;
+                let pending = begin_login(db, session, msg)?;
+                let checked = pending.check();
+                complete_login(db, session, &pending, checked)?;
             } else {
                 // already logged in
                 bail!("Already logged in");
@@ -167,32 +397,73 @@ pub fn receive_and_prepare_relay_message<TSocket: Read + Write + AsFd + AsSource
             check_session!();
             relay_message!(message, Modify, message.message_type());
         }
+        MsgType::Replace => {
+            check_session!();
+            relay_message!(message, Replace, message.message_type());
+        }
+        MsgType::MassCancel => {
+            check_session!();
+            relay_message!(message, MassCancel, message.message_type());
+        }
         MsgType::NewOrder => {
             check_session!();
             relay_message!(message, NewOrder, message.message_type());
         }
+        MsgType::Quote => {
+            check_session!();
+            relay_message!(message, Quote, message.message_type());
+        }
+        MsgType::MassQuote => {
+            check_session!();
+            relay_message!(message, MassQuote, message.message_type());
+        }
+        MsgType::QuoteCancelAll => {
+            check_session!();
+            relay_message!(message, QuoteCancelAll, message.message_type());
+        }
+        MsgType::Heartbeat => {
+            // only keeps the session alive, nothing to relay
+            check_session!();
+        }
+        MsgType::ResendRequest => {
+            check_session!();
+            let msg = message
+                .as_any()
+                .downcast_ref::<ResendRequest>()
+                .expect("Bad pointer conversion");
+            // sent as they went out the first time, with their original sequence
+            let sequence = session.sequence.clone();
+            session.cork();
+            for resent in sequence.borrow().resend(msg.from_seq) {
+                session.send_report(resent)?;
+            }
+            session.uncork()?;
+        }
         MsgType::ExecutionReport => {
-            eprintln!(
-                "Ignoring received execution report from participant {} on session {}",
-                session.participant, session.session_id
+            warn!(
+                participant = session.participant,
+                session_id = session.session_id,
+                "Ignoring an execution report sent by a client"
             );
             bail!("execution report message received");
         }
         MsgType::Trade => {
-            eprintln!(
-                "Ignoring received trade message from participant {} on session {}",
-                session.participant, session.session_id
+            warn!(
+                participant = session.participant,
+                session_id = session.session_id,
+                "Ignoring a trade message sent by a client"
             );
             bail!("trade message received");
         }
         _ => {
-            eprintln!(
-                "Ignoring received unknown message type from participant {} on session {}",
-                session.participant, session.session_id
+            warn!(
+                participant = session.participant,
+                session_id = session.session_id,
+                "Ignoring an unknown message type sent by a client"
             );
             bail!("unknown message received");
         }
     }
 
-    return Ok(session.participant);
+    Ok(session.participant)
 }
@@ -0,0 +1,78 @@
+//! What the gateway counts, for its metrics endpoint
+
+use std::sync::Arc;
+
+use oep::execution_report::RejectReason;
+use utils::metrics::{Counter, Gauge, Registry};
+
+/// The handles of the metrics of the gateway, in the registry of the process
+#[derive(Debug)]
+pub struct GatewayMetrics {
+    registry: &'static Registry,
+    // read off the clients
+    pub messages_in: Arc<Counter>,
+    // relayed to the matching engines
+    pub messages_relayed: Arc<Counter>,
+    // logged in sessions
+    pub sessions: Arc<Gauge>,
+}
+
+impl GatewayMetrics {
+    pub fn new(registry: &'static Registry) -> Self {
+        Self {
+            registry,
+            messages_in: registry.counter(
+                "gateway_messages_in_total",
+                "Messages received from the clients",
+                &[],
+            ),
+            messages_relayed: registry.counter(
+                "gateway_messages_relayed_total",
+                "Messages relayed to the matching engines",
+                &[],
+            ),
+            sessions: registry.gauge("gateway_sessions", "Sessions logged in", &[]),
+        }
+    }
+
+    /// Counts a message of a client rejected by the gateway for @reason
+    pub fn reject(&self, reason: RejectReason) {
+        self.registry
+            .counter(
+                "gateway_rejects_total",
+                "Messages rejected by the gateway, by reason",
+                &[("reason", &format!("{reason:?}"))],
+            )
+            .inc();
+    }
+}
+
+/// The messages sent to the clients, counted by their writers
+pub fn messages_out(registry: &Registry) -> Arc<Counter> {
+    registry.counter(
+        "gateway_messages_out_total",
+        "Messages sent to the clients",
+        &[],
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use oep::execution_report::RejectReason;
+    use utils::metrics::Registry;
+
+    use super::GatewayMetrics;
+
+    #[test]
+    fn rejects_by_reason() {
+        let registry: &'static Registry = Box::leak(Box::new(Registry::new()));
+        let target = GatewayMetrics::new(registry);
+        target.reject(RejectReason::Throttled);
+        target.reject(RejectReason::Throttled);
+        target.reject(RejectReason::OutOfSequence);
+        let rendered = registry.render();
+        assert!(rendered.contains("gateway_rejects_total{reason=\"Throttled\"} 2\n"));
+        assert!(rendered.contains("gateway_rejects_total{reason=\"OutOfSequence\"} 1\n"));
+        assert!(rendered.contains("gateway_sessions 0\n"));
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use dbhook::genericdb::GenericDB;
+
+// execution reports kept per session, unless configured otherwise
+pub const DEFAULT_MAX_PENDING_REPORTS: usize = 10000;
+
+/// The execution reports of the sessions that are not connected, kept in the
+/// database until they log in again
+///
+/// The queue of a session is bounded: once full, the reports are dropped with
+/// an error, the client being left with a resend request for recovering them.
+#[derive(Debug)]
+pub struct OutboundQueue {
+    // 0 keeps nothing
+    max_per_session: usize,
+    // session id -> reports kept for it
+    pending: HashMap<u32, usize>,
+}
+
+impl Default for OutboundQueue {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PENDING_REPORTS)
+    }
+}
+
+impl OutboundQueue {
+    pub fn new(max_per_session: usize) -> Self {
+        Self {
+            max_per_session,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn pending(&self, session_id: u32) -> usize {
+        self.pending.get(&session_id).copied().unwrap_or(0)
+    }
+
+    /// Keeps @message, an execution report with its OEP header, for @session_id
+    ///
+    /// Returns: false if the queue of the session is full and @message was dropped
+    pub fn store(
+        &mut self,
+        db: &mut Box<dyn GenericDB>,
+        session_id: u32,
+        message: &[u8],
+    ) -> Result<bool> {
+        let pending = self.pending.entry(session_id).or_default();
+        if *pending >= self.max_per_session {
+            return Ok(false);
+        }
+        db.store_pending_report(session_id, message)?;
+        *pending += 1;
+        Ok(true)
+    }
+
+    /// The reports kept for @session_id, oldest first, to be sent once it
+    /// logged in again. They are gone from the database afterwards
+    pub fn take(&mut self, db: &mut Box<dyn GenericDB>, session_id: u32) -> Result<Vec<Vec<u8>>> {
+        let r = db.take_pending_reports(session_id)?;
+        self.pending.remove(&session_id);
+        Ok(r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use dbhook::{
+        genericdb::GenericDB,
+        mockdb::{FailureMode, MockDB},
+    };
+
+    use super::OutboundQueue;
+
+    #[test]
+    fn store_and_take() {
+        let mut db = dbhook::factory::build("mock");
+        let mut target = OutboundQueue::new(2);
+        assert!(target.store(&mut db, 1, &[1]).unwrap());
+        assert!(target.store(&mut db, 2, &[2]).unwrap());
+        assert!(target.store(&mut db, 1, &[3]).unwrap());
+        // full
+        assert!(!target.store(&mut db, 1, &[4]).unwrap());
+        assert_eq!(2, target.pending(1));
+
+        assert_eq!(vec![vec![1], vec![3]], target.take(&mut db, 1).unwrap());
+        assert_eq!(0, target.pending(1));
+        assert!(target.take(&mut db, 1).unwrap().is_empty());
+        // the other sessions keep theirs
+        assert_eq!(1, target.pending(2));
+        assert_eq!(vec![vec![2]], target.take(&mut db, 2).unwrap());
+    }
+
+    #[test]
+    fn database_failures() {
+        let db = MockDB::default();
+        db.faults().set_mode(FailureMode::Nth(2));
+        let mut db: Box<dyn GenericDB> = Box::new(db);
+        let mut target = OutboundQueue::new(2);
+        assert!(target.store(&mut db, 1, &[1]).unwrap());
+        // not kept, nor counted against the session
+        assert!(target.store(&mut db, 1, &[2]).is_err());
+        assert_eq!(1, target.pending(1));
+        assert!(target.store(&mut db, 1, &[3]).unwrap());
+        assert_eq!(vec![vec![1], vec![3]], target.take(&mut db, 1).unwrap());
+    }
+
+    #[test]
+    fn keeps_nothing() {
+        let mut db = dbhook::factory::build("mock");
+        let mut target = OutboundQueue::new(0);
+        assert!(!target.store(&mut db, 1, &[1]).unwrap());
+        assert!(target.take(&mut db, 1).unwrap().is_empty());
+    }
+}
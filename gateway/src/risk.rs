@@ -0,0 +1,299 @@
+use std::collections::{HashMap, HashSet};
+
+use dbhook::risklimits::RiskLimits;
+use oep::{
+    execution_report::{ExecutionReport, RejectReason},
+    massquote::MassQuote,
+    modify::Modify,
+    neworder::NewOrder,
+    oep_message::{MsgType, OepMessage},
+    quote::Quote,
+    replace::Replace,
+};
+use order::OrderState;
+
+/// Pre-trade checks of the orders, against the limits of their participant
+///
+/// The open orders are counted from the execution reports relayed back to the
+/// clients, so only the orders entered through this gateway are accounted for.
+/// The quotes are bound by the quantity and the notional on each of their
+/// sides, not by the open orders. The participants without limits are not
+/// checked.
+#[derive(Debug, Default)]
+pub struct RiskChecker {
+    limits: HashMap<u64, RiskLimits>,
+    // participant -> ids of its orders resting in the books
+    open_orders: HashMap<u64, HashSet<u64>>,
+}
+
+impl RiskChecker {
+    /// Replaces all the limits, e.g. after reloading them from the database.
+    /// The open orders are kept
+    pub fn set_limits(&mut self, limits: Vec<RiskLimits>) {
+        self.limits = limits.into_iter().map(|l| (l.participant, l)).collect();
+    }
+
+    pub fn get_limits(&self, participant: u64) -> Option<&RiskLimits> {
+        self.limits.get(&participant)
+    }
+
+    pub fn open_orders(&self, participant: u64) -> usize {
+        self.open_orders.get(&participant).map_or(0, |o| o.len())
+    }
+
+    /// Checks @message before it is relayed to the matching engine
+    ///
+    /// Returns: why the message has to be rejected, if it has to
+    pub fn check(&self, message: &dyn OepMessage) -> Result<(), RejectReason> {
+        let Some(limits) = self.limits.get(&message.get_participant()) else {
+            return Ok(());
+        };
+        let (quantity, price, new_order) = match message.message_type() {
+            MsgType::NewOrder => {
+                let m = message.as_any().downcast_ref::<NewOrder>().unwrap();
+                (m.quantity, m.price, true)
+            }
+            MsgType::Modify => {
+                let m = message.as_any().downcast_ref::<Modify>().unwrap();
+                (m.quantity, m.price, false)
+            }
+            // takes the place of an open order
+            MsgType::Replace => {
+                let m = message.as_any().downcast_ref::<Replace>().unwrap();
+                (m.quantity, m.price, false)
+            }
+            MsgType::Quote => {
+                let m = message.as_any().downcast_ref::<Quote>().unwrap();
+                return check_size(limits, m.bid_quantity, m.bid_price)
+                    .and_then(|_| check_size(limits, m.ask_quantity, m.ask_price));
+            }
+            MsgType::MassQuote => {
+                let m = message.as_any().downcast_ref::<MassQuote>().unwrap();
+                return m.quotes().try_for_each(|q| {
+                    check_size(limits, q.bid_quantity, q.bid_price)
+                        .and_then(|_| check_size(limits, q.ask_quantity, q.ask_price))
+                });
+            }
+            _ => return Ok(()),
+        };
+
+        check_size(limits, quantity, price)?;
+        if new_order
+            && limits.max_open_orders != 0
+            && self.open_orders(limits.participant) >= limits.max_open_orders as usize
+        {
+            return Err(RejectReason::OpenOrdersLimit);
+        }
+        Ok(())
+    }
+
+    /// Keeps track of the open orders, from the reports of the matching engine
+    pub fn on_execution_report(&mut self, ereport: &ExecutionReport) {
+        let order_id = ereport.order_id;
+        let orders = self.open_orders.entry(ereport.participant).or_default();
+        match OrderState::from(ereport.state) {
+            // a rejected modify leaves the order as it was
+            OrderState::Rejected => {}
+            // the quotes are not counted
+            OrderState::QuoteAck => {}
+            OrderState::Cancelled => {
+                orders.remove(&order_id);
+            }
+            _ if ereport.leaves_quantity > 0 => {
+                orders.insert(order_id);
+            }
+            _ => {
+                orders.remove(&order_id);
+            }
+        }
+    }
+}
+
+fn check_size(limits: &RiskLimits, quantity: u64, price: u64) -> Result<(), RejectReason> {
+    if limits.max_quantity != 0 && quantity > limits.max_quantity {
+        return Err(RejectReason::QuantityLimit);
+    }
+    // the market orders carry no price, they are only bound by the quantity
+    if limits.max_notional != 0 && quantity as u128 * price as u128 > limits.max_notional as u128 {
+        return Err(RejectReason::NotionalLimit);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use dbhook::risklimits::RiskLimits;
+    use oep::{
+        cancel::Cancel,
+        execution_report::{ExecutionReport, RejectReason},
+        modify::Modify,
+        neworder::NewOrder,
+        quote::Quote,
+    };
+    use order::OrderState;
+
+    use super::RiskChecker;
+
+    const PARTICIPANT: u64 = 3;
+
+    fn new_order(quantity: u64, price: u64) -> NewOrder {
+        NewOrder {
+            client_order_id: 7,
+            participant: PARTICIPANT,
+            book_id: 1,
+            quantity,
+            price,
+            order_type: 0,
+            side: 1,
+            gateway_id: 1,
+            session_id: 1,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        }
+    }
+
+    fn report(order_id: u64, state: OrderState, leaves_quantity: u64) -> ExecutionReport {
+        ExecutionReport {
+            participant: PARTICIPANT,
+            order_id,
+            submitted_order_id: 7,
+            book: 1,
+            quantity: 100,
+            price: 10,
+            flags: 0,
+            side: 1,
+            state: state.into(),
+            gateway_id: 1,
+            session_id: 1,
+            filled_quantity: 0,
+            leaves_quantity,
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
+        }
+    }
+
+    fn target() -> RiskChecker {
+        let mut target = RiskChecker::default();
+        target.set_limits(vec![RiskLimits {
+            participant: PARTICIPANT,
+            max_quantity: 1000,
+            max_notional: 50000,
+            max_open_orders: 2,
+        }]);
+        target
+    }
+
+    #[test]
+    fn quantity_and_notional() {
+        let target = target();
+        assert_eq!(Ok(()), target.check(&new_order(1000, 50)));
+        assert_eq!(
+            Err(RejectReason::QuantityLimit),
+            target.check(&new_order(1001, 1))
+        );
+        assert_eq!(
+            Err(RejectReason::NotionalLimit),
+            target.check(&new_order(1000, 51))
+        );
+        // no price to check
+        assert_eq!(Ok(()), target.check(&new_order(1000, 0)));
+        let modify = Modify {
+            participant: PARTICIPANT,
+            order_id: 1,
+            book_id: 1,
+            quantity: 2000,
+            price: 10,
+            side: 1,
+            gateway_id: 1,
+            session_id: 1,
+            orig_client_order_id: 0,
+        };
+        assert_eq!(Err(RejectReason::QuantityLimit), target.check(&modify));
+        // never too much to cancel
+        let cancel = Cancel {
+            participant: PARTICIPANT,
+            order_id: 1,
+            book_id: 1,
+            side: 1,
+            gateway_id: 1,
+            session_id: 1,
+            orig_client_order_id: 0,
+        };
+        assert_eq!(Ok(()), target.check(&cancel));
+        // each side of a quote on its own
+        let quote = Quote {
+            quote_id: 1,
+            participant: PARTICIPANT,
+            book_id: 1,
+            bid_price: 50,
+            bid_quantity: 1000,
+            ask_price: 51,
+            ask_quantity: 900,
+            gateway_id: 1,
+            session_id: 1,
+        };
+        assert_eq!(Ok(()), target.check(&quote));
+        let quote = Quote {
+            ask_quantity: 1000,
+            ..quote
+        };
+        assert_eq!(Err(RejectReason::NotionalLimit), target.check(&quote));
+    }
+
+    #[test]
+    fn unknown_participants_are_not_checked() {
+        let target = target();
+        let order = NewOrder {
+            participant: PARTICIPANT + 1,
+            ..new_order(u64::MAX, u64::MAX)
+        };
+        assert_eq!(Ok(()), target.check(&order));
+    }
+
+    #[test]
+    fn open_orders() {
+        let mut target = target();
+        target.on_execution_report(&report(1, OrderState::Inserted, 100));
+        target.on_execution_report(&report(2, OrderState::PartiallyTraded, 50));
+        // traded on entry, never rested
+        target.on_execution_report(&report(3, OrderState::Traded, 0));
+        assert_eq!(2, target.open_orders(PARTICIPANT));
+        assert_eq!(
+            Err(RejectReason::OpenOrdersLimit),
+            target.check(&new_order(10, 10))
+        );
+
+        // a rejected modify doesn't close the order
+        target.on_execution_report(&report(1, OrderState::Rejected, 0));
+        assert_eq!(2, target.open_orders(PARTICIPANT));
+        target.on_execution_report(&report(1, OrderState::Cancelled, 0));
+        target.on_execution_report(&report(2, OrderState::Traded, 0));
+        assert_eq!(0, target.open_orders(PARTICIPANT));
+        // busting a trade doesn't bring the order back
+        target.on_execution_report(&report(2, OrderState::TradeBusted, 0));
+        assert_eq!(0, target.open_orders(PARTICIPANT));
+        assert_eq!(Ok(()), target.check(&new_order(10, 10)));
+    }
+
+    #[test]
+    fn reloading_keeps_the_open_orders() {
+        let mut target = target();
+        target.on_execution_report(&report(1, OrderState::Inserted, 100));
+        target.set_limits(vec![RiskLimits {
+            participant: PARTICIPANT,
+            max_open_orders: 1,
+            ..Default::default()
+        }]);
+        assert_eq!(1, target.get_limits(PARTICIPANT).unwrap().max_open_orders);
+        assert_eq!(
+            Err(RejectReason::OpenOrdersLimit),
+            target.check(&new_order(u64::MAX, 10))
+        );
+        target.set_limits(vec![]);
+        assert!(target.get_limits(PARTICIPANT).is_none());
+        assert_eq!(Ok(()), target.check(&new_order(u64::MAX, 10)));
+    }
+}
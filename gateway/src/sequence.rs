@@ -0,0 +1,132 @@
+//! Sequencing of the OEP sessions
+//!
+//! The clients number the order messages they send, starting with 1 and going
+//! up by one per message. The gateway numbers the execution reports it sends
+//! back the same way and keeps the last ones, so that a client can get again
+//! what it missed, e.g. while it was disconnected. The sequences last as long
+//! as the gateway, whatever the reconnects of the client.
+
+use std::collections::VecDeque;
+
+use oep::header::OEP_HEADER_SIZE;
+
+// where the sequence sits in the OEP header
+const SEQ_OFFSET: usize = OEP_HEADER_SIZE - 4;
+// messages kept for the resends of a session, unless configured otherwise
+pub const DEFAULT_MAX_SENT: usize = 10000;
+
+/// How the sequence of a received message relates to the expected one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sequencing {
+    Expected,
+    // already received, e.g. sent again by a client unsure it made it
+    Duplicate,
+    // some messages were skipped, the client has to start again from the expected one
+    Gap { expected: u32 },
+}
+
+#[derive(Debug)]
+pub struct SessionSequence {
+    next_inbound: u32,
+    next_outbound: u32,
+    // the last messages sent, header included, oldest first
+    sent: VecDeque<(u32, Vec<u8>)>,
+    max_sent: usize,
+}
+
+impl SessionSequence {
+    /// @max_sent - how many of the messages sent are kept for a resend
+    pub fn new(max_sent: usize) -> Self {
+        Self {
+            next_inbound: 1,
+            next_outbound: 1,
+            sent: VecDeque::new(),
+            max_sent,
+        }
+    }
+
+    pub fn next_inbound(&self) -> u32 {
+        self.next_inbound
+    }
+
+    /// Checks the sequence of a message received from the client, moving on
+    /// to the next one only if it was the expected one
+    pub fn check_inbound(&mut self, seq: u32) -> Sequencing {
+        match seq {
+            s if s == self.next_inbound => {
+                self.next_inbound += 1;
+                Sequencing::Expected
+            }
+            s if s < self.next_inbound => Sequencing::Duplicate,
+            _ => Sequencing::Gap {
+                expected: self.next_inbound,
+            },
+        }
+    }
+
+    /// Writes the next outbound sequence in the header of @message and keeps
+    /// a copy of it for the resends
+    pub fn stamp(&mut self, message: &mut [u8]) {
+        let seq = self.next_outbound;
+        self.next_outbound += 1;
+        message[SEQ_OFFSET..OEP_HEADER_SIZE].copy_from_slice(&seq.to_le_bytes());
+        if self.max_sent == 0 {
+            return;
+        }
+        if self.sent.len() == self.max_sent {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((seq, message.to_vec()));
+    }
+
+    /// The messages sent with @from_seq and after, as far as they were kept
+    pub fn resend(&self, from_seq: u32) -> impl Iterator<Item = &[u8]> {
+        self.sent
+            .iter()
+            .filter(move |(seq, _)| *seq >= from_seq)
+            .map(|(_, message)| message.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use oep::{
+        decoder::Decoder,
+        header::{OepHeader, OEP_HEADER_SIZE},
+    };
+
+    use super::{Sequencing, SessionSequence};
+
+    #[test]
+    fn inbound() {
+        let mut target = SessionSequence::new(10);
+        assert_eq!(Sequencing::Expected, target.check_inbound(1));
+        assert_eq!(Sequencing::Expected, target.check_inbound(2));
+        assert_eq!(Sequencing::Duplicate, target.check_inbound(2));
+        assert_eq!(Sequencing::Gap { expected: 3 }, target.check_inbound(5));
+        // the gap doesn't move the expected sequence
+        assert_eq!(3, target.next_inbound());
+        assert_eq!(Sequencing::Expected, target.check_inbound(3));
+    }
+
+    #[test]
+    fn outbound_and_resend() {
+        let mut target = SessionSequence::new(2);
+        for i in 0..3u8 {
+            let mut message = [&[0; OEP_HEADER_SIZE][..], &[i]].concat();
+            target.stamp(&mut message);
+            let header = OepHeader::decode(message[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+            assert_eq!(i as u32 + 1, { header.seq });
+        }
+        // only the last 2 were kept
+        let resent: Vec<u8> = target.resend(1).map(|m| m[OEP_HEADER_SIZE]).collect();
+        assert_eq!(vec![1, 2], resent);
+        assert_eq!(1, target.resend(3).count());
+        assert_eq!(0, target.resend(4).count());
+
+        let mut target = SessionSequence::new(0);
+        let mut message = [0; OEP_HEADER_SIZE];
+        target.stamp(&mut message);
+        assert_eq!(0, target.resend(1).count());
+    }
+}
@@ -0,0 +1,2653 @@
+//! The networking of the gateway, on a single threaded tokio runtime
+//!
+//! Every listener has an acceptor task, and every client a reader task and a
+//! writer task. What comes back from the matching engines is dispatched by the
+//! router task, a second one taking the execution reports sent by unicast if
+//! the gateway has an endpoint, while the messages for the matching engines go
+//! through the relay channel, drained by the relay task. The tasks share the GatewayState,
+//! which is only borrowed in between two awaits: handling a message never yields.
+//!
+//! The drop copy consumers, if any, have an acceptor task of their own, and a
+//! reader and a writer task each. The gateway stops with the first task that
+//! does, the one waiting for SIGTERM or SIGINT included. Another one waits
+//! for SIGHUP, reloading the settings that can change while serving.
+//!
+//! The OEP logic itself lives in GatewayState, free of any networking, so that
+//! it can be driven directly by the tests.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
+    ops::ControlFlow,
+    rc::Rc,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use dbhook::genericdb::GenericDB;
+use fix_gateway::session::FixSession;
+use oep::{
+    decoder::Decoder,
+    egress::{EgressNak, EGRESSNAK_SIZE},
+    engine_status::{EngineStatus, ENGINESTATUS_SIZE},
+    execution_report::{ExecutionReport, RejectReason, EXECUTIONREPORT_SIZE},
+    header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+    ingress::{IngressMode, IngressNak, INGRESSNAK_SIZE},
+    login::Login,
+    loginreject::LoginRejectReason,
+    oep_decode,
+    oep_message::{MsgType, OepMessage},
+    registration::{
+        GatewayRegistration, GatewayRegistrationReply, RegistrationKind, RegistrationStatus,
+        FLAG_ALL_REPORTS, GATEWAYREGISTRATIONREPLY_SIZE, GATEWAYREGISTRATION_SIZE,
+    },
+    version::{negotiate_version, VersionReject, VERSIONREJECT_SIZE},
+};
+use serde::Deserialize;
+use socket2::{Protocol, Socket, Type};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, UdpSocket,
+    },
+    runtime,
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Notify,
+    },
+    task::{self, LocalSet},
+    time,
+};
+use tracing::{debug_span, error, info, warn};
+use utils::{
+    config::{self, ConfigError},
+    metrics::{self, Counter, MetricsConfig},
+    network::{self, MulticastConfig},
+    reload, shutdown,
+};
+
+use crate::{
+    dropcopy::{DropCopy, DropCopyConfig},
+    duplicates::{RecentClientOrderIds, DEFAULT_CLIENT_ORDER_ID_WINDOW},
+    egress::{Egress, EgressTracker},
+    failover::{rejection_for, FailoverBuffer, FailoverConfig, PendingMessage, Relay},
+    ingress::{SequencedIngress, DEFAULT_MAX_RETRANSMIT},
+    listener::{ListenerConfig, Throttle},
+    messages::{
+        begin_login, complete_login, receive_and_prepare_relay_message, ConnectedSession,
+        PendingLogin,
+    },
+    metrics::{messages_out, GatewayMetrics},
+    outbound::{OutboundQueue, DEFAULT_MAX_PENDING_REPORTS},
+    risk::RiskChecker,
+    sequence::{Sequencing, SessionSequence, DEFAULT_MAX_SENT},
+};
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+/// Where the configuration is loaded from, at the start and on SIGHUP
+pub const CONFIG_FILE: &str = "gateway.ini";
+
+const MAX_READ_ARRAY_SIZE: usize = 15000;
+// no OEP message is that long, a header announcing more is garbage
+const MAX_MESSAGE_SIZE: usize = 1024;
+// how often the failover timeouts and the risk limits are looked at
+const HOUSEKEEPING_EVERY: Duration = Duration::from_millis(100);
+// well below the gateway_timeout_ms of the engines
+const REGISTER_EVERY: Duration = Duration::from_secs(1);
+// how often the FIX sessions are checked for a due heartbeat
+const FIX_HEARTBEAT_CHECK_EVERY: Duration = Duration::from_secs(1);
+// for the goodbyes to the clients and the engines to go out, once stopping
+const SHUT_DOWN_GRACE: Duration = Duration::from_millis(500);
+
+/// What happens to a login for a session logged in on another connection,
+/// the `duplicate_session` key of the [gateway] section
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum DuplicateSessionPolicy {
+    // the second connection gets a login reject, the first one keeps the session
+    #[default]
+    Reject,
+    // the first connection is closed once the second one is logged in
+    Takeover,
+}
+
+impl FromStr for DuplicateSessionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(DuplicateSessionPolicy::Reject),
+            "takeover" => Ok(DuplicateSessionPolicy::Takeover),
+            _ => bail!("Unknown duplicate session policy {s}"),
+        }
+    }
+}
+
+/// The [gateway] section, with its listeners
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    pub gateway_id: u8,
+    pub listeners: Vec<ListenerConfig>,
+    // where the messages for the matching engines are sent
+    pub publisher_addr: String,
+    pub publisher_port: u16,
+    // where the matching engines send their execution reports and statuses
+    pub internal_publisher_group: String,
+    pub internal_publisher_port: u16,
+    // where the engines with unicast reports send the ones of the gateway,
+    // announced with its registration
+    pub report_endpoint: Option<SocketAddr>,
+    // the interface, ttl and loopback of both
+    pub multicast: MulticastConfig,
+    pub max_packet_size: usize,
+    pub failover: FailoverConfig,
+    pub risk_refresh: Duration,
+    pub resend_buffer_size: usize,
+    pub max_pending_reports: usize,
+    // how the messages get to the matching engines, and how many are kept
+    // for their retransmission when sequenced
+    pub ingress: IngressMode,
+    pub ingress_buffer_size: usize,
+    // the [dropcopy] section, if any
+    pub dropcopy: Option<DropCopyConfig>,
+    pub duplicate_session: DuplicateSessionPolicy,
+    // client order ids remembered per session, for the duplicates to be refused
+    pub client_order_id_window: usize,
+    // the metrics endpoint, none without a metrics_port
+    pub metrics: Option<MetricsConfig>,
+}
+
+fn default_risk_refresh_s() -> u64 {
+    60
+}
+
+fn default_resend_buffer_size() -> usize {
+    DEFAULT_MAX_SENT
+}
+
+fn default_max_pending_reports() -> usize {
+    DEFAULT_MAX_PENDING_REPORTS
+}
+
+fn default_ingress_buffer_size() -> usize {
+    DEFAULT_MAX_RETRANSMIT
+}
+
+fn default_client_order_id_window() -> usize {
+    DEFAULT_CLIENT_ORDER_ID_WINDOW
+}
+
+/// The keys of the [gateway] section, as written in gateway.ini
+#[derive(Debug, Deserialize)]
+struct GatewaySection {
+    id: u8,
+    publisher_addr: String,
+    publisher_port: u16,
+    internal_publisher_group: String,
+    internal_publisher_port: u16,
+    report_address: Option<String>,
+    report_port: Option<u16>,
+    max_packet_size: u16,
+    #[serde(default = "default_risk_refresh_s")]
+    risk_refresh_s: u64,
+    #[serde(default = "default_resend_buffer_size")]
+    resend_buffer_size: usize,
+    #[serde(default = "default_max_pending_reports")]
+    max_pending_reports: usize,
+    #[serde(default, deserialize_with = "config::from_str")]
+    ingress: IngressMode,
+    #[serde(default = "default_ingress_buffer_size")]
+    ingress_buffer_size: usize,
+    #[serde(default, deserialize_with = "config::from_str")]
+    duplicate_session: DuplicateSessionPolicy,
+    #[serde(default = "default_client_order_id_window")]
+    client_order_id_window: usize,
+}
+
+impl GatewayConfig {
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self> {
+        let section: GatewaySection = config::section(config_map, "gateway")?;
+        let max_packet_size = section.max_packet_size as usize;
+        if max_packet_size > MAX_READ_ARRAY_SIZE {
+            return Err(ConfigError::new(
+                "gateway",
+                Some("max_packet_size"),
+                format!("can't be over {MAX_READ_ARRAY_SIZE}"),
+            )
+            .into());
+        }
+        let report_endpoint = match (&section.report_address, section.report_port) {
+            (None, None) => None,
+            (Some(address), Some(port)) => match address.parse::<IpAddr>() {
+                Ok(address) if !address.is_unspecified() => Some(SocketAddr::new(address, port)),
+                _ => {
+                    return Err(ConfigError::new(
+                        "gateway",
+                        Some("report_address"),
+                        "must be the IPv4 or IPv6 address of the gateway",
+                    )
+                    .into())
+                }
+            },
+            _ => {
+                return Err(ConfigError::new(
+                    "gateway",
+                    Some("report_port"),
+                    "goes with report_address",
+                )
+                .into())
+            }
+        };
+        Ok(Self {
+            gateway_id: section.id,
+            listeners: ListenerConfig::load_all(config_map)?,
+            publisher_addr: section.publisher_addr,
+            publisher_port: section.publisher_port,
+            internal_publisher_group: section.internal_publisher_group,
+            internal_publisher_port: section.internal_publisher_port,
+            report_endpoint,
+            multicast: MulticastConfig::from_config(config_map, "gateway")
+                .map_err(|e| anyhow!(e))?,
+            max_packet_size,
+            failover: FailoverConfig::from_config(config_map)?,
+            risk_refresh: Duration::from_secs(section.risk_refresh_s),
+            resend_buffer_size: section.resend_buffer_size,
+            max_pending_reports: section.max_pending_reports,
+            ingress: section.ingress,
+            ingress_buffer_size: section.ingress_buffer_size,
+            dropcopy: DropCopyConfig::from_config(config_map)?,
+            duplicate_session: section.duplicate_session,
+            client_order_id_window: section.client_order_id_window,
+            metrics: MetricsConfig::from_config(config_map, "gateway")?,
+        })
+    }
+}
+
+/// The write side of a client connection, as seen by its ConnectedSession:
+/// the bytes are handed over to the writer task of the client, translated to
+/// FIX first for the clients of the FIX listeners
+pub struct ClientWriter {
+    outgoing: UnboundedSender<Vec<u8>>,
+    fix: Option<Rc<RefCell<FixSession>>>,
+    sent: Arc<Counter>,
+}
+
+impl ClientWriter {
+    pub fn new(outgoing: UnboundedSender<Vec<u8>>) -> Self {
+        Self {
+            outgoing,
+            fix: None,
+            sent: messages_out(metrics::registry()),
+        }
+    }
+
+    /// The writer of a FIX client, whose session is translated by @fix
+    pub fn fix(outgoing: UnboundedSender<Vec<u8>>, fix: Rc<RefCell<FixSession>>) -> Self {
+        Self {
+            outgoing,
+            fix: Some(fix),
+            sent: messages_out(metrics::registry()),
+        }
+    }
+}
+
+impl Write for ClientWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let translated = match &self.fix {
+            Some(fix) => fix.borrow_mut().on_oep_data(buf),
+            None => buf.to_vec(),
+        };
+        if !translated.is_empty() {
+            self.outgoing
+                .send(translated)
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            self.sent.inc();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ClientWriter {
+    /// Logs a FIX client out with @text, nothing for an OEP client, which
+    /// only sees its connection closed
+    pub fn logout(&mut self, text: &str) {
+        let Some(fix) = &self.fix else {
+            return;
+        };
+        let logout = fix.borrow_mut().logout(text);
+        if !logout.is_empty() && self.outgoing.send(logout).is_ok() {
+            self.sent.inc();
+        }
+    }
+}
+
+/// Takes the first complete message off @buffer, along with its header
+///
+/// Returns: None while the message is incomplete, an error if it can't be decoded
+pub fn next_message(buffer: &mut Vec<u8>) -> io::Result<Option<(Box<dyn OepMessage>, OepHeader)>> {
+    if buffer.len() < OEP_HEADER_SIZE {
+        return Ok(None);
+    }
+    let header = OepHeader::decode(buffer[..OEP_HEADER_SIZE].try_into().unwrap())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    if header.msg_len as usize > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too long",
+        ));
+    }
+    let len = OEP_HEADER_SIZE + header.msg_len as usize;
+    if buffer.len() < len {
+        return Ok(None);
+    }
+    let message = oep_decode(&buffer[..len])?;
+    buffer.drain(..len);
+    Ok(Some((message, header)))
+}
+
+/// Sends the rejection @ereport to @session, counting it in @metrics
+fn send_rejection(
+    session: &mut ConnectedSession<ClientWriter>,
+    ereport: &ExecutionReport,
+    metrics: &GatewayMetrics,
+) {
+    metrics.reject(ereport.get_reject_reason());
+    let header = OepHeader::new(
+        OEP_VERSION,
+        MsgType::ExecutionReport.into(),
+        EXECUTIONREPORT_SIZE as u32,
+    )
+    .encode();
+    let _ = session.send_sequenced(&[header.as_slice(), ereport.encode().as_slice()].concat());
+}
+
+/// Everything the gateway knows about its clients and the matching engines
+pub struct GatewayState {
+    gateway_id: u8,
+    // tells this run of the gateway apart, for the engines to notice another
+    // one with the same id
+    instance: u32,
+    report_endpoint: Option<SocketAddr>,
+    // the engines (engine id, partition id) which accepted the registration
+    registered_with: HashSet<(u8, u8)>,
+    // by an engine, another gateway running with the same id
+    registration_refused: bool,
+    db: Box<dyn GenericDB>,
+    // where the passwords are checked off the thread serving the sessions,
+    // None to check them on it
+    login_checks: Option<UnboundedSender<(usize, PendingLogin)>>,
+    // the clients waiting for their password to be checked, what they send
+    // meanwhile kept in their receive buffer
+    checking_logins: HashSet<usize>,
+    risk: RiskChecker,
+    failover: FailoverBuffer,
+    // execution reports of the disconnected sessions
+    outbound: OutboundQueue,
+    // messages for the matching engines, drained by the relay task
+    relay: UnboundedSender<Vec<u8>>,
+    // framing of the messages for the engines, with a sequenced ingress
+    ingress: Option<SequencedIngress>,
+    // the sequences of the execution reports, from the engines with a sequenced egress
+    egress: EgressTracker,
+    // listener name -> its current settings, replaced on reloads
+    listeners: HashMap<String, Rc<ListenerConfig>>,
+    // client id -> its session
+    sessions: HashMap<usize, ConnectedSession<ClientWriter>>,
+    // logged in session id -> client id
+    session_id_to_client: HashMap<u32, usize>,
+    // session id -> its sequences, kept across the reconnects
+    sequences: HashMap<u32, Rc<RefCell<SessionSequence>>>,
+    resend_buffer_size: usize,
+    // session id -> the last client order ids of its orders, kept across the reconnects
+    client_order_ids: HashMap<u32, RecentClientOrderIds>,
+    client_order_id_window: usize,
+    next_client_id: usize,
+    // copies of all the execution reports, for the consumers allowed to see them
+    dropcopy: Option<DropCopy>,
+    duplicate_session: DuplicateSessionPolicy,
+    metrics: GatewayMetrics,
+}
+
+impl GatewayState {
+    pub fn new(
+        config: &GatewayConfig,
+        mut db: Box<dyn GenericDB>,
+        relay: UnboundedSender<Vec<u8>>,
+    ) -> Result<Self> {
+        let mut risk = RiskChecker::default();
+        risk.set_limits(db.get_risk_limits()?);
+        let ingress = match config.ingress {
+            IngressMode::Multicast => None,
+            IngressMode::Sequenced => {
+                // the engines tell the runs of the gateway apart by their start time
+                let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+                Some(SequencedIngress::new(
+                    config.gateway_id,
+                    epoch,
+                    config.ingress_buffer_size,
+                ))
+            }
+        };
+        Ok(Self {
+            gateway_id: config.gateway_id,
+            instance: SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u32,
+            report_endpoint: config.report_endpoint,
+            registered_with: HashSet::new(),
+            registration_refused: false,
+            db,
+            login_checks: None,
+            checking_logins: HashSet::new(),
+            risk,
+            failover: FailoverBuffer::new(config.failover.clone()),
+            outbound: OutboundQueue::new(config.max_pending_reports),
+            relay,
+            ingress,
+            egress: EgressTracker::new(config.gateway_id),
+            listeners: config
+                .listeners
+                .iter()
+                .map(|l| (l.name.clone(), Rc::new(l.clone())))
+                .collect(),
+            sessions: HashMap::new(),
+            session_id_to_client: HashMap::new(),
+            sequences: HashMap::new(),
+            resend_buffer_size: config.resend_buffer_size,
+            client_order_ids: HashMap::new(),
+            client_order_id_window: config.client_order_id_window,
+            next_client_id: 1,
+            dropcopy: config.dropcopy.as_ref().map(DropCopy::new),
+            duplicate_session: config.duplicate_session,
+            metrics: GatewayMetrics::new(metrics::registry()),
+        })
+    }
+
+    /// Hands the password checks of the logins over to @checks, which calls
+    /// on_login_checked with the outcome of PendingLogin::check
+    pub fn check_logins_on(&mut self, checks: UnboundedSender<(usize, PendingLogin)>) {
+        self.login_checks = Some(checks);
+    }
+
+    /// Starts the session of a newly connected client
+    ///
+    /// Returns: the id the client is known by from now on
+    pub fn add_client(
+        &mut self,
+        listener: Option<Rc<ListenerConfig>>,
+        writer: ClientWriter,
+    ) -> usize {
+        let client_id = self.next_client_id;
+        self.next_client_id += 1;
+        let mut session = ConnectedSession::new(Rc::new(RefCell::new(writer)));
+        if let Some(listener) = listener {
+            // the limits may have been reloaded since the listener started
+            let current = self.listeners.get(&listener.name).cloned();
+            session.set_listener(current.unwrap_or(listener));
+        }
+        self.sessions.insert(client_id, session);
+        client_id
+    }
+
+    pub fn is_connected(&self, client_id: usize) -> bool {
+        self.sessions.contains_key(&client_id)
+    }
+
+    /// How long @client_id can stay silent, as of the last reload
+    pub fn read_timeout(&self, client_id: usize) -> Option<Duration> {
+        self.sessions
+            .get(&client_id)
+            .and_then(|s| s.listener.as_ref())
+            .and_then(|l| l.session_timeout())
+    }
+
+    /// The sequences of @session_id, starting them if the session is new
+    fn sequence_for(&mut self, session_id: u32) -> Rc<RefCell<SessionSequence>> {
+        let resend_buffer_size = self.resend_buffer_size;
+        self.sequences
+            .entry(session_id)
+            .or_insert_with(|| Rc::new(RefCell::new(SessionSequence::new(resend_buffer_size))))
+            .clone()
+    }
+
+    /// Handles the bytes read from @client_id, as many messages as they complete
+    ///
+    /// Returns: Break if the client has to be disconnected
+    pub fn on_client_data(&mut self, client_id: usize, data: &[u8]) -> ControlFlow<()> {
+        let Some(session) = self.sessions.get_mut(&client_id) else {
+            return ControlFlow::Break(());
+        };
+        session.last_activity = Instant::now();
+        let recv_buffer = session.recv_buffer.clone();
+        recv_buffer.borrow_mut().extend_from_slice(data);
+        loop {
+            // the rest once logged in, see on_login_checked
+            if self.checking_logins.contains(&client_id) {
+                return ControlFlow::Continue(());
+            }
+            let next = timeit!(decode, next_message(&mut recv_buffer.borrow_mut()));
+            match next {
+                Ok(Some((message, header))) => {
+                    if self
+                        .on_client_message(client_id, message.as_ref(), header)
+                        .is_break()
+                    {
+                        return ControlFlow::Break(());
+                    }
+                }
+                Ok(None) => return ControlFlow::Continue(()),
+                Err(e) => {
+                    warn!(client_id, error = %e, "Client sent an invalid command, closing its socket");
+                    return ControlFlow::Break(());
+                }
+            }
+        }
+    }
+
+    fn on_client_message(
+        &mut self,
+        client_id: usize,
+        msg: &dyn OepMessage,
+        header: OepHeader,
+    ) -> ControlFlow<()> {
+        let _span = debug_span!(
+            "client_message",
+            client_id,
+            session_id = msg.get_session_id(),
+            participant = msg.get_participant(),
+            msg_type = ?msg.message_type(),
+            seq = { header.seq },
+        )
+        .entered();
+        self.metrics.messages_in.inc();
+        // another connection logged in with the same session, still there
+        let duplicate = self
+            .session_id_to_client
+            .get(&msg.get_session_id())
+            .copied()
+            .filter(|c| *c != client_id && self.sessions.contains_key(c));
+        let Some(p) = self.sessions.get_mut(&client_id) else {
+            return ControlFlow::Break(());
+        };
+        let (participant, session, seq) = (p.participant, p.session_id, header.seq);
+        if participant == 0 && msg.message_type() == MsgType::Login {
+            // the version of the session, out of the one proposed by the client
+            let proposed = header.oep_version;
+            match negotiate_version(proposed) {
+                Ok(version) => p.oep_version = version,
+                Err(e) => {
+                    warn!(session_id = msg.get_session_id(), error = %e, "Session can't log in");
+                    let reject = VersionReject::new(
+                        msg.get_participant(),
+                        msg.get_session_id(),
+                        self.gateway_id,
+                        proposed,
+                    );
+                    let header = OepHeader::new(
+                        OEP_VERSION,
+                        MsgType::VersionReject.into(),
+                        VERSIONREJECT_SIZE as u32,
+                    );
+                    let _ = p.send(&[header.encode().as_slice(), &reject.encode()].concat());
+                    return ControlFlow::Break(());
+                }
+            }
+            if duplicate.is_some() && self.duplicate_session == DuplicateSessionPolicy::Reject {
+                warn!(
+                    session_id = msg.get_session_id(),
+                    "Session already logged in on another connection"
+                );
+                let _ = p.send_login_reject(msg, LoginRejectReason::DuplicateSession);
+                return ControlFlow::Break(());
+            }
+            // carry on with the sequences of the previous connections
+            let sequence = self.sequence_for(msg.get_session_id());
+            self.sessions
+                .get_mut(&client_id)
+                .unwrap()
+                .set_sequence(sequence);
+        }
+        let p = self.sessions.get_mut(&client_id).unwrap();
+
+        // a logged in session sticks to the version agreed on
+        if participant != 0 && header.oep_version != p.oep_version {
+            warn!(
+                session_id = session,
+                version = { header.oep_version },
+                agreed = p.oep_version,
+                "Session sent another version than the one agreed on, closing connection"
+            );
+            return ControlFlow::Break(());
+        }
+
+        // the order messages of a logged in session come in sequence
+        if participant != 0 && msg.message_type().is_sequenced() {
+            match p.check_inbound(seq) {
+                Sequencing::Expected => {}
+                Sequencing::Duplicate => {
+                    info!(
+                        session_id = session,
+                        seq, "Session sent a message again, dropping it"
+                    );
+                    return ControlFlow::Continue(());
+                }
+                Sequencing::Gap { expected } => {
+                    warn!(
+                        session_id = session,
+                        seq, expected, "Session sent a message out of sequence"
+                    );
+                    if let Some(ereport) = rejection_for(msg, RejectReason::OutOfSequence) {
+                        send_rejection(p, &ereport, &self.metrics);
+                    }
+                    return ControlFlow::Continue(());
+                }
+            }
+        }
+
+        // enforce the rate limit of the listener that accepted the client
+        match p.rate_limiter.check(Instant::now()) {
+            Throttle::Allow => {}
+            Throttle::Reject | Throttle::Disconnect
+                if participant == 0 && msg.message_type() == MsgType::Login =>
+            {
+                warn!(
+                    session_id = msg.get_session_id(),
+                    "Session logs in over its message rate, closing connection"
+                );
+                let _ = p.send_login_reject(msg, LoginRejectReason::Throttled);
+                return ControlFlow::Break(());
+            }
+            Throttle::Reject => {
+                if let Some(ereport) = rejection_for(msg, RejectReason::Throttled) {
+                    send_rejection(p, &ereport, &self.metrics);
+                }
+                return ControlFlow::Continue(());
+            }
+            Throttle::Disconnect => {
+                warn!(
+                    session_id = session,
+                    "Session keeps exceeding its message rate, closing connection"
+                );
+                return ControlFlow::Break(());
+            }
+        }
+
+        // check if the message was addressed to the right gateway
+        if msg.get_gateway_id() != self.gateway_id {
+            warn!(
+                client_id,
+                gateway_id = msg.get_gateway_id(),
+                "Message was sent for a different gateway"
+            );
+            return ControlFlow::Break(());
+        }
+        // check the message session_id if this was set
+        if session != 0 && msg.get_session_id() != session {
+            warn!(
+                session_id = session,
+                other = msg.get_session_id(),
+                "Message was sent for a different session"
+            );
+            return ControlFlow::Break(());
+        }
+        if (participant == 0 || session == 0) && msg.message_type() != MsgType::Login {
+            warn!(
+                client_id,
+                "Expected login, received something else, closing client socket"
+            );
+            return ControlFlow::Break(());
+        }
+
+        if participant == 0 {
+            let login = msg
+                .as_any()
+                .downcast_ref::<Login>()
+                .expect("Bad pointer conversion");
+            let pending = match begin_login(&mut self.db, p, login) {
+                Ok(pending) => pending,
+                Err(e) => {
+                    warn!(client_id, error = %e, "Login failed");
+                    return ControlFlow::Break(());
+                }
+            };
+            return match &self.login_checks {
+                Some(checks) => {
+                    self.checking_logins.insert(client_id);
+                    let _ = checks.send((client_id, pending));
+                    ControlFlow::Continue(())
+                }
+                None => {
+                    let checked = pending.check();
+                    self.on_login_checked(client_id, pending, checked)
+                }
+            };
+        }
+
+        match receive_and_prepare_relay_message(&mut self.db, p, msg) {
+            Ok(_) => {
+                // regular message, check if we have to relay something to the matching engine
+                if p.response_buffer.is_empty() {
+                    return ControlFlow::Continue(());
+                }
+                let payload = std::mem::take(&mut p.response_buffer);
+                let window = self.client_order_id_window;
+                let client_order_ids = self
+                    .client_order_ids
+                    .entry(session)
+                    .or_insert_with(|| RecentClientOrderIds::new(window));
+                // remembered once all the other checks passed, for a rejected
+                // order to be sent again as it is
+                if let Err(reason) = p
+                    .entitlements
+                    .check(msg)
+                    .and_then(|_| self.risk.check(msg))
+                    .and_then(|_| client_order_ids.check(msg))
+                {
+                    if let Some(ereport) = rejection_for(msg, reason) {
+                        send_rejection(p, &ereport, &self.metrics);
+                    }
+                    return ControlFlow::Continue(());
+                }
+                let relay = self.failover.relay(
+                    PendingMessage::new(
+                        session,
+                        payload,
+                        rejection_for(msg, RejectReason::EngineUnavailable),
+                    ),
+                    Instant::now(),
+                );
+                self.deliver(relay);
+            }
+            Err(err) => {
+                warn!(
+                    participant,
+                    session_id = session,
+                    error = %err,
+                    "Invalid message, closing connection"
+                );
+                return ControlFlow::Break(());
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Completes the login of @client_id as @pending, once its password was
+    /// @checked, then handles what the client sent meanwhile
+    ///
+    /// Returns: Break if the client has to be disconnected
+    pub fn on_login_checked(
+        &mut self,
+        client_id: usize,
+        pending: PendingLogin,
+        checked: Result<Option<String>>,
+    ) -> ControlFlow<()> {
+        let waiting = self.checking_logins.remove(&client_id);
+        let session_id = pending.login.session_id;
+        // another connection logged in with the same session meanwhile
+        let duplicate = self
+            .session_id_to_client
+            .get(&session_id)
+            .copied()
+            .filter(|c| *c != client_id && self.sessions.contains_key(c));
+        let Some(p) = self.sessions.get_mut(&client_id) else {
+            return ControlFlow::Break(());
+        };
+        if duplicate.is_some() && self.duplicate_session == DuplicateSessionPolicy::Reject {
+            warn!(
+                session_id,
+                "Session already logged in on another connection"
+            );
+            let _ = p.send_login_reject(&pending.login, LoginRejectReason::DuplicateSession);
+            return ControlFlow::Break(());
+        }
+        if let Err(e) = complete_login(&mut self.db, p, &pending, checked) {
+            warn!(client_id, error = %e, "Login failed");
+            return ControlFlow::Break(());
+        }
+        // taken over, the matching engine cancelling the orders of
+        // the first connection as for any disconnect
+        if let Some(other) = duplicate {
+            info!(
+                session_id,
+                client_id, other, "Session taken over, closing the other client"
+            );
+            self.disconnect(other);
+        }
+        let p = self.sessions.get_mut(&client_id).unwrap();
+        // login successful, the session can receive its execution reports
+        self.session_id_to_client.insert(session_id, client_id);
+        self.metrics
+            .sessions
+            .set(self.session_id_to_client.len() as i64);
+        // followed by what was missed while away
+        match self.outbound.take(&mut self.db, session_id) {
+            Ok(reports) => {
+                for report in reports {
+                    let _ = p.send_report(&report);
+                }
+            }
+            Err(e) => error!(
+                session_id,
+                error = %e,
+                "Unable to load the pending execution reports"
+            ),
+        }
+        match waiting {
+            true => self.on_client_data(client_id, &[]),
+            false => ControlFlow::Continue(()),
+        }
+    }
+
+    /// Starts a newly connected drop copy consumer
+    ///
+    /// Returns: the id the consumer is known by from now on, None without a drop copy
+    pub fn add_dropcopy_consumer(&mut self, writer: ClientWriter) -> Option<usize> {
+        Some(self.dropcopy.as_mut()?.add_consumer(writer))
+    }
+
+    /// Handles the bytes read from the drop copy consumer @consumer_id
+    ///
+    /// Returns: Break if the consumer has to be disconnected
+    pub fn on_dropcopy_data(&mut self, consumer_id: usize, data: &[u8]) -> ControlFlow<()> {
+        match self.dropcopy.as_mut() {
+            Some(dropcopy) => dropcopy.on_consumer_data(&mut self.db, consumer_id, data),
+            None => ControlFlow::Break(()),
+        }
+    }
+
+    pub fn disconnect_dropcopy(&mut self, consumer_id: usize) {
+        if let Some(dropcopy) = self.dropcopy.as_mut() {
+            dropcopy.remove_consumer(consumer_id);
+        }
+    }
+
+    /// Lets go of @client_id, after asking the matching engine to cancel the
+    /// orders of its session if it logged in asking for it
+    pub fn disconnect(&mut self, client_id: usize) {
+        self.checking_logins.remove(&client_id);
+        let Some(session) = self.sessions.remove(&client_id) else {
+            return;
+        };
+        let (participant, session_id) = (session.participant, session.session_id);
+        if participant == 0 || session_id == 0 {
+            return;
+        }
+        if self.session_id_to_client.get(&session_id) == Some(&client_id) {
+            self.session_id_to_client.remove(&session_id);
+            self.metrics
+                .sessions
+                .set(self.session_id_to_client.len() as i64);
+        }
+        let Some(buffer) = session.disconnect_notification(self.gateway_id) else {
+            info!(session_id, "Session disconnected, its orders kept");
+            return;
+        };
+        let relay = self.failover.relay(
+            PendingMessage::new(session_id, buffer, None),
+            Instant::now(),
+        );
+        self.deliver(relay);
+    }
+
+    /// Lets go of all the clients, the gateway stopping: the FIX clients are
+    /// logged out, and the orders of the sessions asking for it are cancelled
+    /// as on any disconnect
+    pub fn shut_down(&mut self) {
+        let clients: Vec<usize> = self.sessions.keys().copied().collect();
+        info!(clients = clients.len(), "Disconnecting the clients");
+        for client_id in clients {
+            if let Some(session) = self.sessions.get(&client_id) {
+                session
+                    .socket
+                    .borrow_mut()
+                    .logout("The gateway is shutting down");
+            }
+            self.disconnect(client_id);
+        }
+        self.send_registration(RegistrationKind::Unregister);
+    }
+
+    /// Handles a datagram of the matching engines: an engine status, a
+    /// retransmission request or an execution report to send further down
+    /// the wire to its client, framed with its sequence or not
+    pub fn on_engine_message(&mut self, buf: &[u8]) {
+        let r = buf.len();
+        if r < OEP_HEADER_SIZE {
+            return;
+        }
+        let oep_header = OepHeader::decode(buf[0..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+        if oep_header.message_type() == MsgType::IngressNak
+            && r == OEP_HEADER_SIZE + INGRESSNAK_SIZE
+        {
+            let nak = IngressNak::decode(buf[OEP_HEADER_SIZE..r].try_into().unwrap()).unwrap();
+            if let Some(ingress) = &self.ingress {
+                for frame in ingress.retransmit(&nak) {
+                    self.send_to_engine(frame);
+                }
+            }
+            return;
+        }
+        if oep_header.message_type() == MsgType::GatewayRegistrationReply
+            && r == OEP_HEADER_SIZE + GATEWAYREGISTRATIONREPLY_SIZE
+        {
+            match GatewayRegistrationReply::decode(buf[OEP_HEADER_SIZE..r].try_into().unwrap()) {
+                Ok(reply) => self.on_registration_reply(&reply),
+                Err(e) => warn!(error = %e, "Invalid registration reply received"),
+            }
+            return;
+        }
+        if oep_header.message_type() == MsgType::EngineStatus
+            && r == OEP_HEADER_SIZE + ENGINESTATUS_SIZE
+        {
+            match EngineStatus::decode(buf[OEP_HEADER_SIZE..r].try_into().unwrap()) {
+                Ok(status) => {
+                    for message in self.failover.engine_status(&status, Instant::now()) {
+                        self.deliver(Relay::Now(message));
+                    }
+                }
+                Err(e) => warn!(error = %e, "Invalid engine status received"),
+            }
+            return;
+        }
+        let message = if oep_header.message_type() == MsgType::EgressFrame {
+            match self
+                .egress
+                .receive(&buf[OEP_HEADER_SIZE..r], Instant::now())
+            {
+                Egress::Report(message) => message,
+                Egress::Gap(nak) => {
+                    self.send_egress_nak(nak);
+                    return;
+                }
+                Egress::Nothing => return,
+            }
+        } else {
+            buf
+        };
+        let is_ereport = message.len() == OEP_HEADER_SIZE + EXECUTIONREPORT_SIZE
+            && OepHeader::decode(message[0..OEP_HEADER_SIZE].try_into().unwrap())
+                .is_ok_and(|h| h.message_type() == MsgType::ExecutionReport);
+        if !is_ereport {
+            warn!("Non-execution report received from the matching engine");
+            return;
+        }
+        timeit!(fan_out, self.fan_out(message));
+    }
+
+    /// Hands the execution report @buf over to the drop copy and to its
+    /// session, or keeps it for the session if disconnected
+    fn fan_out(&mut self, buf: &[u8]) {
+        let ereport = ExecutionReport::decode(buf[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        // whatever the gateway it is for
+        if let Some(dropcopy) = self.dropcopy.as_mut() {
+            dropcopy.on_execution_report(&ereport);
+        }
+        // quickly check if we're the target for this message
+        if ereport.gateway_id != self.gateway_id {
+            return;
+        }
+        self.risk.on_execution_report(&ereport);
+        // sequenced even if the client is not connected, so that it can ask for it later
+        let session_id = ereport.session_id;
+        let mut message = buf.to_vec();
+        self.sequence_for(session_id)
+            .borrow_mut()
+            .stamp(&mut message);
+        let sent = match self
+            .session_id_to_client
+            .get(&session_id)
+            .and_then(|client_id| self.sessions.get_mut(client_id))
+        {
+            Some(connection) => connection.send_report(&message).is_ok(),
+            None => false,
+        };
+        if !sent {
+            // forwarded once the session logs in again
+            match self.outbound.store(&mut self.db, session_id, &message) {
+                Ok(true) => {}
+                Ok(false) => warn!(session_id, order_id = { ereport.order_id }, "Too many execution reports pending, dropping one. It can still be asked for with a resend request"),
+                Err(e) => error!(session_id, order_id = { ereport.order_id }, error = %e, "Unable to keep the execution report"),
+            }
+        }
+    }
+
+    /// Gives up on the messages buffered for too long during a failover
+    pub fn expire(&mut self, now: Instant) {
+        for message in self.failover.expire(now) {
+            self.notify_rejection(message);
+        }
+    }
+
+    /// Reloads the risk limits from the database, keeping the old ones on errors
+    pub fn refresh_risk_limits(&mut self) {
+        match self.db.get_risk_limits() {
+            Ok(limits) => self.risk.set_limits(limits),
+            Err(e) => error!(error = %e, "Unable to reload the risk limits, keeping the old ones"),
+        }
+    }
+
+    /// Applies the settings of @reloaded that can change while serving: the
+    /// rate limits and the timeouts of the listeners, to the connected sessions
+    /// too, and the risk limits, reloaded right away. The rest needs a restart.
+    pub fn reload(&mut self, reloaded: &GatewayConfig) {
+        for new in &reloaded.listeners {
+            let Some(current) = self.listeners.get(&new.name) else {
+                warn!(listener = %new.name, "New listener ignored until the restart");
+                continue;
+            };
+            if (
+                &current.address,
+                current.port,
+                current.protocol,
+                &current.session_ids,
+            ) != (&new.address, new.port, new.protocol, &new.session_ids)
+            {
+                warn!(
+                    listener = %new.name,
+                    "The address, protocol and sessions of the listener only change with a restart"
+                );
+            }
+            let updated = Rc::new(current.with_limits_of(new));
+            for session in self.sessions.values_mut() {
+                if session
+                    .listener
+                    .as_ref()
+                    .is_some_and(|l| l.name == new.name)
+                {
+                    session.update_listener(updated.clone());
+                }
+            }
+            self.listeners.insert(new.name.clone(), updated);
+        }
+        self.refresh_risk_limits();
+        info!("Reloaded the configuration");
+    }
+
+    /// Lets the matching engines know which message comes next, with a
+    /// sequenced ingress
+    pub fn send_ingress_heartbeat(&mut self) {
+        if let Some(heartbeat) = self.ingress.as_ref().map(|i| i.heartbeat()) {
+            self.send_to_engine(heartbeat);
+        }
+    }
+
+    /// Lets the matching engines know this gateway runs with its id, and
+    /// where its execution reports can go
+    pub fn register(&mut self) {
+        self.send_registration(RegistrationKind::Register);
+    }
+
+    /// Whether an engine refused the registration, another gateway running
+    /// with the same id
+    pub fn registration_refused(&self) -> bool {
+        self.registration_refused
+    }
+
+    fn send_registration(&mut self, kind: RegistrationKind) {
+        // the reports of everyone, for the drop copy
+        let flags = match self.dropcopy {
+            Some(_) => FLAG_ALL_REPORTS,
+            None => 0,
+        };
+        let registration = GatewayRegistration::new(
+            self.gateway_id,
+            kind,
+            flags,
+            self.instance,
+            self.report_endpoint,
+        );
+        self.send_own(
+            MsgType::GatewayRegistration,
+            GATEWAYREGISTRATION_SIZE,
+            &registration.encode(),
+        );
+    }
+
+    fn on_registration_reply(&mut self, reply: &GatewayRegistrationReply) {
+        if reply.gateway_id != self.gateway_id || { reply.instance } != self.instance {
+            return;
+        }
+        let engine = (reply.engine_id, reply.partition_id);
+        match reply.get_status() {
+            RegistrationStatus::Accepted => {
+                if self.registered_with.insert(engine) {
+                    info!(
+                        engine_id = engine.0,
+                        partition_id = engine.1,
+                        "Registered with the matching engine"
+                    );
+                }
+            }
+            RegistrationStatus::DuplicateId => {
+                error!(
+                    engine_id = engine.0,
+                    partition_id = engine.1,
+                    gateway_id = self.gateway_id,
+                    "Another gateway runs with the same id"
+                );
+                self.registration_refused = true;
+            }
+        }
+    }
+
+    /// Asks an engine for the execution reports the gateway missed
+    fn send_egress_nak(&mut self, nak: EgressNak) {
+        self.send_own(MsgType::EgressNak, EGRESSNAK_SIZE, &nak.encode());
+    }
+
+    /// Sends a message of the gateway itself to the engines, framed like the
+    /// ones of the clients with a sequenced ingress
+    fn send_own(&mut self, msg_type: MsgType, len: usize, body: &[u8]) {
+        let header = OepHeader::new(OEP_VERSION, msg_type.into(), len as u32).encode();
+        let message = [header.as_slice(), body].concat();
+        let message = match &mut self.ingress {
+            Some(ingress) => ingress.frame(&message),
+            None => message,
+        };
+        self.send_to_engine(message);
+    }
+
+    fn send_to_engine(&mut self, message: Vec<u8>) {
+        if self.relay.send(message).is_err() {
+            error!("The relay to the matching engine is gone");
+        }
+    }
+
+    /// carries out what the failover buffer decided about a message
+    fn deliver(&mut self, relay: Relay) {
+        match relay {
+            Relay::Now(message) => {
+                let message = match &mut self.ingress {
+                    Some(ingress) => ingress.frame(&message.payload),
+                    None => message.payload,
+                };
+                self.metrics.messages_relayed.inc();
+                self.send_to_engine(message);
+            }
+            Relay::Buffered => {}
+            Relay::Rejected(message) => self.notify_rejection(message),
+        }
+    }
+
+    /// lets the client know that its message never made it to the matching engine
+    fn notify_rejection(&mut self, message: PendingMessage) {
+        let Some(ereport) = message.rejection else {
+            return;
+        };
+        if let Some(session) = self
+            .session_id_to_client
+            .get(&message.session_id)
+            .and_then(|client_id| self.sessions.get_mut(client_id))
+        {
+            send_rejection(session, &ereport, &self.metrics);
+        }
+    }
+}
+
+/// Runs the gateway described by its configuration
+pub struct GatewayServer {
+    config: GatewayConfig,
+    db: Box<dyn GenericDB>,
+}
+
+impl GatewayServer {
+    /// @db - a connected database session
+    pub fn new(config: GatewayConfig, db: Box<dyn GenericDB>) -> Self {
+        Self { config, db }
+    }
+
+    /// Serves the clients until one of the sockets of the gateway fails
+    pub fn run(self) -> Result<()> {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        LocalSet::new().block_on(&runtime, self.serve())
+    }
+
+    async fn serve(self) -> Result<()> {
+        let config = self.config;
+        if let Some(metrics) = &config.metrics {
+            metrics.serve()?;
+        }
+        let (relay, relayed) = mpsc::unbounded_channel();
+        let (login_checks, to_check) = mpsc::unbounded_channel();
+        let mut state = GatewayState::new(&config, self.db, relay)?;
+        state.check_logins_on(login_checks);
+        let state = Rc::new(RefCell::new(state));
+
+        info!("Initializing sockets");
+        let engine = network::udp_sender(
+            &network::socket_address(&config.publisher_addr, config.publisher_port)?,
+            &config.multicast,
+        )?;
+        engine.set_nonblocking(true)?;
+        let engine = UdpSocket::from_std(engine.into())?;
+        // we use this socket in order to receive messages back from the matching engine
+        let internal_publisher = network::join_multicast_group(
+            &network::socket_address(
+                &config.internal_publisher_group,
+                config.internal_publisher_port,
+            )?,
+            &config.multicast,
+        )?;
+        internal_publisher.set_nonblocking(true)?;
+        let internal_publisher = UdpSocket::from_std(internal_publisher.into())?;
+
+        // the gateway stops with the first of its tasks that does
+        let (stopped, mut first_stopped) = mpsc::unbounded_channel::<Result<()>>();
+        let spawn = |task: std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>>>>| {
+            let stopped = stopped.clone();
+            task::spawn_local(async move {
+                let _ = stopped.send(task.await);
+            });
+        };
+        spawn(Box::pin(relay_to_engine(engine, relayed)));
+        spawn(Box::pin(check_logins(state.clone(), to_check)));
+        spawn(Box::pin(route_engine_messages(
+            state.clone(),
+            internal_publisher,
+        )));
+        if let Some(endpoint) = config.report_endpoint {
+            info!(%endpoint, "Taking the execution reports by unicast");
+            let reports = std::net::UdpSocket::bind(endpoint)?;
+            reports.set_nonblocking(true)?;
+            spawn(Box::pin(route_engine_messages(
+                state.clone(),
+                UdpSocket::from_std(reports)?,
+            )));
+        }
+        spawn(Box::pin(housekeeping(state.clone(), config.risk_refresh)));
+        for listener in config.listeners {
+            info!(
+                protocol = ?listener.protocol,
+                address = %listener.address,
+                port = listener.port,
+                listener = %listener.name,
+                "Listening for clients"
+            );
+            let socket = bind_listener(&listener.address, listener.port)?;
+            spawn(Box::pin(accept_clients(
+                state.clone(),
+                socket,
+                Rc::new(listener),
+                config.max_packet_size,
+            )));
+        }
+
+        if let Some(dropcopy) = &config.dropcopy {
+            info!(
+                address = %dropcopy.address,
+                port = dropcopy.port,
+                "Listening for drop copy consumers"
+            );
+            let socket = bind_listener(&dropcopy.address, dropcopy.port)?;
+            spawn(Box::pin(accept_dropcopy_consumers(
+                state.clone(),
+                socket,
+                config.max_packet_size,
+            )));
+        }
+
+        let stop = Arc::new(Notify::new());
+        let signalled = stop.clone();
+        shutdown::on_stop_signal(move || signalled.notify_one())?;
+        spawn(Box::pin(stop_when_asked(state.clone(), stop)));
+
+        let reload = Arc::new(Notify::new());
+        let signalled = reload.clone();
+        reload::on_reload_signal(move || signalled.notify_one())?;
+        spawn(Box::pin(reload_when_asked(state.clone(), reload)));
+
+        info!("Serving");
+        first_stopped.recv().await.unwrap_or(Ok(()))
+    }
+}
+
+fn bind_listener(addr: &str, port: u16) -> Result<TcpListener> {
+    let address = network::socket_address(addr, port)?;
+    let listener = Socket::new(address.domain(), Type::STREAM, Some(Protocol::TCP))?;
+    listener.set_linger(None)?;
+    listener.set_reuse_address(true)?;
+    listener.set_reuse_port(true)?;
+    listener.bind(&address)?;
+    listener.listen(10)?;
+    listener.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(listener.into())?)
+}
+
+async fn accept_clients(
+    state: Rc<RefCell<GatewayState>>,
+    socket: TcpListener,
+    listener: Rc<ListenerConfig>,
+    max_packet_size: usize,
+) -> Result<()> {
+    loop {
+        let (stream, _) = socket.accept().await?;
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        let (outgoing, to_send) = mpsc::unbounded_channel();
+        info!(listener = %listener.name, "New client accepted");
+        task::spawn_local(write_client(writer, to_send));
+        let Some(fix_config) = &listener.fix else {
+            let client_id = state
+                .borrow_mut()
+                .add_client(Some(listener.clone()), ClientWriter::new(outgoing));
+            task::spawn_local(read_client(
+                state.clone(),
+                client_id,
+                reader,
+                max_packet_size,
+            ));
+            continue;
+        };
+        let gateway_id = state.borrow().gateway_id;
+        let fix = Rc::new(RefCell::new(FixSession::new(
+            fix_config.clone(),
+            gateway_id,
+        )));
+        let client_id = state.borrow_mut().add_client(
+            Some(listener.clone()),
+            ClientWriter::fix(outgoing.clone(), fix.clone()),
+        );
+        task::spawn_local(read_fix_client(
+            state.clone(),
+            client_id,
+            reader,
+            FixClient { fix, outgoing },
+            max_packet_size,
+        ));
+    }
+}
+
+async fn read_client(
+    state: Rc<RefCell<GatewayState>>,
+    client_id: usize,
+    mut reader: OwnedReadHalf,
+    max_packet_size: usize,
+) {
+    let mut buf = vec![0; max_packet_size];
+    loop {
+        // the client has to show up at least this often, as of the last reload
+        let timeout = state.borrow().read_timeout(client_id);
+        let read = match timeout {
+            Some(timeout) => match time::timeout(timeout, reader.read(&mut buf)).await {
+                Ok(read) => read,
+                Err(_) => {
+                    info!(client_id, "Client timed out, closing connection");
+                    break;
+                }
+            },
+            None => reader.read(&mut buf).await,
+        };
+        match read {
+            Ok(0) => {
+                info!(client_id, "EOF, closing connection");
+                break;
+            }
+            Ok(r) => {
+                if state
+                    .borrow_mut()
+                    .on_client_data(client_id, &buf[..r])
+                    .is_break()
+                {
+                    break;
+                }
+            }
+            Err(e) => {
+                info!(client_id, error = %e, "Client disconnected");
+                break;
+            }
+        }
+    }
+    state.borrow_mut().disconnect(client_id);
+}
+
+// what the reader task of a FIX client needs on top of the OEP ones
+struct FixClient {
+    fix: Rc<RefCell<FixSession>>,
+    // for the session level replies, which don't go through the gateway
+    outgoing: UnboundedSender<Vec<u8>>,
+}
+
+impl FixClient {
+    fn send(&self, bytes: Vec<u8>) {
+        if !bytes.is_empty() {
+            let _ = self.outgoing.send(bytes);
+        }
+    }
+}
+
+/// Same as read_client, for a FIX client: what it sends is translated to OEP
+/// before being handled as the messages of an OEP client, and it gets its
+/// heartbeats while nothing else goes out
+async fn read_fix_client(
+    state: Rc<RefCell<GatewayState>>,
+    client_id: usize,
+    mut reader: OwnedReadHalf,
+    client: FixClient,
+    max_packet_size: usize,
+) {
+    let mut buf = vec![0; max_packet_size];
+    let mut last_read = Instant::now();
+    'reading: loop {
+        let read = match time::timeout(FIX_HEARTBEAT_CHECK_EVERY, reader.read(&mut buf)).await {
+            Ok(read) => read,
+            Err(_) => {
+                let timeout = state.borrow().read_timeout(client_id);
+                if timeout.is_some_and(|timeout| last_read.elapsed() > timeout) {
+                    info!(client_id, "Client timed out, closing connection");
+                    break;
+                }
+                let heartbeat = client.fix.borrow_mut().heartbeat_due(Instant::now());
+                client.send(heartbeat.unwrap_or_default());
+                continue;
+            }
+        };
+        let r = match read {
+            Ok(0) => {
+                info!(client_id, "EOF, closing connection");
+                break;
+            }
+            Ok(r) => r,
+            Err(e) => {
+                info!(client_id, error = %e, "Client disconnected");
+                break;
+            }
+        };
+        last_read = Instant::now();
+        client.fix.borrow_mut().receive(&buf[..r]);
+        loop {
+            let next = client.fix.borrow_mut().next_input();
+            let translated = match next {
+                Ok(Some(translated)) => translated,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!(client_id, error = %e, "Client sent invalid FIX, closing its socket");
+                    break 'reading;
+                }
+            };
+            client.send(translated.reply);
+            if translated.disconnect {
+                break 'reading;
+            }
+            // the session messages count as activity too, with nothing to relay
+            if state
+                .borrow_mut()
+                .on_client_data(client_id, &translated.oep)
+                .is_break()
+            {
+                let logout = client.fix.borrow_mut().logout("Closed by the gateway");
+                client.send(logout);
+                break 'reading;
+            }
+        }
+    }
+    state.borrow_mut().disconnect(client_id);
+}
+
+async fn write_client(mut writer: OwnedWriteHalf, mut to_send: UnboundedReceiver<Vec<u8>>) {
+    while let Some(buf) = to_send.recv().await {
+        if writer.write_all(&buf).await.is_err() {
+            return;
+        }
+    }
+    // the session is gone, so is the connection
+    let _ = writer.shutdown().await;
+}
+
+async fn accept_dropcopy_consumers(
+    state: Rc<RefCell<GatewayState>>,
+    socket: TcpListener,
+    max_packet_size: usize,
+) -> Result<()> {
+    loop {
+        let (stream, _) = socket.accept().await?;
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        let (outgoing, to_send) = mpsc::unbounded_channel();
+        let Some(consumer_id) = state
+            .borrow_mut()
+            .add_dropcopy_consumer(ClientWriter::new(outgoing))
+        else {
+            continue;
+        };
+        info!("New drop copy consumer accepted");
+        task::spawn_local(write_client(writer, to_send));
+        task::spawn_local(read_dropcopy_consumer(
+            state.clone(),
+            consumer_id,
+            reader,
+            max_packet_size,
+        ));
+    }
+}
+
+async fn read_dropcopy_consumer(
+    state: Rc<RefCell<GatewayState>>,
+    consumer_id: usize,
+    mut reader: OwnedReadHalf,
+    max_packet_size: usize,
+) {
+    let mut buf = vec![0; max_packet_size];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(r) => {
+                if state
+                    .borrow_mut()
+                    .on_dropcopy_data(consumer_id, &buf[..r])
+                    .is_break()
+                {
+                    break;
+                }
+            }
+        }
+    }
+    info!(consumer_id, "Drop copy consumer disconnected");
+    state.borrow_mut().disconnect_dropcopy(consumer_id);
+}
+
+async fn route_engine_messages(state: Rc<RefCell<GatewayState>>, socket: UdpSocket) -> Result<()> {
+    let mut buf = [0; 10000];
+    loop {
+        let r = socket.recv(&mut buf).await?;
+        state.borrow_mut().on_engine_message(&buf[..r]);
+    }
+}
+
+async fn relay_to_engine(socket: UdpSocket, mut relayed: UnboundedReceiver<Vec<u8>>) -> Result<()> {
+    while let Some(message) = relayed.recv().await {
+        timeit!(relay, socket.send(&message).await)?;
+    }
+    Ok(())
+}
+
+/// Checks the passwords of the logins on the blocking threads, the hashing
+/// being slow on purpose, each login then completing on the gateway's
+async fn check_logins(
+    state: Rc<RefCell<GatewayState>>,
+    mut to_check: UnboundedReceiver<(usize, PendingLogin)>,
+) -> Result<()> {
+    while let Some((client_id, pending)) = to_check.recv().await {
+        let state = state.clone();
+        task::spawn_local(async move {
+            let checked = task::spawn_blocking(move || {
+                let checked = pending.check();
+                (pending, checked)
+            })
+            .await;
+            let mut state = state.borrow_mut();
+            let flow = match checked {
+                Ok((pending, checked)) => state.on_login_checked(client_id, pending, checked),
+                Err(e) => {
+                    error!(client_id, error = %e, "Unable to check the login");
+                    ControlFlow::Break(())
+                }
+            };
+            if flow.is_break() {
+                state.disconnect(client_id);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Lets go of the clients once @stop is notified, then stops, which stops the gateway
+async fn stop_when_asked(state: Rc<RefCell<GatewayState>>, stop: Arc<Notify>) -> Result<()> {
+    stop.notified().await;
+    state.borrow_mut().shut_down();
+    // the writer tasks and the relay task drain meanwhile
+    time::sleep(SHUT_DOWN_GRACE).await;
+    info!("Stopped");
+    Ok(())
+}
+
+/// Reloads CONFIG_FILE every time @reload is notified, keeping the settings
+/// in use when it doesn't load
+async fn reload_when_asked(state: Rc<RefCell<GatewayState>>, reload: Arc<Notify>) -> Result<()> {
+    loop {
+        reload.notified().await;
+        let reloaded = config::load(CONFIG_FILE)
+            .map_err(anyhow::Error::from)
+            .and_then(|config_map| GatewayConfig::from_config(&config_map));
+        match reloaded {
+            Ok(reloaded) => state.borrow_mut().reload(&reloaded),
+            Err(e) => error!(error = %e, "Unable to reload the configuration, keeping the old one"),
+        }
+    }
+}
+
+async fn housekeeping(state: Rc<RefCell<GatewayState>>, risk_refresh: Duration) -> Result<()> {
+    let mut last_risk_refresh = Instant::now();
+    state.borrow_mut().register();
+    let mut last_registration = Instant::now();
+    loop {
+        time::sleep(HOUSEKEEPING_EVERY).await;
+        let mut state = state.borrow_mut();
+        if state.registration_refused() {
+            bail!("Gateway id {} taken by another gateway", state.gateway_id);
+        }
+        state.expire(Instant::now());
+        state.send_ingress_heartbeat();
+        if last_registration.elapsed() > REGISTER_EVERY {
+            state.register();
+            last_registration = Instant::now();
+        }
+        if last_risk_refresh.elapsed() > risk_refresh {
+            state.refresh_risk_limits();
+            last_risk_refresh = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, collections::HashMap, ops::ControlFlow, rc::Rc, time::Duration};
+
+    use dbhook::mockdb::MockDB;
+    use fix_gateway::{
+        config::FixConfig,
+        message::{msg_type, tag, FixMessage},
+        session::FixSession,
+    };
+    use oep::{
+        decoder::Decoder,
+        egress::{EgressHeader, EgressNak, EGRESSHEADER_SIZE, EGRESSNAK_SIZE},
+        execution_report::{
+            ExecutionReport, RejectReason, EXECUTIONREPORT_SIZE, EXECUTIONREPORT_V6_SIZE,
+        },
+        header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+        heartbeat::{Heartbeat, HEARTBEAT_SIZE},
+        ingress::{
+            IngressHeader, IngressKind, IngressMode, IngressNak, INGRESSHEADER_SIZE,
+            INGRESSNAK_SIZE,
+        },
+        login::{Login, LOGIN_SIZE},
+        loginreject::{LoginReject, LoginRejectReason, LOGINREJECT_SIZE},
+        neworder::{NewOrder, NEWORDER_SIZE},
+        oep_decode,
+        oep_message::MsgType,
+        registration::{
+            GatewayRegistration, GatewayRegistrationReply, RegistrationKind, RegistrationStatus,
+            GATEWAYREGISTRATIONREPLY_SIZE, GATEWAYREGISTRATION_SIZE,
+        },
+        resendrequest::{ResendRequest, RESENDREQUEST_SIZE},
+        version::{VersionReject, MIN_OEP_VERSION, VERSIONREJECT_SIZE},
+    };
+    use order::OrderState;
+    use tokio::sync::mpsc::{self, error::TryRecvError, UnboundedReceiver};
+    use utils::network::MulticastConfig;
+
+    use super::{next_message, ClientWriter, DuplicateSessionPolicy, GatewayConfig, GatewayState};
+    use crate::{failover::FailoverConfig, listener::ListenerConfig};
+
+    const GATEWAY_ID: u8 = 1;
+    const SESSION_ID: u32 = 2;
+    // as logged in by the mock database
+    const PARTICIPANT: u64 = 111;
+
+    fn config() -> GatewayConfig {
+        GatewayConfig {
+            gateway_id: GATEWAY_ID,
+            listeners: vec![],
+            publisher_addr: String::from("127.0.0.1"),
+            publisher_port: 9000,
+            internal_publisher_group: String::from("224.0.0.1"),
+            internal_publisher_port: 9001,
+            report_endpoint: None,
+            multicast: MulticastConfig::default(),
+            max_packet_size: 1500,
+            failover: FailoverConfig::default(),
+            risk_refresh: Duration::from_secs(60),
+            resend_buffer_size: 100,
+            max_pending_reports: 100,
+            ingress: IngressMode::Multicast,
+            ingress_buffer_size: 100,
+            dropcopy: None,
+            duplicate_session: DuplicateSessionPolicy::Reject,
+            client_order_id_window: 100,
+            metrics: None,
+        }
+    }
+
+    fn framed(msg_type: MsgType, len: usize, seq: u32, payload: &[u8]) -> Vec<u8> {
+        let header = OepHeader::new(OEP_VERSION, msg_type.into(), len as u32).with_seq(seq);
+        [header.encode().as_slice(), payload].concat()
+    }
+
+    fn login() -> Vec<u8> {
+        let login = Login::new(PARTICIPANT, SESSION_ID, GATEWAY_ID, "test");
+        framed(MsgType::Login, LOGIN_SIZE, 0, &login.encode())
+    }
+
+    fn new_order(seq: u32) -> Vec<u8> {
+        let order = NewOrder {
+            client_order_id: seq as u64,
+            participant: PARTICIPANT,
+            book_id: 1,
+            quantity: 10,
+            price: 100,
+            order_type: 0,
+            side: 1,
+            gateway_id: GATEWAY_ID,
+            session_id: SESSION_ID,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        };
+        framed(MsgType::NewOrder, NEWORDER_SIZE, seq, &order.encode())
+    }
+
+    fn engine_report(order_id: u64, gateway_id: u8) -> Vec<u8> {
+        let ereport = ExecutionReport {
+            participant: PARTICIPANT,
+            order_id,
+            submitted_order_id: order_id,
+            book: 1,
+            quantity: 10,
+            price: 100,
+            flags: 0,
+            side: 1,
+            state: OrderState::Inserted.into(),
+            gateway_id,
+            session_id: SESSION_ID,
+            filled_quantity: 0,
+            leaves_quantity: 10,
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
+        };
+        framed(
+            MsgType::ExecutionReport,
+            EXECUTIONREPORT_SIZE,
+            0,
+            &ereport.encode(),
+        )
+    }
+
+    fn target() -> (GatewayState, UnboundedReceiver<Vec<u8>>) {
+        let (relay, relayed) = mpsc::unbounded_channel();
+        let state = GatewayState::new(&config(), dbhook::factory::build("mock"), relay).unwrap();
+        (state, relayed)
+    }
+
+    fn connect(target: &mut GatewayState) -> (usize, UnboundedReceiver<Vec<u8>>) {
+        let (outgoing, to_send) = mpsc::unbounded_channel();
+        (
+            target.add_client(None, ClientWriter::new(outgoing)),
+            to_send,
+        )
+    }
+
+    fn received(to_send: &mut UnboundedReceiver<Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut r = vec![];
+        while let Ok(buf) = to_send.try_recv() {
+            r.push(buf);
+        }
+        r
+    }
+
+    fn login_reject_of(message: &[u8]) -> LoginRejectReason {
+        assert_eq!(OEP_HEADER_SIZE + LOGINREJECT_SIZE, message.len());
+        LoginReject::decode(message[OEP_HEADER_SIZE..].try_into().unwrap())
+            .unwrap()
+            .get_reason()
+    }
+
+    fn seq_of(message: &[u8]) -> u32 {
+        OepHeader::decode(message[..OEP_HEADER_SIZE].try_into().unwrap())
+            .unwrap()
+            .seq
+    }
+
+    #[test]
+    fn framing() {
+        let heartbeat = Heartbeat::new(PARTICIPANT, SESSION_ID, GATEWAY_ID);
+        let heartbeat = framed(MsgType::Heartbeat, HEARTBEAT_SIZE, 0, &heartbeat.encode());
+        // two messages in a single read, the second one incomplete
+        let mut buffer = [new_order(7).as_slice(), &heartbeat[..5]].concat();
+
+        let (message, header) = next_message(&mut buffer).unwrap().unwrap();
+        assert_eq!(MsgType::NewOrder, message.message_type());
+        assert_eq!(7, { header.seq });
+        assert!(next_message(&mut buffer).unwrap().is_none());
+        buffer.extend_from_slice(&heartbeat[5..]);
+        let (message, header) = next_message(&mut buffer).unwrap().unwrap();
+        assert_eq!(MsgType::Heartbeat, message.message_type());
+        assert_eq!(0, { header.seq });
+        assert!(buffer.is_empty());
+
+        // announcing more than any message can carry
+        let mut buffer = framed(MsgType::NewOrder, 100000, 1, &[]);
+        assert!(next_message(&mut buffer).is_err());
+        // the length doesn't match the type
+        let mut buffer = framed(
+            MsgType::Heartbeat,
+            NEWORDER_SIZE,
+            1,
+            &new_order(1)[OEP_HEADER_SIZE..],
+        );
+        assert!(next_message(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn config_from_ini() {
+        let config_map = configparser::ini::Ini::new()
+            .read(String::from(
+                "[gateway]
+                id=3
+                address=127.0.0.1
+                port=10000
+                publisher_addr=127.0.0.1
+                publisher_port=9000
+                internal_publisher_group=224.0.0.1
+                internal_publisher_port=9001
+                max_packet_size=1500
+                max_pending_reports=5
+                ingress=sequenced
+                duplicate_session=takeover",
+            ))
+            .unwrap();
+        let config = GatewayConfig::from_config(&config_map).unwrap();
+        assert_eq!(3, config.gateway_id);
+        assert_eq!(1, config.listeners.len());
+        assert_eq!(5, config.max_pending_reports);
+        assert_eq!(IngressMode::Sequenced, config.ingress);
+        assert_eq!(DuplicateSessionPolicy::Takeover, config.duplicate_session);
+        assert_eq!(Duration::from_secs(60), config.risk_refresh);
+        assert_eq!(None, config.report_endpoint);
+
+        let mut config_map: HashMap<_, _> = config_map;
+        let gateway = config_map.get_mut("gateway").unwrap();
+        gateway.insert(String::from("report_port"), Some(String::from("9002")));
+        assert!(GatewayConfig::from_config(&config_map)
+            .unwrap_err()
+            .to_string()
+            .starts_with("report_port in the [gateway] section"));
+        let gateway = config_map.get_mut("gateway").unwrap();
+        gateway.insert(
+            String::from("report_address"),
+            Some(String::from("0.0.0.0")),
+        );
+        assert!(GatewayConfig::from_config(&config_map)
+            .unwrap_err()
+            .to_string()
+            .starts_with("report_address in the [gateway] section"));
+        let gateway = config_map.get_mut("gateway").unwrap();
+        gateway.insert(
+            String::from("report_address"),
+            Some(String::from("10.0.0.1")),
+        );
+        assert_eq!(
+            Some("10.0.0.1:9002".parse().unwrap()),
+            GatewayConfig::from_config(&config_map)
+                .unwrap()
+                .report_endpoint
+        );
+        let gateway = config_map.get_mut("gateway").unwrap();
+        gateway.insert(
+            String::from("report_address"),
+            Some(String::from("fd00::1")),
+        );
+        assert_eq!(
+            Some("[fd00::1]:9002".parse().unwrap()),
+            GatewayConfig::from_config(&config_map)
+                .unwrap()
+                .report_endpoint
+        );
+
+        config_map
+            .get_mut("gateway")
+            .unwrap()
+            .insert(String::from("max_packet_size"), Some(String::from("65000")));
+        assert!(GatewayConfig::from_config(&config_map).is_err());
+        config_map
+            .get_mut("gateway")
+            .unwrap()
+            .insert(String::from("ingress"), Some(String::from("broadcast")));
+        assert!(GatewayConfig::from_config(&config_map)
+            .unwrap_err()
+            .to_string()
+            .starts_with("ingress in the [gateway] section"));
+    }
+
+    #[test]
+    fn login_and_relay() {
+        let (mut target, mut relayed) = target();
+        let (client, mut to_send) = connect(&mut target);
+
+        // nothing but a login is accepted first
+        assert!(target.on_client_data(client, &new_order(1)).is_break());
+        let (client, mut to_send2) = connect(&mut target);
+        assert!(received(&mut to_send).is_empty());
+
+        // split over two reads
+        let login = login();
+        assert_eq!(
+            ControlFlow::Continue(()),
+            target.on_client_data(client, &login[..10])
+        );
+        assert!(received(&mut to_send2).is_empty());
+        assert_eq!(
+            ControlFlow::Continue(()),
+            target.on_client_data(client, &login[10..])
+        );
+        let reply = received(&mut to_send2).concat();
+        assert_eq!(OEP_HEADER_SIZE + LOGIN_SIZE, reply.len());
+        // the first sequence expected from the client
+        assert_eq!(1, seq_of(&reply));
+
+        assert!(target.on_client_data(client, &new_order(1)).is_continue());
+        let relayed = received(&mut relayed);
+        assert_eq!(1, relayed.len());
+        assert_eq!([MsgType::NewOrder as u8, 0, 0, 0], relayed[0][..4]);
+        assert_eq!(new_order(1)[OEP_HEADER_SIZE..], relayed[0][4..]);
+    }
+
+    #[test]
+    fn login_checked_elsewhere() {
+        let (mut target, mut relayed) = target();
+        let (checks, mut to_check) = mpsc::unbounded_channel();
+        target.check_logins_on(checks);
+        let (client, mut to_send) = connect(&mut target);
+
+        // an order right behind the login waits for the password check
+        let data = [login(), new_order(1)].concat();
+        assert!(target.on_client_data(client, &data).is_continue());
+        assert!(received(&mut to_send).is_empty());
+        let (checked_client, pending) = to_check.try_recv().unwrap();
+        assert_eq!(client, checked_client);
+        let checked = pending.check();
+        assert!(target
+            .on_login_checked(client, pending, checked)
+            .is_continue());
+        let reply = received(&mut to_send).concat();
+        assert_eq!(OEP_HEADER_SIZE + LOGIN_SIZE, reply.len());
+        let relayed = received(&mut relayed);
+        assert_eq!(1, relayed.len());
+        assert_eq!(new_order(1)[OEP_HEADER_SIZE..], relayed[0][4..]);
+        target.disconnect(client);
+
+        // gone before its password was checked
+        let (client, _to_send) = connect(&mut target);
+        assert!(target.on_client_data(client, &login()).is_continue());
+        let (_, pending) = to_check.try_recv().unwrap();
+        target.disconnect(client);
+        let checked = pending.check();
+        assert!(target.on_login_checked(client, pending, checked).is_break());
+        assert_eq!(Err(TryRecvError::Empty), to_check.try_recv().map(|_| ()));
+    }
+
+    #[test]
+    fn fix_client() {
+        let (mut target, mut relayed) = target();
+        let config = FixConfig {
+            comp_id: String::from("EXCHANGE"),
+            sessions: HashMap::from([(String::from("BROKER1"), SESSION_ID)]),
+        };
+        let fix = Rc::new(RefCell::new(FixSession::new(config, GATEWAY_ID)));
+        let (outgoing, mut to_send) = mpsc::unbounded_channel();
+        let client = target.add_client(None, ClientWriter::fix(outgoing, fix.clone()));
+        let mut send = |message: FixMessage| {
+            fix.borrow_mut().receive(&message.encode());
+            let translated = fix.borrow_mut().next_input().unwrap().unwrap();
+            target.on_client_data(client, &translated.oep)
+        };
+        let header = |msg_type: &str, seq: u64| {
+            FixMessage::new(msg_type)
+                .with(tag::SENDER_COMP_ID, "BROKER1")
+                .with(tag::TARGET_COMP_ID, "EXCHANGE")
+                .with(tag::MSG_SEQ_NUM, seq)
+        };
+        let fix_received = |to_send: &mut UnboundedReceiver<Vec<u8>>| {
+            let bytes = received(to_send).concat();
+            FixMessage::decode(&bytes).unwrap().unwrap().0
+        };
+
+        let logon = header(msg_type::LOGON, 1)
+            .with(tag::HEART_BT_INT, 30)
+            .with(tag::USERNAME, "test");
+        assert!(send(logon).is_continue());
+        assert_eq!(msg_type::LOGON, fix_received(&mut to_send).msg_type());
+        assert!(fix.borrow().is_logged_on());
+
+        let order = header(msg_type::NEW_ORDER_SINGLE, 2)
+            .with(tag::CL_ORD_ID, 5)
+            .with(tag::SYMBOL, 1)
+            .with(tag::SIDE, 2)
+            .with(tag::ORDER_QTY, 10)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, 100);
+        assert!(send(order).is_continue());
+        let relayed = received(&mut relayed);
+        assert_eq!(1, relayed.len());
+        let order = NewOrder::decode(relayed[0][4..].try_into().unwrap()).unwrap();
+        assert_eq!(PARTICIPANT, { order.participant });
+        assert_eq!(5, { order.client_order_id });
+
+        target.on_engine_message(&engine_report(1001, GATEWAY_ID));
+        let ereport = fix_received(&mut to_send);
+        assert_eq!(msg_type::EXECUTION_REPORT, ereport.msg_type());
+        assert_eq!(Some("1001"), ereport.get(tag::ORDER_ID));
+        assert_eq!(Some("2"), ereport.get(tag::SIDE));
+
+        target.shut_down();
+        assert_eq!(msg_type::LOGOUT, fix_received(&mut to_send).msg_type());
+        assert!(!target.is_connected(client));
+    }
+
+    #[test]
+    fn version_negotiation() {
+        let (mut target, _relayed) = target();
+        let login_in = |version: u16| {
+            let mut login = login();
+            login[..2].copy_from_slice(&version.to_le_bytes());
+            login
+        };
+        let header_of = |message: &[u8]| {
+            OepHeader::decode(message[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap()
+        };
+
+        // a newer client falls back to the version of the gateway
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target
+            .on_client_data(client, &login_in(OEP_VERSION + 1))
+            .is_continue());
+        let reply = received(&mut to_send).concat();
+        assert_eq!(MsgType::Login, header_of(&reply).message_type());
+        assert_eq!(OEP_VERSION, { header_of(&reply).oep_version });
+        assert!(target.on_client_data(client, &new_order(1)).is_continue());
+        // and sticks to it
+        let mut order = new_order(2);
+        order[..2].copy_from_slice(&(OEP_VERSION + 1).to_le_bytes());
+        assert!(target.on_client_data(client, &order).is_break());
+
+        // an older one is turned away
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target
+            .on_client_data(client, &login_in(MIN_OEP_VERSION - 1))
+            .is_break());
+        let reply = received(&mut to_send).concat();
+        assert_eq!(OEP_HEADER_SIZE + VERSIONREJECT_SIZE, reply.len());
+        assert_eq!(MsgType::VersionReject, header_of(&reply).message_type());
+        let reject = VersionReject::decode(reply[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(MIN_OEP_VERSION - 1, { reject.proposed });
+        assert_eq!(OEP_VERSION, { reject.max_version });
+    }
+
+    #[test]
+    fn reports_in_the_version_of_the_session() {
+        let (mut target, _relayed) = target();
+        let in_version = |mut message: Vec<u8>, version: u16| {
+            message[..2].copy_from_slice(&version.to_le_bytes());
+            message
+        };
+        let mut report = engine_report(10, GATEWAY_ID);
+        let len = report.len();
+        report[len - 8..].copy_from_slice(&77u64.to_le_bytes());
+        for (version, size, trade_id) in [
+            (5, EXECUTIONREPORT_V6_SIZE, 0),
+            (6, EXECUTIONREPORT_V6_SIZE, 0),
+            (7, EXECUTIONREPORT_SIZE, 77),
+        ] {
+            let (client, mut to_send) = connect(&mut target);
+            assert!(target
+                .on_client_data(client, &in_version(login(), version))
+                .is_continue());
+            received(&mut to_send);
+            target.on_engine_message(&report);
+            let sent = received(&mut to_send).concat();
+            let resend = ResendRequest::new(PARTICIPANT, SESSION_ID, GATEWAY_ID, seq_of(&sent));
+            let resend = framed(
+                MsgType::ResendRequest,
+                RESENDREQUEST_SIZE,
+                0,
+                &resend.encode(),
+            );
+            assert!(target
+                .on_client_data(client, &in_version(resend, version))
+                .is_continue());
+            // sent again the same way
+            assert_eq!(sent, received(&mut to_send).concat());
+            assert_eq!(OEP_HEADER_SIZE + size, sent.len());
+            let header = OepHeader::decode(sent[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+            assert_eq!(version, { header.oep_version });
+            let decoded = oep_decode(&sent).unwrap();
+            let decoded = decoded.as_any().downcast_ref::<ExecutionReport>().unwrap();
+            assert_eq!(10, { decoded.order_id });
+            assert_eq!(trade_id, decoded.get_trade_id());
+            target.disconnect(client);
+        }
+    }
+
+    #[test]
+    fn sequenced_ingress() {
+        let config = GatewayConfig {
+            ingress: IngressMode::Sequenced,
+            ..config()
+        };
+        let (relay, mut relayed) = mpsc::unbounded_channel();
+        let mut target = GatewayState::new(&config, dbhook::factory::build("mock"), relay).unwrap();
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(client, &login()).is_continue());
+        received(&mut to_send);
+
+        let ingress_header = |frame: &[u8]| {
+            IngressHeader::decode(frame[..INGRESSHEADER_SIZE].try_into().unwrap()).unwrap()
+        };
+        assert!(target.on_client_data(client, &new_order(1)).is_continue());
+        assert!(target.on_client_data(client, &new_order(2)).is_continue());
+        let frames = received(&mut relayed);
+        assert_eq!(2, frames.len());
+        let header = ingress_header(&frames[0]);
+        assert_eq!(GATEWAY_ID, header.gateway_id);
+        assert_eq!(IngressKind::Message, header.get_kind());
+        assert_eq!(1, { header.seq });
+        assert_eq!(
+            [MsgType::NewOrder as u8, 0, 0, 0],
+            frames[0][INGRESSHEADER_SIZE..INGRESSHEADER_SIZE + 4]
+        );
+        assert_eq!(2, { ingress_header(&frames[1]).seq });
+
+        // an engine missed the first one
+        let nak = IngressNak::new(0, GATEWAY_ID, header.epoch, 1);
+        target.on_engine_message(&framed(
+            MsgType::IngressNak,
+            INGRESSNAK_SIZE,
+            0,
+            &nak.encode(),
+        ));
+        assert_eq!(frames, received(&mut relayed));
+
+        target.send_ingress_heartbeat();
+        let heartbeat = received(&mut relayed);
+        assert_eq!(1, heartbeat.len());
+        assert_eq!(
+            IngressKind::Heartbeat,
+            ingress_header(&heartbeat[0]).get_kind()
+        );
+        assert_eq!(3, { ingress_header(&heartbeat[0]).seq });
+    }
+
+    #[test]
+    fn sequenced_egress() {
+        let (mut target, mut relayed) = target();
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(client, &login()).is_continue());
+        received(&mut to_send);
+
+        let egress_frame = |gateway_id: u8, seq: u64, report: &[u8]| {
+            let header = EgressHeader::new(0, 0, gateway_id, IngressKind::Message, 100, seq);
+            framed(
+                MsgType::EgressFrame,
+                EGRESSHEADER_SIZE + report.len(),
+                0,
+                &[header.encode().as_slice(), report].concat(),
+            )
+        };
+        target.on_engine_message(&egress_frame(GATEWAY_ID, 1, &engine_report(10, GATEWAY_ID)));
+        assert_eq!(1, received(&mut to_send).len());
+        // the second one was lost
+        target.on_engine_message(&egress_frame(GATEWAY_ID, 3, &engine_report(12, GATEWAY_ID)));
+        assert!(received(&mut to_send).is_empty());
+        let nak = received(&mut relayed);
+        assert_eq!(1, nak.len());
+        assert_eq!(OEP_HEADER_SIZE + EGRESSNAK_SIZE, nak[0].len());
+        assert_eq!(
+            MsgType::EgressNak,
+            OepHeader::decode(nak[0][..OEP_HEADER_SIZE].try_into().unwrap())
+                .unwrap()
+                .message_type()
+        );
+        let nak = EgressNak::decode(nak[0][OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(GATEWAY_ID, nak.gateway_id);
+        assert_eq!(100, { nak.epoch });
+        assert_eq!(2, { nak.from_seq });
+
+        // the retransmission
+        target.on_engine_message(&egress_frame(GATEWAY_ID, 2, &engine_report(11, GATEWAY_ID)));
+        target.on_engine_message(&egress_frame(GATEWAY_ID, 3, &engine_report(12, GATEWAY_ID)));
+        let reports = received(&mut to_send);
+        assert_eq!(2, reports.len());
+        assert_eq!(2, seq_of(&reports[0]));
+        assert_eq!(3, seq_of(&reports[1]));
+        // the others are not asked for
+        target.on_engine_message(&egress_frame(GATEWAY_ID + 1, 5, &engine_report(13, 2)));
+        assert!(received(&mut relayed).is_empty());
+        // nor anything but execution reports let through
+        target.on_engine_message(&egress_frame(GATEWAY_ID, 4, &login()));
+        assert!(received(&mut to_send).is_empty());
+    }
+
+    #[test]
+    fn registration() {
+        let config = GatewayConfig {
+            report_endpoint: Some("10.0.0.1:9002".parse().unwrap()),
+            ..config()
+        };
+        let (relay, mut relayed) = mpsc::unbounded_channel();
+        let mut target = GatewayState::new(&config, dbhook::factory::build("mock"), relay).unwrap();
+        let registration = |message: &[u8]| {
+            assert_eq!(OEP_HEADER_SIZE + GATEWAYREGISTRATION_SIZE, message.len());
+            assert_eq!(
+                MsgType::GatewayRegistration,
+                OepHeader::decode(message[..OEP_HEADER_SIZE].try_into().unwrap())
+                    .unwrap()
+                    .message_type()
+            );
+            GatewayRegistration::decode(message[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap()
+        };
+        target.register();
+        let sent = received(&mut relayed);
+        assert_eq!(1, sent.len());
+        let sent = registration(&sent[0]);
+        assert_eq!(GATEWAY_ID, sent.gateway_id);
+        assert_eq!(RegistrationKind::Register, sent.get_kind());
+        assert!(!sent.wants_all_reports());
+        assert_eq!(config.report_endpoint, sent.endpoint());
+
+        let reply = |gateway_id, status, instance| {
+            let reply = GatewayRegistrationReply::new(0, 0, gateway_id, status, instance);
+            framed(
+                MsgType::GatewayRegistrationReply,
+                GATEWAYREGISTRATIONREPLY_SIZE,
+                0,
+                &reply.encode(),
+            )
+        };
+        let instance = sent.instance;
+        target.on_engine_message(&reply(GATEWAY_ID, RegistrationStatus::Accepted, instance));
+        assert!(!target.registration_refused());
+        // the other gateways, or another instance, refused
+        target.on_engine_message(&reply(
+            GATEWAY_ID + 1,
+            RegistrationStatus::DuplicateId,
+            instance,
+        ));
+        target.on_engine_message(&reply(
+            GATEWAY_ID,
+            RegistrationStatus::DuplicateId,
+            instance.wrapping_add(1),
+        ));
+        assert!(!target.registration_refused());
+        target.on_engine_message(&reply(
+            GATEWAY_ID,
+            RegistrationStatus::DuplicateId,
+            instance,
+        ));
+        assert!(target.registration_refused());
+
+        // the id is free again once stopped
+        target.shut_down();
+        let sent = received(&mut relayed);
+        assert_eq!(
+            RegistrationKind::Unregister,
+            registration(&sent[0]).get_kind()
+        );
+    }
+
+    #[test]
+    fn out_of_sequence() {
+        let (mut target, mut relayed) = target();
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(client, &login()).is_continue());
+        received(&mut to_send);
+
+        assert!(target.on_client_data(client, &new_order(2)).is_continue());
+        let reject = received(&mut to_send).concat();
+        let ereport =
+            ExecutionReport::decode(reject[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(RejectReason::OutOfSequence, ereport.get_reject_reason());
+        assert!(received(&mut relayed).is_empty());
+
+        assert!(target.on_client_data(client, &new_order(1)).is_continue());
+        // already processed
+        assert!(target.on_client_data(client, &new_order(1)).is_continue());
+        assert_eq!(1, received(&mut relayed).len());
+        assert!(received(&mut to_send).is_empty());
+    }
+
+    #[test]
+    fn entitlements() {
+        let mut db = MockDB::default();
+        db.set_entitlements(PARTICIPANT, vec![2]);
+        let (relay, mut relayed) = mpsc::unbounded_channel();
+        let mut target = GatewayState::new(&config(), Box::new(db), relay).unwrap();
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(client, &login()).is_continue());
+        received(&mut to_send);
+
+        // on book 1
+        assert!(target.on_client_data(client, &new_order(1)).is_continue());
+        let reject = received(&mut to_send).concat();
+        let ereport =
+            ExecutionReport::decode(reject[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(RejectReason::NotEntitled, ereport.get_reject_reason());
+        assert!(received(&mut relayed).is_empty());
+
+        let mut order =
+            NewOrder::decode(new_order(2)[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        order.book_id = 2;
+        let order = framed(MsgType::NewOrder, NEWORDER_SIZE, 2, &order.encode());
+        assert!(target.on_client_data(client, &order).is_continue());
+        assert_eq!(1, received(&mut relayed).len());
+        assert!(received(&mut to_send).is_empty());
+    }
+
+    #[test]
+    fn duplicate_client_order_id() {
+        let (mut target, mut relayed) = target();
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(client, &login()).is_continue());
+        received(&mut to_send);
+        assert!(target.on_client_data(client, &new_order(1)).is_continue());
+        assert_eq!(1, received(&mut relayed).len());
+
+        // sent again under the next sequence, e.g. by a client retrying
+        let again = |seq| {
+            let order = NewOrder::decode(new_order(1)[OEP_HEADER_SIZE..].try_into().unwrap());
+            framed(
+                MsgType::NewOrder,
+                NEWORDER_SIZE,
+                seq,
+                &order.unwrap().encode(),
+            )
+        };
+        assert!(target.on_client_data(client, &again(2)).is_continue());
+        let reject = received(&mut to_send).concat();
+        let ereport =
+            ExecutionReport::decode(reject[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(
+            RejectReason::DuplicateClientOrderId,
+            ereport.get_reject_reason()
+        );
+        assert_eq!(1, ereport.get_submitted_order_id());
+        assert!(received(&mut relayed).is_empty());
+
+        // still known to the session once reconnected
+        target.disconnect(client);
+        received(&mut relayed);
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(client, &login()).is_continue());
+        received(&mut to_send);
+        assert!(target.on_client_data(client, &again(3)).is_continue());
+        assert_eq!(1, received(&mut to_send).len());
+        assert!(received(&mut relayed).is_empty());
+        assert!(target.on_client_data(client, &new_order(4)).is_continue());
+        assert_eq!(1, received(&mut relayed).len());
+    }
+
+    #[test]
+    fn reports_are_routed_or_kept() {
+        let (mut target, mut relayed) = target();
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(client, &login()).is_continue());
+        received(&mut to_send);
+
+        target.on_engine_message(&engine_report(10, GATEWAY_ID));
+        let reports = received(&mut to_send);
+        assert_eq!(1, reports.len());
+        assert_eq!(1, seq_of(&reports[0]));
+        // for another gateway
+        target.on_engine_message(&engine_report(11, GATEWAY_ID + 1));
+        assert!(received(&mut to_send).is_empty());
+
+        // the orders of the session are cancelled when it goes away
+        target.disconnect(client);
+        assert!(!target.is_connected(client));
+        let relayed = received(&mut relayed);
+        assert_eq!(1, relayed.len());
+        assert_eq!(MsgType::SessionNotification as u8, relayed[0][0]);
+
+        target.on_engine_message(&engine_report(12, GATEWAY_ID));
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(client, &login()).is_continue());
+        let received = received(&mut to_send);
+        // the login reply, followed by what was missed
+        assert_eq!(2, received.len());
+        assert_eq!(
+            MsgType::Login,
+            OepHeader::decode(received[0][..OEP_HEADER_SIZE].try_into().unwrap())
+                .unwrap()
+                .message_type()
+        );
+        assert_eq!(2, seq_of(&received[1]));
+        let ereport =
+            ExecutionReport::decode(received[1][OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(12, { ereport.order_id });
+    }
+
+    #[test]
+    fn orders_kept_on_disconnect() {
+        let (mut target, mut relayed) = target();
+        let (client, _to_send) = connect(&mut target);
+        let login = Login::new(PARTICIPANT, SESSION_ID, GATEWAY_ID, "test")
+            .with_cancel_on_disconnect(false);
+        let login = framed(MsgType::Login, LOGIN_SIZE, 0, &login.encode());
+        assert!(target.on_client_data(client, &login).is_continue());
+        target.disconnect(client);
+        // no SessionNotification, the engine leaving the orders in the book
+        assert!(received(&mut relayed).is_empty());
+    }
+
+    #[test]
+    fn legacy_login_cancels_on_disconnect() {
+        let (mut target, mut relayed) = target();
+        let (client, _to_send) = connect(&mut target);
+        // the gateway id packed as a u32, as the clients did before the flag
+        let mut body = Login::new(PARTICIPANT, SESSION_ID, GATEWAY_ID, "test").encode();
+        body[12..16].copy_from_slice(&(GATEWAY_ID as u32).to_le_bytes());
+        let login = framed(MsgType::Login, LOGIN_SIZE, 0, &body);
+        assert!(target.on_client_data(client, &login).is_continue());
+        target.disconnect(client);
+        let relayed = received(&mut relayed);
+        assert_eq!(1, relayed.len());
+        assert_eq!(MsgType::SessionNotification as u8, relayed[0][0]);
+    }
+
+    #[test]
+    fn shut_down() {
+        let (mut target, mut relayed) = target();
+        let (client, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(client, &login()).is_continue());
+        assert_eq!(1, received(&mut to_send).len());
+
+        target.shut_down();
+        assert!(!target.is_connected(client));
+        // the session asked for its orders to be cancelled, then the gateway unregisters
+        let relayed = received(&mut relayed);
+        assert_eq!(2, relayed.len());
+        assert_eq!(MsgType::SessionNotification as u8, relayed[0][0]);
+        assert_eq!(
+            MsgType::GatewayRegistration,
+            OepHeader::decode(relayed[1][..OEP_HEADER_SIZE].try_into().unwrap())
+                .unwrap()
+                .message_type()
+        );
+        // the connection closes once its writer is done
+        assert_eq!(Err(TryRecvError::Disconnected), to_send.try_recv());
+    }
+
+    #[test]
+    fn listener_namespace() {
+        let (mut target, _relayed) = target();
+        let (outgoing, mut to_send) = mpsc::unbounded_channel();
+        let client = target.add_client(
+            Some(Rc::new(ListenerConfig {
+                name: String::from("retail"),
+                address: String::from("127.0.0.1"),
+                port: 10001,
+                protocol: crate::listener::ListenerProtocol::Oep,
+                session_ids: 1000..=1999,
+                max_messages_per_second: 0,
+                burst_messages: 0,
+                max_throttled_per_second: 0,
+                session_timeout_ms: 0,
+                fix: None,
+            })),
+            ClientWriter::new(outgoing),
+        );
+        assert!(target.on_client_data(client, &login()).is_break());
+        assert_eq!(
+            LoginRejectReason::BadCredentials,
+            login_reject_of(&received(&mut to_send).concat())
+        );
+    }
+
+    #[test]
+    fn duplicate_session() {
+        let (mut target, _relayed) = target();
+        let (first, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(first, &login()).is_continue());
+        received(&mut to_send);
+
+        let (second, mut to_send2) = connect(&mut target);
+        assert!(target.on_client_data(second, &login()).is_break());
+        assert_eq!(
+            LoginRejectReason::DuplicateSession,
+            login_reject_of(&received(&mut to_send2).concat())
+        );
+        target.disconnect(second);
+        // the first one keeps the session
+        assert!(target.on_client_data(first, &new_order(1)).is_continue());
+
+        // and lets it go once gone
+        target.disconnect(first);
+        let (third, mut to_send3) = connect(&mut target);
+        assert!(target.on_client_data(third, &login()).is_continue());
+        assert_eq!(
+            MsgType::Login,
+            OepHeader::decode(
+                received(&mut to_send3)[0][..OEP_HEADER_SIZE]
+                    .try_into()
+                    .unwrap()
+            )
+            .unwrap()
+            .message_type()
+        );
+    }
+
+    #[test]
+    fn session_takeover() {
+        let config = GatewayConfig {
+            duplicate_session: DuplicateSessionPolicy::Takeover,
+            ..config()
+        };
+        let (relay, mut relayed) = mpsc::unbounded_channel();
+        let mut target = GatewayState::new(&config, dbhook::factory::build("mock"), relay).unwrap();
+        let (first, mut to_send) = connect(&mut target);
+        assert!(target.on_client_data(first, &login()).is_continue());
+        received(&mut to_send);
+
+        let (second, mut to_send2) = connect(&mut target);
+        assert!(target.on_client_data(second, &login()).is_continue());
+        assert_eq!(1, received(&mut to_send2).len());
+        // the first connection is gone, its orders cancelled
+        assert!(!target.is_connected(first));
+        // its writer task sees the channel closed, shutting the socket down
+        assert_eq!(Err(TryRecvError::Disconnected), to_send.try_recv());
+        let notifications = received(&mut relayed);
+        assert_eq!(1, notifications.len());
+        assert_eq!(MsgType::SessionNotification as u8, notifications[0][0]);
+
+        // the session goes on with the second one
+        target.on_engine_message(&engine_report(10, GATEWAY_ID));
+        assert_eq!(1, received(&mut to_send2).len());
+        assert!(target.on_client_data(first, &new_order(1)).is_break());
+        assert!(target.on_client_data(second, &new_order(1)).is_continue());
+    }
+
+    #[test]
+    fn throttled_login() {
+        let (mut target, _relayed) = target();
+        let (outgoing, mut to_send) = mpsc::unbounded_channel();
+        let client = target.add_client(
+            Some(Rc::new(ListenerConfig {
+                name: String::from("retail"),
+                address: String::from("127.0.0.1"),
+                port: 10001,
+                protocol: crate::listener::ListenerProtocol::Oep,
+                session_ids: 0..=1999,
+                max_messages_per_second: 1,
+                burst_messages: 1,
+                max_throttled_per_second: 0,
+                session_timeout_ms: 0,
+                fix: None,
+            })),
+            ClientWriter::new(outgoing),
+        );
+        // a message ahead of the login takes the whole burst
+        assert!(target.on_client_data(client, &new_order(1)).is_break());
+        assert!(received(&mut to_send).is_empty());
+        assert!(target.on_client_data(client, &login()).is_break());
+        assert_eq!(
+            LoginRejectReason::Throttled,
+            login_reject_of(&received(&mut to_send).concat())
+        );
+    }
+
+    #[test]
+    fn reloaded_limits() {
+        let listener = ListenerConfig {
+            name: String::from("retail"),
+            address: String::from("127.0.0.1"),
+            port: 10001,
+            protocol: crate::listener::ListenerProtocol::Oep,
+            session_ids: 0..=1999,
+            max_messages_per_second: 0,
+            burst_messages: 0,
+            max_throttled_per_second: 0,
+            session_timeout_ms: 0,
+            fix: None,
+        };
+        let config = GatewayConfig {
+            listeners: vec![listener.clone()],
+            ..config()
+        };
+        let (relay, mut relayed) = mpsc::unbounded_channel();
+        let mut target = GatewayState::new(&config, dbhook::factory::build("mock"), relay).unwrap();
+        let (outgoing, mut to_send) = mpsc::unbounded_channel();
+        let client =
+            target.add_client(Some(Rc::new(listener.clone())), ClientWriter::new(outgoing));
+        assert!(target.on_client_data(client, &login()).is_continue());
+        received(&mut to_send);
+        assert_eq!(None, target.read_timeout(client));
+
+        target.reload(&GatewayConfig {
+            listeners: vec![ListenerConfig {
+                max_messages_per_second: 1,
+                burst_messages: 1,
+                max_throttled_per_second: 5,
+                session_timeout_ms: 3000,
+                // only taken with a restart
+                port: 10002,
+                ..listener.clone()
+            }],
+            ..config.clone()
+        });
+        // the session is kept, with the new limits
+        assert!(target.is_connected(client));
+        assert_eq!(Some(Duration::from_secs(3)), target.read_timeout(client));
+        assert!(target.on_client_data(client, &new_order(1)).is_continue());
+        assert_eq!(1, received(&mut relayed).len());
+        assert!(target.on_client_data(client, &new_order(2)).is_continue());
+        let reject = received(&mut to_send).concat();
+        let ereport =
+            ExecutionReport::decode(reject[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(RejectReason::Throttled, ereport.get_reject_reason());
+        assert!(ereport.is_throttle_reject());
+
+        // and so are the clients accepted from now on
+        let (outgoing, _to_send) = mpsc::unbounded_channel();
+        let later = target.add_client(Some(Rc::new(listener)), ClientWriter::new(outgoing));
+        assert_eq!(Some(Duration::from_secs(3)), target.read_timeout(later));
+    }
+}
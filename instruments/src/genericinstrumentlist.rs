@@ -1,4 +1,4 @@
-use std::{cell::RefCell, rc::Rc};
+use std::sync::{Arc, RwLock};
 
 use super::instrument::{Instrument, InstrumentType};
 
@@ -6,9 +6,11 @@ pub trait GenericInstrumentList: Iterator + Clone + Sized {
     fn new() -> Self
     where
         Self: Sized;
-    fn add_instrument(&mut self, i: Instrument) -> Rc<RefCell<Instrument>>;
+    fn add_instrument(&mut self, i: Instrument) -> Arc<RwLock<Instrument>>;
     fn update_instrument(&mut self, i: &Instrument);
-    fn get(&self, id: u64) -> Option<Rc<RefCell<Instrument>>>;
+    /// Removes the instrument @id, returning it if it was there
+    fn remove(&mut self, id: u64) -> Option<Arc<RwLock<Instrument>>>;
+    fn get(&self, id: u64) -> Option<Arc<RwLock<Instrument>>>;
     fn len(&self) -> usize;
     fn contains(&self, id: u64) -> bool;
     fn add(&mut self, id: u64, itype: InstrumentType);
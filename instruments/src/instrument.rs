@@ -1,10 +1,16 @@
 use std::hash::Hash;
 
+use crate::price::MAX_PRICE_DECIMALS;
+
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum InstrumentState {
     Trading,
     Closed,
     Auction,
+    // trading interrupted, e.g. on high volatility: only the cancels are accepted
+    Halted,
+    // before the opening: orders are accepted as long as they don't cross the book
+    PreOpen,
 }
 
 impl Into<u8> for InstrumentState {
@@ -13,6 +19,8 @@ impl Into<u8> for InstrumentState {
             Self::Trading => 0,
             Self::Closed => 1,
             Self::Auction => 2,
+            Self::Halted => 3,
+            Self::PreOpen => 4,
         }
     }
 }
@@ -23,6 +31,8 @@ impl From<u8> for InstrumentState {
             0 => Self::Trading,
             1 => Self::Closed,
             2 => Self::Auction,
+            3 => Self::Halted,
+            4 => Self::PreOpen,
             _ => Self::Closed,
         }
     }
@@ -74,8 +84,29 @@ pub struct Instrument {
     percentage_bands: u8,
     // percentage of allowed daily variation
     percentage_variation_allowed: u8,
+    // the prices must be a multiple of it
+    tick_size: u64,
+    // the quantities must be a multiple of it
+    round_lot: u64,
+    // the closing price of the previous trading day, 0 if unknown
+    previous_close: u64,
+    // the prices are scaled by 10^price_decimals, see price.rs
+    price_decimals: u8,
+    // the group of instruments it belongs to, e.g. equities or derivatives,
+    // for the operations on all of them at once. 0 if none
+    segment: u16,
+    // of the derivatives: the unix timestamp (seconds) the contract expires
+    // at, the market closing for good, 0 if none
+    expiry: u64,
+    // of the options and warrants, scaled as the prices. 0 if none
+    strike: u64,
+    // the instrument id of the underlying of a derivative, 0 if none
+    underlying: u64,
 }
 
+/// length of the encoded instrument, without the name
+pub const INSTRUMENT_FIXED_SIZE: usize = 63;
+
 impl Instrument {
     pub fn new(
         id: u64,
@@ -92,6 +123,14 @@ impl Instrument {
             state: state,
             percentage_bands: percentage_bands,
             percentage_variation_allowed: percentage_variation_allowed,
+            tick_size: 1,
+            round_lot: 1,
+            previous_close: 0,
+            price_decimals: 0,
+            segment: 0,
+            expiry: 0,
+            strike: 0,
+            underlying: 0,
         }
     }
 
@@ -104,6 +143,14 @@ impl Instrument {
             state: InstrumentState::Closed,
             percentage_bands: 0,
             percentage_variation_allowed: 30,
+            tick_size: 1,
+            round_lot: 1,
+            previous_close: 0,
+            price_decimals: 0,
+            segment: 0,
+            expiry: 0,
+            strike: 0,
+            underlying: 0,
         }
     }
 
@@ -115,6 +162,14 @@ impl Instrument {
             state: i.state,
             percentage_bands: i.percentage_bands,
             percentage_variation_allowed: i.percentage_variation_allowed,
+            tick_size: i.tick_size,
+            round_lot: i.round_lot,
+            previous_close: i.previous_close,
+            price_decimals: i.price_decimals,
+            segment: i.segment,
+            expiry: i.expiry,
+            strike: i.strike,
+            underlying: i.underlying,
         }
     }
 
@@ -155,6 +210,79 @@ impl Instrument {
         self.percentage_variation_allowed
     }
 
+    pub fn set_tick_size(&mut self, tick_size: u64) {
+        assert!(tick_size > 0);
+        self.tick_size = tick_size;
+    }
+
+    pub fn get_tick_size(&self) -> u64 {
+        self.tick_size
+    }
+
+    pub fn set_round_lot(&mut self, round_lot: u64) {
+        assert!(round_lot > 0);
+        self.round_lot = round_lot;
+    }
+
+    pub fn get_round_lot(&self) -> u64 {
+        self.round_lot
+    }
+
+    pub fn set_previous_close(&mut self, previous_close: u64) {
+        self.previous_close = previous_close;
+    }
+
+    pub fn get_previous_close(&self) -> u64 {
+        self.previous_close
+    }
+
+    pub fn set_price_decimals(&mut self, price_decimals: u8) {
+        assert!(price_decimals <= MAX_PRICE_DECIMALS);
+        self.price_decimals = price_decimals;
+    }
+
+    pub fn get_price_decimals(&self) -> u8 {
+        self.price_decimals
+    }
+
+    pub fn set_segment(&mut self, segment: u16) {
+        self.segment = segment;
+    }
+
+    pub fn get_segment(&self) -> u16 {
+        self.segment
+    }
+
+    pub fn set_expiry(&mut self, expiry: u64) {
+        self.expiry = expiry;
+    }
+
+    pub fn get_expiry(&self) -> u64 {
+        self.expiry
+    }
+
+    /// true if the instrument has an expiry and reached it at @now
+    /// (unix timestamp, seconds)
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expiry != 0 && now >= self.expiry
+    }
+
+    pub fn set_strike(&mut self, strike: u64) {
+        self.strike = strike;
+    }
+
+    pub fn get_strike(&self) -> u64 {
+        self.strike
+    }
+
+    pub fn set_underlying(&mut self, underlying: u64) {
+        self.underlying = underlying;
+    }
+
+    pub fn get_underlying(&self) -> u64 {
+        self.underlying
+    }
+
     /// encode the instrument e.g. in order to send it over feed
     pub fn encode(&self) -> Vec<u8> {
         let mut r = vec![];
@@ -165,6 +293,14 @@ impl Instrument {
             self.get_percentage_bands(),
             self.get_percentage_variation_allowed(),
         ]);
+        r.extend_from_slice(&self.get_tick_size().to_le_bytes());
+        r.extend_from_slice(&self.get_round_lot().to_le_bytes());
+        r.extend_from_slice(&self.get_previous_close().to_le_bytes());
+        r.push(self.get_price_decimals());
+        r.extend_from_slice(&self.get_segment().to_le_bytes());
+        r.extend_from_slice(&self.get_expiry().to_le_bytes());
+        r.extend_from_slice(&self.get_strike().to_le_bytes());
+        r.extend_from_slice(&self.get_underlying().to_le_bytes());
         r.extend_from_slice(self.get_name().as_bytes());
         r
     }
@@ -173,11 +309,19 @@ impl Instrument {
     pub fn decode(buf: &[u8]) -> Self {
         Self {
             id: u64::from_le_bytes(buf[0..8].try_into().unwrap()),
-            name: String::from_utf8(buf[12..].to_vec()).unwrap(),
+            name: String::from_utf8(buf[INSTRUMENT_FIXED_SIZE..].to_vec()).unwrap(),
             i_type: buf[8].into(),
             state: buf[9].into(),
             percentage_bands: buf[10].into(),
             percentage_variation_allowed: buf[11].into(),
+            tick_size: u64::from_le_bytes(buf[12..20].try_into().unwrap()),
+            round_lot: u64::from_le_bytes(buf[20..28].try_into().unwrap()),
+            previous_close: u64::from_le_bytes(buf[28..36].try_into().unwrap()),
+            price_decimals: buf[36].min(MAX_PRICE_DECIMALS),
+            segment: u16::from_le_bytes(buf[37..39].try_into().unwrap()),
+            expiry: u64::from_le_bytes(buf[39..47].try_into().unwrap()),
+            strike: u64::from_le_bytes(buf[47..55].try_into().unwrap()),
+            underlying: u64::from_le_bytes(buf[55..63].try_into().unwrap()),
         }
     }
 }
@@ -203,7 +347,7 @@ mod test {
 
     use crate::instrument::InstrumentType;
 
-    use super::Instrument;
+    use super::{Instrument, INSTRUMENT_FIXED_SIZE};
 
     fn calculate_hash(instrument: &Instrument) -> u64 {
         let mut hasher = DefaultHasher::new();
@@ -234,4 +378,41 @@ mod test {
 
         assert_ne!(calculate_hash(&i1), calculate_hash(&i2));
     }
+
+    #[test]
+    fn encode_decode() {
+        let mut original = Instrument::new_fast(100, InstrumentType::Future);
+        original.set_tick_size(5);
+        original.set_round_lot(100);
+        original.set_previous_close(1250);
+        original.set_price_decimals(2);
+        original.set_segment(3);
+        original.set_expiry(1_800_000_000);
+        original.set_strike(9000);
+        original.set_underlying(42);
+
+        let encoded = original.encode();
+        assert_eq!(INSTRUMENT_FIXED_SIZE, encoded.len());
+        let decoded = Instrument::decode(&encoded);
+        assert_eq!(100, decoded.get_id());
+        assert_eq!(InstrumentType::Future, decoded.get_type());
+        assert_eq!(5, decoded.get_tick_size());
+        assert_eq!(100, decoded.get_round_lot());
+        assert_eq!(1250, decoded.get_previous_close());
+        assert_eq!(2, decoded.get_price_decimals());
+        assert_eq!(3, decoded.get_segment());
+        assert_eq!(1_800_000_000, decoded.get_expiry());
+        assert_eq!(9000, decoded.get_strike());
+        assert_eq!(42, decoded.get_underlying());
+    }
+
+    #[test]
+    fn expiry() {
+        let mut target = Instrument::new_fast(100, InstrumentType::OptionCall);
+        assert!(!target.is_expired(u64::MAX));
+        target.set_expiry(5000);
+        assert!(!target.is_expired(4999));
+        assert!(target.is_expired(5000));
+        assert!(target.is_expired(5001));
+    }
 }
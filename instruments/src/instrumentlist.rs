@@ -1,4 +1,7 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
 use super::genericinstrumentlist::GenericInstrumentList;
 use crate::instrument::{Instrument, InstrumentType};
@@ -18,12 +21,12 @@ use crate::instrument::{Instrument, InstrumentType};
 ///
 #[derive(Debug, Clone)]
 pub struct InstrumentList {
-    instrument_list: HashMap<u64, Rc<RefCell<Instrument>>>,
+    instrument_list: HashMap<u64, Arc<RwLock<Instrument>>>,
     iter_count: usize,
 }
 
 impl Iterator for InstrumentList {
-    type Item = Rc<RefCell<Instrument>>;
+    type Item = Arc<RwLock<Instrument>>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.iter_count < self.instrument_list.len() {
@@ -54,6 +57,10 @@ impl GenericInstrumentList for InstrumentList {
         self.add(i.get_id(), i.get_type());
     }
 
+    fn remove(&mut self, id: u64) -> Option<Arc<RwLock<Instrument>>> {
+        self.instrument_list.remove(&id)
+    }
+
     fn len(&self) -> usize {
         self.instrument_list.len()
     }
@@ -62,7 +69,7 @@ impl GenericInstrumentList for InstrumentList {
         self.instrument_list.contains_key(&id)
     }
 
-    fn get(&self, id: u64) -> Option<Rc<RefCell<Instrument>>> {
+    fn get(&self, id: u64) -> Option<Arc<RwLock<Instrument>>> {
         match self.instrument_list.get(&id) {
             Some(v) => Some(v.clone()),
             None => None,
@@ -73,22 +80,22 @@ impl GenericInstrumentList for InstrumentList {
     /// Use add_instrument instead
     fn add(&mut self, id: u64, itype: InstrumentType) {
         self.instrument_list
-            .insert(id, Rc::new(RefCell::new(Instrument::new_fast(id, itype))));
+            .insert(id, Arc::new(RwLock::new(Instrument::new_fast(id, itype))));
     }
 
     /// adds an instrument to the list.
     /// If the ID is already present, then the previous entry is replaced
-    fn add_instrument(&mut self, i: Instrument) -> Rc<RefCell<Instrument>> {
+    fn add_instrument(&mut self, i: Instrument) -> Arc<RwLock<Instrument>> {
         let id = i.get_id();
         match self.contains(id) {
             false => {
-                let r = Rc::new(RefCell::new(i));
+                let r = Arc::new(RwLock::new(i));
                 self.instrument_list.insert(id, r.clone());
                 r
             }
             true => {
                 let existing = self.get(i.get_id()).unwrap();
-                existing.borrow_mut().clone_from(&i);
+                existing.write().unwrap().clone_from(&i);
                 return existing.clone();
             }
         }
@@ -112,6 +119,10 @@ mod tests {
         assert!(target.contains(100));
         assert!(target.contains(200));
         assert_eq!(false, target.contains(300));
+
+        assert!(target.remove(100).is_some());
+        assert!(target.remove(100).is_none());
+        assert_eq!(1, target.len());
     }
 
     #[test]
@@ -128,15 +139,15 @@ mod tests {
             .for_each(drop);
 
         let mut response: Vec<_> = target.collect();
-        response.sort_unstable_by_key(|i| i.borrow().get_id());
+        response.sort_unstable_by_key(|i| i.read().unwrap().get_id());
 
         let mut expected = instruments.iter();
         let mut actual = response.iter();
 
         while let Some(ex) = expected.next() {
             let ac = actual.next().unwrap();
-            assert_eq!(ex.0, ac.borrow().get_id());
-            assert_eq!(ex.1, ac.borrow().get_type());
+            assert_eq!(ex.0, ac.read().unwrap().get_id());
+            assert_eq!(ex.1, ac.read().unwrap().get_type());
         }
     }
 
@@ -154,7 +165,7 @@ mod tests {
             .for_each(drop);
 
         let mut response: Vec<_> = target.clone().collect();
-        response.sort_unstable_by_key(|i| i.borrow().get_id());
+        response.sort_unstable_by_key(|i| i.read().unwrap().get_id());
         assert_eq!(instruments.len(), response.len());
 
         let mut expected = instruments.iter();
@@ -162,13 +173,13 @@ mod tests {
 
         while let Some(ex) = expected.next() {
             let ac = actual.next().unwrap();
-            assert_eq!(ex.0, ac.borrow().get_id());
-            assert_eq!(ex.1, ac.borrow().get_type());
+            assert_eq!(ex.0, ac.read().unwrap().get_id());
+            assert_eq!(ex.1, ac.read().unwrap().get_type());
         }
 
         // Second time
         let mut response: Vec<_> = target.collect();
-        response.sort_unstable_by_key(|i| i.borrow().get_id());
+        response.sort_unstable_by_key(|i| i.read().unwrap().get_id());
         assert_eq!(instruments.len(), response.len());
 
         let mut expected = instruments.iter();
@@ -176,8 +187,8 @@ mod tests {
 
         while let Some(ex) = expected.next() {
             let ac = actual.next().unwrap();
-            assert_eq!(ex.0, ac.borrow().get_id());
-            assert_eq!(ex.1, ac.borrow().get_type());
+            assert_eq!(ex.0, ac.read().unwrap().get_id());
+            assert_eq!(ex.1, ac.read().unwrap().get_type());
         }
     }
 }
@@ -2,3 +2,5 @@ pub mod genericinstrumentlist;
 pub mod instrument;
 pub mod instrumentlist;
 pub mod mockinstrumentlist;
+pub mod partition;
+pub mod price;
@@ -1,17 +1,20 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
 use super::{genericinstrumentlist::GenericInstrumentList, instrument::Instrument};
 
 #[derive(Clone)]
 pub struct MockInstrumentList {
-    instrument_list: HashMap<u64, Rc<RefCell<Instrument>>>,
+    instrument_list: HashMap<u64, Arc<RwLock<Instrument>>>,
     iter_count: usize,
 }
 
 impl MockInstrumentList {}
 
 impl Iterator for MockInstrumentList {
-    type Item = Rc<RefCell<Instrument>>;
+    type Item = Arc<RwLock<Instrument>>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.iter_count < self.instrument_list.len() {
@@ -38,7 +41,11 @@ impl GenericInstrumentList for MockInstrumentList {
 
     fn update_instrument(&mut self, i: &Instrument) {
         self.instrument_list
-            .insert(i.get_id(), Rc::new(RefCell::new(i.clone())));
+            .insert(i.get_id(), Arc::new(RwLock::new(i.clone())));
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Arc<RwLock<Instrument>>> {
+        self.instrument_list.remove(&id)
     }
 
     fn len(&self) -> usize {
@@ -53,16 +60,16 @@ impl GenericInstrumentList for MockInstrumentList {
         self.add_instrument(Instrument::new_fast(id, i_type));
     }
 
-    fn add_instrument(&mut self, i: Instrument) -> Rc<RefCell<Instrument>> {
+    fn add_instrument(&mut self, i: Instrument) -> Arc<RwLock<Instrument>> {
         let id = i.get_id();
-        self.instrument_list.insert(id, Rc::new(RefCell::new(i)));
+        self.instrument_list.insert(id, Arc::new(RwLock::new(i)));
         self.instrument_list
             .get(&id)
             .expect("Can't insert instrument into market")
             .clone()
     }
 
-    fn get(&self, id: u64) -> Option<Rc<RefCell<Instrument>>> {
+    fn get(&self, id: u64) -> Option<Arc<RwLock<Instrument>>> {
         match self.instrument_list.get(&id) {
             Some(v) => Some(v.clone()),
             None => None,
@@ -0,0 +1,64 @@
+use std::ops::RangeInclusive;
+
+/// The books handled by one matching engine, when the instruments are
+/// sharded across several of them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Partition {
+    id: u8,
+    // empty means all the books
+    ranges: Vec<RangeInclusive<u64>>,
+}
+
+impl Partition {
+    /// Parses @books, a comma separated list of book ids and inclusive ranges,
+    /// e.g. "1-1000,2000,3000-3999". An empty list holds all the books
+    ///
+    /// Returns: None if @books is malformed
+    pub fn parse(id: u8, books: &str) -> Option<Self> {
+        let mut ranges = vec![];
+        for range in books.split(',').map(|r| r.trim()).filter(|r| !r.is_empty()) {
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start.trim().parse().ok()?, end.trim().parse().ok()?),
+                None => {
+                    let book = range.parse().ok()?;
+                    (book, book)
+                }
+            };
+            if start > end {
+                return None;
+            }
+            ranges.push(start..=end);
+        }
+        Some(Self { id, ranges })
+    }
+
+    pub fn get_id(&self) -> u8 {
+        self.id
+    }
+
+    pub fn contains(&self, book_id: u64) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|r| r.contains(&book_id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Partition;
+
+    #[test]
+    fn parse_and_contains() {
+        let target = Partition::parse(2, "1-1000, 2000,3000-3999").unwrap();
+        assert_eq!(2, target.get_id());
+        assert!(target.contains(1));
+        assert!(target.contains(1000));
+        assert!(!target.contains(1001));
+        assert!(target.contains(2000));
+        assert!(!target.contains(2001));
+        assert!(target.contains(3500));
+
+        assert!(Partition::parse(0, "").unwrap().contains(12345));
+        assert!(Partition::default().contains(12345));
+        assert!(Partition::parse(0, "10-1").is_none());
+        assert!(Partition::parse(0, "1-x").is_none());
+    }
+}
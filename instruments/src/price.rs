@@ -0,0 +1,88 @@
+//! The prices travel as integers, scaled by 10^price_decimals of their
+//! instrument: with 2 decimals, 123.45 is sent as 12345
+
+/// More would overflow the scaled u64 prices on their own
+pub const MAX_PRICE_DECIMALS: u8 = 18;
+
+/// 10^@decimals, the scaled value of 1
+pub fn multiplier(decimals: u8) -> u64 {
+    10u64.pow(decimals.min(MAX_PRICE_DECIMALS) as u32)
+}
+
+/// Parses @text, e.g. "123.45", into a price scaled to @decimals
+///
+/// Returns: None if @text is not a number, has more decimals than @decimals
+/// or doesn't fit
+pub fn parse_price(text: &str, decimals: u8) -> Option<u64> {
+    let decimals = decimals.min(MAX_PRICE_DECIMALS) as usize;
+    let (units, fraction) = text.trim().split_once('.').unwrap_or((text.trim(), ""));
+    if (units.is_empty() && fraction.is_empty())
+        || fraction.len() > decimals
+        || !units
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let units: u128 = if units.is_empty() {
+        0
+    } else {
+        units.parse().ok()?
+    };
+    let fraction: u128 = format!("{fraction:0<decimals$}")
+        .parse()
+        .unwrap_or_default();
+    let scaled = units.checked_mul(multiplier(decimals as u8) as u128)? + fraction;
+    u64::try_from(scaled).ok()
+}
+
+/// The price @scaled to @decimals, as a decimal number
+pub fn format_price(scaled: u64, decimals: u8) -> String {
+    let decimals = decimals.min(MAX_PRICE_DECIMALS);
+    if decimals == 0 {
+        return scaled.to_string();
+    }
+    let multiplier = multiplier(decimals);
+    format!(
+        "{}.{:0width$}",
+        scaled / multiplier,
+        scaled % multiplier,
+        width = decimals as usize
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_price, multiplier, parse_price};
+
+    #[test]
+    fn parse_and_format() {
+        assert_eq!(1, multiplier(0));
+        assert_eq!(100, multiplier(2));
+        assert_eq!(Some(12345), parse_price("123.45", 2));
+        assert_eq!(Some(12340), parse_price("123.4", 2));
+        assert_eq!(Some(12300), parse_price(" 123 ", 2));
+        assert_eq!(Some(5), parse_price(".05", 2));
+        assert_eq!(Some(123), parse_price("123", 0));
+        for invalid in [
+            "123.456",
+            "",
+            ".",
+            "-1",
+            "1e3",
+            "12.3.4",
+            "18446744073709551616",
+        ] {
+            assert_eq!(None, parse_price(invalid, 2), "{invalid}");
+        }
+        assert_eq!(None, parse_price("1.5", 0));
+        assert_eq!(Some(u64::MAX), parse_price("18446744073709551615", 0));
+        assert_eq!(None, parse_price("184467440737095517", 2));
+
+        assert_eq!("123.45", format_price(12345, 2));
+        assert_eq!("0.05", format_price(5, 2));
+        assert_eq!("123", format_price(123, 0));
+        assert_eq!(Some(987654321), parse_price(&format_price(987654321, 4), 4));
+    }
+}
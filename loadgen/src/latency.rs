@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+/// The time between the submission of the orders and their first execution
+/// report, as measured by the sessions
+#[derive(Debug, Default, Clone)]
+pub struct Latencies {
+    samples: Vec<Duration>,
+    sorted: bool,
+}
+
+impl Latencies {
+    pub fn record(&mut self, latency: Duration) {
+        self.samples.push(latency);
+        self.sorted = false;
+    }
+
+    /// Adds the samples of another session
+    pub fn merge(&mut self, other: Latencies) {
+        self.samples.extend(other.samples);
+        self.sorted = false;
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// The latency under which @percent of the samples are, by the nearest
+    /// rank. None without samples
+    pub fn percentile(&mut self, percent: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        if !self.sorted {
+            self.samples.sort_unstable();
+            self.sorted = true;
+        }
+        let rank = (percent / 100.0 * self.samples.len() as f64).ceil() as usize;
+        Some(self.samples[rank.clamp(1, self.samples.len()) - 1])
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.samples.len()).ok().filter(|c| *c > 0)?;
+        Some(self.samples.iter().sum::<Duration>() / count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Latencies;
+
+    #[test]
+    fn percentiles() {
+        let mut target = Latencies::default();
+        assert_eq!(None, target.percentile(50.0));
+        assert_eq!(None, target.mean());
+
+        for us in (1..=50).rev() {
+            target.record(Duration::from_micros(us));
+        }
+        let mut other = Latencies::default();
+        for us in 51..=100 {
+            other.record(Duration::from_micros(us));
+        }
+        target.merge(other);
+        assert_eq!(100, target.count());
+
+        assert_eq!(Some(Duration::from_micros(1)), target.percentile(0.0));
+        assert_eq!(Some(Duration::from_micros(50)), target.percentile(50.0));
+        assert_eq!(Some(Duration::from_micros(99)), target.percentile(99.0));
+        assert_eq!(Some(Duration::from_micros(100)), target.percentile(99.9));
+        assert_eq!(Some(Duration::from_micros(100)), target.percentile(100.0));
+        assert_eq!(Some(Duration::from_nanos(50500)), target.mean());
+    }
+}
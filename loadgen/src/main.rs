@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Result};
+use configparser::ini::Ini;
+use oep::connection::{Connection, MessageTypes};
+use order::{OrderState, OrderType};
+use utils::config;
+
+use latency::Latencies;
+use orders::{OrderGenerator, OrderProfile};
+
+mod latency;
+mod orders;
+
+/// How long the acks of the last orders are waited for
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where and how the sessions log in, the session ids going up from
+/// first_session_id
+#[derive(Debug, Clone)]
+struct GatewayConfig {
+    address: String,
+    port: u16,
+    username: String,
+    password: String,
+    participant: u64,
+    gateway_id: u8,
+    first_session_id: u32,
+}
+
+/// What a session measured
+#[derive(Debug, Default)]
+struct SessionResult {
+    sent: usize,
+    rejected: usize,
+    // sent, but never acked
+    lost: usize,
+    latencies: Latencies,
+}
+
+/// Times the order of @message, if it is the first execution report of an
+/// order of @in_flight
+fn acknowledge(
+    message: MessageTypes,
+    in_flight: &mut HashMap<u64, Instant>,
+    result: &mut SessionResult,
+) {
+    let MessageTypes::ExecutionReport(report) = message else {
+        return;
+    };
+    let Some(sent_at) = in_flight.remove(&report.get_submitted_order_id()) else {
+        // a passive fill, or the report of an order already acked
+        return;
+    };
+    result.latencies.record(sent_at.elapsed());
+    if OrderState::from(report.state) == OrderState::Rejected {
+        result.rejected += 1;
+    }
+}
+
+/// Logs in session @index and sends @rate orders per second for @duration,
+/// timing each of them up to its first execution report
+fn run_session(
+    gateway: &GatewayConfig,
+    index: u32,
+    mut orders: OrderGenerator,
+    rate: u64,
+    duration: Duration,
+) -> Result<SessionResult> {
+    let mut connection = Connection::default();
+    // the resting orders don't pile up from one run to the next
+    connection.set_cancel_on_disconnect(true);
+    connection.connect(&gateway.address, gateway.port)?;
+    connection.login(
+        gateway.participant,
+        gateway.first_session_id + index,
+        gateway.gateway_id,
+        &gateway.username,
+        &gateway.password,
+    )?;
+    connection.wait_for_login(Some(5000))?;
+
+    let mut result = SessionResult::default();
+    // by client order id, until acked
+    let mut in_flight: HashMap<u64, Instant> = HashMap::new();
+
+    let interval = Duration::from_secs(1) / rate.max(1) as u32;
+    let start = Instant::now();
+    let mut next_send = start;
+    while next_send < start + duration {
+        // the acks are timestamped as they come, while waiting for the next order
+        while let Some(message) =
+            connection.recv_message(next_send.saturating_duration_since(Instant::now()))
+        {
+            acknowledge(message, &mut in_flight, &mut result);
+        }
+        let order = orders.next_order();
+        in_flight.insert(order.client_order_id, Instant::now());
+        connection.send_message(MessageTypes::NewOrder(order))?;
+        result.sent += 1;
+        next_send += interval;
+    }
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    while !in_flight.is_empty() {
+        let Some(message) =
+            connection.recv_message(deadline.saturating_duration_since(Instant::now()))
+        else {
+            break;
+        };
+        acknowledge(message, &mut in_flight, &mut result);
+    }
+    result.lost = in_flight.len();
+    Ok(result)
+}
+
+fn print_report(mut total: SessionResult, sessions: u32, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    println!(
+        "{} sessions, {} orders sent in {:.1}s: {:.0} orders/s, {:.0} acks/s",
+        sessions,
+        total.sent,
+        seconds,
+        total.sent as f64 / seconds,
+        total.latencies.count() as f64 / seconds
+    );
+    println!(
+        "{} acked, {} rejected, {} without an ack",
+        total.latencies.count(),
+        total.rejected,
+        total.lost
+    );
+    let Some(mean) = total.latencies.mean() else {
+        return;
+    };
+    println!("latency mean: {:?}", mean);
+    for percent in [50.0, 90.0, 99.0, 99.9, 100.0] {
+        println!(
+            "latency p{}: {:?}",
+            percent,
+            total.latencies.percentile(percent).unwrap()
+        );
+    }
+}
+
+fn main() -> Result<()> {
+    println!("Loading configuration file");
+    let mut config = Ini::new();
+    let config_map = config
+        .load("loadgen.ini")
+        .expect("Unable to load the configuration file");
+    let string = |section: &str, key: &str| config::get_config_string(&config_map, section, key);
+    let optional = |key: &str, default: u64| {
+        config::get_optional_config_string(&config_map, "load", key)
+            .map(|v| {
+                v.parse::<u64>()
+                    .unwrap_or_else(|_| panic!("{key} must be an integer"))
+            })
+            .unwrap_or(default)
+    };
+
+    let gateway = GatewayConfig {
+        address: string("gateway", "address"),
+        port: string("gateway", "port")
+            .parse()
+            .expect("Gateway port must be an u16"),
+        username: string("gateway", "username"),
+        password: string("gateway", "password"),
+        participant: string("gateway", "participant")
+            .parse()
+            .expect("Gateway participant must be an u64"),
+        gateway_id: string("gateway", "gateway_id")
+            .parse()
+            .expect("Gateway gateway_id must be an u8"),
+        first_session_id: string("gateway", "first_session_id")
+            .parse()
+            .expect("Gateway first_session_id must be an u32"),
+    };
+    let books = string("load", "books")
+        .split(',')
+        .map(|b| b.trim().parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .expect("books must be a list of book ids");
+    if books.is_empty() {
+        bail!("No books to send orders to");
+    }
+    let profile = OrderProfile {
+        books,
+        mid_price: optional("mid_price", 1000),
+        spread: optional("spread_ticks", 10),
+        tick_size: optional("tick_size", 1),
+        max_quantity: optional("max_quantity", 100),
+        order_type: match config::get_optional_config_string(&config_map, "load", "order_type")
+            .as_deref()
+        {
+            None | Some("day") => OrderType::Day,
+            Some("ioc") => OrderType::FillAndKill,
+            Some(other) => bail!("Unknown order type {other}"),
+        },
+    };
+    let sessions = optional("sessions", 1) as u32;
+    let rate = optional("rate", 100);
+    let duration = Duration::from_secs(optional("duration_s", 10));
+    let seed = optional(
+        "seed",
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_nanos() as u64,
+    );
+
+    println!("Starting {sessions} sessions, {rate} orders/s each, for {duration:?}");
+    let start = Instant::now();
+    let handles: Vec<_> = (0..sessions)
+        .map(|index| {
+            let gateway = gateway.clone();
+            let orders = OrderGenerator::new(
+                profile.clone(),
+                seed.wrapping_add(index as u64),
+                gateway.participant,
+                gateway.gateway_id,
+                gateway.first_session_id + index,
+            );
+            thread::spawn(move || run_session(&gateway, index, orders, rate, duration))
+        })
+        .collect();
+
+    let mut total = SessionResult::default();
+    for (index, handle) in handles.into_iter().enumerate() {
+        match handle.join().expect("Session thread panicked") {
+            Ok(result) => {
+                total.sent += result.sent;
+                total.rejected += result.rejected;
+                total.lost += result.lost;
+                total.latencies.merge(result.latencies);
+            }
+            Err(e) => eprintln!("Session {index} failed: {e}"),
+        }
+    }
+    print_report(total, sessions, start.elapsed());
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use oep::neworder::NewOrder;
+use order::{OrderType, Side};
+
+/// What the random orders are made of
+#[derive(Debug, Clone)]
+pub struct OrderProfile {
+    pub books: Vec<u64>,
+    // the prices are drawn around it, up to spread ticks away
+    pub mid_price: u64,
+    pub spread: u64,
+    pub tick_size: u64,
+    // from 1 to max_quantity
+    pub max_quantity: u64,
+    pub order_type: OrderType,
+}
+
+/// Draws random new orders for a session, from a xorshift generator: good
+/// enough for a load, and the same orders for the same seed
+#[derive(Debug)]
+pub struct OrderGenerator {
+    profile: OrderProfile,
+    state: u64,
+    participant: u64,
+    gateway_id: u8,
+    session_id: u32,
+    next_client_order_id: u64,
+}
+
+impl OrderGenerator {
+    pub fn new(
+        profile: OrderProfile,
+        seed: u64,
+        participant: u64,
+        gateway_id: u8,
+        session_id: u32,
+    ) -> Self {
+        Self {
+            profile,
+            // xorshift never leaves 0
+            state: seed.max(1),
+            participant,
+            gateway_id,
+            session_id,
+            next_client_order_id: 1,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A number from 0 to @n - 1
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n.max(1)
+    }
+
+    pub fn next_order(&mut self) -> NewOrder {
+        let book = self.below(self.profile.books.len() as u64) as usize;
+        let book_id = self.profile.books[book];
+        let side = match self.below(2) {
+            0 => Side::Bid,
+            _ => Side::Ask,
+        };
+        let ticks = self.below(2 * self.profile.spread + 1);
+        let price = (self.profile.mid_price + ticks * self.profile.tick_size)
+            .saturating_sub(self.profile.spread * self.profile.tick_size)
+            .max(self.profile.tick_size);
+        let quantity = 1 + self.below(self.profile.max_quantity);
+        let client_order_id = self.next_client_order_id;
+        self.next_client_order_id += 1;
+        NewOrder {
+            client_order_id,
+            participant: self.participant,
+            book_id,
+            quantity,
+            price,
+            order_type: self.profile.order_type.into(),
+            side: side.into(),
+            gateway_id: self.gateway_id,
+            session_id: self.session_id,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use order::OrderType;
+
+    use super::{OrderGenerator, OrderProfile};
+
+    fn profile() -> OrderProfile {
+        OrderProfile {
+            books: vec![1, 2, 3],
+            mid_price: 1000,
+            spread: 5,
+            tick_size: 10,
+            max_quantity: 100,
+            order_type: OrderType::FillAndKill,
+        }
+    }
+
+    #[test]
+    fn random_orders() {
+        let mut target = OrderGenerator::new(profile(), 42, 666, 1, 1000);
+        let orders: Vec<_> = (0..1000).map(|_| target.next_order()).collect();
+        for (i, order) in orders.iter().enumerate() {
+            assert_eq!(i as u64 + 1, { order.client_order_id });
+            assert!([1, 2, 3].contains(&{ order.book_id }));
+            assert!((950..=1050).contains(&{ order.price }));
+            assert_eq!(0, { order.price } % 10);
+            assert!((1..=100).contains(&{ order.quantity }));
+            assert_eq!(OrderType::FillAndKill, { order.order_type }.into());
+            assert_eq!(
+                (666, 1, 1000),
+                ({ order.participant }, order.gateway_id, {
+                    order.session_id
+                })
+            );
+        }
+        // both sides, all the books
+        assert!(orders.iter().any(|o| o.side == 0) && orders.iter().any(|o| o.side == 1));
+        assert!(orders.iter().any(|o| o.book_id == 3));
+
+        // the same orders for the same seed
+        let mut again = OrderGenerator::new(profile(), 42, 666, 1, 1000);
+        assert!(orders
+            .iter()
+            .all(|o| { again.next_order().price } == { o.price }));
+    }
+}
@@ -0,0 +1,76 @@
+//! The arithmetic on the prices and the quantities of a market
+//!
+//! Both can take any u64 value, the scaled prices being large (see
+//! instruments::price). What doesn't fit is an error, never a wrap: the
+//! callers decide whether it saturates or refuses the order.
+
+use std::{error::Error, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticError {
+    Overflow,
+    Underflow,
+}
+
+impl fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overflow => write!(f, "Overflow"),
+            Self::Underflow => write!(f, "Underflow"),
+        }
+    }
+}
+
+impl Error for ArithmeticError {}
+
+pub fn checked_add(a: u64, b: u64) -> Result<u64, ArithmeticError> {
+    a.checked_add(b).ok_or(ArithmeticError::Overflow)
+}
+
+pub fn checked_sub(a: u64, b: u64) -> Result<u64, ArithmeticError> {
+    a.checked_sub(b).ok_or(ArithmeticError::Underflow)
+}
+
+/// The sum of @values, e.g. the quantities of several price levels
+pub fn checked_sum(values: impl IntoIterator<Item = u64>) -> Result<u64, ArithmeticError> {
+    values.into_iter().try_fold(0, checked_add)
+}
+
+/// @percentage percent of @value, rounded down and computed in 128 bits
+pub fn percentage_of(value: u64, percentage: u64) -> Result<u64, ArithmeticError> {
+    u64::try_from(value as u128 * percentage as u128 / 100).map_err(|_| ArithmeticError::Overflow)
+}
+
+/// The midpoint of @bid and @ask, rounded down. Always fits
+pub fn midpoint(bid: u64, ask: u64) -> u64 {
+    ((bid as u128 + ask as u128) / 2) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::{checked_add, checked_sub, checked_sum, midpoint, percentage_of, ArithmeticError};
+
+    #[test]
+    fn extremes() {
+        assert_eq!(Ok(u64::MAX), checked_add(u64::MAX - 1, 1));
+        assert_eq!(Err(ArithmeticError::Overflow), checked_add(u64::MAX, 1));
+        assert_eq!(Ok(0), checked_sub(u64::MAX, u64::MAX));
+        assert_eq!(Err(ArithmeticError::Underflow), checked_sub(0, 1));
+        assert_eq!(Ok(u64::MAX), checked_sum([u64::MAX / 2, u64::MAX / 2, 1]));
+        assert_eq!(
+            Err(ArithmeticError::Overflow),
+            checked_sum([u64::MAX / 2, u64::MAX / 2, 2])
+        );
+        assert_eq!(Ok(0), checked_sum([]));
+
+        assert_eq!(Ok(90), percentage_of(100, 90));
+        assert_eq!(Ok(u64::MAX / 10 * 9), percentage_of(u64::MAX / 10, 900));
+        assert_eq!(Ok(u64::MAX), percentage_of(u64::MAX, 100));
+        assert_eq!(Err(ArithmeticError::Overflow), percentage_of(u64::MAX, 101));
+
+        assert_eq!(u64::MAX - 1, midpoint(u64::MAX - 2, u64::MAX));
+        assert_eq!(u64::MAX, midpoint(u64::MAX, u64::MAX));
+        assert_eq!(1002, midpoint(1000, 1005));
+        assert_eq!("Overflow", ArithmeticError::Overflow.to_string());
+    }
+}
@@ -0,0 +1,86 @@
+//! The price bands, where the orders are accepted around a reference price
+//!
+//! The reference is the midpoint of the book when both sides have orders.
+//! Otherwise it is the previous close of the instrument, or the last price
+//! traded in the session if there's no previous close.
+
+use std::str::FromStr;
+
+use crate::arith;
+
+/// What happens to the orders of a book without a reference price: one side
+/// empty, no previous close and nothing traded yet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WithoutReference {
+    // no bands to check the orders against, they all go in
+    #[default]
+    Accept,
+    Reject,
+}
+
+impl FromStr for WithoutReference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" => Ok(Self::Accept),
+            "reject" => Ok(Self::Reject),
+            other => Err(format!(
+                "Unknown behavior without a reference price {other}"
+            )),
+        }
+    }
+}
+
+/// Whether @price is within @bands percent of @reference. Bands of 0 are
+/// no bands at all, and the ones over 100 are taken as 100
+pub(crate) fn within_bands(price: u64, reference: u64, bands: u8) -> bool {
+    if bands == 0 {
+        return true;
+    }
+    let bands = bands.min(100) as u128;
+    let (price, reference) = (price as u128, reference as u128);
+    price * 100 >= reference * (100 - bands) && price * 100 <= reference * (100 + bands)
+}
+
+/// Whether @price is no further than @percentage percent from @reference,
+/// the limits rounded down, as for the daily variation and the volatility
+/// interruption. An upper limit past u64::MAX is no limit at all
+pub(crate) fn within_percentage(price: u64, reference: u64, percentage: u8) -> bool {
+    let percentage = percentage as u64;
+    // never over @reference, so it always fits
+    let low = arith::percentage_of(reference, 100 - percentage.min(100)).unwrap_or(reference);
+    price >= low
+        && arith::percentage_of(reference, 100 + percentage).map_or(true, |high| price <= high)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{within_bands, within_percentage, WithoutReference};
+
+    #[test]
+    fn bands() {
+        assert!(within_bands(90, 100, 10));
+        assert!(within_bands(110, 100, 10));
+        assert!(!within_bands(89, 100, 10));
+        assert!(!within_bands(111, 100, 10));
+        // no bands
+        assert!(within_bands(1, 100, 0));
+        // as wide as they go, without wrapping
+        assert!(within_bands(0, 100, 200));
+        assert!(within_bands(200, 100, 200));
+        assert!(!within_bands(201, 100, 200));
+        assert!(within_bands(u64::MAX, u64::MAX, 10));
+
+        assert!(within_percentage(90, 100, 10));
+        assert!(!within_percentage(111, 100, 10));
+        assert!(within_percentage(100, 100, 0));
+        assert!(!within_percentage(101, 100, 0));
+        assert!(within_percentage(u64::MAX, u64::MAX - 1, 10));
+        assert!(!within_percentage(u64::MAX, u64::MAX / 2, 10));
+        assert!(within_percentage(u64::MAX, u64::MAX, 0));
+
+        assert_eq!(Ok(WithoutReference::Reject), "reject".parse());
+        assert!("maybe".parse::<WithoutReference>().is_err());
+    }
+}
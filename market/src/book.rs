@@ -0,0 +1,240 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use order::{Order, Side};
+
+use crate::arith;
+
+/// One side of the order book
+///
+/// The orders are grouped in price levels, each level being a FIFO queue
+/// sorted by the order sequence. An index from the order id to its level
+/// and sequence keeps the lookups, cancels and modifies sub-linear.
+/// Another index, from the participant and the client order id to the order
+/// ids, resolves the orders known to the participant by its own id only.
+/// The price, the sequence, the participant and the client order id of an
+/// order must not be changed while in the book.
+#[derive(Debug, Clone)]
+pub(crate) struct BookSide {
+    side: Side,
+    levels: BTreeMap<u64, VecDeque<Order>>,
+    // order id -> (price of the level holding that order, order sequence)
+    index: HashMap<u64, (u64, u64)>,
+    // (participant, client order id) -> order ids, in insertion order. Not
+    // unique, nothing stopping a participant from reusing its client order ids
+    client_index: HashMap<(u64, u64), Vec<u64>>,
+}
+
+impl BookSide {
+    pub(crate) fn new(side: Side) -> Self {
+        Self {
+            side,
+            levels: BTreeMap::new(),
+            index: HashMap::new(),
+            client_index: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    fn best_level(&self) -> Option<(&u64, &VecDeque<Order>)> {
+        match self.side {
+            Side::Bid => self.levels.iter().next_back(),
+            Side::Ask => self.levels.iter().next(),
+        }
+    }
+
+    /// The order with the highest priority on this side
+    pub(crate) fn best(&self) -> Option<&Order> {
+        self.best_level().and_then(|(_, level)| level.front())
+    }
+
+    /// Inserts the order in its price level, behind the orders with an earlier sequence
+    pub(crate) fn insert(&mut self, order: Order) {
+        self.index
+            .insert(order.get_id(), (order.price, order.get_sequence()));
+        if order.client_order_id != 0 {
+            self.client_index
+                .entry((order.participant, order.client_order_id))
+                .or_default()
+                .push(order.get_id());
+        }
+        let level = self.levels.entry(order.price).or_default();
+        match level.back() {
+            Some(last) if last.get_sequence() > order.get_sequence() => {
+                let pos = level.partition_point(|o| o.get_sequence() < order.get_sequence());
+                level.insert(pos, order);
+            }
+            _ => level.push_back(order),
+        }
+    }
+
+    fn position(&self, order_id: u64) -> Option<(u64, usize)> {
+        let (price, sequence) = *self.index.get(&order_id)?;
+        let pos = self
+            .levels
+            .get(&price)?
+            .binary_search_by_key(&sequence, |o| o.get_sequence())
+            .ok()?;
+        Some((price, pos))
+    }
+
+    pub(crate) fn get(&self, order_id: u64) -> Option<&Order> {
+        let (price, pos) = self.position(order_id)?;
+        self.levels.get(&price)?.get(pos)
+    }
+
+    pub(crate) fn get_mut(&mut self, order_id: u64) -> Option<&mut Order> {
+        let (price, pos) = self.position(order_id)?;
+        self.levels.get_mut(&price)?.get_mut(pos)
+    }
+
+    pub(crate) fn remove(&mut self, order_id: u64) -> Option<Order> {
+        let (price, pos) = self.position(order_id)?;
+        let level = self.levels.get_mut(&price)?;
+        let order = level.remove(pos);
+        if level.is_empty() {
+            self.levels.remove(&price);
+        }
+        self.index.remove(&order_id);
+        if let Some(order) = &order {
+            self.forget_client_order_id(order);
+        }
+        order
+    }
+
+    fn forget_client_order_id(&mut self, order: &Order) {
+        let key = (order.participant, order.client_order_id);
+        if let Some(ids) = self.client_index.get_mut(&key) {
+            ids.retain(|id| *id != order.get_id());
+            if ids.is_empty() {
+                self.client_index.remove(&key);
+            }
+        }
+    }
+
+    /// The ids of the orders of @participant carrying @client_order_id, more
+    /// than one if the participant reused it
+    pub(crate) fn ids_of_client_order(&self, participant: u64, client_order_id: u64) -> &[u64] {
+        self.client_index
+            .get(&(participant, client_order_id))
+            .map_or(&[], |ids| ids.as_slice())
+    }
+
+    /// Trades @quantity out of the best order, which is removed once fully filled.
+    /// Returns the best order, as left after the fill. None if the side is
+    /// empty, or if the best order has less than @quantity
+    pub(crate) fn fill_best(&mut self, quantity: u64) -> Option<Order> {
+        let price = *self.best_level()?.0;
+        let level = self.levels.get_mut(&price)?;
+        let best = level.front_mut()?;
+        best.quantity = arith::checked_sub(best.quantity, quantity).ok()?;
+        let filled = best.clone();
+        if filled.quantity == 0 {
+            self.remove(filled.get_id());
+        }
+        Some(filled)
+    }
+
+    /// All the orders, in priority order
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = &Order> + '_> {
+        match self.side {
+            Side::Bid => Box::new(self.levels.values().rev().flatten()),
+            Side::Ask => Box::new(self.levels.values().flatten()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, RwLock};
+
+    use instruments::instrument::{Instrument, InstrumentType};
+    use order::{Order, OrderType, Side};
+
+    use super::BookSide;
+
+    fn order(id: u64, sequence: u64, price: u64, side: Side) -> Order {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let mut o = Order::new(1000, i, price, 100, side, OrderType::Day).with_session(1, 1);
+        o.set_id(id);
+        o.set_sequence(sequence);
+        o
+    }
+
+    #[test]
+    fn priority_order() {
+        let mut bids = BookSide::new(Side::Bid);
+        bids.insert(order(1, 1, 100, Side::Bid));
+        bids.insert(order(2, 2, 101, Side::Bid));
+        bids.insert(order(3, 4, 100, Side::Bid));
+        // out of sequence insert still goes in front of the later orders
+        bids.insert(order(4, 3, 100, Side::Bid));
+
+        assert_eq!(4, bids.iter().count());
+        assert_eq!(2, bids.best().unwrap().get_id());
+        assert_eq!(
+            vec![2, 1, 4, 3],
+            bids.iter().map(|o| o.get_id()).collect::<Vec<_>>()
+        );
+
+        let mut asks = BookSide::new(Side::Ask);
+        asks.insert(order(1, 1, 101, Side::Ask));
+        asks.insert(order(2, 2, 100, Side::Ask));
+        assert_eq!(
+            vec![2, 1],
+            asks.iter().map(|o| o.get_id()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn remove_and_fill() {
+        let mut asks = BookSide::new(Side::Ask);
+        asks.insert(order(1, 1, 100, Side::Ask));
+        asks.insert(order(2, 2, 100, Side::Ask));
+        asks.insert(order(3, 3, 101, Side::Ask));
+
+        assert_eq!(2, asks.remove(2).unwrap().get_id());
+        assert!(asks.remove(2).is_none());
+        assert!(asks.get(2).is_none());
+        asks.get_mut(3).unwrap().quantity = 10;
+
+        assert_eq!(40, asks.fill_best(60).unwrap().quantity);
+        assert_eq!(1, asks.best().unwrap().get_id());
+        assert_eq!(0, asks.fill_best(40).unwrap().quantity);
+        // the level is gone, the next one is the best one now
+        assert_eq!(3, asks.best().unwrap().get_id());
+        assert_eq!(1, asks.iter().count());
+
+        asks.remove(3);
+        assert!(asks.is_empty());
+        assert!(asks.best().is_none());
+    }
+
+    #[test]
+    fn client_order_ids() {
+        let mut bids = BookSide::new(Side::Bid);
+        let mut first = order(1, 1, 100, Side::Bid);
+        first.client_order_id = 77;
+        let mut second = order(2, 2, 101, Side::Bid);
+        second.client_order_id = 77;
+        // without a client order id
+        bids.insert(order(3, 3, 100, Side::Bid));
+        bids.insert(first);
+        bids.insert(second);
+
+        assert_eq!([1, 2], bids.ids_of_client_order(1000, 77));
+        assert!(bids.ids_of_client_order(1001, 77).is_empty());
+        assert!(bids.ids_of_client_order(1000, 0).is_empty());
+
+        bids.remove(1);
+        assert_eq!([2], bids.ids_of_client_order(1000, 77));
+        // fully filled
+        assert_eq!(0, bids.fill_best(100).unwrap().quantity);
+        assert!(bids.ids_of_client_order(1000, 77).is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+//! What a market holds, for the operators debugging it
+//!
+//! Unlike the state saved for a restart, the snapshot is meant to be looked
+//! at: the orders are kept whole, the statistics and the counters of the
+//! market are given as they are, and what the market hands out and forgets
+//! is counted rather than listed.
+
+use instruments::instrument::InstrumentState;
+use order::{Order, Side};
+
+use crate::{statistics::SessionStatistics, Market};
+
+/// The book, the statistics and the counters of a market at one point in time
+#[derive(Debug, Clone)]
+pub struct DebugSnapshot {
+    pub book_id: u64,
+    pub name: String,
+    pub state: InstrumentState,
+    // in priority order
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+    // waiting for their trigger, in arrival order
+    pub stops: Vec<Order>,
+    pub statistics: SessionStatistics,
+    pub reference_price: u64,
+    // what the price bands are around, see Market::get_band_reference
+    pub band_reference: Option<u64>,
+    // end of the volatility halt, unix timestamp in seconds, 0 if none
+    pub halted_until: u64,
+    // the last order id handed out by the market
+    pub order_id: u64,
+    // the last time priority given
+    pub sequence: u64,
+    pub trade_id: u64,
+    // the trades of the session, kept for them to be busted
+    pub day_trades: usize,
+    // the last quotes, by participant, whether still in the book or not
+    pub quotes: usize,
+    // by participant
+    pub exposure_blocks: Vec<(u64, Side)>,
+    // not taken yet by the engine
+    pub passive_fills: usize,
+    pub trade_captures: usize,
+}
+
+impl Market {
+    /// The book, the statistics and the counters of the market, as they are now
+    pub fn to_debug_snapshot(&self) -> DebugSnapshot {
+        let instrument = self.instrument.read().unwrap();
+        let mut exposure_blocks: Vec<(u64, Side)> = self
+            .exposure_blocks
+            .iter()
+            .map(|(participant, side)| (*participant, *side))
+            .collect();
+        exposure_blocks.sort_by_key(|(participant, _)| *participant);
+        DebugSnapshot {
+            book_id: instrument.get_id(),
+            name: instrument.get_name().to_string(),
+            state: instrument.get_state(),
+            bids: self.bids.iter().cloned().collect(),
+            asks: self.asks.iter().cloned().collect(),
+            stops: self.stops.clone(),
+            statistics: self.statistics.clone(),
+            reference_price: self.reference_price,
+            band_reference: self.get_band_reference(),
+            halted_until: self.halted_until,
+            order_id: self.order_id,
+            sequence: self.sequence,
+            trade_id: self.trade_id,
+            day_trades: self.day_trades.len(),
+            quotes: self.quotes.len(),
+            exposure_blocks,
+            passive_fills: self.passive_fills.len(),
+            trade_captures: self.trade_captures.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use disseminator::mockdisseminator::MockDisseminator;
+    use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+    use order::{Order, OrderType, Side};
+
+    use crate::{orderid::OrderIdGenerator, Market};
+
+    fn order(i: &Arc<RwLock<Instrument>>, participant: u64, price: u64, side: Side) -> Order {
+        Order::new(participant, i.clone(), price, 100, side, OrderType::Day).with_session(1, 2)
+    }
+
+    #[test]
+    fn debug_snapshot() {
+        let i = Arc::new(RwLock::new(Instrument::new(
+            500,
+            "TEST",
+            InstrumentType::Share,
+            InstrumentState::Trading,
+            10,
+            20,
+        )));
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        let snapshot = target.to_debug_snapshot();
+        assert_eq!(500, snapshot.book_id);
+        assert_eq!("TEST", snapshot.name);
+        assert_eq!(InstrumentState::Trading, snapshot.state);
+        assert!(snapshot.bids.is_empty() && snapshot.asks.is_empty());
+        assert_eq!(None, snapshot.band_reference);
+
+        target.add_order(order(&i, 1, 100, Side::Bid));
+        let (_, better_id) = target.add_order(order(&i, 2, 101, Side::Bid));
+        target.add_order(order(&i, 3, 103, Side::Ask));
+        // trades half of the best bid
+        let mut o = order(&i, 4, 101, Side::Ask);
+        o.quantity = 50;
+        target.add_order(o);
+        let mut stop = order(&i, 5, 99, Side::Ask);
+        stop.order_type = OrderType::StopLoss;
+        stop.stop_price = 99;
+        target.add_order(stop);
+        target.set_exposure_block(7, Some(Side::Ask));
+        target.set_exposure_block(6, Some(Side::Bid));
+
+        let snapshot = target.to_debug_snapshot();
+        // in priority order
+        assert_eq!(
+            vec![(101, 50), (100, 100)],
+            snapshot
+                .bids
+                .iter()
+                .map(|o| (o.price, o.quantity))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(better_id, snapshot.bids[0].get_id());
+        assert_eq!(1, snapshot.asks.len());
+        assert_eq!(1, snapshot.stops.len());
+        assert_eq!(99, snapshot.stops[0].stop_price);
+        assert_eq!(101, snapshot.statistics.last);
+        assert_eq!(50, snapshot.statistics.volume);
+        assert_eq!(1, snapshot.statistics.trade_count);
+        assert_eq!(Some(102), snapshot.band_reference);
+        assert_eq!(target.get_order_id(), snapshot.order_id);
+        assert_eq!(1, snapshot.trade_id);
+        assert_eq!(1, snapshot.day_trades);
+        assert_eq!(
+            vec![(6, Side::Bid), (7, Side::Ask)],
+            snapshot.exposure_blocks
+        );
+        // until the engine takes them
+        assert_eq!(1, snapshot.passive_fills);
+        assert_eq!(1, snapshot.trade_captures);
+        target.take_passive_fills();
+        target.take_trade_captures();
+        let snapshot = target.to_debug_snapshot();
+        assert_eq!((0, 0), (snapshot.passive_fills, snapshot.trade_captures));
+    }
+}
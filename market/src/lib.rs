@@ -1,22 +1,170 @@
-use std::{cell::RefCell, collections::VecDeque, rc::Rc};
-
-use disseminator::disseminator::Disseminator;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bands::WithoutReference;
+use book::BookSide;
+use orderid::OrderIdGenerator;
+use statistics::SessionStatistics;
+use volatility::{RollingReference, VolatilityConfig};
+
+use disseminator::{
+    checksum::{aggregate_levels, BookChecksum, BOOK_CHECKSUM_DEPTH},
+    disseminator::Disseminator,
+    error::DisseminateError,
+    snapshot::SnapshotHeader,
+};
 use instruments::instrument::{Instrument, InstrumentState};
+use oep::{
+    auctioninfo::AuctionInfo,
+    delisting::InstrumentDelisted,
+    eodsummary::EodSummary,
+    segmentstate::SegmentState,
+    statechange::{InstrumentStateChange, StateChangeReason},
+    statistics::Statistics,
+    trade::{Trade, NO_AGGRESSOR},
+    tradebust::TradeBust,
+    tradecapture::TradeCapture,
+};
 use order::{Order, OrderState, OrderType, Side};
 
+pub mod arith;
+pub mod bands;
+mod book;
+pub mod debug;
+pub mod orderid;
+mod state;
+pub mod statistics;
+pub mod volatility;
+
+/// A resting order touched by a trade, to be reported to its owner
 #[derive(Debug, Clone)]
-pub struct Market {
-    instrument: Rc<RefCell<Instrument>>,
-    bids: VecDeque<Order>,
-    asks: VecDeque<Order>,
-    order_id: u64,
-    disseminator: Rc<RefCell<dyn Disseminator>>,
+pub struct PassiveFill {
+    // the resting order, as left after the trade
+    pub order: Order,
+    pub price: u64,
+    pub quantity: u64,
+    // the incoming order that traded against it, 0 for an auction uncross
+    pub aggressor_id: u64,
+    // the trade, as published on the feed and captured for the clearing
+    pub trade_id: u64,
+}
+
+/// The order on one side of a trade, and where its session is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TradeParty {
+    pub order_id: u64,
+    pub participant: u64,
+    pub gateway_id: u8,
+    pub session_id: u32,
+}
+
+impl TradeParty {
+    fn of(order: &Order) -> Self {
+        Self {
+            order_id: order.get_id(),
+            participant: order.participant,
+            gateway_id: order.gateway_id,
+            session_id: order.session_id,
+        }
+    }
+}
+
+/// A trade of the current session, kept for it to be busted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DayTrade {
+    pub trade_id: u64,
+    pub price: u64,
+    pub quantity: u64,
+    pub timestamp: u64,
+    pub bid: TradeParty,
+    pub ask: TradeParty,
+}
+
+/// What goes on the feed after the matching, in the order it happened
+#[derive(Debug, Clone)]
+pub enum FeedEvent {
+    // an order entering the book: an order not trading on its way in, or
+    // the next peak of an iceberg order
+    NewOrder(Order),
+    Trade(Trade),
+    // the market moved away from the state given, for the reason given
+    StateChange(InstrumentState, StateChangeReason),
+    AuctionInfo(AuctionInfo),
+}
+
+/// What an order did to the market, see Market::enter_order
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    pub state: OrderState,
+    // 0 if rejected before getting one
+    pub order_id: u64,
+    // in trade order, those of the stop orders it triggered included
+    pub trades: Vec<Trade>,
+    // what is left of the order in the book, or waiting for its trigger
+    pub resting: Option<Order>,
+}
 
-    bids_ops: u32,
-    asks_ops: u32,
+/// Why a client order id doesn't resolve to a single resting order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientOrderIdError {
+    // no resting order of the participant carries it
+    Unknown,
+    // several do, the participant having reused it
+    Ambiguous,
 }
 
-const REARRANGE_THRESHOLD: u32 = 10000;
+#[derive(Debug, Clone)]
+pub struct Market {
+    instrument: Arc<RwLock<Instrument>>,
+    bids: BookSide,
+    asks: BookSide,
+    // stop orders waiting for their trigger, in arrival order
+    stops: Vec<Order>,
+    // resting orders traded since the last take_passive_fills
+    passive_fills: Vec<PassiveFill>,
+    // trades since the last take_trade_captures, for the clearing
+    trade_captures: Vec<TradeCapture>,
+    // day orders cancelled by the close since the last take_closed_out_orders
+    closed_out_orders: Vec<Order>,
+    // what the matching left for the feed, see flush_feed_events
+    feed_events: Vec<FeedEvent>,
+    // the trades of the session, in trade order, for bust_trade
+    day_trades: Vec<DayTrade>,
+    // participant -> the side it can't add risk on, as decided by the clearing
+    exposure_blocks: HashMap<u64, Side>,
+    // participant -> the ids of the bid and the ask of its quote, 0 for none.
+    // They stay after the orders left the book, as traded or cancelled
+    quotes: HashMap<u64, (u64, u64)>,
+    // shared by all the markets of the engine
+    order_ids: Arc<Mutex<OrderIdGenerator>>,
+    // the last id handed out by this market
+    order_id: u64,
+    // time priority: every order entering (or re-entering) the book gets the next one
+    sequence: u64,
+    // id of the last trade of the book, never reset
+    trade_id: u64,
+    disseminator: Arc<Mutex<dyn Disseminator>>,
+
+    // daily statistics, reset when the market closes
+    statistics: SessionStatistics,
+    // the first traded price of the day, the daily variation is measured against it
+    reference_price: u64,
+    // the instrument state when last seen by the market, used to detect transitions
+    known_state: InstrumentState,
+
+    volatility: VolatilityConfig,
+    rolling_reference: RollingReference,
+    // what the price bands do without a reference price
+    without_reference: WithoutReference,
+    // end of the current volatility halt, unix timestamp in seconds
+    halted_until: u64,
+    // whether the last order or quote was rejected by the price bands, until
+    // take_band_reject
+    band_rejected: bool,
+}
 
 /// Structure that holds a market for a certain instrument
 ///
@@ -25,242 +173,1018 @@ const REARRANGE_THRESHOLD: u32 = 10000;
 /// Arguments should be an order structure as defined in the order library
 ///
 /// Other notable functions:
-/// @publish_snapshot -> publishes instrument and market snapshot on the disseminator socket
+/// @enter_order -> adds an order, telling what it traded and what is left of it
+/// @get_order -> looks up a resting order by its id
+/// @executable_quantity -> how much an order could trade right away, before it does
+/// @replace_order -> cancels an order and enters another one in its place
+/// @quote -> enters the bid and the ask of a market maker in place of its previous ones
+/// @take_passive_fills -> the resting orders traded since the last call
+/// @take_trade_captures -> the trades since the last call, with their participants
+/// @take_closed_out_orders -> the day orders cancelled by the close since the last call
+/// @take_band_reject -> whether the price bands rejected an order since the last call
+/// @bust_trade -> cancels a trade of the session after the fact
+/// @set_exposure_block -> stops a participant from adding risk on one side of the book
+/// @expire_orders -> cancels the GoodTillDate orders that reached their expiry
+/// @publish_snapshot -> publishes instrument and market snapshot on the snapshot disseminator
+/// @publish_checksum -> publishes the checksum of the top levels of the book
+/// @get_statistics -> open, high, low, last, volume and VWAP of the session
+/// @instrument_updated -> reacts to instrument state changes, e.g. closes the market
+/// @change_state -> changes the instrument state and reacts to it
+/// @resume_trading -> ends the volatility halt once its cooldown is over
+/// @get_auction_info -> indicative price and volume while in auction
+/// @uncross -> matches the crossing orders at the end of an auction
 /// @get_state -> returns the instrument state that is implicitely assumed to also be the market state
+/// @encode_state -> saves the market, for @decode_state to restore it after a restart
+/// @to_debug_snapshot -> the book, statistics and counters of the market, for the operators
 impl Market {
     /// Create a market for a certain instrument and attaches a feed disseminator
+    /// The order ids are taken from @order_ids, shared with the other markets
     pub fn new(
-        instrument: Rc<RefCell<Instrument>>,
-        disseminator: Rc<RefCell<dyn Disseminator>>,
+        instrument: Arc<RwLock<Instrument>>,
+        disseminator: Arc<Mutex<dyn Disseminator>>,
+        order_ids: Arc<Mutex<OrderIdGenerator>>,
     ) -> Self {
+        let known_state = instrument.read().unwrap().get_state();
         Self {
-            instrument: instrument,
-            bids: VecDeque::new(),
-            asks: VecDeque::new(),
+            instrument,
+            bids: BookSide::new(Side::Bid),
+            asks: BookSide::new(Side::Ask),
+            stops: vec![],
+            passive_fills: vec![],
+            trade_captures: vec![],
+            closed_out_orders: vec![],
+            feed_events: vec![],
+            day_trades: vec![],
+            exposure_blocks: HashMap::new(),
+            quotes: HashMap::new(),
+            order_ids,
             order_id: 0,
+            sequence: 0,
+            trade_id: 0,
             disseminator: disseminator.clone(),
-            bids_ops: 0,
-            asks_ops: 0,
+            statistics: SessionStatistics::default(),
+            reference_price: 0,
+            known_state,
+            volatility: VolatilityConfig::default(),
+            rolling_reference: RollingReference::default(),
+            without_reference: WithoutReference::default(),
+            halted_until: 0,
+            band_rejected: false,
         }
     }
 
-    /// Close the market and cancel all the orders
-    pub fn close(&mut self) {
+    /// Enables the volatility interruption, disabled by default
+    pub fn set_volatility_config(&mut self, config: VolatilityConfig) {
+        self.volatility = config;
+    }
+
+    /// The orders are accepted without a reference price to check them
+    /// against, unless told otherwise
+    pub fn set_without_reference(&mut self, without_reference: WithoutReference) {
+        self.without_reference = without_reference;
+    }
+
+    /// Close the market and cancel all the orders, except the GoodTillCancel
+    /// and GoodTillDate ones, kept for take_closed_out_orders
+    /// Publishes and returns the end of day summary
+    pub fn close(&mut self) -> EodSummary {
         self.instrument
-            .borrow_mut()
+            .write()
+            .unwrap()
             .set_state(InstrumentState::Closed);
-        let mut iter = self.bids.iter().chain(self.asks.iter()).into_iter();
-        while let Some(o) = iter.next() {
-            self.publish_cancel_order(&o);
+        self.known_state = InstrumentState::Closed;
+
+        let summary = self.get_eod_summary();
+        // only the GoodTillCancel and GoodTillDate orders stay in the book overnight
+        let day_orders: Vec<Order> = self
+            .bids
+            .iter()
+            .chain(self.asks.iter())
+            .filter(|o| !o.is_persistent())
+            .cloned()
+            .collect();
+        for o in &day_orders {
+            self.remove_and_publish_cancel(o);
+        }
+        self.closed_out_orders.extend(day_orders);
+        self.stops.clear();
+
+        if self
+            .disseminator
+            .lock()
+            .unwrap()
+            .send_eod_summary(&summary)
+            .is_err()
+        {
+            eprintln!(
+                "Error publishing the EOD summary for {}",
+                self.instrument.read().unwrap().get_id()
+            );
+        }
+        // the session is over, the final statistics carry the closing price
+        self.statistics.close = summary.closing_price;
+        if self.publish_statistics().is_err() {
+            eprintln!(
+                "Error publishing the statistics for {}",
+                self.instrument.read().unwrap().get_id()
+            );
+        }
+        // the reference of the bands for the next day, until the clearing
+        // tells otherwise
+        if summary.closing_price > 0 {
+            self.instrument
+                .write()
+                .unwrap()
+                .set_previous_close(summary.closing_price);
+        }
+        self.statistics = SessionStatistics::default();
+        self.day_trades.clear();
+        self.reference_price = 0;
+        self.rolling_reference.clear();
+        self.halted_until = 0;
+        summary
+    }
+
+    /// Closes the market for good, once its instrument is deleted: unlike
+    /// close, the GoodTillCancel and GoodTillDate orders go too, and the
+    /// delisting of the book is published last
+    ///
+    /// Returns: the end of day summary if the market was still open, and the
+    /// orders cancelled besides the day ones
+    pub fn delete(&mut self) -> (Option<EodSummary>, Vec<Order>) {
+        let summary = (self.get_state() != InstrumentState::Closed).then(|| self.close());
+        let orders: Vec<Order> = self
+            .bids
+            .iter()
+            .chain(self.asks.iter())
+            .chain(self.stops.iter())
+            .cloned()
+            .collect();
+        for o in &orders {
+            self.remove_and_publish_cancel(o);
+        }
+        self.stops.clear();
+        self.publish_delisting();
+        (summary, orders)
+    }
+
+    /// Closes the market for good, its contract having expired: the state
+    /// change is published with its own reason, then all the orders are
+    /// cancelled as when the instrument is deleted
+    ///
+    /// Returns: the end of day summary if the market was still open, and the
+    /// orders cancelled besides the day ones
+    pub fn expire(&mut self) -> (Option<EodSummary>, Vec<Order>) {
+        let summary = (self.get_state() != InstrumentState::Closed).then(|| {
+            self.set_state_and_publish(InstrumentState::Closed, StateChangeReason::Expiry);
+            self.close()
+        });
+        let (_, orders) = self.delete();
+        (summary, orders)
+    }
+
+    /// Closes the market, the engine stopping: the state change is published
+    /// with its own reason, then the market is closed as by the schedule
+    ///
+    /// Returns: the end of day summary and the day orders cancelled, nothing
+    /// if the market was already closed
+    pub fn shut_down(&mut self) -> Option<(EodSummary, Vec<Order>)> {
+        if self.get_state() == InstrumentState::Closed {
+            return None;
+        }
+        self.set_state_and_publish(InstrumentState::Closed, StateChangeReason::Shutdown);
+        let summary = self.close();
+        Some((summary, self.take_closed_out_orders()))
+    }
+
+    /// Summary of the trading day so far
+    /// If nothing traded, the closing price falls back to the book midpoint
+    pub fn get_eod_summary(&self) -> EodSummary {
+        let closing_price = match (
+            self.statistics.trade_count,
+            self.bids.best(),
+            self.asks.best(),
+        ) {
+            (0, Some(bid), Some(ask)) => arith::midpoint(bid.price, ask.price),
+            (0, _, _) => 0,
+            _ => self.statistics.last,
+        };
+        EodSummary {
+            book_id: self.instrument.read().unwrap().get_id(),
+            closing_price,
+            volume: self.statistics.volume,
+            trade_count: self.statistics.trade_count,
+        }
+    }
+
+    /// The disseminator already kept what it could for a retry, so the message
+    /// is lost. The feed consumers notice it through the book checksums
+    fn report_failure(result: Result<usize, DisseminateError>, what: &str) {
+        if let Err(e) = result {
+            eprintln!("Error publishing the {what}: {e}");
         }
-        self.bids.clear();
-        self.asks.clear();
     }
 
     fn publish_cancel_order(&self, o: &Order) {
-        self.disseminator.borrow().send_cancel_order(o).unwrap();
+        Self::report_failure(
+            self.disseminator.lock().unwrap().send_cancel_order(o),
+            "cancel",
+        );
     }
 
     fn publish_new_order(&self, o: &Order) {
-        self.disseminator.borrow().send_new_order(o).unwrap();
+        Self::report_failure(
+            self.disseminator.lock().unwrap().send_new_order(o),
+            "new order",
+        );
     }
 
     fn publish_modified_order(&self, o: &Order) {
-        self.disseminator.borrow().send_modify_order(o).unwrap();
+        Self::report_failure(
+            self.disseminator.lock().unwrap().send_modify_order(o),
+            "modify",
+        );
+    }
+
+    fn publish_trade(&self, trade: &Trade) {
+        Self::report_failure(self.disseminator.lock().unwrap().send_trade(trade), "trade");
     }
 
-    fn publish_trade(&self, trade: &oep::trade::Trade) {
-        self.disseminator.borrow().send_trade(trade).unwrap();
+    /// Publishes what the matching left for the feed, in order
+    ///
+    /// Returns: what was published
+    fn flush_feed_events(&mut self) -> Vec<FeedEvent> {
+        let events = std::mem::take(&mut self.feed_events);
+        for event in &events {
+            match event {
+                FeedEvent::NewOrder(o) => self.publish_new_order(o),
+                FeedEvent::Trade(trade) => self.publish_trade(trade),
+                FeedEvent::StateChange(previous, reason) => {
+                    self.publish_state_change(*previous, *reason)
+                }
+                FeedEvent::AuctionInfo(info) => Self::report_failure(
+                    self.disseminator.lock().unwrap().send_auction_info(info),
+                    "auction info",
+                ),
+            }
+        }
+        events
+    }
+
+    /// The checks an order has to pass regardless of the state of the book
+    fn is_well_formed(o: &Order) -> bool {
+        if o.quantity == 0
+            || (o.price == 0
+                && o.order_type != OrderType::Market
+                && o.order_type != OrderType::StopLoss)
+        {
+            return false;
+        }
+
+        // a stop order can't be activated without a stop price
+        if o.is_stop() && o.stop_price == 0 {
+            return false;
+        }
+
+        // a GoodTillDate order needs to know when to go away
+        !(o.order_type == OrderType::GoodTillDate && o.expiry == 0)
+    }
+
+    /// Prices have to be multiples of the tick size and quantities multiples
+    /// of the round lot of the instrument. A zero price (e.g. market orders) is on any tick
+    fn is_on_grid(&self, o: &Order) -> bool {
+        let instrument = self.instrument.read().unwrap();
+        o.price.is_multiple_of(instrument.get_tick_size())
+            && o.stop_price.is_multiple_of(instrument.get_tick_size())
+            && o.quantity.is_multiple_of(instrument.get_round_lot())
+    }
+
+    /// New orders, replaces and modifies are rejected while closed or halted,
+    /// only the cancels get through
+    fn accepts_orders(&self) -> bool {
+        !matches!(
+            self.instrument.read().unwrap().get_state(),
+            InstrumentState::Closed | InstrumentState::Halted
+        )
+    }
+
+    /// Adds @o to the market, see enter_order
+    ///
+    /// Returns: the state and the id of the order
+    pub fn add_order(&mut self, o: Order) -> (OrderState, u64) {
+        let result = self.enter_order(o);
+        (result.state, result.order_id)
+    }
+
+    /// Matches @o against the book and posts what is left of it, then
+    /// publishes what happened on the feed
+    ///
+    /// Returns: the state of the order, its trades and what is left of it
+    pub fn enter_order(&mut self, o: Order) -> MatchResult {
+        let (state, order_id) = self.execute_order(o);
+        let trades = self
+            .flush_feed_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                FeedEvent::Trade(trade) => Some(trade),
+                _ => None,
+            })
+            .collect();
+        let resting = match state {
+            OrderState::Inserted | OrderState::PartiallyTraded => self.get_order(order_id).cloned(),
+            _ => None,
+        };
+        MatchResult {
+            state,
+            order_id,
+            trades,
+            resting,
+        }
     }
 
-    pub fn add_order(&mut self, mut o: Order) -> (OrderState, u64) {
+    /// Everything enter_order does but publishing, what goes on the feed
+    /// being left in feed_events
+    fn execute_order(&mut self, mut o: Order) -> (OrderState, u64) {
         assert_eq!(
-            self.instrument.borrow().get_id(),
-            o.instrument.borrow().get_id()
+            self.instrument.read().unwrap().get_id(),
+            o.instrument.read().unwrap().get_id()
         );
-        if InstrumentState::Closed == self.instrument.borrow().get_state() {
+        if !self.accepts_orders() {
             return (OrderState::Rejected, 0);
         }
 
-        self.order_id += 1;
+        self.order_id = self.order_ids.lock().unwrap().next_id();
         o.set_id(self.order_id); // FIXME: who is using this, since the value is not returned?
+        o.set_sequence(self.next_sequence());
 
-        if o.quantity == 0 || (o.price == 0 && o.order_type != OrderType::Market) {
+        if !Self::is_well_formed(&o) || !self.is_on_grid(&o) {
             return (OrderState::Rejected, 0);
         }
 
-        // Check out of bands
-        if o.order_type != OrderType::Market && self.bids.len() > 0 && self.asks.len() > 0 {
-            let midpoint =
-                (self.bids.front().unwrap().price + self.asks.front().unwrap().price) / 2;
-            if o.price
-                < midpoint * (100 - self.instrument.borrow().get_percentage_bands() as u64) / 100
-                || o.price
-                    > midpoint * (100 + self.instrument.borrow().get_percentage_bands() as u64)
-                        / 100
-            {
-                return (OrderState::Rejected, 0);
+        let state = self.instrument.read().unwrap().get_state();
+        let in_auction = InstrumentState::Auction == state;
+        if o.is_stop() {
+            if state != InstrumentState::Trading || !o.is_triggered(self.statistics.last) {
+                // kept aside, and out of the feed, until a trade reaches the stop price
+                let id = o.get_id();
+                self.stops.push(o);
+                return (OrderState::Inserted, id);
             }
+            Self::activate_stop(&mut o);
         }
 
-        if self.bids_ops > REARRANGE_THRESHOLD {
-            self.bids.make_contiguous();
-            self.bids_ops = 0;
-        } else if self.asks_ops > REARRANGE_THRESHOLD {
-            self.asks.make_contiguous();
-            self.asks_ops = 0;
-        }
-
-        macro_rules! trade_and_add {
-            ($list:expr, $comp:ident, $order:expr) => {{
-                let mut trades = 0;
-                while $order.quantity > 0
-                    && $list.len() > 0
-                    && ($order.price.$comp(&$list.front().unwrap().price)
-                        || $order.order_type == OrderType::Market)
-                {
-                    // trade
-                    let trade_volume =
-                        std::cmp::min($list.front().unwrap().quantity, $order.quantity);
-                    $order.quantity -= trade_volume;
-                    // passive order massaging
-                    let mut p = $list.pop_front().unwrap();
-                    p.quantity -= trade_volume;
-                    if p.quantity > 0 {
-                        $list.push_front(p.clone());
-                    }
-                    // publish it
-                    self.publish_trade(&oep::trade::Trade {
-                        bid_order_id: if $order.side == Side::Bid {
-                            $order.get_id()
-                        } else {
-                            p.get_id()
-                        },
-                        ask_order_id: if $order.side == Side::Ask {
-                            $order.get_id()
-                        } else {
-                            p.get_id()
-                        },
-                        price: p.price,
-                        quantity: trade_volume,
-                    });
-                    trades += 1;
-                }
-                if $order.quantity == 0 {
-                    // aggressor fully traded
-                    return (OrderState::Traded, $order.get_id());
-                }
-                match $order.order_type {
-                    OrderType::FillAndKill | OrderType::FillOrKill => {
-                        return (OrderState::Cancelled, $order.get_id())
-                    }
-                    OrderType::Market => match trades {
-                        0 => return (OrderState::Cancelled, $order.get_id()),
-                        _ => return (OrderState::Traded, $order.get_id()),
-                    },
-                    _ => {
-                        self.insert_into_right_position(&$order);
-                        if trades > 0 {
-                            return (OrderState::PartiallyTraded, $order.get_id());
-                        } else {
-                            self.publish_new_order(&$order);
-                            return (OrderState::Inserted, $order.get_id());
-                        }
-                    }
-                }
-            }};
+        if in_auction {
+            return self.accumulate_order(o);
         }
 
-        match o.side {
-            Side::Bid => {
-                self.bids_ops += 1;
-                trade_and_add!(self.asks, ge, o)
+        // nothing trades before the opening, only the orders adding liquidity get in
+        if InstrumentState::PreOpen == state && self.crosses_the_book(&o) {
+            return (OrderState::Rejected, 0);
+        }
+
+        // rejected by match_order, the stops it triggers aside
+        self.band_rejected = self.is_out_of_bands(&o);
+        let trade_count = self.statistics.trade_count;
+        let result = self.match_order(o);
+        if self.statistics.trade_count != trade_count {
+            self.trigger_stop_orders();
+        }
+        result
+    }
+
+    /// Adds the order to the book of a market in auction, without matching it
+    fn accumulate_order(&mut self, mut o: Order) -> (OrderState, u64) {
+        match o.order_type {
+            // nothing to trade against before the uncross
+            OrderType::Market | OrderType::FillAndKill | OrderType::FillOrKill => {
+                (OrderState::Rejected, 0)
             }
-            Side::Ask => {
-                self.asks_ops += 1;
-                trade_and_add!(self.bids, le, o)
+            _ => {
+                o.hide_quantity();
+                self.insert_into_right_position(&o);
+                self.feed_events.push(FeedEvent::NewOrder(o.clone()));
+                self.queue_auction_info();
+                (OrderState::Inserted, o.get_id())
             }
         }
     }
 
-    fn insert_into_right_position(&mut self, o: &Order) {
-        macro_rules! fit_into_position {
-            ($list:expr, $comp:ident, $order:expr) => {{
-                let mut pos = 0;
-                for b in &$list {
-                    if b.price.$comp(&$order.price) {
-                        break;
-                    }
-                    pos += 1;
+    /// A StopLoss order becomes a market order once triggered, a StopLimit a limit one
+    fn activate_stop(o: &mut Order) {
+        o.order_type = match o.order_type {
+            OrderType::StopLoss => OrderType::Market,
+            _ => OrderType::Day,
+        };
+    }
+
+    /// Activates, in their arrival order, the stop orders triggered by the last
+    /// traded price. Their own trades might trigger further stop orders
+    fn trigger_stop_orders(&mut self) {
+        // a trade might have halted the market
+        while InstrumentState::Trading == self.instrument.read().unwrap().get_state() {
+            let last_trade_price = self.statistics.last;
+            let Some(pos) = self
+                .stops
+                .iter()
+                .position(|o| o.is_triggered(last_trade_price))
+            else {
+                break;
+            };
+            let mut o = self.stops.remove(pos);
+            Self::activate_stop(&mut o);
+            // the time priority starts at the activation
+            o.set_sequence(self.next_sequence());
+            self.match_order(o);
+        }
+    }
+
+    /// The side of the book an order on @side trades against
+    fn opposite(&self, side: Side) -> &BookSide {
+        match side {
+            Side::Bid => &self.asks,
+            Side::Ask => &self.bids,
+        }
+    }
+
+    fn opposite_mut(&mut self, side: Side) -> &mut BookSide {
+        match side {
+            Side::Bid => &mut self.asks,
+            Side::Ask => &mut self.bids,
+        }
+    }
+
+    /// Whether the order would trade against the opposite side of the book
+    fn crosses_the_book(&self, o: &Order) -> bool {
+        self.opposite(o.side)
+            .best()
+            .is_some_and(|best| match (o.order_type, o.side) {
+                (OrderType::Market, _) => true,
+                (_, Side::Bid) => o.price >= best.price,
+                (_, Side::Ask) => o.price <= best.price,
+            })
+    }
+
+    /// How much an order on @side at @price (any price if 0) would trade if it
+    /// came in now: the opposite orders it crosses, hidden quantities included,
+    /// up to the first price halting or interrupting the trading
+    pub fn executable_quantity(&self, side: Side, price: u64) -> u64 {
+        let now = now_nanos();
+        let mut reference_price = self.reference_price;
+        let mut volatility_reference = match self.volatility.is_enabled() {
+            true => self.rolling_reference.peek(now, self.volatility.window),
+            false => 0,
+        };
+        let mut quantity = 0;
+        for o in self.opposite(side).iter() {
+            let crosses = match side {
+                _ if price == 0 => true,
+                Side::Bid => price >= o.price,
+                Side::Ask => price <= o.price,
+            };
+            if !crosses || !self.is_within_variation_of(o.price, reference_price) {
+                break;
+            }
+            if volatility_reference != 0
+                && !bands::within_percentage(
+                    o.price,
+                    volatility_reference,
+                    self.volatility.percentage,
+                )
+            {
+                break;
+            }
+            // the first trade of the day sets the references of the next ones
+            if reference_price == 0 {
+                reference_price = o.price;
+            }
+            if self.volatility.is_enabled() && volatility_reference == 0 {
+                volatility_reference = o.price;
+            }
+            quantity += o.quantity + o.hidden_quantity;
+        }
+        quantity
+    }
+
+    /// The price the bands are around: the midpoint of the book, else the
+    /// previous close, else the last traded price. None if there's none
+    pub fn get_band_reference(&self) -> Option<u64> {
+        if let (Some(bid), Some(ask)) = (self.bids.best(), self.asks.best()) {
+            return Some(arith::midpoint(bid.price, ask.price));
+        }
+        [
+            self.instrument.read().unwrap().get_previous_close(),
+            self.statistics.last,
+        ]
+        .into_iter()
+        .find(|p| *p > 0)
+    }
+
+    /// Whether the price of @o is too far from the band reference
+    fn is_out_of_bands(&self, o: &Order) -> bool {
+        if o.order_type == OrderType::Market {
+            return false;
+        }
+        match self.get_band_reference() {
+            Some(reference) => !bands::within_bands(
+                o.price,
+                reference,
+                self.instrument.read().unwrap().get_percentage_bands(),
+            ),
+            None => self.without_reference == WithoutReference::Reject,
+        }
+    }
+
+    /// Matches the order against the opposite side and posts what is left of it
+    fn match_order(&mut self, mut o: Order) -> (OrderState, u64) {
+        if self.is_out_of_bands(&o) {
+            return (OrderState::Rejected, 0);
+        }
+
+        // post only, the order is rejected rather than taking liquidity
+        if o.order_type == OrderType::PostOrKill && self.crosses_the_book(&o) {
+            return (OrderState::Rejected, 0);
+        }
+
+        // all or nothing, without leaving a partial fill on the feed
+        if o.order_type == OrderType::FillOrKill
+            && self.executable_quantity(o.side, o.price) < o.quantity
+        {
+            return (OrderState::Cancelled, o.get_id());
+        }
+
+        let mut trades = 0;
+        while o.quantity > 0 && self.crosses_the_book(&o) {
+            let best_price = self.opposite(o.side).best().unwrap().price;
+            if !self.is_within_variation(best_price) {
+                // the rest of the order is handled as in auction
+                self.halt();
+                break;
+            }
+            if self.breaks_volatility_limit(best_price) {
+                self.interrupt();
+                break;
+            }
+            let trade_volume =
+                std::cmp::min(self.opposite(o.side).best().unwrap().quantity, o.quantity);
+            o.quantity -= trade_volume;
+            // the resting order, as left after the trade
+            let p = self.opposite_mut(o.side).fill_best(trade_volume).unwrap();
+            let (bid, ask) = match o.side {
+                Side::Bid => (&o, &p),
+                Side::Ask => (&p, &o),
+            };
+            self.record_trade(bid, ask, p.price, trade_volume, o.side.into());
+            self.record_passive_fill(&p, p.price, trade_volume, o.get_id());
+            self.replenish_iceberg(&p);
+            trades += 1;
+        }
+        if o.quantity == 0 {
+            // aggressor fully traded
+            return (OrderState::Traded, o.get_id());
+        }
+        let state = match o.order_type {
+            OrderType::FillAndKill | OrderType::FillOrKill => OrderState::Cancelled,
+            OrderType::Market if trades == 0 => OrderState::Cancelled,
+            OrderType::Market => OrderState::Traded,
+            // the order halted the trading, no new orders get in until it resumes
+            _ if InstrumentState::Halted == self.instrument.read().unwrap().get_state() => {
+                OrderState::Cancelled
+            }
+            _ => {
+                o.hide_quantity();
+                self.insert_into_right_position(&o);
+                // the trades already told the feed about the order
+                if trades == 0 {
+                    self.feed_events.push(FeedEvent::NewOrder(o.clone()));
+                }
+                self.queue_auction_info();
+                match trades {
+                    0 => OrderState::Inserted,
+                    _ => OrderState::PartiallyTraded,
                 }
-                $list.insert(pos, $order.clone());
-            }};
+            }
+        };
+        (state, o.get_id())
+    }
+
+    /// Leaves the trade for the feed, reports it to the clearing and accounts
+    /// for it in the daily statistics
+    fn record_trade(
+        &mut self,
+        bid: &Order,
+        ask: &Order,
+        price: u64,
+        quantity: u64,
+        aggressor_side: u8,
+    ) {
+        self.trade_id += 1;
+        let timestamp = now_nanos();
+        let book_id = self.instrument.read().unwrap().get_id();
+        self.feed_events.push(FeedEvent::Trade(Trade {
+            bid_order_id: bid.get_id(),
+            ask_order_id: ask.get_id(),
+            price,
+            quantity,
+            book_id,
+            trade_id: self.trade_id,
+            timestamp,
+            aggressor_side,
+        }));
+        self.trade_captures.push(TradeCapture {
+            seq: 0,
+            book_id,
+            trade_id: self.trade_id,
+            price,
+            quantity,
+            buyer: bid.participant,
+            seller: ask.participant,
+            timestamp,
+        });
+        self.day_trades.push(DayTrade {
+            trade_id: self.trade_id,
+            price,
+            quantity,
+            timestamp,
+            bid: TradeParty::of(bid),
+            ask: TradeParty::of(ask),
+        });
+        if self.volatility.is_enabled() {
+            self.rolling_reference.add_trade(timestamp, price);
+        }
+        self.statistics.add_trade(price, quantity);
+        if self.reference_price == 0 {
+            self.reference_price = price;
+        }
+    }
+
+    /// Whether trading at @price keeps the instrument within its allowed daily variation
+    fn is_within_variation(&self, price: u64) -> bool {
+        self.is_within_variation_of(price, self.reference_price)
+    }
+
+    fn is_within_variation_of(&self, price: u64, reference_price: u64) -> bool {
+        let allowed = self
+            .instrument
+            .read()
+            .unwrap()
+            .get_percentage_variation_allowed();
+        reference_price == 0 || bands::within_percentage(price, reference_price, allowed)
+    }
+
+    /// Stops the continuous trading by moving the market into auction,
+    /// leaving the new instrument state for the feed
+    fn halt(&mut self) {
+        let previous = self.set_state(InstrumentState::Auction);
+        self.feed_events.push(FeedEvent::StateChange(
+            previous,
+            StateChangeReason::PriceVariation,
+        ));
+    }
+
+    /// A state change decided by the market itself, rather than by the clearing
+    ///
+    /// Returns: the previous state
+    fn set_state(&mut self, state: InstrumentState) -> InstrumentState {
+        let previous = self.instrument.read().unwrap().get_state();
+        self.instrument.write().unwrap().set_state(state);
+        self.known_state = state;
+        previous
+    }
+
+    /// see set_state, the new state being published right away
+    fn set_state_and_publish(&mut self, state: InstrumentState, reason: StateChangeReason) {
+        let previous = self.set_state(state);
+        self.publish_state_change(previous, reason);
+    }
+
+    /// A state change decided by the engine, e.g. by the trading schedule
+    /// Publishes the new state, then closes or uncrosses the market as needed
+    ///
+    /// Returns: the end of day summary, if the market got closed
+    pub fn change_state(&mut self, state: InstrumentState) -> Option<EodSummary> {
+        if state == self.instrument.read().unwrap().get_state() {
+            return None;
+        }
+        self.instrument.write().unwrap().set_state(state);
+        self.state_updated(StateChangeReason::Schedule)
+    }
+
+    /// Publishes the instrument in its new state, followed by the state change
+    /// from @previous
+    fn publish_state_change(&self, previous: InstrumentState, reason: StateChangeReason) {
+        self.publish_instrument_info();
+        let instrument = self.instrument.read().unwrap();
+        let change = InstrumentStateChange {
+            book_id: instrument.get_id(),
+            timestamp: now_nanos(),
+            previous_state: previous.into(),
+            state: instrument.get_state().into(),
+            reason: reason.into(),
+        };
+        if self
+            .disseminator
+            .lock()
+            .unwrap()
+            .send_state_change(&change)
+            .is_err()
+        {
+            eprintln!(
+                "Error publishing the state change of {}",
+                instrument.get_id()
+            );
+        }
+    }
+
+    fn publish_delisting(&self) {
+        let book_id = self.instrument.read().unwrap().get_id();
+        let delisting = InstrumentDelisted {
+            book_id,
+            timestamp: now_nanos(),
+        };
+        if self
+            .disseminator
+            .lock()
+            .unwrap()
+            .send_delisting(&delisting)
+            .is_err()
+        {
+            eprintln!("Error publishing the delisting of {}", book_id);
+        }
+    }
+
+    fn publish_instrument_info(&self) {
+        if self
+            .disseminator
+            .lock()
+            .unwrap()
+            .send_instrument_info(&self.instrument.read().unwrap())
+            .is_err()
+        {
+            eprintln!(
+                "Error publishing the state of {}",
+                self.instrument.read().unwrap().get_id()
+            );
+        }
+    }
+
+    /// Whether trading at @price deviates more than allowed from the rolling reference price
+    fn breaks_volatility_limit(&mut self, price: u64) -> bool {
+        if !self.volatility.is_enabled() {
+            return false;
+        }
+        let reference = self
+            .rolling_reference
+            .price(now_nanos(), self.volatility.window);
+        reference != 0 && !bands::within_percentage(price, reference, self.volatility.percentage)
+    }
+
+    /// Halts the trading for the cooldown of the volatility interruption,
+    /// leaving the new instrument state for the feed
+    fn interrupt(&mut self) {
+        self.halted_until = now_nanos() / 1_000_000_000 + self.volatility.cooldown.as_secs();
+        let previous = self.set_state(InstrumentState::Halted);
+        self.feed_events.push(FeedEvent::StateChange(
+            previous,
+            StateChangeReason::Volatility,
+        ));
+    }
+
+    /// Goes back to trading if the volatility halt is over at @now (unix timestamp, in seconds)
+    ///
+    /// Returns: whether the trading resumed
+    pub fn resume_trading(&mut self, now: u64) -> bool {
+        if InstrumentState::Halted != self.instrument.read().unwrap().get_state()
+            || now < self.halted_until
+        {
+            return false;
+        }
+        self.set_state_and_publish(InstrumentState::Trading, StateChangeReason::Resumed);
+        true
+    }
+
+    /// Keeps the fill of the resting order @filled by the trade just recorded
+    fn record_passive_fill(
+        &mut self,
+        filled: &Order,
+        price: u64,
+        quantity: u64,
+        aggressor_id: u64,
+    ) {
+        self.passive_fills.push(PassiveFill {
+            order: filled.clone(),
+            price,
+            quantity,
+            aggressor_id,
+            trade_id: self.trade_id,
+        });
+    }
+
+    /// Returns the resting orders traded since the last call, in trade order
+    pub fn take_passive_fills(&mut self) -> Vec<PassiveFill> {
+        std::mem::take(&mut self.passive_fills)
+    }
+
+    /// Returns the trades since the last call, to be sent to the clearing
+    pub fn take_trade_captures(&mut self) -> Vec<TradeCapture> {
+        std::mem::take(&mut self.trade_captures)
+    }
+
+    /// Returns the day orders cancelled by the closes since the last call,
+    /// for their sessions to be told
+    pub fn take_closed_out_orders(&mut self) -> Vec<Order> {
+        std::mem::take(&mut self.closed_out_orders)
+    }
+
+    /// Whether the last order or quote entered since the last call was
+    /// rejected for a price out of the bands
+    pub fn take_band_reject(&mut self) -> bool {
+        std::mem::take(&mut self.band_rejected)
+    }
+
+    /// Cancels the trade @trade_id of the session: the statistics are worked
+    /// out again without it, the bust is published on the feed along with the
+    /// new statistics, and the clearing gets the trade with the buyer and the
+    /// seller swapped, undoing the positions. The orders stay as they are
+    ///
+    /// Returns: the trade busted, None if the session had no such trade
+    pub fn bust_trade(&mut self, trade_id: u64) -> Option<DayTrade> {
+        let index = self
+            .day_trades
+            .iter()
+            .position(|t| t.trade_id == trade_id)?;
+        let busted = self.day_trades.remove(index);
+        self.statistics = SessionStatistics::default();
+        for t in &self.day_trades {
+            self.statistics.add_trade(t.price, t.quantity);
+        }
+
+        let book_id = self.instrument.read().unwrap().get_id();
+        let timestamp = now_nanos();
+        Self::report_failure(
+            self.disseminator
+                .lock()
+                .unwrap()
+                .send_trade_bust(&TradeBust {
+                    book_id,
+                    trade_id,
+                    price: busted.price,
+                    quantity: busted.quantity,
+                    timestamp,
+                }),
+            "trade bust",
+        );
+        if self.publish_statistics().is_err() {
+            eprintln!("Error publishing the statistics for {book_id}");
+        }
+        self.trade_captures.push(TradeCapture {
+            seq: 0,
+            book_id,
+            trade_id,
+            price: busted.price,
+            quantity: busted.quantity,
+            buyer: busted.ask.participant,
+            seller: busted.bid.participant,
+            timestamp,
+        });
+        Some(busted)
+    }
+
+    /// The trades of the session, in trade order
+    pub fn get_day_trades(&self) -> &[DayTrade] {
+        &self.day_trades
+    }
+
+    /// Stops @participant from adding risk on @side, or lifts its block with None
+    pub fn set_exposure_block(&mut self, participant: u64, side: Option<Side>) {
+        match side {
+            Some(side) => self.exposure_blocks.insert(participant, side),
+            None => self.exposure_blocks.remove(&participant),
+        };
+    }
+
+    pub fn is_exposure_blocked(&self, participant: u64, side: Side) -> bool {
+        self.exposure_blocks.get(&participant) == Some(&side)
+    }
+
+    /// Shows the next peak of a fully traded iceberg order, at the back of its price level
+    fn replenish_iceberg(&mut self, filled: &Order) {
+        if filled.quantity == 0 && filled.hidden_quantity > 0 {
+            let mut peak = filled.clone();
+            peak.replenish();
+            peak.set_sequence(self.next_sequence());
+            self.insert_into_right_position(&peak);
+            self.feed_events.push(FeedEvent::NewOrder(peak));
         }
+    }
+
+    fn next_sequence(&mut self) -> u64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    /// Inserts the order behind all the orders with a better price, or with
+    /// the same price and an earlier sequence (strict price-time priority)
+    fn insert_into_right_position(&mut self, o: &Order) {
         match o.side {
-            Side::Bid => fit_into_position!(self.bids, lt, o),
-            Side::Ask => fit_into_position!(self.asks, gt, o),
+            Side::Bid => self.bids.insert(o.clone()),
+            Side::Ask => self.asks.insert(o.clone()),
         }
     }
 
-    pub fn modify_order(&mut self, o: Order) -> (OrderState, u64) {
+    pub fn modify_order(&mut self, mut o: Order) -> (OrderState, u64) {
         assert_eq!(
-            o.instrument.borrow().get_id(),
-            self.instrument.borrow().get_id()
+            o.instrument.read().unwrap().get_id(),
+            self.instrument.read().unwrap().get_id()
         );
 
         // run some basic checks
-        if o.quantity == 0 {
+        if !self.accepts_orders() || o.quantity == 0 || !self.is_on_grid(&o) {
             return (OrderState::Rejected, 0);
         }
 
         macro_rules! remove_and_add {
             ($side: expr) => {
-                match $side.iter().position(|x| {
+                match $side.get(o.get_id()).filter(|x| {
                     x.participant == o.participant
                         && x.gateway_id == o.gateway_id
                         && x.session_id == o.session_id
-                        && x.get_id() == o.get_id()
                         && x.order_type == o.order_type
                 }) {
-                    Some(index) => {
-                        if o.price == $side[index].price {
-                            // change the quantity only
-                            $side[index].quantity = o.quantity;
-                            self.publish_modified_order(&$side[index]);
-                            (OrderState::Modified, $side[index].get_id())
+                    Some(existing) => {
+                        // the quantity of an iceberg order includes its hidden part
+                        if o.price == existing.price
+                            && o.quantity
+                                <= existing.quantity.saturating_add(existing.hidden_quantity)
+                        {
+                            // decreasing the quantity keeps the queue position
+                            let modified = $side.get_mut(o.get_id()).unwrap();
+                            modified.hidden_quantity = o.quantity.saturating_sub(modified.quantity);
+                            modified.quantity = std::cmp::min(modified.quantity, o.quantity);
+                            let modified = modified.clone();
+                            self.publish_modified_order(&modified);
+                            (OrderState::Modified, modified.get_id())
+                        } else if o.price == existing.price {
+                            // increasing it sends the order to the back of its price level
+                            let mut modified = $side.remove(o.get_id()).unwrap();
+                            modified.quantity = o.quantity;
+                            modified.hidden_quantity = 0;
+                            modified.hide_quantity();
+                            modified.set_sequence(self.next_sequence());
+                            self.insert_into_right_position(&modified);
+                            self.publish_modified_order(&modified);
+                            (OrderState::Modified, modified.get_id())
                         } else {
-                            self.publish_cancel_order(&$side[index]);
-                            $side.remove(index);
+                            let canceled_order = $side.remove(o.get_id()).unwrap();
+                            self.publish_cancel_order(&canceled_order);
+                            o.expiry = canceled_order.expiry;
+                            o.display_quantity = canceled_order.display_quantity;
+                            o.client_order_id = canceled_order.client_order_id;
                             self.add_order(o)
                         }
                     }
-                    None => return (OrderState::Rejected, 0),
+                    None => (OrderState::Rejected, 0),
                 }
             };
         }
 
-        match o.side {
+        let result = match o.side {
             Side::Bid => remove_and_add!(self.bids),
             Side::Ask => remove_and_add!(self.asks),
+        };
+        // a new price goes through add_order, which takes care of it
+        if result.0 == OrderState::Modified {
+            self.publish_auction_info();
         }
+        result
     }
 
     pub fn cancel_order(&mut self, o: &Order) -> OrderState {
         assert_eq!(
-            o.instrument.borrow().get_id(),
-            self.instrument.borrow().get_id()
+            o.instrument.read().unwrap().get_id(),
+            self.instrument.read().unwrap().get_id()
         );
+        if let Some(pos) = self.stops.iter().position(|x| {
+            x.get_id() == o.get_id()
+                && x.side == o.side
+                && x.participant == o.participant
+                && x.gateway_id == o.gateway_id
+                && x.session_id == o.session_id
+        }) {
+            // never published, so nothing to publish on cancel either
+            self.stops.remove(pos);
+            return OrderState::Cancelled;
+        }
         macro_rules! remove {
             ($side: expr) => {
-                match $side.iter().position(|x| {
+                match $side.get(o.get_id()).filter(|x| {
                     x.participant == o.participant
                         && x.gateway_id == o.gateway_id
                         && x.session_id == o.session_id
-                        && x.get_id() == o.get_id()
                 }) {
-                    Some(index) => {
-                        if let Some(canceled_order) = $side.remove(index) {
+                    Some(_) => {
+                        if let Some(canceled_order) = $side.remove(o.get_id()) {
                             self.publish_cancel_order(&canceled_order);
+                            self.publish_auction_info();
                         }
                         OrderState::Cancelled
                     }
@@ -269,61 +1193,277 @@ impl Market {
             };
         }
         match o.side {
-            Side::Bid => {
-                self.bids_ops += 1;
-                remove!(self.bids)
-            }
-            Side::Ask => {
-                self.asks_ops += 1;
-                remove!(self.asks)
-            }
+            Side::Bid => remove!(self.bids),
+            Side::Ask => remove!(self.asks),
         }
     }
 
-    #[must_use]
-    /// cancels all the standing orders for a certain (participant, gateway, session) tuple
+    /// Cancels the standing order @old and enters @new in its place, in one step.
+    /// Nothing changes if @old can't be cancelled, if @new is on the other side
+    /// or if @new would be rejected regardless of the book
     ///
-    /// Returns: a vector of tuples (order_id, book_id, side)
-    pub fn cancel_all_orders_for_session(
+    /// Returns: the state of the cancel, then the state and the id of the new order
+    pub fn replace_order(&mut self, old: &Order, new: Order) -> (OrderState, OrderState, u64) {
+        let replaceable = self.accepts_orders()
+            && new.side == old.side
+            && Self::is_well_formed(&new)
+            && self.is_on_grid(&new)
+            && self.get_order(old.get_id()).is_some_and(|resting| {
+                resting.side == old.side
+                    && resting.participant == old.participant
+                    && resting.gateway_id == old.gateway_id
+                    && resting.session_id == old.session_id
+            });
+        if !replaceable {
+            return (OrderState::Rejected, OrderState::Rejected, 0);
+        }
+
+        let cancelled = self.cancel_order(old);
+        let (state, id) = self.add_order(new);
+        (cancelled, state, id)
+    }
+
+    /// Enters the quote of @participant, @bid and @ask, in place of its previous
+    /// one on both sides in one step. A side left out pulls the previous one.
+    /// A side at the same price and no bigger keeps its queue position, as for
+    /// a modify. The quotes only add liquidity: nothing changes if a side would
+    /// cross the book or is out of the price bands, its previous quote aside,
+    /// if the bid isn't below the ask, or outside of the trading and pre-open
+    /// states
+    ///
+    /// Returns: QuoteAck and the ids of the bid and the ask, 0 for a side left
+    /// out, or Rejected
+    pub fn quote(
         &mut self,
         participant: u64,
-        gateway_id: u8,
-        session_id: u32,
-    ) -> Vec<(u64, u64, Side)> {
-        let bid_matches: Vec<Order> = self
+        bid: Option<Order>,
+        ask: Option<Order>,
+    ) -> (OrderState, u64, u64) {
+        let state = self.instrument.read().unwrap().get_state();
+        let valid = |o: &Order, side: Side| {
+            o.participant == participant
+                && o.side == side
+                && o.order_type == OrderType::PostOrKill
+                && Self::is_well_formed(o)
+                && self.is_on_grid(o)
+        };
+        if !matches!(state, InstrumentState::Trading | InstrumentState::PreOpen)
+            || !bid.as_ref().is_none_or(|o| valid(o, Side::Bid))
+            || !ask.as_ref().is_none_or(|o| valid(o, Side::Ask))
+            || matches!((&bid, &ask), (Some(bid), Some(ask)) if bid.price >= ask.price)
+        {
+            return (OrderState::Rejected, 0, 0);
+        }
+
+        // out of the book while the new quote is checked against it
+        let (old_bid, old_ask) = self.quotes.get(&participant).copied().unwrap_or_default();
+        let old_bid = self.bids.remove(old_bid);
+        let old_ask = self.asks.remove(old_ask);
+        self.band_rejected = bid
+            .iter()
+            .chain(ask.iter())
+            .any(|o| self.is_out_of_bands(o));
+        if self.band_rejected
+            || bid
+                .iter()
+                .chain(ask.iter())
+                .any(|o| self.crosses_the_book(o))
+        {
+            old_bid.into_iter().for_each(|o| self.bids.insert(o));
+            old_ask.into_iter().for_each(|o| self.asks.insert(o));
+            return (OrderState::Rejected, 0, 0);
+        }
+
+        let ids = (self.requote(old_bid, bid), self.requote(old_ask, ask));
+        match ids {
+            (0, 0) => self.quotes.remove(&participant),
+            _ => self.quotes.insert(participant, ids),
+        };
+        (OrderState::QuoteAck, ids.0, ids.1)
+    }
+
+    /// The ids of the bid and the ask of the last quote of @participant, 0 for
+    /// none, whether still in the book or not
+    pub fn get_quote(&self, participant: u64) -> (u64, u64) {
+        self.quotes.get(&participant).copied().unwrap_or_default()
+    }
+
+    #[must_use]
+    /// pulls the quote of @participant, whatever the state of the book
+    ///
+    /// Returns: a vector of tuples (order_id, book_id, side), for the sides
+    /// still in the book
+    pub fn cancel_quote(&mut self, participant: u64) -> Vec<(u64, u64, Side)> {
+        let Some((bid_id, ask_id)) = self.quotes.remove(&participant) else {
+            return vec![];
+        };
+        self.cancel_all_orders_matching(|o| {
+            o.participant == participant
+                && o.get_id() != 0
+                && [bid_id, ask_id].contains(&o.get_id())
+        })
+    }
+
+    /// Puts @new in the book in place of @old, a side of a quote taken out of it
+    ///
+    /// Returns: the id of the order in the book, 0 for none
+    fn requote(&mut self, old: Option<Order>, new: Option<Order>) -> u64 {
+        match (old, new) {
+            (Some(mut old), Some(new))
+                if new.price == old.price && new.quantity <= old.quantity =>
+            {
+                let modified = new.quantity != old.quantity;
+                old.quantity = new.quantity;
+                (old.gateway_id, old.session_id) = (new.gateway_id, new.session_id);
+                self.insert_into_right_position(&old);
+                if modified {
+                    self.publish_modified_order(&old);
+                }
+                old.get_id()
+            }
+            (old, Some(mut new)) => {
+                if let Some(old) = old {
+                    self.publish_cancel_order(&old);
+                }
+                self.order_id = self.order_ids.lock().unwrap().next_id();
+                new.set_id(self.order_id);
+                new.set_sequence(self.next_sequence());
+                self.insert_into_right_position(&new);
+                self.publish_new_order(&new);
+                new.get_id()
+            }
+            (Some(old), None) => {
+                self.publish_cancel_order(&old);
+                0
+            }
+            (None, None) => 0,
+        }
+    }
+
+    #[must_use]
+    /// cancels all the standing orders for a certain (participant, gateway, session) tuple
+    ///
+    /// Returns: a vector of tuples (order_id, book_id, side)
+    pub fn cancel_all_orders_for_session(
+        &mut self,
+        participant: u64,
+        gateway_id: u8,
+        session_id: u32,
+    ) -> Vec<(u64, u64, Side)> {
+        self.cancel_all_orders_matching(|o| {
+            o.participant == participant && o.gateway_id == gateway_id && o.session_id == session_id
+        })
+    }
+
+    #[must_use]
+    /// cancels all the standing orders of a participant, regardless of the session
+    /// that entered them, optionally only the ones on a certain @side
+    ///
+    /// Returns: a vector of tuples (order_id, book_id, side)
+    pub fn cancel_all_orders_for_participant(
+        &mut self,
+        participant: u64,
+        side: Option<Side>,
+    ) -> Vec<(u64, u64, Side)> {
+        self.cancel_all_orders_matching(|o| {
+            o.participant == participant && side.is_none_or(|side| o.side == side)
+        })
+    }
+
+    /// cancels the bids, the asks and the stop orders accepted by @filter
+    fn cancel_all_orders_matching(
+        &mut self,
+        filter: impl Fn(&Order) -> bool,
+    ) -> Vec<(u64, u64, Side)> {
+        let matches: Vec<Order> = self
             .bids
             .iter()
-            .filter(|&o| {
-                o.participant == participant
-                    && o.gateway_id == gateway_id
-                    && o.session_id == session_id
-            })
-            .map(|o| o.clone())
+            .chain(self.asks.iter())
+            .chain(self.stops.iter())
+            .filter(|&o| filter(o))
+            .cloned()
             .collect();
-        let ask_matches: Vec<Order> = self
-            .asks
+
+        for o in matches.iter() {
+            self.cancel_order(o);
+        }
+
+        matches
             .iter()
-            .filter(|&o| {
-                o.participant == participant
-                    && o.gateway_id == gateway_id
-                    && o.session_id == session_id
-            })
-            .map(|o| o.clone())
+            .map(|o| (o.get_id(), o.instrument.read().unwrap().get_id(), o.side))
+            .collect()
+    }
+
+    /// Cancels the GoodTillDate orders that expired at or before @now (unix timestamp, seconds)
+    ///
+    /// Returns: the expired orders, as they were in the book
+    pub fn expire_orders(&mut self, now: u64) -> Vec<Order> {
+        let expired: Vec<Order> = self
+            .bids
+            .iter()
+            .chain(self.asks.iter())
+            .filter(|o| o.is_expired(now))
+            .cloned()
             .collect();
+        for o in &expired {
+            self.remove_and_publish_cancel(o);
+        }
+        expired
+    }
 
-        for o in bid_matches.iter().chain(ask_matches.iter()) {
-            self.cancel_order(o);
+    fn remove_and_publish_cancel(&mut self, o: &Order) {
+        let removed = match o.side {
+            Side::Bid => self.bids.remove(o.get_id()),
+            Side::Ask => self.asks.remove(o.get_id()),
+        };
+        if let Some(canceled_order) = removed {
+            self.publish_cancel_order(&canceled_order);
         }
+    }
 
-        self.bids_ops += bid_matches.len() as u32;
-        self.asks_ops += ask_matches.len() as u32;
+    /// Looks up a resting order by its exchange order id, on both sides of the book
+    /// and among the stop orders waiting for their trigger
+    pub fn get_order(&self, order_id: u64) -> Option<&Order> {
+        self.bids
+            .get(order_id)
+            .or_else(|| self.asks.get(order_id))
+            .or_else(|| self.stops.iter().find(|o| o.get_id() == order_id))
+    }
 
-        // return both matching bids and asks
-        bid_matches
+    /// The exchange id of the resting order of @participant on @side carrying
+    /// @client_order_id, in the book or among the stop orders
+    pub fn resolve_client_order_id(
+        &self,
+        participant: u64,
+        side: Side,
+        client_order_id: u64,
+    ) -> Result<u64, ClientOrderIdError> {
+        if client_order_id == 0 {
+            return Err(ClientOrderIdError::Unknown);
+        }
+        let book = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let mut ids = book
+            .ids_of_client_order(participant, client_order_id)
             .iter()
-            .chain(ask_matches.iter())
-            .map(|o| (o.get_id(), o.instrument.borrow().get_id(), o.side.into()))
-            .collect()
+            .copied()
+            .chain(
+                self.stops
+                    .iter()
+                    .filter(|o| {
+                        o.side == side
+                            && o.participant == participant
+                            && o.client_order_id == client_order_id
+                    })
+                    .map(|o| o.get_id()),
+            );
+        match (ids.next(), ids.next()) {
+            (Some(id), None) => Ok(id),
+            (Some(_), Some(_)) => Err(ClientOrderIdError::Ambiguous),
+            (None, _) => Err(ClientOrderIdError::Unknown),
+        }
     }
 
     pub fn generate_bids(&self) -> Vec<&Order> {
@@ -337,73 +1477,293 @@ impl Market {
     #[cfg(test)]
     pub(crate) fn set_state_trading(&mut self) {
         self.instrument
-            .borrow_mut()
+            .write()
+            .unwrap()
             .set_state(InstrumentState::Trading);
     }
 
-    pub fn get_instrument(&self) -> Rc<RefCell<Instrument>> {
+    pub fn get_instrument(&self) -> Arc<RwLock<Instrument>> {
         self.instrument.clone()
     }
 
     pub fn get_state(&self) -> InstrumentState {
-        self.instrument.borrow().get_state()
+        self.instrument.read().unwrap().get_state()
+    }
+
+    pub fn get_segment(&self) -> u16 {
+        self.instrument.read().unwrap().get_segment()
     }
 
     pub fn get_order_id(&self) -> u64 {
         self.order_id
     }
 
+    pub fn get_reference_price(&self) -> u64 {
+        self.reference_price
+    }
+
     /// Publishes the state of the registered instrument and the snapshot
-    /// of the market
-    pub fn publish_snapshot(&self) -> Result<usize, std::io::Error> {
-        let mut result = self
-            .disseminator
-            .borrow()
-            .send_instrument_info(&self.instrument.borrow())?;
+    /// of the market on @snapshot_disseminator, tagged with the sequence
+    /// the incremental feed reached
+    pub fn publish_snapshot(
+        &self,
+        snapshot_disseminator: &dyn Disseminator,
+    ) -> Result<usize, DisseminateError> {
+        // what is held back for batching is already part of the book
+        self.disseminator.lock().unwrap().flush()?;
+        let (bids, asks) = (self.generate_bids(), self.generate_asks());
+        let header = SnapshotHeader {
+            book_id: self.instrument.read().unwrap().get_id(),
+            next_seq: self.disseminator.lock().unwrap().get_sequence(),
+            order_count: (bids.len() + asks.len()) as u32,
+        };
+        let mut result = snapshot_disseminator.send_snapshot_header(&header)?;
+        result += snapshot_disseminator.send_instrument_info(&self.instrument.read().unwrap())?;
+
+        for o in bids.iter().chain(asks.iter()) {
+            result += snapshot_disseminator.send_market_order(o)?;
+        }
+        snapshot_disseminator.flush()?;
 
-        for o in self
-            .generate_bids()
-            .iter()
-            .chain(self.generate_asks().iter())
+        Ok(result)
+    }
+
+    /// Checksum of the top BOOK_CHECKSUM_DEPTH levels of the book, as it should be
+    /// computed by a feed consumer out of the orders it knows about
+    pub fn get_checksum(&self) -> BookChecksum {
+        let bids = aggregate_levels(self.bids.iter().map(|o| (o.price, o.quantity)));
+        let asks = aggregate_levels(self.asks.iter().map(|o| (o.price, o.quantity)));
+        BookChecksum::new(
+            self.instrument.read().unwrap().get_id(),
+            &bids,
+            &asks,
+            BOOK_CHECKSUM_DEPTH,
+        )
+    }
+
+    pub fn publish_checksum(&self) -> Result<usize, DisseminateError> {
+        self.disseminator
+            .lock()
+            .unwrap()
+            .send_book_checksum(&self.get_checksum())
+    }
+
+    /// Open, high, low, last, volume and VWAP of the current session
+    pub fn get_statistics(&self) -> Statistics {
+        self.statistics
+            .to_message(self.instrument.read().unwrap().get_id())
+    }
+
+    pub fn publish_statistics(&self) -> Result<usize, DisseminateError> {
+        self.disseminator
+            .lock()
+            .unwrap()
+            .send_statistics(&self.get_statistics())
+    }
+
+    /// Indicative auction price and volume: the price maximizing the executable volume,
+    /// then minimizing the volume left unmatched, then the closest to the last traded price
+    /// Both are 0 if the book is not crossed
+    pub fn get_auction_info(&self) -> AuctionInfo {
+        // the hidden part of the iceberg orders takes part in the auction too
+        let bids = aggregate_levels(
+            self.bids
+                .iter()
+                .map(|o| (o.price, o.quantity.saturating_add(o.hidden_quantity))),
+        );
+        let asks = aggregate_levels(
+            self.asks
+                .iter()
+                .map(|o| (o.price, o.quantity.saturating_add(o.hidden_quantity))),
+        );
+
+        let mut best: Option<(u64, u64, u64)> = None; // (price, volume, imbalance)
+        for &(price, _) in bids.iter().chain(asks.iter()) {
+            // more than can ever be traded saturates, the volume being bound
+            // by the orders anyway
+            let demand = arith::checked_sum(bids.iter().filter(|l| l.0 >= price).map(|l| l.1))
+                .unwrap_or(u64::MAX);
+            let supply = arith::checked_sum(asks.iter().filter(|l| l.0 <= price).map(|l| l.1))
+                .unwrap_or(u64::MAX);
+            let volume = std::cmp::min(demand, supply);
+            let imbalance = demand.abs_diff(supply);
+            if volume == 0 {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some((best_price, best_volume, best_imbalance)) => {
+                    (volume, std::cmp::Reverse(imbalance))
+                        .cmp(&(best_volume, std::cmp::Reverse(best_imbalance)))
+                        .then(
+                            best_price
+                                .abs_diff(self.statistics.last)
+                                .cmp(&price.abs_diff(self.statistics.last)),
+                        )
+                        .then(best_price.cmp(&price))
+                        .is_gt()
+                }
+            };
+            if better {
+                best = Some((price, volume, imbalance));
+            }
+        }
+
+        let (price, volume, _) = best.unwrap_or_default();
+        AuctionInfo {
+            book_id: self.instrument.read().unwrap().get_id(),
+            price,
+            volume,
+        }
+    }
+
+    /// Leaves the indicative price and volume for the feed, if the market is in auction
+    fn queue_auction_info(&mut self) {
+        if InstrumentState::Auction == self.instrument.read().unwrap().get_state() {
+            let info = self.get_auction_info();
+            self.feed_events.push(FeedEvent::AuctionInfo(info));
+        }
+    }
+
+    /// Publishes the indicative price and volume, if the market is in auction
+    fn publish_auction_info(&self) {
+        if InstrumentState::Auction == self.instrument.read().unwrap().get_state()
+            && self
+                .disseminator
+                .lock()
+                .unwrap()
+                .send_auction_info(&self.get_auction_info())
+                .is_err()
         {
-            result += self.disseminator.borrow().send_market_order(o)?;
+            eprintln!("Error publishing the auction info");
         }
+    }
 
-        Ok(result)
+    /// Ends an auction by matching all the crossing orders at the equilibrium price,
+    /// in price-time priority
+    /// Publishes and returns the executed price and volume
+    pub fn uncross(&mut self) -> AuctionInfo {
+        let info = self.get_auction_info();
+        let (price, volume) = (info.price, info.volume);
+        let mut executed = 0;
+        while executed < volume && !self.bids.is_empty() && !self.asks.is_empty() {
+            let quantity = [
+                self.bids.best().unwrap().quantity,
+                self.asks.best().unwrap().quantity,
+                volume - executed,
+            ]
+            .into_iter()
+            .min()
+            .unwrap();
+            // both orders were resting in the book
+            let bid = self.bids.fill_best(quantity).unwrap();
+            let ask = self.asks.fill_best(quantity).unwrap();
+            self.record_trade(&bid, &ask, price, quantity, NO_AGGRESSOR);
+            self.record_passive_fill(&bid, price, quantity, 0);
+            self.record_passive_fill(&ask, price, quantity, 0);
+            self.replenish_iceberg(&bid);
+            self.replenish_iceberg(&ask);
+            executed += quantity;
+        }
+
+        self.feed_events.push(FeedEvent::AuctionInfo(info));
+        if executed > 0 && InstrumentState::Auction != self.instrument.read().unwrap().get_state() {
+            self.trigger_stop_orders();
+        }
+        self.flush_feed_events();
+        info
+    }
+
+    /// To be called after the instrument has been updated by the clearing
+    /// Publishes a new state, then closes the market if the instrument has
+    /// just been closed, returning the end of day summary. Uncrosses the book
+    /// when an auction ends
+    pub fn instrument_updated(&mut self) -> Option<EodSummary> {
+        self.state_updated(StateChangeReason::Clearing)
+    }
+
+    /// see instrument_updated, the state having changed because of @reason
+    fn state_updated(&mut self, reason: StateChangeReason) -> Option<EodSummary> {
+        let state = self.instrument.read().unwrap().get_state();
+        if state == self.known_state {
+            return None;
+        }
+        let previous = self.known_state;
+        self.known_state = state;
+        self.publish_state_change(previous, reason);
+        match state {
+            InstrumentState::Closed => Some(self.close()),
+            InstrumentState::Trading if previous == InstrumentState::Auction => {
+                self.uncross();
+                None
+            }
+            _ => None,
+        }
     }
+}
+
+/// Publishes on @disseminator that the instruments of @segment were put in
+/// @state because of @reason, once their markets published their own change
+pub fn publish_segment_state(
+    disseminator: &Mutex<dyn Disseminator>,
+    segment: u16,
+    state: InstrumentState,
+    reason: StateChangeReason,
+) -> Result<usize, DisseminateError> {
+    disseminator
+        .lock()
+        .unwrap()
+        .send_segment_state(&SegmentState {
+            segment,
+            timestamp: now_nanos(),
+            state: state.into(),
+            reason: reason.into(),
+        })
+}
 
-    pub fn instrument_updated(&self) {}
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
 }
 
 #[cfg(test)]
 mod test {
-    use std::{cell::RefCell, rc::Rc};
-
-    use disseminator::mockdisseminator::MockDisseminator;
+    use std::{
+        sync::{Arc, Mutex, RwLock},
+        time::Duration,
+    };
+
+    use disseminator::{
+        error::DisseminateError,
+        mockdisseminator::{FailureMode, MockDisseminator},
+        snapshot::SnapshotHeader,
+    };
     use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
 
+    use oep::statechange::StateChangeReason;
     use order::{Order, OrderState, OrderType, Side};
 
-    use super::Market;
+    use super::{
+        bands::WithoutReference, orderid::OrderIdGenerator, volatility::VolatilityConfig,
+        ClientOrderIdError, Market,
+    };
 
     #[test]
     fn order_insert() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o = Order::new(
-            1000,
+        let o = Order::new(1000, i.clone(), 123, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+
+        let mut target = Market::new(
             i.clone(),
-            123,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
         target.set_state_trading();
 
         let r = target.add_order(o);
@@ -417,31 +1777,27 @@ mod test {
         assert_eq!(Side::Bid, bids[0].side);
         assert_eq!(OrderType::Day, bids[0].order_type);
 
-        assert_eq!(500, bids[0].instrument.borrow().get_id());
+        assert_eq!(500, bids[0].instrument.read().unwrap().get_id());
         assert_eq!(
             InstrumentType::Share,
-            bids[0].instrument.borrow().get_type()
+            bids[0].instrument.read().unwrap().get_type()
         );
     }
 
     #[test]
     fn ioc_cancelled() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o = Order::new(
-            1000,
+        let o = Order::new(1000, i.clone(), 123, 100, Side::Bid, OrderType::FillOrKill)
+            .with_session(100, 2000);
+
+        let mut target = Market::new(
             i.clone(),
-            123,
-            100,
-            Side::Bid,
-            OrderType::FillOrKill,
-            100,
-            2000,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
         target.set_state_trading();
 
         let r = target.add_order(o);
@@ -451,23 +1807,76 @@ mod test {
     }
 
     #[test]
-    fn new_order_zero_quantity_rejected() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn fill_or_kill_all_or_nothing() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o = Order::new(
-            1000,
+        i.write().unwrap().set_percentage_bands(50);
+        i.write().unwrap().set_percentage_variation_allowed(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            123,
-            0,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        let order = |price, quantity, side, order_type| {
+            Order::new(1000, i.clone(), price, quantity, side, order_type).with_session(100, 2000)
+        };
+        target.add_order(order(1000, 100, Side::Ask, OrderType::Day));
+        let mut iceberg = order(1010, 300, Side::Ask, OrderType::Day);
+        iceberg.display_quantity = 100;
+        target.add_order(iceberg);
+        target.add_order(order(1200, 100, Side::Ask, OrderType::Day));
+        assert_eq!(0, target.executable_quantity(Side::Bid, 990));
+        assert_eq!(100, target.executable_quantity(Side::Bid, 1000));
+        // hidden quantities included
+        assert_eq!(400, target.executable_quantity(Side::Bid, 1100));
+        // 1200 is beyond the allowed variation from the first trade at 1000
+        assert_eq!(400, target.executable_quantity(Side::Bid, 1200));
+        assert_eq!(400, target.executable_quantity(Side::Bid, 0));
+
+        // more than there is: nothing trades, nothing is published
+        let (state, _) = target.add_order(order(1100, 401, Side::Bid, OrderType::FillOrKill));
+        assert_eq!(OrderState::Cancelled, state);
+        let (state, _) = target.add_order(order(1200, 500, Side::Bid, OrderType::FillOrKill));
+        assert_eq!(OrderState::Cancelled, state);
+        assert_eq!(0, disseminator.lock().unwrap().trades.borrow().len());
+        assert_eq!(InstrumentState::Trading, target.get_state());
+        assert_eq!(3, target.generate_asks().len());
+        assert_eq!(0, target.get_reference_price());
+
+        // a fill or kill that fits trades fully
+        let result = target.enter_order(order(1100, 250, Side::Bid, OrderType::FillOrKill));
+        assert_eq!(OrderState::Traded, result.state);
+        assert_eq!(
+            vec![(1000, 100), (1010, 100), (1010, 50)],
+            result
+                .trades
+                .iter()
+                .map(|t| (t.price, t.quantity))
+                .collect::<Vec<_>>()
         );
+        assert!(result.resting.is_none());
+        assert_eq!(150, target.executable_quantity(Side::Bid, 1100));
+    }
+
+    #[test]
+    fn new_order_zero_quantity_rejected() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let o =
+            Order::new(1000, i.clone(), 123, 0, Side::Bid, OrderType::Day).with_session(100, 2000);
 
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
         target.set_state_trading();
 
         let r = target.add_order(o);
@@ -479,33 +1888,21 @@ mod test {
 
     #[test]
     fn cross_completely() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o_passive = Order::new(
-            1000,
-            i.clone(),
-            123,
-            400,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o_aggressive = Order::new(
-            1001,
+        let o_passive = Order::new(1000, i.clone(), 123, 400, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o_aggressive = Order::new(1001, i.clone(), 123, 100, Side::Ask, OrderType::Day)
+            .with_session(101, 2001);
+
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            123,
-            100,
-            Side::Ask,
-            OrderType::Day,
-            101,
-            2001,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
         assert_eq!(OrderState::Inserted, target.add_order(o_passive).0);
@@ -524,17 +1921,17 @@ mod test {
         assert_eq!(Side::Bid, bids[0].side);
         assert_eq!(OrderType::Day, bids[0].order_type);
 
-        assert_eq!(500, bids[0].instrument.borrow().get_id());
+        assert_eq!(500, bids[0].instrument.read().unwrap().get_id());
         assert_eq!(
             InstrumentType::Share,
-            bids[0].instrument.borrow().get_type()
+            bids[0].instrument.read().unwrap().get_type()
         );
 
         // make sure we're publishing the trade
-        assert_eq!(1, disseminator.borrow().trades.borrow().len());
-        let binding = disseminator.borrow();
+        assert_eq!(1, disseminator.lock().unwrap().trades.borrow().len());
+        let binding = disseminator.lock().unwrap();
         let trades = binding.trades.borrow();
-        let trade = trades.get(0).unwrap();
+        let trade = trades.first().unwrap();
         let bid_id = trade.bid_order_id;
         let ask_id = trade.ask_order_id;
         let quantity = trade.quantity;
@@ -548,32 +1945,20 @@ mod test {
 
     #[test]
     fn cross_partially_and_post() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o_passive = Order::new(
-            1000,
-            i.clone(),
-            123,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o_aggressive = Order::new(
-            1001,
+        let o_passive = Order::new(1000, i.clone(), 123, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o_aggressive = Order::new(1001, i.clone(), 123, 300, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+
+        let mut target = Market::new(
             i.clone(),
-            123,
-            300,
-            Side::Ask,
-            OrderType::Day,
-            100,
-            2000,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
         target.set_state_trading();
 
         let r = target.add_order(o_passive);
@@ -592,41 +1977,29 @@ mod test {
         assert_eq!(Side::Ask, asks[0].side);
         assert_eq!(OrderType::Day, asks[0].order_type);
 
-        assert_eq!(500, asks[0].instrument.borrow().get_id());
+        assert_eq!(500, asks[0].instrument.read().unwrap().get_id());
         assert_eq!(
             InstrumentType::Share,
-            asks[0].instrument.borrow().get_type()
+            asks[0].instrument.read().unwrap().get_type()
         );
     }
 
     #[test]
     fn post_if_same_side() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o_passive = Order::new(
-            1000,
-            i.clone(),
-            123,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o_aggressive = Order::new(
-            1001,
+        let o_passive = Order::new(1000, i.clone(), 123, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o_aggressive = Order::new(1001, i.clone(), 123, 300, Side::Bid, OrderType::Day)
+            .with_session(100, 2001);
+
+        let mut target = Market::new(
             i.clone(),
-            123,
-            300,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2001,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
         target.set_state_trading();
 
         let r = target.add_order(o_passive);
@@ -646,10 +2019,10 @@ mod test {
         assert_eq!(Side::Bid, bids[0].side);
         assert_eq!(OrderType::Day, bids[0].order_type);
 
-        assert_eq!(500, bids[0].instrument.borrow().get_id());
+        assert_eq!(500, bids[0].instrument.read().unwrap().get_id());
         assert_eq!(
             InstrumentType::Share,
-            bids[0].instrument.borrow().get_type()
+            bids[0].instrument.read().unwrap().get_type()
         );
 
         // second order
@@ -659,63 +2032,35 @@ mod test {
         assert_eq!(Side::Bid, bids[1].side);
         assert_eq!(OrderType::Day, bids[1].order_type);
 
-        assert_eq!(500, bids[1].instrument.borrow().get_id());
+        assert_eq!(500, bids[1].instrument.read().unwrap().get_id());
         assert_eq!(
             InstrumentType::Share,
-            bids[1].instrument.borrow().get_type()
+            bids[1].instrument.read().unwrap().get_type()
         );
     }
 
     #[test]
     fn cross_against_multiple_passive() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o_passive1 = Order::new(
-            1000,
-            i.clone(),
-            123,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o_passive2 = Order::new(
-            1001,
-            i.clone(),
-            123,
-            200,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o_passive3 = Order::new(
-            1002,
-            i.clone(),
-            123,
-            300,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-
-        let o_aggressive = Order::new(
-            1003,
+        let o_passive1 = Order::new(1000, i.clone(), 123, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o_passive2 = Order::new(1001, i.clone(), 123, 200, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o_passive3 = Order::new(1002, i.clone(), 123, 300, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+
+        let o_aggressive = Order::new(1003, i.clone(), 123, 400, Side::Ask, OrderType::Day)
+            .with_session(101, 2002);
+
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            123,
-            400,
-            Side::Ask,
-            OrderType::Day,
-            101,
-            2002,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
         let r = target.add_order(o_passive1);
@@ -739,65 +2084,37 @@ mod test {
         assert_eq!(Side::Bid, bids[0].side);
         assert_eq!(OrderType::Day, bids[0].order_type);
 
-        assert_eq!(500, bids[0].instrument.borrow().get_id());
+        assert_eq!(500, bids[0].instrument.read().unwrap().get_id());
         assert_eq!(
             InstrumentType::Share,
-            bids[0].instrument.borrow().get_type()
+            bids[0].instrument.read().unwrap().get_type()
         );
 
         // check the feed
-        assert_eq!(3, disseminator.borrow().trades.borrow().len());
+        assert_eq!(3, disseminator.lock().unwrap().trades.borrow().len());
     }
 
     #[test]
     fn cross_partially_against_multiple_passive_and_post() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o_passive1 = Order::new(
-            1000,
-            i.clone(),
-            123,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o_passive2 = Order::new(
-            1001,
-            i.clone(),
-            123,
-            200,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o_passive3 = Order::new(
-            1002,
-            i.clone(),
-            123,
-            300,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
+        let o_passive1 = Order::new(1000, i.clone(), 123, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o_passive2 = Order::new(1001, i.clone(), 123, 200, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o_passive3 = Order::new(1002, i.clone(), 123, 300, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
 
-        let o_aggressive = Order::new(
-            1003,
+        let o_aggressive = Order::new(1003, i.clone(), 123, 900, Side::Ask, OrderType::Day)
+            .with_session(200, 1000);
+
+        let mut target = Market::new(
             i.clone(),
-            123,
-            900,
-            Side::Ask,
-            OrderType::Day,
-            200,
-            1000,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
         target.set_state_trading();
 
         let r = target.add_order(o_passive1);
@@ -820,31 +2137,469 @@ mod test {
         assert_eq!(Side::Ask, asks[0].side);
         assert_eq!(OrderType::Day, asks[0].order_type);
 
-        assert_eq!(500, asks[0].instrument.borrow().get_id());
+        assert_eq!(500, asks[0].instrument.read().unwrap().get_id());
         assert_eq!(
             InstrumentType::Share,
-            asks[0].instrument.borrow().get_type()
+            asks[0].instrument.read().unwrap().get_type()
         );
     }
 
     #[test]
-    fn close_deletes_orders() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn post_or_kill_rejected_if_crossing() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o = Order::new(
-            1000,
+        i.write().unwrap().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            123,
-            100,
-            Side::Bid,
-            OrderType::Day,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        for (price, side) in [(1000, Side::Bid), (1010, Side::Ask)] {
+            target.add_order(
+                Order::new(1000, i.clone(), price, 100, side, OrderType::Day)
+                    .with_session(100, 2000),
+            );
+        }
+
+        for (price, side) in [(1010, Side::Bid), (1000, Side::Ask)] {
+            let o = Order::new(1001, i.clone(), price, 50, side, OrderType::PostOrKill)
+                .with_session(100, 2000);
+            assert_eq!(OrderState::Rejected, target.add_order(o).0);
+        }
+
+        assert!(disseminator.lock().unwrap().trades.borrow().is_empty());
+        assert_eq!(100, target.generate_bids()[0].quantity);
+        assert_eq!(100, target.generate_asks()[0].quantity);
+    }
+
+    #[test]
+    fn post_or_kill_posted_if_not_crossing() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        for (price, side) in [(1000, Side::Bid), (1010, Side::Ask)] {
+            target.add_order(
+                Order::new(1000, i.clone(), price, 100, side, OrderType::Day)
+                    .with_session(100, 2000),
+            );
+        }
+
+        for (price, side) in [(1005, Side::Bid), (1006, Side::Ask)] {
+            let o = Order::new(1001, i.clone(), price, 50, side, OrderType::PostOrKill)
+                .with_session(100, 2000);
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+
+        assert_eq!(1005, target.generate_bids()[0].price);
+        assert_eq!(1006, target.generate_asks()[0].price);
+        assert_eq!(4, disseminator.lock().unwrap().new_orders.borrow().len());
+    }
+
+    #[test]
+    fn iceberg_shows_peak_only() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        let mut o = Order::new(1000, i.clone(), 1000, 250, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        o.display_quantity = 100;
+        let (state, id) = target.add_order(o);
+        assert_eq!(OrderState::Inserted, state);
+
+        let bids = target.generate_bids();
+        assert_eq!(100, bids[0].quantity);
+        assert_eq!(150, bids[0].hidden_quantity);
+        assert_eq!(
             100,
-            2000,
+            disseminator.lock().unwrap().new_orders.borrow()[0].quantity
+        );
+        assert_eq!(id, bids[0].get_id());
+    }
+
+    #[test]
+    fn iceberg_replenished_at_the_back() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        let mut iceberg = Order::new(1000, i.clone(), 1000, 250, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        iceberg.display_quantity = 100;
+        let (_, iceberg_id) = target.add_order(iceberg);
+        let (_, other_id) = target.add_order(
+            Order::new(1001, i.clone(), 1000, 100, Side::Ask, OrderType::Day)
+                .with_session(100, 2000),
+        );
+
+        // takes the first peak and half of the other order
+        let o = Order::new(1002, i.clone(), 1000, 150, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Traded, target.add_order(o).0);
+
+        let asks = target.generate_asks();
+        assert_eq!(2, asks.len());
+        assert_eq!(other_id, asks[0].get_id());
+        assert_eq!(50, asks[0].quantity);
+        assert_eq!(iceberg_id, asks[1].get_id());
+        assert_eq!(100, asks[1].quantity);
+        assert_eq!(50, asks[1].hidden_quantity);
+        // the new peak is published as a new order
+        let new_orders = disseminator.lock().unwrap().new_orders.borrow().clone();
+        assert_eq!(iceberg_id, new_orders.last().unwrap().get_id());
+        assert_eq!(100, new_orders.last().unwrap().quantity);
+
+        // an aggressor can trade through all the peaks
+        let o = Order::new(1002, i.clone(), 1000, 200, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Traded, target.add_order(o).0);
+        assert!(target.generate_asks().is_empty());
+        assert_eq!(5, disseminator.lock().unwrap().trades.borrow().len());
+    }
+
+    #[test]
+    fn iceberg_modify_includes_hidden_quantity() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        let mut o = Order::new(1000, i.clone(), 1000, 250, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        o.display_quantity = 100;
+        let (_, id) = target.add_order(o.clone());
+        let sequence = target.get_order(id).unwrap().get_sequence();
+
+        let mut modified = o.clone();
+        modified.set_id(id);
+        modified.quantity = 200;
+        assert_eq!(
+            OrderState::Modified,
+            target.modify_order(modified.clone()).0
+        );
+        let resting = target.get_order(id).unwrap();
+        assert_eq!(100, resting.quantity);
+        assert_eq!(100, resting.hidden_quantity);
+        assert_eq!(sequence, resting.get_sequence());
+
+        modified.quantity = 300;
+        assert_eq!(OrderState::Modified, target.modify_order(modified).0);
+        let resting = target.get_order(id).unwrap();
+        assert_eq!(100, resting.quantity);
+        assert_eq!(200, resting.hidden_quantity);
+        assert!(sequence < resting.get_sequence());
+    }
+
+    fn auction_market() -> (
+        Market,
+        Arc<RwLock<Instrument>>,
+        Arc<Mutex<MockDisseminator>>,
+    ) {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_state(InstrumentState::Auction);
+        i.write().unwrap().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        assert!(target.instrument_updated().is_none());
+        (target, i, disseminator)
+    }
+
+    #[test]
+    fn auction_accumulates_orders() {
+        let (mut target, i, disseminator) = auction_market();
+
+        for (price, side) in [(1010, Side::Bid), (1000, Side::Ask)] {
+            let o = Order::new(1000, i.clone(), price, 100, side, OrderType::Day)
+                .with_session(100, 2000);
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+        for order_type in [
+            OrderType::Market,
+            OrderType::FillAndKill,
+            OrderType::FillOrKill,
+        ] {
+            let o = Order::new(1000, i.clone(), 1010, 100, Side::Bid, order_type)
+                .with_session(100, 2000);
+            assert_eq!(OrderState::Rejected, target.add_order(o).0);
+        }
+
+        // crossed, but nothing traded
+        assert!(disseminator.lock().unwrap().trades.borrow().is_empty());
+        assert_eq!(1, target.generate_bids().len());
+        assert_eq!(1, target.generate_asks().len());
+
+        let infos = disseminator.lock().unwrap().auction_infos.borrow().clone();
+        assert_eq!(2, infos.len());
+        assert_eq!(0, { infos[0].volume });
+        assert_eq!(500, { infos[1].book_id });
+        assert_eq!(100, { infos[1].volume });
+        assert_eq!(1000, { infos[1].price });
+    }
+
+    #[test]
+    fn auction_equilibrium_price() {
+        let (mut target, i, _) = auction_market();
+        assert_eq!(0, { target.get_auction_info().price });
+
+        for (price, quantity, side) in [
+            (1020, 100, Side::Bid),
+            (1010, 200, Side::Bid),
+            (1000, 100, Side::Bid),
+            (990, 150, Side::Ask),
+            (1000, 100, Side::Ask),
+            (1010, 100, Side::Ask),
+        ] {
+            let o = Order::new(1000, i.clone(), price, quantity, side, OrderType::Day)
+                .with_session(100, 2000);
+            target.add_order(o);
+        }
+
+        // 1000: demand 400, supply 250. 1010: demand 300, supply 350
+        let info = target.get_auction_info();
+        assert_eq!(1010, { info.price });
+        assert_eq!(300, { info.volume });
+    }
+
+    #[test]
+    fn extreme_prices_and_quantities() {
+        let (mut target, i, disseminator) = auction_market();
+        let price = u64::MAX - 1;
+        for side in [Side::Bid, Side::Bid, Side::Ask] {
+            let o = Order::new(1000, i.clone(), price, u64::MAX, side, OrderType::Day)
+                .with_session(100, 2000);
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+        // the demand is more than a u64, all of the supply can trade
+        let info = target.get_auction_info();
+        assert_eq!(price, { info.price });
+        assert_eq!(u64::MAX, { info.volume });
+
+        i.write().unwrap().set_state(InstrumentState::Trading);
+        assert!(target.instrument_updated().is_none());
+        let trades = disseminator.lock().unwrap().trades.borrow().clone();
+        assert_eq!(1, trades.len());
+        assert_eq!(
+            (price, u64::MAX),
+            ({ trades[0].price }, { trades[0].quantity })
+        );
+        assert_eq!(u64::MAX, { target.get_statistics().volume });
+        assert_eq!(price, { target.get_statistics().vwap });
+        assert_eq!(Some(price), target.get_band_reference());
+        assert_eq!(price, { target.get_eod_summary().closing_price });
+    }
+
+    #[test]
+    fn uncross_when_auction_ends() {
+        let (mut target, i, disseminator) = auction_market();
+
+        for (price, quantity, side) in [
+            (1010, 200, Side::Bid),
+            (1000, 100, Side::Bid),
+            (990, 150, Side::Ask),
+            (1005, 100, Side::Ask),
+        ] {
+            let o = Order::new(1000, i.clone(), price, quantity, side, OrderType::Day)
+                .with_session(100, 2000);
+            target.add_order(o);
+        }
+
+        i.write().unwrap().set_state(InstrumentState::Trading);
+        assert!(target.instrument_updated().is_none());
+
+        // everything traded at the single equilibrium price
+        let trades = disseminator.lock().unwrap().trades.borrow().clone();
+        assert_eq!(2, trades.len());
+        assert!(trades.iter().all(|t| 1005 == { t.price }));
+        assert!(trades
+            .iter()
+            .all(|t| oep::trade::NO_AGGRESSOR == t.aggressor_side));
+        assert_eq!(200, trades.iter().map(|t| t.quantity).sum::<u64>());
+
+        // the leftovers
+        let bids = target.generate_bids();
+        assert_eq!(1, bids.len());
+        assert_eq!(1000, bids[0].price);
+        let asks = target.generate_asks();
+        assert_eq!(1, asks.len());
+        assert_eq!(50, asks[0].quantity);
+
+        let result = *disseminator
+            .lock()
+            .unwrap()
+            .auction_infos
+            .borrow()
+            .last()
+            .unwrap();
+        assert_eq!(200, { result.volume });
+        assert_eq!(1005, { target.get_eod_summary().closing_price });
+
+        // back to continuous trading
+        let o = Order::new(1000, i.clone(), 1005, 50, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Traded, target.add_order(o).0);
+    }
+
+    #[test]
+    fn trade_attributes() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        let (_, bid_id) = target.add_order(
+            Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+                .with_session(100, 2000),
+        );
+        for _ in 0..2 {
+            let o = Order::new(1001, i.clone(), 1000, 50, Side::Ask, OrderType::Day)
+                .with_session(100, 2000);
+            assert_eq!(OrderState::Traded, target.add_order(o).0);
+        }
+
+        let trades = disseminator.lock().unwrap().trades.borrow().clone();
+        assert_eq!(2, trades.len());
+        for (n, trade) in trades.iter().enumerate() {
+            assert_eq!(500, { trade.book_id });
+            assert_eq!(n as u64 + 1, { trade.trade_id });
+            assert_eq!(bid_id, { trade.bid_order_id });
+            assert_eq!(1, trade.aggressor_side);
+            assert!({ trade.timestamp } > 0);
+        }
+        assert!({ trades[0].timestamp } <= { trades[1].timestamp });
+    }
+
+    /// The markets sharing a generator never hand out the same id
+    #[test]
+    fn order_ids_unique_across_markets() {
+        let order_ids = Arc::new(Mutex::new(OrderIdGenerator::new(3)));
+        let mut ids = vec![];
+        for book_id in [500, 501] {
+            let i = Arc::new(RwLock::new(Instrument::new_fast(
+                book_id,
+                InstrumentType::Share,
+            )));
+            let mut target = Market::new(
+                i.clone(),
+                Arc::new(Mutex::new(MockDisseminator::new())),
+                order_ids.clone(),
+            );
+            target.set_state_trading();
+            let o =
+                Order::new(1000, i, 1000, 100, Side::Ask, OrderType::Day).with_session(100, 2000);
+            let (state, id) = target.add_order(o);
+            assert_eq!(OrderState::Inserted, state);
+            assert_eq!(id, target.get_order_id());
+            ids.push(id);
+        }
+        assert_eq!(vec![(3 << 32) + 1, (3 << 32) + 2], ids);
+    }
+
+    #[test]
+    fn passive_fills() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
+        target.set_state_trading();
+
+        let mut ids = vec![];
+        for participant in [1000, 1001] {
+            let o = Order::new(participant, i.clone(), 1000, 100, Side::Ask, OrderType::Day)
+                .with_session(100, 2000);
+            ids.push(target.add_order(o).1);
+        }
+        assert!(target.take_passive_fills().is_empty());
+
+        let o = Order::new(1002, i.clone(), 1000, 150, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let (state, aggressor_id) = target.add_order(o);
+        assert_eq!(OrderState::Traded, state);
+
+        let fills = target.take_passive_fills();
+        assert_eq!(2, fills.len());
+        assert!(fills.iter().all(|f| f.aggressor_id == aggressor_id));
+        assert_eq!(ids[0], fills[0].order.get_id());
+        assert_eq!(1000, fills[0].order.participant);
+        assert_eq!(0, fills[0].order.quantity);
+        assert_eq!(100, fills[0].quantity);
+        assert_eq!(ids[1], fills[1].order.get_id());
+        assert_eq!(50, fills[1].order.quantity);
+        assert_eq!(50, fills[1].quantity);
+        assert_eq!(1000, fills[1].price);
+        // taken only once
+        assert!(target.take_passive_fills().is_empty());
+    }
+
+    #[test]
+    fn close_deletes_orders() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let o = Order::new(1000, i.clone(), 123, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
 
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
         target.set_state_trading();
         let _ = (0..100).map(|_| {
             assert_eq!(OrderState::Inserted, target.add_order(o.clone()).0);
@@ -856,802 +2611,2231 @@ mod test {
     }
 
     #[test]
-    fn close_closes_instrument() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn close_keeps_good_till_orders() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o = Order::new(
-            1000,
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            123,
-            100,
-            Side::Bid,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        for order_type in [
             OrderType::Day,
-            100,
-            2000,
+            OrderType::GoodTillCancel,
+            OrderType::GoodTillDate,
+        ] {
+            let mut o = Order::new(1000, i.clone(), 123, 100, Side::Bid, order_type)
+                .with_session(100, 2000);
+            o.expiry = 5000;
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+        target.close();
+
+        let bids = target.generate_bids();
+        assert_eq!(2, bids.len());
+        assert_eq!(OrderType::GoodTillCancel, bids[0].order_type);
+        assert_eq!(OrderType::GoodTillDate, bids[1].order_type);
+        assert_eq!(1, disseminator.lock().unwrap().cancels.borrow().len());
+        let closed_out = target.take_closed_out_orders();
+        assert_eq!(1, closed_out.len());
+        assert_eq!(OrderType::Day, closed_out[0].order_type);
+        assert!(target.take_closed_out_orders().is_empty());
+    }
+
+    #[test]
+    fn delete_cancels_all_the_orders() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
+        target.set_state_trading();
+        for (order_type, side, price) in [
+            (OrderType::Day, Side::Bid, 123),
+            (OrderType::GoodTillCancel, Side::Bid, 123),
+            (OrderType::GoodTillCancel, Side::Ask, 125),
+        ] {
+            let o =
+                Order::new(1000, i.clone(), price, 100, side, order_type).with_session(100, 2000);
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+
+        let (summary, cancelled) = target.delete();
+        assert!(summary.is_some());
+        assert_eq!(2, cancelled.len());
+        assert!(cancelled
+            .iter()
+            .all(|o| o.order_type == OrderType::GoodTillCancel));
+        assert!(target.generate_bids().is_empty());
+        assert!(target.generate_asks().is_empty());
+        assert_eq!(3, disseminator.lock().unwrap().cancels.borrow().len());
+        assert_eq!(1, disseminator.lock().unwrap().eod_summaries.borrow().len());
+        let delistings = disseminator.lock().unwrap().delistings.borrow().clone();
+        assert_eq!(1, delistings.len());
+        assert_eq!(500, { delistings[0].book_id });
+
+        // already closed
+        let (summary, cancelled) = target.delete();
+        assert!(summary.is_none() && cancelled.is_empty());
+    }
 
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
+    #[test]
+    fn shut_down_cancels_the_day_orders() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
         target.set_state_trading();
-        let _ = (0..100).map(|_| {
-            assert_eq!(OrderState::Inserted, target.add_order(o.clone()).0);
-        });
-        target.close();
+        for (order_type, side, price) in [
+            (OrderType::Day, Side::Bid, 123),
+            (OrderType::GoodTillCancel, Side::Bid, 123),
+            (OrderType::Day, Side::Ask, 125),
+        ] {
+            let o =
+                Order::new(1000, i.clone(), price, 100, side, order_type).with_session(100, 2000);
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+
+        let (summary, cancelled) = target.shut_down().unwrap();
+        assert_eq!(124, { summary.closing_price });
+        assert_eq!(2, cancelled.len());
+        assert!(cancelled.iter().all(|o| o.order_type == OrderType::Day));
+        assert_eq!(InstrumentState::Closed, target.get_state());
+        assert_eq!(1, target.generate_bids().len());
+        assert_eq!(2, disseminator.lock().unwrap().cancels.borrow().len());
+        let changes = disseminator.lock().unwrap().state_changes.borrow().clone();
+        assert_eq!(1, changes.len());
+        assert_eq!(StateChangeReason::Shutdown, changes[0].get_reason());
 
-        assert_eq!(InstrumentState::Closed, i.borrow().get_state());
+        assert!(target.shut_down().is_none());
     }
 
     #[test]
-    fn reject_if_out_of_price_bands() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn expire_cancels_all_the_orders() {
+        let mut instrument = Instrument::new_fast(500, InstrumentType::Future);
+        instrument.set_expiry(5000);
+        let i = Arc::new(RwLock::new(instrument));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        for (order_type, side, price) in [
+            (OrderType::Day, Side::Bid, 123),
+            (OrderType::GoodTillCancel, Side::Ask, 125),
+        ] {
+            let o =
+                Order::new(1000, i.clone(), price, 100, side, order_type).with_session(100, 2000);
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+
+        let (summary, cancelled) = target.expire();
+        assert_eq!(124, { summary.unwrap().closing_price });
+        assert_eq!(1, cancelled.len());
+        assert_eq!(OrderType::GoodTillCancel, cancelled[0].order_type);
+        assert_eq!(1, target.take_closed_out_orders().len());
+        assert_eq!(InstrumentState::Closed, target.get_state());
+        assert!(target.generate_bids().is_empty());
+        assert!(target.generate_asks().is_empty());
+        let changes = disseminator.lock().unwrap().state_changes.borrow().clone();
+        assert_eq!(1, changes.len());
+        assert_eq!(StateChangeReason::Expiry, changes[0].get_reason());
+        assert_eq!(1, disseminator.lock().unwrap().delistings.borrow().len());
+
+        // already closed
+        let (summary, cancelled) = target.expire();
+        assert!(summary.is_none() && cancelled.is_empty());
+    }
+
+    #[test]
+    fn bust_trade() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        for (participant, price, quantity) in [(1, 101, 4), (2, 100, 6)] {
+            let o = Order::new(
+                participant,
+                i.clone(),
+                price,
+                quantity,
+                Side::Bid,
+                OrderType::Day,
+            )
+            .with_session(100, 2000);
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+        let o =
+            Order::new(3, i.clone(), 100, 10, Side::Ask, OrderType::Day).with_session(101, 3000);
+        assert_eq!(OrderState::Traded, target.add_order(o).0);
+        assert_eq!(2, target.get_day_trades().len());
+        target.take_trade_captures();
+
+        let busted = target.bust_trade(1).unwrap();
+        assert_eq!((101, 4), (busted.price, busted.quantity));
+        assert_eq!((1, 3), (busted.bid.participant, busted.ask.participant));
+        assert_eq!((101, 3000), (busted.ask.gateway_id, busted.ask.session_id));
+        // as if only the second trade happened
+        let statistics = target.get_statistics();
+        assert_eq!(
+            (100, 100, 100, 6, 1),
+            (
+                { statistics.open },
+                { statistics.high },
+                { statistics.last_price },
+                { statistics.volume },
+                { statistics.trade_count }
+            )
+        );
+        let busts = disseminator.lock().unwrap().trade_busts.borrow().clone();
+        assert_eq!(1, busts.len());
+        assert_eq!(
+            (500, 1, 4),
+            ({ busts[0].book_id }, { busts[0].trade_id }, {
+                busts[0].quantity
+            })
+        );
+        assert_eq!(1, disseminator.lock().unwrap().statistics.borrow().len());
+        // the positions are undone by the same trade the other way around
+        let captures = target.take_trade_captures();
+        assert_eq!(1, captures.len());
+        assert_eq!((3, 1), ({ captures[0].buyer }, { captures[0].seller }));
+
+        // only once, and only for the trades of the session
+        assert!(target.bust_trade(1).is_none());
+        target.close();
+        assert!(target.bust_trade(2).is_none());
+    }
 
-        let o1 = Order::new(
+    #[test]
+    fn good_till_date_needs_expiry() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let mut o = Order::new(
             1000,
             i.clone(),
-            1000,
+            123,
             100,
             Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            OrderType::GoodTillDate,
+        )
+        .with_session(100, 2000);
+
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let o2 = Order::new(
-            1000,
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Rejected, target.add_order(o.clone()).0);
+        o.expiry = 5000;
+        assert_eq!(OrderState::Inserted, target.add_order(o).0);
+    }
+
+    #[test]
+    fn expire_orders() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1001,
-            100,
-            Side::Ask,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let o = Order::new(
+        target.set_state_trading();
+
+        for (price, side, order_type, expiry) in [
+            (1000, Side::Bid, OrderType::GoodTillDate, 5000),
+            (1010, Side::Ask, OrderType::GoodTillDate, 6000),
+            (999, Side::Bid, OrderType::GoodTillCancel, 0),
+            (1011, Side::Ask, OrderType::Day, 0),
+        ] {
+            let mut o =
+                Order::new(1000, i.clone(), price, 100, side, order_type).with_session(100, 2000);
+            o.expiry = expiry;
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+
+        assert!(target.expire_orders(4999).is_empty());
+        let expired = target.expire_orders(5000);
+        assert_eq!(1, expired.len());
+        assert_eq!(1000, expired[0].price);
+        assert_eq!(1, disseminator.lock().unwrap().cancels.borrow().len());
+
+        let expired = target.expire_orders(10000);
+        assert_eq!(1, expired.len());
+        assert_eq!(Side::Ask, expired[0].side);
+        assert_eq!(1, target.generate_bids().len());
+        assert_eq!(1, target.generate_asks().len());
+        assert_eq!(2, disseminator.lock().unwrap().cancels.borrow().len());
+    }
+
+    #[test]
+    fn modify_price_keeps_expiry() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let mut o = Order::new(
             1000,
             i.clone(),
             123,
             100,
             Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            OrderType::GoodTillDate,
+        )
+        .with_session(100, 2000);
+        o.expiry = 5000;
+
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
+        target.set_state_trading();
+        let (_, id) = target.add_order(o.clone());
+
+        let mut modified = o.clone();
+        modified.set_id(id);
+        modified.price = 124;
+        modified.expiry = 0;
+        let (state, new_id) = target.modify_order(modified);
+        assert_eq!(OrderState::Inserted, state);
+        assert_eq!(5000, target.get_order(new_id).unwrap().expiry);
+    }
+
+    #[test]
+    fn resolve_client_order_ids() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        let order = |price, client_order_id| {
+            let mut o = Order::new(1000, i.clone(), price, 100, Side::Bid, OrderType::Day)
+                .with_session(100, 2000);
+            o.client_order_id = client_order_id;
+            o
+        };
+
+        let (_, first) = target.add_order(order(123, 7));
+        assert_eq!(
+            Ok(first),
+            target.resolve_client_order_id(1000, Side::Bid, 7)
+        );
+        // another participant, or the other side
+        assert_eq!(
+            Err(ClientOrderIdError::Unknown),
+            target.resolve_client_order_id(1001, Side::Bid, 7)
+        );
+        assert_eq!(
+            Err(ClientOrderIdError::Unknown),
+            target.resolve_client_order_id(1000, Side::Ask, 7)
+        );
+        assert_eq!(
+            Err(ClientOrderIdError::Unknown),
+            target.resolve_client_order_id(1000, Side::Bid, 0)
+        );
+
+        // the new order of a price change keeps the client order id
+        let mut modified = order(124, 0);
+        modified.set_id(first);
+        let (state, moved) = target.modify_order(modified);
+        assert_eq!(OrderState::Inserted, state);
+        assert_eq!(
+            Ok(moved),
+            target.resolve_client_order_id(1000, Side::Bid, 7)
+        );
+
+        // reused, by a stop order too
+        let mut stop = order(125, 7);
+        stop.order_type = OrderType::StopLimit;
+        stop.stop_price = 125;
+        let (state, stop_id) = target.add_order(stop);
+        assert_eq!(OrderState::Inserted, state);
+        assert_eq!(
+            Err(ClientOrderIdError::Ambiguous),
+            target.resolve_client_order_id(1000, Side::Bid, 7)
+        );
+        assert_eq!(
+            OrderState::Cancelled,
+            target.cancel_order(&target.get_order(moved).unwrap().clone())
+        );
+        assert_eq!(
+            Ok(stop_id),
+            target.resolve_client_order_id(1000, Side::Bid, 7)
+        );
+    }
+
+    #[test]
+    fn stop_loss_triggered_by_trade() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        for (price, quantity, side) in [(990, 100, Side::Bid), (1000, 100, Side::Bid)] {
+            target.add_order(
+                Order::new(1000, i.clone(), price, quantity, side, OrderType::Day)
+                    .with_session(100, 2000),
+            );
+        }
+        // sell 100 at market once something trades at 1000 or lower
+        let mut stop = Order::new(1001, i.clone(), 0, 100, Side::Ask, OrderType::StopLoss)
+            .with_session(100, 2000);
+        stop.stop_price = 1000;
+        let (state, stop_id) = target.add_order(stop);
+        assert_eq!(OrderState::Inserted, state);
+        // held aside, neither in the book nor on the feed
+        assert_eq!(0, target.generate_asks().len());
+        assert_eq!(2, disseminator.lock().unwrap().new_orders.borrow().len());
+        assert_eq!(
+            OrderType::StopLoss,
+            target.get_order(stop_id).unwrap().order_type
+        );
+
+        let o = Order::new(1002, i.clone(), 1000, 50, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Traded, target.add_order(o).0);
+
+        // the stop order traded the rest of the 1000 bid and then the 990 one
+        assert!(target.get_order(stop_id).is_none());
+        let bids = target.generate_bids();
+        assert_eq!(1, bids.len());
+        assert_eq!(990, bids[0].price);
+        assert_eq!(50, bids[0].quantity);
+        assert_eq!(3, disseminator.lock().unwrap().trades.borrow().len());
+        assert_eq!(990, { target.get_eod_summary().closing_price });
+    }
+
+    #[test]
+    fn stop_limit_posts_when_triggered() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        // buy 100 up to 1010 once something trades at 1005 or higher
+        let mut stop = Order::new(1001, i.clone(), 1010, 100, Side::Bid, OrderType::StopLimit)
+            .with_session(100, 2000);
+        stop.stop_price = 1005;
+        let (_, stop_id) = target.add_order(stop);
+
+        for (price, side) in [(1000, Side::Ask), (1000, Side::Bid)] {
+            target.add_order(
+                Order::new(1000, i.clone(), price, 100, side, OrderType::Day)
+                    .with_session(100, 2000),
+            );
+        }
+        // a trade below the stop price doesn't trigger it
+        assert_eq!(
+            OrderType::StopLimit,
+            target.get_order(stop_id).unwrap().order_type
+        );
+
+        for (price, side) in [(1005, Side::Ask), (1005, Side::Bid)] {
+            target.add_order(
+                Order::new(1000, i.clone(), price, 100, side, OrderType::Day)
+                    .with_session(100, 2000),
+            );
+        }
+        let o = target.get_order(stop_id).unwrap();
+        assert_eq!(OrderType::Day, o.order_type);
+        assert_eq!(1010, o.price);
+        assert_eq!(1, target.generate_bids().len());
+        assert_eq!(
+            stop_id,
+            disseminator
+                .lock()
+                .unwrap()
+                .new_orders
+                .borrow()
+                .last()
+                .unwrap()
+                .get_id()
+        );
+    }
+
+    #[test]
+    fn stop_orders_cascade() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        for price in [1000, 995, 990] {
+            target.add_order(
+                Order::new(1000, i.clone(), price, 100, Side::Bid, OrderType::Day)
+                    .with_session(100, 2000),
+            );
+        }
+        let mut ids = vec![];
+        for stop_price in [995, 1000] {
+            let mut stop = Order::new(1001, i.clone(), 0, 100, Side::Ask, OrderType::StopLoss)
+                .with_session(100, 2000);
+            stop.stop_price = stop_price;
+            ids.push(target.add_order(stop).1);
+        }
+
+        let o = Order::new(1002, i.clone(), 1000, 100, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Traded, target.add_order(o).0);
+
+        // the 1000 stop traded at 995, which triggered the 995 one
+        assert!(target.get_order(ids[0]).is_none());
+        assert!(target.get_order(ids[1]).is_none());
+        assert!(target.generate_bids().is_empty());
+    }
+
+    #[test]
+    fn stop_order_cancel() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        let mut stop = Order::new(1001, i.clone(), 0, 100, Side::Ask, OrderType::StopLoss)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Rejected, target.add_order(stop.clone()).0);
+
+        stop.stop_price = 1000;
+        let (_, id) = target.add_order(stop.clone());
+        stop.set_id(id);
+        assert_eq!(OrderState::Cancelled, target.cancel_order(&stop));
+        assert!(target.get_order(id).is_none());
+        assert_eq!(OrderState::Rejected, target.cancel_order(&stop));
+        assert!(disseminator.lock().unwrap().cancels.borrow().is_empty());
+    }
+
+    #[test]
+    fn close_closes_instrument() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let o = Order::new(1000, i.clone(), 123, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        let _ = (0..100).map(|_| {
+            assert_eq!(OrderState::Inserted, target.add_order(o.clone()).0);
+        });
+        target.close();
+
+        assert_eq!(InstrumentState::Closed, i.read().unwrap().get_state());
+    }
+
+    #[test]
+    fn close_publishes_eod_summary() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        for (price, quantity, side) in [
+            (1000, 100, Side::Bid),
+            (1000, 300, Side::Bid),
+            (990, 100, Side::Ask),
+            (1000, 250, Side::Ask),
+        ] {
+            target.add_order(
+                Order::new(1000, i.clone(), price, quantity, side, OrderType::Day)
+                    .with_session(100, 2000),
+            );
+        }
+
+        let summary = target.close();
+        assert_eq!(500, { summary.book_id });
+        assert_eq!(1000, { summary.closing_price });
+        assert_eq!(350, { summary.volume });
+        assert_eq!(2, { summary.trade_count });
+        assert_eq!(1, disseminator.lock().unwrap().eod_summaries.borrow().len());
+
+        // statistics are reset for the next day
+        assert_eq!(0, { target.get_eod_summary().trade_count });
+    }
+
+    #[test]
+    fn eod_summary_without_trades_uses_midpoint() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        assert_eq!(0, { target.get_eod_summary().closing_price });
+
+        target.set_state_trading();
+        for (price, side) in [(1000, Side::Bid), (1010, Side::Ask)] {
+            target.add_order(
+                Order::new(1000, i.clone(), price, 100, side, OrderType::Day)
+                    .with_session(100, 2000),
+            );
+        }
+        assert_eq!(1005, { target.close().closing_price });
+    }
+
+    #[test]
+    fn instrument_updated_closes_once() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        // new_fast instruments start closed
+        assert!(target.instrument_updated().is_none());
+
+        i.write().unwrap().set_state(InstrumentState::Trading);
+        assert!(target.instrument_updated().is_none());
+
+        i.write().unwrap().set_state(InstrumentState::Closed);
+        assert!(target.instrument_updated().is_some());
+        assert!(target.instrument_updated().is_none());
+        assert_eq!(1, disseminator.lock().unwrap().eod_summaries.borrow().len());
+    }
+
+    #[test]
+    fn reject_if_out_of_price_bands() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o2 = Order::new(1000, i.clone(), 1001, 100, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        let o = Order::new(1000, i.clone(), 123, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        assert_eq!(OrderState::Inserted, target.add_order(o1).0);
+        assert_eq!(OrderState::Inserted, target.add_order(o2).0);
+        assert!(!target.take_band_reject());
+        assert_eq!(OrderState::Rejected, target.add_order(o).0);
+        assert!(target.take_band_reject());
+        assert!(!target.take_band_reject());
+    }
+
+    #[test]
+    fn price_bands_without_both_sides() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+        let bid = |price| {
+            Order::new(1000, i.clone(), price, 100, Side::Bid, OrderType::Day)
+                .with_session(100, 2000)
+        };
+
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        // nothing to check the first order of the day against
+        assert_eq!(None, target.get_band_reference());
+        target.set_without_reference(WithoutReference::Reject);
+        assert_eq!(OrderState::Rejected, target.add_order(bid(123)).0);
+        target.set_without_reference(WithoutReference::Accept);
+        assert_eq!(OrderState::Inserted, target.add_order(bid(123)).0);
+
+        // the previous close, while the asks are empty
+        i.write().unwrap().set_previous_close(1000);
+        assert_eq!(Some(1000), target.get_band_reference());
+        assert_eq!(OrderState::Rejected, target.add_order(bid(123)).0);
+        assert_eq!(OrderState::Inserted, target.add_order(bid(950)).0);
+
+        // the last trade, without a previous close
+        let ask = Order::new(1001, i.clone(), 950, 100, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Traded, target.add_order(ask).0);
+        i.write().unwrap().set_previous_close(0);
+        assert_eq!(Some(950), target.get_band_reference());
+        assert_eq!(OrderState::Rejected, target.add_order(bid(1100)).0);
+
+        // the closing price is the next day's reference
+        target.close();
+        assert_eq!(950, i.read().unwrap().get_previous_close());
+    }
+
+    #[test]
+    fn market_order_not_inserted() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let o = Order::new(1000, i.clone(), 0, 1000, Side::Bid, OrderType::Market)
+            .with_session(100, 2000);
+
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        assert_eq!(OrderState::Cancelled, target.add_order(o).0);
+    }
+
+    #[test]
+    fn market_order_not_inserted_after_trade() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o2 = Order::new(1000, i.clone(), 1001, 100, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        let o = Order::new(1000, i.clone(), 0, 1000, Side::Bid, OrderType::Market)
+            .with_session(100, 2000);
+
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        assert_eq!(OrderState::Inserted, target.add_order(o1).0);
+        assert_eq!(OrderState::Inserted, target.add_order(o2).0);
+        assert_eq!(OrderState::Traded, target.add_order(o).0);
+    }
+
+    #[test]
+    fn market_order_trades() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o2 = Order::new(1000, i.clone(), 1001, 100, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        let o = Order::new(1000, i.clone(), 0, 100, Side::Bid, OrderType::Market)
+            .with_session(100, 2000);
+
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        assert_eq!(OrderState::Inserted, target.add_order(o1).0);
+        assert_eq!(OrderState::Inserted, target.add_order(o2).0);
+        assert_eq!(OrderState::Traded, target.add_order(o).0);
+    }
+
+    #[test]
+    fn order_id_increments() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o2 = Order::new(1000, i.clone(), 1001, 100, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        let o3 =
+            Order::new(1000, i.clone(), 0, 100, Side::Bid, OrderType::Day).with_session(100, 2000);
+
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        assert_eq!(OrderState::Inserted, target.add_order(o1).0);
+        assert_eq!(1, target.get_order_id());
+        assert_eq!(OrderState::Inserted, target.add_order(o2).0);
+        assert_eq!(2, target.get_order_id());
+        assert_eq!(OrderState::Rejected, target.add_order(o3).0);
+        assert_eq!(3, target.get_order_id());
+    }
+
+    #[test]
+    fn publish_new_order() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        assert_eq!(1, disseminator.lock().unwrap().new_orders.borrow().len());
+        assert_eq!(0, disseminator.lock().unwrap().cancels.borrow().len());
+
+        o1.set_id(target.get_order_id()); // fix the order id
+        o1.set_sequence(1); // and the time priority
+        assert_eq!(disseminator.lock().unwrap().new_orders.borrow()[0], o1);
+    }
+
+    #[test]
+    fn enter_order() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+        let order = |participant, price, quantity, side, order_type| {
+            Order::new(participant, i.clone(), price, quantity, side, order_type)
+                .with_session(100, 2000)
+        };
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        let first_ask = target
+            .enter_order(order(2, 1000, 100, Side::Ask, OrderType::Day))
+            .order_id;
+        target.enter_order(order(3, 1010, 50, Side::Ask, OrderType::Day));
+
+        // sweeps both asks, the rest of it stays in the book
+        let result = target.enter_order(order(1, 1010, 200, Side::Bid, OrderType::Day));
+        assert_eq!(OrderState::PartiallyTraded, result.state);
+        assert_eq!(target.get_order_id(), result.order_id);
+        assert_eq!(
+            vec![(first_ask, 1000, 100), (first_ask + 1, 1010, 50)],
+            result
+                .trades
+                .iter()
+                .map(|t| (t.ask_order_id, t.price, t.quantity))
+                .collect::<Vec<_>>()
+        );
+        assert!(result
+            .trades
+            .iter()
+            .all(|t| t.bid_order_id == result.order_id));
+        let resting = result.resting.unwrap();
+        assert_eq!((1010, 50), (resting.price, resting.quantity));
+        // and went on the feed, the rest of the order being known from the trades
+        assert_eq!(2, disseminator.lock().unwrap().trades.borrow().len());
+        assert_eq!(2, disseminator.lock().unwrap().new_orders.borrow().len());
+
+        // takes all of it
+        let result = target.enter_order(order(4, 1010, 80, Side::Ask, OrderType::FillAndKill));
+        assert_eq!(OrderState::Cancelled, result.state);
+        assert_eq!(1, result.trades.len());
+        assert_eq!(50, { result.trades[0].quantity });
+        assert!(result.resting.is_none());
+        // the passive order left the book by trading
+        assert!(target.get_order(resting.get_id()).is_none());
+
+        let result = target.enter_order(order(4, 1020, 10, Side::Ask, OrderType::Day));
+        assert_eq!(OrderState::Inserted, result.state);
+        assert!(result.trades.is_empty());
+        assert_eq!(Some(result.order_id), result.resting.map(|o| o.get_id()));
+        assert_eq!(3, disseminator.lock().unwrap().new_orders.borrow().len());
+
+        let result = target.enter_order(order(4, 1020, 0, Side::Ask, OrderType::Day));
+        assert_eq!(OrderState::Rejected, result.state);
+        assert_eq!(0, result.order_id);
+        assert!(result.resting.is_none());
+    }
+
+    #[test]
+    fn cancel_invalid_order() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        assert_eq!(OrderState::Rejected, target.cancel_order(&o1));
+    }
+
+    #[test]
+    fn cancel_valid_order() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        assert_eq!(OrderState::Cancelled, target.cancel_order(&o1));
+    }
+
+    #[test]
+    fn cancel_invalid_order_side() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        o1.side = Side::Ask;
+        assert_eq!(OrderState::Rejected, target.cancel_order(&o1));
+    }
+
+    #[test]
+    fn cancel_publishes() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        assert_eq!(OrderState::Cancelled, target.cancel_order(&o1));
+        assert_eq!(1, disseminator.lock().unwrap().new_orders.borrow().len());
+        assert_eq!(1, disseminator.lock().unwrap().cancels.borrow().len());
+    }
+
+    #[test]
+    fn get_order() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let bid =
+            Order::new(1000, i.clone(), 100, 10, Side::Bid, OrderType::Day).with_session(100, 2000);
+        let ask = Order::new(
+            1001,
+            i.clone(),
+            110,
+            20,
+            Side::Ask,
+            OrderType::GoodTillCancel,
+        )
+        .with_session(100, 2000);
+
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        let (_, bid_id) = target.add_order(bid);
+        let (_, ask_id) = target.add_order(ask);
+
+        assert_eq!(1000, target.get_order(bid_id).unwrap().participant);
+        let o = target.get_order(ask_id).unwrap();
+        assert_eq!(Side::Ask, o.side);
+        assert_eq!(OrderType::GoodTillCancel, o.order_type);
+        assert!(target.get_order(ask_id + 1).is_none());
+
+        target.cancel_order(&target.get_order(bid_id).unwrap().clone());
+        assert!(target.get_order(bid_id).is_none());
+    }
+
+    #[test]
+    fn modify_invalid_order() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        assert_eq!(OrderState::Rejected, target.modify_order(o1).0);
+    }
+
+    #[test]
+    fn modify_valid_order() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        o1.price = 990;
+        assert_eq!(OrderState::Inserted, target.modify_order(o1).0);
+
+        let bids = target.generate_bids();
+        assert_eq!(1, bids.len());
+        assert_eq!(990, bids[0].price);
+    }
+
+    #[test]
+    fn modify_to_zero_quantity_reject() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        o1.quantity = 0;
+        assert_eq!(OrderState::Rejected, target.modify_order(o1).0);
+
+        let bids = target.generate_bids();
+
+        // let's make sure that the original order is still there
+        // and its price is the original one
+        assert_eq!(1, bids.len());
+        assert_eq!(1000, bids[0].price);
+    }
+
+    #[test]
+    fn modify_cant_change_sides() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        o1.side = Side::Ask;
+        assert_eq!(OrderState::Rejected, target.modify_order(o1).0);
+
+        // let's make sure that the original order is still there
+        assert_eq!(1, target.generate_bids().len());
+        assert_eq!(0, target.generate_asks().len());
+    }
+
+    #[test]
+    fn modify_cant_change_order_type() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        o1.order_type = OrderType::FillOrKill;
+        assert_eq!(OrderState::Rejected, target.modify_order(o1).0);
 
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
-        target.set_state_trading();
-        assert_eq!(OrderState::Inserted, target.add_order(o1).0);
-        assert_eq!(OrderState::Inserted, target.add_order(o2).0);
-        assert_eq!(OrderState::Rejected, target.add_order(o).0);
+        // let's make sure that the original order is still there
+        assert_eq!(1, target.generate_bids().len());
     }
 
     #[test]
-    fn market_order_not_inserted() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn modify_quantity_doesnt_change_position() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        let o = Order::new(
-            1000,
+        i.write().unwrap().set_percentage_bands(10);
+
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let mut o2 = Order::new(1000, i.clone(), 1000, 200, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            0,
-            1000,
-            Side::Bid,
-            OrderType::Market,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
         target.set_state_trading();
-        assert_eq!(OrderState::Cancelled, target.add_order(o).0);
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        assert_eq!(OrderState::Inserted, target.add_order(o2.clone()).0);
+        o2.set_id(target.get_order_id());
+
+        // modify the first order quantity should keep it in the first position
+        o1.quantity = 50;
+        assert_eq!(OrderState::Modified, target.modify_order(o1).0);
+
+        let bids = target.generate_bids();
+        assert_eq!(2, bids.len());
+        assert_eq!(50, bids[0].quantity);
+        assert_eq!(200, bids[1].quantity);
     }
 
     #[test]
-    fn market_order_not_inserted_after_trade() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn modify_valid_order_invalid_side() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        i.write().unwrap().set_percentage_bands(10);
 
-        let o1 = Order::new(
-            1000,
-            i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o2 = Order::new(
-            1000,
-            i.clone(),
-            1001,
-            100,
-            Side::Ask,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o = Order::new(
-            1000,
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            0,
-            1000,
-            Side::Bid,
-            OrderType::Market,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
         target.set_state_trading();
-        assert_eq!(OrderState::Inserted, target.add_order(o1).0);
-        assert_eq!(OrderState::Inserted, target.add_order(o2).0);
-        assert_eq!(OrderState::Traded, target.add_order(o).0);
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        o1.side = Side::Ask;
+        assert_eq!(OrderState::Rejected, target.modify_order(o1).0);
     }
 
     #[test]
-    fn market_order_trades() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn modify_valid_order_publish_delete_new() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        i.write().unwrap().set_percentage_bands(10);
 
-        let o1 = Order::new(
-            1000,
-            i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o2 = Order::new(
-            1000,
-            i.clone(),
-            1001,
-            100,
-            Side::Ask,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o = Order::new(
-            1000,
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            0,
-            100,
-            Side::Bid,
-            OrderType::Market,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
         target.set_state_trading();
-        assert_eq!(OrderState::Inserted, target.add_order(o1).0);
-        assert_eq!(OrderState::Inserted, target.add_order(o2).0);
-        assert_eq!(OrderState::Traded, target.add_order(o).0);
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        o1.price += 1;
+        assert_eq!(OrderState::Inserted, target.modify_order(o1).0);
+
+        assert_eq!(1, disseminator.lock().unwrap().cancels.borrow().len());
+        assert_eq!(2, disseminator.lock().unwrap().new_orders.borrow().len());
     }
 
     #[test]
-    fn order_id_increments() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn modify_quantity_publish_modify() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        i.write().unwrap().set_percentage_bands(10);
 
-        let o1 = Order::new(
-            1000,
-            i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o2 = Order::new(
-            1000,
-            i.clone(),
-            1001,
-            100,
-            Side::Ask,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o3 = Order::new(
-            1000,
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            0,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let mut target = Market::new(i.clone(), Rc::new(RefCell::new(MockDisseminator::new())));
         target.set_state_trading();
-        assert_eq!(OrderState::Inserted, target.add_order(o1).0);
-        assert_eq!(1, target.get_order_id());
-        assert_eq!(OrderState::Inserted, target.add_order(o2).0);
-        assert_eq!(2, target.get_order_id());
-        assert_eq!(OrderState::Rejected, target.add_order(o3).0);
-        assert_eq!(3, target.get_order_id());
+
+        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        o1.quantity += 100;
+        assert_eq!(OrderState::Modified, target.modify_order(o1).0);
+
+        assert_eq!(1, disseminator.lock().unwrap().modifies.borrow().len());
     }
 
     #[test]
-    fn publish_new_order() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn modify_price_loses_queue_position() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        i.write().unwrap().set_percentage_bands(10);
 
-        let mut o1 = Order::new(
-            1000,
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let mut o2 = Order::new(1000, i.clone(), 1001, 200, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
         assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        assert_eq!(1, disseminator.borrow().new_orders.borrow().len());
-        assert_eq!(0, disseminator.borrow().cancels.borrow().len());
+        o1.set_id(target.get_order_id());
+        assert_eq!(OrderState::Inserted, target.add_order(o2.clone()).0);
+        o2.set_id(target.get_order_id());
 
-        o1.set_id(target.get_order_id()); // fix the order id
-        assert_eq!(disseminator.borrow().new_orders.borrow()[0], o1);
+        // o2 joins o1's price level, behind it
+        o2.price = 1000;
+        assert_eq!(OrderState::Inserted, target.modify_order(o2).0);
+        // o1 moves away and back, now behind o2
+        o1.price = 999;
+        assert_eq!(OrderState::Inserted, target.modify_order(o1.clone()).0);
+        o1.set_id(target.get_order_id());
+        o1.price = 1000;
+        assert_eq!(OrderState::Inserted, target.modify_order(o1).0);
+
+        let bids = target.generate_bids();
+        assert_eq!(2, bids.len());
+        assert_eq!(200, bids[0].quantity);
+        assert_eq!(100, bids[1].quantity);
+        assert!(bids[0].get_sequence() < bids[1].get_sequence());
     }
 
     #[test]
-    fn cancel_invalid_order() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn modify_quantity_up_loses_queue_position() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        i.write().unwrap().set_percentage_bands(10);
 
-        let o1 = Order::new(
-            1000,
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        let o2 = Order::new(1001, i.clone(), 1000, 200, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        let aggressor = Order::new(1002, i.clone(), 1000, 150, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
         assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        assert_eq!(OrderState::Rejected, target.cancel_order(&o1));
+        o1.set_id(target.get_order_id());
+        assert_eq!(OrderState::Inserted, target.add_order(o2).0);
+
+        o1.quantity = 300;
+        assert_eq!(OrderState::Modified, target.modify_order(o1).0);
+        let asks = target.generate_asks();
+        assert_eq!(1001, asks[0].participant);
+        assert_eq!(1000, asks[1].participant);
+
+        // the order that kept its priority trades first
+        assert_eq!(OrderState::Traded, target.add_order(aggressor).0);
+        let asks = target.generate_asks();
+        assert_eq!(2, asks.len());
+        assert_eq!(50, asks[0].quantity);
+        assert_eq!(300, asks[1].quantity);
     }
 
     #[test]
-    fn cancel_valid_order() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn partial_fill_keeps_queue_position() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
 
-        let mut o1 = Order::new(
-            1000,
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        assert_eq!(OrderState::Cancelled, target.cancel_order(&o1));
+        for participant in [1000, 1001] {
+            let o = Order::new(participant, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+                .with_session(100, 2000);
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+        let aggressor = Order::new(1002, i.clone(), 1000, 50, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Traded, target.add_order(aggressor).0);
+
+        let bids = target.generate_bids();
+        assert_eq!(1000, bids[0].participant);
+        assert_eq!(50, bids[0].quantity);
+        assert_eq!(1001, bids[1].participant);
+        assert!(bids[0].get_sequence() < bids[1].get_sequence());
     }
 
     #[test]
-    fn cancel_invalid_order_side() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn publish_instrument_and_market() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        i.write().unwrap().set_percentage_bands(10);
 
-        let mut o1 = Order::new(
-            1000,
+        let mut o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let mut o2 = Order::new(1001, i.clone(), 990, 200, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
         assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
         o1.set_id(target.get_order_id());
-        o1.side = Side::Ask;
-        assert_eq!(OrderState::Rejected, target.cancel_order(&o1));
+        assert_eq!(OrderState::Inserted, target.add_order(o2.clone()).0);
+        o2.set_id(target.get_order_id());
+
+        disseminator.lock().unwrap().sequence.set(42);
+        let snapshot_disseminator = MockDisseminator::new();
+        let r = target.publish_snapshot(&snapshot_disseminator);
+        assert!(r.is_ok());
+        assert_eq!(4, r.unwrap());
+        assert_eq!(
+            vec![SnapshotHeader {
+                book_id: 500,
+                next_seq: 42,
+                order_count: 2,
+            }],
+            *snapshot_disseminator.snapshot_headers.borrow()
+        );
+        assert_eq!(1, snapshot_disseminator.instrument_info.borrow().len());
+        assert_eq!(2, snapshot_disseminator.market_orders.borrow().len());
+        // nothing goes on the incremental feed
+        assert!(disseminator
+            .lock()
+            .unwrap()
+            .market_orders
+            .borrow()
+            .is_empty());
     }
 
     #[test]
-    fn cancel_publishes() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn publish_checksum() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        i.write().unwrap().set_percentage_bands(10);
 
-        let mut o1 = Order::new(
-            1000,
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        assert_eq!(OrderState::Cancelled, target.cancel_order(&o1));
-        assert_eq!(1, disseminator.borrow().new_orders.borrow().len());
-        assert_eq!(1, disseminator.borrow().cancels.borrow().len());
+        for (price, quantity, side) in [
+            (1000, 100, Side::Bid),
+            (1000, 50, Side::Bid),
+            (990, 200, Side::Bid),
+            (1010, 300, Side::Ask),
+        ] {
+            let o = Order::new(1000, i.clone(), price, quantity, side, OrderType::Day)
+                .with_session(100, 2000);
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
+
+        assert!(target.publish_checksum().is_ok());
+        let checksums = disseminator.lock().unwrap().checksums.borrow().clone();
+        assert_eq!(1, checksums.len());
+        assert_eq!(500, checksums[0].book_id);
+        assert!(checksums[0].verify(&[(1000, 150), (990, 200)], &[(1010, 300)]));
+        assert!(!checksums[0].verify(&[(1000, 100), (990, 200)], &[(1010, 300)]));
     }
 
     #[test]
-    fn modify_invalid_order() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn session_statistics() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        i.write().unwrap().set_percentage_bands(10);
 
-        let o1 = Order::new(
-            1000,
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        assert_eq!(OrderState::Rejected, target.modify_order(o1).0);
+        for (price, quantity) in [(1000, 100), (1020, 50), (990, 150)] {
+            let bid = Order::new(1000, i.clone(), price, quantity, Side::Bid, OrderType::Day)
+                .with_session(100, 2000);
+            let ask = Order::new(1001, i.clone(), price, quantity, Side::Ask, OrderType::Day)
+                .with_session(100, 2000);
+            assert_eq!(OrderState::Inserted, target.add_order(bid).0);
+            assert_eq!(OrderState::Traded, target.add_order(ask).0);
+        }
+
+        let statistics = target.get_statistics();
+        assert_eq!(500, { statistics.book_id });
+        assert_eq!(1000, { statistics.open });
+        assert_eq!(1020, { statistics.high });
+        assert_eq!(990, { statistics.low });
+        assert_eq!(990, { statistics.last_price });
+        assert_eq!(0, { statistics.close });
+        assert_eq!(300, { statistics.volume });
+        assert_eq!(998, { statistics.vwap });
+        assert_eq!(3, { statistics.trade_count });
+
+        assert!(target.publish_statistics().is_ok());
+        assert_eq!(1, disseminator.lock().unwrap().statistics.borrow().len());
+
+        // the last statistics of the session go out with the close
+        target.close();
+        let published = disseminator.lock().unwrap().statistics.borrow().clone();
+        assert_eq!(2, published.len());
+        assert_eq!(990, { published[1].close });
+        assert_eq!(300, { published[1].volume });
+        assert_eq!(0, { target.get_statistics().volume });
     }
 
     #[test]
-    fn modify_valid_order() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn trade_captures_name_the_participants() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        i.write().unwrap().set_percentage_bands(10);
 
-        let mut o1 = Order::new(
-            1000,
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        o1.price = 990;
-        assert_eq!(OrderState::Inserted, target.modify_order(o1).0);
+        let ask = Order::new(1001, i.clone(), 1000, 100, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        let bid = Order::new(1000, i.clone(), 1000, 60, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Inserted, target.add_order(ask).0);
+        assert!(target.take_trade_captures().is_empty());
+        assert_eq!(OrderState::Traded, target.add_order(bid).0);
+
+        let captures = target.take_trade_captures();
+        assert_eq!(1, captures.len());
+        assert_eq!(500, { captures[0].book_id });
+        assert_eq!(1000, { captures[0].price });
+        assert_eq!(60, { captures[0].quantity });
+        assert_eq!(1000, { captures[0].buyer });
+        assert_eq!(1001, { captures[0].seller });
+        assert_ne!(0, { captures[0].timestamp });
+        // sequenced by the clearing connection
+        assert_eq!(0, { captures[0].seq });
+        assert!(target.take_trade_captures().is_empty());
+    }
 
-        let bids = target.generate_bids();
-        assert_eq!(1, bids.len());
-        assert_eq!(990, bids[0].price);
+    #[test]
+    fn exposure_blocks() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let mut target = Market::new(
+            i,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        assert!(!target.is_exposure_blocked(1000, Side::Bid));
+        target.set_exposure_block(1000, Some(Side::Bid));
+        assert!(target.is_exposure_blocked(1000, Side::Bid));
+        assert!(!target.is_exposure_blocked(1000, Side::Ask));
+        assert!(!target.is_exposure_blocked(1001, Side::Bid));
+        target.set_exposure_block(1000, None);
+        assert!(!target.is_exposure_blocked(1000, Side::Bid));
+    }
+
+    #[test]
+    fn dissemination_failures_dont_stop_matching() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        i.write().unwrap().set_percentage_bands(10);
+
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
+            i.clone(),
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        target.set_state_trading();
+        disseminator
+            .lock()
+            .unwrap()
+            .failure
+            .set(Some(DisseminateError::Disconnected));
+
+        let bid = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let ask = Order::new(1001, i.clone(), 1000, 40, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Inserted, target.add_order(bid).0);
+        assert_eq!(OrderState::Traded, target.add_order(ask).0);
+        assert!(disseminator.lock().unwrap().trades.borrow().is_empty());
+        assert_eq!(60, target.generate_bids()[0].quantity);
+
+        // back to normal
+        disseminator.lock().unwrap().failure.set(None);
+        let ask = Order::new(1001, i.clone(), 1000, 60, Side::Ask, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Traded, target.add_order(ask).0);
+        assert_eq!(1, disseminator.lock().unwrap().trades.borrow().len());
+
+        // the cancel of the day order lost at the close, the rest goes out
+        let bid = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        assert_eq!(OrderState::Inserted, target.add_order(bid).0);
+        disseminator
+            .lock()
+            .unwrap()
+            .faults
+            .set_mode(FailureMode::Nth(1));
+        target.close();
+        assert_eq!(1, target.take_closed_out_orders().len());
+        assert!(target.generate_bids().is_empty());
+        let binding = disseminator.lock().unwrap();
+        assert!(binding.cancels.borrow().is_empty());
+        assert_eq!(1, binding.eod_summaries.borrow().len());
+        assert_eq!(1, binding.faults.failures());
     }
 
     #[test]
-    fn modify_to_zero_quantity_reject() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn cancel_all_orders_for_session() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
-
-        let mut o1 = Order::new(
-            1000,
+        i.write().unwrap().set_percentage_bands(10);
+
+        let o1 = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o2 = Order::new(1000, i.clone(), 990, 200, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let o3 = Order::new(1001, i.clone(), 1010, 300, Side::Ask, OrderType::Day)
+            .with_session(100, 2001);
+        let o4 = Order::new(1001, i.clone(), 1020, 400, Side::Ask, OrderType::Day)
+            .with_session(101, 2002);
+
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
         assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        o1.quantity = 0;
-        assert_eq!(OrderState::Rejected, target.modify_order(o1).0);
+        assert_eq!(OrderState::Inserted, target.add_order(o2.clone()).0);
+        assert_eq!(OrderState::Inserted, target.add_order(o3.clone()).0);
+        assert_eq!(OrderState::Inserted, target.add_order(o4.clone()).0);
+
+        // Verify initial state
+        assert_eq!(2, target.generate_bids().len());
+        assert_eq!(2, target.generate_asks().len());
+
+        // Cancel all orders for participant 1000, gateway 100, session 2000
+        let r = target.cancel_all_orders_for_session(1000, 100, 2000);
+        assert_eq!(2, r.len());
 
+        // Verify state after cancellation
         let bids = target.generate_bids();
+        let asks = target.generate_asks();
 
-        // let's make sure that the original order is still there
-        // and its price is the original one
-        assert_eq!(1, bids.len());
-        assert_eq!(1000, bids[0].price);
+        assert_eq!(0, bids.len());
+        assert_eq!(2, asks.len());
+
+        // Verify remaining orders
+        assert_eq!(1001, asks[0].participant);
+        assert_eq!(1010, asks[0].price);
+        assert_eq!(300, asks[0].quantity);
+        assert_eq!(1001, asks[1].participant);
+        assert_eq!(1020, asks[1].price);
+        assert_eq!(400, asks[1].quantity);
     }
 
     #[test]
-    fn modify_cant_change_sides() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn cancel_all_orders_for_participant() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
+        i.write().unwrap().set_percentage_bands(10);
 
-        let mut o1 = Order::new(
-            1000,
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        o1.side = Side::Ask;
-        assert_eq!(OrderState::Rejected, target.modify_order(o1).0);
+        // participant 1000 has orders on two sessions, 1001 has one
+        for (participant, price, side, session_id) in [
+            (1000, 990, Side::Bid, 2000),
+            (1000, 1010, Side::Ask, 2000),
+            (1000, 1020, Side::Ask, 2001),
+            (1001, 1030, Side::Ask, 2002),
+        ] {
+            let o = Order::new(participant, i.clone(), price, 100, side, OrderType::Day)
+                .with_session(100, session_id);
+            assert_eq!(OrderState::Inserted, target.add_order(o).0);
+        }
 
-        // let's make sure that the original order is still there
+        // only the asks, but of all the sessions
+        let r = target.cancel_all_orders_for_participant(1000, Some(Side::Ask));
+        assert_eq!(2, r.len());
+        assert!(r
+            .iter()
+            .all(|(_, book_id, side)| *book_id == 500 && *side == Side::Ask));
         assert_eq!(1, target.generate_bids().len());
-        assert_eq!(0, target.generate_asks().len());
+        assert_eq!(1, target.generate_asks().len());
+
+        // and now everything that is left
+        let r = target.cancel_all_orders_for_participant(1000, None);
+        assert_eq!(1, r.len());
+        assert_eq!(Side::Bid, r[0].2);
+        assert_eq!(0, target.generate_bids().len());
+        assert_eq!(1001, target.generate_asks()[0].participant);
     }
 
     #[test]
-    fn modify_cant_change_order_type() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn replace_order() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
-
-        let mut o1 = Order::new(
-            1000,
+        i.write().unwrap().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        o1.order_type = OrderType::FillOrKill;
-        assert_eq!(OrderState::Rejected, target.modify_order(o1).0);
+        let mut old = Order::new(1000, i.clone(), 1000, 100, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let (state, old_id) = target.add_order(old.clone());
+        assert_eq!(OrderState::Inserted, state);
+        old.set_id(old_id);
 
-        // let's make sure that the original order is still there
-        assert_eq!(1, target.generate_bids().len());
+        // the order type can change
+        let new = Order::new(
+            1000,
+            i.clone(),
+            1010,
+            200,
+            Side::Bid,
+            OrderType::GoodTillCancel,
+        )
+        .with_session(100, 2000);
+        let (cancelled, state, new_id) = target.replace_order(&old, new);
+        assert_eq!(OrderState::Cancelled, cancelled);
+        assert_eq!(OrderState::Inserted, state);
+        assert_ne!(old_id, new_id);
+        assert!(target.get_order(old_id).is_none());
+        let resting = target.get_order(new_id).unwrap();
+        assert_eq!(OrderType::GoodTillCancel, resting.order_type);
+        assert_eq!(1010, resting.price);
+        assert_eq!(1, disseminator.lock().unwrap().cancels.borrow().len());
+        assert_eq!(2, disseminator.lock().unwrap().new_orders.borrow().len());
+
+        // nothing happens when the old order is gone, or when the new one is malformed
+        let (cancelled, state, _) = target.replace_order(
+            &old,
+            Order::new(1000, i.clone(), 1020, 200, Side::Bid, OrderType::Day)
+                .with_session(100, 2000),
+        );
+        assert_eq!(OrderState::Rejected, cancelled);
+        assert_eq!(OrderState::Rejected, state);
+
+        let mut old = target.get_order(new_id).unwrap().clone();
+        old.set_id(new_id);
+        for new in [
+            Order::new(1000, i.clone(), 1020, 0, Side::Bid, OrderType::Day).with_session(100, 2000),
+            Order::new(1000, i.clone(), 1020, 200, Side::Ask, OrderType::Day)
+                .with_session(100, 2000),
+        ] {
+            let (cancelled, state, _) = target.replace_order(&old, new);
+            assert_eq!(OrderState::Rejected, cancelled);
+            assert_eq!(OrderState::Rejected, state);
+        }
+        assert!(target.get_order(new_id).is_some());
     }
 
     #[test]
-    fn modify_quantity_doesnt_change_position() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn quotes() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
-
-        let mut o1 = Order::new(
-            1000,
-            i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let mut o2 = Order::new(
-            1000,
+        i.write().unwrap().set_percentage_bands(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            200,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
+        let side = |price, quantity, side| {
+            Some(
+                Order::new(
+                    1000,
+                    i.clone(),
+                    price,
+                    quantity,
+                    side,
+                    OrderType::PostOrKill,
+                )
+                .with_session(100, 2000),
+            )
+        };
+        let (state, _) = target.add_order(
+            Order::new(2000, i.clone(), 1020, 100, Side::Ask, OrderType::Day)
+                .with_session(100, 3000),
+        );
+        assert_eq!(OrderState::Inserted, state);
+        let (state, _) = target.add_order(
+            Order::new(2000, i.clone(), 990, 100, Side::Bid, OrderType::Day)
+                .with_session(100, 3000),
+        );
+        assert_eq!(OrderState::Inserted, state);
+
+        let (state, bid_id, ask_id) =
+            target.quote(1000, side(1000, 100, Side::Bid), side(1010, 100, Side::Ask));
+        assert_eq!(OrderState::QuoteAck, state);
+        assert_eq!((bid_id, ask_id), target.get_quote(1000));
+        assert_eq!(1010, target.generate_asks()[0].price);
+        assert_eq!(4, disseminator.lock().unwrap().new_orders.borrow().len());
+
+        // a smaller bid at the same price stays where it was, the ask moves
+        let (state, bid, ask) =
+            target.quote(1000, side(1000, 50, Side::Bid), side(1020, 100, Side::Ask));
+        assert_eq!((OrderState::QuoteAck, bid_id), (state, bid));
+        assert_ne!(ask_id, ask);
+        assert!(target.get_order(ask_id).is_none());
+        assert_eq!(50, target.get_order(bid_id).unwrap().quantity);
+        assert_eq!(1, disseminator.lock().unwrap().modifies.borrow().len());
+        assert_eq!(1, disseminator.lock().unwrap().cancels.borrow().len());
+        // behind the ask already there
+        assert_eq!(2000, target.generate_asks()[0].participant);
+
+        // the bid can go up to where the previous ask was, the ask being pulled
+        let (state, new_bid, new_ask) = target.quote(1000, side(1010, 50, Side::Bid), None);
+        assert_eq!((OrderState::QuoteAck, 0), (state, new_ask));
+        assert!(target.get_order(bid_id).is_none() && target.get_order(ask).is_none());
+        assert_eq!(1010, target.get_order(new_bid).unwrap().price);
+        assert_eq!(1, target.generate_asks().len());
+
+        // all or nothing: crossing, out of the bands, bid over ask, malformed
+        for (bid, ask) in [
+            (side(1020, 50, Side::Bid), side(1030, 100, Side::Ask)),
+            (side(1000, 50, Side::Bid), side(1500, 100, Side::Ask)),
+            (side(1005, 50, Side::Bid), side(1005, 100, Side::Ask)),
+            (side(1000, 50, Side::Ask), None),
+            (side(1000, 0, Side::Bid), side(1015, 100, Side::Ask)),
+        ] {
+            assert_eq!((OrderState::Rejected, 0, 0), target.quote(1000, bid, ask));
+        }
+        assert_eq!((new_bid, 0), target.get_quote(1000));
+        assert_eq!(1010, target.generate_bids()[0].price);
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        assert_eq!(OrderState::Inserted, target.add_order(o2.clone()).0);
-        o2.set_id(target.get_order_id());
-
-        // modify the first order quantity should keep it in the first position
-        o1.quantity = 50;
-        assert_eq!(OrderState::Modified, target.modify_order(o1).0);
+        // pulled altogether
+        assert_eq!((OrderState::QuoteAck, 0, 0), target.quote(1000, None, None));
+        assert_eq!(990, target.generate_bids()[0].price);
+        assert_eq!((0, 0), target.get_quote(1000));
 
-        let bids = target.generate_bids();
-        assert_eq!(2, bids.len());
-        assert_eq!(50, bids[0].quantity);
-        assert_eq!(200, bids[1].quantity);
+        let (_, bid_id, ask_id) =
+            target.quote(1000, side(1000, 50, Side::Bid), side(1020, 100, Side::Ask));
+        target.change_state(InstrumentState::Halted);
+        assert_eq!(
+            OrderState::Rejected,
+            target.quote(1000, side(1000, 50, Side::Bid), None).0
+        );
+        // pulled while halted, the other orders staying
+        let mut cancelled = target.cancel_quote(1000);
+        cancelled.sort_by_key(|c| c.0);
+        assert_eq!(
+            vec![(bid_id, 500, Side::Bid), (ask_id, 500, Side::Ask)],
+            cancelled
+        );
+        assert_eq!((0, 0), target.get_quote(1000));
+        assert_eq!(990, target.generate_bids()[0].price);
+        assert!(target.cancel_quote(1000).is_empty());
     }
 
     #[test]
-    fn modify_valid_order_invalid_side() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn tick_size_and_round_lot() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
-
-        let mut o1 = Order::new(
-            1000,
+        i.write().unwrap().set_tick_size(5);
+        i.write().unwrap().set_round_lot(100);
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        o1.side = Side::Ask;
-        assert_eq!(OrderState::Rejected, target.modify_order(o1).0);
+        // off tick
+        let (state, _) = target.add_order(
+            Order::new(1000, i.clone(), 1002, 100, Side::Bid, OrderType::Day)
+                .with_session(100, 2000),
+        );
+        assert_eq!(OrderState::Rejected, state);
+
+        // not a round lot
+        let (state, _) = target.add_order(
+            Order::new(1000, i.clone(), 1000, 150, Side::Bid, OrderType::Day)
+                .with_session(100, 2000),
+        );
+        assert_eq!(OrderState::Rejected, state);
+
+        let mut order = Order::new(1000, i.clone(), 1005, 300, Side::Bid, OrderType::Day)
+            .with_session(100, 2000);
+        let (state, id) = target.add_order(order.clone());
+        assert_eq!(OrderState::Inserted, state);
+        order.set_id(id);
+
+        // the modifies have to stay on the grid too
+        order.price = 1007;
+        let (state, _) = target.modify_order(order.clone());
+        assert_eq!(OrderState::Rejected, state);
+        order.price = 1010;
+        order.quantity = 250;
+        let (state, _) = target.modify_order(order.clone());
+        assert_eq!(OrderState::Rejected, state);
+        assert_eq!(1005, target.get_order(id).unwrap().price);
     }
 
     #[test]
-    fn modify_valid_order_publish_delete_new() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn variation_limit_halts_trading() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
-
-        let mut o1 = Order::new(
-            1000,
+        i.write().unwrap().set_percentage_bands(50);
+        i.write().unwrap().set_percentage_variation_allowed(10);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        o1.price += 1;
-        assert_eq!(OrderState::Inserted, target.modify_order(o1).0);
+        let order = |price, quantity, side| {
+            Order::new(1000, i.clone(), price, quantity, side, OrderType::Day)
+                .with_session(100, 2000)
+        };
+
+        // the first trade sets the reference price
+        target.add_order(order(1000, 100, Side::Ask));
+        let (state, _) = target.add_order(order(1000, 100, Side::Bid));
+        assert_eq!(OrderState::Traded, state);
+        assert_eq!(1000, target.get_reference_price());
+
+        // trading up to the limit is fine
+        target.add_order(order(1100, 100, Side::Ask));
+        target.add_order(order(1150, 100, Side::Ask));
+        target.add_order(order(1000, 100, Side::Bid));
+        let (state, _) = target.add_order(order(1150, 200, Side::Bid));
+        assert_eq!(OrderState::PartiallyTraded, state);
+        assert_eq!(2, disseminator.lock().unwrap().trades.borrow().len());
+
+        // but the market went into auction instead of trading at 1150
+        assert_eq!(InstrumentState::Auction, target.get_state());
+        assert_eq!(
+            1,
+            disseminator.lock().unwrap().instrument_info.borrow().len()
+        );
+        assert_eq!(
+            InstrumentState::Auction,
+            disseminator.lock().unwrap().instrument_info.borrow()[0].get_state()
+        );
+        assert_eq!(1, target.generate_asks().len());
+        assert_eq!(2, target.generate_bids().len());
+        assert_eq!(1150, { target.get_auction_info().price });
 
-        assert_eq!(1, disseminator.borrow().cancels.borrow().len());
-        assert_eq!(2, disseminator.borrow().new_orders.borrow().len());
+        // the closing resets the reference
+        target.close();
+        assert_eq!(0, target.get_reference_price());
     }
 
     #[test]
-    fn modify_quantity_publish_modify() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn volatility_interruption() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
-
-        let mut o1 = Order::new(
-            1000,
+        i.write().unwrap().set_percentage_bands(50);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
+        target.set_volatility_config(VolatilityConfig {
+            percentage: 5,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+        });
         target.set_state_trading();
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        o1.quantity += 100;
-        assert_eq!(OrderState::Modified, target.modify_order(o1).0);
+        let order = |price, side| {
+            Order::new(1000, i.clone(), price, 100, side, OrderType::Day).with_session(100, 2000)
+        };
+
+        target.add_order(order(1000, Side::Ask));
+        let (state, _) = target.add_order(order(1000, Side::Bid));
+        assert_eq!(OrderState::Traded, state);
+
+        // 10% away from the reference, the trading is halted instead
+        target.add_order(order(1100, Side::Ask));
+        let (state, _) = target.add_order(order(1100, Side::Bid));
+        assert_eq!(OrderState::Cancelled, state);
+        assert_eq!(1, disseminator.lock().unwrap().trades.borrow().len());
+        assert_eq!(InstrumentState::Halted, target.get_state());
+        assert_eq!(
+            InstrumentState::Halted,
+            disseminator.lock().unwrap().instrument_info.borrow()[0].get_state()
+        );
 
-        assert_eq!(1, disseminator.borrow().modifies.borrow().len());
+        // no new orders during the halt
+        let (state, _) = target.add_order(order(1100, Side::Bid));
+        assert_eq!(OrderState::Rejected, state);
+        let (state, _) = target.add_order(order(990, Side::Bid));
+        assert_eq!(OrderState::Rejected, state);
+
+        // until the cooldown is over
+        assert!(!target.resume_trading(0));
+        assert_eq!(InstrumentState::Halted, target.get_state());
+        assert!(target.resume_trading(u64::MAX));
+        assert_eq!(InstrumentState::Trading, target.get_state());
+        assert_eq!(
+            2,
+            disseminator.lock().unwrap().instrument_info.borrow().len()
+        );
+        let changes = disseminator.lock().unwrap().state_changes.borrow().clone();
+        assert_eq!(
+            vec![
+                (StateChangeReason::Volatility, InstrumentState::Halted),
+                (StateChangeReason::Resumed, InstrumentState::Trading)
+            ],
+            changes
+                .iter()
+                .map(|c| (c.get_reason(), InstrumentState::from(c.state)))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(InstrumentState::Trading, changes[0].previous_state.into());
     }
 
     #[test]
-    fn publish_instrument_and_market() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn change_state() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
-
-        let mut o1 = Order::new(
-            1000,
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let mut o2 = Order::new(
-            1001,
-            i.clone(),
-            990,
-            200,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
+        assert!(target.change_state(InstrumentState::Auction).is_none());
+        assert!(target.change_state(InstrumentState::Auction).is_none());
+        assert_eq!(
+            1,
+            disseminator.lock().unwrap().instrument_info.borrow().len()
         );
 
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
-        target.set_state_trading();
+        for side in [Side::Bid, Side::Ask] {
+            let (state, _) = target.add_order(
+                Order::new(1000, i.clone(), 1000, 100, side, OrderType::Day)
+                    .with_session(100, 2000),
+            );
+            assert_eq!(OrderState::Inserted, state);
+        }
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        o1.set_id(target.get_order_id());
-        assert_eq!(OrderState::Inserted, target.add_order(o2.clone()).0);
-        o2.set_id(target.get_order_id());
+        // the open auction uncrosses when the trading starts
+        assert!(target.change_state(InstrumentState::Trading).is_none());
+        assert_eq!(1, disseminator.lock().unwrap().trades.borrow().len());
+        assert_eq!(2, target.take_passive_fills().len());
 
-        let r = target.publish_snapshot();
-        assert!(r.is_ok());
-        assert_eq!(3, r.unwrap());
-        assert_eq!(1, disseminator.borrow().instrument_info.borrow().len());
-        assert_eq!(2, disseminator.borrow().market_orders.borrow().len());
+        let summary = target.change_state(InstrumentState::Closed).unwrap();
+        assert_eq!(100, { summary.volume });
+        assert_eq!(InstrumentState::Closed, target.get_state());
+        assert_eq!(
+            3,
+            disseminator.lock().unwrap().instrument_info.borrow().len()
+        );
+        let changes = disseminator.lock().unwrap().state_changes.borrow().clone();
+        assert_eq!(3, changes.len());
+        assert_eq!(
+            (
+                StateChangeReason::Schedule,
+                InstrumentState::Trading,
+                InstrumentState::Closed
+            ),
+            (
+                changes[2].get_reason(),
+                changes[2].previous_state.into(),
+                changes[2].state.into()
+            )
+        );
     }
 
     #[test]
-    fn cancel_all_orders_for_session() {
-        let i = Rc::new(RefCell::new(Instrument::new_fast(
+    fn acceptance_per_state() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
             500,
             InstrumentType::Share,
         )));
-        i.borrow_mut().set_percentage_bands(10);
-
-        let o1 = Order::new(
-            1000,
-            i.clone(),
-            1000,
-            100,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o2 = Order::new(
-            1000,
-            i.clone(),
-            990,
-            200,
-            Side::Bid,
-            OrderType::Day,
-            100,
-            2000,
-        );
-        let o3 = Order::new(
-            1001,
+        i.write().unwrap().set_state(InstrumentState::PreOpen);
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1010,
-            300,
-            Side::Ask,
-            OrderType::Day,
-            100,
-            2001,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-        let o4 = Order::new(
-            1001,
+        let order = |price, side, order_type| {
+            Order::new(1000, i.clone(), price, 100, side, order_type).with_session(100, 2000)
+        };
+
+        // pre open: only the orders that don't cross the book
+        let (state, _) = target.add_order(order(1000, Side::Bid, OrderType::Day));
+        assert_eq!(OrderState::Inserted, state);
+        let (state, _) = target.add_order(order(1010, Side::Ask, OrderType::Day));
+        assert_eq!(OrderState::Inserted, state);
+        let (state, _) = target.add_order(order(1000, Side::Ask, OrderType::Day));
+        assert_eq!(OrderState::Rejected, state);
+        let (state, _) = target.add_order(order(0, Side::Ask, OrderType::Market));
+        assert_eq!(OrderState::Rejected, state);
+        assert!(disseminator.lock().unwrap().trades.borrow().is_empty());
+
+        // halted: only the cancels
+        i.write().unwrap().set_state(InstrumentState::Halted);
+        let (state, _) = target.add_order(order(990, Side::Bid, OrderType::Day));
+        assert_eq!(OrderState::Rejected, state);
+        let mut resting = target.generate_bids()[0].clone();
+        resting.quantity = 50;
+        let (state, _) = target.modify_order(resting.clone());
+        assert_eq!(OrderState::Rejected, state);
+        let (cancelled, state, _) =
+            target.replace_order(&resting, order(990, Side::Bid, OrderType::Day));
+        assert_eq!(OrderState::Rejected, cancelled);
+        assert_eq!(OrderState::Rejected, state);
+        assert_eq!(OrderState::Cancelled, target.cancel_order(&resting));
+        assert!(target.generate_bids().is_empty());
+    }
+
+    #[test]
+    fn market_moves_to_another_thread() {
+        let i = Arc::new(RwLock::new(Instrument::new_fast(
+            500,
+            InstrumentType::Share,
+        )));
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut target = Market::new(
             i.clone(),
-            1020,
-            400,
-            Side::Ask,
-            OrderType::Day,
-            101,
-            2002,
+            disseminator.clone(),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         );
-
-        let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
-        let mut target = Market::new(i.clone(), disseminator.clone());
         target.set_state_trading();
 
-        assert_eq!(OrderState::Inserted, target.add_order(o1.clone()).0);
-        assert_eq!(OrderState::Inserted, target.add_order(o2.clone()).0);
-        assert_eq!(OrderState::Inserted, target.add_order(o3.clone()).0);
-        assert_eq!(OrderState::Inserted, target.add_order(o4.clone()).0);
-
-        // Verify initial state
-        assert_eq!(2, target.generate_bids().len());
-        assert_eq!(2, target.generate_asks().len());
-
-        // Cancel all orders for participant 1000, gateway 100, session 2000
-        let r = target.cancel_all_orders_for_session(1000, 100, 2000);
-        assert_eq!(2, r.len());
-
-        // Verify state after cancellation
-        let bids = target.generate_bids();
-        let asks = target.generate_asks();
-
-        assert_eq!(0, bids.len());
-        assert_eq!(2, asks.len());
-
-        // Verify remaining orders
-        assert_eq!(1001, asks[0].participant);
-        assert_eq!(1010, asks[0].price);
-        assert_eq!(300, asks[0].quantity);
-        assert_eq!(1001, asks[1].participant);
-        assert_eq!(1020, asks[1].price);
-        assert_eq!(400, asks[1].quantity);
-
-        // Verify operation counters
-        assert_eq!(6, target.bids_ops);
-        assert_eq!(2, target.asks_ops);
+        let o = Order::new(1000, i, 123, 100, Side::Bid, OrderType::Day).with_session(100, 2000);
+        let (state, _) = std::thread::spawn(move || target.add_order(o))
+            .join()
+            .unwrap();
+        assert_eq!(OrderState::Inserted, state);
+        // the disseminator stays shared with the market
+        assert_eq!(1, disseminator.lock().unwrap().new_orders.borrow().len());
     }
 }
@@ -0,0 +1,136 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SEQUENCE_BITS: u32 = 32;
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+
+/// Hands out the order ids for all the markets of an engine
+///
+/// An id is made of an epoch in the upper 32 bits and a sequence in the lower 32 bits.
+/// The epoch is the time the generator was started at, so the ids stay unique
+/// across books and across restarts of the engine, as long as two runs don't start
+/// in the same second.
+/// The engines running several shards give each of them its own generator, the
+/// sequences of a shard being the ones equal to its index, modulo the shard count.
+#[derive(Debug, Clone)]
+pub struct OrderIdGenerator {
+    epoch: u64,
+    sequence: u64,
+    shard: u64,
+    shards: u64,
+}
+
+impl OrderIdGenerator {
+    pub fn new(epoch: u32) -> Self {
+        Self {
+            epoch: epoch as u64,
+            sequence: 0,
+            shard: 0,
+            shards: 1,
+        }
+    }
+
+    /// Restricts the ids to the ones of @shard, out of @shards, so that the
+    /// shards of an engine never hand out the same id
+    pub fn for_shard(mut self, shard: usize, shards: usize) -> Self {
+        assert!(shard < shards, "Shard {shard} out of {shards}");
+        self.shard = shard as u64;
+        self.shards = shards as u64;
+        self
+    }
+
+    /// A generator whose epoch is the current unix timestamp, in seconds
+    pub fn from_clock() -> Self {
+        Self::new(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as u32,
+        )
+    }
+
+    /// The upper 32 bits of the ids handed out next
+    pub fn epoch(&self) -> u32 {
+        self.epoch as u32
+    }
+
+    /// How far the generator got, for `resume` to go on from there after a restart
+    pub fn get_sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Goes on after the last id handed out by a generator at @epoch and @sequence,
+    /// as given by `epoch` and `get_sequence`
+    pub fn resume(&mut self, epoch: u32, sequence: u64) {
+        self.epoch = epoch as u64;
+        self.sequence = sequence;
+    }
+
+    pub fn next_id(&mut self) -> u64 {
+        self.sequence += 1;
+        if self.id_sequence() > MAX_SEQUENCE {
+            // moving to the next epoch is safe, it takes way more than a second
+            // to run out of sequences
+            self.epoch += 1;
+            self.sequence = 1;
+        }
+        (self.epoch << SEQUENCE_BITS) | self.id_sequence()
+    }
+
+    // the lower bits of the id, the sequence spread over the shards
+    fn id_sequence(&self) -> u64 {
+        (self.sequence - 1) * self.shards + self.shard + 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ids_carry_the_epoch() {
+        let mut target = OrderIdGenerator::new(0);
+        assert_eq!(1, target.next_id());
+        assert_eq!(2, target.next_id());
+
+        let mut target = OrderIdGenerator::new(7);
+        assert_eq!(7, target.epoch());
+        assert_eq!((7 << 32) + 1, target.next_id());
+    }
+
+    #[test]
+    fn sequence_overflow_moves_to_next_epoch() {
+        let mut target = OrderIdGenerator::new(7);
+        target.sequence = MAX_SEQUENCE - 1;
+        assert_eq!((7 << 32) + MAX_SEQUENCE, target.next_id());
+        assert_eq!((8 << 32) + 1, target.next_id());
+    }
+
+    #[test]
+    fn shards_dont_share_ids() {
+        let mut first = OrderIdGenerator::new(7).for_shard(0, 3);
+        let mut last = OrderIdGenerator::new(7).for_shard(2, 3);
+        assert_eq!((7 << 32) + 1, first.next_id());
+        assert_eq!((7 << 32) + 4, first.next_id());
+        assert_eq!((7 << 32) + 3, last.next_id());
+        assert_eq!((7 << 32) + 6, last.next_id());
+
+        last.sequence = MAX_SEQUENCE / 3;
+        assert_eq!((8 << 32) + 3, last.next_id());
+    }
+
+    #[test]
+    fn resume_after_a_restart() {
+        let mut before = OrderIdGenerator::new(7).for_shard(1, 2);
+        before.next_id();
+        let mut after = OrderIdGenerator::new(9).for_shard(1, 2);
+        after.resume(before.epoch(), before.get_sequence());
+        assert_eq!(before.next_id(), after.next_id());
+    }
+
+    #[test]
+    fn restarts_dont_reuse_ids() {
+        let before = OrderIdGenerator::new(1000).next_id();
+        let after = OrderIdGenerator::new(1001).next_id();
+        assert!(after > before);
+    }
+}
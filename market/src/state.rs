@@ -0,0 +1,372 @@
+//! The state of a market, saved to be restored by a later run of the engine
+//!
+//! Everything the market needs to go on where it was is kept: the instrument,
+//! the orders resting in the book and waiting for their trigger, the order id
+//! and trade id counters, the statistics and the trades of the session, for
+//! them to be busted, and the volatility interruption state. What the market
+//! hands out and forgets, the passive fills and the trade captures, is not.
+//! The state is encoded as
+//!
+//! ```text
+//! | Instrument length (2) | Instrument (var) | Known state (1) | Order id (8) | Sequence (8) |
+//! | Trade id (8) | Reference price (8) | Halted until (8) | Statistics (72) |
+//! | Trade count (4) | then Trade count times: | Timestamp (8) | Price (8) |
+//! | Day trade count (4) | then Day trade count times: | Trade id (8) | Price (8) | Quantity (8) |
+//!     | Timestamp (8) | then for the bid and the ask: | Order id (8) | Participant (8) | Gateway (1) | Session (4) |
+//! | Block count (4) | then Block count times, by participant: | Participant (8) | Side (1) |
+//! | Quote count (4) | then Quote count times, by participant: | Participant (8) | Bid id (8) | Ask id (8) |
+//! | Bid count (4) | Ask count (4) | Stop count (4) | then the bids, asks and stops: | Order (88) |
+//! ```
+//!
+//! the bids and the asks in priority order, the stops in arrival order.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use disseminator::disseminator::Disseminator;
+use instruments::instrument::{Instrument, INSTRUMENT_FIXED_SIZE};
+use order::{Order, Side};
+
+use crate::{
+    book::BookSide,
+    orderid::OrderIdGenerator,
+    statistics::{SessionStatistics, STATISTICS_STATE_SIZE},
+    volatility::RollingReference,
+    DayTrade, Market, TradeParty,
+};
+
+const ORDER_STATE_SIZE: usize = 88;
+
+/// Reads the fields of an encoded state one after the other
+pub(crate) struct StateReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let r = self.buf.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(r)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+}
+
+fn encode_day_trade(trade: &DayTrade, r: &mut Vec<u8>) {
+    for value in [trade.trade_id, trade.price, trade.quantity, trade.timestamp] {
+        r.extend_from_slice(&value.to_le_bytes());
+    }
+    for party in [trade.bid, trade.ask] {
+        r.extend_from_slice(&party.order_id.to_le_bytes());
+        r.extend_from_slice(&party.participant.to_le_bytes());
+        r.push(party.gateway_id);
+        r.extend_from_slice(&party.session_id.to_le_bytes());
+    }
+}
+
+fn decode_trade_party(reader: &mut StateReader) -> Option<TradeParty> {
+    Some(TradeParty {
+        order_id: reader.u64()?,
+        participant: reader.u64()?,
+        gateway_id: reader.u8()?,
+        session_id: reader.u32()?,
+    })
+}
+
+fn decode_day_trade(reader: &mut StateReader) -> Option<DayTrade> {
+    Some(DayTrade {
+        trade_id: reader.u64()?,
+        price: reader.u64()?,
+        quantity: reader.u64()?,
+        timestamp: reader.u64()?,
+        bid: decode_trade_party(reader)?,
+        ask: decode_trade_party(reader)?,
+    })
+}
+
+fn encode_order(order: &Order, r: &mut Vec<u8>) {
+    r.extend_from_slice(&order.get_id().to_le_bytes());
+    r.extend_from_slice(&order.get_sequence().to_le_bytes());
+    r.extend_from_slice(&order.participant.to_le_bytes());
+    r.extend_from_slice(&order.price.to_le_bytes());
+    r.extend_from_slice(&order.quantity.to_le_bytes());
+    r.push(order.side.into());
+    r.extend_from_slice(&Into::<u16>::into(order.order_type).to_le_bytes());
+    r.push(order.gateway_id);
+    r.extend_from_slice(&order.session_id.to_le_bytes());
+    r.extend_from_slice(&order.expiry.to_le_bytes());
+    r.extend_from_slice(&order.stop_price.to_le_bytes());
+    r.extend_from_slice(&order.display_quantity.to_le_bytes());
+    r.extend_from_slice(&order.hidden_quantity.to_le_bytes());
+    r.extend_from_slice(&order.client_order_id.to_le_bytes());
+}
+
+fn decode_order(reader: &mut StateReader, instrument: &Arc<RwLock<Instrument>>) -> Option<Order> {
+    let (id, sequence, participant, price, quantity) = (
+        reader.u64()?,
+        reader.u64()?,
+        reader.u64()?,
+        reader.u64()?,
+        reader.u64()?,
+    );
+    let side = Side::from(reader.u8()?);
+    let order_type = reader.u16()?.into();
+    let (gateway_id, session_id) = (reader.u8()?, reader.u32()?);
+    let mut order = Order::new(
+        participant,
+        instrument.clone(),
+        price,
+        quantity,
+        side,
+        order_type,
+    )
+    .with_session(gateway_id, session_id);
+    order.set_id(id);
+    order.set_sequence(sequence);
+    order.expiry = reader.u64()?;
+    order.stop_price = reader.u64()?;
+    order.display_quantity = reader.u64()?;
+    order.hidden_quantity = reader.u64()?;
+    order.client_order_id = reader.u64()?;
+    Some(order)
+}
+
+impl Market {
+    /// The state of the market, for `decode_state` to bring it back
+    pub fn encode_state(&self) -> Vec<u8> {
+        let instrument = self.instrument.read().unwrap().encode();
+        let mut r = vec![];
+        r.extend_from_slice(&(instrument.len() as u16).to_le_bytes());
+        r.extend_from_slice(&instrument);
+        r.push(self.known_state.into());
+        for value in [
+            self.order_id,
+            self.sequence,
+            self.trade_id,
+            self.reference_price,
+            self.halted_until,
+        ] {
+            r.extend_from_slice(&value.to_le_bytes());
+        }
+        r.extend_from_slice(&self.statistics.encode());
+        r.append(&mut self.rolling_reference.encode());
+        r.extend_from_slice(&(self.day_trades.len() as u32).to_le_bytes());
+        for trade in &self.day_trades {
+            encode_day_trade(trade, &mut r);
+        }
+
+        // sorted, the same market always giving the same state
+        let mut blocks: Vec<_> = self.exposure_blocks.iter().collect();
+        blocks.sort_by_key(|(participant, _)| **participant);
+        r.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        for (participant, side) in blocks {
+            r.extend_from_slice(&participant.to_le_bytes());
+            r.push((*side).into());
+        }
+        let mut quotes: Vec<_> = self.quotes.iter().collect();
+        quotes.sort_by_key(|(participant, _)| **participant);
+        r.extend_from_slice(&(quotes.len() as u32).to_le_bytes());
+        for (participant, (bid, ask)) in quotes {
+            for value in [*participant, *bid, *ask] {
+                r.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        let (bids, asks) = (self.generate_bids(), self.generate_asks());
+        for count in [bids.len(), asks.len(), self.stops.len()] {
+            r.extend_from_slice(&(count as u32).to_le_bytes());
+        }
+        for order in bids
+            .iter()
+            .chain(asks.iter())
+            .copied()
+            .chain(self.stops.iter())
+        {
+            encode_order(order, &mut r);
+        }
+        r
+    }
+
+    /// A market as it was when @buf was encoded by `encode_state`, publishing
+    /// on @disseminator and taking the order ids from @order_ids
+    ///
+    /// Returns: None if @buf is not a valid state
+    pub fn decode_state(
+        buf: &[u8],
+        disseminator: Arc<Mutex<dyn Disseminator>>,
+        order_ids: Arc<Mutex<OrderIdGenerator>>,
+    ) -> Option<Self> {
+        let mut reader = StateReader::new(buf);
+        let instrument_len = reader.u16()? as usize;
+        if instrument_len < INSTRUMENT_FIXED_SIZE {
+            return None;
+        }
+        let instrument_bytes = reader.bytes(instrument_len)?;
+        // the name is checked, the decoding of the instrument insisting on it
+        std::str::from_utf8(&instrument_bytes[INSTRUMENT_FIXED_SIZE..]).ok()?;
+        let instrument = Arc::new(RwLock::new(Instrument::decode(instrument_bytes)));
+        let mut market = Market::new(instrument.clone(), disseminator, order_ids);
+        market.known_state = reader.u8()?.into();
+        market.order_id = reader.u64()?;
+        market.sequence = reader.u64()?;
+        market.trade_id = reader.u64()?;
+        market.reference_price = reader.u64()?;
+        market.halted_until = reader.u64()?;
+        market.statistics =
+            SessionStatistics::decode(reader.bytes(STATISTICS_STATE_SIZE)?.try_into().ok()?);
+        market.rolling_reference = RollingReference::decode(&mut reader)?;
+        for _ in 0..reader.u32()? {
+            market.day_trades.push(decode_day_trade(&mut reader)?);
+        }
+
+        for _ in 0..reader.u32()? {
+            let participant = reader.u64()?;
+            market
+                .exposure_blocks
+                .insert(participant, Side::from(reader.u8()?));
+        }
+        for _ in 0..reader.u32()? {
+            let participant = reader.u64()?;
+            market
+                .quotes
+                .insert(participant, (reader.u64()?, reader.u64()?));
+        }
+
+        let (bids, asks, stops) = (reader.u32()?, reader.u32()?, reader.u32()?);
+        let orders = (bids as usize)
+            .checked_add(asks as usize)?
+            .checked_add(stops as usize)?;
+        if buf.len() - reader.pos != orders.checked_mul(ORDER_STATE_SIZE)? {
+            return None;
+        }
+        market.bids = BookSide::new(Side::Bid);
+        for _ in 0..bids {
+            market.bids.insert(decode_order(&mut reader, &instrument)?);
+        }
+        market.asks = BookSide::new(Side::Ask);
+        for _ in 0..asks {
+            market.asks.insert(decode_order(&mut reader, &instrument)?);
+        }
+        for _ in 0..stops {
+            market.stops.push(decode_order(&mut reader, &instrument)?);
+        }
+        match reader.is_done() {
+            true => Some(market),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use disseminator::mockdisseminator::MockDisseminator;
+    use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+    use order::{Order, OrderState, OrderType, Side};
+
+    use crate::{orderid::OrderIdGenerator, Market};
+
+    fn order(i: &Arc<RwLock<Instrument>>, participant: u64, price: u64, side: Side) -> Order {
+        Order::new(participant, i.clone(), price, 100, side, OrderType::Day).with_session(1, 2)
+    }
+
+    #[test]
+    fn encode_decode_state() {
+        let i = Arc::new(RwLock::new(Instrument::new(
+            500,
+            "TEST",
+            InstrumentType::Share,
+            InstrumentState::Trading,
+            10,
+            20,
+        )));
+        let order_ids = Arc::new(Mutex::new(OrderIdGenerator::new(0)));
+        let mut target = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            order_ids.clone(),
+        );
+        target.add_order(order(&i, 1, 100, Side::Bid));
+        target.add_order(order(&i, 2, 101, Side::Bid));
+        let mut ask = order(&i, 3, 103, Side::Ask);
+        ask.client_order_id = 33;
+        let (_, ask_id) = target.add_order(ask);
+        // trades half of the best bid
+        let mut o = order(&i, 4, 101, Side::Ask);
+        o.quantity = 50;
+        target.add_order(o);
+        let mut stop = order(&i, 5, 0, Side::Ask);
+        stop.order_type = OrderType::StopLoss;
+        stop.stop_price = 90;
+        target.add_order(stop);
+        target.set_exposure_block(6, Some(Side::Bid));
+        let mut bid = order(&i, 8, 99, Side::Bid);
+        bid.order_type = OrderType::PostOrKill;
+        assert_eq!(OrderState::QuoteAck, target.quote(8, Some(bid), None).0);
+
+        let encoded = target.encode_state();
+        let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
+        let mut restored =
+            Market::decode_state(&encoded, disseminator.clone(), order_ids.clone()).unwrap();
+        assert_eq!(encoded, restored.encode_state());
+        assert_eq!(target.generate_bids(), restored.generate_bids());
+        assert_eq!(target.generate_asks(), restored.generate_asks());
+        assert_eq!(
+            Ok(ask_id),
+            restored.resolve_client_order_id(3, Side::Ask, 33)
+        );
+        assert_eq!(1, { restored.get_statistics().trade_count });
+        assert_eq!(target.get_day_trades(), restored.get_day_trades());
+        assert_eq!(1, restored.get_day_trades().len());
+        assert_eq!(101, { restored.get_statistics().vwap });
+        assert_eq!(101, restored.get_reference_price());
+        assert_eq!(InstrumentState::Trading, restored.get_state());
+        assert_eq!("TEST", restored.get_instrument().read().unwrap().get_name());
+        // the orders belong to the instrument of the restored market
+        assert!(Arc::ptr_eq(
+            &restored.get_instrument(),
+            &restored.generate_bids()[0].instrument
+        ));
+
+        // and it trades on from there
+        let mut o = order(&i, 7, 101, Side::Ask);
+        o.quantity = 50;
+        let r = restored.add_order(o);
+        assert_eq!(OrderState::Traded, r.0);
+        assert_eq!(1, disseminator.lock().unwrap().trades.borrow().len());
+        assert!(restored.is_exposure_blocked(6, Side::Bid));
+        assert_eq!(target.get_quote(8), restored.get_quote(8));
+
+        // damaged
+        assert!(Market::decode_state(
+            &encoded[..encoded.len() - 1],
+            disseminator.clone(),
+            order_ids.clone()
+        )
+        .is_none());
+        assert!(Market::decode_state(&[], disseminator, order_ids).is_none());
+    }
+}
@@ -0,0 +1,138 @@
+use oep::statistics::Statistics;
+
+// the encoded size of the statistics, see `SessionStatistics::encode`
+pub(crate) const STATISTICS_STATE_SIZE: usize = 7 * 8 + 16;
+
+/// The trades of a market during the current session, summed up as they happen
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionStatistics {
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    pub last: u64,
+    // 0 until the market closes
+    pub close: u64,
+    pub volume: u64,
+    pub trade_count: u64,
+    // sum of price * quantity, for the VWAP
+    turnover: u128,
+}
+
+impl SessionStatistics {
+    pub fn add_trade(&mut self, price: u64, quantity: u64) {
+        if self.trade_count == 0 {
+            self.open = price;
+            self.high = price;
+            self.low = price;
+        }
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.last = price;
+        self.volume = self.volume.saturating_add(quantity);
+        self.trade_count += 1;
+        // the product of two u64 always fits, only the sum can overflow
+        self.turnover = self
+            .turnover
+            .saturating_add(price as u128 * quantity as u128);
+    }
+
+    /// Volume weighted average price, rounded down. 0 if nothing traded
+    pub fn vwap(&self) -> u64 {
+        match self.volume {
+            0 => 0,
+            volume => (self.turnover / volume as u128) as u64,
+        }
+    }
+
+    /// The statistics, turnover included, as kept in the state of the market
+    pub(crate) fn encode(&self) -> [u8; STATISTICS_STATE_SIZE] {
+        let mut r = [0; STATISTICS_STATE_SIZE];
+        for (i, value) in [
+            self.open,
+            self.high,
+            self.low,
+            self.last,
+            self.close,
+            self.volume,
+            self.trade_count,
+        ]
+        .iter()
+        .enumerate()
+        {
+            r[i * 8..(i + 1) * 8].copy_from_slice(&value.to_le_bytes());
+        }
+        r[7 * 8..].copy_from_slice(&self.turnover.to_le_bytes());
+        r
+    }
+
+    pub(crate) fn decode(buf: [u8; STATISTICS_STATE_SIZE]) -> Self {
+        let value = |i: usize| u64::from_le_bytes(buf[i * 8..(i + 1) * 8].try_into().unwrap());
+        Self {
+            open: value(0),
+            high: value(1),
+            low: value(2),
+            last: value(3),
+            close: value(4),
+            volume: value(5),
+            trade_count: value(6),
+            turnover: u128::from_le_bytes(buf[7 * 8..].try_into().unwrap()),
+        }
+    }
+
+    pub fn to_message(&self, book_id: u64) -> Statistics {
+        Statistics {
+            book_id,
+            last_price: self.last,
+            open: self.open,
+            high: self.high,
+            low: self.low,
+            close: self.close,
+            volume: self.volume,
+            vwap: self.vwap(),
+            trade_count: self.trade_count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SessionStatistics;
+
+    #[test]
+    fn accumulate_trades() {
+        let mut target = SessionStatistics::default();
+        assert_eq!(0, target.vwap());
+
+        target.add_trade(1000, 100);
+        target.add_trade(1020, 50);
+        target.add_trade(990, 150);
+        assert_eq!(1000, target.open);
+        assert_eq!(1020, target.high);
+        assert_eq!(990, target.low);
+        assert_eq!(990, target.last);
+        assert_eq!(300, target.volume);
+        assert_eq!(3, target.trade_count);
+        // (100000 + 51000 + 148500) / 300
+        assert_eq!(998, target.vwap());
+
+        let message = target.to_message(5);
+        assert_eq!(5, { message.book_id });
+        assert_eq!(998, { message.vwap });
+        assert_eq!(0, { message.close });
+
+        let decoded = SessionStatistics::decode(target.encode());
+        assert_eq!(target, decoded);
+        assert_eq!(998, decoded.vwap());
+    }
+
+    #[test]
+    fn scaled_prices() {
+        let mut target = SessionStatistics::default();
+        // 10^10 with 8 decimals, traded in large lots
+        target.add_trade(1_000_000_000_000_000_000, 1_000_000_000);
+        target.add_trade(1_000_000_000_000_000_002, 1_000_000_000);
+        assert_eq!(1_000_000_000_000_000_001, target.vwap());
+        target.add_trade(1, u64::MAX);
+        assert_eq!(u64::MAX, target.volume);
+    }
+}
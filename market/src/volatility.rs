@@ -0,0 +1,104 @@
+use std::{collections::VecDeque, time::Duration};
+
+use crate::state::StateReader;
+
+/// Settings of the volatility interruption: the trading is halted for @cooldown
+/// when a trade would deviate more than @percentage from the price of @window ago
+///
+/// A percentage of 0 disables it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VolatilityConfig {
+    pub percentage: u8,
+    pub window: Duration,
+    pub cooldown: Duration,
+}
+
+impl VolatilityConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.percentage > 0
+    }
+}
+
+/// The recent trades of a market, out of which the rolling reference price is computed
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RollingReference {
+    // (timestamp in nanoseconds, price), oldest first
+    trades: VecDeque<(u64, u64)>,
+}
+
+impl RollingReference {
+    pub(crate) fn add_trade(&mut self, timestamp: u64, price: u64) {
+        self.trades.push_back((timestamp, price));
+    }
+
+    /// The last price traded at least @window before @now, or the first price of the
+    /// window if nothing traded before it. 0 if nothing traded at all
+    pub(crate) fn price(&mut self, now: u64, window: Duration) -> u64 {
+        let start = now.saturating_sub(window.as_nanos() as u64);
+        // only the last trade before the window start is still needed
+        while self.trades.len() > 1 && self.trades[1].0 <= start {
+            self.trades.pop_front();
+        }
+        self.trades.front().map(|t| t.1).unwrap_or_default()
+    }
+
+    /// Same as @price, leaving the trades as they are
+    pub(crate) fn peek(&self, now: u64, window: Duration) -> u64 {
+        let start = now.saturating_sub(window.as_nanos() as u64);
+        let before = self.trades.iter().take_while(|t| t.0 <= start).count();
+        self.trades
+            .get(before.saturating_sub(1))
+            .map(|t| t.1)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.trades.clear();
+    }
+
+    /// The trades still needed, as kept in the state of the market
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut r = (self.trades.len() as u32).to_le_bytes().to_vec();
+        for (timestamp, price) in self.trades.iter() {
+            r.extend_from_slice(&timestamp.to_le_bytes());
+            r.extend_from_slice(&price.to_le_bytes());
+        }
+        r
+    }
+
+    pub(crate) fn decode(reader: &mut StateReader) -> Option<Self> {
+        let mut trades = VecDeque::new();
+        for _ in 0..reader.u32()? {
+            trades.push_back((reader.u64()?, reader.u64()?));
+        }
+        Some(Self { trades })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::RollingReference;
+
+    #[test]
+    fn reference_rolls_with_the_window() {
+        let mut target = RollingReference::default();
+        assert_eq!(0, target.price(100, Duration::from_nanos(10)));
+
+        // nothing older than the window, the first trade of the window is used
+        target.add_trade(100, 1000);
+        target.add_trade(105, 1010);
+        assert_eq!(1000, target.price(108, Duration::from_nanos(10)));
+
+        // the last trade before the window start
+        target.add_trade(112, 1020);
+        assert_eq!(1000, target.peek(108, Duration::from_nanos(10)));
+        assert_eq!(1010, target.peek(115, Duration::from_nanos(10)));
+        assert_eq!(1010, target.price(115, Duration::from_nanos(10)));
+        assert_eq!(1020, target.price(200, Duration::from_nanos(10)));
+
+        target.clear();
+        assert_eq!(0, target.price(200, Duration::from_nanos(10)));
+    }
+}
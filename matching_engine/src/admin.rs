@@ -0,0 +1,298 @@
+//! The admin socket of the engine, for the operators debugging its markets
+//!
+//! A local unix socket taking one command per connection, a line of text,
+//! answered with a line of JSON before the connection is closed:
+//!
+//! ```text
+//! dump <book id>    the book, statistics and counters of the market of the book
+//! ```
+//!
+//! The commands are read by a thread of their own and handed over to the
+//! main loop of the engine, the markets answering from the thread they run
+//! on. A socket left behind by a previous run is replaced.
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{fs::FileTypeExt, net::UnixListener},
+    path::Path,
+    str::FromStr,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use market::debug::DebugSnapshot;
+use order::Order;
+use tracing::{error, info};
+use utils::json::escape;
+
+use crate::shard::Waker;
+
+// for a client that doesn't send its command
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+// for the markets to answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the admin can ask the engine for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminCommand {
+    // the market of a book, see Market::to_debug_snapshot
+    Dump(u64),
+}
+
+impl FromStr for AdminCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut words = s.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("dump"), Some(book_id), None) => Ok(AdminCommand::Dump(
+                book_id
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid book id {book_id}"))?,
+            )),
+            _ => bail!("Unknown command {}", s.trim()),
+        }
+    }
+}
+
+/// A command, with where its answer goes: none if the engine has no market
+/// for the book
+pub type AdminRequest = (AdminCommand, Sender<Option<DebugSnapshot>>);
+
+fn error_json(message: &str) -> String {
+    format!("{{\"error\":\"{}\"}}", escape(message))
+}
+
+fn order_json(o: &Order) -> String {
+    format!(
+        "{{\"id\":{},\"sequence\":{},\"participant\":{},\"side\":\"{:?}\",\"type\":\"{:?}\",\
+         \"price\":{},\"quantity\":{},\"display_quantity\":{},\"hidden_quantity\":{},\
+         \"stop_price\":{},\"expiry\":{},\"gateway_id\":{},\"session_id\":{},\
+         \"client_order_id\":{}}}",
+        o.get_id(),
+        o.get_sequence(),
+        o.participant,
+        o.side,
+        o.order_type,
+        o.price,
+        o.quantity,
+        o.display_quantity,
+        o.hidden_quantity,
+        o.stop_price,
+        o.expiry,
+        o.gateway_id,
+        o.session_id,
+        o.client_order_id,
+    )
+}
+
+fn orders_json(orders: &[Order]) -> String {
+    let orders: Vec<String> = orders.iter().map(order_json).collect();
+    format!("[{}]", orders.join(","))
+}
+
+/// @snapshot as a JSON object, on one line
+pub fn snapshot_json(snapshot: &DebugSnapshot) -> String {
+    let statistics = &snapshot.statistics;
+    let exposure_blocks: Vec<String> = snapshot
+        .exposure_blocks
+        .iter()
+        .map(|(participant, side)| {
+            format!("{{\"participant\":{participant},\"side\":\"{side:?}\"}}")
+        })
+        .collect();
+    format!(
+        "{{\"book_id\":{},\"name\":\"{}\",\"state\":\"{:?}\",\
+         \"bids\":{},\"asks\":{},\"stops\":{},\
+         \"statistics\":{{\"open\":{},\"high\":{},\"low\":{},\"last\":{},\"close\":{},\
+         \"volume\":{},\"trade_count\":{},\"vwap\":{}}},\
+         \"counters\":{{\"order_id\":{},\"sequence\":{},\"trade_id\":{},\"day_trades\":{},\
+         \"quotes\":{},\"passive_fills\":{},\"trade_captures\":{}}},\
+         \"reference_price\":{},\"band_reference\":{},\"halted_until\":{},\
+         \"exposure_blocks\":[{}]}}",
+        snapshot.book_id,
+        escape(&snapshot.name),
+        snapshot.state,
+        orders_json(&snapshot.bids),
+        orders_json(&snapshot.asks),
+        orders_json(&snapshot.stops),
+        statistics.open,
+        statistics.high,
+        statistics.low,
+        statistics.last,
+        statistics.close,
+        statistics.volume,
+        statistics.trade_count,
+        statistics.vwap(),
+        snapshot.order_id,
+        snapshot.sequence,
+        snapshot.trade_id,
+        snapshot.day_trades,
+        snapshot.quotes,
+        snapshot.passive_fills,
+        snapshot.trade_captures,
+        snapshot.reference_price,
+        snapshot
+            .band_reference
+            .map_or(String::from("null"), |p| p.to_string()),
+        snapshot.halted_until,
+        exposure_blocks.join(","),
+    )
+}
+
+/// Runs @line, handing the command over to the engine on @requests
+fn answer(line: &str, requests: &Sender<AdminRequest>, wake: &Waker) -> String {
+    let command = match line.parse::<AdminCommand>() {
+        Ok(command) => command,
+        Err(e) => return error_json(&e.to_string()),
+    };
+    let (reply, snapshot) = mpsc::channel();
+    if requests.send((command, reply)).is_err() {
+        return error_json("The engine is going down");
+    }
+    wake();
+    match (command, snapshot.recv_timeout(REPLY_TIMEOUT)) {
+        (_, Ok(Some(snapshot))) => snapshot_json(&snapshot),
+        (AdminCommand::Dump(book_id), Ok(None)) => error_json(&format!("Unknown book {book_id}")),
+        (_, Err(_)) => error_json("The engine didn't answer"),
+    }
+}
+
+/// Listens on the unix socket @path, sending the commands received on the
+/// receiver returned, @wake being called after each
+pub fn spawn(path: &str, wake: Waker) -> io::Result<Receiver<AdminRequest>> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{path} exists and is not a socket"),
+            ))
+        }
+        Err(_) => {}
+    }
+    let listener = UnixListener::bind(Path::new(path))?;
+    info!(path, "Serving the admin socket");
+    let (requests, received) = mpsc::channel();
+    thread::Builder::new()
+        .name(String::from("admin"))
+        .spawn(move || {
+            // one at a time, there's no hurry for the admin
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!(error = %e, "Error accepting an admin connection");
+                        continue;
+                    }
+                };
+                let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                let mut line = String::new();
+                let response = match BufReader::new(&stream).read_line(&mut line) {
+                    Ok(_) => answer(&line, &requests, &wake),
+                    Err(e) => error_json(&e.to_string()),
+                };
+                if let Err(e) = writeln!(stream, "{response}") {
+                    error!(error = %e, "Error answering the admin");
+                }
+            }
+        })?;
+    Ok(received)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        os::unix::net::UnixStream,
+        sync::{Arc, Mutex, RwLock},
+        thread,
+    };
+
+    use disseminator::mockdisseminator::MockDisseminator;
+    use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+    use market::{debug::DebugSnapshot, orderid::OrderIdGenerator, Market};
+    use order::{Order, OrderType, Side};
+
+    use super::{snapshot_json, spawn, AdminCommand};
+
+    fn snapshot() -> DebugSnapshot {
+        let i = Arc::new(RwLock::new(Instrument::new(
+            500,
+            "A\"B",
+            InstrumentType::Share,
+            InstrumentState::Trading,
+            10,
+            20,
+        )));
+        let mut market = Market::new(
+            i.clone(),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+        );
+        market.add_order(Order::new(7, i, 100, 10, Side::Bid, OrderType::Day).with_session(1, 2));
+        market.set_exposure_block(7, Some(Side::Ask));
+        market.to_debug_snapshot()
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(AdminCommand::Dump(500), " dump  500\n".parse().unwrap());
+        assert!("dump".parse::<AdminCommand>().is_err());
+        assert!("dump x".parse::<AdminCommand>().is_err());
+        assert!("dump 500 501".parse::<AdminCommand>().is_err());
+        assert!("show 500".parse::<AdminCommand>().is_err());
+    }
+
+    #[test]
+    fn json() {
+        let snapshot = snapshot();
+        let target = snapshot_json(&snapshot);
+        assert!(
+            target.starts_with(r#"{"book_id":500,"name":"A\"B","state":"Trading","bids":[{"id":"#)
+        );
+        assert!(target.contains(r#""side":"Bid","type":"Day","price":100,"quantity":10,"#));
+        assert!(target.contains(r#""asks":[],"stops":[],"#));
+        assert!(target.contains(r#""band_reference":null"#));
+        assert!(target.contains(&format!(
+            r#""counters":{{"order_id":{},"#,
+            snapshot.order_id
+        )));
+        assert!(target.ends_with(r#""exposure_blocks":[{"participant":7,"side":"Ask"}]}"#));
+        assert!(!target.contains('\n'));
+    }
+
+    #[test]
+    fn socket() {
+        let path = std::env::temp_dir().join(format!("engine-admin-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        // a leftover of a previous run
+        std::os::unix::net::UnixListener::bind(path).unwrap();
+        let requests = spawn(path, Arc::new(|| {})).unwrap();
+        // the engine
+        thread::spawn(move || {
+            for (command, reply) in requests.iter() {
+                let answer = match command {
+                    AdminCommand::Dump(500) => Some(snapshot()),
+                    AdminCommand::Dump(_) => None,
+                };
+                reply.send(answer).unwrap();
+            }
+        });
+
+        let ask = |command: &str| {
+            let mut stream = UnixStream::connect(path).unwrap();
+            stream.write_all(command.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        assert!(ask("dump 500\n").starts_with(r#"{"book_id":500,"#));
+        assert_eq!("{\"error\":\"Unknown book 501\"}\n", ask("dump 501\n"));
+        assert_eq!("{\"error\":\"Unknown command show\"}\n", ask("show\n"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
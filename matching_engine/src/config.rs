@@ -0,0 +1,253 @@
+//! The typed [engine] and [clearing] sections of matching_engine.ini
+//!
+//! The settings of their own, the logs, metrics, multicast, schedule and
+//! heartbeats, are loaded next to these by their from_config.
+
+use std::time::Duration;
+
+use instruments::partition::Partition;
+use market::{bands::WithoutReference, volatility::VolatilityConfig};
+use oep::{egress::EgressMode, ingress::IngressMode};
+use serde::Deserialize;
+use utils::config::{self, ConfigError, ConfigMap};
+
+use crate::{egress::DEFAULT_MAX_RETRANSMIT, shard::FeedFormat};
+
+const ENGINE_SECTION: &str = "engine";
+const CLEARING_SECTION: &str = "clearing";
+
+fn default_batch_max_delay_us() -> u64 {
+    1000
+}
+
+fn default_any_address() -> String {
+    String::from("0.0.0.0")
+}
+
+fn default_recovery_cache_size() -> usize {
+    100000
+}
+
+fn default_snapshot_every_s() -> u64 {
+    60
+}
+
+fn default_replication_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_egress_buffer_size() -> usize {
+    DEFAULT_MAX_RETRANSMIT
+}
+
+fn default_gateway_timeout_ms() -> u64 {
+    3000
+}
+
+/// The [engine] section
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EngineConfig {
+    // announced to the gateways, tells the primary and the backups apart
+    #[serde(default)]
+    pub id: u8,
+    pub max_packet_size: u16,
+    // where the gateways send the orders
+    pub order_group: String,
+    pub order_port: u16,
+    #[serde(default, deserialize_with = "config::from_str")]
+    pub ingress: IngressMode,
+    pub internal_publisher_group: String,
+    pub internal_publisher_port: u16,
+    #[serde(default, deserialize_with = "config::from_str")]
+    pub egress: EgressMode,
+    // reports kept per gateway for the retransmissions, with a sequenced egress
+    #[serde(default = "default_egress_buffer_size")]
+    pub egress_buffer_size: usize,
+    // the reports of the gateways giving an endpoint sent there rather than
+    // on internal_publisher_group
+    #[serde(default)]
+    pub unicast_reports: bool,
+    // silence after which a registered gateway is forgotten, its id free again
+    #[serde(default = "default_gateway_timeout_ms")]
+    pub gateway_timeout_ms: u64,
+    pub disseminator_group: String,
+    pub disseminator_port: u16,
+    // the B feed goes on disseminator_group without it
+    pub disseminator_b_group: Option<String>,
+    pub disseminator_b_port: Option<u16>,
+    #[serde(default, deserialize_with = "config::from_str")]
+    pub feed_format: FeedFormat,
+    pub feed_mtu: Option<usize>,
+    #[serde(default = "default_batch_max_delay_us")]
+    pub batch_max_delay_us: u64,
+    pub snapshot_group: String,
+    pub snapshot_port: u16,
+    #[serde(default = "default_any_address")]
+    pub recovery_address: String,
+    pub recovery_port: Option<u16>,
+    #[serde(default = "default_recovery_cache_size")]
+    pub recovery_cache_size: usize,
+    #[serde(default)]
+    pub partition_id: u8,
+    // all the books when empty, see Partition::parse
+    #[serde(default)]
+    pub partition_books: String,
+    #[serde(default)]
+    pub volatility_percentage: u8,
+    #[serde(default)]
+    pub volatility_window_s: u64,
+    #[serde(default)]
+    pub volatility_cooldown_s: u64,
+    #[serde(default, deserialize_with = "config::from_str")]
+    pub bands_without_reference: WithoutReference,
+    pub journal: Option<String>,
+    pub snapshot: Option<String>,
+    #[serde(default = "default_snapshot_every_s")]
+    pub snapshot_every_s: u64,
+    pub replicate_from: Option<String>,
+    #[serde(default = "default_replication_timeout_ms")]
+    pub replication_timeout_ms: u64,
+    #[serde(default = "default_any_address")]
+    pub replication_address: String,
+    pub replication_port: Option<u16>,
+    #[serde(default)]
+    pub shards: usize,
+    pub unknown_book_wait_ms: Option<u64>,
+    // the unix socket of the admin commands, off without it
+    pub admin_socket: Option<String>,
+}
+
+impl EngineConfig {
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self, ConfigError> {
+        let r: Self = config::section(config_map, ENGINE_SECTION)?;
+        r.validate()?;
+        Ok(r)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key, message| Err(ConfigError::new(ENGINE_SECTION, Some(key), message));
+        if self.partition().is_none() {
+            return invalid("partition_books", "must be a list of book ids and ranges");
+        }
+        if self.snapshot.is_some() && self.journal.is_none() {
+            return invalid("snapshot", "a snapshot needs a journal");
+        }
+        if self.replication_port.is_some() && self.journal.is_none() {
+            return invalid("replication_port", "the replication needs a journal");
+        }
+        Ok(())
+    }
+
+    /// The books handled by this engine
+    pub fn partition(&self) -> Option<Partition> {
+        Partition::parse(self.partition_id, &self.partition_books)
+    }
+
+    /// Disabled unless a percentage is given
+    pub fn volatility(&self) -> VolatilityConfig {
+        VolatilityConfig {
+            percentage: self.volatility_percentage,
+            window: Duration::from_secs(self.volatility_window_s),
+            cooldown: Duration::from_secs(self.volatility_cooldown_s),
+        }
+    }
+}
+
+/// The [clearing] section, the clearing the engine logs on to
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ClearingConnectionConfig {
+    pub address: String,
+    pub port: u16,
+    // shared with the clearing, see clearing_connection::logon
+    pub secret: String,
+}
+
+impl ClearingConnectionConfig {
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self, ConfigError> {
+        config::section(config_map, CLEARING_SECTION)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use configparser::ini::Ini;
+    use oep::{egress::EgressMode, ingress::IngressMode};
+
+    use super::EngineConfig;
+    use crate::shard::FeedFormat;
+
+    const MANDATORY: &str = "[engine]
+        max_packet_size=10000
+        order_group=239.71.71.71
+        order_port=10000
+        internal_publisher_group=224.224.224.224
+        internal_publisher_port=24000
+        disseminator_group=225.225.225.225
+        disseminator_port=25000
+        snapshot_group=225.225.225.226
+        snapshot_port=25002
+        ";
+
+    fn load(extra: &str) -> Result<EngineConfig, String> {
+        let config_map = Ini::new().read(format!("{MANDATORY}{extra}")).unwrap();
+        EngineConfig::from_config(&config_map).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn defaults() {
+        let target = load("").unwrap();
+        assert_eq!(target.id, 0);
+        assert_eq!(target.ingress, IngressMode::Multicast);
+        assert_eq!(target.egress, EgressMode::Multicast);
+        assert_eq!(target.egress_buffer_size, 100000);
+        assert!(!target.unicast_reports);
+        assert_eq!(target.gateway_timeout_ms, 3000);
+        assert_eq!(target.feed_format, FeedFormat::Mbo);
+        assert_eq!(target.batch_max_delay_us, 1000);
+        assert_eq!(target.recovery_address, "0.0.0.0");
+        assert_eq!(target.recovery_cache_size, 100000);
+        assert_eq!(target.snapshot_every_s, 60);
+        assert_eq!(target.replication_timeout_ms, 2000);
+        assert!(target.partition().is_some());
+        assert!(target.volatility().window.is_zero());
+        assert_eq!(target.admin_socket, None);
+
+        let target =
+            load("ingress=sequenced\negress=sequenced\nfeed_format=itch\nfeed_mtu=1400").unwrap();
+        assert_eq!(target.ingress, IngressMode::Sequenced);
+        assert_eq!(target.egress, EgressMode::Sequenced);
+        assert_eq!(target.feed_format, FeedFormat::Itch);
+        assert_eq!(target.feed_mtu, Some(1400));
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(
+            load("order_port=x").unwrap_err(),
+            "order_port in the [engine] section: x is not a valid u16"
+        );
+        assert!(load("feed_format=fix").unwrap_err().contains("feed_format"));
+        assert!(load("egress=tcp").unwrap_err().contains("egress"));
+        assert!(load("unicast_reports=true").unwrap().unicast_reports);
+        assert!(load("unicast_reports=maybe")
+            .unwrap_err()
+            .contains("unicast_reports"));
+        assert!(load("partition_books=1-x")
+            .unwrap_err()
+            .contains("partition_books"));
+        assert!(load("snapshot=engine.snapshot")
+            .unwrap_err()
+            .contains("needs a journal"));
+        assert!(load("replication_port=26000")
+            .unwrap_err()
+            .contains("needs a journal"));
+        assert!(load("replication_port=26000\njournal=engine.journal").is_ok());
+        assert!(load("volatility_percentage=256").is_err());
+
+        let config_map = Ini::new().read(String::from("[engine]")).unwrap();
+        assert_eq!(
+            EngineConfig::from_config(&config_map).unwrap_err().key(),
+            Some("max_packet_size")
+        );
+    }
+}
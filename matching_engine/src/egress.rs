@@ -0,0 +1,267 @@
+//! Sequencing of the execution reports published to the gateways
+//!
+//! With a sequenced egress, the engine numbers the reports of every gateway
+//! on their own, starting with 1 on each of its runs, and keeps the last
+//! frames sent to each of them. A gateway noticing a gap in its sequence asks
+//! for the missing frames with an EgressNak and gets them again on the
+//! internal publisher group. The heartbeats, sent with the engine status, let
+//! the gateways notice the loss of their last reports.
+
+use std::collections::{HashMap, VecDeque};
+
+use oep::{
+    decoder::Decoder,
+    egress::{EgressHeader, EgressNak, EGRESSHEADER_SIZE, EGRESSNAK_SIZE},
+    header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+    ingress::IngressKind,
+    oep_message::MsgType,
+};
+
+// frames kept per gateway for the retransmissions, unless configured otherwise
+pub const DEFAULT_MAX_RETRANSMIT: usize = 100000;
+
+#[derive(Debug, Default)]
+struct GatewayStream {
+    // the sequence of the last frame sent
+    last_seq: u64,
+    // the last frames sent, headers included, oldest first
+    sent: VecDeque<(u64, Vec<u8>)>,
+}
+
+#[derive(Debug)]
+pub struct SequencedEgress {
+    engine_id: u8,
+    partition_id: u8,
+    epoch: u32,
+    max_sent: usize,
+    // gateway id -> what was sent to it
+    gateways: HashMap<u8, GatewayStream>,
+}
+
+impl SequencedEgress {
+    /// @epoch - tells this run of the engine apart from the previous ones
+    /// @max_sent - how many of the frames sent to each gateway are kept for a
+    /// retransmission
+    pub fn new(engine_id: u8, partition_id: u8, epoch: u32, max_sent: usize) -> Self {
+        Self {
+            engine_id,
+            partition_id,
+            epoch,
+            max_sent,
+            gateways: HashMap::new(),
+        }
+    }
+
+    fn datagram(&self, gateway_id: u8, kind: IngressKind, seq: u64, payload: &[u8]) -> Vec<u8> {
+        let header = OepHeader::new(
+            OEP_VERSION,
+            MsgType::EgressFrame.into(),
+            (EGRESSHEADER_SIZE + payload.len()) as u32,
+        )
+        .encode();
+        let egress_header = EgressHeader::new(
+            self.engine_id,
+            self.partition_id,
+            gateway_id,
+            kind,
+            self.epoch,
+            seq,
+        )
+        .encode();
+        [header.as_slice(), egress_header.as_slice(), payload].concat()
+    }
+
+    /// Frames @message, an execution report with its header, with the next
+    /// sequence of @gateway_id, keeping a copy for the retransmissions
+    pub fn frame(&mut self, gateway_id: u8, message: &[u8]) -> Vec<u8> {
+        let seq = self.gateways.get(&gateway_id).map_or(0, |g| g.last_seq) + 1;
+        let frame = self.datagram(gateway_id, IngressKind::Message, seq, message);
+        let max_sent = self.max_sent;
+        let gateway = self.gateways.entry(gateway_id).or_default();
+        gateway.last_seq = seq;
+        if max_sent > 0 {
+            if gateway.sent.len() == max_sent {
+                gateway.sent.pop_front();
+            }
+            gateway.sent.push_back((seq, frame.clone()));
+        }
+        frame
+    }
+
+    /// Announces the next sequence of every gateway reported to, for them to
+    /// notice what they missed
+    pub fn heartbeats(&self) -> Vec<Vec<u8>> {
+        self.gateways
+            .iter()
+            .map(|(gateway_id, g)| {
+                self.datagram(*gateway_id, IngressKind::Heartbeat, g.last_seq + 1, &[])
+            })
+            .collect()
+    }
+
+    /// The frames to send again for @nak, the ones kept starting with its
+    /// from_seq. They come after a reset if some of those asked for are gone.
+    /// Nothing for the naks of the other engines or of a previous run
+    pub fn retransmit(&self, nak: &EgressNak) -> Vec<Vec<u8>> {
+        if (nak.engine_id, nak.partition_id, { nak.epoch })
+            != (self.engine_id, self.partition_id, self.epoch)
+        {
+            return vec![];
+        }
+        let (gateway_id, from_seq) = (nak.gateway_id, nak.from_seq);
+        let Some(gateway) = self.gateways.get(&gateway_id) else {
+            return vec![];
+        };
+        let first_kept = gateway
+            .sent
+            .front()
+            .map_or(gateway.last_seq + 1, |(seq, _)| *seq);
+        let mut r = vec![];
+        if from_seq < first_kept {
+            r.push(self.datagram(gateway_id, IngressKind::Reset, first_kept, &[]));
+        }
+        r.extend(
+            gateway
+                .sent
+                .iter()
+                .filter(|(seq, _)| *seq >= from_seq)
+                .map(|(_, frame)| frame.clone()),
+        );
+        r
+    }
+}
+
+/// The EgressNak in @message, if that's what it is
+pub fn egress_nak(message: &[u8]) -> Option<EgressNak> {
+    if message.len() != OEP_HEADER_SIZE + EGRESSNAK_SIZE {
+        return None;
+    }
+    let header = OepHeader::decode(message[..OEP_HEADER_SIZE].try_into().unwrap()).ok()?;
+    if header.message_type() != MsgType::EgressNak {
+        return None;
+    }
+    EgressNak::decode(message[OEP_HEADER_SIZE..].try_into().unwrap()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use oep::{
+        decoder::Decoder,
+        egress::{EgressHeader, EgressNak, EGRESSHEADER_SIZE, EGRESSNAK_SIZE},
+        header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+        ingress::IngressKind,
+        oep_message::MsgType,
+    };
+
+    use super::{egress_nak, SequencedEgress};
+
+    fn header(frame: &[u8]) -> EgressHeader {
+        EgressHeader::decode(
+            frame[OEP_HEADER_SIZE..OEP_HEADER_SIZE + EGRESSHEADER_SIZE]
+                .try_into()
+                .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn payload(frame: &[u8]) -> &[u8] {
+        &frame[OEP_HEADER_SIZE + EGRESSHEADER_SIZE..]
+    }
+
+    #[test]
+    fn frame_and_heartbeat() {
+        let mut target = SequencedEgress::new(1, 2, 100, 10);
+        let frame = target.frame(3, &[7, 8]);
+        let oep_header = OepHeader::decode(frame[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(MsgType::EgressFrame, oep_header.message_type());
+        assert_eq!(EGRESSHEADER_SIZE + 2, { oep_header.msg_len } as usize);
+        let h = header(&frame);
+        assert_eq!(1, h.engine_id);
+        assert_eq!(2, h.partition_id);
+        assert_eq!(3, h.gateway_id);
+        assert_eq!(IngressKind::Message, h.get_kind());
+        assert_eq!(100, { h.epoch });
+        assert_eq!(1, { h.seq });
+        assert_eq!([7, 8], payload(&frame));
+        assert_eq!(2, { header(&target.frame(3, &[9])).seq });
+        // each gateway has its own sequence
+        assert_eq!(1, { header(&target.frame(4, &[9])).seq });
+
+        let mut heartbeats = target.heartbeats();
+        heartbeats.sort_by_key(|h| header(h).gateway_id);
+        assert_eq!(2, heartbeats.len());
+        assert!(payload(&heartbeats[0]).is_empty());
+        assert_eq!(IngressKind::Heartbeat, header(&heartbeats[0]).get_kind());
+        assert_eq!(3, { header(&heartbeats[0]).seq });
+        assert_eq!(2, { header(&heartbeats[1]).seq });
+    }
+
+    #[test]
+    fn retransmit() {
+        let mut target = SequencedEgress::new(1, 2, 100, 2);
+        for i in 0..3u8 {
+            target.frame(3, &[i]);
+        }
+        target.frame(4, &[9]);
+        let resent = target.retransmit(&EgressNak::new(1, 2, 3, 100, 3));
+        assert_eq!(1, resent.len());
+        assert_eq!(3, { header(&resent[0]).seq });
+        assert_eq!([2], payload(&resent[0]));
+
+        // the first one is gone, the gateway has to skip it
+        let resent = target.retransmit(&EgressNak::new(1, 2, 3, 100, 1));
+        assert_eq!(3, resent.len());
+        assert_eq!(IngressKind::Reset, header(&resent[0]).get_kind());
+        assert_eq!(2, { header(&resent[0]).seq });
+        assert_eq!(2, { header(&resent[1]).seq });
+        assert_eq!(3, { header(&resent[2]).seq });
+
+        // not for us
+        assert!(target
+            .retransmit(&EgressNak::new(0, 2, 3, 100, 1))
+            .is_empty());
+        assert!(target
+            .retransmit(&EgressNak::new(1, 0, 3, 100, 1))
+            .is_empty());
+        assert!(target
+            .retransmit(&EgressNak::new(1, 2, 3, 99, 1))
+            .is_empty());
+        assert!(target
+            .retransmit(&EgressNak::new(1, 2, 5, 100, 1))
+            .is_empty());
+        // nothing missed
+        assert!(target
+            .retransmit(&EgressNak::new(1, 2, 3, 100, 4))
+            .is_empty());
+    }
+
+    #[test]
+    fn keeps_nothing() {
+        let mut target = SequencedEgress::new(1, 2, 100, 0);
+        target.frame(3, &[1]);
+        let resent = target.retransmit(&EgressNak::new(1, 2, 3, 100, 1));
+        assert_eq!(1, resent.len());
+        assert_eq!(IngressKind::Reset, header(&resent[0]).get_kind());
+        assert_eq!(2, { header(&resent[0]).seq });
+    }
+
+    #[test]
+    fn decode_nak() {
+        let nak = EgressNak::new(1, 2, 3, 100, 5);
+        let header = OepHeader::new(
+            OEP_VERSION,
+            MsgType::EgressNak.into(),
+            EGRESSNAK_SIZE as u32,
+        )
+        .encode();
+        let message = [header.as_slice(), nak.encode().as_slice()].concat();
+        let decoded = egress_nak(&message).unwrap();
+        assert_eq!(3, decoded.gateway_id);
+        assert_eq!(5, { decoded.from_seq });
+
+        assert!(egress_nak(&message[..message.len() - 1]).is_none());
+        let cancel =
+            OepHeader::new(OEP_VERSION, MsgType::Cancel.into(), EGRESSNAK_SIZE as u32).encode();
+        assert!(egress_nak(&[cancel.as_slice(), nak.encode().as_slice()].concat()).is_none());
+    }
+}
@@ -0,0 +1,244 @@
+//! The sequences of the gateways, with a sequenced ingress
+//!
+//! Every gateway numbers the frames it sends to the engines, starting with 1
+//! on each of its runs. The engine processes them in that order only: a frame
+//! coming after a gap is dropped and the gateway is asked with an IngressNak
+//! for everything starting with the first one missing. A gateway seen for the
+//! first time is taken as it is, whatever it sent before the engine started
+//! being none of its business.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use oep::{
+    decoder::Decoder,
+    ingress::{IngressHeader, IngressKind, IngressNak, INGRESSHEADER_SIZE},
+};
+use tracing::{info, warn};
+
+// a gap is asked for again only after this long, the frames being on their way
+const NAK_EVERY: Duration = Duration::from_millis(100);
+
+/// What to do with a frame received from a gateway
+#[derive(Debug)]
+pub enum Ingress<'a> {
+    // the next message of the gateway, to be processed
+    Message(&'a [u8]),
+    // some frames were lost, the gateway has to send them again
+    Gap(IngressNak),
+    // a duplicate, a heartbeat, or a gap already asked for
+    Nothing,
+}
+
+#[derive(Debug)]
+struct GatewaySequence {
+    epoch: u32,
+    next_seq: u64,
+    // the last gap asked for, and when
+    last_nak: Option<(u64, Instant)>,
+}
+
+#[derive(Debug)]
+pub struct IngressTracker {
+    engine_id: u8,
+    // gateway id -> its sequence
+    gateways: HashMap<u8, GatewaySequence>,
+}
+
+impl IngressTracker {
+    pub fn new(engine_id: u8) -> Self {
+        Self {
+            engine_id,
+            gateways: HashMap::new(),
+        }
+    }
+
+    /// The next sequence expected from @gateway_id, if it was heard of
+    pub fn next_seq(&self, gateway_id: u8) -> Option<u64> {
+        self.gateways.get(&gateway_id).map(|g| g.next_seq)
+    }
+
+    /// Checks the sequence of @frame, received at @now
+    pub fn receive<'a>(&mut self, frame: &'a [u8], now: Instant) -> Ingress<'a> {
+        if frame.len() < INGRESSHEADER_SIZE {
+            return Ingress::Nothing;
+        }
+        let Ok(header) = IngressHeader::decode(frame[..INGRESSHEADER_SIZE].try_into().unwrap())
+        else {
+            warn!("Invalid ingress header received");
+            return Ingress::Nothing;
+        };
+        let (gateway_id, epoch, seq) = (header.gateway_id, header.epoch, header.seq);
+        let gateway = self
+            .gateways
+            .entry(gateway_id)
+            .or_insert_with(|| GatewaySequence {
+                epoch,
+                next_seq: seq,
+                last_nak: None,
+            });
+        if gateway.epoch != epoch {
+            info!(
+                gateway_id,
+                epoch, "Gateway started again, expecting its sequence from 1"
+            );
+            *gateway = GatewaySequence {
+                epoch,
+                next_seq: 1,
+                last_nak: None,
+            };
+        }
+        match header.get_kind() {
+            IngressKind::Message if seq == gateway.next_seq => {
+                gateway.next_seq += 1;
+                Ingress::Message(&frame[INGRESSHEADER_SIZE..])
+            }
+            IngressKind::Message | IngressKind::Heartbeat if seq > gateway.next_seq => {
+                match gateway.last_nak {
+                    Some((from_seq, at))
+                        if from_seq == gateway.next_seq && now.duration_since(at) < NAK_EVERY =>
+                    {
+                        Ingress::Nothing
+                    }
+                    _ => {
+                        gateway.last_nak = Some((gateway.next_seq, now));
+                        Ingress::Gap(IngressNak::new(
+                            self.engine_id,
+                            gateway_id,
+                            epoch,
+                            gateway.next_seq,
+                        ))
+                    }
+                }
+            }
+            IngressKind::Reset if seq > gateway.next_seq => {
+                warn!(
+                    gateway_id,
+                    lost = seq - gateway.next_seq,
+                    "Lost messages of a gateway, no longer kept by it"
+                );
+                gateway.next_seq = seq;
+                Ingress::Nothing
+            }
+            _ => Ingress::Nothing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use oep::{
+        decoder::Decoder,
+        ingress::{IngressHeader, IngressKind},
+    };
+
+    use super::{Ingress, IngressTracker};
+
+    fn frame(gateway_id: u8, kind: IngressKind, epoch: u32, seq: u64) -> Vec<u8> {
+        let header = IngressHeader::new(gateway_id, kind, epoch, seq).encode();
+        match kind {
+            IngressKind::Message => [header.as_slice(), &[seq as u8]].concat(),
+            _ => header.to_vec(),
+        }
+    }
+
+    fn message(seq: u64) -> Vec<u8> {
+        frame(1, IngressKind::Message, 100, seq)
+    }
+
+    #[test]
+    fn in_sequence() {
+        let mut target = IngressTracker::new(0);
+        let now = Instant::now();
+        // taken as it is the first time
+        assert!(matches!(
+            target.receive(&message(5), now),
+            Ingress::Message([5])
+        ));
+        assert!(matches!(
+            target.receive(&message(6), now),
+            Ingress::Message([6])
+        ));
+        // duplicates
+        assert!(matches!(target.receive(&message(6), now), Ingress::Nothing));
+        assert!(matches!(target.receive(&message(2), now), Ingress::Nothing));
+        assert_eq!(Some(7), target.next_seq(1));
+        assert_eq!(None, target.next_seq(2));
+        // garbage
+        assert!(matches!(target.receive(&[1, 2], now), Ingress::Nothing));
+    }
+
+    #[test]
+    fn gap() {
+        let mut target = IngressTracker::new(3);
+        let now = Instant::now();
+        target.receive(&message(1), now);
+        let Ingress::Gap(nak) = target.receive(&message(3), now) else {
+            panic!("gap expected");
+        };
+        assert_eq!(3, nak.engine_id);
+        assert_eq!(1, nak.gateway_id);
+        assert_eq!(100, { nak.epoch });
+        assert_eq!(2, { nak.from_seq });
+        // already asked for
+        assert!(matches!(target.receive(&message(4), now), Ingress::Nothing));
+        // but not for ever
+        let later = now + Duration::from_millis(200);
+        assert!(matches!(
+            target.receive(&message(4), later),
+            Ingress::Gap(_)
+        ));
+
+        // the retransmission
+        assert!(matches!(
+            target.receive(&message(2), later),
+            Ingress::Message([2])
+        ));
+        assert!(matches!(
+            target.receive(&message(3), later),
+            Ingress::Message([3])
+        ));
+        assert_eq!(Some(4), target.next_seq(1));
+    }
+
+    #[test]
+    fn heartbeat_and_reset() {
+        let mut target = IngressTracker::new(0);
+        let now = Instant::now();
+        target.receive(&message(1), now);
+        let heartbeat = frame(1, IngressKind::Heartbeat, 100, 2);
+        assert!(matches!(target.receive(&heartbeat, now), Ingress::Nothing));
+        // the last message was lost
+        let heartbeat = frame(1, IngressKind::Heartbeat, 100, 3);
+        let Ingress::Gap(nak) = target.receive(&heartbeat, now) else {
+            panic!("gap expected");
+        };
+        assert_eq!(2, { nak.from_seq });
+
+        // the gateway no longer has it
+        let reset = frame(1, IngressKind::Reset, 100, 3);
+        assert!(matches!(target.receive(&reset, now), Ingress::Nothing));
+        assert_eq!(Some(3), target.next_seq(1));
+        assert!(matches!(
+            target.receive(&message(3), now),
+            Ingress::Message([3])
+        ));
+    }
+
+    #[test]
+    fn gateway_restart() {
+        let mut target = IngressTracker::new(0);
+        let now = Instant::now();
+        target.receive(&message(10), now);
+        // a new run starts again with 1, whose first message was lost
+        let Ingress::Gap(nak) = target.receive(&frame(1, IngressKind::Message, 101, 2), now) else {
+            panic!("gap expected");
+        };
+        assert_eq!(101, { nak.epoch });
+        assert_eq!(1, { nak.from_seq });
+    }
+}
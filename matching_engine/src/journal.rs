@@ -0,0 +1,621 @@
+//! Write-ahead journal of the engine
+//!
+//! Every order message is appended to the journal before being processed,
+//! and so is every update of the clearing applied to the markets. Replayed
+//! in order on the next start, they bring the markets back to where they
+//! were when the engine stopped, crash included. The execution reports
+//! published are journaled as well, for the audit trail only, and so is the
+//! sequence reached by the feed of each shard, for the feed to go on from it.
+//! The backups of the engine get the journal streamed to them, see `replication`.
+//!
+//! Each record is framed as
+//!
+//! ```text
+//! | Length (4) | CRC-32 (4) | Timestamp (8) | Kind (1) | Payload (var) |
+//! ```
+//!
+//! Length counting the bytes after the CRC, which covers the same bytes. The
+//! timestamp is in nanoseconds since the unix epoch.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clearing_connection::genericclearingprotocol::MarketUpdate;
+use instruments::instrument::{Instrument, InstrumentState, INSTRUMENT_FIXED_SIZE};
+use oep::{
+    decoder::Decoder,
+    execution_report::{ExecutionReport, EXECUTIONREPORT_SIZE},
+};
+use order::Side;
+use tracing::warn;
+
+use crate::replication::ReplicationServer;
+
+// length and CRC
+const FRAME_HEADER_SIZE: usize = 8;
+// timestamp and kind
+const RECORD_HEADER_SIZE: usize = 9;
+// a length over this is garbage
+const MAX_RECORD_SIZE: usize = 1 << 24;
+const NO_BLOCKED_SIDE: u8 = 2;
+
+const KIND_START: u8 = 0;
+const KIND_INBOUND: u8 = 1;
+const KIND_INSTRUMENT: u8 = 2;
+const KIND_EXPOSURE: u8 = 3;
+const KIND_EXECUTION_REPORTS: u8 = 4;
+const KIND_FEED: u8 = 5;
+const KIND_DELETED: u8 = 6;
+const KIND_SHUTDOWN: u8 = 7;
+const KIND_TRADE_BUST: u8 = 8;
+const KIND_SEGMENT: u8 = 9;
+
+/// What the journal keeps
+#[derive(Debug, Clone)]
+pub enum JournalEntry {
+    // first entry of a journal: the epoch of the order ids handed out by the engine
+    Start { epoch: u32 },
+    // an order message as received from a gateway
+    Inbound(Vec<u8>),
+    // an update of the clearing applied to the markets
+    Market(MarketUpdate),
+    // published to the gateways
+    ExecutionReports(Vec<ExecutionReport>),
+    // the sequence the feed of a shard reached, for a restart to go on from it
+    Feed { shard: u8, seq: u64 },
+    // the engine stopped, closing all its markets
+    Shutdown,
+}
+
+impl JournalEntry {
+    fn kind(&self) -> u8 {
+        match self {
+            JournalEntry::Start { .. } => KIND_START,
+            JournalEntry::Inbound(_) => KIND_INBOUND,
+            JournalEntry::Market(MarketUpdate::Instrument(_)) => KIND_INSTRUMENT,
+            JournalEntry::Market(MarketUpdate::Exposure { .. }) => KIND_EXPOSURE,
+            JournalEntry::Market(MarketUpdate::Deleted(_)) => KIND_DELETED,
+            JournalEntry::Market(MarketUpdate::TradeBust { .. }) => KIND_TRADE_BUST,
+            JournalEntry::Market(MarketUpdate::Segment { .. }) => KIND_SEGMENT,
+            JournalEntry::ExecutionReports(_) => KIND_EXECUTION_REPORTS,
+            JournalEntry::Feed { .. } => KIND_FEED,
+            JournalEntry::Shutdown => KIND_SHUTDOWN,
+        }
+    }
+
+    fn encode_payload(&self) -> Vec<u8> {
+        match self {
+            JournalEntry::Start { epoch } => epoch.to_le_bytes().to_vec(),
+            JournalEntry::Inbound(message) => message.clone(),
+            JournalEntry::Market(MarketUpdate::Instrument(instrument)) => instrument.encode(),
+            JournalEntry::Market(MarketUpdate::Exposure {
+                participant,
+                book_id,
+                blocked_side,
+            }) => {
+                let mut r = participant.to_le_bytes().to_vec();
+                r.extend_from_slice(&book_id.to_le_bytes());
+                r.push(blocked_side.map_or(NO_BLOCKED_SIDE, |side| side.into()));
+                r
+            }
+            JournalEntry::Market(MarketUpdate::Deleted(book_id)) => book_id.to_le_bytes().to_vec(),
+            JournalEntry::Market(MarketUpdate::TradeBust { book_id, trade_id }) => {
+                [book_id.to_le_bytes(), trade_id.to_le_bytes()].concat()
+            }
+            JournalEntry::Market(MarketUpdate::Segment { segment, state }) => {
+                [segment.to_le_bytes().as_slice(), &[(*state).into()]].concat()
+            }
+            JournalEntry::ExecutionReports(ereports) => ereports
+                .iter()
+                .flat_map(|ereport| ereport.encode())
+                .collect(),
+            JournalEntry::Feed { shard, seq } => [&[*shard], seq.to_le_bytes().as_slice()].concat(),
+            JournalEntry::Shutdown => vec![],
+        }
+    }
+
+    fn decode(kind: u8, payload: &[u8]) -> Option<Self> {
+        match kind {
+            KIND_START => Some(JournalEntry::Start {
+                epoch: u32::from_le_bytes(payload.try_into().ok()?),
+            }),
+            KIND_INBOUND => Some(JournalEntry::Inbound(payload.to_vec())),
+            KIND_INSTRUMENT if payload.len() >= INSTRUMENT_FIXED_SIZE => Some(
+                JournalEntry::Market(MarketUpdate::Instrument(Instrument::decode(payload))),
+            ),
+            KIND_EXPOSURE if payload.len() == 17 => {
+                Some(JournalEntry::Market(MarketUpdate::Exposure {
+                    participant: u64::from_le_bytes(payload[0..8].try_into().unwrap()),
+                    book_id: u64::from_le_bytes(payload[8..16].try_into().unwrap()),
+                    blocked_side: match payload[16] {
+                        NO_BLOCKED_SIDE => None,
+                        side => Some(Side::from(side)),
+                    },
+                }))
+            }
+            KIND_DELETED => Some(JournalEntry::Market(MarketUpdate::Deleted(
+                u64::from_le_bytes(payload.try_into().ok()?),
+            ))),
+            KIND_TRADE_BUST if payload.len() == 16 => {
+                Some(JournalEntry::Market(MarketUpdate::TradeBust {
+                    book_id: u64::from_le_bytes(payload[0..8].try_into().unwrap()),
+                    trade_id: u64::from_le_bytes(payload[8..16].try_into().unwrap()),
+                }))
+            }
+            KIND_SEGMENT if payload.len() == 3 => {
+                Some(JournalEntry::Market(MarketUpdate::Segment {
+                    segment: u16::from_le_bytes([payload[0], payload[1]]),
+                    state: InstrumentState::from(payload[2]),
+                }))
+            }
+            KIND_EXECUTION_REPORTS if payload.len().is_multiple_of(EXECUTIONREPORT_SIZE) => {
+                Some(JournalEntry::ExecutionReports(
+                    payload
+                        .chunks(EXECUTIONREPORT_SIZE)
+                        .map(|chunk| ExecutionReport::decode(chunk.try_into().unwrap()).ok())
+                        .collect::<Option<Vec<_>>>()?,
+                ))
+            }
+            KIND_FEED if payload.len() == 9 => Some(JournalEntry::Feed {
+                shard: payload[0],
+                seq: u64::from_le_bytes(payload[1..9].try_into().unwrap()),
+            }),
+            KIND_SHUTDOWN if payload.is_empty() => Some(JournalEntry::Shutdown),
+            _ => None,
+        }
+    }
+}
+
+/// Why a record of the journal can't be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordError {
+    // not all there, e.g. cut by a crash in the middle of the write
+    Incomplete,
+    // the CRC doesn't match, or the length is garbage
+    Damaged,
+    // sound, but of a kind, or with a payload, this engine can't decode
+    Undecodable(u8),
+}
+
+/// An entry of the journal, with the time it was appended at
+#[derive(Debug, Clone)]
+pub struct JournalRecord {
+    pub timestamp: u64,
+    pub entry: JournalEntry,
+}
+
+impl JournalRecord {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = self.timestamp.to_le_bytes().to_vec();
+        body.push(self.entry.kind());
+        body.append(&mut self.entry.encode_payload());
+        let mut r = (body.len() as u32).to_le_bytes().to_vec();
+        r.extend_from_slice(&crc32(&body).to_le_bytes());
+        r.append(&mut body);
+        r
+    }
+
+    /// The first record of @buffer, along with its size
+    pub fn decode(buffer: &[u8]) -> Result<(Self, usize), RecordError> {
+        if buffer.len() < FRAME_HEADER_SIZE {
+            return Err(RecordError::Incomplete);
+        }
+        let len = u32::from_le_bytes(buffer[0..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(buffer[4..8].try_into().unwrap());
+        if !(RECORD_HEADER_SIZE..=MAX_RECORD_SIZE).contains(&len) {
+            return Err(RecordError::Damaged);
+        }
+        if buffer.len() < FRAME_HEADER_SIZE + len {
+            return Err(RecordError::Incomplete);
+        }
+        let body = &buffer[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + len];
+        if crc32(body) != crc {
+            return Err(RecordError::Damaged);
+        }
+        let entry = JournalEntry::decode(body[8], &body[RECORD_HEADER_SIZE..])
+            .ok_or(RecordError::Undecodable(body[8]))?;
+        Ok((
+            Self {
+                timestamp: u64::from_le_bytes(body[0..8].try_into().unwrap()),
+                entry,
+            },
+            FRAME_HEADER_SIZE + len,
+        ))
+    }
+}
+
+/// The journal file, appended to only
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    // in the file, the ones there at the opening included
+    records: u64,
+    // the backups, getting every record appended
+    replication: Option<ReplicationServer>,
+}
+
+impl Journal {
+    /// Opens the journal at @path, creating it if needed
+    ///
+    /// Returns: the journal, ready for appending, and the records it already
+    /// held. An incomplete or damaged last record is cut off, and so are the
+    /// zeros ending the file: that's what a crash in the middle of a write
+    /// leaves behind. Anything else wrong
+    /// with the records fails the opening, the file being left as it is.
+    pub fn open(path: &Path) -> io::Result<(Self, Vec<JournalRecord>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut buffer = vec![];
+        file.read_to_end(&mut buffer)?;
+        let (records, valid) = decode_records(&buffer)?;
+        if valid < buffer.len() {
+            warn!(
+                bytes = buffer.len() - valid,
+                "Cutting off a damaged record at the end of the journal"
+            );
+            file.set_len(valid as u64)?;
+        }
+        let journal = Self {
+            file,
+            records: records.len() as u64,
+            replication: None,
+        };
+        Ok((journal, records))
+    }
+
+    /// The records of the journal at @path, but for an incomplete or damaged
+    /// last one or the zeros ending it, leaving the file as it is, e.g. for a replay tool while the
+    /// engine still appends to it
+    pub fn read(path: &Path) -> io::Result<Vec<JournalRecord>> {
+        let buffer = std::fs::read(path)?;
+        Ok(decode_records(&buffer)?.0)
+    }
+
+    /// Appends @entry, timestamped now. It is in the file once this returns,
+    /// though not necessarily on the disk yet
+    pub fn append(&mut self, entry: JournalEntry) -> io::Result<()> {
+        self.append_record(&JournalRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            entry,
+        })
+    }
+
+    /// Waits for what was appended to be on the disk
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Appends @record as it is, e.g. received from the primary engine
+    pub fn append_record(&mut self, record: &JournalRecord) -> io::Result<()> {
+        let encoded = record.encode();
+        self.file.write_all(&encoded)?;
+        self.records += 1;
+        if let Some(server) = self.replication.as_mut() {
+            server.send(&encoded);
+        }
+        Ok(())
+    }
+
+    /// Streams the journal to the backups connecting to @server from now on
+    pub fn replicate_to(&mut self, server: ReplicationServer) {
+        self.replication = Some(server);
+    }
+
+    /// Lets in the backups that connected, the records so far going to them
+    /// first, and keeps the others posted with heartbeats
+    pub fn poll_replication(&mut self) -> io::Result<()> {
+        let Some(server) = self.replication.as_mut() else {
+            return Ok(());
+        };
+        let file = &mut self.file;
+        // the writes go to the end, wherever the reads left the file
+        server.accept(|| {
+            let mut buffer = vec![];
+            file.seek(SeekFrom::Start(0))?;
+            file.read_to_end(&mut buffer)?;
+            Ok(buffer)
+        })?;
+        server.heartbeat();
+        Ok(())
+    }
+
+    /// How many records the journal holds, e.g. for a snapshot to tell the
+    /// ones already applied to it
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+}
+
+/// The records of @buffer, along with the size of the valid ones
+///
+/// Fails on a record that can't be read, unless it is the last one and is
+/// either incomplete or fails its CRC, or only zeros follow it
+fn decode_records(buffer: &[u8]) -> io::Result<(Vec<JournalRecord>, usize)> {
+    let mut records = vec![];
+    let mut valid = 0;
+    while valid < buffer.len() {
+        match JournalRecord::decode(&buffer[valid..]) {
+            Ok((record, len)) => {
+                records.push(record);
+                valid += len;
+            }
+            Err(RecordError::Incomplete) => break,
+            // the length is right, the frame ending with the file
+            Err(RecordError::Damaged)
+                if buffer.len() - valid >= FRAME_HEADER_SIZE
+                    && frame_size(&buffer[valid..]) == buffer.len() - valid =>
+            {
+                break
+            }
+            // the file grown, but the data not written yet when it crashed
+            Err(_) if buffer[valid..].iter().all(|b| *b == 0) => break,
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{e:?} journal record at byte {valid}"),
+                ))
+            }
+        }
+    }
+    Ok((records, valid))
+}
+
+// the bytes taken by the frame at the start of @buffer, as told by its length
+fn frame_size(buffer: &[u8]) -> usize {
+    FRAME_HEADER_SIZE + u32::from_le_bytes(buffer[0..4].try_into().unwrap()) as usize
+}
+
+// CRC-32 (IEEE 802.3), the one of zlib and ethernet
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::Write};
+
+    use clearing_connection::genericclearingprotocol::MarketUpdate;
+    use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+    use oep::{
+        decoder::Decoder,
+        execution_report::{ExecutionReport, EXECUTIONREPORT_SIZE},
+    };
+    use order::Side;
+
+    use super::{crc32, Journal, JournalEntry, JournalRecord, RecordError};
+
+    fn entries() -> Vec<JournalEntry> {
+        let mut ereport = ExecutionReport::decode([0; EXECUTIONREPORT_SIZE]).unwrap();
+        ereport.order_id = 12;
+        vec![
+            JournalEntry::Start { epoch: 7 },
+            JournalEntry::Market(MarketUpdate::Instrument(Instrument::new(
+                5,
+                "TEST",
+                InstrumentType::Share,
+                InstrumentState::Trading,
+                10,
+                20,
+            ))),
+            JournalEntry::Market(MarketUpdate::Exposure {
+                participant: 11,
+                book_id: 5,
+                blocked_side: Some(Side::Ask),
+            }),
+            JournalEntry::Market(MarketUpdate::Exposure {
+                participant: 11,
+                book_id: 5,
+                blocked_side: None,
+            }),
+            JournalEntry::Market(MarketUpdate::Deleted(5)),
+            JournalEntry::Market(MarketUpdate::TradeBust {
+                book_id: 5,
+                trade_id: 3,
+            }),
+            JournalEntry::Market(MarketUpdate::Segment {
+                segment: 2,
+                state: InstrumentState::Halted,
+            }),
+            JournalEntry::Inbound(vec![0, 0, 0, 0, 1, 2, 3]),
+            JournalEntry::ExecutionReports(vec![ereport, ereport]),
+            JournalEntry::Feed { shard: 2, seq: 300 },
+            JournalEntry::Shutdown,
+        ]
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+        assert_eq!(0, crc32(&[]));
+    }
+
+    #[test]
+    fn encode_decode() {
+        for entry in entries() {
+            let record = JournalRecord {
+                timestamp: 1000,
+                entry,
+            };
+            let encoded = record.encode();
+            let (decoded, len) = JournalRecord::decode(&encoded).unwrap();
+            assert_eq!(encoded.len(), len);
+            assert_eq!(1000, decoded.timestamp);
+            assert_eq!(encoded, decoded.encode());
+            // incomplete
+            assert_eq!(
+                RecordError::Incomplete,
+                JournalRecord::decode(&encoded[..encoded.len() - 1]).unwrap_err()
+            );
+        }
+    }
+
+    #[test]
+    fn damaged_record() {
+        let record = JournalRecord {
+            timestamp: 1000,
+            entry: JournalEntry::Inbound(vec![1, 2, 3]),
+        };
+        let mut encoded = record.encode();
+        encoded[10] ^= 1;
+        assert_eq!(
+            RecordError::Damaged,
+            JournalRecord::decode(&encoded).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn undecodable_record() {
+        let mut record = JournalRecord {
+            timestamp: 1000,
+            entry: JournalEntry::Shutdown,
+        }
+        .encode();
+        // a kind unknown to the engine, with a CRC to match
+        record[16] = 200;
+        let crc = crc32(&record[8..]);
+        record[4..8].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(
+            RecordError::Undecodable(200),
+            JournalRecord::decode(&record).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn append_and_reopen() {
+        let path = std::env::temp_dir().join(format!("journal_test_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let (mut target, records) = Journal::open(&path).unwrap();
+        assert!(records.is_empty());
+        for entry in entries() {
+            target.append(entry).unwrap();
+        }
+        drop(target);
+
+        let (mut target, records) = Journal::open(&path).unwrap();
+        assert_eq!(entries().len(), records.len());
+        assert_eq!(entries().len() as u64, target.records());
+        for (entry, record) in entries().into_iter().zip(records) {
+            assert_eq!(entry.encode_payload(), record.entry.encode_payload());
+        }
+        target.append(JournalEntry::Start { epoch: 8 }).unwrap();
+        drop(target);
+
+        // a crash in the middle of a write
+        let len = fs::metadata(&path).unwrap().len();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[30, 0, 0, 0, 1])
+            .unwrap();
+        // read as it is
+        assert_eq!(entries().len() + 1, Journal::read(&path).unwrap().len());
+        assert_eq!(len + 5, fs::metadata(&path).unwrap().len());
+        let (mut target, records) = Journal::open(&path).unwrap();
+        assert_eq!(entries().len() + 1, records.len());
+        assert_eq!(len, fs::metadata(&path).unwrap().len());
+        // appending after the records kept
+        target.append(JournalEntry::Start { epoch: 9 }).unwrap();
+        assert_eq!(entries().len() as u64 + 2, target.records());
+        let (_, records) = Journal::open(&path).unwrap();
+        assert!(matches!(
+            records.last().map(|r| &r.entry),
+            Some(JournalEntry::Start { epoch: 9 })
+        ));
+
+        // a whole last record failing its CRC is cut off too
+        let len = fs::metadata(&path).unwrap().len();
+        let mut damaged = JournalRecord {
+            timestamp: 1000,
+            entry: JournalEntry::Shutdown,
+        }
+        .encode();
+        damaged[8] ^= 1;
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&damaged)
+            .unwrap();
+        let (_, records) = Journal::open(&path).unwrap();
+        assert_eq!(entries().len() + 2, records.len());
+        assert_eq!(len, fs::metadata(&path).unwrap().len());
+
+        // the file grown before the crash, without the records written
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0; 100])
+            .unwrap();
+        assert_eq!(entries().len() + 2, Journal::read(&path).unwrap().len());
+        let (_, records) = Journal::open(&path).unwrap();
+        assert_eq!(entries().len() + 2, records.len());
+        assert_eq!(len, fs::metadata(&path).unwrap().len());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn records_after_an_unreadable_one_are_kept() {
+        let path =
+            std::env::temp_dir().join(format!("journal_unreadable_test_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let record = |entry| JournalRecord {
+            timestamp: 1000,
+            entry,
+        };
+        let mut undecodable = record(JournalEntry::Shutdown).encode();
+        undecodable[16] = 200;
+        let crc = crc32(&undecodable[8..]);
+        undecodable[4..8].copy_from_slice(&crc.to_le_bytes());
+        let mut damaged = record(JournalEntry::Shutdown).encode();
+        damaged[8] ^= 1;
+        let good = record(JournalEntry::Start { epoch: 7 }).encode();
+
+        for unreadable in [undecodable, damaged] {
+            let content = [good.clone(), unreadable, good.clone()].concat();
+            fs::write(&path, &content).unwrap();
+            assert_eq!(
+                std::io::ErrorKind::InvalidData,
+                Journal::open(&path).unwrap_err().kind()
+            );
+            assert!(Journal::read(&path).is_err());
+            // nothing cut off
+            assert_eq!(content, fs::read(&path).unwrap());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1 +1,61 @@
+#[cfg(feature = "usdt")]
+usdt::dtrace_provider!("matching_engine_probes.d");
+
+/// Evaluates @func, firing the probe @probe with the time it took, in nanoseconds
+#[cfg(feature = "usdt")]
+#[macro_export]
+macro_rules! timeit {
+    ($probe: ident, $func: expr) => {{
+        let start = std::time::Instant::now();
+        let result = $func;
+        let duration = start.elapsed().as_nanos() as u64;
+        $crate::probes::$probe(duration);
+        result
+    }};
+}
+#[cfg(not(feature = "usdt"))]
+#[macro_export]
+macro_rules! timeit {
+    ($_probe: ident, $func: expr) => {{
+        $func
+    }};
+}
+
+/// The probes of the engine, callable from the binary as well
+#[cfg(feature = "usdt")]
+pub mod probes {
+    macro_rules! probes {
+        ($($probe: ident),*) => {
+            $(
+                pub fn $probe(duration: u64) {
+                    crate::matching::$probe!(|| (duration));
+                }
+            )*
+        };
+    }
+    probes!(
+        decode,
+        process,
+        publish,
+        clearing_process,
+        send_snapshots,
+        send_checksums,
+        send_statistics,
+        expire_orders,
+        recovery
+    );
+}
+
+pub mod admin;
+pub mod config;
+pub mod egress;
+pub mod ingress;
+pub mod journal;
+pub mod metrics;
+pub mod pending;
 pub mod processor;
+pub mod registry;
+pub mod replication;
+pub mod schedule;
+pub mod shard;
+pub mod snapshot;
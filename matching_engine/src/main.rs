@@ -1,119 +1,652 @@
-use configparser::ini::Ini;
-use disseminator::mbooepdisseminator::MBOOepDisseminator;
 use instruments::genericinstrumentlist::GenericInstrumentList;
 use oep::decoder::Decoder;
-use oep::execution_report::EXECUTIONREPORT_SIZE;
+use oep::egress::EgressMode;
+use oep::engine_status::{EngineState, EngineStatus, ENGINESTATUS_SIZE};
+use oep::eodsummary::EodSummary;
+use oep::execution_report::RejectReason;
 use oep::header::{OepHeader, OEP_VERSION};
+use oep::ingress::{IngressMode, IngressNak, INGRESSNAK_SIZE};
 use oep::oep_message::MsgType;
+use oep::registration::{GatewayRegistrationReply, GATEWAYREGISTRATIONREPLY_SIZE};
+use oep::tradecapture::TradeCapture;
 use polling::{Event, Events, PollMode, Poller};
 
 #[cfg(feature = "usdt")]
-use usdt::{dtrace_provider, register_probes};
+use usdt::register_probes;
 
-use std::cell::RefCell;
-use std::collections::HashMap;
 use std::error::Error;
 use std::io::{Read, Write};
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::os::fd::AsRawFd;
-use std::rc::Rc;
-use std::str::FromStr;
-use std::time::{Duration, Instant};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use socket2::Socket;
+use tracing::{error, info, warn};
 
 use clearing_connection::clearclearingconnection::ClearClearingConnection;
 use clearing_connection::clearingconnection::ClearingConnection;
 use clearing_connection::clearprotocol::ClearProtocol;
 use clearing_connection::genericclearingprotocol::GenericClearingProtocol;
+use clearing_connection::liveness::{Liveness, LivenessConfig};
+use clearing_connection::logon::EngineCredentials;
 use instruments::instrumentlist::InstrumentList;
-use market::Market;
+use instruments::partition::Partition;
+use market::orderid::OrderIdGenerator;
+use matching_engine::admin::{self, AdminCommand};
+use matching_engine::config::{ClearingConnectionConfig, EngineConfig};
+use matching_engine::egress::{self, SequencedEgress};
+use matching_engine::ingress::{Ingress, IngressTracker};
+use matching_engine::journal::{Journal, JournalEntry, JournalRecord};
+use matching_engine::metrics::EngineMetrics;
+use matching_engine::pending::{Hold, PendingOrders};
+use matching_engine::processor::MessageWrapper;
+use matching_engine::registry::{self, GatewayRegistry};
+use matching_engine::replication::{ReplicationClient, ReplicationServer};
+use matching_engine::shard::{
+    self, Dispatcher, ExecutionReportPublisher, FeedConfig, Shard, ShardCommand, ShardConfig,
+    ShardEvent,
+};
+use matching_engine::snapshot::{EngineSnapshot, PendingSnapshot};
+use matching_engine::{processor, schedule, timeit};
 use utils::config;
-use utils::network;
+use utils::logging::{self, LogConfig};
+use utils::metrics::{self, MetricsConfig};
+use utils::network::{self, MulticastConfig};
+use utils::{reload, shutdown};
 
-mod processor;
+const CONFIG_FILE: &str = "matching_engine.ini";
+
+/// Where the markets of the engine live
+enum Markets {
+    // on the main thread, created by the clearing protocol
+    Single(Box<Shard>),
+    // on the worker threads, the clearing updates being forwarded to them
+    Sharded {
+        dispatcher: Dispatcher,
+        events: Receiver<ShardEvent>,
+    },
+}
+
+/// Connects again to the clearing, downloading the instruments and resending
+/// the trade captures it didn't ack
+fn reconnect_clearing(
+    clearing_connection: &mut ClearClearingConnection,
+    poller: &Poller,
+) -> Result<(), Box<dyn Error>> {
+    clearing_connection.reconnect(poller)?;
+    clearing_connection.request_instruments()?;
+    clearing_connection.resend_trade_captures(u64::MAX)?;
+    Ok(())
+}
+
+/// Hands @msg over to the market of @book_id
+fn dispatch_order(
+    markets: &mut Markets,
+    publisher: &mut ExecutionReportPublisher,
+    msg: MessageWrapper,
+    book_id: u64,
+) -> Result<(), Box<dyn Error>> {
+    match markets {
+        Markets::Single(shard) => publisher.publish(&shard.process(msg, book_id))?,
+        Markets::Sharded { dispatcher, .. } => dispatcher
+            .dispatch_order(msg, book_id)
+            .map_err(|_| "A shard stopped")?,
+    }
+    Ok(())
+}
+
+/// Rejects @msg for @reason, without a market getting to it
+fn reject_order(
+    publisher: &mut ExecutionReportPublisher,
+    msg: &MessageWrapper,
+    reason: RejectReason,
+    partition: &Partition,
+) -> std::io::Result<()> {
+    let mut ereports = processor::reject_message(msg, reason);
+    for ereport in ereports.iter_mut() {
+        ereport.partition_id = partition.get_id();
+    }
+    publisher.publish(&ereports)
+}
+
+/// Brings the markets back to where they were before a restart, going again
+/// through the order messages and the market updates of the journal
+///
+/// Returns: the number of order messages replayed
+fn replay_journal(
+    records: &[JournalRecord],
+    markets: &mut Markets,
+    partition: &Partition,
+    pending: &mut Option<PendingOrders>,
+) -> Result<usize, Box<dyn Error>> {
+    if let Markets::Sharded { dispatcher, .. } = markets {
+        dispatcher.set_replaying(true);
+    }
+    let mut replayed = 0;
+    for record in records {
+        match &record.entry {
+            JournalEntry::Inbound(message) => {
+                let Ok((msg, book_id)) = processor::decode_message(message) else {
+                    continue;
+                };
+                // rejected the first time
+                if !shard::acts_on_all_books(&msg) && !partition.contains(book_id) {
+                    continue;
+                }
+                match markets {
+                    Markets::Single(shard) => shard.replay(ShardCommand::Order(msg, book_id)),
+                    Markets::Sharded { dispatcher, .. } => dispatcher
+                        .dispatch_order(msg, book_id)
+                        .map_err(|_| "A shard stopped")?,
+                }
+                replayed += 1;
+            }
+            JournalEntry::Market(update) => {
+                // nothing held yet, only the books with a market to learn
+                if let Some(pending) = pending.as_mut() {
+                    pending.market_updated(update);
+                }
+                match markets {
+                    Markets::Single(shard) => shard.replay(ShardCommand::Market(update.clone())),
+                    Markets::Sharded { dispatcher, .. } => dispatcher
+                        .dispatch_market_update(update.clone())
+                        .map_err(|_| "A shard stopped")?,
+                }
+            }
+            JournalEntry::Feed { shard: index, seq } => match markets {
+                Markets::Single(shard) if *index == 0 => {
+                    shard.replay(ShardCommand::ResumeFeed(*seq))
+                }
+                Markets::Single(_) => {}
+                Markets::Sharded { dispatcher, .. } => dispatcher
+                    .resume_feed(*index as usize, *seq)
+                    .map_err(|_| "A shard stopped")?,
+            },
+            // the markets were closed, their day orders cancelled
+            JournalEntry::Shutdown => match markets {
+                Markets::Single(shard) => shard.replay(ShardCommand::ShutDown),
+                Markets::Sharded { dispatcher, .. } => {
+                    dispatcher.shut_down().map_err(|_| "A shard stopped")?
+                }
+            },
+            _ => {}
+        }
+    }
+    if let Markets::Sharded { dispatcher, .. } = markets {
+        dispatcher.set_replaying(false);
+    }
+    Ok(replayed)
+}
+
+/// Appends @records, received from the primary, to the journal of the backup
+fn mirror(journal: &Option<Arc<Mutex<Journal>>>, records: &[JournalRecord]) -> std::io::Result<()> {
+    if let Some(journal) = journal {
+        let mut journal = journal.lock().unwrap();
+        records
+            .iter()
+            .try_for_each(|record| journal.append_record(record))?;
+    }
+    Ok(())
+}
+
+/// Applies the journal records streamed by the primary to the markets, and
+/// keeps them in @journal, until the primary goes away
+///
+/// Returns: why the primary is taken as gone
+fn follow_primary(
+    client: &mut ReplicationClient,
+    journal: &Option<Arc<Mutex<Journal>>>,
+    markets: &mut Markets,
+    partition: &Partition,
+    pending: &mut Option<PendingOrders>,
+) -> Result<std::io::Error, Box<dyn Error>> {
+    loop {
+        let records = match client.receive() {
+            Ok(records) => records,
+            Err(e) => return Ok(e),
+        };
+        mirror(journal, &records)?;
+        replay_journal(&records, markets, partition, pending)?;
+    }
+}
+
+/// Replaces the snapshot at @path, the engine going on without it on errors
+fn save_snapshot(path: &str, snapshot: &EngineSnapshot) {
+    match snapshot.save(Path::new(path)) {
+        Ok(()) => info!(
+            journal_records = snapshot.journal_records,
+            "Saved a snapshot"
+        ),
+        Err(e) => error!(path, error = %e, "Error saving the snapshot"),
+    }
+}
+
+/// Reads the configuration file again, applying its market settings to
+/// @markets. Nothing changes if the file is invalid
+///
+/// Returns: the snapshot interval reloaded, None on errors
+fn reload_settings(markets: &mut Markets) -> Option<Duration> {
+    let reloaded = match config::load(CONFIG_FILE).and_then(|m| EngineConfig::from_config(&m)) {
+        Ok(reloaded) => reloaded,
+        Err(e) => {
+            error!(error = %e, "Unable to reload the configuration, keeping the old one");
+            return None;
+        }
+    };
+    let (volatility, without_reference) = (reloaded.volatility(), reloaded.bands_without_reference);
+    match markets {
+        Markets::Single(shard) => shard.reconfigure(volatility, without_reference),
+        Markets::Sharded { dispatcher, .. } => {
+            if dispatcher
+                .reconfigure(volatility, without_reference)
+                .is_err()
+            {
+                error!("A shard stopped, not reloading");
+                return None;
+            }
+        }
+    }
+    info!(
+        snapshot_every_s = reloaded.snapshot_every_s,
+        volatility_percentage = volatility.percentage,
+        bands_without_reference = ?without_reference,
+        "Reloaded the configuration"
+    );
+    Some(Duration::from_secs(reloaded.snapshot_every_s))
+}
+
+/// Sends @trade_captures to the clearing, for the position keeping, and
+/// @eod_summaries, for it to keep the closing prices
+fn report_to_clearing(
+    clearing_connection: &mut ClearClearingConnection,
+    trade_captures: Vec<TradeCapture>,
+    eod_summaries: Vec<EodSummary>,
+    metrics: &EngineMetrics,
+) {
+    for summary in eod_summaries {
+        let message = clearing_connection
+            .get_protocol()
+            .as_ref()
+            .map(|p| p.prepare_eod_summary(&summary));
+        if let Some(message) = message {
+            if let Err(e) = clearing_connection.write_all(&message) {
+                error!(
+                    book_id = { summary.book_id },
+                    error = %e,
+                    "Error sending the EOD summary"
+                );
+            }
+        }
+    }
+    metrics.trades.add(trade_captures.len() as u64);
+    for capture in trade_captures {
+        // kept until acked, resent after a reconnect
+        if let Err(e) = clearing_connection.send_trade_capture(capture) {
+            error!(error = %e, "Error sending a trade capture");
+        }
+    }
+}
+
+/// Collects the trade captures and the end of day summaries the shards send
+/// back until all @count of them stopped, waiting @timeout at most
+fn wait_for_shards(
+    events: &Receiver<ShardEvent>,
+    count: usize,
+    timeout: Duration,
+) -> (Vec<TradeCapture>, Vec<EodSummary>) {
+    let deadline = Instant::now() + timeout;
+    let (mut captures, mut summaries, mut stopped) = (vec![], vec![], 0);
+    while stopped < count {
+        match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(ShardEvent::TradeCapture(capture)) => captures.push(capture),
+            Ok(ShardEvent::EodSummary(summary)) => summaries.push(summary),
+            Ok(ShardEvent::State(..)) => {}
+            Ok(ShardEvent::Stopped(_)) => stopped += 1,
+            Err(RecvTimeoutError::Timeout) => {
+                error!(stopped, count, "Gave up waiting for the shards to stop");
+                break;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    (captures, summaries)
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
     // load USDTs
     #[cfg(feature = "usdt")]
-    dtrace_provider!("matching_engine_probes.d");
-    #[cfg(feature = "usdt")]
     register_probes().unwrap();
-    #[cfg(feature = "usdt")]
-    macro_rules! timeit {
-        ($probe: ident, $func: expr) => {{
-            let start = Instant::now();
-            let result = $func;
-            let duration = start.elapsed().as_nanos() as u64;
-            matching::$probe!(|| (duration));
-            result
-        }};
-    }
-    #[cfg(not(feature = "usdt"))]
-    macro_rules! timeit {
-        ($_probe: ident, $func: expr) => {{
-            $func
-        }};
-    }
-
-    println!("Loading configuration file");
-    let mut config = Ini::new();
-    let config_map = config
-        .load("matching_engine.ini")
-        .expect("Unable to load the configuration file");
-    let disseminator_addr = config::get_config_string(&config_map, "engine", "disseminator_group");
-    let disseminator_port = config::get_config_string(&config_map, "engine", "disseminator_port")
-        .parse::<u16>()
-        .expect("Disseminator port must be an u16");
-    let max_packet_size = config::get_config_string(&config_map, "engine", "max_packet_size")
-        .parse::<u16>()
-        .expect("max_packet_size must be an u16") as usize;
-    let order_addr = config::get_config_string(&config_map, "engine", "order_group");
-    let order_port = config::get_config_string(&config_map, "engine", "order_port")
-        .parse::<u16>()
-        .expect("Order port must be an u16");
-
-    let internal_publisher_addr =
-        config::get_config_string(&config_map, "engine", "internal_publisher_group");
-    let internal_publisher_port =
-        config::get_config_string(&config_map, "engine", "internal_publisher_port")
-            .parse::<u16>()
-            .expect("Internal publisher port must be an u16 integer");
-
-    let clearing_addr = config::get_config_string(&config_map, "clearing", "address");
-    let clearing_port = config::get_config_string(&config_map, "clearing", "port")
-        .parse::<u16>()
-        .expect("Clearing port must be an u16");
-
-    println!("Starting the engine");
-    let poller = Poller::new()?;
+
+    let config_map = config::load(CONFIG_FILE)?;
+    let log_config = LogConfig::from_config(&config_map, "engine")
+        .expect("Invalid log settings in the engine section");
+    logging::init(&log_config).expect("Unable to start logging");
+    info!("Loaded the configuration file");
+    let engine_config = EngineConfig::from_config(&config_map)?;
+    let max_packet_size = engine_config.max_packet_size as usize;
+    // used to tell the primary and its backups apart
+    let engine_id = engine_config.id;
+    // the books handled by this engine, all of them by default
+    let partition = engine_config
+        .partition()
+        .expect("partition_books checked when loading");
+
+    let schedule = schedule::Schedule::from_config(&config_map).expect("Invalid schedule section");
+
+    // the order messages and the market updates are journaled, only if a file is given
+    let journal_path = engine_config.journal.clone();
+    // the markets are saved every now and then, only if a file is given, the
+    // journal records coming after the snapshot being replayed on top of it
+    let snapshot_path = engine_config.snapshot.clone();
+    let mut snapshot_every = Duration::from_secs(engine_config.snapshot_every_s);
+    // a hot standby, following the primary at this address until it goes away
+    let replication_timeout = Duration::from_millis(engine_config.replication_timeout_ms);
+
+    // worker threads, each with its own share of the markets, none by default
+    let shards = engine_config.shards;
+    // the messages for a book without a market wait that long for the
+    // clearing to send its instrument, rejected right away without it
+    let mut pending = engine_config
+        .unknown_book_wait_ms
+        .map(|w| PendingOrders::new(Duration::from_millis(w)));
+    // scraped over HTTP, only if a port is given
+    let metrics_config =
+        MetricsConfig::from_config(&config_map, "engine").expect("Metrics port must be an u16");
+    let multicast = MulticastConfig::from_config(&config_map, "engine")
+        .expect("Invalid multicast settings in the engine section");
+
+    let clearing_config = ClearingConnectionConfig::from_config(&config_map)?;
+    let clearing_liveness_config = LivenessConfig::from_config(&config_map, "clearing")
+        .expect("The clearing heartbeat settings must be integers");
+
+    info!("Starting the engine");
+    let metrics = EngineMetrics::new(metrics::registry());
+    if let Some(metrics_config) = &metrics_config {
+        metrics_config.serve()?;
+    }
+    let poller = Arc::new(Poller::new()?);
     let mut poll_events = Events::new();
-    let markets = Rc::new(RefCell::new(HashMap::<u64, Market>::new()));
-
-    println!("Connecting to clearing");
-    // we will use the "Clear" protocol
-    let protocol_h = Box::new(ClearProtocol::new(
-        InstrumentList::new(),
-        markets.clone(),
-        Rc::new(RefCell::new(MBOOepDisseminator::new(
-            &disseminator_addr,
-            disseminator_port,
-        ))),
-    )) as Box<dyn GenericClearingProtocol>;
-    let mut clearing_connection =
-        ClearClearingConnection::new(&clearing_addr, clearing_port, Some(protocol_h));
+
+    let mut internal_publisher_socket = network::udp_sender(
+        &network::socket_address(
+            &engine_config.internal_publisher_group,
+            engine_config.internal_publisher_port,
+        )?,
+        &multicast,
+    )?;
+    let mut publisher = ExecutionReportPublisher::new(internal_publisher_socket.try_clone()?);
+    let egress = match engine_config.egress {
+        EgressMode::Multicast => None,
+        EgressMode::Sequenced => {
+            // the gateways tell the runs of the engine apart by their start time
+            let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+            let egress = Arc::new(Mutex::new(SequencedEgress::new(
+                engine_id,
+                partition.get_id(),
+                epoch,
+                engine_config.egress_buffer_size,
+            )));
+            publisher = publisher.with_egress(egress.clone());
+            Some(egress)
+        }
+    };
+    // the gateways running, and where their reports go
+    let mut gateways = GatewayRegistry::new(
+        engine_id,
+        partition.get_id(),
+        Duration::from_millis(engine_config.gateway_timeout_ms),
+        engine_config.unicast_reports,
+    );
+    publisher = publisher.with_routes(gateways.routes());
+
+    // what the engine went through before a restart
+    let (journal, mut journal_records) = match &journal_path {
+        Some(path) => {
+            let (journal, records) = Journal::open(Path::new(path))?;
+            info!(path, records = records.len(), "Opened the journal");
+            let journal = Arc::new(Mutex::new(journal));
+            publisher = publisher.with_journal(journal.clone());
+            (Some(journal), records)
+        }
+        None => (None, vec![]),
+    };
+    // a backup goes through the journal of the primary instead
+    let mut replication = match &engine_config.replicate_from {
+        Some(addr) => {
+            assert!(
+                journal_records.is_empty(),
+                "The journal of a backup must start empty"
+            );
+            info!(addr, "Following the primary");
+            let mut client = ReplicationClient::connect(addr, replication_timeout)?;
+            // starting with the epoch of its order ids
+            while journal_records.is_empty() {
+                journal_records = client.receive()?;
+            }
+            mirror(&journal, &journal_records)?;
+            Some(client)
+        }
+        None => None,
+    };
+
+    // let the gateways know they should hold on to the orders until we're ready
+    let engine_status_header = OepHeader {
+        oep_version: OEP_VERSION,
+        msg_type: MsgType::EngineStatus.into(),
+        msg_len: ENGINESTATUS_SIZE as u32,
+        seq: 0,
+    }
+    .encode();
+    let send_engine_status = |socket: &mut Socket, state: EngineState| {
+        socket.write(
+            [
+                engine_status_header.as_slice(),
+                EngineStatus::new(engine_id, state).encode().as_slice(),
+            ]
+            .concat()
+            .as_slice(),
+        )
+    };
+    // a backup stays quiet until taking over
+    if replication.is_none() {
+        send_engine_status(&mut internal_publisher_socket, EngineState::Starting)?;
+    }
+    // asks a gateway for the orders lost on their way
+    let ingress_nak_header = OepHeader::new(
+        OEP_VERSION,
+        MsgType::IngressNak.into(),
+        INGRESSNAK_SIZE as u32,
+    )
+    .encode();
+    let send_ingress_nak = |socket: &mut Socket, nak: IngressNak| {
+        socket.write(
+            [ingress_nak_header.as_slice(), nak.encode().as_slice()]
+                .concat()
+                .as_slice(),
+        )
+    };
+    // answers the registrations of the gateways
+    let registration_reply_header = OepHeader::new(
+        OEP_VERSION,
+        MsgType::GatewayRegistrationReply.into(),
+        GATEWAYREGISTRATIONREPLY_SIZE as u32,
+    )
+    .encode();
+    let send_registration_reply = |socket: &mut Socket, reply: GatewayRegistrationReply| {
+        socket.write(
+            [
+                registration_reply_header.as_slice(),
+                reply.encode().as_slice(),
+            ]
+            .concat()
+            .as_slice(),
+        )
+    };
+    let mut ingress = match engine_config.ingress {
+        IngressMode::Multicast => None,
+        IngressMode::Sequenced => Some(IngressTracker::new(engine_id)),
+    };
+
+    let shard_config = ShardConfig {
+        feed: FeedConfig {
+            format: engine_config.feed_format,
+            disseminator_group: engine_config.disseminator_group.clone(),
+            disseminator_port: engine_config.disseminator_port,
+            // the same feed again on the B group, only if a port is given
+            disseminator_b_group: engine_config
+                .disseminator_b_group
+                .clone()
+                .unwrap_or(engine_config.disseminator_group.clone()),
+            disseminator_b_port: engine_config.disseminator_b_port,
+            snapshot_group: engine_config.snapshot_group.clone(),
+            snapshot_port: engine_config.snapshot_port,
+            mtu: engine_config.feed_mtu,
+            batch_max_delay: Duration::from_micros(engine_config.batch_max_delay_us),
+            recovery_address: engine_config.recovery_address.clone(),
+            recovery_port: engine_config.recovery_port,
+            recovery_cache_size: engine_config.recovery_cache_size,
+            multicast: multicast.clone(),
+        },
+        volatility: engine_config.volatility(),
+        without_reference: engine_config.bands_without_reference,
+        partition_id: partition.get_id(),
+        schedule,
+        index: 0,
+        journal: journal.clone(),
+        standby: replication.is_some(),
+    };
+    // one id space for all the markets, the same one across the restarts
+    // journaled, for the replayed orders to get their ids back
+    let order_ids = match journal_records.first().map(|r| &r.entry) {
+        Some(JournalEntry::Start { epoch }) => OrderIdGenerator::new(*epoch),
+        _ => {
+            let order_ids = OrderIdGenerator::from_clock();
+            if let Some(journal) = &journal {
+                journal.lock().unwrap().append(JournalEntry::Start {
+                    epoch: order_ids.epoch(),
+                })?;
+            }
+            order_ids
+        }
+    };
+
+    let mut markets = match shards {
+        0 => Markets::Single(Box::new(Shard::new(shard_config, order_ids)?)),
+        _ => {
+            info!(shards, "Starting the shards");
+            let (events_sender, events) = mpsc::channel();
+            let waker = poller.clone();
+            let dispatcher = shard::spawn_shards(
+                shards,
+                &shard_config,
+                order_ids,
+                &publisher,
+                events_sender,
+                Arc::new(move || {
+                    let _ = waker.notify();
+                }),
+            )?;
+            Markets::Sharded { dispatcher, events }
+        }
+    };
+    // the snapshot saves replaying the journal from its start
+    let mut replay_from = 0;
+    // that of a backup being no match for the journal of the primary
+    if let (Some(path), None) = (&snapshot_path, &replication) {
+        match EngineSnapshot::load(Path::new(path)) {
+            Ok(Some(snapshot))
+                if snapshot.shards.len() != shards.max(1)
+                    || snapshot.journal_records > journal_records.len() as u64 =>
+            {
+                warn!(
+                    path,
+                    "Ignoring the snapshot, not matching the shards or the journal"
+                )
+            }
+            Ok(Some(snapshot)) => {
+                replay_from = snapshot.journal_records as usize;
+                match &mut markets {
+                    Markets::Single(shard) => {
+                        shard.restore_state(snapshot.shards.into_iter().next().unwrap())
+                    }
+                    Markets::Sharded { dispatcher, .. } => dispatcher
+                        .restore_states(snapshot.shards)
+                        .map_err(|_| "A shard stopped")?,
+                }
+                info!(path, journal_records = replay_from, "Restored the snapshot");
+            }
+            Ok(None) => {}
+            Err(e) => warn!(path, error = %e, "Ignoring the snapshot"),
+        }
+    }
+    // the books of the markets restored from the snapshot
+    if let Some(pending) = pending.as_mut() {
+        for record in &journal_records[..replay_from] {
+            if let JournalEntry::Market(update) = &record.entry {
+                pending.market_updated(update);
+            }
+        }
+    }
+    if journal_records.len() > replay_from {
+        let replayed = replay_journal(
+            &journal_records[replay_from..],
+            &mut markets,
+            &partition,
+            &mut pending,
+        )?;
+        info!(replayed, "Replayed the order messages of the journal");
+    }
+    if let Some(client) = replication.as_mut() {
+        let e = follow_primary(client, &journal, &mut markets, &partition, &mut pending)?;
+        warn!(error = %e, "Lost the primary, taking over");
+        // where the primary stopped: the books, the order ids and the feed sequences
+        match &mut markets {
+            Markets::Single(shard) => shard.take_over(),
+            Markets::Sharded { dispatcher, .. } => {
+                dispatcher.take_over().map_err(|_| "A shard stopped")?
+            }
+        }
+        send_engine_status(&mut internal_publisher_socket, EngineState::Starting)?;
+    }
+    if let (Some(port), Some(journal)) = (engine_config.replication_port, &journal) {
+        info!(port, "Replicating the journal");
+        journal.lock().unwrap().replicate_to(ReplicationServer::new(
+            &engine_config.replication_address,
+            port,
+        )?);
+    }
+
+    info!("Connecting to clearing");
+    // we will use the "Clear" protocol, the updates being applied by the markets
+    let mut protocol = ClearProtocol::forwarding(InstrumentList::new());
+    protocol.set_partition(partition.clone());
+    // the engine id tells the engines apart for the clearing as well
+    protocol.set_credentials(EngineCredentials {
+        engine_id,
+        secret: clearing_config.secret,
+    });
+    let protocol_h = Box::new(protocol) as Box<dyn GenericClearingProtocol>;
+    let mut clearing_connection = ClearClearingConnection::new(
+        &clearing_config.address,
+        clearing_config.port,
+        Some(protocol_h),
+    );
     clearing_connection.connect()?;
     clearing_connection.register_with_poller(&poller)?;
-    let clearing_socket_fd = clearing_connection.get_socket_key();
+    let mut clearing_socket_fd = clearing_connection.get_socket_key();
 
-    println!("Requesting the instrument list");
+    info!("Requesting the instrument list");
     clearing_connection.request_instruments()?;
 
     // at this point we have an instrument list, so theoretically we can accept orders
-    println!("Preparing order socket");
-    let mut order_socket = network::join_multicast_group(&SockAddr::from(SocketAddr::V4(
-        SocketAddrV4::new(Ipv4Addr::from_str(&order_addr)?, order_port),
-    )))?;
+    info!("Preparing order socket");
+    let mut order_socket = network::join_multicast_group(
+        &network::socket_address(&engine_config.order_group, engine_config.order_port)?,
+        &multicast,
+    )?;
     let order_socket_fd = order_socket.as_raw_fd() as usize;
     unsafe {
         poller.add_with_mode(
@@ -123,65 +656,152 @@ fn main() -> Result<(), Box<dyn Error>> {
         )?;
     }
 
-    let mut internal_publisher_socket =
-        Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
-    internal_publisher_socket.connect(&SockAddr::from(SocketAddr::V4(SocketAddrV4::new(
-        Ipv4Addr::from_str(&internal_publisher_addr).unwrap(),
-        internal_publisher_port,
-    ))))?;
-    internal_publisher_socket
-        .set_multicast_loop_v4(true)
-        .expect("set_multicast_loop_v4");
+    // from now on a signal closes the markets instead of killing the engine
+    let waker = poller.clone();
+    let stopping = shutdown::on_stop_signal(move || {
+        let _ = waker.notify();
+    })?;
+    // and SIGHUP reloads the settings that can change on the fly
+    let waker = poller.clone();
+    let reloading = reload::on_reload_signal(move || {
+        let _ = waker.notify();
+    })?;
+    // the admin wakes the poller up for its commands to be run here
+    let admin_requests = match &engine_config.admin_socket {
+        Some(path) => {
+            let waker = poller.clone();
+            Some(admin::spawn(
+                path,
+                Arc::new(move || {
+                    let _ = waker.notify();
+                }),
+            )?)
+        }
+        None => None,
+    };
 
     // the main loop
-    println!("Ready to trade");
+    info!("Ready to trade");
+    send_engine_status(&mut internal_publisher_socket, EngineState::Ready)?;
     let mut read_buffer = Vec::with_capacity(max_packet_size);
     read_buffer.resize_with(max_packet_size, Default::default);
 
     let mut clearing_buffer = vec![];
+    let mut clearing_liveness = Liveness::new(clearing_liveness_config, Instant::now());
 
-    const SEND_SNAPSHOTS_EVERY_MS: Duration = Duration::from_millis(20000);
-    let mut last_snapshot_sent = Instant::now() - Duration::from_millis(18000);
+    // must stay well below the engine_timeout_ms of the gateways
+    const SEND_ENGINE_STATUS_EVERY_MS: Duration = Duration::from_millis(500);
+    let mut last_engine_status_sent = Instant::now();
+    const RESEND_CAPTURES_EVERY_MS: Duration = Duration::from_millis(5000);
+    // for the shards to close their markets
+    const SHUT_DOWN_TIMEOUT: Duration = Duration::from_millis(5000);
+    let mut last_capture_check = Instant::now();
+    let mut last_state_saved = Instant::now();
+    // asked by the clearing, not waiting for snapshot_every
+    let mut snapshot_requested = false;
+    // waiting for the states of the shards
+    let mut pending_snapshot: Option<PendingSnapshot> = None;
 
-    let execution_report_header = OepHeader {
-        oep_version: OEP_VERSION,
-        msg_type: MsgType::ExecutionReport.into(),
-        msg_len: EXECUTIONREPORT_SIZE as u32,
-    }
-    .encode();
+    // wake up in time for the batches to go out, the shards see to their own
+    let poll_timeout = match &markets {
+        Markets::Single(shard) => shard.poll_timeout(),
+        Markets::Sharded { .. } => Duration::from_millis(500),
+    };
 
-    loop {
+    while !stopping.load(Ordering::SeqCst) {
         poll_events.clear();
-        poller.wait(&mut poll_events, Some(Duration::from_millis(500)))?;
+        poller.wait(&mut poll_events, Some(poll_timeout))?;
         for ev in poll_events.iter() {
             match ev.key {
                 k if k == order_socket_fd => {
                     let r = order_socket.read(&mut read_buffer).unwrap_or_default();
-                    if r > 3 {
-                        let msg_result =
-                            timeit!(decode, processor::decode_message(&read_buffer[0..r]));
-                        match msg_result {
-                            Ok((msg, book_id)) => match markets.borrow_mut().get_mut(&book_id) {
-                                Some(market) => {
-                                    let ereports =
-                                        timeit!(process, processor::process_message(market, msg));
-                                    for ereport in &ereports {
-                                        timeit!(
-                                            publish,
-                                            internal_publisher_socket.write(
-                                                [
-                                                    execution_report_header.as_slice(),
-                                                    ereport.encode().as_slice(),
-                                                ]
-                                                .concat()
-                                                .as_slice(),
-                                            )?
-                                        );
-                                    }
+                    let message = match &mut ingress {
+                        None => &read_buffer[0..r],
+                        Some(tracker) => {
+                            match tracker.receive(&read_buffer[0..r], Instant::now()) {
+                                Ingress::Message(message) => message,
+                                Ingress::Gap(nak) => {
+                                    send_ingress_nak(&mut internal_publisher_socket, nak)?;
+                                    continue;
                                 }
-                                None => {}
-                            },
-                            Err(_) => {}
+                                Ingress::Nothing => continue,
+                            }
+                        }
+                    };
+                    // a gateway missed some of its execution reports
+                    if let Some(nak) = egress::egress_nak(message) {
+                        if let Some(egress) = &egress {
+                            for frame in egress.lock().unwrap().retransmit(&nak) {
+                                internal_publisher_socket.write_all(&frame)?;
+                            }
+                        }
+                        continue;
+                    }
+                    if let Some(registration) = registry::gateway_registration(message) {
+                        if let Some(reply) = gateways.register(&registration, Instant::now()) {
+                            send_registration_reply(&mut internal_publisher_socket, reply)?;
+                        }
+                        continue;
+                    }
+                    if message.len() > 3 {
+                        let msg_result = timeit!(decode, processor::decode_message(message));
+                        // the ones for a book without a market wait for its instrument
+                        let hold = match (&msg_result, pending.as_mut()) {
+                            (Ok((msg, book_id)), Some(pending))
+                                if !shard::acts_on_all_books(msg)
+                                    && partition.contains(*book_id) =>
+                            {
+                                pending.hold(*book_id, message, Instant::now())
+                            }
+                            _ => Hold::Process,
+                        };
+                        // before acting on it, for a replay to get to the same
+                        // place, the held ones once let go
+                        if let (Ok(_), Some(journal), Hold::Process) = (&msg_result, &journal, hold)
+                        {
+                            journal
+                                .lock()
+                                .unwrap()
+                                .append(JournalEntry::Inbound(message.to_vec()))?;
+                        }
+                        match (msg_result, hold) {
+                            (Ok((_, book_id)), Hold::Request) => {
+                                info!(book_id, "Asking the clearing for an unknown book");
+                                if let Err(e) = clearing_connection.request_instrument(book_id) {
+                                    error!(book_id, error = %e, "Error requesting the instrument");
+                                }
+                            }
+                            (Ok(_), Hold::Held) => {}
+                            (Ok((msg, book_id)), Hold::Reject) => {
+                                warn!(book_id, "Too many messages held for the book, rejecting");
+                                metrics.unknown_book.inc();
+                                reject_order(
+                                    &mut publisher,
+                                    &msg,
+                                    RejectReason::UnknownBook,
+                                    &partition,
+                                )?;
+                            }
+                            // sent to the wrong engine
+                            (Ok((msg, book_id)), Hold::Process)
+                                if !shard::acts_on_all_books(&msg)
+                                    && !partition.contains(book_id) =>
+                            {
+                                reject_order(
+                                    &mut publisher,
+                                    &msg,
+                                    RejectReason::OutsidePartition,
+                                    &partition,
+                                )?;
+                            }
+                            (Ok((msg, book_id)), Hold::Process) => {
+                                dispatch_order(&mut markets, &mut publisher, msg, book_id)?
+                            }
+                            (Err(e), _) => {
+                                // nothing to reply to, without the message
+                                warn!(len = message.len(), error = %e, "Dropping a message that doesn't decode");
+                                metrics.undecodable.inc();
+                            }
                         }
                     };
                 }
@@ -189,6 +809,12 @@ fn main() -> Result<(), Box<dyn Error>> {
                     let r = clearing_connection
                         .read(&mut read_buffer)
                         .unwrap_or_default();
+                    if r == 0 {
+                        // closed by the clearing, reconnecting below
+                        clearing_liveness.on_closed();
+                        continue;
+                    }
+                    clearing_liveness.on_received(Instant::now());
                     clearing_buffer.append(&mut read_buffer[0..r].to_vec());
                     match timeit!(
                         clearing_process,
@@ -197,27 +823,235 @@ fn main() -> Result<(), Box<dyn Error>> {
                         Ok(bytes) => {
                             assert!(bytes <= clearing_buffer.len());
                             clearing_buffer.drain(0..bytes);
+                            for update in clearing_connection.take_market_updates() {
+                                if let Some(journal) = &journal {
+                                    journal
+                                        .lock()
+                                        .unwrap()
+                                        .append(JournalEntry::Market(update.clone()))?;
+                                }
+                                let released = pending
+                                    .as_mut()
+                                    .map(|pending| pending.market_updated(&update))
+                                    .unwrap_or_default();
+                                match &mut markets {
+                                    // an instrument update might have ended an auction
+                                    Markets::Single(shard) => {
+                                        publisher.publish(&shard.update_market(update))?
+                                    }
+                                    Markets::Sharded { dispatcher, .. } => dispatcher
+                                        .dispatch_market_update(update)
+                                        .map_err(|_| "A shard stopped")?,
+                                }
+                                // the messages that waited for the instrument, in order,
+                                // rejected by the shard if the clearing didn't have it
+                                for message in released {
+                                    let Ok((msg, book_id)) = processor::decode_message(&message)
+                                    else {
+                                        continue;
+                                    };
+                                    if let Some(journal) = &journal {
+                                        journal
+                                            .lock()
+                                            .unwrap()
+                                            .append(JournalEntry::Inbound(message))?;
+                                    }
+                                    dispatch_order(&mut markets, &mut publisher, msg, book_id)?;
+                                }
+                            }
+                            if clearing_connection.take_snapshot_request() {
+                                match snapshot_path {
+                                    Some(_) => snapshot_requested = true,
+                                    None => warn!(
+                                        "Ignoring the snapshot request of the clearing, no snapshot configured"
+                                    ),
+                                }
+                            }
                         }
                         Err(e) => {
-                            eprintln!("Clearing message decoding error {}", e);
+                            error!(error = %e, "Clearing message decoding error");
                         }
                     }
                 }
                 _ => panic!("Got event on unknown socket"),
             }
         }
-        // send snapshots around if needed
-        if last_snapshot_sent.elapsed() > SEND_SNAPSHOTS_EVERY_MS {
-            eprintln!("Sending snapshots for {} markets", markets.borrow().len());
-            timeit!(
-                send_snapshots,
-                markets.borrow().iter().for_each(|(_id, m)| {
-                    if m.publish_snapshot().is_err() {
-                        eprintln!("Error publishing instrument snapshot");
+        // answered by the markets, from the shard of the book
+        for (command, reply) in admin_requests.iter().flat_map(|r| r.try_iter()) {
+            match (command, &markets) {
+                (AdminCommand::Dump(book_id), Markets::Single(shard)) => {
+                    // the admin may have given up waiting
+                    let _ = reply.send(shard.debug_snapshot(book_id));
+                }
+                (AdminCommand::Dump(book_id), Markets::Sharded { dispatcher, .. }) => dispatcher
+                    .debug_snapshot(book_id, reply)
+                    .map_err(|_| "A shard stopped")?,
+            }
+        }
+        // the instruments the clearing didn't send in time
+        if let Some(pending) = pending.as_mut() {
+            for message in pending.expired(Instant::now()) {
+                if let Ok((msg, book_id)) = processor::decode_message(&message) {
+                    warn!(book_id, "No instrument for the book, rejecting");
+                    metrics.unknown_book.inc();
+                    reject_order(&mut publisher, &msg, RejectReason::UnknownBook, &partition)?;
+                }
+            }
+        }
+        // the work of the markets: what the shards send back, or the timers
+        // of the markets run here
+        let (trade_captures, eod_summaries) = match &mut markets {
+            Markets::Single(shard) => {
+                publisher.publish(&shard.run_timers())?;
+                (shard.take_trade_captures(), shard.take_eod_summaries())
+            }
+            Markets::Sharded { events, .. } => {
+                let (mut captures, mut summaries) = (vec![], vec![]);
+                for event in events.try_iter() {
+                    match event {
+                        ShardEvent::TradeCapture(capture) => captures.push(capture),
+                        ShardEvent::EodSummary(summary) => summaries.push(summary),
+                        ShardEvent::State(index, state) => {
+                            let snapshot =
+                                pending_snapshot.as_mut().and_then(|p| p.add(index, state));
+                            if let (Some(snapshot), Some(path)) = (snapshot, &snapshot_path) {
+                                save_snapshot(path, &snapshot);
+                                pending_snapshot = None;
+                            }
+                        }
+                        // only once shut down
+                        ShardEvent::Stopped(_) => {}
                     }
-                })
-            );
-            last_snapshot_sent = Instant::now();
+                }
+                (captures, summaries)
+            }
+        };
+        report_to_clearing(
+            &mut clearing_connection,
+            trade_captures,
+            eod_summaries,
+            &metrics,
+        );
+        // the clearing has to hear from us, and we from it
+        if clearing_liveness.is_silent(Instant::now()) {
+            warn!("Lost the clearing, reconnecting");
+            clearing_buffer.clear();
+            match reconnect_clearing(&mut clearing_connection, &poller) {
+                Ok(()) => {
+                    clearing_socket_fd = clearing_connection.get_socket_key();
+                    info!("Reconnected to the clearing");
+                }
+                Err(e) => error!(error = %e, "Error reconnecting to the clearing"),
+            }
+            // the next attempt waits for another timeout
+            clearing_liveness = Liveness::new(clearing_liveness_config, Instant::now());
+        } else if clearing_liveness.heartbeat_due(Instant::now()) {
+            let heartbeat = clearing_connection
+                .get_protocol()
+                .as_ref()
+                .map(|p| p.prepare_heartbeat());
+            if let Some(heartbeat) = heartbeat {
+                // a dead connection shows up as silence
+                let _ = clearing_connection.write_all(&heartbeat);
+            }
+        }
+        // and again, if the clearing didn't ack them in time
+        if last_capture_check.elapsed() > RESEND_CAPTURES_EVERY_MS {
+            let before = SystemTime::now()
+                .checked_sub(RESEND_CAPTURES_EVERY_MS)
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default()
+                .as_nanos() as u64;
+            match clearing_connection.resend_trade_captures(before) {
+                Ok(0) => {}
+                Ok(resent) => {
+                    warn!(
+                        resent,
+                        "Resent the trade captures not acked by the clearing"
+                    )
+                }
+                Err(e) => error!(error = %e, "Error resending the trade captures"),
+            }
+            last_capture_check = Instant::now();
+        }
+        if reloading.swap(false, Ordering::SeqCst) {
+            snapshot_every = reload_settings(&mut markets).unwrap_or(snapshot_every);
+        }
+        // the state of the markets, for a warm restart
+        if let (Some(path), Some(journal)) = (&snapshot_path, &journal) {
+            if pending_snapshot.is_none()
+                && (snapshot_requested || last_state_saved.elapsed() > snapshot_every)
+            {
+                let journal_records = journal.lock().unwrap().records();
+                match &mut markets {
+                    Markets::Single(shard) => save_snapshot(
+                        path,
+                        &EngineSnapshot {
+                            journal_records,
+                            shards: vec![shard.save_state()],
+                        },
+                    ),
+                    // each shard once done with what came before
+                    Markets::Sharded { dispatcher, .. } => {
+                        dispatcher.save_states().map_err(|_| "A shard stopped")?;
+                        pending_snapshot = Some(PendingSnapshot::new(journal_records, shards));
+                    }
+                }
+                last_state_saved = Instant::now();
+                snapshot_requested = false;
+            }
+        }
+        // the backups, getting the journal
+        if let Some(journal) = &journal {
+            if let Err(e) = journal.lock().unwrap().poll_replication() {
+                error!(error = %e, "Error replicating the journal");
+            }
+        }
+        // heartbeat for the gateways
+        if last_engine_status_sent.elapsed() > SEND_ENGINE_STATUS_EVERY_MS {
+            send_engine_status(&mut internal_publisher_socket, EngineState::Ready)?;
+            gateways.expire(Instant::now());
+            if let Some(egress) = &egress {
+                for heartbeat in egress.lock().unwrap().heartbeats() {
+                    internal_publisher_socket.write_all(&heartbeat)?;
+                }
+            }
+            last_engine_status_sent = Instant::now();
+        }
+    }
+
+    // the gateways hold on to the orders, for a backup to take over
+    info!("Stopping, closing the markets");
+    send_engine_status(&mut internal_publisher_socket, EngineState::Stopping)?;
+    // a restart doesn't bring back the orders cancelled
+    if let Some(journal) = &journal {
+        journal.lock().unwrap().append(JournalEntry::Shutdown)?;
+    }
+    let (trade_captures, eod_summaries) = match &mut markets {
+        Markets::Single(shard) => {
+            publisher.publish(&shard.shut_down())?;
+            (shard.take_trade_captures(), shard.take_eod_summaries())
+        }
+        Markets::Sharded { dispatcher, events } => {
+            dispatcher.shut_down().map_err(|_| "A shard stopped")?;
+            wait_for_shards(events, shards, SHUT_DOWN_TIMEOUT)
+        }
+    };
+    report_to_clearing(
+        &mut clearing_connection,
+        trade_captures,
+        eod_summaries,
+        &metrics,
+    );
+    clearing_connection.flush()?;
+    if let Some(journal) = &journal {
+        let mut journal = journal.lock().unwrap();
+        // the backups get the end of the journal as well
+        if let Err(e) = journal.poll_replication() {
+            error!(error = %e, "Error replicating the journal");
         }
+        journal.sync()?;
     }
+    info!("Stopped");
+    Ok(())
 }
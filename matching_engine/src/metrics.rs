@@ -0,0 +1,53 @@
+//! What the engine counts, for its metrics endpoint
+
+use std::sync::Arc;
+
+use utils::metrics::{Counter, Histogram, Registry, LATENCY_BOUNDS};
+
+/// The handles of the metrics of the engine, shared by all its shards
+#[derive(Debug, Clone)]
+pub struct EngineMetrics {
+    // the order messages processed by the markets, those replayed from the journal included
+    pub orders_processed: Arc<Counter>,
+    // how long the markets took to process them
+    pub match_latency: Arc<Histogram>,
+    // reported to the clearing
+    pub trades: Arc<Counter>,
+    // the messages no market could take: rejected for their unknown book,
+    // or dropped not decoding
+    pub unknown_book: Arc<Counter>,
+    pub undecodable: Arc<Counter>,
+}
+
+impl EngineMetrics {
+    pub fn new(registry: &Registry) -> Self {
+        Self {
+            orders_processed: registry.counter(
+                "engine_orders_processed_total",
+                "Order messages processed by the markets",
+                &[],
+            ),
+            match_latency: registry.histogram(
+                "engine_match_latency_seconds",
+                "Time taken by the markets to process an order message",
+                &[],
+                LATENCY_BOUNDS,
+            ),
+            trades: registry.counter(
+                "engine_trades_total",
+                "Trades reported to the clearing",
+                &[],
+            ),
+            unknown_book: dead_letters(registry, "unknown_book"),
+            undecodable: dead_letters(registry, "undecodable"),
+        }
+    }
+}
+
+fn dead_letters(registry: &Registry, reason: &str) -> Arc<Counter> {
+    registry.counter(
+        "engine_dead_letters_total",
+        "Messages no market could take, by reason",
+        &[("reason", reason)],
+    )
+}
@@ -0,0 +1,183 @@
+//! The order messages for the books the engine has no market for yet
+//!
+//! An instrument created by the clearing only reaches the engine with its
+//! next refresh, and the orders for it might come first. With a wait
+//! configured, the engine asks the clearing for the instrument and holds the
+//! messages of the book in their arrival order until it comes, rejecting them
+//! once the wait is over or as soon as the clearing answers it doesn't have
+//! it. They are journaled when let go, after the instrument, for a replay to
+//! get to the same place.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use clearing_connection::genericclearingprotocol::MarketUpdate;
+
+// held per book at most, the ones coming on top being rejected right away
+pub const MAX_HELD_PER_BOOK: usize = 1000;
+
+/// What to do with an order message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hold {
+    // the book has a market, the message goes on to it
+    Process,
+    // held, the first one of its book: the instrument has to be asked for
+    Request,
+    // held, behind the others of its book
+    Held,
+    // too many held for the book already
+    Reject,
+}
+
+#[derive(Debug)]
+struct HeldBook {
+    // when the first message came
+    since: Instant,
+    messages: Vec<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct PendingOrders {
+    wait: Duration,
+    // the books with a market, as told by the clearing
+    known: HashSet<u64>,
+    held: HashMap<u64, HeldBook>,
+}
+
+impl PendingOrders {
+    /// @wait - how long the messages of a book wait for its instrument
+    pub fn new(wait: Duration) -> Self {
+        Self {
+            wait,
+            known: HashSet::new(),
+            held: HashMap::new(),
+        }
+    }
+
+    /// Keeps track of the books with a market, as told by @update
+    ///
+    /// Returns: the messages held for the book of an instrument or of a
+    /// deletion, oldest first, the latter to be rejected by the shard
+    pub fn market_updated(&mut self, update: &MarketUpdate) -> Vec<Vec<u8>> {
+        match update {
+            MarketUpdate::Instrument(instrument) => {
+                let book_id = instrument.get_id();
+                self.known.insert(book_id);
+                self.held
+                    .remove(&book_id)
+                    .map(|book| book.messages)
+                    .unwrap_or_default()
+            }
+            MarketUpdate::Deleted(book_id) => {
+                self.known.remove(book_id);
+                self.held
+                    .remove(book_id)
+                    .map(|book| book.messages)
+                    .unwrap_or_default()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Decides over @message, for @book_id, received at @now, keeping it if
+    /// it has to wait for its instrument
+    pub fn hold(&mut self, book_id: u64, message: &[u8], now: Instant) -> Hold {
+        if self.known.contains(&book_id) {
+            return Hold::Process;
+        }
+        match self.held.get_mut(&book_id) {
+            None => {
+                self.held.insert(
+                    book_id,
+                    HeldBook {
+                        since: now,
+                        messages: vec![message.to_vec()],
+                    },
+                );
+                Hold::Request
+            }
+            Some(book) if book.messages.len() >= MAX_HELD_PER_BOOK => Hold::Reject,
+            Some(book) => {
+                book.messages.push(message.to_vec());
+                Hold::Held
+            }
+        }
+    }
+
+    /// Gives up on the books whose instrument didn't come within the wait
+    ///
+    /// Returns: their messages, to be rejected, by book and oldest first
+    pub fn expired(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let mut books: Vec<u64> = self
+            .held
+            .iter()
+            .filter(|(_, book)| now.duration_since(book.since) >= self.wait)
+            .map(|(book_id, _)| *book_id)
+            .collect();
+        books.sort_unstable();
+        books
+            .into_iter()
+            .flat_map(|book_id| self.held.remove(&book_id).unwrap().messages)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use clearing_connection::genericclearingprotocol::MarketUpdate;
+    use instruments::instrument::{Instrument, InstrumentType};
+
+    use super::{Hold, PendingOrders, MAX_HELD_PER_BOOK};
+
+    #[test]
+    fn held_until_the_instrument_comes() {
+        let now = Instant::now();
+        let mut target = PendingOrders::new(Duration::from_millis(500));
+        assert_eq!(Hold::Request, target.hold(5, &[1], now));
+        assert_eq!(Hold::Held, target.hold(5, &[2], now));
+        assert_eq!(Hold::Request, target.hold(6, &[3], now));
+
+        let released = target.market_updated(&MarketUpdate::Instrument(Instrument::new_fast(
+            5,
+            InstrumentType::Share,
+        )));
+        assert_eq!(vec![vec![1], vec![2]], released);
+        assert_eq!(Hold::Process, target.hold(5, &[4], now));
+
+        // the other one never came
+        assert!(target.expired(now + Duration::from_millis(499)).is_empty());
+        assert_eq!(
+            vec![vec![3]],
+            target.expired(now + Duration::from_millis(500))
+        );
+        assert!(target.expired(now + Duration::from_secs(1)).is_empty());
+
+        // and a deleted book is unknown again
+        target.market_updated(&MarketUpdate::Deleted(5));
+        assert_eq!(Hold::Request, target.hold(5, &[5], now));
+        // the clearing doesn't have it either
+        assert_eq!(
+            vec![vec![5]],
+            target.market_updated(&MarketUpdate::Deleted(5))
+        );
+    }
+
+    #[test]
+    fn held_at_most() {
+        let now = Instant::now();
+        let mut target = PendingOrders::new(Duration::from_millis(500));
+        assert_eq!(Hold::Request, target.hold(5, &[0], now));
+        for _ in 1..MAX_HELD_PER_BOOK {
+            assert_eq!(Hold::Held, target.hold(5, &[0], now));
+        }
+        assert_eq!(Hold::Reject, target.hold(5, &[0], now));
+        assert_eq!(
+            MAX_HELD_PER_BOOK,
+            target.expired(now + Duration::from_secs(1)).len()
+        );
+    }
+}
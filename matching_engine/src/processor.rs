@@ -1,85 +1,139 @@
-use anyhow::{bail, Result};
-use market::Market;
+use anyhow::{anyhow, bail, Result};
+use market::{ClientOrderIdError, Market, PassiveFill};
 use oep::{
     cancel::{Cancel, CANCEL_SIZE},
     decoder::Decoder,
-    execution_report::ExecutionReport,
+    eodsummary::EodSummary,
+    execution_report::{
+        ExecutionReport, RejectReason, FLAG_AGGRESSOR, FLAG_BAND_REJECT, FLAG_CANCEL_ON_DISCONNECT,
+        FLAG_PARTIAL_FILL,
+    },
+    masscancel::{MassCancel, ANY_BOOK, ANY_SIDE, MASSCANCEL_SIZE},
+    massquote::{MassQuote, MASSQUOTE_SIZE},
     modify::{Modify, MODIFY_SIZE},
     neworder::{NewOrder, NEWORDER_SIZE},
     oep_message::{MsgType, OepMessage},
+    quote::{Quote, QUOTE_SIZE},
+    quotecancelall::{QuoteCancelAll, QUOTECANCELALL_SIZE},
+    replace::{Replace, REPLACE_SIZE},
     sessioninfo::{SessionInfo, SESSIONINFO_SIZE},
 };
-use order::{Order, OrderState};
+use order::{Order, OrderState, OrderType, Side};
 
+#[derive(Clone)]
 pub enum MessageWrapper {
     NewOrder(NewOrder),
     Modify(Modify),
     Cancel(Cancel),
     KillSession(SessionInfo),
+    MassCancel(MassCancel),
+    Replace(Replace),
+    Quote(Quote),
+    // boxed, being that much bigger than the others
+    MassQuote(Box<MassQuote>),
+    QuoteCancelAll(QuoteCancelAll),
+}
+
+impl MessageWrapper {
+    /// The message itself, for its session and participant
+    pub fn as_oep_message(&self) -> &dyn OepMessage {
+        match self {
+            MessageWrapper::NewOrder(m) => m,
+            MessageWrapper::Modify(m) => m,
+            MessageWrapper::Cancel(m) => m,
+            MessageWrapper::KillSession(m) => m,
+            MessageWrapper::MassCancel(m) => m,
+            MessageWrapper::Replace(m) => m,
+            MessageWrapper::Quote(m) => m,
+            MessageWrapper::MassQuote(m) => m.as_ref(),
+            MessageWrapper::QuoteCancelAll(m) => m,
+        }
+    }
 }
 
 static HEADER_SIZE: usize = 4;
 
+/// Decodes the body of @buffer, after the header, as a message M of S bytes
+fn decode_body<M: Decoder<S>, const S: usize>(buffer: &[u8], what: &str) -> Result<M> {
+    let Ok(body) = <[u8; S]>::try_from(&buffer[HEADER_SIZE.min(buffer.len())..]) else {
+        bail!(
+            "Invalid {what} length {}, expected {}",
+            buffer.len(),
+            HEADER_SIZE + S
+        );
+    };
+    M::decode(body).map_err(|e| anyhow!("decoding {what}: {e}"))
+}
+
+/// Decodes a message of a gateway, along with the book it is for, 0 for
+/// the ones acting on all the books
+///
+/// Returns: an error for the messages of an unknown type, or of the wrong length
 #[must_use]
 pub fn decode_message(buffer: &[u8]) -> Result<(MessageWrapper, u64)> {
-    match (buffer[0] as u16).into() {
+    let Some(msg_type) = buffer.first() else {
+        bail!("Empty message");
+    };
+    match (*msg_type as u16).into() {
         MsgType::NewOrder => {
-            assert_eq!(HEADER_SIZE + NEWORDER_SIZE, buffer.len());
-            let o = NewOrder::decode(
-                buffer[HEADER_SIZE..HEADER_SIZE + NEWORDER_SIZE]
-                    .try_into()
-                    .expect("new order buffer try_into failed"),
-            )
-            .expect("decoding new order");
+            let o: NewOrder = decode_body::<_, NEWORDER_SIZE>(buffer, "new order")?;
             let instrument = o.book_id;
             Ok((MessageWrapper::NewOrder(o), instrument))
         }
         MsgType::Modify => {
-            assert_eq!(HEADER_SIZE + MODIFY_SIZE, buffer.len());
-            let o = Modify::decode(
-                buffer[HEADER_SIZE..HEADER_SIZE + MODIFY_SIZE]
-                    .try_into()
-                    .expect("modify buffer try_into failed"),
-            )
-            .expect("decoding modify");
+            let o: Modify = decode_body::<_, MODIFY_SIZE>(buffer, "modify")?;
             let instrument = o.book_id;
             Ok((MessageWrapper::Modify(o), instrument))
         }
         MsgType::Cancel => {
-            assert_eq!(HEADER_SIZE + CANCEL_SIZE, buffer.len());
-            let o = Cancel::decode(
-                buffer[HEADER_SIZE..HEADER_SIZE + CANCEL_SIZE]
-                    .try_into()
-                    .expect("cancel buffer try_into failed"),
-            )
-            .expect("decoding cancel");
+            let o: Cancel = decode_body::<_, CANCEL_SIZE>(buffer, "cancel")?;
             let instrument = o.book_id;
             Ok((MessageWrapper::Cancel(o), instrument))
         }
         MsgType::SessionNotification => {
-            assert_eq!(HEADER_SIZE + SESSIONINFO_SIZE, buffer.len());
-            let o = SessionInfo::decode(
-                buffer[HEADER_SIZE..HEADER_SIZE + SESSIONINFO_SIZE]
-                    .try_into()
-                    .expect("session info buffer try_into failed"),
-            )
-            .expect("decoding kill session");
+            let o = decode_body::<_, SESSIONINFO_SIZE>(buffer, "kill session")?;
             Ok((MessageWrapper::KillSession(o), 0))
         }
-        _ => bail!("Invalid message type: {:?}", buffer[0] as u16),
+        MsgType::Replace => {
+            let o: Replace = decode_body::<_, REPLACE_SIZE>(buffer, "replace")?;
+            let instrument = o.book_id;
+            Ok((MessageWrapper::Replace(o), instrument))
+        }
+        MsgType::MassCancel => {
+            let o: MassCancel = decode_body::<_, MASSCANCEL_SIZE>(buffer, "mass cancel")?;
+            let instrument = o.book_id;
+            Ok((MessageWrapper::MassCancel(o), instrument))
+        }
+        MsgType::Quote => {
+            let o: Quote = decode_body::<_, QUOTE_SIZE>(buffer, "quote")?;
+            let instrument = o.book_id;
+            Ok((MessageWrapper::Quote(o), instrument))
+        }
+        MsgType::MassQuote => {
+            let o = decode_body::<_, MASSQUOTE_SIZE>(buffer, "mass quote")?;
+            Ok((MessageWrapper::MassQuote(Box::new(o)), 0))
+        }
+        MsgType::QuoteCancelAll => {
+            let o: QuoteCancelAll =
+                decode_body::<_, QUOTECANCELALL_SIZE>(buffer, "quote cancel all")?;
+            let instrument = o.book_id;
+            Ok((MessageWrapper::QuoteCancelAll(o), instrument))
+        }
+        _ => bail!("Invalid message type: {:?}", *msg_type as u16),
     }
 }
 
 #[must_use]
-/// process a message in the supplied market and returns an execution report
+/// process a message in the supplied market and returns an execution report,
+/// followed by one for each resting order it traded against
 ///
 /// # Example
 ///
 /// ```
-/// use std::{cell::RefCell, rc::Rc};
+/// use std::sync::{Arc, Mutex, RwLock};
 /// use disseminator::mockdisseminator::MockDisseminator;
 /// use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
-/// use market::Market;
+/// use market::{orderid::OrderIdGenerator, Market};
 /// use oep::{
 ///     execution_report::ExecutionReport, neworder::NewOrder,
 ///     oep_message::OepMessage,
@@ -98,8 +152,9 @@ pub fn decode_message(buffer: &[u8]) -> Result<(MessageWrapper, u64)> {
 ///         20,
 ///     );
 /// let mut market = Market::new(
-///         Rc::new(RefCell::new(instrument)),
-///         Rc::new(RefCell::new(MockDisseminator::new())),
+///         Arc::new(RwLock::new(instrument)),
+///         Arc::new(Mutex::new(MockDisseminator::new())),
+///         Arc::new(Mutex::new(OrderIdGenerator::new(0))),
 ///     );
 /// let new_order = MessageWrapper::NewOrder(NewOrder {
 ///         client_order_id: 7000,
@@ -111,16 +166,215 @@ pub fn decode_message(buffer: &[u8]) -> Result<(MessageWrapper, u64)> {
 ///         side: 1,
 ///         gateway_id: 15,
 ///         session_id: 2,
+///         expiry: 0,
+///         stop_price: 0,
+///         display_quantity: 0,
 ///     });
 /// let execution_reports = process_message(&mut market, new_order);
 /// assert_eq!(1, execution_reports.len());
-/// assert_eq!(execution_reports[0].state, OrderState::Inserted.into());
+/// assert_eq!(execution_reports[0].state, Into::<u8>::into(OrderState::Inserted));
 /// ```
 ///
-pub fn process_message(market: &mut Market, msg: MessageWrapper) -> Vec<ExecutionReport> {
+pub fn process_message(market: &mut Market, mut msg: MessageWrapper) -> Vec<ExecutionReport> {
+    if resolve_client_order_id(market, &mut msg) == Err(ClientOrderIdError::Ambiguous) {
+        return reject_message(&msg, RejectReason::AmbiguousClientOrderId);
+    }
+    if adds_blocked_exposure(market, &msg) {
+        return reject_message(&msg, RejectReason::ExposureLimit);
+    }
+    let mut ereports = process_order_message(market, msg);
+    let fills = market.take_passive_fills();
+    let band_rejected = market.take_band_reject();
+    for ereport in ereports.iter_mut() {
+        if OrderState::from(ereport.state) == OrderState::Rejected {
+            if ereport.reject_reason == 0 {
+                ereport.reject_reason = RejectReason::Unspecified.into();
+            }
+            if band_rejected {
+                ereport.flags |= FLAG_BAND_REJECT;
+            }
+        }
+        let order_id = ereport.order_id;
+        let traded = fills.iter().filter(|fill| fill.aggressor_id == order_id);
+        // its trades follow the first one, one per resting order it hit
+        ereport.trade_id = traded.clone().next().map_or(0, |fill| fill.trade_id);
+        ereport.filled_quantity =
+            traded.fold(0u64, |filled, fill| filled.saturating_add(fill.quantity));
+        if ereport.filled_quantity > 0 {
+            ereport.flags |= FLAG_AGGRESSOR;
+            if ereport.filled_quantity < ereport.quantity {
+                ereport.flags |= FLAG_PARTIAL_FILL;
+            }
+        }
+    }
+    ereports.append(&mut fill_reports(&fills));
+    ereports
+}
+
+/// Fills in the order_id of a modify or a cancel naming its order by the
+/// orig_client_order_id only, with the exchange id of the order of the
+/// participant carrying it. Left 0 when there is none, for the message to be
+/// rejected as for any unknown order
+fn resolve_client_order_id(
+    market: &Market,
+    msg: &mut MessageWrapper,
+) -> Result<(), ClientOrderIdError> {
+    macro_rules! resolve {
+        ($m: expr) => {
+            if $m.order_id == 0 && $m.orig_client_order_id != 0 {
+                $m.order_id = market.resolve_client_order_id(
+                    $m.participant,
+                    $m.side.into(),
+                    $m.orig_client_order_id,
+                )?;
+            }
+        };
+    }
+    match msg {
+        MessageWrapper::Modify(m) => resolve!(m),
+        MessageWrapper::Cancel(m) => resolve!(m),
+        _ => (),
+    }
+    Ok(())
+}
+
+/// The id a modify or a cancel is reported with: the exchange id of its order,
+/// or the client order id it named the order by when that is not resolved
+fn submitted_id(order_id: u64, orig_client_order_id: u64) -> u64 {
+    match order_id {
+        0 => orig_client_order_id,
+        _ => order_id,
+    }
+}
+
+#[must_use]
+/// rejects @msg without processing it, e.g. when its book is handled by another
+/// partition. Nothing is sent back for the session kills, the mass cancels,
+/// the mass quotes and the quote cancels
+pub fn reject_message(msg: &MessageWrapper, reason: RejectReason) -> Vec<ExecutionReport> {
+    let rejected = |participant, order_id, book, side, gateway_id, session_id| ExecutionReport {
+        participant,
+        order_id,
+        submitted_order_id: order_id,
+        book,
+        quantity: 0,
+        price: 0,
+        flags: 0,
+        side,
+        state: OrderState::Rejected.into(),
+        gateway_id,
+        session_id,
+        filled_quantity: 0,
+        leaves_quantity: 0,
+        orig_order_id: 0,
+        partition_id: 0,
+        reject_reason: reason.into(),
+        trade_id: 0,
+    };
+    match msg {
+        MessageWrapper::NewOrder(m) => vec![ExecutionReport {
+            quantity: m.quantity,
+            price: m.price,
+            ..rejected(
+                m.participant,
+                m.client_order_id,
+                m.book_id,
+                m.side,
+                m.gateway_id,
+                m.session_id,
+            )
+        }],
+        MessageWrapper::Modify(m) => vec![ExecutionReport {
+            submitted_order_id: submitted_id(m.order_id, m.orig_client_order_id),
+            quantity: m.quantity,
+            price: m.price,
+            ..rejected(
+                m.participant,
+                m.order_id,
+                m.book_id,
+                m.side,
+                m.gateway_id,
+                m.session_id,
+            )
+        }],
+        MessageWrapper::Cancel(m) => vec![ExecutionReport {
+            submitted_order_id: submitted_id(m.order_id, m.orig_client_order_id),
+            ..rejected(
+                m.participant,
+                m.order_id,
+                m.book_id,
+                m.side,
+                m.gateway_id,
+                m.session_id,
+            )
+        }],
+        MessageWrapper::Replace(m) => vec![ExecutionReport {
+            quantity: m.quantity,
+            price: m.price,
+            orig_order_id: m.orig_order_id,
+            ..rejected(
+                m.participant,
+                m.client_order_id,
+                m.book_id,
+                m.side,
+                m.gateway_id,
+                m.session_id,
+            )
+        }],
+        MessageWrapper::Quote(m) => vec![rejected(
+            m.participant,
+            m.quote_id,
+            m.book_id,
+            Side::Bid.into(),
+            m.gateway_id,
+            m.session_id,
+        )],
+        MessageWrapper::KillSession(_)
+        | MessageWrapper::MassCancel(_)
+        | MessageWrapper::MassQuote(_)
+        | MessageWrapper::QuoteCancelAll(_) => vec![],
+    }
+}
+
+/// whether @msg would add risk on the side of the book its participant is
+/// blocked on by the clearing: a new order, or a bigger modify, replace or
+/// side of a quote
+fn adds_blocked_exposure(market: &Market, msg: &MessageWrapper) -> bool {
+    let (participant, side, quantity, resting_id) = match msg {
+        MessageWrapper::Quote(m) => {
+            let (bid_id, ask_id) = market.get_quote(m.participant);
+            return [
+                (Side::Bid, m.bid_quantity, bid_id),
+                (Side::Ask, m.ask_quantity, ask_id),
+            ]
+            .into_iter()
+            .any(|(side, quantity, id)| {
+                market.is_exposure_blocked(m.participant, side)
+                    && quantity > leaves_quantity(market, id)
+            });
+        }
+        MessageWrapper::NewOrder(m) => (m.participant, m.side, m.quantity, None),
+        MessageWrapper::Modify(m) => (m.participant, m.side, m.quantity, Some(m.order_id)),
+        MessageWrapper::Replace(m) => (m.participant, m.side, m.quantity, Some(m.orig_order_id)),
+        _ => return false,
+    };
+    market.is_exposure_blocked(participant, side.into())
+        && resting_id.is_none_or(|id| quantity > leaves_quantity(market, id))
+}
+
+/// what is left of the order @id in the book, hidden quantity included
+fn leaves_quantity(market: &Market, id: u64) -> u64 {
+    market
+        .get_order(id)
+        .map_or(0, |resting| resting.quantity + resting.hidden_quantity)
+}
+
+fn process_order_message(market: &mut Market, msg: MessageWrapper) -> Vec<ExecutionReport> {
     match msg {
         MessageWrapper::NewOrder(m) => {
-            if market.get_instrument().borrow().get_id() != m.book_id || m.get_participant() == 0 {
+            if market.get_instrument().read().unwrap().get_id() != m.book_id
+                || m.get_participant() == 0
+            {
                 return vec![ExecutionReport {
                     participant: m.participant,
                     order_id: m.client_order_id,
@@ -133,41 +387,60 @@ pub fn process_message(market: &mut Market, msg: MessageWrapper) -> Vec<Executio
                     state: OrderState::Rejected.into(),
                     gateway_id: m.gateway_id,
                     session_id: m.session_id,
+                    filled_quantity: 0,
+                    leaves_quantity: 0,
+                    orig_order_id: 0,
+                    partition_id: 0,
+                    reject_reason: 0,
+                    trade_id: 0,
                 }];
             }
 
-            let o = Order::new(
+            let mut o = Order::new(
                 m.get_participant(),
                 market.get_instrument().clone(),
                 m.price,
                 m.quantity,
                 m.side.into(),
                 m.order_type.into(),
-                m.get_gateway_id(),
-                m.get_session_id(),
-            );
-            let (state, id) = market.add_order(o);
+            )
+            .with_session(m.get_gateway_id(), m.get_session_id());
+            o.expiry = m.expiry;
+            o.stop_price = m.stop_price;
+            o.display_quantity = m.display_quantity;
+            o.client_order_id = m.client_order_id;
+            let result = market.enter_order(o);
             // publish back the execution report
             vec![ExecutionReport {
                 participant: m.participant,
-                order_id: id,
+                order_id: result.order_id,
                 submitted_order_id: m.client_order_id,
                 book: m.book_id,
                 quantity: m.quantity,
                 price: m.price,
                 flags: 0,
                 side: m.side,
-                state: state.into(),
+                state: result.state.into(),
                 gateway_id: m.gateway_id,
                 session_id: m.session_id,
+                filled_quantity: 0, // accounted for by process_message, from the fills
+                leaves_quantity: result
+                    .resting
+                    .map_or(0, |resting| resting.quantity + resting.hidden_quantity),
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: 0,
+                trade_id: 0,
             }]
         }
         MessageWrapper::Modify(m) => {
-            if market.get_instrument().borrow().get_id() != m.book_id || m.get_participant() == 0 {
+            if market.get_instrument().read().unwrap().get_id() != m.book_id
+                || m.get_participant() == 0
+            {
                 return vec![ExecutionReport {
                     participant: m.participant,
                     order_id: m.order_id,
-                    submitted_order_id: m.order_id,
+                    submitted_order_id: submitted_id(m.order_id, m.orig_client_order_id),
                     book: m.book_id,
                     quantity: m.quantity,
                     price: m.price,
@@ -176,6 +449,12 @@ pub fn process_message(market: &mut Market, msg: MessageWrapper) -> Vec<Executio
                     state: OrderState::Rejected.into(),
                     gateway_id: m.gateway_id,
                     session_id: m.session_id,
+                    filled_quantity: 0,
+                    leaves_quantity: 0,
+                    orig_order_id: 0,
+                    partition_id: 0,
+                    reject_reason: 0,
+                    trade_id: 0,
                 }];
             }
 
@@ -185,32 +464,42 @@ pub fn process_message(market: &mut Market, msg: MessageWrapper) -> Vec<Executio
                 m.price,
                 m.quantity,
                 m.get_side().into(),
-                order::OrderType::Day, // FIXME:
-                m.get_gateway_id(),
-                m.get_session_id(),
-            );
+                // the order type can't be modified, keep the one of the resting order
+                market
+                    .get_order(m.order_id)
+                    .map_or(order::OrderType::Day, |resting| resting.order_type),
+            )
+            .with_session(m.get_gateway_id(), m.get_session_id());
             o.set_id(m.order_id);
             let (state, id) = market.modify_order(o);
             vec![ExecutionReport {
                 participant: m.participant,
                 order_id: id,
-                submitted_order_id: m.order_id,
+                submitted_order_id: submitted_id(m.order_id, m.orig_client_order_id),
                 book: m.book_id,
                 quantity: m.quantity,
                 price: m.price,
-                flags: 0, // FIXME:
+                flags: 0,
                 side: m.get_side().into(),
                 state: state.into(),
                 gateway_id: m.gateway_id,
                 session_id: m.session_id,
+                filled_quantity: 0, // accounted for by process_message, from the fills
+                leaves_quantity: leaves_quantity(market, id),
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: 0,
+                trade_id: 0,
             }]
         }
         MessageWrapper::Cancel(m) => {
-            if market.get_instrument().borrow().get_id() != m.book_id || m.get_participant() == 0 {
+            if market.get_instrument().read().unwrap().get_id() != m.book_id
+                || m.get_participant() == 0
+            {
                 return vec![ExecutionReport {
                     participant: m.participant,
                     order_id: m.order_id,
-                    submitted_order_id: m.order_id,
+                    submitted_order_id: submitted_id(m.order_id, m.orig_client_order_id),
                     book: m.book_id,
                     quantity: 0,
                     price: 0,
@@ -219,6 +508,12 @@ pub fn process_message(market: &mut Market, msg: MessageWrapper) -> Vec<Executio
                     state: OrderState::Rejected.into(),
                     gateway_id: m.gateway_id,
                     session_id: m.session_id,
+                    filled_quantity: 0,
+                    leaves_quantity: 0,
+                    orig_order_id: 0,
+                    partition_id: 0,
+                    reject_reason: 0,
+                    trade_id: 0,
                 }];
             }
 
@@ -229,23 +524,176 @@ pub fn process_message(market: &mut Market, msg: MessageWrapper) -> Vec<Executio
                 0,
                 m.get_side().into(),
                 order::OrderType::Day, // FIXME:
-                m.get_gateway_id(),
-                m.get_session_id(),
-            );
+            )
+            .with_session(m.get_gateway_id(), m.get_session_id());
             o.set_id(m.order_id);
+            // report what was left of the order, once cancelled
+            let (quantity, price) = market.get_order(m.order_id).map_or((0, 0), |resting| {
+                (resting.quantity + resting.hidden_quantity, resting.price)
+            });
             let state = market.cancel_order(&o);
+            let (quantity, price) = match state {
+                OrderState::Cancelled => (quantity, price),
+                _ => (0, 0),
+            };
             vec![ExecutionReport {
                 participant: m.participant,
                 order_id: m.order_id,
-                submitted_order_id: m.order_id,
+                submitted_order_id: submitted_id(m.order_id, m.orig_client_order_id),
                 book: m.book_id,
-                quantity: 0,
-                price: 0,
+                quantity,
+                price,
                 flags: 0,
                 side: m.get_side().into(),
                 state: state.into(),
                 gateway_id: m.gateway_id,
                 session_id: m.session_id,
+                filled_quantity: 0,
+                leaves_quantity: 0,
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: 0,
+                trade_id: 0,
+            }]
+        }
+        MessageWrapper::Replace(m) => {
+            let rejected = ExecutionReport {
+                participant: m.participant,
+                order_id: m.client_order_id,
+                submitted_order_id: m.client_order_id,
+                book: m.book_id,
+                quantity: m.quantity,
+                price: m.price,
+                flags: 0,
+                side: m.side,
+                state: OrderState::Rejected.into(),
+                gateway_id: m.gateway_id,
+                session_id: m.session_id,
+                filled_quantity: 0,
+                leaves_quantity: 0,
+                orig_order_id: m.orig_order_id,
+                partition_id: 0,
+                reject_reason: 0,
+                trade_id: 0,
+            };
+            if market.get_instrument().read().unwrap().get_id() != m.book_id
+                || m.get_participant() == 0
+            {
+                return vec![rejected];
+            }
+
+            let mut old = Order::new(
+                m.participant,
+                market.get_instrument().clone(),
+                0,
+                0,
+                m.side.into(),
+                order::OrderType::Day,
+            )
+            .with_session(m.get_gateway_id(), m.get_session_id());
+            old.set_id(m.orig_order_id);
+            let mut o = Order::new(
+                m.get_participant(),
+                market.get_instrument().clone(),
+                m.price,
+                m.quantity,
+                m.side.into(),
+                m.order_type.into(),
+            )
+            .with_session(m.get_gateway_id(), m.get_session_id());
+            o.expiry = m.expiry;
+            o.stop_price = m.stop_price;
+            o.display_quantity = m.display_quantity;
+            o.client_order_id = m.client_order_id;
+            // report what was left of the replaced order
+            let (quantity, price) = market.get_order(m.orig_order_id).map_or((0, 0), |resting| {
+                (resting.quantity + resting.hidden_quantity, resting.price)
+            });
+            let (cancelled, state, id) = market.replace_order(&old, o);
+            if cancelled != OrderState::Cancelled {
+                return vec![rejected];
+            }
+            vec![
+                ExecutionReport {
+                    participant: m.participant,
+                    order_id: m.orig_order_id,
+                    submitted_order_id: m.orig_order_id,
+                    book: m.book_id,
+                    quantity,
+                    price,
+                    flags: 0,
+                    side: m.side,
+                    state: cancelled.into(),
+                    gateway_id: m.gateway_id,
+                    session_id: m.session_id,
+                    filled_quantity: 0,
+                    leaves_quantity: 0,
+                    orig_order_id: m.orig_order_id,
+                    partition_id: 0,
+                    reject_reason: 0,
+                    trade_id: 0,
+                },
+                ExecutionReport {
+                    order_id: id,
+                    state: state.into(),
+                    filled_quantity: 0, // accounted for by process_message, from the fills
+                    leaves_quantity: leaves_quantity(market, id),
+                    ..rejected
+                },
+            ]
+        }
+        MessageWrapper::Quote(m) => {
+            let rejected = ExecutionReport {
+                participant: m.participant,
+                order_id: 0,
+                submitted_order_id: m.quote_id,
+                book: m.book_id,
+                quantity: m.bid_quantity,
+                price: m.bid_price,
+                flags: 0,
+                side: Side::Bid.into(),
+                state: OrderState::Rejected.into(),
+                gateway_id: m.gateway_id,
+                session_id: m.session_id,
+                filled_quantity: 0,
+                leaves_quantity: 0,
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: 0,
+                trade_id: 0,
+            };
+            if market.get_instrument().read().unwrap().get_id() != m.book_id
+                || m.get_participant() == 0
+            {
+                return vec![rejected];
+            }
+
+            let side = |price, quantity, side| {
+                (quantity > 0).then(|| {
+                    Order::new(
+                        m.participant,
+                        market.get_instrument().clone(),
+                        price,
+                        quantity,
+                        side,
+                        OrderType::PostOrKill,
+                    )
+                    .with_session(m.gateway_id, m.session_id)
+                })
+            };
+            let bid = side(m.bid_price, m.bid_quantity, Side::Bid);
+            let ask = side(m.ask_price, m.ask_quantity, Side::Ask);
+            let (state, bid_id, ask_id) = market.quote(m.participant, bid, ask);
+            if state != OrderState::QuoteAck {
+                return vec![rejected];
+            }
+            // a single report for both sides, the ask carried by orig_order_id
+            vec![ExecutionReport {
+                order_id: bid_id,
+                state: state.into(),
+                leaves_quantity: leaves_quantity(market, bid_id),
+                orig_order_id: ask_id,
+                ..rejected
             }]
         }
         MessageWrapper::KillSession(m) => {
@@ -254,41 +702,296 @@ pub fn process_message(market: &mut Market, msg: MessageWrapper) -> Vec<Executio
                 m.get_gateway_id(),
                 m.get_session_id(),
             );
-            let mut r = Vec::with_capacity(v.len());
-            for i in v {
-                r.push(ExecutionReport {
-                    participant: m.get_participant(),
-                    order_id: i.0,
-                    submitted_order_id: i.0,
-                    book: i.1,
-                    quantity: 0,
-                    price: 0,
-                    flags: 0,
-                    side: i.2.into(),
-                    state: OrderState::Cancelled.into(),
-                    session_id: m.get_session_id(),
-                    gateway_id: m.get_gateway_id(),
-                });
+            cancelled_reports(&m, v, FLAG_CANCEL_ON_DISCONNECT)
+        }
+        MessageWrapper::MassCancel(m) => {
+            let book_id = m.get_book_id();
+            if m.get_participant() == 0
+                || (book_id != ANY_BOOK
+                    && book_id != market.get_instrument().read().unwrap().get_id())
+            {
+                return vec![];
             }
-            r
+            let side = match m.get_side() {
+                ANY_SIDE => None,
+                side => Some(side.into()),
+            };
+            let v = market.cancel_all_orders_for_participant(m.get_participant(), side);
+            cancelled_reports(&m, v, 0)
+        }
+        MessageWrapper::MassQuote(m) => process_mass_quote(std::iter::once(market), &m),
+        MessageWrapper::QuoteCancelAll(m) => {
+            let book_id = m.get_book_id();
+            if m.get_participant() == 0
+                || (book_id != ANY_BOOK
+                    && book_id != market.get_instrument().read().unwrap().get_id())
+            {
+                return vec![];
+            }
+            let v = market.cancel_quote(m.get_participant());
+            cancelled_reports(&m, v, 0)
+        }
+    }
+}
+
+/// the execution reports for the orders cancelled on behalf of @requester,
+/// sent to the session of the latter, carrying @flags
+fn cancelled_reports(
+    requester: &dyn OepMessage,
+    cancelled: Vec<(u64, u64, Side)>,
+    flags: u16,
+) -> Vec<ExecutionReport> {
+    cancelled
+        .into_iter()
+        .map(|(order_id, book, side)| ExecutionReport {
+            participant: requester.get_participant(),
+            order_id,
+            submitted_order_id: order_id,
+            book,
+            quantity: 0,
+            price: 0,
+            flags,
+            side: side.into(),
+            state: OrderState::Cancelled.into(),
+            session_id: requester.get_session_id(),
+            gateway_id: requester.get_gateway_id(),
+            filled_quantity: 0,
+            leaves_quantity: 0,
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
+        })
+        .collect()
+}
+
+#[must_use]
+/// cancels the orders of a session in all the @markets, e.g. once it disconnected,
+/// and returns an execution report for each of them
+pub fn process_session_kill<'a>(
+    markets: impl IntoIterator<Item = &'a mut Market>,
+    session: SessionInfo,
+) -> Vec<ExecutionReport> {
+    markets
+        .into_iter()
+        .flat_map(|market| process_message(market, MessageWrapper::KillSession(session)))
+        .collect()
+}
+
+#[must_use]
+/// applies a mass cancel that isn't limited to a book to all the @markets
+pub fn process_mass_cancel<'a>(
+    markets: impl IntoIterator<Item = &'a mut Market>,
+    mass_cancel: MassCancel,
+) -> Vec<ExecutionReport> {
+    markets
+        .into_iter()
+        .flat_map(|market| process_message(market, MessageWrapper::MassCancel(mass_cancel)))
+        .collect()
+}
+
+#[must_use]
+/// applies a mass quote to the books of the @markets it has an entry for, each
+/// of them on its own, and returns a report per entry
+pub fn process_mass_quote<'a>(
+    markets: impl IntoIterator<Item = &'a mut Market>,
+    mass_quote: &MassQuote,
+) -> Vec<ExecutionReport> {
+    let mut ereports = vec![];
+    for market in markets {
+        let book_id = market.get_instrument().read().unwrap().get_id();
+        for quote in mass_quote.quotes().filter(|q| q.book_id == book_id) {
+            ereports.append(&mut process_message(market, MessageWrapper::Quote(quote)));
         }
     }
+    ereports
+}
+
+#[must_use]
+/// pulls the quotes of a participant in all the @markets
+pub fn process_quote_cancel_all<'a>(
+    markets: impl IntoIterator<Item = &'a mut Market>,
+    quote_cancel: QuoteCancelAll,
+) -> Vec<ExecutionReport> {
+    markets
+        .into_iter()
+        .flat_map(|market| process_message(market, MessageWrapper::QuoteCancelAll(quote_cancel)))
+        .collect()
+}
+
+#[must_use]
+/// execution reports for the resting orders of the market traded since the last call,
+/// carrying the trade price and the quantity left
+pub fn passive_fill_reports(market: &mut Market) -> Vec<ExecutionReport> {
+    fill_reports(&market.take_passive_fills())
+}
+
+fn fill_reports(fills: &[PassiveFill]) -> Vec<ExecutionReport> {
+    fills
+        .iter()
+        .map(|fill| {
+            let o = &fill.order;
+            let left = o.quantity + o.hidden_quantity;
+            ExecutionReport {
+                participant: o.participant,
+                order_id: o.get_id(),
+                submitted_order_id: o.get_id(),
+                book: o.instrument.read().unwrap().get_id(),
+                quantity: left,
+                price: fill.price,
+                flags: match left {
+                    0 => 0,
+                    _ => FLAG_PARTIAL_FILL,
+                },
+                side: o.side.into(),
+                state: match left {
+                    0 => OrderState::Traded.into(),
+                    _ => OrderState::PartiallyTraded.into(),
+                },
+                gateway_id: o.gateway_id,
+                session_id: o.session_id,
+                filled_quantity: fill.quantity,
+                leaves_quantity: left,
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: 0,
+                trade_id: fill.trade_id,
+            }
+        })
+        .collect()
+}
+
+#[must_use]
+/// cancels the GoodTillDate orders of the market that expired at or before @now
+/// (unix timestamp, seconds) and returns an execution report for each of them
+pub fn expire_orders(market: &mut Market, now: u64) -> Vec<ExecutionReport> {
+    order_cancelled_reports(&market.expire_orders(now))
+}
+
+#[must_use]
+/// deletes the @market, once its instrument is gone, returning its end of day
+/// summary if it was open and an execution report for each of its orders
+pub fn delete_market(market: &mut Market) -> (Option<EodSummary>, Vec<ExecutionReport>) {
+    let (summary, mut cancelled) = market.delete();
+    cancelled.append(&mut market.take_closed_out_orders());
+    (summary, order_cancelled_reports(&cancelled))
+}
+
+#[must_use]
+/// closes the @market for good, its contract having expired, returning its end
+/// of day summary if it was open and an execution report for each of its orders
+pub fn expire_market(market: &mut Market) -> (Option<EodSummary>, Vec<ExecutionReport>) {
+    let (summary, mut cancelled) = market.expire();
+    cancelled.append(&mut market.take_closed_out_orders());
+    (summary, order_cancelled_reports(&cancelled))
+}
+
+#[must_use]
+/// closes the @market, the engine stopping, returning its end of day summary
+/// if it was open and an execution report for each of the day orders cancelled
+pub fn shut_down_market(market: &mut Market) -> (Option<EodSummary>, Vec<ExecutionReport>) {
+    match market.shut_down() {
+        Some((summary, cancelled)) => (Some(summary), order_cancelled_reports(&cancelled)),
+        None => (None, vec![]),
+    }
+}
+
+#[must_use]
+/// an execution report for each of the day orders cancelled by the closes of
+/// the @market since the last call
+pub fn closed_out_reports(market: &mut Market) -> Vec<ExecutionReport> {
+    order_cancelled_reports(&market.take_closed_out_orders())
+}
+
+#[must_use]
+/// cancels the trade @trade_id of the @market, returning an execution report
+/// for the orders on both sides of it, none if there was no such trade
+pub fn bust_trade(market: &mut Market, trade_id: u64) -> Vec<ExecutionReport> {
+    let Some(busted) = market.bust_trade(trade_id) else {
+        return vec![];
+    };
+    let book = market.get_instrument().read().unwrap().get_id();
+    [(busted.bid, Side::Bid), (busted.ask, Side::Ask)]
+        .iter()
+        .map(|(party, side)| ExecutionReport {
+            participant: party.participant,
+            order_id: party.order_id,
+            submitted_order_id: party.order_id,
+            book,
+            quantity: busted.quantity,
+            price: busted.price,
+            flags: 0,
+            side: (*side).into(),
+            state: OrderState::TradeBusted.into(),
+            gateway_id: party.gateway_id,
+            session_id: party.session_id,
+            filled_quantity: 0,
+            // what is still open, the bust leaving the order as it is
+            leaves_quantity: market
+                .get_order(party.order_id)
+                .map_or(0, |o| o.quantity + o.hidden_quantity),
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id,
+        })
+        .collect()
+}
+
+/// the execution reports for the @orders cancelled by the engine, sent to
+/// the sessions that entered them
+fn order_cancelled_reports(orders: &[Order]) -> Vec<ExecutionReport> {
+    orders
+        .iter()
+        .map(|o| ExecutionReport {
+            participant: o.participant,
+            order_id: o.get_id(),
+            submitted_order_id: o.get_id(),
+            book: o.instrument.read().unwrap().get_id(),
+            quantity: o.quantity + o.hidden_quantity,
+            price: o.price,
+            flags: 0,
+            side: o.side.into(),
+            state: OrderState::Cancelled.into(),
+            gateway_id: o.gateway_id,
+            session_id: o.session_id,
+            filled_quantity: 0,
+            leaves_quantity: 0,
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod test {
-    use std::{cell::RefCell, rc::Rc};
+    use std::sync::{Arc, Mutex, RwLock};
 
     use disseminator::mockdisseminator::MockDisseminator;
     use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
-    use market::Market;
+    use market::{orderid::OrderIdGenerator, Market};
     use oep::{
-        cancel::Cancel, execution_report::ExecutionReport, modify::Modify, neworder::NewOrder,
-        oep_message::OepMessage,
+        cancel::Cancel,
+        execution_report::{ExecutionReport, RejectReason},
+        masscancel::{MassCancel, ANY_BOOK, ANY_SIDE},
+        massquote::{MassQuote, QuoteEntry, MAX_MASS_QUOTE_ENTRIES},
+        modify::Modify,
+        neworder::{NewOrder, NEWORDER_SIZE},
+        oep_message::{MsgType, OepMessage},
+        quote::Quote,
+        quotecancelall::QuoteCancelAll,
+        replace::Replace,
+        sessioninfo::SessionInfo,
     };
     use order::{OrderState, OrderType, Side};
 
-    use super::{process_message, MessageWrapper};
+    use super::{
+        bust_trade, decode_message, expire_orders, passive_fill_reports, process_mass_cancel,
+        process_mass_quote, process_message, process_quote_cancel_all, process_session_kill,
+        reject_message, MessageWrapper,
+    };
 
     const BOOK_ID: u64 = 10000;
 
@@ -302,8 +1005,9 @@ mod test {
             20,
         );
         Market::new(
-            Rc::new(RefCell::new(instrument)),
-            Rc::new(RefCell::new(MockDisseminator::new())),
+            Arc::new(RwLock::new(instrument)),
+            Arc::new(Mutex::new(MockDisseminator::new())),
+            Arc::new(Mutex::new(OrderIdGenerator::new(0))),
         )
     }
 
@@ -321,6 +1025,9 @@ mod test {
             side: Side::Ask.into(),
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
         });
 
         let r = process_message(market, new_order);
@@ -334,7 +1041,7 @@ mod test {
         let ereport = process_default_day_order(&mut market);
 
         assert_eq!(BOOK_ID, ereport.get_book());
-        assert_eq!(ereport.state, OrderState::Inserted.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Inserted));
         assert_eq!(1, ereport.side);
         assert_eq!(100, ereport.get_price());
         assert_eq!(DEFAULT_GATEWAY_ID, ereport.get_gateway_id());
@@ -344,6 +1051,25 @@ mod test {
         assert_eq!(1, ereport.get_order_id());
     }
 
+    #[test]
+    fn decode_message_errors() {
+        let new_order: u8 = Into::<u16>::into(MsgType::NewOrder) as u8;
+        let mut buffer = vec![new_order, 0, 0, 0];
+        buffer.extend_from_slice(&[0; NEWORDER_SIZE]);
+        assert!(decode_message(&buffer).is_ok());
+
+        // errors rather than panics, for the engine to count and drop them
+        assert_eq!(
+            "Empty message",
+            decode_message(&[]).err().unwrap().to_string()
+        );
+        assert!(decode_message(&buffer[..buffer.len() - 1]).is_err());
+        assert!(decode_message(&buffer[..2]).is_err());
+        buffer.push(0);
+        assert!(decode_message(&buffer).is_err());
+        assert!(decode_message(&[0xff, 0, 0, 0]).is_err());
+    }
+
     #[test]
     fn process_modify() {
         let mut market = default_market();
@@ -360,6 +1086,7 @@ mod test {
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
             side: Side::Ask.into(),
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, modify_order);
@@ -369,7 +1096,597 @@ mod test {
         assert_eq!(2, ereport.get_order_id());
         assert_eq!(15, ereport.get_quantity());
         assert_eq!(12, ereport.get_price());
-        assert_eq!(ereport.state, OrderState::Inserted.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Inserted));
+    }
+
+    #[test]
+    fn exposure_block_rejects_adding_risk() {
+        let mut market = default_market();
+        let order_id = process_default_day_order(&mut market).order_id;
+        market.set_exposure_block(123, Some(Side::Ask));
+
+        let ereport = process_default_day_order(&mut market);
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
+        assert_eq!(RejectReason::ExposureLimit, ereport.get_reject_reason());
+
+        let modify = |quantity| {
+            MessageWrapper::Modify(Modify {
+                participant: 123,
+                order_id,
+                book_id: BOOK_ID,
+                quantity,
+                price: 100,
+                gateway_id: DEFAULT_GATEWAY_ID,
+                session_id: DEFAULT_SESSION_ID,
+                side: Side::Ask.into(),
+                orig_client_order_id: 0,
+            })
+        };
+        let ereport = process_message(&mut market, modify(300))[0];
+        assert_eq!(RejectReason::ExposureLimit, ereport.get_reject_reason());
+        // decreasing the quantity lowers the risk
+        let ereport = process_message(&mut market, modify(100))[0];
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Modified));
+
+        market.set_exposure_block(123, None);
+        let ereport = process_default_day_order(&mut market);
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Inserted));
+    }
+
+    #[test]
+    fn process_quote() {
+        let mut market = default_market();
+        process_default_day_order(&mut market);
+        let quote = |quote_id, bid_price, ask_quantity| {
+            MessageWrapper::Quote(Quote {
+                quote_id,
+                participant: 456,
+                book_id: BOOK_ID,
+                bid_price,
+                bid_quantity: 10,
+                ask_price: 110,
+                ask_quantity,
+                gateway_id: DEFAULT_GATEWAY_ID,
+                session_id: DEFAULT_SESSION_ID,
+            })
+        };
+
+        // a single report for both sides
+        let ereports = process_message(&mut market, quote(1, 90, 10));
+        assert_eq!(1, ereports.len());
+        let ack = ereports[0];
+        assert_eq!(ack.state, Into::<u8>::into(OrderState::QuoteAck));
+        assert_eq!(1, ack.get_submitted_order_id());
+        assert_eq!((10, 10), (ack.get_quantity(), ack.get_leaves_quantity()));
+        let (bid_id, ask_id) = (ack.get_order_id(), ack.orig_order_id);
+        assert_eq!((bid_id, ask_id), market.get_quote(456));
+        assert_eq!(90, market.get_order(bid_id).unwrap().price);
+        assert_eq!(110, market.get_order(ask_id).unwrap().price);
+
+        market.set_exposure_block(456, Some(Side::Ask));
+        let ereport = process_message(&mut market, quote(2, 90, 20))[0];
+        assert_eq!(RejectReason::ExposureLimit, ereport.get_reject_reason());
+        assert_eq!(2, ereport.get_submitted_order_id());
+        let ereport = process_message(&mut market, quote(3, 95, 10))[0];
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::QuoteAck));
+
+        // crossing the ask of 123, nothing changes
+        let ereport = process_message(&mut market, quote(4, 100, 10))[0];
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
+        assert_eq!(RejectReason::Unspecified, ereport.get_reject_reason());
+        let bid_id = market.get_quote(456).0;
+        assert_eq!(95, market.get_order(bid_id).unwrap().price);
+    }
+
+    #[test]
+    fn process_reports_passive_fills() {
+        let mut market = default_market();
+        let passive = process_default_day_order(&mut market);
+
+        let new_order = MessageWrapper::NewOrder(NewOrder {
+            client_order_id: 8000,
+            participant: 456,
+            book_id: BOOK_ID,
+            quantity: 150,
+            price: 100,
+            order_type: OrderType::Day.into(),
+            side: Side::Bid.into(),
+            gateway_id: DEFAULT_GATEWAY_ID + 1,
+            session_id: DEFAULT_SESSION_ID + 1,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        });
+        let ereports = process_message(&mut market, new_order);
+        assert_eq!(2, ereports.len());
+        assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::Traded));
+        assert_eq!(456, ereports[0].get_participant());
+        assert_eq!(150, ereports[0].get_filled_quantity());
+        assert_eq!(0, ereports[0].get_leaves_quantity());
+
+        // the resting order owner learns about the fill, on its own session
+        let fill = ereports[1];
+        assert_eq!(fill.state, Into::<u8>::into(OrderState::PartiallyTraded));
+        assert_eq!(passive.get_order_id(), fill.get_order_id());
+        assert_eq!(123, fill.get_participant());
+        assert_eq!(DEFAULT_GATEWAY_ID, fill.get_gateway_id());
+        assert_eq!(DEFAULT_SESSION_ID, fill.get_session_id());
+        assert_eq!(50, fill.get_quantity());
+        assert_eq!(100, fill.get_price());
+        assert_eq!(150, fill.get_filled_quantity());
+        assert_eq!(50, fill.get_leaves_quantity());
+        assert!(passive_fill_reports(&mut market).is_empty());
+    }
+
+    #[test]
+    fn session_kill_cancels_in_all_markets() {
+        let order_ids = Arc::new(Mutex::new(OrderIdGenerator::new(0)));
+        let mut markets: Vec<Market> = [BOOK_ID, BOOK_ID + 1]
+            .into_iter()
+            .map(|book_id| {
+                Market::new(
+                    Arc::new(RwLock::new(Instrument::new(
+                        book_id,
+                        "TEST",
+                        InstrumentType::Share,
+                        InstrumentState::Trading,
+                        10,
+                        20,
+                    ))),
+                    Arc::new(Mutex::new(MockDisseminator::new())),
+                    order_ids.clone(),
+                )
+            })
+            .collect();
+        for market in markets.iter_mut() {
+            for session_id in [DEFAULT_SESSION_ID, DEFAULT_SESSION_ID + 1] {
+                let book_id = market.get_instrument().read().unwrap().get_id();
+                let new_order = MessageWrapper::NewOrder(NewOrder {
+                    client_order_id: 7000,
+                    participant: 123,
+                    book_id,
+                    quantity: 200,
+                    price: 100,
+                    order_type: OrderType::Day.into(),
+                    side: Side::Ask.into(),
+                    gateway_id: DEFAULT_GATEWAY_ID,
+                    session_id,
+                    expiry: 0,
+                    stop_price: 0,
+                    display_quantity: 0,
+                });
+                let ereports = process_message(market, new_order);
+                assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::Inserted));
+            }
+        }
+
+        let session = SessionInfo::new(123, DEFAULT_SESSION_ID, DEFAULT_GATEWAY_ID);
+        let ereports = process_session_kill(markets.iter_mut(), session);
+        assert_eq!(2, ereports.len());
+        assert_eq!(BOOK_ID, ereports[0].get_book());
+        assert_eq!(BOOK_ID + 1, ereports[1].get_book());
+        for ereport in ereports {
+            assert_eq!(ereport.state, Into::<u8>::into(OrderState::Cancelled));
+            assert_eq!(DEFAULT_SESSION_ID, ereport.get_session_id());
+            assert!(ereport.is_cancel_on_disconnect());
+        }
+        // the other session keeps its orders
+        for market in markets {
+            assert_eq!(1, market.generate_asks().len());
+        }
+    }
+
+    #[test]
+    fn process_replace() {
+        let mut market = default_market();
+        let resting = process_default_day_order(&mut market);
+
+        let replace = Replace {
+            orig_order_id: resting.get_order_id(),
+            client_order_id: 7001,
+            participant: 123,
+            book_id: BOOK_ID,
+            quantity: 300,
+            price: 101,
+            order_type: OrderType::GoodTillCancel.into(),
+            side: Side::Ask.into(),
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        };
+        let ereports = process_message(&mut market, MessageWrapper::Replace(replace));
+        assert_eq!(2, ereports.len());
+        // first the cancel of the old order
+        assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::Cancelled));
+        assert_eq!(resting.get_order_id(), ereports[0].get_order_id());
+        assert_eq!(200, ereports[0].get_quantity());
+        // then the new one, both pointing back to the replaced order
+        assert_eq!(ereports[1].state, Into::<u8>::into(OrderState::Inserted));
+        assert_eq!(7001, ereports[1].get_submitted_order_id());
+        assert_eq!(300, ereports[1].get_leaves_quantity());
+        assert!(ereports
+            .iter()
+            .all(|ereport| ereport.get_orig_order_id() == resting.get_order_id()));
+        assert_eq!(
+            OrderType::GoodTillCancel,
+            market
+                .get_order(ereports[1].get_order_id())
+                .unwrap()
+                .order_type
+        );
+
+        // the old order is gone, so it can't be replaced again
+        let ereports = process_message(&mut market, MessageWrapper::Replace(replace));
+        assert_eq!(1, ereports.len());
+        assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::Rejected));
+        assert_eq!(7001, ereports[0].get_order_id());
+        assert_eq!(resting.get_order_id(), ereports[0].get_orig_order_id());
+    }
+
+    // two books, with a bid at 90 and an ask at 100 of participant 123 in each
+    fn two_markets() -> Vec<Market> {
+        let order_ids = Arc::new(Mutex::new(OrderIdGenerator::new(0)));
+        let mut markets: Vec<Market> = [BOOK_ID, BOOK_ID + 1]
+            .into_iter()
+            .map(|book_id| {
+                Market::new(
+                    Arc::new(RwLock::new(Instrument::new(
+                        book_id,
+                        "TEST",
+                        InstrumentType::Share,
+                        InstrumentState::Trading,
+                        10,
+                        20,
+                    ))),
+                    Arc::new(Mutex::new(MockDisseminator::new())),
+                    order_ids.clone(),
+                )
+            })
+            .collect();
+        for market in markets.iter_mut() {
+            for (side, price) in [(Side::Bid, 90), (Side::Ask, 100)] {
+                let book_id = market.get_instrument().read().unwrap().get_id();
+                let new_order = MessageWrapper::NewOrder(NewOrder {
+                    client_order_id: 7000,
+                    participant: 123,
+                    book_id,
+                    quantity: 200,
+                    price,
+                    order_type: OrderType::Day.into(),
+                    side: side.into(),
+                    gateway_id: DEFAULT_GATEWAY_ID,
+                    session_id: DEFAULT_SESSION_ID,
+                    expiry: 0,
+                    stop_price: 0,
+                    display_quantity: 0,
+                });
+                let ereports = process_message(market, new_order);
+                assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::Inserted));
+            }
+        }
+        markets
+    }
+
+    #[test]
+    fn mass_cancel() {
+        let mut markets = two_markets();
+
+        // the bids of one book, requested from another session
+        let mass_cancel = MassCancel {
+            participant: 123,
+            book_id: BOOK_ID,
+            side: Side::Bid.into(),
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID + 1,
+        };
+        let ereports = process_message(&mut markets[1], MessageWrapper::MassCancel(mass_cancel));
+        assert!(ereports.is_empty());
+        let ereports = process_message(&mut markets[0], MessageWrapper::MassCancel(mass_cancel));
+        assert_eq!(1, ereports.len());
+        assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::Cancelled));
+        assert_eq!(Side::Bid as u8, ereports[0].side);
+        assert_eq!(DEFAULT_SESSION_ID + 1, ereports[0].get_session_id());
+
+        // and everything else, everywhere
+        let mass_cancel = MassCancel {
+            participant: 123,
+            book_id: ANY_BOOK,
+            side: ANY_SIDE,
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID,
+        };
+        let ereports = process_mass_cancel(markets.iter_mut(), mass_cancel);
+        assert_eq!(3, ereports.len());
+        assert!(ereports
+            .iter()
+            .all(|ereport| ereport.state == Into::<u8>::into(OrderState::Cancelled)));
+        for market in markets {
+            assert!(market.generate_bids().is_empty());
+            assert!(market.generate_asks().is_empty());
+        }
+    }
+
+    #[test]
+    fn mass_quote() {
+        let mut markets = two_markets();
+        let entry = |book_id, bid_price| QuoteEntry {
+            book_id,
+            bid_price,
+            bid_quantity: 10,
+            ask_price: 98,
+            ask_quantity: 10,
+        };
+        let mut mass_quote = MassQuote {
+            mass_quote_id: 77,
+            participant: 456,
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID,
+            entry_count: 3,
+            entries: [QuoteEntry::default(); MAX_MASS_QUOTE_ENTRIES],
+        };
+        // the second one crosses the ask at 100, the third book is unknown
+        mass_quote.entries[0] = entry(BOOK_ID, 92);
+        mass_quote.entries[1] = entry(BOOK_ID + 1, 100);
+        mass_quote.entries[2] = entry(BOOK_ID + 2, 92);
+        let mut ereports = process_mass_quote(markets.iter_mut(), &mass_quote);
+        ereports.sort_by_key(|ereport| ereport.book);
+        assert_eq!(2, ereports.len());
+        assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::QuoteAck));
+        assert_eq!(
+            (BOOK_ID, 77),
+            (ereports[0].book, ereports[0].get_submitted_order_id())
+        );
+        assert_eq!(
+            (ereports[0].get_order_id(), ereports[0].orig_order_id),
+            markets[0].get_quote(456)
+        );
+        assert_eq!(ereports[1].state, Into::<u8>::into(OrderState::Rejected));
+        assert_eq!(
+            (BOOK_ID + 1, 77),
+            (ereports[1].book, ereports[1].get_submitted_order_id())
+        );
+        assert_eq!((0, 0), markets[1].get_quote(456));
+
+        // only the quotes go
+        let cancel = QuoteCancelAll {
+            participant: 456,
+            book_id: ANY_BOOK,
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID,
+        };
+        let ereports = process_quote_cancel_all(markets.iter_mut(), cancel);
+        assert_eq!(2, ereports.len());
+        assert!(ereports
+            .iter()
+            .all(|ereport| ereport.state == Into::<u8>::into(OrderState::Cancelled)));
+        assert_eq!(90, markets[0].generate_bids()[0].price);
+        assert_eq!(100, markets[0].generate_asks()[0].price);
+    }
+
+    #[test]
+    fn process_reports_filled_and_leaves_quantity() {
+        let mut market = default_market();
+        let passive = process_default_day_order(&mut market);
+        assert_eq!(0, passive.get_filled_quantity());
+        assert_eq!(200, passive.get_leaves_quantity());
+
+        let new_order = MessageWrapper::NewOrder(NewOrder {
+            client_order_id: 8000,
+            participant: 456,
+            book_id: BOOK_ID,
+            quantity: 250,
+            price: 100,
+            order_type: OrderType::Day.into(),
+            side: Side::Bid.into(),
+            gateway_id: DEFAULT_GATEWAY_ID + 1,
+            session_id: DEFAULT_SESSION_ID + 1,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        });
+        let ereports = process_message(&mut market, new_order);
+        assert_eq!(2, ereports.len());
+        // the aggressor traded what was available and rests with the remainder
+        assert_eq!(
+            ereports[0].state,
+            Into::<u8>::into(OrderState::PartiallyTraded)
+        );
+        assert_eq!(250, ereports[0].get_quantity());
+        assert_eq!(200, ereports[0].get_filled_quantity());
+        assert_eq!(50, ereports[0].get_leaves_quantity());
+        assert!(ereports[0].is_aggressor());
+        assert!(ereports[0].is_partial_fill());
+        assert_eq!(1, ereports[0].get_trade_id());
+        assert_eq!(ereports[1].state, Into::<u8>::into(OrderState::Traded));
+        assert_eq!(200, ereports[1].get_filled_quantity());
+        assert_eq!(0, ereports[1].get_leaves_quantity());
+        assert_eq!(0, ereports[1].get_flags());
+        assert_eq!(1, ereports[1].get_trade_id());
+
+        // both sides of the trade are told of its bust
+        let busted = bust_trade(&mut market, 1);
+        assert_eq!(2, busted.len());
+        assert!(busted.iter().all(|ereport| ereport.get_trade_id() == 1));
+        assert!(bust_trade(&mut market, 1).is_empty());
+
+        let cancel = MessageWrapper::Cancel(Cancel {
+            participant: 456,
+            order_id: ereports[0].get_order_id(),
+            book_id: BOOK_ID,
+            side: Side::Bid.into(),
+            gateway_id: DEFAULT_GATEWAY_ID + 1,
+            session_id: DEFAULT_SESSION_ID + 1,
+            orig_client_order_id: 0,
+        });
+        let ereports = process_message(&mut market, cancel);
+        assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::Cancelled));
+        assert_eq!(0, ereports[0].get_filled_quantity());
+        assert_eq!(0, ereports[0].get_leaves_quantity());
+        assert_eq!(0, ereports[0].get_flags());
+        assert_eq!(0, ereports[0].get_trade_id());
+    }
+
+    #[test]
+    fn aggressor_reports_its_first_trade() {
+        let mut market = default_market();
+        let mut ask = |price| {
+            let new_order = MessageWrapper::NewOrder(NewOrder {
+                client_order_id: 7000,
+                participant: 123,
+                book_id: BOOK_ID,
+                quantity: 10,
+                price,
+                order_type: OrderType::Day.into(),
+                side: Side::Ask.into(),
+                gateway_id: DEFAULT_GATEWAY_ID,
+                session_id: DEFAULT_SESSION_ID,
+                expiry: 0,
+                stop_price: 0,
+                display_quantity: 0,
+            });
+            assert_eq!(0, process_message(&mut market, new_order)[0].get_trade_id());
+        };
+        ask(100);
+        ask(101);
+        let new_order = MessageWrapper::NewOrder(NewOrder {
+            client_order_id: 8000,
+            participant: 456,
+            book_id: BOOK_ID,
+            quantity: 20,
+            price: 101,
+            order_type: OrderType::Day.into(),
+            side: Side::Bid.into(),
+            gateway_id: DEFAULT_GATEWAY_ID + 1,
+            session_id: DEFAULT_SESSION_ID + 1,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        });
+        let ereports = process_message(&mut market, new_order);
+        assert_eq!(3, ereports.len());
+        assert_eq!(1, ereports[0].get_trade_id());
+        assert_eq!(1, ereports[1].get_trade_id());
+        assert_eq!(2, ereports[2].get_trade_id());
+    }
+
+    #[test]
+    fn process_reports_partial_passive_fills_and_band_rejects() {
+        let mut market = default_market();
+        market
+            .get_instrument()
+            .write()
+            .unwrap()
+            .set_percentage_bands(10);
+        process_default_day_order(&mut market);
+        let bid = |client_order_id, price, quantity| {
+            MessageWrapper::NewOrder(NewOrder {
+                client_order_id,
+                participant: 456,
+                book_id: BOOK_ID,
+                quantity,
+                price,
+                order_type: OrderType::Day.into(),
+                side: Side::Bid.into(),
+                gateway_id: DEFAULT_GATEWAY_ID + 1,
+                session_id: DEFAULT_SESSION_ID + 1,
+                expiry: 0,
+                stop_price: 0,
+                display_quantity: 0,
+            })
+        };
+
+        let ereports = process_message(&mut market, bid(8000, 100, 50));
+        assert_eq!(2, ereports.len());
+        // the aggressor traded in full, the resting order in part
+        assert!(ereports[0].is_aggressor());
+        assert!(!ereports[0].is_partial_fill());
+        assert!(!ereports[1].is_aggressor());
+        assert!(ereports[1].is_partial_fill());
+
+        let ereports = process_message(&mut market, bid(8001, 95, 10));
+        assert_eq!(0, ereports[0].get_flags());
+        let ereports = process_message(&mut market, bid(8002, 50, 10));
+        assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::Rejected));
+        assert!(ereports[0].is_band_reject());
+        // the other rejects aren't
+        let ereports = process_message(&mut market, bid(8003, 95, 0));
+        assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::Rejected));
+        assert!(!ereports[0].is_band_reject());
+    }
+
+    #[test]
+    fn process_modify_keeps_order_type() {
+        let mut market = default_market();
+
+        let new_order = MessageWrapper::NewOrder(NewOrder {
+            client_order_id: 7000,
+            participant: 123,
+            book_id: BOOK_ID,
+            quantity: 200,
+            price: 100,
+            order_type: OrderType::GoodTillCancel.into(),
+            side: Side::Ask.into(),
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        });
+        let order_id = process_message(&mut market, new_order)[0].order_id;
+
+        let modify_order = MessageWrapper::Modify(Modify {
+            participant: 123,
+            order_id,
+            book_id: BOOK_ID,
+            quantity: 150,
+            price: 100,
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID,
+            side: Side::Ask.into(),
+            orig_client_order_id: 0,
+        });
+
+        let ereports = process_message(&mut market, modify_order);
+        assert_eq!(ereports[0].state, Into::<u8>::into(OrderState::Modified));
+        assert_eq!(
+            OrderType::GoodTillCancel,
+            market.get_order(order_id).unwrap().order_type
+        );
+    }
+
+    #[test]
+    fn expire_good_till_date_orders() {
+        let mut market = default_market();
+        process_default_day_order(&mut market);
+
+        let new_order = MessageWrapper::NewOrder(NewOrder {
+            client_order_id: 7001,
+            participant: 123,
+            book_id: BOOK_ID,
+            quantity: 150,
+            price: 101,
+            order_type: OrderType::GoodTillDate.into(),
+            side: Side::Ask.into(),
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID,
+            expiry: 5000,
+            stop_price: 0,
+            display_quantity: 0,
+        });
+        let order_id = process_message(&mut market, new_order)[0].order_id;
+
+        assert!(expire_orders(&mut market, 4999).is_empty());
+        let ereports = expire_orders(&mut market, 5000);
+        assert_eq!(1, ereports.len());
+        let ereport = ereports[0];
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Cancelled));
+        assert_eq!(order_id, ereport.get_order_id());
+        assert_eq!(150, ereport.get_quantity());
+        assert_eq!(101, ereport.get_price());
+        assert_eq!(DEFAULT_SESSION_ID, ereport.get_session_id());
+        assert_eq!(1, market.generate_asks().len());
     }
 
     #[test]
@@ -388,6 +1705,7 @@ mod test {
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
             side: Side::Ask.into(),
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, modify_order);
@@ -395,7 +1713,7 @@ mod test {
         let ereport = ereports[0];
 
         assert_eq!(0, ereport.get_order_id());
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -411,12 +1729,48 @@ mod test {
             side: Side::Ask.into(),
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
         });
 
-        assert_eq!(
-            process_message(&mut market, new_order)[0].state,
-            OrderState::Rejected.into()
-        );
+        let ereport = process_message(&mut market, new_order)[0];
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
+        assert_eq!(RejectReason::Unspecified, ereport.get_reject_reason());
+    }
+
+    #[test]
+    fn reject_outside_partition() {
+        let new_order = MessageWrapper::NewOrder(NewOrder {
+            client_order_id: 7000,
+            participant: 123,
+            book_id: BOOK_ID,
+            quantity: 200,
+            price: 100,
+            order_type: OrderType::Day.into(),
+            side: Side::Ask.into(),
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        });
+
+        let r = reject_message(&new_order, RejectReason::OutsidePartition);
+        assert_eq!(1, r.len());
+        assert_eq!(r[0].state, Into::<u8>::into(OrderState::Rejected));
+        assert_eq!(RejectReason::OutsidePartition, r[0].get_reject_reason());
+        assert_eq!(BOOK_ID, r[0].get_book());
+        assert_eq!(7000, r[0].get_order_id());
+        assert_eq!(DEFAULT_GATEWAY_ID, r[0].get_gateway_id());
+        assert_eq!(DEFAULT_SESSION_ID, r[0].get_session_id());
+
+        let session_kill = MessageWrapper::KillSession(SessionInfo::new(
+            123,
+            DEFAULT_SESSION_ID,
+            DEFAULT_GATEWAY_ID,
+        ));
+        assert!(reject_message(&session_kill, RejectReason::OutsidePartition).is_empty());
     }
 
     #[test]
@@ -435,6 +1789,7 @@ mod test {
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
             side: Side::Ask.into(),
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, modify_order);
@@ -442,7 +1797,7 @@ mod test {
         let ereport = ereports[0];
 
         assert_eq!(0, ereport.get_order_id());
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -461,6 +1816,7 @@ mod test {
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
             side: Side::Ask.into(),
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, modify_order);
@@ -468,7 +1824,7 @@ mod test {
         let ereport = ereports[0];
 
         assert_eq!(order_id, ereport.get_order_id());
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -487,6 +1843,7 @@ mod test {
             gateway_id: DEFAULT_GATEWAY_ID + 5,
             session_id: DEFAULT_SESSION_ID,
             side: Side::Ask.into(),
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, modify_order);
@@ -494,7 +1851,7 @@ mod test {
         let ereport = ereports[0];
 
         assert_eq!(0, ereport.get_order_id());
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -513,6 +1870,7 @@ mod test {
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID + 5,
             side: Side::Ask.into(),
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, modify_order);
@@ -520,7 +1878,7 @@ mod test {
         let ereport = ereports[0];
 
         assert_eq!(0, ereport.get_order_id());
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -539,6 +1897,7 @@ mod test {
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
             side: Side::Bid.into(),
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, modify_order);
@@ -546,7 +1905,7 @@ mod test {
         let ereport = ereports[0];
 
         assert_eq!(0, ereport.get_order_id());
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -563,13 +1922,75 @@ mod test {
             side: Side::Ask.into(),
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, cancel_order);
         assert_eq!(1, ereports.len());
         let ereport = ereports[0];
 
-        assert_eq!(ereport.state, OrderState::Cancelled.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Cancelled));
+        // enriched with the cancelled order
+        assert_eq!(200, ereport.get_quantity());
+        assert_eq!(100, ereport.get_price());
+    }
+
+    #[test]
+    fn process_cancel_by_client_order_id() {
+        let mut market = default_market();
+        let order_id = process_default_day_order(&mut market).order_id;
+        let cancel = Cancel {
+            participant: 123,
+            order_id: 0,
+            book_id: BOOK_ID,
+            side: Side::Ask.into(),
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID,
+            orig_client_order_id: 7000,
+        };
+        // the modify of the same order, named the same way
+        let modify = Modify {
+            participant: 123,
+            order_id: 0,
+            book_id: BOOK_ID,
+            quantity: 150,
+            price: 100,
+            side: Side::Ask.into(),
+            gateway_id: DEFAULT_GATEWAY_ID,
+            session_id: DEFAULT_SESSION_ID,
+            orig_client_order_id: 7000,
+        };
+        let ereports = process_message(&mut market, MessageWrapper::Modify(modify));
+        assert_eq!(OrderState::Modified, ereports[0].state.into());
+        assert_eq!(order_id, ereports[0].get_order_id());
+
+        // the client order id used again, by a second order
+        process_default_day_order(&mut market);
+        let ereports = process_message(&mut market, MessageWrapper::Cancel(cancel));
+        assert_eq!(1, ereports.len());
+        assert_eq!(OrderState::Rejected, ereports[0].state.into());
+        assert_eq!(
+            RejectReason::AmbiguousClientOrderId,
+            ereports[0].get_reject_reason()
+        );
+        assert_eq!(7000, ereports[0].get_submitted_order_id());
+        assert_eq!(2, market.generate_asks().len());
+
+        // no longer ambiguous once the first one is gone by its exchange id
+        let by_id = Cancel { order_id, ..cancel };
+        let ereports = process_message(&mut market, MessageWrapper::Cancel(by_id));
+        assert_eq!(OrderState::Cancelled, ereports[0].state.into());
+        let ereports = process_message(&mut market, MessageWrapper::Cancel(cancel));
+        assert_eq!(OrderState::Cancelled, ereports[0].state.into());
+        assert_eq!(200, ereports[0].get_quantity());
+        assert_ne!(order_id, ereports[0].get_order_id());
+        assert!(market.generate_asks().is_empty());
+
+        // nothing left carrying it
+        let ereports = process_message(&mut market, MessageWrapper::Cancel(cancel));
+        assert_eq!(OrderState::Rejected, ereports[0].state.into());
+        assert_eq!(RejectReason::Unspecified, ereports[0].get_reject_reason());
+        assert_eq!(7000, ereports[0].get_submitted_order_id());
     }
 
     #[test]
@@ -586,13 +2007,14 @@ mod test {
             side: Side::Ask.into(),
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, cancel_order);
         assert_eq!(1, ereports.len());
         let ereport = ereports[0];
 
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -609,13 +2031,14 @@ mod test {
             side: Side::Ask.into(),
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, cancel_order);
         assert_eq!(1, ereports.len());
         let ereport = ereports[0];
 
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -632,13 +2055,14 @@ mod test {
             side: Side::Ask.into(),
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, cancel_order);
         assert_eq!(1, ereports.len());
         let ereport = ereports[0];
 
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -655,13 +2079,14 @@ mod test {
             side: Side::Bid.into(),
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID,
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, cancel_order);
         assert_eq!(1, ereports.len());
         let ereport = ereports[0];
 
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -678,13 +2103,14 @@ mod test {
             side: Side::Ask.into(),
             gateway_id: DEFAULT_GATEWAY_ID + 5,
             session_id: DEFAULT_SESSION_ID,
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, cancel_order);
         assert_eq!(1, ereports.len());
         let ereport = ereports[0];
 
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 
     #[test]
@@ -701,12 +2127,13 @@ mod test {
             side: Side::Ask.into(),
             gateway_id: DEFAULT_GATEWAY_ID,
             session_id: DEFAULT_SESSION_ID + 5,
+            orig_client_order_id: 0,
         });
 
         let ereports = process_message(&mut market, cancel_order);
         assert_eq!(1, ereports.len());
         let ereport = ereports[0];
 
-        assert_eq!(ereport.state, OrderState::Rejected.into());
+        assert_eq!(ereport.state, Into::<u8>::into(OrderState::Rejected));
     }
 }
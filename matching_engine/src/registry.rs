@@ -0,0 +1,326 @@
+//! The gateways registered with the engine, and where their reports go
+//!
+//! Every gateway registers at startup and again every second, with an
+//! instance of its own. A registration for a gateway id heard of less than
+//! the gateway timeout ago from another instance is refused, two gateways
+//! running with the same id getting each other's execution reports. A
+//! gateway unregistering, or silent for that long, frees its id.
+//!
+//! With unicast reports, the reports of a gateway giving an endpoint go to
+//! that endpoint only, unless one of the gateways wants the reports of all
+//! of them for its drop copy, everything then staying on multicast.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use oep::{
+    decoder::Decoder,
+    header::{OepHeader, OEP_HEADER_SIZE},
+    oep_message::MsgType,
+    registration::{
+        GatewayRegistration, GatewayRegistrationReply, RegistrationKind, RegistrationStatus,
+        GATEWAYREGISTRATION_SIZE,
+    },
+};
+use tracing::{info, warn};
+
+/// The endpoints of the gateways taking their reports by unicast, shared by
+/// the publishers of all the shards
+#[derive(Debug, Default)]
+pub struct Routes {
+    // gateway id -> where its reports go
+    endpoints: HashMap<u8, SocketAddr>,
+}
+
+impl Routes {
+    /// Where the reports of @gateway_id go, none for the multicast
+    pub fn endpoint(&self, gateway_id: u8) -> Option<SocketAddr> {
+        self.endpoints.get(&gateway_id).copied()
+    }
+}
+
+#[derive(Debug)]
+struct RegisteredGateway {
+    instance: u32,
+    endpoint: Option<SocketAddr>,
+    all_reports: bool,
+    last_seen: Instant,
+}
+
+#[derive(Debug)]
+pub struct GatewayRegistry {
+    engine_id: u8,
+    partition_id: u8,
+    // silence after which a gateway is forgotten
+    timeout: Duration,
+    // whether the endpoints of the gateways are used
+    unicast: bool,
+    // gateway id -> its running instance
+    gateways: HashMap<u8, RegisteredGateway>,
+    routes: Arc<RwLock<Routes>>,
+}
+
+impl GatewayRegistry {
+    pub fn new(engine_id: u8, partition_id: u8, timeout: Duration, unicast: bool) -> Self {
+        Self {
+            engine_id,
+            partition_id,
+            timeout,
+            unicast,
+            gateways: HashMap::new(),
+            routes: Arc::new(RwLock::new(Routes::default())),
+        }
+    }
+
+    /// The routing table, kept up to date with the registrations
+    pub fn routes(&self) -> Arc<RwLock<Routes>> {
+        self.routes.clone()
+    }
+
+    /// Takes @registration, received at @now
+    ///
+    /// Returns: the reply for the gateway, none for an unregistration
+    pub fn register(
+        &mut self,
+        registration: &GatewayRegistration,
+        now: Instant,
+    ) -> Option<GatewayRegistrationReply> {
+        let (gateway_id, instance) = (registration.gateway_id, registration.instance);
+        let current = self.gateways.get(&gateway_id);
+        let taken = current.is_some_and(|g| {
+            g.instance != instance && now.duration_since(g.last_seen) < self.timeout
+        });
+        let status = match registration.get_kind() {
+            RegistrationKind::Unregister => {
+                if current.is_some_and(|g| g.instance == instance) {
+                    info!(gateway_id, "Gateway unregistered");
+                    self.gateways.remove(&gateway_id);
+                    self.update_routes();
+                }
+                return None;
+            }
+            RegistrationKind::Register if taken => {
+                warn!(
+                    gateway_id,
+                    instance, "Refused the registration of a gateway id already taken"
+                );
+                RegistrationStatus::DuplicateId
+            }
+            RegistrationKind::Register => {
+                let endpoint = registration.endpoint();
+                let all_reports = registration.wants_all_reports();
+                let changed = current.is_none_or(|g| {
+                    (g.instance, g.endpoint, g.all_reports) != (instance, endpoint, all_reports)
+                });
+                if changed {
+                    info!(
+                        gateway_id,
+                        instance,
+                        ?endpoint,
+                        all_reports,
+                        "Gateway registered"
+                    );
+                }
+                self.gateways.insert(
+                    gateway_id,
+                    RegisteredGateway {
+                        instance,
+                        endpoint,
+                        all_reports,
+                        last_seen: now,
+                    },
+                );
+                if changed {
+                    self.update_routes();
+                }
+                RegistrationStatus::Accepted
+            }
+        };
+        Some(GatewayRegistrationReply::new(
+            self.engine_id,
+            self.partition_id,
+            gateway_id,
+            status,
+            instance,
+        ))
+    }
+
+    /// Forgets the gateways not heard of for the timeout, at @now
+    pub fn expire(&mut self, now: Instant) {
+        let before = self.gateways.len();
+        self.gateways.retain(|gateway_id, g| {
+            let alive = now.duration_since(g.last_seen) < self.timeout;
+            if !alive {
+                warn!(gateway_id, "Gateway silent for too long, forgotten");
+            }
+            alive
+        });
+        if self.gateways.len() != before {
+            self.update_routes();
+        }
+    }
+
+    fn update_routes(&self) {
+        let multicast_all = !self.unicast || self.gateways.values().any(|g| g.all_reports);
+        let mut routes = self.routes.write().unwrap();
+        routes.endpoints = if multicast_all {
+            HashMap::new()
+        } else {
+            self.gateways
+                .iter()
+                .filter_map(|(gateway_id, g)| g.endpoint.map(|e| (*gateway_id, e)))
+                .collect()
+        };
+    }
+}
+
+/// The GatewayRegistration in @message, if that's what it is
+pub fn gateway_registration(message: &[u8]) -> Option<GatewayRegistration> {
+    if message.len() != OEP_HEADER_SIZE + GATEWAYREGISTRATION_SIZE {
+        return None;
+    }
+    let header = OepHeader::decode(message[..OEP_HEADER_SIZE].try_into().unwrap()).ok()?;
+    if header.message_type() != MsgType::GatewayRegistration {
+        return None;
+    }
+    GatewayRegistration::decode(message[OEP_HEADER_SIZE..].try_into().unwrap()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        time::{Duration, Instant},
+    };
+
+    use oep::{
+        decoder::Decoder,
+        header::{OepHeader, OEP_VERSION},
+        oep_message::MsgType,
+        registration::{
+            GatewayRegistration, RegistrationKind, RegistrationStatus, FLAG_ALL_REPORTS,
+            GATEWAYREGISTRATION_SIZE,
+        },
+    };
+
+    use super::{gateway_registration, GatewayRegistry};
+
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    fn endpoint(port: u16) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)
+    }
+
+    fn register(gateway_id: u8, instance: u32, port: u16) -> GatewayRegistration {
+        GatewayRegistration::new(
+            gateway_id,
+            RegistrationKind::Register,
+            0,
+            instance,
+            Some(endpoint(port)),
+        )
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let mut target = GatewayRegistry::new(1, 2, TIMEOUT, true);
+        let now = Instant::now();
+        let reply = target.register(&register(3, 100, 24001), now).unwrap();
+        assert_eq!(RegistrationStatus::Accepted, reply.get_status());
+        assert_eq!(
+            (1, 2, 3),
+            (reply.engine_id, reply.partition_id, reply.gateway_id)
+        );
+        assert_eq!(100, { reply.instance });
+        // again, as every second
+        let later = now + Duration::from_secs(1);
+        let reply = target.register(&register(3, 100, 24001), later).unwrap();
+        assert_eq!(RegistrationStatus::Accepted, reply.get_status());
+
+        // another instance with the same id
+        let reply = target.register(&register(3, 200, 24002), later).unwrap();
+        assert_eq!(RegistrationStatus::DuplicateId, reply.get_status());
+        assert_eq!(200, { reply.instance });
+        assert_eq!(
+            Some(endpoint(24001)),
+            target.routes().read().unwrap().endpoint(3)
+        );
+
+        // once the first one is gone
+        let much_later = later + TIMEOUT;
+        let reply = target
+            .register(&register(3, 200, 24002), much_later)
+            .unwrap();
+        assert_eq!(RegistrationStatus::Accepted, reply.get_status());
+        assert_eq!(
+            Some(endpoint(24002)),
+            target.routes().read().unwrap().endpoint(3)
+        );
+    }
+
+    #[test]
+    fn unregister_and_expire() {
+        let mut target = GatewayRegistry::new(1, 2, TIMEOUT, true);
+        let now = Instant::now();
+        target.register(&register(3, 100, 24001), now);
+        target.register(&register(4, 100, 24002), now);
+        // not the instance registered
+        let unregister = GatewayRegistration::new(3, RegistrationKind::Unregister, 0, 99, None);
+        assert!(target.register(&unregister, now).is_none());
+        assert!(target.routes().read().unwrap().endpoint(3).is_some());
+
+        let unregister = GatewayRegistration::new(3, RegistrationKind::Unregister, 0, 100, None);
+        assert!(target.register(&unregister, now).is_none());
+        assert_eq!(None, target.routes().read().unwrap().endpoint(3));
+        // free right away
+        let reply = target.register(&register(3, 200, 24003), now).unwrap();
+        assert_eq!(RegistrationStatus::Accepted, reply.get_status());
+
+        target.register(&register(3, 200, 24003), now + TIMEOUT);
+        target.expire(now + TIMEOUT);
+        let routes = target.routes();
+        assert!(routes.read().unwrap().endpoint(3).is_some());
+        assert_eq!(None, routes.read().unwrap().endpoint(4));
+    }
+
+    #[test]
+    fn multicast() {
+        // without unicast reports
+        let mut target = GatewayRegistry::new(1, 2, TIMEOUT, false);
+        let now = Instant::now();
+        target.register(&register(3, 100, 24001), now);
+        assert_eq!(None, target.routes().read().unwrap().endpoint(3));
+
+        // a drop copy wants all the reports
+        let mut target = GatewayRegistry::new(1, 2, TIMEOUT, true);
+        target.register(&register(3, 100, 24001), now);
+        assert!(target.routes().read().unwrap().endpoint(3).is_some());
+        let dropcopy =
+            GatewayRegistration::new(4, RegistrationKind::Register, FLAG_ALL_REPORTS, 100, None);
+        target.register(&dropcopy, now);
+        assert_eq!(None, target.routes().read().unwrap().endpoint(3));
+        target.expire(now + TIMEOUT);
+        target.register(&register(3, 100, 24001), now + TIMEOUT);
+        assert!(target.routes().read().unwrap().endpoint(3).is_some());
+    }
+
+    #[test]
+    fn decode_registration() {
+        let registration = register(3, 100, 24001);
+        let header = OepHeader::new(
+            OEP_VERSION,
+            MsgType::GatewayRegistration.into(),
+            GATEWAYREGISTRATION_SIZE as u32,
+        )
+        .encode();
+        let message = [header.as_slice(), registration.encode().as_slice()].concat();
+        let decoded = gateway_registration(&message).unwrap();
+        assert_eq!(3, decoded.gateway_id);
+        assert_eq!(Some(endpoint(24001)), decoded.endpoint());
+        assert!(gateway_registration(&message[..message.len() - 1]).is_none());
+    }
+}
@@ -0,0 +1,341 @@
+//! Replication of the journal to hot-standby engines
+//!
+//! A primary engine with a replication port streams its journal to the backups
+//! connecting to it: the records journaled so far first, then every record as
+//! it gets appended. A backup applies them to its markets the way a restart
+//! replays the journal, nothing going out, so it holds the same books, order
+//! ids and feed sequences as the primary. Once the primary is gone, the
+//! connection closing or nothing coming from it for a while, the backup takes
+//! over where it stopped. The stream is made of frames
+//!
+//! ```text
+//! | Type (1) | Length (4) | Data (var) |
+//! ```
+//!
+//! type 0 carrying journal records, framed as in the journal, and type 1 being
+//! a heartbeat, without data, sent while there is nothing else to send.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use tracing::{info, warn};
+
+use crate::journal::JournalRecord;
+
+const FRAME_HEADER_SIZE: usize = 5;
+const TYPE_RECORDS: u8 = 0;
+const TYPE_HEARTBEAT: u8 = 1;
+// must stay well below the replication timeout of the backups
+const HEARTBEAT_EVERY: Duration = Duration::from_millis(200);
+// a backup not taking what is sent to it for this long is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+// the journal so far goes out in frames of about this size
+const HISTORY_FRAME_SIZE: usize = 1 << 20;
+// how long a backup waits for the primary at once
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+fn encode_frame(frame_type: u8, data: &[u8]) -> Vec<u8> {
+    let mut r = vec![frame_type];
+    r.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    r.extend_from_slice(data);
+    r
+}
+
+/// The journal records of the complete frames at the start of @buffer, along
+/// with the bytes taken by those frames
+///
+/// Fails if @buffer is not a replication stream
+pub fn decode_frames(buffer: &[u8]) -> io::Result<(Vec<JournalRecord>, usize)> {
+    let mut records = vec![];
+    let mut processed = 0;
+    while buffer.len() - processed >= FRAME_HEADER_SIZE {
+        let header = &buffer[processed..processed + FRAME_HEADER_SIZE];
+        let len = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+        let Some(data) =
+            buffer.get(processed + FRAME_HEADER_SIZE..processed + FRAME_HEADER_SIZE + len)
+        else {
+            break;
+        };
+        match header[0] {
+            TYPE_RECORDS => {
+                let mut decoded = 0;
+                while decoded < data.len() {
+                    let (record, len) = JournalRecord::decode(&data[decoded..]).map_err(|e| {
+                        io::Error::new(ErrorKind::InvalidData, format!("{e:?} replicated record"))
+                    })?;
+                    records.push(record);
+                    decoded += len;
+                }
+            }
+            TYPE_HEARTBEAT => {}
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Unknown replication frame",
+                ))
+            }
+        }
+        processed += FRAME_HEADER_SIZE + len;
+    }
+    Ok((records, processed))
+}
+
+// the frames carrying @records, cut between two records
+fn history_frames(records: &[u8]) -> Vec<Vec<u8>> {
+    let mut frames = vec![];
+    let (mut start, mut end) = (0, 0);
+    while end + 4 <= records.len() {
+        // length and CRC, then the bytes counted by the length
+        let len = u32::from_le_bytes(records[end..end + 4].try_into().unwrap()) as usize;
+        end = (end + 8 + len).min(records.len());
+        if end - start >= HISTORY_FRAME_SIZE {
+            frames.push(encode_frame(TYPE_RECORDS, &records[start..end]));
+            start = end;
+        }
+    }
+    if start < records.len() {
+        frames.push(encode_frame(TYPE_RECORDS, &records[start..]));
+    }
+    frames
+}
+
+/// Streams the journal of the primary to its backups, over TCP
+///
+/// Nothing waits for the backups to connect: `accept` is meant to be called
+/// from the main loop of the engine
+#[derive(Debug)]
+pub struct ReplicationServer {
+    listener: TcpListener,
+    backups: Vec<TcpStream>,
+    last_sent: Instant,
+}
+
+impl ReplicationServer {
+    pub fn new(addr: &str, port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((addr, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            backups: vec![],
+            last_sent: Instant::now(),
+        })
+    }
+
+    pub fn local_port(&self) -> io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    /// Takes in the backups that connected, sending them the records given by
+    /// @history first, as found in the journal file
+    pub fn accept(&mut self, mut history: impl FnMut() -> io::Result<Vec<u8>>) -> io::Result<()> {
+        while let Ok((mut stream, addr)) = self.listener.accept() {
+            let frames = history_frames(&history()?);
+            let sent = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+                .and_then(|_| stream.set_nodelay(true))
+                .and_then(|_| frames.iter().try_for_each(|f| stream.write_all(f)));
+            match sent {
+                Ok(()) => {
+                    info!(%addr, "Backup connected, sent it the journal so far");
+                    self.backups.push(stream);
+                }
+                Err(e) => warn!(%addr, error = %e, "Error sending the journal to the backup"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends @records, framed as in the journal, to all the backups. The
+    /// backups are dropped on errors
+    pub fn send(&mut self, records: &[u8]) {
+        self.send_frame(&encode_frame(TYPE_RECORDS, records));
+    }
+
+    /// Lets the backups know the primary is still there, if nothing was sent lately
+    pub fn heartbeat(&mut self) {
+        if self.last_sent.elapsed() > HEARTBEAT_EVERY {
+            self.send_frame(&encode_frame(TYPE_HEARTBEAT, &[]));
+        }
+    }
+
+    fn send_frame(&mut self, frame: &[u8]) {
+        self.backups
+            .retain_mut(|stream| match stream.write_all(frame) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!(error = %e, "Dropping a backup");
+                    false
+                }
+            });
+        self.last_sent = Instant::now();
+    }
+}
+
+/// The end of the stream kept by a backup
+#[derive(Debug)]
+pub struct ReplicationClient {
+    stream: TcpStream,
+    // the bytes of the frames not received entirely yet
+    buffer: Vec<u8>,
+    timeout: Duration,
+    last_heard: Instant,
+}
+
+impl ReplicationClient {
+    /// Connects to the primary at @addr, taken as gone once silent for @timeout
+    pub fn connect(addr: &str, timeout: Duration) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT.min(timeout)))?;
+        Ok(Self {
+            stream,
+            buffer: vec![],
+            timeout,
+            last_heard: Instant::now(),
+        })
+    }
+
+    /// The records received, waiting a bit for some
+    ///
+    /// Fails once the primary is gone: the connection closed or broken, or
+    /// nothing heard from it for the timeout
+    pub fn receive(&mut self) -> io::Result<Vec<JournalRecord>> {
+        let mut chunk = [0; 65536];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Closed by the primary",
+                ))
+            }
+            Ok(r) => {
+                self.buffer.extend_from_slice(&chunk[..r]);
+                self.last_heard = Instant::now();
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e),
+        }
+        if self.last_heard.elapsed() > self.timeout {
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "Nothing heard from the primary",
+            ));
+        }
+        let (records, processed) = decode_frames(&self.buffer)?;
+        self.buffer.drain(..processed);
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::ErrorKind, thread, time::Duration};
+
+    use crate::journal::{JournalEntry, JournalRecord};
+
+    use super::{
+        decode_frames, encode_frame, history_frames, ReplicationClient, ReplicationServer,
+        TYPE_HEARTBEAT, TYPE_RECORDS,
+    };
+
+    fn record(epoch: u32) -> Vec<u8> {
+        JournalRecord {
+            timestamp: 1000,
+            entry: JournalEntry::Start { epoch },
+        }
+        .encode()
+    }
+
+    fn epochs(records: &[JournalRecord]) -> Vec<u32> {
+        records
+            .iter()
+            .map(|r| match r.entry {
+                JournalEntry::Start { epoch } => epoch,
+                _ => panic!("Unexpected {r:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frames() {
+        let records = [record(1), record(2)].concat();
+        let stream = [
+            encode_frame(TYPE_RECORDS, &records),
+            encode_frame(TYPE_HEARTBEAT, &[]),
+            encode_frame(TYPE_RECORDS, &record(3)),
+        ]
+        .concat();
+        let (decoded, processed) = decode_frames(&stream).unwrap();
+        assert_eq!(vec![1, 2, 3], epochs(&decoded));
+        assert_eq!(stream.len(), processed);
+
+        // the last frame is not there entirely
+        let (decoded, processed) = decode_frames(&stream[..stream.len() - 1]).unwrap();
+        assert_eq!(vec![1, 2], epochs(&decoded));
+        assert_eq!(stream.len() - 5 - record(3).len(), processed);
+
+        // garbage
+        assert!(decode_frames(&encode_frame(7, &[])).is_err());
+        assert!(decode_frames(&encode_frame(TYPE_RECORDS, &[1, 2, 3])).is_err());
+
+        // the history is cut between the records
+        let history = record(1).repeat(100000);
+        let frames = history_frames(&history);
+        assert!(frames.len() > 1);
+        let (decoded, _) = decode_frames(&frames.concat()).unwrap();
+        assert_eq!(100000, decoded.len());
+        assert!(history_frames(&[]).is_empty());
+    }
+
+    #[test]
+    fn follow_the_primary() {
+        let mut server = ReplicationServer::new("127.0.0.1", 0).unwrap();
+        let addr = format!("127.0.0.1:{}", server.local_port().unwrap());
+        let mut target = ReplicationClient::connect(&addr, Duration::from_secs(5)).unwrap();
+        server
+            .accept(|| Ok([record(1), record(2)].concat()))
+            .unwrap();
+        server.send(&record(3));
+
+        let mut received = vec![];
+        while received.len() < 3 {
+            received.append(&mut target.receive().unwrap());
+        }
+        assert_eq!(vec![1, 2, 3], epochs(&received));
+
+        // the primary going away
+        drop(server);
+        let e = loop {
+            match target.receive() {
+                Ok(records) => assert!(records.is_empty()),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(ErrorKind::UnexpectedEof, e.kind());
+    }
+
+    #[test]
+    fn silent_primary() {
+        let mut server = ReplicationServer::new("127.0.0.1", 0).unwrap();
+        let addr = format!("127.0.0.1:{}", server.local_port().unwrap());
+        let mut target = ReplicationClient::connect(&addr, Duration::from_secs(1)).unwrap();
+        server.accept(|| Ok(vec![])).unwrap();
+
+        // kept alive by the heartbeats
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(50));
+            server.heartbeat();
+            assert!(target.receive().unwrap().is_empty());
+        }
+        // and no longer
+        let e = loop {
+            if let Err(e) = target.receive() {
+                break e;
+            }
+        };
+        assert_eq!(ErrorKind::TimedOut, e.kind());
+    }
+}
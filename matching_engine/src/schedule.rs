@@ -0,0 +1,356 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, bail, Result};
+use instruments::instrument::InstrumentState;
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+const SECONDS_PER_DAY: u64 = 24 * 3600;
+
+/// The trading hours of an instrument, in seconds since midnight UTC
+/// Before the open auction and after the close the instrument is closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TradingHours {
+    pub open_auction: u64,
+    pub continuous: u64,
+    pub close_auction: u64,
+    pub close: u64,
+}
+
+impl TradingHours {
+    /// Parses "HH:MM,HH:MM,HH:MM,HH:MM": the start of the open auction, of the
+    /// continuous trading, of the close auction and the close
+    pub fn parse(value: &str) -> Result<Self> {
+        let times = value
+            .split(',')
+            .map(|t| parse_time_of_day(t.trim()))
+            .collect::<Result<Vec<u64>>>()?;
+        let [open_auction, continuous, close_auction, close] = times[..] else {
+            bail!("Trading hours need 4 times, got {value}");
+        };
+        if !(open_auction <= continuous && continuous <= close_auction && close_auction <= close) {
+            bail!("Trading hours out of order: {value}");
+        }
+        Ok(Self {
+            open_auction,
+            continuous,
+            close_auction,
+            close,
+        })
+    }
+
+    /// The state of the instrument at @time_of_day, in seconds since midnight UTC
+    pub fn phase_at(&self, time_of_day: u64) -> InstrumentState {
+        match time_of_day {
+            t if t < self.open_auction => InstrumentState::Closed,
+            t if t < self.continuous => InstrumentState::Auction,
+            t if t < self.close_auction => InstrumentState::Trading,
+            t if t < self.close => InstrumentState::Auction,
+            _ => InstrumentState::Closed,
+        }
+    }
+}
+
+fn parse_time_of_day(value: &str) -> Result<u64> {
+    let (hours, minutes) = value
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Time {value} is not HH:MM"))?;
+    let (hours, minutes) = (hours.parse::<u64>()?, minutes.parse::<u64>()?);
+    if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+        bail!("Invalid time {value}");
+    }
+    Ok(hours * 3600 + minutes * 60)
+}
+
+/// Days since the unix epoch of a YYYY-MM-DD date
+fn parse_date(value: &str) -> Result<u64> {
+    let parts = value
+        .split('-')
+        .map(|p| p.parse::<u64>())
+        .collect::<Result<Vec<u64>, _>>()?;
+    let [year, month, day] = parts[..] else {
+        bail!("Date {value} is not YYYY-MM-DD");
+    };
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        bail!("Invalid date {value}");
+    }
+    // see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year / 400, year % 400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Ok(era * 146097 + day_of_era - 719468)
+}
+
+/// The trading calendar of the engine, loaded from the [schedule] section
+///
+/// The `default` key holds the trading hours of all the instruments, a `segment.N`
+/// key overrides them for the instruments of the segment N, and a key named after an
+/// instrument id overrides both for that instrument. The instruments without
+/// trading hours are only driven by the clearing. Saturdays, Sundays and the
+/// `holidays` (a comma separated list of YYYY-MM-DD dates) are closed all day.
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    default: Option<TradingHours>,
+    segments: HashMap<u16, TradingHours>,
+    instruments: HashMap<u64, TradingHours>,
+    // as days since the unix epoch
+    holidays: HashSet<u64>,
+    // the phase each instrument was put in last, the schedule only acts on transitions
+    applied: HashMap<u64, InstrumentState>,
+    // same, for the segments with trading hours of their own
+    applied_segments: HashMap<u16, InstrumentState>,
+}
+
+impl Schedule {
+    /// An empty schedule if there is no [schedule] section
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self> {
+        let Some(section) = config_map.get("schedule") else {
+            return Ok(Self::default());
+        };
+        let mut schedule = Self::default();
+        for (key, value) in section {
+            let value = value.as_deref().unwrap_or_default();
+            match key.as_str() {
+                "default" => schedule.default = Some(TradingHours::parse(value)?),
+                "holidays" => {
+                    schedule.holidays = value
+                        .split(',')
+                        .map(|d| d.trim())
+                        .filter(|d| !d.is_empty())
+                        .map(parse_date)
+                        .collect::<Result<HashSet<u64>>>()?
+                }
+                segment if segment.starts_with("segment.") => {
+                    let segment = segment["segment.".len()..]
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|s| *s != 0)
+                        .ok_or_else(|| anyhow!("Invalid schedule key {segment}"))?;
+                    schedule
+                        .segments
+                        .insert(segment, TradingHours::parse(value)?);
+                }
+                id => {
+                    let id = id
+                        .parse::<u64>()
+                        .map_err(|_| anyhow!("Unknown schedule key {id}"))?;
+                    schedule.instruments.insert(id, TradingHours::parse(value)?);
+                }
+            }
+        }
+        Ok(schedule)
+    }
+
+    fn hours_for(&self, instrument_id: u64, segment: u16) -> Option<TradingHours> {
+        self.instruments
+            .get(&instrument_id)
+            .or(self.segments.get(&segment))
+            .copied()
+            .or(self.default)
+    }
+
+    /// The state of the instrument of @segment at @now (unix timestamp, in
+    /// seconds), None if it has no trading hours
+    pub fn phase_at(&self, instrument_id: u64, segment: u16, now: u64) -> Option<InstrumentState> {
+        Some(self.phase_of(self.hours_for(instrument_id, segment)?, now))
+    }
+
+    fn phase_of(&self, hours: TradingHours, now: u64) -> InstrumentState {
+        let day = now / SECONDS_PER_DAY;
+        // the epoch was a Thursday
+        let weekend = (day + 3) % 7 >= 5;
+        if weekend || self.holidays.contains(&day) {
+            return InstrumentState::Closed;
+        }
+        hours.phase_at(now % SECONDS_PER_DAY)
+    }
+
+    /// The instruments entering a new phase at @now, and that phase
+    /// An instrument seen for the first time is put in its current phase
+    ///
+    /// @instruments - the instrument ids, with their segment
+    pub fn transitions(
+        &mut self,
+        instruments: impl Iterator<Item = (u64, u16)>,
+        now: u64,
+    ) -> Vec<(u64, InstrumentState)> {
+        let mut r = vec![];
+        for (id, segment) in instruments {
+            let Some(phase) = self.phase_at(id, segment, now) else {
+                continue;
+            };
+            if self.applied.insert(id, phase) != Some(phase) {
+                r.push((id, phase));
+            }
+        }
+        r
+    }
+
+    /// The segments with trading hours of their own entering a new phase at
+    /// @now, and that phase, the same way as `transitions`
+    pub fn segment_transitions(&mut self, now: u64) -> Vec<(u16, InstrumentState)> {
+        let mut r = vec![];
+        let mut segments: Vec<(u16, TradingHours)> =
+            self.segments.iter().map(|(s, h)| (*s, *h)).collect();
+        segments.sort_by_key(|(segment, _)| *segment);
+        for (segment, hours) in segments {
+            let phase = self.phase_of(hours, now);
+            if self.applied_segments.insert(segment, phase) != Some(phase) {
+                r.push((segment, phase));
+            }
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use configparser::ini::Ini;
+    use instruments::instrument::InstrumentState;
+
+    use super::{parse_date, Schedule, TradingHours};
+
+    // Monday, 2024-01-08 00:00:00 UTC
+    const MONDAY: u64 = 1704672000;
+
+    #[test]
+    fn parse_trading_hours() {
+        let hours = TradingHours::parse("07:50, 08:00,16:30,16:35").unwrap();
+        assert_eq!(7 * 3600 + 50 * 60, hours.open_auction);
+        assert_eq!(16 * 3600 + 35 * 60, hours.close);
+
+        assert!(TradingHours::parse("08:00,16:30,16:35").is_err());
+        assert!(TradingHours::parse("08:00,07:50,16:30,16:35").is_err());
+        assert!(TradingHours::parse("08:00,08:10,16:30,25:00").is_err());
+    }
+
+    #[test]
+    fn phases() {
+        let hours = TradingHours::parse("07:50,08:00,16:30,16:35").unwrap();
+        assert_eq!(InstrumentState::Closed, hours.phase_at(0));
+        assert_eq!(InstrumentState::Auction, hours.phase_at(7 * 3600 + 55 * 60));
+        assert_eq!(InstrumentState::Trading, hours.phase_at(8 * 3600));
+        assert_eq!(
+            InstrumentState::Auction,
+            hours.phase_at(16 * 3600 + 30 * 60)
+        );
+        assert_eq!(InstrumentState::Closed, hours.phase_at(16 * 3600 + 35 * 60));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(0, parse_date("1970-01-01").unwrap());
+        assert_eq!(MONDAY / 86400, parse_date("2024-01-08").unwrap());
+        assert_eq!(MONDAY / 86400 + 53, parse_date("2024-03-01").unwrap());
+        assert!(parse_date("2024-13-01").is_err());
+    }
+
+    #[test]
+    fn load_from_config() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[schedule]
+                default=07:50,08:00,16:30,16:35
+                100=09:00,09:30,16:00,16:10
+                holidays=2024-01-09",
+            ))
+            .unwrap();
+        let target = Schedule::from_config(&config_map).unwrap();
+        let noon = MONDAY + 12 * 3600;
+        assert_eq!(Some(InstrumentState::Trading), target.phase_at(1, 0, noon));
+        assert_eq!(
+            Some(InstrumentState::Auction),
+            target.phase_at(1, 0, MONDAY + 16 * 3600 + 30 * 60)
+        );
+        assert_eq!(
+            Some(InstrumentState::Closed),
+            target.phase_at(100, 0, MONDAY + 16 * 3600 + 30 * 60)
+        );
+        // holiday, then the weekend
+        assert_eq!(
+            Some(InstrumentState::Closed),
+            target.phase_at(1, 0, noon + 86400)
+        );
+        assert_eq!(
+            Some(InstrumentState::Trading),
+            target.phase_at(1, 0, noon + 2 * 86400)
+        );
+        assert_eq!(
+            Some(InstrumentState::Closed),
+            target.phase_at(1, 0, noon + 5 * 86400)
+        );
+
+        assert!(
+            Schedule::from_config(&Ini::new().read(String::new()).unwrap())
+                .unwrap()
+                .phase_at(1, 0, noon)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn transitions_only_once() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[schedule]
+                100=09:00,09:30,16:00,16:10",
+            ))
+            .unwrap();
+        let mut target = Schedule::from_config(&config_map).unwrap();
+
+        // the instruments without trading hours are left alone
+        let now = MONDAY + 9 * 3600;
+        assert_eq!(
+            vec![(100, InstrumentState::Auction)],
+            target.transitions([(100, 0), (200, 0)].into_iter(), now)
+        );
+        assert!(target
+            .transitions([(100, 0), (200, 0)].into_iter(), now + 60)
+            .is_empty());
+        assert_eq!(
+            vec![(100, InstrumentState::Trading)],
+            target.transitions([(100, 0), (200, 0)].into_iter(), now + 30 * 60)
+        );
+    }
+
+    #[test]
+    fn segments() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[schedule]
+                default=07:50,08:00,16:30,16:35
+                segment.2=09:00,09:30,16:00,16:10
+                100=10:00,10:30,15:00,15:10",
+            ))
+            .unwrap();
+        let mut target = Schedule::from_config(&config_map).unwrap();
+
+        // the instrument keys win over the segment ones, which win over the default
+        let now = MONDAY + 9 * 3600;
+        assert_eq!(Some(InstrumentState::Trading), target.phase_at(1, 1, now));
+        assert_eq!(Some(InstrumentState::Auction), target.phase_at(1, 2, now));
+        assert_eq!(Some(InstrumentState::Closed), target.phase_at(100, 2, now));
+        assert_eq!(
+            vec![(2, InstrumentState::Auction)],
+            target.segment_transitions(now)
+        );
+        assert!(target.segment_transitions(now + 60).is_empty());
+        assert_eq!(
+            vec![(2, InstrumentState::Trading)],
+            target.segment_transitions(now + 30 * 60)
+        );
+        // closed on the weekend
+        assert_eq!(
+            vec![(2, InstrumentState::Closed)],
+            target.segment_transitions(now + 5 * 86400)
+        );
+
+        for invalid in ["segment.0", "segment.x", "segment.70000"] {
+            let config_map = Ini::new()
+                .read(format!("[schedule]\n{invalid}=09:00,09:30,16:00,16:10"))
+                .unwrap();
+            assert!(Schedule::from_config(&config_map).is_err(), "{invalid}");
+        }
+    }
+}
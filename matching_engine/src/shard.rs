@@ -0,0 +1,1721 @@
+//! Sharding of the markets of the engine over worker threads
+//!
+//! A shard owns the markets of the books hashed to it by `shard_of`, their
+//! order ids and their feed, so it never shares anything with the other
+//! shards. The main thread reads the orders and the clearing and hands them
+//! over to the shards through channels. Each shard publishes its execution
+//! reports itself, and sends back what the clearing has to know about: the
+//! trade captures and the end of day summaries.
+//! Without shards, the engine runs a single one on its main thread.
+
+use std::{
+    collections::HashMap,
+    io,
+    str::FromStr,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, SendError, Sender},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::bail;
+use clearing_connection::genericclearingprotocol::MarketUpdate;
+use disseminator::{
+    disseminator::FeedDisseminator,
+    itchdisseminator::ItchDisseminator,
+    mbooepdisseminator::MBOOepDisseminator,
+    recovery::{RecoveryCache, RecoveryServer},
+};
+use market::{
+    bands::WithoutReference, debug::DebugSnapshot, orderid::OrderIdGenerator,
+    publish_segment_state, volatility::VolatilityConfig, Market,
+};
+use oep::{
+    decoder::Decoder,
+    eodsummary::EodSummary,
+    execution_report::{ExecutionReport, RejectReason, EXECUTIONREPORT_SIZE},
+    header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+    masscancel::ANY_BOOK,
+    oep_message::MsgType,
+    statechange::StateChangeReason,
+    tradecapture::TradeCapture,
+};
+use socket2::Socket;
+use tracing::{debug_span, error, info, warn};
+use utils::network::MulticastConfig;
+
+use crate::{
+    egress::SequencedEgress,
+    journal::{Journal, JournalEntry},
+    metrics::EngineMetrics,
+    processor::{self, MessageWrapper},
+    registry::Routes,
+    schedule::Schedule,
+    snapshot::ShardState,
+};
+
+const SEND_SNAPSHOTS_EVERY: Duration = Duration::from_millis(20000);
+// the first snapshots go out a couple of seconds after the start
+const FIRST_SNAPSHOTS_AFTER: Duration = Duration::from_millis(2000);
+const SEND_CHECKSUMS_EVERY: Duration = Duration::from_millis(1000);
+const SEND_STATISTICS_EVERY: Duration = Duration::from_millis(5000);
+const EXPIRE_ORDERS_EVERY: Duration = Duration::from_millis(1000);
+const APPLY_SCHEDULE_EVERY: Duration = Duration::from_millis(1000);
+// longest wait for a message, the timers being checked in between
+const MAX_POLL_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The shard handling @book_id, out of @shards
+///
+/// The book ids are mixed before being spread, so that the ranges of
+/// consecutive ids end up on all the shards.
+pub fn shard_of(book_id: u64, shards: usize) -> usize {
+    // the finalizer of splitmix64
+    let mut h = book_id;
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^= h >> 31;
+    (h % shards as u64) as usize
+}
+
+/// Whether @msg acts on all the books, instead of on a single one
+pub fn acts_on_all_books(msg: &MessageWrapper) -> bool {
+    match msg {
+        MessageWrapper::KillSession(_) => true,
+        MessageWrapper::MassCancel(mass_cancel) => mass_cancel.get_book_id() == ANY_BOOK,
+        // its entries can be for any of the books
+        MessageWrapper::MassQuote(_) => true,
+        MessageWrapper::QuoteCancelAll(cancel) => cancel.get_book_id() == ANY_BOOK,
+        _ => false,
+    }
+}
+
+/// How the market data is encoded, on the incremental and snapshot channels alike
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum FeedFormat {
+    // market by order, as described in doc/feed_protocol.md
+    #[default]
+    Mbo,
+    // NASDAQ ITCH like, for the standard feed handlers
+    Itch,
+}
+
+impl FromStr for FeedFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "mbo" => Ok(FeedFormat::Mbo),
+            "itch" => Ok(FeedFormat::Itch),
+            _ => bail!("Unknown feed format {s}"),
+        }
+    }
+}
+
+/// Where the feed of a shard goes
+#[derive(Debug, Clone)]
+pub struct FeedConfig {
+    pub format: FeedFormat,
+    pub disseminator_group: String,
+    pub disseminator_port: u16,
+    // the redundant B feed, none without a port
+    pub disseminator_b_group: String,
+    pub disseminator_b_port: Option<u16>,
+    pub snapshot_group: String,
+    pub snapshot_port: u16,
+    // several messages per datagram, only if set
+    pub mtu: Option<usize>,
+    pub batch_max_delay: Duration,
+    pub recovery_address: String,
+    // no retransmission without it
+    pub recovery_port: Option<u16>,
+    pub recovery_cache_size: usize,
+    // the interface, ttl and loopback of the feeds
+    pub multicast: MulticastConfig,
+}
+
+impl FeedConfig {
+    /// The feed of the shard @shard: same groups, the ports moved up by @shard
+    pub fn for_shard(&self, shard: usize) -> Self {
+        let offset = shard as u16;
+        Self {
+            disseminator_port: self.disseminator_port + offset,
+            disseminator_b_port: self.disseminator_b_port.map(|p| p + offset),
+            snapshot_port: self.snapshot_port + offset,
+            recovery_port: self.recovery_port.map(|p| p + offset),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShardConfig {
+    pub feed: FeedConfig,
+    pub volatility: VolatilityConfig,
+    // for the price bands of the books without a reference price
+    pub without_reference: WithoutReference,
+    // echoed in the execution reports
+    pub partition_id: u8,
+    pub schedule: Schedule,
+    // of the shard among the shards of the engine, 0 without shards
+    pub index: usize,
+    // where the feed sequence is kept, if journaling
+    pub journal: Option<Arc<Mutex<Journal>>>,
+    // following a primary engine, the timers being left to it until taking over
+    pub standby: bool,
+}
+
+/// Sends the execution reports to the gateways
+#[derive(Debug)]
+pub struct ExecutionReportPublisher {
+    socket: Socket,
+    header: [u8; OEP_HEADER_SIZE],
+    // where the reports are kept as well, if journaling
+    journal: Option<Arc<Mutex<Journal>>>,
+    // numbering the reports of each gateway, with a sequenced egress
+    egress: Option<Arc<Mutex<SequencedEgress>>>,
+    // the gateways taking their reports by unicast
+    routes: Option<Arc<RwLock<Routes>>>,
+}
+
+impl ExecutionReportPublisher {
+    /// @socket must be connected to the internal publisher group
+    pub fn new(socket: Socket) -> Self {
+        Self {
+            socket,
+            header: OepHeader {
+                oep_version: OEP_VERSION,
+                msg_type: MsgType::ExecutionReport.into(),
+                msg_len: EXECUTIONREPORT_SIZE as u32,
+                // sequenced by the gateway of the session
+                seq: 0,
+            }
+            .encode(),
+            journal: None,
+            egress: None,
+            routes: None,
+        }
+    }
+
+    /// Appends the reports published from now on to @journal as well
+    pub fn with_journal(mut self, journal: Arc<Mutex<Journal>>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Frames the reports published from now on with the sequences of
+    /// @egress, shared by the publishers of all the shards
+    pub fn with_egress(mut self, egress: Arc<Mutex<SequencedEgress>>) -> Self {
+        self.egress = Some(egress);
+        self
+    }
+
+    /// Sends the reports of the gateways found in @routes to their endpoints,
+    /// the others still going to the group
+    pub fn with_routes(mut self, routes: Arc<RwLock<Routes>>) -> Self {
+        self.routes = Some(routes);
+        self
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            socket: self.socket.try_clone()?,
+            header: self.header,
+            journal: self.journal.clone(),
+            egress: self.egress.clone(),
+            routes: self.routes.clone(),
+        })
+    }
+
+    fn send(&self, gateway_id: u8, datagram: &[u8]) -> io::Result<usize> {
+        let endpoint = self
+            .routes
+            .as_ref()
+            .and_then(|routes| routes.read().unwrap().endpoint(gateway_id));
+        match endpoint {
+            Some(endpoint) => self.socket.send_to(datagram, &endpoint.into()),
+            None => self.socket.send(datagram),
+        }
+    }
+
+    pub fn publish(&mut self, ereports: &[ExecutionReport]) -> io::Result<()> {
+        if let (Some(journal), false) = (&self.journal, ereports.is_empty()) {
+            journal
+                .lock()
+                .unwrap()
+                .append(JournalEntry::ExecutionReports(ereports.to_vec()))?;
+        }
+        for ereport in ereports {
+            let message = [self.header.as_slice(), ereport.encode().as_slice()].concat();
+            match &self.egress {
+                Some(egress) => {
+                    // sent while holding the lock, for the frames to go out in
+                    // the order of their sequences
+                    let mut egress = egress.lock().unwrap();
+                    let frame = egress.frame(ereport.gateway_id, &message);
+                    timeit!(publish, self.send(ereport.gateway_id, &frame)?);
+                }
+                None => {
+                    timeit!(publish, self.send(ereport.gateway_id, &message)?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The markets of a shard, with everything they need to run
+#[derive(Debug)]
+pub struct Shard {
+    markets: Arc<Mutex<HashMap<u64, Market>>>,
+    feed: Arc<Mutex<dyn FeedDisseminator>>,
+    snapshots: Box<dyn FeedDisseminator>,
+    recovery: Option<RecoveryServer>,
+    order_ids: Arc<Mutex<OrderIdGenerator>>,
+    // applied to the markets created by the shard
+    volatility: VolatilityConfig,
+    without_reference: WithoutReference,
+    partition_id: u8,
+    schedule: Schedule,
+    batch_max_delay: Option<Duration>,
+    // for the clearing
+    eod_summaries: Vec<EodSummary>,
+    index: usize,
+    journal: Option<Arc<Mutex<Journal>>>,
+    // the feed sequence last written to the journal
+    journaled_seq: u64,
+    // saved for a snapshot of the engine, since the last take_states
+    states: Vec<ShardState>,
+    standby: bool,
+    metrics: EngineMetrics,
+
+    last_snapshot_sent: Instant,
+    last_checksum_sent: Instant,
+    last_statistics_sent: Instant,
+    last_expiry_check: Instant,
+    last_schedule_check: Instant,
+}
+
+impl Shard {
+    pub fn new(config: ShardConfig, order_ids: OrderIdGenerator) -> io::Result<Self> {
+        let feed_config = config.feed;
+        let (group, port) = (
+            feed_config.disseminator_group.as_str(),
+            feed_config.disseminator_port,
+        );
+        let feed: Arc<Mutex<dyn FeedDisseminator>> = match feed_config.format {
+            FeedFormat::Mbo => Arc::new(Mutex::new(MBOOepDisseminator::new(
+                group,
+                port,
+                feed_config.multicast.clone(),
+            ))),
+            FeedFormat::Itch => Arc::new(Mutex::new(ItchDisseminator::new(
+                group,
+                port,
+                feed_config.multicast.clone(),
+            ))),
+        };
+        let (group, port) = (
+            feed_config.snapshot_group.as_str(),
+            feed_config.snapshot_port,
+        );
+        let mut snapshots: Box<dyn FeedDisseminator> = match feed_config.format {
+            FeedFormat::Mbo => Box::new(MBOOepDisseminator::new(
+                group,
+                port,
+                feed_config.multicast.clone(),
+            )),
+            FeedFormat::Itch => Box::new(ItchDisseminator::new(
+                group,
+                port,
+                feed_config.multicast.clone(),
+            )),
+        };
+        if let Some(mtu) = feed_config.mtu {
+            feed.lock().unwrap().set_mtu(mtu);
+            snapshots.set_mtu(mtu);
+        }
+        if let Some(port) = feed_config.disseminator_b_port {
+            feed.lock()
+                .unwrap()
+                .set_b_feed(&feed_config.disseminator_b_group, port);
+        }
+        let recovery = match feed_config.recovery_port {
+            Some(port) => {
+                let cache = Arc::new(Mutex::new(RecoveryCache::new(
+                    feed_config.recovery_cache_size,
+                )));
+                feed.lock().unwrap().set_recovery_cache(cache.clone());
+                Some(RecoveryServer::new(
+                    &feed_config.recovery_address,
+                    port,
+                    cache,
+                )?)
+            }
+            None => None,
+        };
+        let now = Instant::now();
+        Ok(Self {
+            markets: Arc::new(Mutex::new(HashMap::new())),
+            feed,
+            snapshots,
+            recovery,
+            order_ids: Arc::new(Mutex::new(order_ids)),
+            volatility: config.volatility,
+            without_reference: config.without_reference,
+            partition_id: config.partition_id,
+            schedule: config.schedule,
+            batch_max_delay: feed_config.mtu.map(|_| feed_config.batch_max_delay),
+            eod_summaries: vec![],
+            index: config.index,
+            journal: config.journal,
+            journaled_seq: 0,
+            states: vec![],
+            standby: config.standby,
+            metrics: EngineMetrics::new(utils::metrics::registry()),
+            last_snapshot_sent: now - SEND_SNAPSHOTS_EVERY + FIRST_SNAPSHOTS_AFTER,
+            last_checksum_sent: now,
+            last_statistics_sent: now,
+            last_expiry_check: now,
+            last_schedule_check: now - APPLY_SCHEDULE_EVERY,
+        })
+    }
+
+    /// The markets, feed and order ids of the shard, for a clearing protocol
+    /// creating the markets itself
+    pub fn markets(&self) -> Arc<Mutex<HashMap<u64, Market>>> {
+        self.markets.clone()
+    }
+
+    pub fn feed(&self) -> Arc<Mutex<dyn FeedDisseminator>> {
+        self.feed.clone()
+    }
+
+    pub fn order_ids(&self) -> Arc<Mutex<OrderIdGenerator>> {
+        self.order_ids.clone()
+    }
+
+    /// How long the shard can wait for a message, its feed batches going out in time
+    pub fn poll_timeout(&self) -> Duration {
+        self.batch_max_delay
+            .map_or(MAX_POLL_TIMEOUT, |d| d.min(MAX_POLL_TIMEOUT))
+    }
+
+    /// Processes an order message for @book_id, or for all the books of the
+    /// shard, see `acts_on_all_books`
+    pub fn process(&mut self, msg: MessageWrapper, book_id: u64) -> Vec<ExecutionReport> {
+        let oep_message = msg.as_oep_message();
+        let _span = debug_span!(
+            "process",
+            shard = self.index,
+            book_id,
+            session_id = oep_message.get_session_id(),
+            participant = oep_message.get_participant(),
+            msg_type = ?oep_message.message_type(),
+        )
+        .entered();
+        let start = Instant::now();
+        let mut markets = self.markets.lock().unwrap();
+        let ereports = match msg {
+            // the session might have orders in any of the markets
+            MessageWrapper::KillSession(session) => timeit!(
+                process,
+                processor::process_session_kill(markets.values_mut(), session)
+            ),
+            MessageWrapper::MassCancel(mass_cancel) if mass_cancel.get_book_id() == ANY_BOOK => {
+                timeit!(
+                    process,
+                    processor::process_mass_cancel(markets.values_mut(), mass_cancel)
+                )
+            }
+            MessageWrapper::MassQuote(mass_quote) => timeit!(
+                process,
+                processor::process_mass_quote(markets.values_mut(), &mass_quote)
+            ),
+            MessageWrapper::QuoteCancelAll(cancel) if cancel.get_book_id() == ANY_BOOK => timeit!(
+                process,
+                processor::process_quote_cancel_all(markets.values_mut(), cancel)
+            ),
+            _ => match markets.get_mut(&book_id) {
+                Some(market) => timeit!(process, processor::process_message(market, msg)),
+                // not left waiting for a reply that would never come
+                None => {
+                    warn!("No market for the book, rejecting the message");
+                    self.metrics.unknown_book.inc();
+                    processor::reject_message(&msg, RejectReason::UnknownBook)
+                }
+            },
+        };
+        self.metrics.orders_processed.inc();
+        self.metrics.match_latency.observe(start.elapsed());
+        with_partition(ereports, self.partition_id)
+    }
+
+    /// Applies an update of the clearing, creating the market of a new instrument
+    ///
+    /// Returns: the fills of an auction ended by the update
+    pub fn update_market(&mut self, update: MarketUpdate) -> Vec<ExecutionReport> {
+        let mut markets = self.markets.lock().unwrap();
+        match update {
+            MarketUpdate::Instrument(instrument) => {
+                let id = instrument.get_id();
+                let Some(market) = markets.get_mut(&id) else {
+                    // e.g. sent again after a reconnect, the contract being over
+                    if instrument.is_expired(unix_now()) {
+                        info!(
+                            book_id = id,
+                            "Not opening the market of an expired instrument"
+                        );
+                        return vec![];
+                    }
+                    let mut market = Market::new(
+                        Arc::new(RwLock::new(instrument)),
+                        self.feed.clone(),
+                        self.order_ids.clone(),
+                    );
+                    market.set_volatility_config(self.volatility);
+                    market.set_without_reference(self.without_reference);
+                    markets.insert(id, market);
+                    return vec![];
+                };
+                market
+                    .get_instrument()
+                    .write()
+                    .unwrap()
+                    .clone_from(&instrument);
+                // a market closing reports its day back to the clearing
+                if let Some(summary) = market.instrument_updated() {
+                    self.eod_summaries.push(summary);
+                }
+                let mut ereports = processor::passive_fill_reports(market);
+                ereports.append(&mut processor::closed_out_reports(market));
+                with_partition(ereports, self.partition_id)
+            }
+            MarketUpdate::Exposure {
+                participant,
+                book_id,
+                blocked_side,
+            } => {
+                if let Some(market) = markets.get_mut(&book_id) {
+                    market.set_exposure_block(participant, blocked_side);
+                }
+                vec![]
+            }
+            MarketUpdate::Deleted(book_id) => {
+                let Some(mut market) = markets.remove(&book_id) else {
+                    return vec![];
+                };
+                let (summary, ereports) = processor::delete_market(&mut market);
+                self.eod_summaries.extend(summary);
+                with_partition(ereports, self.partition_id)
+            }
+            MarketUpdate::TradeBust { book_id, trade_id } => {
+                let ereports = markets
+                    .get_mut(&book_id)
+                    .map(|market| processor::bust_trade(market, trade_id))
+                    .unwrap_or_default();
+                if ereports.is_empty() {
+                    warn!(book_id, trade_id, "No trade to bust");
+                }
+                with_partition(ereports, self.partition_id)
+            }
+            MarketUpdate::Segment { segment, state } => {
+                let mut ereports = vec![];
+                for market in markets.values_mut().filter(|m| m.get_segment() == segment) {
+                    market.get_instrument().write().unwrap().set_state(state);
+                    if let Some(summary) = market.instrument_updated() {
+                        self.eod_summaries.push(summary);
+                    }
+                    ereports.append(&mut processor::passive_fill_reports(market));
+                    ereports.append(&mut processor::closed_out_reports(market));
+                }
+                // on the feed of every shard, whether it has markets of the segment or not
+                if publish_segment_state(&*self.feed, segment, state, StateChangeReason::Clearing)
+                    .is_err()
+                {
+                    error!(
+                        shard = self.index,
+                        segment, "Error publishing the segment state"
+                    );
+                }
+                with_partition(ereports, self.partition_id)
+            }
+        }
+    }
+
+    /// Carries out @command
+    ///
+    /// Returns: the execution reports to publish
+    pub fn apply(&mut self, command: ShardCommand) -> Vec<ExecutionReport> {
+        match command {
+            ShardCommand::Order(msg, book_id) => self.process(msg, book_id),
+            ShardCommand::Market(update) => self.update_market(update),
+            ShardCommand::Replayed(command) => {
+                self.replay(*command);
+                vec![]
+            }
+            ShardCommand::SaveState => {
+                let state = self.save_state();
+                self.states.push(state);
+                vec![]
+            }
+            ShardCommand::RestoreState(state) => {
+                self.restore_state(state);
+                vec![]
+            }
+            ShardCommand::ResumeFeed(seq) => {
+                self.resume_feed(seq);
+                vec![]
+            }
+            ShardCommand::TakeOver => {
+                self.take_over();
+                vec![]
+            }
+            ShardCommand::Reconfigure(volatility, without_reference) => {
+                self.reconfigure(volatility, without_reference);
+                vec![]
+            }
+            ShardCommand::DebugSnapshot(book_id, reply) => {
+                // the admin may have given up waiting
+                let _ = reply.send(self.debug_snapshot(book_id));
+                vec![]
+            }
+            // replayed, the shard threads stopping on the others
+            ShardCommand::ShutDown => self.close_markets(),
+        }
+    }
+
+    /// Carries out @command, read back from the journal: the markets get to
+    /// where they were, but the execution reports, the trade captures, the
+    /// end of day summaries and the feed messages already went out the first time
+    pub fn replay(&mut self, command: ShardCommand) {
+        self.feed.lock().unwrap().set_muted(true);
+        self.apply(command);
+        self.feed.lock().unwrap().set_muted(false);
+        self.take_trade_captures();
+        self.take_eod_summaries();
+    }
+
+    /// The state of the markets, for a snapshot of the engine
+    pub fn save_state(&self) -> ShardState {
+        let order_ids = self.order_ids.lock().unwrap();
+        let markets = self.markets.lock().unwrap();
+        let mut book_ids: Vec<u64> = markets.keys().copied().collect();
+        book_ids.sort();
+        ShardState {
+            order_id_epoch: order_ids.epoch(),
+            order_id_seq: order_ids.get_sequence(),
+            feed_seq: self.feed.lock().unwrap().get_sequence(),
+            markets: book_ids
+                .iter()
+                .map(|id| markets[id].encode_state())
+                .collect(),
+        }
+    }
+
+    /// Brings the markets back to @state, saved by `save_state`
+    pub fn restore_state(&mut self, state: ShardState) {
+        let mut markets = self.markets.lock().unwrap();
+        markets.clear();
+        for buf in state.markets {
+            let mut market = Market::decode_state(&buf, self.feed.clone(), self.order_ids.clone())
+                .expect("Invalid market in the snapshot");
+            market.set_volatility_config(self.volatility);
+            market.set_without_reference(self.without_reference);
+            markets.insert(market.get_instrument().read().unwrap().get_id(), market);
+        }
+        self.order_ids
+            .lock()
+            .unwrap()
+            .resume(state.order_id_epoch, state.order_id_seq);
+        self.feed.lock().unwrap().set_sequence(state.feed_seq);
+        self.journaled_seq = state.feed_seq;
+    }
+
+    /// Moves the feed on to @seq, reached by a previous run of the engine
+    pub fn resume_feed(&mut self, seq: u64) {
+        let mut feed = self.feed.lock().unwrap();
+        if seq > feed.get_sequence() {
+            feed.set_sequence(seq);
+            self.journaled_seq = seq;
+        }
+    }
+
+    /// Leaves the standby, the primary being gone: the timers run from now on
+    pub fn take_over(&mut self) {
+        self.standby = false;
+    }
+
+    /// Applies @volatility and @without_reference to the markets, from their
+    /// next order on, and to the ones created from now on
+    pub fn reconfigure(
+        &mut self,
+        volatility: VolatilityConfig,
+        without_reference: WithoutReference,
+    ) {
+        self.volatility = volatility;
+        self.without_reference = without_reference;
+        for market in self.markets.lock().unwrap().values_mut() {
+            market.set_volatility_config(volatility);
+            market.set_without_reference(without_reference);
+        }
+    }
+
+    /// The book, statistics and counters of the market of @book_id, none
+    /// without such a market in the shard
+    pub fn debug_snapshot(&self, book_id: u64) -> Option<DebugSnapshot> {
+        self.markets
+            .lock()
+            .unwrap()
+            .get(&book_id)
+            .map(Market::to_debug_snapshot)
+    }
+
+    /// The states saved since the last call
+    pub fn take_states(&mut self) -> Vec<ShardState> {
+        std::mem::take(&mut self.states)
+    }
+
+    /// The reports of the resting orders traded outside of an order message,
+    /// e.g. when an instrument update ended an auction
+    pub fn passive_fill_reports(&mut self) -> Vec<ExecutionReport> {
+        let ereports = self
+            .markets
+            .lock()
+            .unwrap()
+            .values_mut()
+            .flat_map(processor::passive_fill_reports)
+            .collect();
+        with_partition(ereports, self.partition_id)
+    }
+
+    /// Runs what is due among the periodic tasks of the markets: the snapshots,
+    /// checksums and statistics on the feed, the order expiry, the trading
+    /// schedule, the feed batches and the retransmissions
+    ///
+    /// Returns: the execution reports of the orders expired or traded meanwhile
+    pub fn run_timers(&mut self) -> Vec<ExecutionReport> {
+        // the primary sees to all of it, a backup catching up once taking over
+        if self.standby {
+            return vec![];
+        }
+        let mut ereports = vec![];
+        let markets = self.markets.clone();
+        let mut markets = markets.lock().unwrap();
+        // send snapshots around if needed
+        if self.last_snapshot_sent.elapsed() > SEND_SNAPSHOTS_EVERY {
+            info!(
+                shard = self.index,
+                markets = markets.len(),
+                "Sending snapshots"
+            );
+            timeit!(
+                send_snapshots,
+                markets.values().for_each(|m| {
+                    if m.publish_snapshot(self.snapshots.as_ref()).is_err() {
+                        error!("Error publishing instrument snapshot");
+                    }
+                })
+            );
+            self.last_snapshot_sent = Instant::now();
+        }
+        // and the book checksums, more often, as they are cheap
+        if self.last_checksum_sent.elapsed() > SEND_CHECKSUMS_EVERY {
+            timeit!(
+                send_checksums,
+                markets.values().for_each(|m| {
+                    if m.publish_checksum().is_err() {
+                        error!("Error publishing book checksum");
+                    }
+                })
+            );
+            self.last_checksum_sent = Instant::now();
+        }
+        // the session statistics of the markets that traded
+        if self.last_statistics_sent.elapsed() > SEND_STATISTICS_EVERY {
+            timeit!(
+                send_statistics,
+                markets
+                    .values()
+                    .filter(|m| m.get_statistics().trade_count > 0)
+                    .for_each(|m| {
+                        if m.publish_statistics().is_err() {
+                            error!("Error publishing the statistics");
+                        }
+                    })
+            );
+            self.last_statistics_sent = Instant::now();
+        }
+        // cancel the GoodTillDate orders that reached their expiry, end the
+        // volatility halts that cooled down and drop the expired contracts
+        if self.last_expiry_check.elapsed() > EXPIRE_ORDERS_EVERY {
+            let now = unix_now();
+            let expired: Vec<u64> = markets
+                .iter()
+                .filter(|(_, m)| m.get_instrument().read().unwrap().is_expired(now))
+                .map(|(id, _)| *id)
+                .collect();
+            for id in expired {
+                info!(shard = self.index, book_id = id, "Instrument expired");
+                let mut market = markets.remove(&id).expect("Expired an unknown market");
+                let (summary, mut cancelled) = processor::expire_market(&mut market);
+                self.eod_summaries.extend(summary);
+                ereports.append(&mut cancelled);
+            }
+            for market in markets.values_mut() {
+                market.resume_trading(now);
+                ereports.append(&mut timeit!(
+                    expire_orders,
+                    processor::expire_orders(market, now)
+                ));
+            }
+            self.last_expiry_check = Instant::now();
+        }
+        // move the instruments through their trading phases
+        if self.last_schedule_check.elapsed() > APPLY_SCHEDULE_EVERY {
+            let now = unix_now();
+            let instruments: Vec<(u64, u16)> = markets
+                .iter()
+                .map(|(id, m)| (*id, m.get_segment()))
+                .collect();
+            for (id, state) in self.schedule.transitions(instruments.into_iter(), now) {
+                let market = markets.get_mut(&id).expect("Scheduled an unknown market");
+                // the clearing keeps the closing prices, same as when it closes the market
+                if let Some(summary) = market.change_state(state) {
+                    self.eod_summaries.push(summary);
+                }
+                // the end of the open auction might have traded
+                ereports.append(&mut processor::passive_fill_reports(market));
+                ereports.append(&mut processor::closed_out_reports(market));
+            }
+            // once the instruments of the segments moved
+            for (segment, state) in self.schedule.segment_transitions(now) {
+                if publish_segment_state(&*self.feed, segment, state, StateChangeReason::Schedule)
+                    .is_err()
+                {
+                    error!(
+                        shard = self.index,
+                        segment, "Error publishing the segment state"
+                    );
+                }
+            }
+            self.last_schedule_check = Instant::now();
+        }
+        // the feed messages held back for too long
+        if let Some(max_delay) = self.batch_max_delay {
+            if self.feed.lock().unwrap().flush_expired(max_delay).is_err() {
+                error!(shard = self.index, "Error flushing the feed batch");
+            }
+        }
+        // the feed consumers asking for the packets they missed
+        if let Some(server) = self.recovery.as_mut() {
+            timeit!(recovery, server.poll());
+        }
+        // how far the feed got, for a restart to go on from there
+        self.journal_feed_sequence();
+        with_partition(ereports, self.partition_id)
+    }
+
+    /// Closes all the markets, the engine stopping. The day orders are
+    /// reported cancelled and the end of day summaries kept for the clearing,
+    /// as when the markets close on schedule
+    ///
+    /// Returns: the execution reports of the day orders
+    pub fn close_markets(&mut self) -> Vec<ExecutionReport> {
+        let mut ereports = vec![];
+        for market in self.markets.lock().unwrap().values_mut() {
+            let (summary, mut cancelled) = processor::shut_down_market(market);
+            self.eod_summaries.extend(summary);
+            ereports.append(&mut cancelled);
+        }
+        with_partition(ereports, self.partition_id)
+    }
+
+    /// Closes all the markets, see `close_markets`, then sends the feed
+    /// messages held back and journals how far the feed got
+    pub fn shut_down(&mut self) -> Vec<ExecutionReport> {
+        let ereports = self.close_markets();
+        if self.feed.lock().unwrap().flush().is_err() {
+            error!(shard = self.index, "Error flushing the feed batch");
+        }
+        self.journal_feed_sequence();
+        info!(shard = self.index, "Closed the markets");
+        ereports
+    }
+
+    /// Appends the feed sequence to the journal if it moved since the last time
+    fn journal_feed_sequence(&mut self) {
+        let seq = self.feed.lock().unwrap().get_sequence();
+        if let (Some(journal), true) = (&self.journal, seq != self.journaled_seq) {
+            let entry = JournalEntry::Feed {
+                shard: self.index as u8,
+                seq,
+            };
+            if let Err(e) = journal.lock().unwrap().append(entry) {
+                error!(shard = self.index, error = %e, "Error journaling the feed sequence");
+            }
+            self.journaled_seq = seq;
+        }
+    }
+
+    /// The trades of all the markets since the last call, for the clearing
+    pub fn take_trade_captures(&mut self) -> Vec<TradeCapture> {
+        self.markets
+            .lock()
+            .unwrap()
+            .values_mut()
+            .flat_map(|m| m.take_trade_captures())
+            .collect()
+    }
+
+    /// The end of day summaries of the markets closed since the last call
+    pub fn take_eod_summaries(&mut self) -> Vec<EodSummary> {
+        std::mem::take(&mut self.eod_summaries)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn with_partition(mut ereports: Vec<ExecutionReport>, partition_id: u8) -> Vec<ExecutionReport> {
+    for ereport in ereports.iter_mut() {
+        ereport.partition_id = partition_id;
+    }
+    ereports
+}
+
+/// Work handed to a shard by the main thread
+pub enum ShardCommand {
+    // an order message, for a book of the shard or for all of them
+    Order(MessageWrapper, u64),
+    Market(MarketUpdate),
+    // replayed from the journal: applied to the markets, but nothing goes out
+    Replayed(Box<ShardCommand>),
+    // for a snapshot of the engine, sent back as a ShardEvent::State
+    SaveState,
+    RestoreState(ShardState),
+    // the feed sequence reached by a previous run
+    ResumeFeed(u64),
+    // the primary is gone, see Shard::take_over
+    TakeOver,
+    // the settings of the markets were reloaded, see Shard::reconfigure
+    Reconfigure(VolatilityConfig, WithoutReference),
+    // the admin asked for the market of a book, see Shard::debug_snapshot.
+    // The answer goes back on the sender given
+    DebugSnapshot(u64, Sender<Option<DebugSnapshot>>),
+    // the engine is stopping, see Shard::shut_down. The shard thread stops
+    // once done, after a ShardEvent::Stopped
+    ShutDown,
+}
+
+/// Sent back by the shards, to be forwarded to the clearing, or for the
+/// snapshots of the engine
+#[derive(Debug)]
+pub enum ShardEvent {
+    TradeCapture(TradeCapture),
+    EodSummary(EodSummary),
+    // the state of the shard with the index given
+    State(usize, ShardState),
+    // the shard with the index given closed its markets and stopped
+    Stopped(usize),
+}
+
+/// Lets the main thread know there are events waiting
+pub type Waker = Arc<dyn Fn() + Send + Sync>;
+
+/// Routes the order messages and the market updates to the shards
+#[derive(Debug)]
+pub struct Dispatcher {
+    shards: Vec<Sender<ShardCommand>>,
+    // the commands are wrapped as Replayed
+    replaying: bool,
+}
+
+impl Dispatcher {
+    pub fn new(shards: Vec<Sender<ShardCommand>>) -> Self {
+        Self {
+            shards,
+            replaying: false,
+        }
+    }
+
+    /// While @replaying, what is dispatched comes from the journal and only
+    /// updates the markets
+    pub fn set_replaying(&mut self, replaying: bool) {
+        self.replaying = replaying;
+    }
+
+    fn send(&self, shard: usize, command: ShardCommand) -> Result<(), SendError<ShardCommand>> {
+        let command = match self.replaying {
+            true => ShardCommand::Replayed(Box::new(command)),
+            false => command,
+        };
+        self.shards[shard].send(command)
+    }
+
+    /// Hands @msg to the shard of @book_id, or to all of them if it acts on all the books
+    ///
+    /// Fails if a shard stopped
+    pub fn dispatch_order(
+        &self,
+        msg: MessageWrapper,
+        book_id: u64,
+    ) -> Result<(), SendError<ShardCommand>> {
+        if !acts_on_all_books(&msg) {
+            return self.send(
+                shard_of(book_id, self.shards.len()),
+                ShardCommand::Order(msg, book_id),
+            );
+        }
+        (0..self.shards.len())
+            .try_for_each(|shard| self.send(shard, ShardCommand::Order(msg.clone(), book_id)))
+    }
+
+    pub fn dispatch_market_update(
+        &self,
+        update: MarketUpdate,
+    ) -> Result<(), SendError<ShardCommand>> {
+        let book_id = match &update {
+            MarketUpdate::Instrument(instrument) => instrument.get_id(),
+            MarketUpdate::Exposure { book_id, .. } => *book_id,
+            MarketUpdate::Deleted(book_id) => *book_id,
+            MarketUpdate::TradeBust { book_id, .. } => *book_id,
+            // every shard has its part of the segment
+            MarketUpdate::Segment { .. } => {
+                return (0..self.shards.len())
+                    .try_for_each(|shard| self.send(shard, ShardCommand::Market(update.clone())));
+            }
+        };
+        self.send(
+            shard_of(book_id, self.shards.len()),
+            ShardCommand::Market(update),
+        )
+    }
+
+    /// Asks all the shards for their state, once done with what they were sent before
+    pub fn save_states(&self) -> Result<(), SendError<ShardCommand>> {
+        (0..self.shards.len()).try_for_each(|shard| self.send(shard, ShardCommand::SaveState))
+    }
+
+    /// Brings the shards back to @states, by shard index
+    pub fn restore_states(&self, states: Vec<ShardState>) -> Result<(), SendError<ShardCommand>> {
+        assert_eq!(self.shards.len(), states.len(), "One state per shard");
+        states
+            .into_iter()
+            .enumerate()
+            .try_for_each(|(shard, state)| self.send(shard, ShardCommand::RestoreState(state)))
+    }
+
+    /// Takes all the shards out of the standby
+    pub fn take_over(&self) -> Result<(), SendError<ShardCommand>> {
+        // never replayed, whatever the dispatcher is doing
+        self.shards
+            .iter()
+            .try_for_each(|shard| shard.send(ShardCommand::TakeOver))
+    }
+
+    /// Has all the shards apply the market settings reloaded, see Shard::reconfigure
+    pub fn reconfigure(
+        &self,
+        volatility: VolatilityConfig,
+        without_reference: WithoutReference,
+    ) -> Result<(), SendError<ShardCommand>> {
+        // not journaled, so never replayed either
+        self.shards.iter().try_for_each(|shard| {
+            shard.send(ShardCommand::Reconfigure(volatility, without_reference))
+        })
+    }
+
+    /// Has the shard of @book_id send the snapshot of its market on @reply,
+    /// once done with what it was sent before
+    pub fn debug_snapshot(
+        &self,
+        book_id: u64,
+        reply: Sender<Option<DebugSnapshot>>,
+    ) -> Result<(), SendError<ShardCommand>> {
+        // not journaled, so never replayed either
+        self.shards[shard_of(book_id, self.shards.len())]
+            .send(ShardCommand::DebugSnapshot(book_id, reply))
+    }
+
+    /// Has all the shards close their markets and stop, once done with what
+    /// they were sent before
+    pub fn shut_down(&self) -> Result<(), SendError<ShardCommand>> {
+        (0..self.shards.len()).try_for_each(|shard| self.send(shard, ShardCommand::ShutDown))
+    }
+
+    /// Moves the feed of @shard on to @seq, nothing if there is no such shard
+    pub fn resume_feed(&self, shard: usize, seq: u64) -> Result<(), SendError<ShardCommand>> {
+        match shard < self.shards.len() {
+            true => self.send(shard, ShardCommand::ResumeFeed(seq)),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Starts @count shards, each on a thread of its own, and returns the
+/// dispatcher feeding them
+///
+/// The shard n publishes its feed as configured in @config, moved to the
+/// shard with `FeedConfig::for_shard`, and hands out the order ids of
+/// @order_ids kept for it by `OrderIdGenerator::for_shard`. The events of
+/// the shards go to @events, @wake being called after sending some.
+pub fn spawn_shards(
+    count: usize,
+    config: &ShardConfig,
+    order_ids: OrderIdGenerator,
+    publisher: &ExecutionReportPublisher,
+    events: Sender<ShardEvent>,
+    wake: Waker,
+) -> io::Result<Dispatcher> {
+    let mut shards = vec![];
+    for index in 0..count {
+        let (commands, received) = mpsc::channel();
+        let config = ShardConfig {
+            feed: config.feed.for_shard(index),
+            index,
+            ..config.clone()
+        };
+        let shard = Shard::new(config, order_ids.clone().for_shard(index, count))?;
+        let publisher = publisher.try_clone()?;
+        let events = events.clone();
+        let wake = wake.clone();
+        thread::Builder::new()
+            .name(format!("shard-{index}"))
+            .spawn(move || run_shard(shard, received, publisher, events, wake))?;
+        shards.push(commands);
+    }
+    Ok(Dispatcher::new(shards))
+}
+
+/// The loop of a shard thread, until it is shut down or the dispatcher or
+/// the receiver of the events go away
+fn run_shard(
+    mut shard: Shard,
+    commands: Receiver<ShardCommand>,
+    mut publisher: ExecutionReportPublisher,
+    events: Sender<ShardEvent>,
+    wake: Waker,
+) {
+    let mut stopping = false;
+    while !stopping {
+        let mut ereports = match commands.recv_timeout(shard.poll_timeout()) {
+            Ok(ShardCommand::Replayed(command)) => {
+                shard.replay(*command);
+                continue;
+            }
+            Ok(ShardCommand::ShutDown) => {
+                stopping = true;
+                shard.shut_down()
+            }
+            Ok(command) => shard.apply(command),
+            Err(RecvTimeoutError::Timeout) => vec![],
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        ereports.append(&mut shard.run_timers());
+        if let Err(e) = publisher.publish(&ereports) {
+            error!(shard = shard.index, error = %e, "Error publishing the execution reports");
+        }
+        let index = shard.index;
+        let to_main: Vec<ShardEvent> = shard
+            .take_trade_captures()
+            .into_iter()
+            .map(ShardEvent::TradeCapture)
+            .chain(
+                shard
+                    .take_eod_summaries()
+                    .into_iter()
+                    .map(ShardEvent::EodSummary),
+            )
+            .chain(
+                shard
+                    .take_states()
+                    .into_iter()
+                    .map(|state| ShardEvent::State(index, state)),
+            )
+            .chain(stopping.then_some(ShardEvent::Stopped(index)))
+            .collect();
+        if to_main.is_empty() {
+            continue;
+        }
+        for event in to_main {
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+        wake();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::UdpSocket,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        time::Duration,
+    };
+
+    use clearing_connection::genericclearingprotocol::MarketUpdate;
+    use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
+    use market::{
+        bands::WithoutReference, orderid::OrderIdGenerator, volatility::VolatilityConfig,
+    };
+    use oep::{
+        decoder::Decoder,
+        execution_report::{ExecutionReport, RejectReason, EXECUTIONREPORT_SIZE},
+        header::OEP_HEADER_SIZE,
+        neworder::NewOrder,
+        sessioninfo::SessionInfo,
+    };
+    use order::{OrderState, OrderType, Side};
+    use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+    use utils::network::MulticastConfig;
+
+    use super::{
+        shard_of, spawn_shards, unix_now, Dispatcher, ExecutionReportPublisher, FeedConfig,
+        FeedFormat, Shard, ShardCommand, ShardConfig, ShardEvent,
+    };
+    use crate::{processor::MessageWrapper, schedule::Schedule};
+
+    const BOOK_ID: u64 = 5;
+    const PARTITION_ID: u8 = 3;
+
+    // something listening on the feed ports, for the sends to go through
+    fn feed_sinks() -> (UdpSocket, UdpSocket) {
+        (
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        )
+    }
+
+    fn config(feed: &UdpSocket, snapshots: &UdpSocket) -> ShardConfig {
+        ShardConfig {
+            feed: FeedConfig {
+                format: FeedFormat::Mbo,
+                disseminator_group: String::from("127.0.0.1"),
+                disseminator_port: feed.local_addr().unwrap().port(),
+                disseminator_b_group: String::from("127.0.0.1"),
+                disseminator_b_port: None,
+                snapshot_group: String::from("127.0.0.1"),
+                snapshot_port: snapshots.local_addr().unwrap().port(),
+                mtu: None,
+                batch_max_delay: Duration::from_millis(1),
+                recovery_address: String::from("127.0.0.1"),
+                recovery_port: None,
+                recovery_cache_size: 10,
+                multicast: MulticastConfig::default(),
+            },
+            volatility: VolatilityConfig::default(),
+            without_reference: WithoutReference::default(),
+            partition_id: PARTITION_ID,
+            schedule: Schedule::default(),
+            index: 0,
+            journal: None,
+            standby: false,
+        }
+    }
+
+    fn instrument(book_id: u64, state: InstrumentState) -> MarketUpdate {
+        MarketUpdate::Instrument(Instrument::new(
+            book_id,
+            "TEST",
+            InstrumentType::Share,
+            state,
+            10,
+            20,
+        ))
+    }
+
+    fn order(book_id: u64, participant: u64, side: Side) -> MessageWrapper {
+        MessageWrapper::NewOrder(NewOrder {
+            client_order_id: 7000,
+            participant,
+            book_id,
+            quantity: 200,
+            price: 100,
+            order_type: OrderType::Day.into(),
+            side: side.into(),
+            gateway_id: 15,
+            session_id: 2500,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        })
+    }
+
+    #[test]
+    fn books_spread_over_the_shards() {
+        let mut counts = [0; 4];
+        for book_id in 1..=1000 {
+            counts[shard_of(book_id, 4)] += 1;
+        }
+        assert!(counts.iter().all(|&c| c > 200 && c < 300), "{counts:?}");
+        assert_eq!(shard_of(BOOK_ID, 4), shard_of(BOOK_ID, 4));
+        assert_eq!(0, shard_of(BOOK_ID, 1));
+    }
+
+    #[test]
+    fn feed_per_shard() {
+        let (feed, snapshots) = feed_sinks();
+        let mut config = config(&feed, &snapshots).feed;
+        config.recovery_port = Some(25001);
+        let shard = config.for_shard(2);
+        assert_eq!(config.disseminator_port + 2, shard.disseminator_port);
+        assert_eq!(config.snapshot_port + 2, shard.snapshot_port);
+        assert_eq!(Some(25003), shard.recovery_port);
+        assert_eq!(config.disseminator_group, shard.disseminator_group);
+    }
+
+    #[test]
+    fn itch_feed() {
+        assert_eq!(FeedFormat::Itch, "ITCH".parse::<FeedFormat>().unwrap());
+        assert!("fast".parse::<FeedFormat>().is_err());
+
+        let (feed, snapshots) = feed_sinks();
+        feed.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut config = config(&feed, &snapshots);
+        config.feed.format = FeedFormat::Itch;
+        let mut target = Shard::new(config, OrderIdGenerator::new(0)).unwrap();
+        target.apply(ShardCommand::Market(instrument(
+            BOOK_ID,
+            InstrumentState::Trading,
+        )));
+        target.apply(ShardCommand::Order(order(BOOK_ID, 11, Side::Bid), BOOK_ID));
+
+        // the order added to the book, its fields big endian
+        let mut buffer = [0; 1500];
+        let r = feed.recv(&mut buffer).unwrap();
+        assert_eq!(8 + 42, r);
+        assert_eq!(b'A', buffer[8]);
+        assert_eq!(BOOK_ID.to_be_bytes(), buffer[25..33]);
+        assert_eq!(b'B', buffer[33]);
+        assert_eq!(200u64.to_be_bytes(), buffer[34..42]);
+    }
+
+    #[test]
+    fn dispatch() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| mpsc::channel()).unzip();
+        let mut target = Dispatcher::new(senders);
+        let received = || {
+            receivers
+                .iter()
+                .map(|r| r.try_iter().count())
+                .collect::<Vec<_>>()
+        };
+
+        let mut expected = vec![0; 3];
+        expected[shard_of(BOOK_ID, 3)] = 2;
+        target
+            .dispatch_order(order(BOOK_ID, 11, Side::Bid), BOOK_ID)
+            .unwrap();
+        target
+            .dispatch_market_update(instrument(BOOK_ID, InstrumentState::Trading))
+            .unwrap();
+        assert_eq!(expected, received());
+
+        // the session might have orders on any of the shards
+        let kill = MessageWrapper::KillSession(SessionInfo::new(11, 2500, 15));
+        target.dispatch_order(kill, 0).unwrap();
+        assert_eq!(vec![1; 3], received());
+        // so might the instruments of a segment
+        target
+            .dispatch_market_update(MarketUpdate::Segment {
+                segment: 2,
+                state: InstrumentState::Halted,
+            })
+            .unwrap();
+        assert_eq!(vec![1; 3], received());
+
+        // read back from the journal
+        target.set_replaying(true);
+        target
+            .dispatch_order(order(BOOK_ID, 11, Side::Bid), BOOK_ID)
+            .unwrap();
+        assert!(matches!(
+            receivers[shard_of(BOOK_ID, 3)].try_recv(),
+            Ok(ShardCommand::Replayed(_))
+        ));
+    }
+
+    #[test]
+    fn replay() {
+        let (feed, snapshots) = feed_sinks();
+        let mut target = Shard::new(config(&feed, &snapshots), OrderIdGenerator::new(0)).unwrap();
+        target.replay(ShardCommand::Market(instrument(
+            BOOK_ID,
+            InstrumentState::Trading,
+        )));
+        target.replay(ShardCommand::Order(order(BOOK_ID, 11, Side::Bid), BOOK_ID));
+        target.replay(ShardCommand::Order(order(BOOK_ID, 12, Side::Ask), BOOK_ID));
+        // the trade went to the clearing the first time, and so did the feed
+        assert!(target.take_trade_captures().is_empty());
+        assert_eq!(0, target.feed().lock().unwrap().get_sequence());
+        // which goes on where it was
+        target.replay(ShardCommand::ResumeFeed(40));
+        target.replay(ShardCommand::ResumeFeed(30));
+        assert_eq!(40, target.feed().lock().unwrap().get_sequence());
+
+        // the book is back where it was
+        target.replay(ShardCommand::Order(order(BOOK_ID, 11, Side::Bid), BOOK_ID));
+        let ereports = target.apply(ShardCommand::Order(order(BOOK_ID, 12, Side::Ask), BOOK_ID));
+        assert_eq!(2, ereports.len());
+        assert_eq!(1, target.take_trade_captures().len());
+        assert!(target.feed().lock().unwrap().get_sequence() > 40);
+    }
+
+    #[test]
+    fn standby() {
+        let (feed, snapshots) = feed_sinks();
+        let mut target = Shard::new(
+            ShardConfig {
+                standby: true,
+                ..config(&feed, &snapshots)
+            },
+            OrderIdGenerator::new(0),
+        )
+        .unwrap();
+        let mut expiring = order(BOOK_ID, 11, Side::Bid);
+        if let MessageWrapper::NewOrder(o) = &mut expiring {
+            o.order_type = OrderType::GoodTillDate.into();
+            o.expiry = 1;
+        }
+        target.replay(ShardCommand::Market(instrument(
+            BOOK_ID,
+            InstrumentState::Trading,
+        )));
+        target.replay(ShardCommand::Order(expiring, BOOK_ID));
+        // the primary expires the order, not the backup
+        target.last_expiry_check -= Duration::from_secs(10);
+        assert!(target.run_timers().is_empty());
+        assert_eq!(1, target.save_state().markets.len());
+
+        target.apply(ShardCommand::TakeOver);
+        let ereports = target.run_timers();
+        assert_eq!(1, ereports.len());
+        assert_eq!(OrderState::Cancelled, OrderState::from(ereports[0].state));
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let (feed, snapshots) = feed_sinks();
+        let mut target = Shard::new(config(&feed, &snapshots), OrderIdGenerator::new(7)).unwrap();
+        target.update_market(instrument(BOOK_ID, InstrumentState::Trading));
+        let resting = target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID)[0].order_id;
+        assert!(target.take_states().is_empty());
+        target.apply(ShardCommand::SaveState);
+        let state = target.take_states().pop().unwrap();
+        assert_eq!(1, state.markets.len());
+        assert_eq!(7, state.order_id_epoch);
+        assert_eq!(target.feed().lock().unwrap().get_sequence(), state.feed_seq);
+
+        let mut restored = Shard::new(config(&feed, &snapshots), OrderIdGenerator::new(8)).unwrap();
+        restored.apply(ShardCommand::RestoreState(state.clone()));
+        assert_eq!(state, restored.save_state());
+        // the resting order is back, and the order ids go on
+        let ereports = restored.process(order(BOOK_ID, 12, Side::Ask), BOOK_ID);
+        assert_eq!(2, ereports.len());
+        assert!(ereports.iter().any(|e| e.order_id == resting));
+        assert!(ereports.iter().any(|e| e.order_id == resting + 1));
+    }
+
+    #[test]
+    fn markets_of_a_shard() {
+        let (feed, snapshots) = feed_sinks();
+        let mut target = Shard::new(config(&feed, &snapshots), OrderIdGenerator::new(0)).unwrap();
+        // nothing to trade yet, the order is rejected rather than dropped
+        let ereports = target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+        assert_eq!(1, ereports.len());
+        assert_eq!(OrderState::Rejected, OrderState::from(ereports[0].state));
+        assert_eq!(RejectReason::UnknownBook, ereports[0].get_reject_reason());
+        assert_eq!(PARTITION_ID, ereports[0].partition_id);
+
+        assert!(target
+            .update_market(instrument(BOOK_ID, InstrumentState::Trading))
+            .is_empty());
+        let ereports = target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+        assert_eq!(1, ereports.len());
+        assert_eq!(PARTITION_ID, ereports[0].partition_id);
+        // the aggressor and the resting order
+        let ereports = target.process(order(BOOK_ID, 12, Side::Ask), BOOK_ID);
+        assert_eq!(2, ereports.len());
+        assert_eq!(OrderState::Traded, OrderState::from(ereports[0].state));
+        assert!(ereports.iter().all(|e| e.partition_id == PARTITION_ID));
+        assert_eq!(1, target.take_trade_captures().len());
+        assert!(target.take_trade_captures().is_empty());
+        let snapshot = target.debug_snapshot(BOOK_ID).unwrap();
+        assert_eq!(1, snapshot.trade_id);
+        assert!(snapshot.bids.is_empty() && snapshot.asks.is_empty());
+        assert!(target.debug_snapshot(BOOK_ID + 1).is_none());
+
+        target.update_market(MarketUpdate::Exposure {
+            participant: 11,
+            book_id: BOOK_ID,
+            blocked_side: Some(Side::Bid),
+        });
+        let ereports = target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+        assert_eq!(RejectReason::ExposureLimit, ereports[0].get_reject_reason());
+
+        // closing the market reports its day to the clearing, and cancels the
+        // day orders left
+        let resting = target.process(order(BOOK_ID, 12, Side::Ask), BOOK_ID)[0].order_id;
+        let ereports = target.update_market(instrument(BOOK_ID, InstrumentState::Closed));
+        assert_eq!(1, ereports.len());
+        assert_eq!(resting, { ereports[0].order_id });
+        assert_eq!(OrderState::Cancelled, OrderState::from(ereports[0].state));
+        assert_eq!(PARTITION_ID, ereports[0].partition_id);
+        let summaries = target.take_eod_summaries();
+        assert_eq!(1, summaries.len());
+        assert_eq!(BOOK_ID, { summaries[0].book_id });
+        assert_eq!(1, { summaries[0].trade_count });
+    }
+
+    #[test]
+    fn segment_state() {
+        let (feed, snapshots) = feed_sinks();
+        let mut target = Shard::new(config(&feed, &snapshots), OrderIdGenerator::new(0)).unwrap();
+        for (book_id, segment) in [(BOOK_ID, 2), (BOOK_ID + 1, 1)] {
+            let mut instrument = Instrument::new_fast(book_id, InstrumentType::Share);
+            instrument.set_state(InstrumentState::Trading);
+            instrument.set_segment(segment);
+            target.update_market(MarketUpdate::Instrument(instrument));
+        }
+        target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+
+        // the day order of the segment goes with the close
+        let ereports = target.update_market(MarketUpdate::Segment {
+            segment: 2,
+            state: InstrumentState::Closed,
+        });
+        assert_eq!(1, ereports.len());
+        assert_eq!(OrderState::Cancelled, OrderState::from(ereports[0].state));
+        assert_eq!(PARTITION_ID, ereports[0].partition_id);
+        let summaries = target.take_eod_summaries();
+        assert_eq!(1, summaries.len());
+        assert_eq!(BOOK_ID, { summaries[0].book_id });
+        // the other segment still trades
+        let ereports = target.process(order(BOOK_ID + 1, 11, Side::Bid), BOOK_ID + 1);
+        assert_eq!(OrderState::Inserted, OrderState::from(ereports[0].state));
+    }
+
+    #[test]
+    fn deleted_market() {
+        let (feed, snapshots) = feed_sinks();
+        let mut target = Shard::new(config(&feed, &snapshots), OrderIdGenerator::new(0)).unwrap();
+        target.update_market(instrument(BOOK_ID, InstrumentState::Trading));
+        let mut persistent = order(BOOK_ID, 11, Side::Bid);
+        if let MessageWrapper::NewOrder(o) = &mut persistent {
+            o.order_type = OrderType::GoodTillCancel.into();
+        }
+        target.process(persistent, BOOK_ID);
+        target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+
+        // the day order goes with the close, the persistent one with the market
+        let ereports = target.update_market(MarketUpdate::Deleted(BOOK_ID));
+        assert_eq!(2, ereports.len());
+        assert!(ereports
+            .iter()
+            .all(|e| OrderState::from(e.state) == OrderState::Cancelled));
+        assert!(ereports.iter().all(|e| e.partition_id == PARTITION_ID));
+        assert_eq!(1, target.take_eod_summaries().len());
+        let ereports = target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+        assert_eq!(RejectReason::UnknownBook, ereports[0].get_reject_reason());
+        assert!(target
+            .update_market(MarketUpdate::Deleted(BOOK_ID))
+            .is_empty());
+    }
+
+    #[test]
+    fn reconfigured_markets() {
+        let (feed, snapshots) = feed_sinks();
+        let mut target = Shard::new(config(&feed, &snapshots), OrderIdGenerator::new(0)).unwrap();
+        target.update_market(instrument(BOOK_ID, InstrumentState::Trading));
+        let state = |ereports: Vec<ExecutionReport>| OrderState::from(ereports[0].state);
+        // no reference price yet, accepted by default
+        assert_eq!(
+            OrderState::Inserted,
+            state(target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID))
+        );
+
+        target.apply(ShardCommand::Reconfigure(
+            VolatilityConfig::default(),
+            WithoutReference::Reject,
+        ));
+        assert_eq!(
+            OrderState::Rejected,
+            state(target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID))
+        );
+        // the markets opened afterwards as well
+        target.update_market(instrument(BOOK_ID + 1, InstrumentState::Trading));
+        assert_eq!(
+            OrderState::Rejected,
+            state(target.process(order(BOOK_ID + 1, 11, Side::Bid), BOOK_ID + 1))
+        );
+    }
+
+    #[test]
+    fn expired_market() {
+        let (feed, snapshots) = feed_sinks();
+        let mut target = Shard::new(config(&feed, &snapshots), OrderIdGenerator::new(0)).unwrap();
+        let mut future = Instrument::new(
+            BOOK_ID,
+            "FUT",
+            InstrumentType::Future,
+            InstrumentState::Trading,
+            10,
+            20,
+        );
+        future.set_expiry(unix_now() + 3600);
+        target.update_market(MarketUpdate::Instrument(future.clone()));
+        target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+        target.last_expiry_check -= Duration::from_secs(10);
+        assert!(target.run_timers().is_empty());
+
+        // the expiry brought forward by the clearing
+        future.set_expiry(1);
+        target.update_market(MarketUpdate::Instrument(future.clone()));
+        target.last_expiry_check -= Duration::from_secs(10);
+        let ereports = target.run_timers();
+        assert_eq!(1, ereports.len());
+        assert_eq!(OrderState::Cancelled, OrderState::from(ereports[0].state));
+        assert_eq!(1, target.take_eod_summaries().len());
+        let ereports = target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+        assert_eq!(RejectReason::UnknownBook, ereports[0].get_reject_reason());
+
+        // sent again, e.g. after a reconnect
+        target.update_market(MarketUpdate::Instrument(future));
+        let ereports = target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+        assert_eq!(RejectReason::UnknownBook, ereports[0].get_reject_reason());
+    }
+
+    #[test]
+    fn shards_on_their_threads() {
+        let (feed, snapshots) = feed_sinks();
+        let gateway = UdpSocket::bind("127.0.0.1:0").unwrap();
+        gateway
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        socket
+            .connect(&SockAddr::from(gateway.local_addr().unwrap()))
+            .unwrap();
+        let (events_sender, events) = mpsc::channel();
+        let woken = Arc::new(AtomicUsize::new(0));
+        let wake = woken.clone();
+
+        let target = spawn_shards(
+            2,
+            &config(&feed, &snapshots),
+            OrderIdGenerator::new(0),
+            &ExecutionReportPublisher::new(socket),
+            events_sender,
+            Arc::new(move || {
+                wake.fetch_add(1, Ordering::Relaxed);
+            }),
+        )
+        .unwrap();
+        // one book per shard
+        let other_book = (BOOK_ID + 1..)
+            .find(|&b| shard_of(b, 2) != shard_of(BOOK_ID, 2))
+            .unwrap();
+        for book_id in [BOOK_ID, other_book] {
+            target
+                .dispatch_market_update(instrument(book_id, InstrumentState::Trading))
+                .unwrap();
+            target
+                .dispatch_order(order(book_id, 11, Side::Bid), book_id)
+                .unwrap();
+        }
+        target
+            .dispatch_order(order(BOOK_ID, 12, Side::Ask), BOOK_ID)
+            .unwrap();
+
+        let mut buffer = [0; 1024];
+        let mut ereports = vec![];
+        for _ in 0..4 {
+            let r = gateway.recv(&mut buffer).unwrap();
+            assert_eq!(OEP_HEADER_SIZE + EXECUTIONREPORT_SIZE, r);
+            ereports.push(
+                ExecutionReport::decode(buffer[OEP_HEADER_SIZE..r].try_into().unwrap()).unwrap(),
+            );
+        }
+        // each shard hands out its own order ids
+        let mut order_ids: Vec<u64> = ereports.iter().map(|e| e.order_id).collect();
+        order_ids.sort();
+        order_ids.dedup();
+        assert_eq!(3, order_ids.len());
+
+        match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            ShardEvent::TradeCapture(capture) => assert_eq!(BOOK_ID, { capture.book_id }),
+            event => panic!("Unexpected {event:?}"),
+        }
+        assert!(woken.load(Ordering::Relaxed) > 0);
+
+        // asked to the shard of the book
+        let (reply, snapshot) = mpsc::channel();
+        target.debug_snapshot(other_book, reply).unwrap();
+        let snapshot = snapshot
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert_eq!(other_book, snapshot.book_id);
+        assert_eq!(1, snapshot.bids.len());
+
+        // the bid left on the other book is cancelled, each shard closing its market
+        target.shut_down().unwrap();
+        let r = gateway.recv(&mut buffer).unwrap();
+        let ereport =
+            ExecutionReport::decode(buffer[OEP_HEADER_SIZE..r].try_into().unwrap()).unwrap();
+        assert_eq!(OrderState::Cancelled, OrderState::from(ereport.state));
+        assert_eq!(other_book, ereport.get_book());
+        let (mut summaries, mut stopped) = (0, vec![]);
+        while stopped.len() < 2 {
+            match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+                ShardEvent::EodSummary(_) => summaries += 1,
+                ShardEvent::Stopped(index) => stopped.push(index),
+                event => panic!("Unexpected {event:?}"),
+            }
+        }
+        stopped.sort();
+        assert_eq!(vec![0, 1], stopped);
+        assert_eq!(2, summaries);
+        // the threads are gone
+        assert!(target.shut_down().is_err());
+    }
+
+    #[test]
+    fn shut_down() {
+        let (feed, snapshots) = feed_sinks();
+        let mut target = Shard::new(config(&feed, &snapshots), OrderIdGenerator::new(0)).unwrap();
+        target.update_market(instrument(BOOK_ID, InstrumentState::Trading));
+        target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+
+        let ereports = target.shut_down();
+        assert_eq!(1, ereports.len());
+        assert_eq!(OrderState::Cancelled, OrderState::from(ereports[0].state));
+        assert_eq!(PARTITION_ID, ereports[0].partition_id);
+        assert_eq!(1, target.take_eod_summaries().len());
+        // closed already
+        assert!(target.shut_down().is_empty());
+        assert!(target.take_eod_summaries().is_empty());
+
+        // replayed, the day orders are gone all the same, nothing going out
+        let mut target = Shard::new(config(&feed, &snapshots), OrderIdGenerator::new(0)).unwrap();
+        target.update_market(instrument(BOOK_ID, InstrumentState::Trading));
+        target.process(order(BOOK_ID, 11, Side::Bid), BOOK_ID);
+        target.replay(ShardCommand::ShutDown);
+        assert!(target.take_eod_summaries().is_empty());
+        target.update_market(instrument(BOOK_ID, InstrumentState::Trading));
+        // nothing to trade against
+        let ereports = target.process(order(BOOK_ID, 12, Side::Ask), BOOK_ID);
+        assert_eq!(1, ereports.len());
+        assert_eq!(OrderState::Inserted, OrderState::from(ereports[0].state));
+    }
+}
@@ -0,0 +1,236 @@
+//! Snapshots of the state of the engine, for a warm restart
+//!
+//! Every now and then, the engine saves the state of all its markets, shard by
+//! shard, along with the number of journal records it reflects. On start, the
+//! markets are restored from the snapshot and only the journal records after
+//! it are replayed. The file is written as
+//!
+//! ```text
+//! | CRC-32 (4) | Journal records (8) | Shard count (2) | then for each shard: | Shard state (var) |
+//! ```
+//!
+//! the CRC covering the bytes after it, and a shard state being
+//!
+//! ```text
+//! | Order id epoch (4) | Order id sequence (8) | Feed sequence (8) | Market count (4) |
+//! | then for each market, by book id: | Length (4) | Market state (var) |
+//! ```
+//!
+//! the market state being the one of `Market::encode_state`.
+
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use crate::journal::crc32;
+
+/// What a shard needs to go on where it was
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardState {
+    // where the order ids of the shard got, see OrderIdGenerator::resume
+    pub order_id_epoch: u32,
+    pub order_id_seq: u64,
+    // sequence of the next feed datagram
+    pub feed_seq: u64,
+    // the encoded markets, see Market::encode_state
+    pub markets: Vec<Vec<u8>>,
+}
+
+impl ShardState {
+    fn encode(&self) -> Vec<u8> {
+        let mut r = self.order_id_epoch.to_le_bytes().to_vec();
+        r.extend_from_slice(&self.order_id_seq.to_le_bytes());
+        r.extend_from_slice(&self.feed_seq.to_le_bytes());
+        r.extend_from_slice(&(self.markets.len() as u32).to_le_bytes());
+        for market in self.markets.iter() {
+            r.extend_from_slice(&(market.len() as u32).to_le_bytes());
+            r.extend_from_slice(market);
+        }
+        r
+    }
+
+    // the state at the start of @buf, along with its size
+    fn decode(buf: &[u8]) -> Option<(Self, usize)> {
+        let header = buf.get(0..24)?;
+        let count = u32::from_le_bytes(header[20..24].try_into().unwrap());
+        let mut processed = 24;
+        let mut markets = vec![];
+        for _ in 0..count {
+            let len =
+                u32::from_le_bytes(buf.get(processed..processed + 4)?.try_into().unwrap()) as usize;
+            processed += 4;
+            markets.push(buf.get(processed..processed.checked_add(len)?)?.to_vec());
+            processed += len;
+        }
+        Some((
+            Self {
+                order_id_epoch: u32::from_le_bytes(header[0..4].try_into().unwrap()),
+                order_id_seq: u64::from_le_bytes(header[4..12].try_into().unwrap()),
+                feed_seq: u64::from_le_bytes(header[12..20].try_into().unwrap()),
+                markets,
+            },
+            processed,
+        ))
+    }
+}
+
+/// The state of all the shards of the engine
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineSnapshot {
+    // the journal records already applied to the markets
+    pub journal_records: u64,
+    // by shard index
+    pub shards: Vec<ShardState>,
+}
+
+impl EngineSnapshot {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = self.journal_records.to_le_bytes().to_vec();
+        body.extend_from_slice(&(self.shards.len() as u16).to_le_bytes());
+        for shard in self.shards.iter() {
+            body.append(&mut shard.encode());
+        }
+        [crc32(&body).to_le_bytes().as_slice(), &body].concat()
+    }
+
+    /// Returns: None if @buf is damaged
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let crc = u32::from_le_bytes(buf.get(0..4)?.try_into().unwrap());
+        let body = &buf[4..];
+        if crc32(body) != crc {
+            return None;
+        }
+        let journal_records = u64::from_le_bytes(body.get(0..8)?.try_into().unwrap());
+        let count = u16::from_le_bytes(body.get(8..10)?.try_into().unwrap());
+        let mut processed = 10;
+        let mut shards = vec![];
+        for _ in 0..count {
+            let (shard, len) = ShardState::decode(&body[processed..])?;
+            shards.push(shard);
+            processed += len;
+        }
+        match processed == body.len() {
+            true => Some(Self {
+                journal_records,
+                shards,
+            }),
+            false => None,
+        }
+    }
+
+    /// Writes the snapshot to @path, replacing the previous one only once
+    /// written entirely
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, self.encode())?;
+        fs::rename(&temporary, path)
+    }
+
+    /// The snapshot saved at @path, None if there is none
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        let buf = match fs::read(path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match Self::decode(&buf) {
+            Some(snapshot) => Ok(Some(snapshot)),
+            None => Err(io::Error::new(ErrorKind::InvalidData, "Damaged snapshot")),
+        }
+    }
+}
+
+/// A snapshot being put together out of the states sent by the shards
+#[derive(Debug)]
+pub struct PendingSnapshot {
+    journal_records: u64,
+    shards: Vec<Option<ShardState>>,
+}
+
+impl PendingSnapshot {
+    /// A snapshot of @shards shards, reflecting the first @journal_records records
+    pub fn new(journal_records: u64, shards: usize) -> Self {
+        Self {
+            journal_records,
+            shards: vec![None; shards],
+        }
+    }
+
+    /// Adds the @state of @shard
+    ///
+    /// Returns: the snapshot, once all the shards sent their state
+    pub fn add(&mut self, shard: usize, state: ShardState) -> Option<EngineSnapshot> {
+        *self.shards.get_mut(shard)? = Some(state);
+        if self.shards.iter().any(Option::is_none) {
+            return None;
+        }
+        Some(EngineSnapshot {
+            journal_records: self.journal_records,
+            shards: self.shards.drain(..).flatten().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{EngineSnapshot, PendingSnapshot, ShardState};
+
+    fn shard(feed_seq: u64) -> ShardState {
+        ShardState {
+            order_id_epoch: 7,
+            order_id_seq: 30,
+            feed_seq,
+            markets: vec![vec![1, 2, 3], vec![], vec![4]],
+        }
+    }
+
+    #[test]
+    fn encode_decode() {
+        let target = EngineSnapshot {
+            journal_records: 1000,
+            shards: vec![shard(10), shard(20)],
+        };
+        let encoded = target.encode();
+        assert_eq!(Some(target), EngineSnapshot::decode(&encoded));
+
+        // damaged
+        let mut damaged = encoded.clone();
+        damaged[20] ^= 1;
+        assert!(EngineSnapshot::decode(&damaged).is_none());
+        assert!(EngineSnapshot::decode(&encoded[..encoded.len() - 1]).is_none());
+        assert!(EngineSnapshot::decode(&[]).is_none());
+    }
+
+    #[test]
+    fn collect_the_shards() {
+        let mut target = PendingSnapshot::new(1000, 2);
+        assert!(target.add(1, shard(20)).is_none());
+        assert!(target.add(2, shard(30)).is_none());
+        let snapshot = target.add(0, shard(10)).unwrap();
+        assert_eq!(1000, snapshot.journal_records);
+        assert_eq!(vec![shard(10), shard(20)], snapshot.shards);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("snapshot_test_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(None, EngineSnapshot::load(&path).unwrap());
+
+        let target = EngineSnapshot {
+            journal_records: 1000,
+            shards: vec![shard(10)],
+        };
+        target.save(&path).unwrap();
+        assert_eq!(Some(target), EngineSnapshot::load(&path).unwrap());
+
+        fs::write(&path, [1, 2, 3]).unwrap();
+        assert!(EngineSnapshot::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
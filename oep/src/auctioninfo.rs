@@ -0,0 +1,64 @@
+use std::error::Error;
+
+use crate::decoder::{Decoder, FieldReader, FieldWriter};
+
+/// Indicative (or, once uncrossed, final) price and volume of an auction,
+/// published on the feed while the instrument is in auction
+/// Not a real OEP message either, just sharing the encoding
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct AuctionInfo {
+    pub book_id: u64,
+    // equilibrium price, 0 if the book is not crossed
+    pub price: u64,
+    // volume that would be executed at the equilibrium price
+    pub volume: u64,
+}
+
+pub const AUCTIONINFO_SIZE: usize = std::mem::size_of::<AuctionInfo>();
+
+impl Decoder<AUCTIONINFO_SIZE> for AuctionInfo {
+    fn encode(self) -> [u8; AUCTIONINFO_SIZE] {
+        FieldWriter::default()
+            .put(self.book_id)
+            .put(self.price)
+            .put(self.volume)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; AUCTIONINFO_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            book_id: reader.get()?,
+            price: reader.get()?,
+            volume: reader.get()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = AuctionInfo {
+            book_id: 0x0102030405060708,
+            price: 1000,
+            volume: 300,
+        };
+
+        let encoded = original.encode();
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1], encoded[0..8]);
+        let decoded = AuctionInfo::decode(encoded).unwrap();
+
+        assert_eq!({ original.book_id }, { decoded.book_id });
+        assert_eq!({ original.price }, { decoded.price });
+        assert_eq!({ original.volume }, { decoded.volume });
+    }
+
+    #[test]
+    fn test_auctioninfo_size() {
+        assert_eq!(24, AUCTIONINFO_SIZE);
+    }
+}
@@ -1,7 +1,7 @@
 use std::error::Error;
 
 use crate::{
-    decoder::Decoder,
+    decoder::{Decoder, FieldReader, FieldWriter},
     oep_message::{MsgType, OepMessage},
 };
 
@@ -14,6 +14,9 @@ pub struct Cancel {
     pub side: u8,
     pub gateway_id: u8,
     pub session_id: u32,
+    // the client order id of the order, used instead of order_id when that is 0.
+    // Since version 6
+    pub orig_client_order_id: u64,
 }
 
 impl Cancel {
@@ -23,14 +26,33 @@ impl Cancel {
 }
 
 pub const CANCEL_SIZE: usize = std::mem::size_of::<Cancel>();
+// without the orig_client_order_id
+pub const CANCEL_V5_SIZE: usize = CANCEL_SIZE - 8;
 
 impl Decoder<CANCEL_SIZE> for Cancel {
     fn encode(self) -> [u8; CANCEL_SIZE] {
-        unsafe { std::mem::transmute::<Self, [u8; CANCEL_SIZE]>(self) }
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.order_id)
+            .put(self.book_id)
+            .put(self.side)
+            .put(self.gateway_id)
+            .put(self.session_id)
+            .put(self.orig_client_order_id)
+            .finish()
     }
 
     fn decode(buffer: [u8; CANCEL_SIZE]) -> Result<Self, Box<dyn Error>> {
-        unsafe { Ok(std::mem::transmute::<[u8; CANCEL_SIZE], Self>(buffer).try_into()?) }
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            order_id: reader.get()?,
+            book_id: reader.get()?,
+            side: reader.get()?,
+            gateway_id: reader.get()?,
+            session_id: reader.get()?,
+            orig_client_order_id: reader.get()?,
+        })
     }
 }
 
@@ -68,6 +90,7 @@ mod tests {
             side: 1,
             gateway_id: 5,
             session_id: 987654,
+            orig_client_order_id: 77,
         };
 
         let encoded = cancel.encode();
@@ -79,6 +102,7 @@ mod tests {
             1, // side
             5, // gateway_id
             6, 18, 15, 0, // session_id (987654)
+            77, 0, 0, 0, 0, 0, 0, 0, // orig_client_order_id (77)
         ];
 
         assert_eq!(encoded, expected);
@@ -93,16 +117,18 @@ mod tests {
             1, // side
             5, // gateway_id
             6, 18, 15, 0, // session_id (987654)
+            77, 0, 0, 0, 0, 0, 0, 0, // orig_client_order_id (77)
         ];
 
         let decoded = Cancel::decode(buffer).unwrap();
 
-        assert_eq!(decoded.participant as u64, 1234567890);
-        assert_eq!(decoded.order_id as u64, 9876543210);
-        assert_eq!(decoded.book_id as u64, 42);
+        assert_eq!({ decoded.participant }, 1234567890);
+        assert_eq!({ decoded.order_id }, 9876543210);
+        assert_eq!({ decoded.book_id }, 42);
         assert_eq!(decoded.side, 1);
         assert_eq!(decoded.gateway_id, 5);
-        assert_eq!(decoded.session_id as u32, 987654);
+        assert_eq!({ decoded.session_id }, 987654);
+        assert_eq!(77, { decoded.orig_client_order_id });
     }
 
     #[test]
@@ -114,17 +140,18 @@ mod tests {
             side: 1,
             gateway_id: 5,
             session_id: 987654,
+            orig_client_order_id: 0,
         };
 
         let encoded = original.encode();
         let decoded = Cancel::decode(encoded).unwrap();
 
-        assert_eq!(decoded.participant as u64, original.participant as u64);
-        assert_eq!(decoded.order_id as u64, original.order_id as u64);
-        assert_eq!(decoded.book_id as u64, original.book_id as u64);
+        assert_eq!({ decoded.participant }, { original.participant });
+        assert_eq!({ decoded.order_id }, { original.order_id });
+        assert_eq!({ decoded.book_id }, { original.book_id });
         assert_eq!(decoded.side, original.side);
         assert_eq!(decoded.gateway_id, original.gateway_id);
-        assert_eq!(decoded.session_id as u32, original.session_id as u32);
+        assert_eq!({ decoded.session_id }, { original.session_id });
     }
 
     #[test]
@@ -136,6 +163,7 @@ mod tests {
             side: 1,
             gateway_id: 5,
             session_id: 987654,
+            orig_client_order_id: 0,
         };
 
         assert_eq!(cancel.message_type(), MsgType::Cancel);
@@ -153,6 +181,7 @@ mod tests {
             side: 1,
             gateway_id: 5,
             session_id: 987654,
+            orig_client_order_id: 0,
         };
 
         assert_eq!(cancel.get_side(), 1);
@@ -1,24 +1,36 @@
-use anyhow::{bail, Result};
-use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use anyhow::{anyhow, bail, Result};
+use socket2::{Protocol, Socket, Type};
 use std::{
-    io::Read,
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
-    str::FromStr,
-    time::Duration,
+    cell::{Cell, RefCell},
+    io::{ErrorKind, Read},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
 };
+use utils::network;
 
 use crate::{
-    cancel::CANCEL_SIZE,
+    cancel::{CANCEL_SIZE, CANCEL_V5_SIZE},
     decoder::Decoder,
-    execution_report::EXECUTIONREPORT_SIZE,
-    header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+    execution_report::{execution_report_size, ExecutionReport},
+    header::{OepHeader, OEP_VERSION},
+    heartbeat::{Heartbeat, HEARTBEAT_SIZE},
     login::{Login, LOGIN_SIZE},
-    modify::MODIFY_SIZE,
+    loginreject::{LoginError, LoginReject},
+    masscancel::MASSCANCEL_SIZE,
+    messagebuffer::MessageBuffer,
+    modify::{MODIFY_SIZE, MODIFY_V5_SIZE},
     neworder::NEWORDER_SIZE,
-    oep_decode,
-    oep_message::MsgType,
+    oep_message::{MsgType, OepMessage},
+    ordertracker::OrderTracker,
+    replace::REPLACE_SIZE,
+    resendrequest::{ResendRequest, RESENDREQUEST_SIZE},
+    version::{VersionReject, MIN_OEP_VERSION},
 };
 
+// how often the thread of track_orders checks the connection is still there
+const TRACKER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum ConnectionState {
     Disconnected,
@@ -43,14 +55,31 @@ pub enum MessageTypes {
     Cancel(crate::cancel::Cancel),
     ExecutionReport(crate::execution_report::ExecutionReport),
     Login(crate::login::Login),
+    MassCancel(crate::masscancel::MassCancel),
     Modify(crate::modify::Modify),
     NewOrder(crate::neworder::NewOrder),
+    Replace(crate::replace::Replace),
     Trade(crate::trade::Trade),
 }
 
 pub struct Connection {
     socket: Option<Socket>,
     state: ConnectionState,
+    // when the last message went out, shared with the heartbeat thread
+    // also keeps the two from writing at the same time
+    last_sent: Arc<Mutex<Instant>>,
+    // built at login, with the ids of the session
+    heartbeat: Option<Heartbeat>,
+    // sequence of the next order message, as expected by the gateway
+    next_seq: Cell<u32>,
+    // sequence of the last message received from the gateway
+    last_received_seq: Cell<u32>,
+    // what was read from the gateway, up to the end of the last message
+    received: RefCell<MessageBuffer>,
+    // proposed at login, then the one accepted by the gateway
+    oep_version: u16,
+    // asked for at login
+    cancel_on_disconnect: bool,
 }
 
 impl Default for Connection {
@@ -58,6 +87,13 @@ impl Default for Connection {
         Self {
             socket: None,
             state: ConnectionState::Disconnected,
+            last_sent: Arc::new(Mutex::new(Instant::now())),
+            heartbeat: None,
+            next_seq: Cell::new(1),
+            last_received_seq: Cell::new(0),
+            received: RefCell::new(MessageBuffer::new()),
+            oep_version: OEP_VERSION,
+            cancel_on_disconnect: true,
         }
     }
 }
@@ -66,19 +102,14 @@ impl Connection {
     pub fn connect(&mut self, addr: &str, port: u16) -> Result<()> {
         assert_eq!(ConnectionState::Disconnected, self.state);
 
+        let address = network::socket_address(addr, port)?;
         self.socket = Some(Socket::new(
-            Domain::IPV4,
+            address.domain(),
             Type::STREAM,
             Some(Protocol::TCP),
         )?);
 
-        self.socket
-            .as_mut()
-            .unwrap()
-            .connect(&SockAddr::from(SocketAddr::V4(SocketAddrV4::new(
-                Ipv4Addr::from_str(&addr).unwrap(),
-                port,
-            ))))?;
+        self.socket.as_mut().unwrap().connect(&address)?;
 
         self.state.advance();
 
@@ -86,10 +117,22 @@ impl Connection {
     }
 
     fn send_with_header(&self, header_bytes: &[u8], bytes: &[u8]) -> Result<usize, std::io::Error> {
-        self.socket
+        let mut last_sent = self.last_sent.lock().unwrap();
+        let r = self
+            .socket
             .as_ref()
             .unwrap()
-            .send([header_bytes, bytes].concat().as_slice())
+            .send([header_bytes, bytes].concat().as_slice())?;
+        *last_sent = Instant::now();
+        Ok(r)
+    }
+
+    /// Sends the message with the next sequence of the session
+    fn send_sequenced(&self, header: OepHeader, bytes: &[u8]) -> Result<usize, std::io::Error> {
+        let seq = self.next_seq.get();
+        let r = self.send_with_header(&header.with_seq(seq).encode(), bytes)?;
+        self.next_seq.set(seq + 1);
+        Ok(r)
     }
 
     pub fn login(
@@ -102,68 +145,226 @@ impl Connection {
     ) -> Result<()> {
         assert_eq!(ConnectionState::Connected, self.state);
 
-        let mut msg = Login::new(participant, session_id, gateway_id, username);
+        let mut msg = Login::new(participant, session_id, gateway_id, username)
+            .with_cancel_on_disconnect(self.cancel_on_disconnect);
         msg.hash_text_to_password(password);
-        let header = OepHeader::new(OEP_VERSION, MsgType::Login.into(), LOGIN_SIZE.try_into()?);
+        let header = OepHeader::new(
+            self.oep_version,
+            MsgType::Login.into(),
+            LOGIN_SIZE.try_into()?,
+        );
         self.send_with_header(&header.encode(), &msg.encode())?;
+        self.heartbeat = Some(Heartbeat::new(participant, session_id, gateway_id));
         self.state.advance();
 
         Ok(())
     }
 
-    pub fn wait_for_login(&mut self, timeout_ms: Option<u64>) -> Result<()> {
-        let real_timeout = timeout_ms.unwrap_or(2000);
-        self.socket
-            .as_ref()
-            .unwrap()
-            .set_read_timeout(Some(Duration::from_millis(real_timeout)))?;
-        let mut buf: [u8; 10000] = [0; 10000];
-        let bytes_read = self.socket.as_ref().unwrap().read(&mut buf)?;
-
-        if bytes_read > 0 {
-            match oep_decode(&buf[..bytes_read]) {
-                Err(e) => bail!("Decode err: {}", e),
-                Ok(msg) => match msg.message_type() {
-                    crate::MsgType::Login => {
-                        self.state.advance();
-                        return Ok(());
+    /// Whether the gateway cancels the orders of the session when the
+    /// connection goes away, asked for by the next login. True by default
+    pub fn set_cancel_on_disconnect(&mut self, cancel: bool) {
+        self.cancel_on_disconnect = cancel;
+    }
+
+    /// Sends a heartbeat whenever nothing else was sent for @interval, from a
+    /// thread of its own, so that the gateway doesn't time the session out
+    /// while the client is idle. The thread ends with the connection
+    pub fn start_heartbeats(&self, interval: Duration) -> Result<()> {
+        let Some(heartbeat) = self.heartbeat else {
+            bail!("Heartbeats start after the login");
+        };
+        let socket = self.socket.as_ref().unwrap().try_clone()?;
+        let last_sent = Arc::downgrade(&self.last_sent);
+        let header = OepHeader::new(
+            self.oep_version,
+            MsgType::Heartbeat.into(),
+            HEARTBEAT_SIZE.try_into()?,
+        );
+        let message = [header.encode().as_slice(), heartbeat.encode().as_slice()].concat();
+        thread::spawn(move || loop {
+            // gone with the connection
+            let Some(last_sent) = last_sent.upgrade() else {
+                return;
+            };
+            let wait = {
+                let mut last_sent = last_sent.lock().unwrap();
+                let idle = last_sent.elapsed();
+                if idle < interval {
+                    interval - idle
+                } else {
+                    if socket.send(&message).is_err() {
+                        return;
                     }
-                    _ => bail!("Not login"),
-                },
+                    *last_sent = Instant::now();
+                    interval
+                }
+            };
+            drop(last_sent);
+            thread::sleep(wait);
+        });
+        Ok(())
+    }
+
+    /// Reads the messages of the gateway from a thread of its own, applying
+    /// the execution reports to @tracker. The thread ends with the connection,
+    /// and recv_message is not to be used along with it
+    pub fn track_orders(&self, tracker: Arc<Mutex<OrderTracker>>) -> Result<()> {
+        if self.state != ConnectionState::Logged {
+            bail!("Orders are tracked after the login");
+        }
+        let mut socket = self.socket.as_ref().unwrap().try_clone()?;
+        socket.set_read_timeout(Some(TRACKER_POLL_INTERVAL))?;
+        let connection = Arc::downgrade(&self.last_sent);
+        // along with what was read with the reply to the login
+        let mut received = std::mem::take(&mut *self.received.borrow_mut());
+        thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            loop {
+                // the heartbeats and the session messages are of no use here
+                while let Some(r) = received.next_message() {
+                    let Ok((header, m)) = r else {
+                        continue;
+                    };
+                    if let Some(ereport) = m.as_any().downcast_ref::<ExecutionReport>() {
+                        tracker
+                            .lock()
+                            .unwrap()
+                            .on_execution_report(ereport, header.seq);
+                    }
+                }
+                match socket.read(&mut chunk) {
+                    Ok(0) => return,
+                    Ok(r) => received.extend(&chunk[..r]),
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                        if connection.strong_count() == 0 {
+                            return;
+                        }
+                    }
+                    Err(_) => return,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Waits for the reply of the gateway to the login. What comes after it,
+    /// e.g. the execution reports missed while away, is kept for recv_message.
+    /// A login refused by the gateway fails with a LoginError, telling why
+    pub fn wait_for_login(&mut self, timeout_ms: Option<u64>) -> Result<()> {
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.unwrap_or(2000));
+        let mut socket = self.socket.as_ref().unwrap();
+        let mut chunk = [0; 4096];
+        let (header, msg) = loop {
+            if let Some(r) = self.received.borrow_mut().next_message() {
+                break r.map_err(|e| anyhow!("Decode err: {e}"))?;
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                bail!("No reply to the login");
+            }
+            socket.set_read_timeout(Some(left))?;
+            match socket.read(&mut chunk)? {
+                0 => bail!("Connection closed by the gateway"),
+                r => self.received.borrow_mut().extend(&chunk[..r]),
             }
+        };
+        match msg.message_type() {
+            MsgType::Login => {
+                // the reply carries the version accepted by the
+                // gateway and the sequence it expects next
+                let version = header.oep_version;
+                if !(MIN_OEP_VERSION..=OEP_VERSION).contains(&version) {
+                    bail!("The gateway accepted the unknown version {version}");
+                }
+                self.oep_version = version;
+                self.next_seq.set(header.seq);
+                self.state.advance();
+                Ok(())
+            }
+            MsgType::VersionReject => {
+                let reject = msg
+                    .as_any()
+                    .downcast_ref::<VersionReject>()
+                    .expect("Bad pointer conversion");
+                Err(LoginError::UnsupportedVersion {
+                    proposed: reject.proposed,
+                    min_version: reject.min_version,
+                    max_version: reject.max_version,
+                }
+                .into())
+            }
+            MsgType::LoginReject => {
+                let reject = msg
+                    .as_any()
+                    .downcast_ref::<LoginReject>()
+                    .expect("Bad pointer conversion");
+                Err(LoginError::Rejected(reject.get_reason()).into())
+            }
+            _ => bail!("Not login"),
         }
+    }
 
-        bail!("Too few bytes read");
+    /// The version of the session, the one accepted by the gateway once logged in
+    pub fn oep_version(&self) -> u16 {
+        self.oep_version
     }
 
     pub fn send_message(&self, msg: MessageTypes) -> Result<()> {
+        // version 5 has no orig_client_order_id, at the end of these two
+        let (cancel_size, modify_size) = match self.oep_version {
+            5 => (CANCEL_V5_SIZE, MODIFY_V5_SIZE),
+            _ => (CANCEL_SIZE, MODIFY_SIZE),
+        };
         match msg {
             MessageTypes::Login(_) => bail!("Send login using login fn"),
             MessageTypes::NewOrder(order) => {
                 let header = OepHeader::new(
-                    OEP_VERSION,
+                    self.oep_version,
                     MsgType::NewOrder.into(),
                     NEWORDER_SIZE.try_into()?,
                 );
-                self.send_with_header(&header.encode(), &order.encode())?;
+                self.send_sequenced(header, &order.encode())?;
             }
             MessageTypes::Cancel(order) => {
-                let header =
-                    OepHeader::new(OEP_VERSION, MsgType::Cancel.into(), CANCEL_SIZE.try_into()?);
-                self.send_with_header(&header.encode(), &order.encode())?;
+                let header = OepHeader::new(
+                    self.oep_version,
+                    MsgType::Cancel.into(),
+                    cancel_size.try_into()?,
+                );
+                self.send_sequenced(header, &order.encode()[..cancel_size])?;
             }
             MessageTypes::ExecutionReport(order) => {
+                let size = execution_report_size(self.oep_version);
                 let header = OepHeader::new(
-                    OEP_VERSION,
+                    self.oep_version,
                     MsgType::ExecutionReport.into(),
-                    EXECUTIONREPORT_SIZE.try_into()?,
+                    size.try_into()?,
                 );
-                self.send_with_header(&header.encode(), &order.encode())?;
+                self.send_with_header(&header.encode(), &order.encode()[..size])?;
             }
             MessageTypes::Modify(order) => {
-                let header =
-                    OepHeader::new(OEP_VERSION, MsgType::Modify.into(), MODIFY_SIZE.try_into()?);
-                self.send_with_header(&header.encode(), &order.encode())?;
+                let header = OepHeader::new(
+                    self.oep_version,
+                    MsgType::Modify.into(),
+                    modify_size.try_into()?,
+                );
+                self.send_sequenced(header, &order.encode()[..modify_size])?;
+            }
+            MessageTypes::Replace(replace) => {
+                let header = OepHeader::new(
+                    self.oep_version,
+                    MsgType::Replace.into(),
+                    REPLACE_SIZE.try_into()?,
+                );
+                self.send_sequenced(header, &replace.encode())?;
+            }
+            MessageTypes::MassCancel(mass_cancel) => {
+                let header = OepHeader::new(
+                    self.oep_version,
+                    MsgType::MassCancel.into(),
+                    MASSCANCEL_SIZE.try_into()?,
+                );
+                self.send_sequenced(header, &mass_cancel.encode())?;
             }
             MessageTypes::Trade(_) => bail!("Can't send trades"),
         }
@@ -171,55 +372,126 @@ impl Connection {
         Ok(())
     }
 
-    // Receives messages from the gateway - until now it's implmented to
-    // receive just execution reports.
-    // Blocks for at most twice the duration
+    /// Asks the gateway for the messages of the session sequenced @from_seq
+    /// and after, e.g. the execution reports missed while disconnected
+    pub fn request_resend(&self, from_seq: u32) -> Result<()> {
+        let Some(heartbeat) = self.heartbeat else {
+            bail!("Resends can be requested after the login");
+        };
+        let msg = ResendRequest::new(
+            heartbeat.participant,
+            heartbeat.session_id,
+            heartbeat.gateway_id,
+            from_seq,
+        );
+        let header = OepHeader::new(
+            self.oep_version,
+            MsgType::ResendRequest.into(),
+            RESENDREQUEST_SIZE.try_into()?,
+        );
+        self.send_with_header(&header.encode(), &msg.encode())?;
+        Ok(())
+    }
+
+    /// The sequence of the last message received from the gateway, anything
+    /// after it can be asked for again with request_resend
+    pub fn last_received_seq(&self) -> u32 {
+        self.last_received_seq.get()
+    }
+
+    /// Reads what the gateway sent so far, without blocking, and returns
+    /// the messages completed by it, oldest first. The end of a message not
+    /// read yet is waited for by the next call
+    pub fn poll_messages(&self) -> Result<Vec<MessageTypes>> {
+        assert_eq!(ConnectionState::Logged, self.state);
+        let socket = self.socket.as_ref().unwrap();
+        socket.set_nonblocking(true)?;
+        let r = self.read_available(socket);
+        socket.set_nonblocking(false)?;
+        r?;
+        Ok(std::iter::from_fn(|| self.next_received()).collect())
+    }
+
+    // until the socket would block, or is closed
+    fn read_available(&self, mut socket: &Socket) -> Result<()> {
+        let mut chunk = [0; 4096];
+        loop {
+            match socket.read(&mut chunk) {
+                Ok(0) => bail!("Connection closed by the gateway"),
+                Ok(r) => self.received.borrow_mut().extend(&chunk[..r]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Receives the next message from the gateway, waiting for at most
+    /// @duration, None if nothing came
     #[must_use]
     pub fn recv_message(&self, duration: Duration) -> Option<MessageTypes> {
         assert_eq!(ConnectionState::Logged, self.state);
-        self.socket
-            .as_ref()
-            .unwrap()
-            .set_read_timeout(Some(duration))
-            .unwrap();
-        let mut header_buf: [u8; OEP_HEADER_SIZE] = [0; OEP_HEADER_SIZE];
-        match self.socket.as_ref().unwrap().read_exact(&mut header_buf) {
-            Ok(_) => {
-                let header = OepHeader::decode(header_buf).unwrap();
-                let mut v = Vec::with_capacity(OEP_HEADER_SIZE + header.msg_len as usize);
-                match self.socket.as_ref().unwrap().read_exact(&mut v) {
-                    Ok(_) => match oep_decode(&v) {
-                        Ok(m) => match m.message_type() {
-                            MsgType::NewOrder => todo!(),
-                            MsgType::Modify => todo!(),
-                            MsgType::Cancel => todo!(),
-                            // we only care about execution reports for now
-                            MsgType::ExecutionReport => {
-                                return Some(MessageTypes::ExecutionReport(
-                                    *m.as_any()
-                                        .downcast_ref::<crate::execution_report::ExecutionReport>()
-                                        .expect("Bad pointer conversion"),
-                                ))
-                            }
-                            MsgType::Login => todo!(),
-                            MsgType::Trade => todo!(),
-                            MsgType::Unknown => todo!(),
-                            MsgType::SessionNotification => todo!(),
-                        },
-                        Err(_) => return None,
-                    },
-                    Err(_) => return None, // we lose the header here
-                }
+        let deadline = Instant::now() + duration;
+        let mut socket = self.socket.as_ref().unwrap();
+        let mut chunk = [0; 4096];
+        loop {
+            if let Some(m) = self.next_received() {
+                return Some(m);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() || socket.set_read_timeout(Some(left)).is_err() {
+                return None;
+            }
+            match socket.read(&mut chunk) {
+                Ok(0) | Err(_) => return None,
+                Ok(r) => self.received.borrow_mut().extend(&chunk[..r]),
+            }
+        }
+    }
+
+    /// The next message of those completely read, skipping those not decoding
+    /// and those of no use to a client
+    fn next_received(&self) -> Option<MessageTypes> {
+        loop {
+            let (header, m) = match self.received.borrow_mut().next_message()? {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            if header.seq != 0 {
+                self.last_received_seq.set(header.seq);
+            }
+            if let Some(m) = message_types(m.as_ref()) {
+                return Some(m);
             }
-            Err(_) => return None,
         }
     }
 }
 
+/// @m as one of the MessageTypes, None for the session level messages
+fn message_types(m: &dyn OepMessage) -> Option<MessageTypes> {
+    let any = m.as_any();
+    match m.message_type() {
+        MsgType::NewOrder => any.downcast_ref().copied().map(MessageTypes::NewOrder),
+        MsgType::Modify => any.downcast_ref().copied().map(MessageTypes::Modify),
+        MsgType::Cancel => any.downcast_ref().copied().map(MessageTypes::Cancel),
+        MsgType::MassCancel => any.downcast_ref().copied().map(MessageTypes::MassCancel),
+        MsgType::Replace => any.downcast_ref().copied().map(MessageTypes::Replace),
+        MsgType::ExecutionReport => any
+            .downcast_ref()
+            .copied()
+            .map(MessageTypes::ExecutionReport),
+        MsgType::Login => any.downcast_ref().copied().map(MessageTypes::Login),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::execution_report::EXECUTIONREPORT_SIZE;
+    use crate::header::OEP_HEADER_SIZE;
+    use crate::loginreject::{LoginRejectReason, LOGINREJECT_SIZE};
     use crate::neworder::NewOrder;
+    use crate::version::VERSIONREJECT_SIZE;
     use std::io::Write;
     use std::net::TcpListener;
     use std::thread;
@@ -289,6 +561,78 @@ mod tests {
         }
         assert!(r.is_ok());
         assert_eq!(connection.state, ConnectionState::Logged);
+        assert_eq!(OEP_VERSION, connection.oep_version());
+    }
+
+    #[test]
+    fn test_version_reject() {
+        let server = setup_mock_server();
+        let server_addr = server.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let reject = VersionReject::new(1234, 5678, 1, OEP_VERSION).encode();
+            let header = OepHeader::new(
+                OEP_VERSION,
+                MsgType::VersionReject.into(),
+                VERSIONREJECT_SIZE as u32,
+            )
+            .encode();
+            stream
+                .write_all([header.as_slice(), reject.as_slice()].concat().as_slice())
+                .unwrap();
+        });
+
+        let mut connection = Connection::default();
+        connection
+            .connect(&server_addr.ip().to_string(), server_addr.port())
+            .unwrap();
+        connection
+            .login(1234, 5678, 1, "username", "password")
+            .unwrap();
+
+        let e = connection.wait_for_login(Some(1000)).unwrap_err();
+        assert_eq!(
+            LoginRejectReason::VersionMismatch,
+            e.downcast_ref::<LoginError>().unwrap().reason()
+        );
+        assert_eq!(connection.state, ConnectionState::LoginSent);
+    }
+
+    #[test]
+    fn test_login_reject() {
+        let server = setup_mock_server();
+        let server_addr = server.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let reject =
+                LoginReject::new(1234, 5678, 1, LoginRejectReason::BadCredentials).encode();
+            let header = OepHeader::new(
+                OEP_VERSION,
+                MsgType::LoginReject.into(),
+                LOGINREJECT_SIZE as u32,
+            )
+            .encode();
+            stream
+                .write_all([header.as_slice(), reject.as_slice()].concat().as_slice())
+                .unwrap();
+        });
+
+        let mut connection = Connection::default();
+        connection
+            .connect(&server_addr.ip().to_string(), server_addr.port())
+            .unwrap();
+        connection
+            .login(1234, 5678, 1, "username", "wrong")
+            .unwrap();
+
+        let e = connection.wait_for_login(Some(1000)).unwrap_err();
+        assert_eq!(
+            Some(&LoginError::Rejected(LoginRejectReason::BadCredentials)),
+            e.downcast_ref::<LoginError>()
+        );
+        assert_eq!(connection.state, ConnectionState::LoginSent);
     }
 
     #[test]
@@ -315,10 +659,325 @@ mod tests {
             side: 1,
             gateway_id: 1,
             session_id: 5678,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
         };
 
         assert!(connection
             .send_message(MessageTypes::NewOrder(new_order))
             .is_ok());
     }
+
+    #[test]
+    fn test_sequenced_sends() {
+        let server = setup_mock_server();
+        let server_addr = server.local_addr().unwrap();
+
+        let mut connection = Connection::default();
+        connection
+            .connect(&server_addr.ip().to_string(), server_addr.port())
+            .unwrap();
+        assert!(connection.request_resend(1).is_err());
+        connection
+            .login(1234, 5678, 1, "username", "password")
+            .unwrap();
+
+        let (mut stream, _) = server.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut login = [0; OEP_HEADER_SIZE + LOGIN_SIZE];
+        stream.read_exact(&mut login).unwrap();
+        // the gateway already got 6 messages on this session
+        let header = OepHeader::new(OEP_VERSION, MsgType::Login.into(), LOGIN_SIZE as u32)
+            .with_seq(7)
+            .encode();
+        stream.write_all(&header).unwrap();
+        stream.write_all(&login[OEP_HEADER_SIZE..]).unwrap();
+        connection.wait_for_login(Some(1000)).unwrap();
+
+        let cancel = crate::cancel::Cancel {
+            participant: 1234,
+            order_id: 1,
+            book_id: 1,
+            side: 1,
+            gateway_id: 1,
+            session_id: 5678,
+            orig_client_order_id: 0,
+        };
+        for expected_seq in [7, 8] {
+            connection
+                .send_message(MessageTypes::Cancel(cancel))
+                .unwrap();
+            let mut message = [0; OEP_HEADER_SIZE + CANCEL_SIZE];
+            stream.read_exact(&mut message).unwrap();
+            let header = OepHeader::decode(message[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+            assert_eq!(expected_seq, { header.seq });
+        }
+
+        // session level, not sequenced
+        connection.request_resend(3).unwrap();
+        let mut message = [0; OEP_HEADER_SIZE + RESENDREQUEST_SIZE];
+        stream.read_exact(&mut message).unwrap();
+        let header = OepHeader::decode(message[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(MsgType::ResendRequest, header.message_type());
+        assert_eq!(0, { header.seq });
+        let request =
+            ResendRequest::decode(message[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(3, { request.from_seq });
+        assert_eq!(5678, { request.session_id });
+    }
+
+    #[test]
+    fn test_cancel_in_v5() {
+        let server = setup_mock_server();
+        let server_addr = server.local_addr().unwrap();
+        let mut connection = Connection::default();
+        connection
+            .connect(&server_addr.ip().to_string(), server_addr.port())
+            .unwrap();
+        connection
+            .login(1234, 5678, 1, "username", "password")
+            .unwrap();
+
+        let (mut stream, _) = server.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut login = [0; OEP_HEADER_SIZE + LOGIN_SIZE];
+        stream.read_exact(&mut login).unwrap();
+        // a gateway speaking version 5 only
+        let header = OepHeader::new(5, MsgType::Login.into(), LOGIN_SIZE as u32).encode();
+        stream.write_all(&header).unwrap();
+        stream.write_all(&login[OEP_HEADER_SIZE..]).unwrap();
+        connection.wait_for_login(Some(1000)).unwrap();
+        assert_eq!(5, connection.oep_version());
+
+        let cancel = crate::cancel::Cancel {
+            participant: 1234,
+            order_id: 1,
+            book_id: 1,
+            side: 1,
+            gateway_id: 1,
+            session_id: 5678,
+            orig_client_order_id: 0,
+        };
+        connection
+            .send_message(MessageTypes::Cancel(cancel))
+            .unwrap();
+        let mut message = [0; OEP_HEADER_SIZE + CANCEL_V5_SIZE];
+        stream.read_exact(&mut message).unwrap();
+        let decoded = crate::oep_decode(&message).unwrap();
+        assert_eq!(MsgType::Cancel, decoded.message_type());
+        assert_eq!(5678, decoded.get_session_id());
+    }
+
+    #[test]
+    fn test_heartbeats() {
+        let server = setup_mock_server();
+        let server_addr = server.local_addr().unwrap();
+
+        let mut connection = Connection::default();
+        connection
+            .connect(&server_addr.ip().to_string(), server_addr.port())
+            .unwrap();
+        assert!(connection
+            .start_heartbeats(Duration::from_millis(10))
+            .is_err());
+        connection
+            .login(1234, 5678, 1, "username", "password")
+            .unwrap();
+        connection
+            .start_heartbeats(Duration::from_millis(10))
+            .unwrap();
+
+        let (mut stream, _) = server.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut login = [0; OEP_HEADER_SIZE + LOGIN_SIZE];
+        stream.read_exact(&mut login).unwrap();
+        let mut heartbeat = [0; OEP_HEADER_SIZE + HEARTBEAT_SIZE];
+        stream.read_exact(&mut heartbeat).unwrap();
+        let header = OepHeader::decode(heartbeat[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(MsgType::Heartbeat, header.message_type());
+        let heartbeat =
+            Heartbeat::decode(heartbeat[OEP_HEADER_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(1234, { heartbeat.participant });
+        assert_eq!(5678, { heartbeat.session_id });
+
+        // the thread stops with the connection
+        drop(connection);
+        thread::sleep(Duration::from_millis(50));
+        let mut rest = vec![];
+        stream.read_to_end(&mut rest).unwrap();
+        assert_eq!(0, rest.len() % (OEP_HEADER_SIZE + HEARTBEAT_SIZE));
+    }
+
+    #[test]
+    fn test_track_orders() {
+        let server = setup_mock_server();
+        let server_addr = server.local_addr().unwrap();
+
+        let mut connection = Connection::default();
+        connection
+            .connect(&server_addr.ip().to_string(), server_addr.port())
+            .unwrap();
+        let tracker = Arc::new(Mutex::new(OrderTracker::new()));
+        assert!(connection.track_orders(tracker.clone()).is_err());
+        connection
+            .login(1234, 5678, 1, "username", "password")
+            .unwrap();
+
+        let (mut stream, _) = server.accept().unwrap();
+        let mut login = [0; OEP_HEADER_SIZE + LOGIN_SIZE];
+        stream.read_exact(&mut login).unwrap();
+        stream.write_all(&login).unwrap();
+        connection.wait_for_login(Some(1000)).unwrap();
+        connection.track_orders(tracker.clone()).unwrap();
+
+        let order = NewOrder {
+            client_order_id: 7,
+            participant: 1234,
+            book_id: 1,
+            quantity: 100,
+            price: 1000,
+            order_type: 0,
+            side: 0,
+            gateway_id: 1,
+            session_id: 5678,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        };
+        tracker.lock().unwrap().on_new_order(&order);
+        let ereport = ExecutionReport {
+            participant: 1234,
+            order_id: 500,
+            submitted_order_id: 7,
+            book: 1,
+            quantity: 100,
+            price: 1000,
+            flags: 0,
+            side: 0,
+            state: 0,
+            session_id: 5678,
+            gateway_id: 1,
+            filled_quantity: 0,
+            leaves_quantity: 100,
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
+        };
+        let header = OepHeader::new(
+            OEP_VERSION,
+            MsgType::ExecutionReport.into(),
+            EXECUTIONREPORT_SIZE as u32,
+        )
+        .with_seq(3)
+        .encode();
+        // in two bits, as TCP may have it
+        let message = [header.as_slice(), &ereport.encode()].concat();
+        stream.write_all(&message[..10]).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        stream.write_all(&message[10..]).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while tracker.lock().unwrap().last_seq() != 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let tracker = tracker.lock().unwrap();
+        assert_eq!(500, tracker.get(7).unwrap().order_id);
+        assert_eq!(1, tracker.open_orders().len());
+    }
+
+    #[test]
+    fn test_poll_messages() {
+        let server = setup_mock_server();
+        let server_addr = server.local_addr().unwrap();
+
+        let mut connection = Connection::default();
+        connection
+            .connect(&server_addr.ip().to_string(), server_addr.port())
+            .unwrap();
+        connection
+            .login(1234, 5678, 1, "username", "password")
+            .unwrap();
+
+        let ereport = |seq: u32, order_id: u64| {
+            let ereport = ExecutionReport {
+                participant: 1234,
+                order_id,
+                submitted_order_id: order_id,
+                book: 1,
+                quantity: 100,
+                price: 1000,
+                flags: 0,
+                side: 0,
+                state: 0,
+                session_id: 5678,
+                gateway_id: 1,
+                filled_quantity: 0,
+                leaves_quantity: 100,
+                orig_order_id: 0,
+                partition_id: 0,
+                reject_reason: 0,
+                trade_id: 0,
+            };
+            let header = OepHeader::new(
+                OEP_VERSION,
+                MsgType::ExecutionReport.into(),
+                EXECUTIONREPORT_SIZE as u32,
+            )
+            .with_seq(seq);
+            [header.encode().as_slice(), &ereport.encode()].concat()
+        };
+        let (mut stream, _) = server.accept().unwrap();
+        let mut login = [0; OEP_HEADER_SIZE + LOGIN_SIZE];
+        stream.read_exact(&mut login).unwrap();
+        // a report missed while away, right behind the reply to the login
+        stream
+            .write_all(&[login.as_slice(), &ereport(1, 500)].concat())
+            .unwrap();
+        connection.wait_for_login(Some(1000)).unwrap();
+        match connection.recv_message(Duration::from_millis(100)) {
+            Some(MessageTypes::ExecutionReport(r)) => assert_eq!(500, { r.order_id }),
+            other => panic!("Unexpected {other:?}"),
+        }
+        assert!(connection.poll_messages().unwrap().is_empty());
+
+        // cut in the middle of the header of the last report
+        let heartbeat = Heartbeat::new(1234, 5678, 1);
+        let header = OepHeader::new(
+            OEP_VERSION,
+            MsgType::Heartbeat.into(),
+            HEARTBEAT_SIZE as u32,
+        );
+        let bytes = [
+            ereport(2, 501),
+            header.encode().to_vec(),
+            heartbeat.encode().to_vec(),
+            ereport(3, 502),
+        ]
+        .concat();
+        let cut = bytes.len() - EXECUTIONREPORT_SIZE - 4;
+        stream.write_all(&bytes[..cut]).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let messages = connection.poll_messages().unwrap();
+        // the heartbeat is not for the client
+        assert_eq!(1, messages.len());
+        stream.write_all(&bytes[cut..]).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let messages = connection.poll_messages().unwrap();
+        assert!(
+            matches!(messages.as_slice(), [MessageTypes::ExecutionReport(r)] if { r.order_id } == 502)
+        );
+        assert_eq!(3, connection.last_received_seq());
+
+        drop(stream);
+        thread::sleep(Duration::from_millis(50));
+        assert!(connection.poll_messages().is_err());
+    }
 }
@@ -16,4 +16,135 @@ where
 {
     fn encode(self) -> [u8; S];
     fn decode(buffer: [u8; S]) -> Result<Self, Box<dyn Error>>;
+
+    /// Decodes a message out of @buffer, which has to be exactly S bytes long
+    fn decode_slice(buffer: &[u8]) -> Result<Self, Box<dyn Error>> {
+        Self::decode(buffer.try_into().map_err(|_| DecodeError)?)
+    }
+}
+
+/// A field of a message, as found on the wire: little endian, with no padding
+/// around it
+pub trait Field: Sized {
+    const SIZE: usize;
+    fn write(self, buffer: &mut [u8]);
+    fn read(buffer: &[u8]) -> Self;
+}
+
+macro_rules! integer_field {
+    ($($t:ty),*) => {$(
+        impl Field for $t {
+            const SIZE: usize = std::mem::size_of::<$t>();
+
+            fn write(self, buffer: &mut [u8]) {
+                buffer.copy_from_slice(&self.to_le_bytes());
+            }
+
+            fn read(buffer: &[u8]) -> Self {
+                <$t>::from_le_bytes(buffer.try_into().unwrap())
+            }
+        }
+    )*};
+}
+
+integer_field!(u8, u16, u32, u64);
+
+impl<const N: usize> Field for [u8; N] {
+    const SIZE: usize = N;
+
+    fn write(self, buffer: &mut [u8]) {
+        buffer.copy_from_slice(&self);
+    }
+
+    fn read(buffer: &[u8]) -> Self {
+        buffer.try_into().unwrap()
+    }
+}
+
+/// Lays the fields of a message out one after the other, the way Decoder::encode
+/// puts them on the wire
+pub struct FieldWriter<const S: usize> {
+    buffer: [u8; S],
+    offset: usize,
+}
+
+impl<const S: usize> Default for FieldWriter<S> {
+    fn default() -> Self {
+        Self {
+            buffer: [0; S],
+            offset: 0,
+        }
+    }
+}
+
+impl<const S: usize> FieldWriter<S> {
+    pub fn put<F: Field>(mut self, value: F) -> Self {
+        value.write(&mut self.buffer[self.offset..self.offset + F::SIZE]);
+        self.offset += F::SIZE;
+        self
+    }
+
+    /// The encoded message, which all the S bytes have to be written for
+    pub fn finish(self) -> [u8; S] {
+        assert_eq!(S, self.offset, "Message fields don't add up to its size");
+        self.buffer
+    }
+}
+
+/// Takes the fields of a message off the wire, in the order they were put by
+/// FieldWriter
+pub struct FieldReader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> FieldReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer, offset: 0 }
+    }
+
+    /// Returns: DecodeError past the end of the buffer
+    pub fn get<F: Field>(&mut self) -> Result<F, DecodeError> {
+        let field = self
+            .buffer
+            .get(self.offset..self.offset + F::SIZE)
+            .ok_or(DecodeError)?;
+        self.offset += F::SIZE;
+        Ok(F::read(field))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields() {
+        let encoded = FieldWriter::<14>::default()
+            .put(1u8)
+            .put(0x0203u16)
+            .put(0x04050607u32)
+            .put([8u8, 9])
+            .put(0x0a0b0c0du32)
+            .put(0x0eu8)
+            .finish();
+        assert_eq!([1, 3, 2, 7, 6, 5, 4, 8, 9, 13, 12, 11, 10, 14], encoded);
+
+        let mut reader = FieldReader::new(&encoded);
+        assert_eq!(1, reader.get::<u8>().unwrap());
+        assert_eq!(0x0203, reader.get::<u16>().unwrap());
+        assert_eq!(0x04050607, reader.get::<u32>().unwrap());
+        assert_eq!([8, 9], reader.get::<[u8; 2]>().unwrap());
+        assert_eq!(0x0a0b0c0d, reader.get::<u32>().unwrap());
+        assert_eq!(0x0e, reader.get::<u8>().unwrap());
+        // past the end
+        assert!(reader.get::<u8>().is_err());
+        assert!(FieldReader::new(&[1, 2, 3]).get::<u64>().is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_fields_short_of_the_size() {
+        FieldWriter::<8>::default().put(1u32).finish();
+    }
 }
@@ -0,0 +1,54 @@
+use std::error::Error;
+
+use crate::decoder::{Decoder, FieldReader, FieldWriter};
+
+/// An instrument gone from the engine, its market closed and its orders
+/// cancelled, e.g. deleted by the clearing or expired. The book can be
+/// dropped by the consumers of the feed
+/// Not a real OEP message either, just sharing the encoding
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct InstrumentDelisted {
+    pub book_id: u64,
+    // nanoseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+pub const DELISTING_SIZE: usize = std::mem::size_of::<InstrumentDelisted>();
+
+impl Decoder<DELISTING_SIZE> for InstrumentDelisted {
+    fn encode(self) -> [u8; DELISTING_SIZE] {
+        FieldWriter::default()
+            .put(self.book_id)
+            .put(self.timestamp)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; DELISTING_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            book_id: reader.get()?,
+            timestamp: reader.get()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = InstrumentDelisted {
+            book_id: 0x0102,
+            timestamp: 1000,
+        };
+
+        let encoded = original.encode();
+        assert_eq!(16, encoded.len());
+        assert_eq!([2, 1], encoded[..2]);
+        let decoded = InstrumentDelisted::decode(encoded).unwrap();
+        assert_eq!(0x0102, { decoded.book_id });
+        assert_eq!(1000, { decoded.timestamp });
+    }
+}
@@ -0,0 +1,199 @@
+use std::{error::Error, str::FromStr};
+
+use anyhow::bail;
+
+use crate::{
+    decoder::{Decoder, FieldReader, FieldWriter},
+    ingress::IngressKind,
+};
+
+/// How the matching engines get the execution reports to the gateways, the
+/// `egress` key of the engine configuration
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum EgressMode {
+    // plain multicast, a lost datagram is a lost execution report
+    #[default]
+    Multicast,
+    // each report framed with an EgressHeader, numbered per gateway, the
+    // gateways asking for the lost ones with an EgressNak
+    Sequenced,
+}
+
+impl FromStr for EgressMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "multicast" => Ok(EgressMode::Multicast),
+            "sequenced" => Ok(EgressMode::Sequenced),
+            _ => bail!("Unknown egress mode {s}"),
+        }
+    }
+}
+
+/// Follows the OepHeader of an EgressFrame, ahead of the execution report
+/// for @gateway_id, header included, when of the Message kind. The kinds are
+/// the ones of the ingress frames
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct EgressHeader {
+    pub engine_id: u8,
+    pub partition_id: u8,
+    pub gateway_id: u8,
+    kind: u8, // see IngressKind
+    // tells the runs of an engine apart, its sequences starting again with 1 on each
+    pub epoch: u32,
+    pub seq: u64,
+}
+
+impl EgressHeader {
+    pub fn new(
+        engine_id: u8,
+        partition_id: u8,
+        gateway_id: u8,
+        kind: IngressKind,
+        epoch: u32,
+        seq: u64,
+    ) -> Self {
+        Self {
+            engine_id,
+            partition_id,
+            gateway_id,
+            kind: kind.into(),
+            epoch,
+            seq,
+        }
+    }
+
+    pub fn get_kind(&self) -> IngressKind {
+        // always valid, since it was checked when decoding
+        self.kind.try_into().unwrap()
+    }
+}
+
+pub const EGRESSHEADER_SIZE: usize = std::mem::size_of::<EgressHeader>();
+
+impl Decoder<EGRESSHEADER_SIZE> for EgressHeader {
+    fn encode(self) -> [u8; EGRESSHEADER_SIZE] {
+        FieldWriter::default()
+            .put(self.engine_id)
+            .put(self.partition_id)
+            .put(self.gateway_id)
+            .put(self.kind)
+            .put(self.epoch)
+            .put(self.seq)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; EGRESSHEADER_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        let header = Self {
+            engine_id: reader.get()?,
+            partition_id: reader.get()?,
+            gateway_id: reader.get()?,
+            kind: reader.get()?,
+            epoch: reader.get()?,
+            seq: reader.get()?,
+        };
+        IngressKind::try_from(header.kind)?;
+        Ok(header)
+    }
+}
+
+/// Sent by a gateway to the matching engines on the order channel, asking
+/// the engine @engine_id of @partition_id for the reports of @gateway_id
+/// starting with @from_seq again
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct EgressNak {
+    pub engine_id: u8,
+    pub partition_id: u8,
+    pub gateway_id: u8,
+    pub epoch: u32,
+    pub from_seq: u64,
+}
+
+impl EgressNak {
+    pub fn new(engine_id: u8, partition_id: u8, gateway_id: u8, epoch: u32, from_seq: u64) -> Self {
+        Self {
+            engine_id,
+            partition_id,
+            gateway_id,
+            epoch,
+            from_seq,
+        }
+    }
+}
+
+pub const EGRESSNAK_SIZE: usize = std::mem::size_of::<EgressNak>();
+
+impl Decoder<EGRESSNAK_SIZE> for EgressNak {
+    fn encode(self) -> [u8; EGRESSNAK_SIZE] {
+        FieldWriter::default()
+            .put(self.engine_id)
+            .put(self.partition_id)
+            .put(self.gateway_id)
+            .put(self.epoch)
+            .put(self.from_seq)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; EGRESSNAK_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            engine_id: reader.get()?,
+            partition_id: reader.get()?,
+            gateway_id: reader.get()?,
+            epoch: reader.get()?,
+            from_seq: reader.get()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_egress_mode() {
+        assert_eq!(EgressMode::Multicast, "multicast".parse().unwrap());
+        assert_eq!(EgressMode::Sequenced, "Sequenced".parse().unwrap());
+        assert!("tcp".parse::<EgressMode>().is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_header() {
+        let original = EgressHeader::new(1, 2, 3, IngressKind::Heartbeat, 0x01020304, 300);
+
+        let encoded = original.encode();
+        assert_eq!([1, 2, 3, 1, 4, 3, 2, 1, 44, 1, 0, 0, 0, 0, 0, 0], encoded);
+        let decoded = EgressHeader::decode(encoded).unwrap();
+
+        assert_eq!(1, decoded.engine_id);
+        assert_eq!(2, decoded.partition_id);
+        assert_eq!(3, decoded.gateway_id);
+        assert_eq!(IngressKind::Heartbeat, decoded.get_kind());
+        assert_eq!(0x01020304, { decoded.epoch });
+        assert_eq!(300, { decoded.seq });
+    }
+
+    #[test]
+    fn test_decode_invalid_kind() {
+        assert!(EgressHeader::decode([1, 2, 3, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_nak() {
+        let original = EgressNak::new(1, 2, 3, 0x01020304, 300);
+
+        let encoded = original.encode();
+        assert_eq!([1, 2, 3, 4, 3, 2, 1, 44, 1, 0, 0, 0, 0, 0, 0], encoded);
+        let decoded = EgressNak::decode(encoded).unwrap();
+
+        assert_eq!(1, decoded.engine_id);
+        assert_eq!(2, decoded.partition_id);
+        assert_eq!(3, decoded.gateway_id);
+        assert_eq!(0x01020304, { decoded.epoch });
+        assert_eq!(300, { decoded.from_seq });
+    }
+}
@@ -0,0 +1,103 @@
+use std::error::Error;
+
+use crate::decoder::{DecodeError, Decoder, FieldReader, FieldWriter};
+
+/// Lifecycle of a matching engine, as announced to the gateways
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum EngineState {
+    // the engine is taking over (e.g. a backup being promoted) and can't accept orders yet
+    Starting,
+    // the engine is accepting orders. Sent periodically as a heartbeat as well
+    Ready,
+    // the engine is closing its markets before exiting, orders are no longer accepted
+    Stopping,
+}
+
+impl From<EngineState> for u8 {
+    fn from(value: EngineState) -> Self {
+        match value {
+            EngineState::Starting => 0,
+            EngineState::Ready => 1,
+            EngineState::Stopping => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for EngineState {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(EngineState::Starting),
+            1 => Ok(EngineState::Ready),
+            2 => Ok(EngineState::Stopping),
+            _ => Err(DecodeError),
+        }
+    }
+}
+
+/// Sent by the matching engine to the gateways on the internal publisher
+/// channel, in order to let them know whether orders can be relayed
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct EngineStatus {
+    pub engine_id: u8,
+    state: u8, // see EngineState
+}
+
+impl EngineStatus {
+    pub fn new(engine_id: u8, state: EngineState) -> Self {
+        Self {
+            engine_id,
+            state: state.into(),
+        }
+    }
+
+    pub fn get_state(&self) -> EngineState {
+        // always valid, since it was checked when decoding
+        self.state.try_into().unwrap()
+    }
+}
+
+pub const ENGINESTATUS_SIZE: usize = std::mem::size_of::<EngineStatus>();
+
+impl Decoder<ENGINESTATUS_SIZE> for EngineStatus {
+    fn encode(self) -> [u8; ENGINESTATUS_SIZE] {
+        FieldWriter::default()
+            .put(self.engine_id)
+            .put(self.state)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; ENGINESTATUS_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        let status = Self {
+            engine_id: reader.get()?,
+            state: reader.get()?,
+        };
+        EngineState::try_from(status.state)?;
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = EngineStatus::new(2, EngineState::Ready);
+
+        let encoded = original.encode();
+        assert_eq!([2, 1], encoded);
+        let decoded = EngineStatus::decode(encoded).unwrap();
+
+        assert_eq!(2, decoded.engine_id);
+        assert_eq!(EngineState::Ready, decoded.get_state());
+    }
+
+    #[test]
+    fn test_decode_invalid_state() {
+        assert!(EngineStatus::decode([0, 7]).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use std::error::Error;
+
+use crate::decoder::{Decoder, FieldReader, FieldWriter};
+
+/// End of day summary of an instrument, published on the feed when the
+/// market closes and sent to the clearing to be persisted
+/// Not a real OEP message either, just sharing the encoding
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct EodSummary {
+    pub book_id: u64,
+    // last traded price, or the midpoint if nothing traded during the day. 0 if none is available
+    pub closing_price: u64,
+    pub volume: u64,
+    pub trade_count: u64,
+}
+
+pub const EODSUMMARY_SIZE: usize = std::mem::size_of::<EodSummary>();
+
+impl Decoder<EODSUMMARY_SIZE> for EodSummary {
+    fn encode(self) -> [u8; EODSUMMARY_SIZE] {
+        FieldWriter::default()
+            .put(self.book_id)
+            .put(self.closing_price)
+            .put(self.volume)
+            .put(self.trade_count)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; EODSUMMARY_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            book_id: reader.get()?,
+            closing_price: reader.get()?,
+            volume: reader.get()?,
+            trade_count: reader.get()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = EodSummary {
+            book_id: 0x0102030405060708,
+            closing_price: 1000,
+            volume: 300,
+            trade_count: 2,
+        };
+
+        let encoded = original.encode();
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1], encoded[0..8]);
+        let decoded = EodSummary::decode(encoded).unwrap();
+
+        assert_eq!({ original.book_id }, { decoded.book_id });
+        assert_eq!({ original.closing_price }, { decoded.closing_price });
+        assert_eq!({ original.volume }, { decoded.volume });
+        assert_eq!({ original.trade_count }, { decoded.trade_count });
+    }
+
+    #[test]
+    fn test_eodsummary_size() {
+        assert_eq!(32, EODSUMMARY_SIZE);
+    }
+}
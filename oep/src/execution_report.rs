@@ -1,7 +1,7 @@
 use std::error::Error;
 
 use crate::{
-    decoder::Decoder,
+    decoder::{Decoder, FieldReader, FieldWriter},
     oep_message::{MsgType, OepMessage},
 };
 
@@ -14,11 +14,90 @@ pub struct ExecutionReport {
     pub book: u64,
     pub quantity: u64,
     pub price: u64,
-    pub flags: u16,
+    pub flags: u16, // see the FLAG_ constants, 0 when none applies
     pub side: u8,
     pub state: u8, // see OrderState
     pub session_id: u32,
     pub gateway_id: u8,
+    pub filled_quantity: u64, // traded by the event being reported
+    pub leaves_quantity: u64, // still open in the book afterwards
+    pub orig_order_id: u64,   // the replaced order, for both reports of a replace
+    pub partition_id: u8,     // the matching engine partition that handled the message
+    pub reject_reason: u8,    // see RejectReason, only set for the rejects
+    // Since version 7
+    pub trade_id: u64, // the trade reported, the first one for an aggressor, 0 if none
+}
+
+/// Why a message was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    None = 0,
+    // malformed, or refused by the book (e.g. state, price bands, tick size)
+    Unspecified = 1,
+    // no matching engine was ready to take the message
+    EngineUnavailable = 2,
+    // the book is handled by another matching engine partition
+    OutsidePartition = 3,
+    // the session went over the message rate of its gateway listener
+    Throttled = 4,
+    // the pre-trade risk limits of the participant
+    QuantityLimit = 5,
+    NotionalLimit = 6,
+    OpenOrdersLimit = 7,
+    // the participant reached its exposure limit, as tracked by the clearing
+    ExposureLimit = 8,
+    // the sequence of the message skipped some, see the order entry protocol
+    OutOfSequence = 9,
+    // the participant isn't entitled to trade the book
+    NotEntitled = 10,
+    // no market for the book on the matching engine of its partition
+    UnknownBook = 11,
+    // the client order id of a modify or a cancel is carried by several orders
+    AmbiguousClientOrderId = 12,
+    // the client order id was used by one of the last orders of the session
+    DuplicateClientOrderId = 13,
+}
+
+// The bits of the flags, telling more about the event reported than its state.
+// The incoming order traded against the book as it came in, taking liquidity
+pub const FLAG_AGGRESSOR: u16 = 1;
+// the event traded part of the order only, the rest still open or cancelled
+pub const FLAG_PARTIAL_FILL: u16 = 1 << 1;
+// cancelled as its session went away, see the cancel_on_disconnect of the login
+pub const FLAG_CANCEL_ON_DISCONNECT: u16 = 1 << 2;
+// cancelled for trading against an order of the same participant. Reserved,
+// the matching engine has no self-trade prevention yet
+pub const FLAG_SELF_TRADE_CANCEL: u16 = 1 << 3;
+// rejected for a price out of the price bands of the book
+pub const FLAG_BAND_REJECT: u16 = 1 << 4;
+// rejected by the gateway, the session going over its message rate
+pub const FLAG_THROTTLE_REJECT: u16 = 1 << 5;
+
+impl From<RejectReason> for u8 {
+    fn from(reason: RejectReason) -> Self {
+        reason as u8
+    }
+}
+
+impl From<u8> for RejectReason {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => RejectReason::None,
+            2 => RejectReason::EngineUnavailable,
+            3 => RejectReason::OutsidePartition,
+            4 => RejectReason::Throttled,
+            5 => RejectReason::QuantityLimit,
+            6 => RejectReason::NotionalLimit,
+            7 => RejectReason::OpenOrdersLimit,
+            8 => RejectReason::ExposureLimit,
+            9 => RejectReason::OutOfSequence,
+            10 => RejectReason::NotEntitled,
+            11 => RejectReason::UnknownBook,
+            12 => RejectReason::AmbiguousClientOrderId,
+            13 => RejectReason::DuplicateClientOrderId,
+            _ => RejectReason::Unspecified,
+        }
+    }
 }
 
 impl ExecutionReport {
@@ -41,17 +120,123 @@ impl ExecutionReport {
     pub fn get_quantity(&self) -> u64 {
         self.quantity
     }
+
+    pub fn get_filled_quantity(&self) -> u64 {
+        self.filled_quantity
+    }
+
+    pub fn get_leaves_quantity(&self) -> u64 {
+        self.leaves_quantity
+    }
+
+    pub fn get_orig_order_id(&self) -> u64 {
+        self.orig_order_id
+    }
+
+    pub fn get_trade_id(&self) -> u64 {
+        self.trade_id
+    }
+
+    pub fn get_reject_reason(&self) -> RejectReason {
+        self.reject_reason.into()
+    }
+
+    pub fn get_flags(&self) -> u16 {
+        self.flags
+    }
+
+    fn has_flag(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// The order took liquidity, trading as it came in
+    pub fn is_aggressor(&self) -> bool {
+        self.has_flag(FLAG_AGGRESSOR)
+    }
+
+    /// The event traded only part of the order
+    pub fn is_partial_fill(&self) -> bool {
+        self.has_flag(FLAG_PARTIAL_FILL)
+    }
+
+    /// The order was cancelled as its session disconnected
+    pub fn is_cancel_on_disconnect(&self) -> bool {
+        self.has_flag(FLAG_CANCEL_ON_DISCONNECT)
+    }
+
+    /// The order was cancelled to prevent a self-trade
+    pub fn is_self_trade_cancel(&self) -> bool {
+        self.has_flag(FLAG_SELF_TRADE_CANCEL)
+    }
+
+    /// The message was rejected for a price out of the price bands
+    pub fn is_band_reject(&self) -> bool {
+        self.has_flag(FLAG_BAND_REJECT)
+    }
+
+    /// The message was rejected by the rate limit of the gateway
+    pub fn is_throttle_reject(&self) -> bool {
+        self.has_flag(FLAG_THROTTLE_REJECT)
+    }
 }
 
 pub const EXECUTIONREPORT_SIZE: usize = std::mem::size_of::<ExecutionReport>();
+// without the trade_id
+pub const EXECUTIONREPORT_V6_SIZE: usize = EXECUTIONREPORT_SIZE - 8;
+
+/// The size of an execution report sent in @oep_version, the first bytes of
+/// its encoding
+pub fn execution_report_size(oep_version: u16) -> usize {
+    match oep_version {
+        5 | 6 => EXECUTIONREPORT_V6_SIZE,
+        _ => EXECUTIONREPORT_SIZE,
+    }
+}
 
 impl Decoder<EXECUTIONREPORT_SIZE> for ExecutionReport {
     fn encode(self) -> [u8; EXECUTIONREPORT_SIZE] {
-        unsafe { std::mem::transmute::<Self, [u8; EXECUTIONREPORT_SIZE]>(self) }
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.order_id)
+            .put(self.submitted_order_id)
+            .put(self.book)
+            .put(self.quantity)
+            .put(self.price)
+            .put(self.flags)
+            .put(self.side)
+            .put(self.state)
+            .put(self.session_id)
+            .put(self.gateway_id)
+            .put(self.filled_quantity)
+            .put(self.leaves_quantity)
+            .put(self.orig_order_id)
+            .put(self.partition_id)
+            .put(self.reject_reason)
+            .put(self.trade_id)
+            .finish()
     }
 
     fn decode(buffer: [u8; EXECUTIONREPORT_SIZE]) -> Result<Self, Box<dyn Error>> {
-        unsafe { Ok(std::mem::transmute::<[u8; EXECUTIONREPORT_SIZE], Self>(buffer).try_into()?) }
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            order_id: reader.get()?,
+            submitted_order_id: reader.get()?,
+            book: reader.get()?,
+            quantity: reader.get()?,
+            price: reader.get()?,
+            flags: reader.get()?,
+            side: reader.get()?,
+            state: reader.get()?,
+            session_id: reader.get()?,
+            gateway_id: reader.get()?,
+            filled_quantity: reader.get()?,
+            leaves_quantity: reader.get()?,
+            orig_order_id: reader.get()?,
+            partition_id: reader.get()?,
+            reject_reason: reader.get()?,
+            trade_id: reader.get()?,
+        })
     }
 }
 
@@ -94,18 +279,24 @@ mod tests {
             state: 2,
             session_id: 33333,
             gateway_id: 5,
+            filled_quantity: 40,
+            leaves_quantity: 60,
+            orig_order_id: 55555,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
         };
 
-        assert_eq!(er.participant as u64, 12345);
-        assert_eq!(er.order_id as u64, 67890);
-        assert_eq!(er.submitted_order_id as u64, 11111);
-        assert_eq!(er.book as u64, 22222);
-        assert_eq!(er.quantity as u64, 100);
-        assert_eq!(er.price as u64, 1000);
+        assert_eq!({ er.participant }, 12345);
+        assert_eq!({ er.order_id }, 67890);
+        assert_eq!({ er.submitted_order_id }, 11111);
+        assert_eq!({ er.book }, 22222);
+        assert_eq!({ er.quantity }, 100);
+        assert_eq!({ er.price }, 1000);
         assert_eq!(er.flags as u32, 0);
         assert_eq!(er.side, 1);
         assert_eq!(er.state, 2);
-        assert_eq!(er.session_id as u32, 33333);
+        assert_eq!({ er.session_id }, 33333);
         assert_eq!(er.gateway_id, 5);
     }
 
@@ -123,6 +314,12 @@ mod tests {
             state: 2,
             session_id: 33333,
             gateway_id: 5,
+            filled_quantity: 40,
+            leaves_quantity: 60,
+            orig_order_id: 55555,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
         };
 
         assert_eq!(er.get_book(), 22222);
@@ -130,6 +327,54 @@ mod tests {
         assert_eq!(er.get_submitted_order_id(), 11111);
         assert_eq!(er.get_order_id(), 67890);
         assert_eq!(er.get_quantity(), 100);
+        assert_eq!(er.get_filled_quantity(), 40);
+        assert_eq!(er.get_leaves_quantity(), 60);
+        assert_eq!(er.get_orig_order_id(), 55555);
+        assert_eq!(er.get_flags(), 0);
+        assert!(!er.is_aggressor());
+        assert!(!er.is_throttle_reject());
+    }
+
+    #[test]
+    fn test_flags() {
+        let er = ExecutionReport {
+            participant: 12345,
+            order_id: 67890,
+            submitted_order_id: 11111,
+            book: 22222,
+            quantity: 100,
+            price: 1000,
+            flags: FLAG_AGGRESSOR | FLAG_PARTIAL_FILL,
+            side: 1,
+            state: 2,
+            session_id: 33333,
+            gateway_id: 5,
+            filled_quantity: 40,
+            leaves_quantity: 60,
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
+        };
+        assert!(er.is_aggressor());
+        assert!(er.is_partial_fill());
+        assert!(!er.is_cancel_on_disconnect());
+        assert!(!er.is_self_trade_cancel());
+        assert!(!er.is_band_reject());
+        assert!(!er.is_throttle_reject());
+
+        let decoded = ExecutionReport::decode(
+            ExecutionReport {
+                flags: FLAG_CANCEL_ON_DISCONNECT | FLAG_BAND_REJECT | FLAG_THROTTLE_REJECT,
+                ..er
+            }
+            .encode(),
+        )
+        .unwrap();
+        assert!(!decoded.is_aggressor());
+        assert!(decoded.is_cancel_on_disconnect());
+        assert!(decoded.is_band_reject());
+        assert!(decoded.is_throttle_reject());
     }
 
     #[test]
@@ -146,25 +391,35 @@ mod tests {
             state: 2,
             session_id: 33333,
             gateway_id: 5,
+            filled_quantity: 40,
+            leaves_quantity: 60,
+            orig_order_id: 55555,
+            partition_id: 7,
+            reject_reason: RejectReason::OutsidePartition.into(),
+            trade_id: 0,
         };
 
         let encoded = original.encode();
         let decoded = ExecutionReport::decode(encoded).unwrap();
 
-        assert_eq!(original.participant as u64, decoded.participant as u64);
-        assert_eq!(original.order_id as u64, decoded.order_id as u64);
-        assert_eq!(
-            original.submitted_order_id as u64,
-            decoded.submitted_order_id as u64
-        );
-        assert_eq!(original.book as u64, decoded.book as u64);
-        assert_eq!(original.quantity as u64, decoded.quantity as u64);
-        assert_eq!(original.price as u64, decoded.price as u64);
-        assert_eq!(original.flags as u16, decoded.flags as u16);
+        assert_eq!({ original.participant }, { decoded.participant });
+        assert_eq!({ original.order_id }, { decoded.order_id });
+        assert_eq!({ original.submitted_order_id }, {
+            decoded.submitted_order_id
+        });
+        assert_eq!({ original.book }, { decoded.book });
+        assert_eq!({ original.quantity }, { decoded.quantity });
+        assert_eq!({ original.price }, { decoded.price });
+        assert_eq!({ original.flags }, { decoded.flags });
         assert_eq!(original.side, decoded.side);
         assert_eq!(original.state, decoded.state);
-        assert_eq!(original.session_id as u32, decoded.session_id as u32);
+        assert_eq!({ original.session_id }, { decoded.session_id });
         assert_eq!(original.gateway_id, decoded.gateway_id);
+        assert_eq!({ original.filled_quantity }, { decoded.filled_quantity });
+        assert_eq!({ original.leaves_quantity }, { decoded.leaves_quantity });
+        assert_eq!({ original.orig_order_id }, { decoded.orig_order_id });
+        assert_eq!(7, decoded.partition_id);
+        assert_eq!(RejectReason::OutsidePartition, decoded.get_reject_reason());
     }
 
     #[test]
@@ -181,6 +436,12 @@ mod tests {
             state: 2,
             session_id: 33333,
             gateway_id: 5,
+            filled_quantity: 40,
+            leaves_quantity: 60,
+            orig_order_id: 55555,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
         };
 
         assert_eq!(er.message_type(), MsgType::ExecutionReport);
@@ -203,11 +464,17 @@ mod tests {
             state: 2,
             session_id: 33333,
             gateway_id: 5,
+            filled_quantity: 40,
+            leaves_quantity: 60,
+            orig_order_id: 55555,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
         };
 
         let any = er.as_any();
         let downcast = any.downcast_ref::<ExecutionReport>();
         assert!(downcast.is_some());
-        assert_eq!(downcast.unwrap().order_id as u64, 67890);
+        assert_eq!({ downcast.unwrap().order_id }, 67890);
     }
 }
@@ -1,16 +1,28 @@
 use std::error::Error;
 
-use crate::{decoder::Decoder, oep_message::MsgType};
+use crate::{
+    decoder::{Decoder, FieldReader, FieldWriter},
+    oep_message::MsgType,
+};
 
+/// Prefixes every OEP message
+///
+/// The prices of all the messages are integers, scaled by 10^price_decimals
+/// of their instrument (see instruments::price): with 2 decimals, 123.45
+/// travels as 12345. The quantities are not scaled
 #[repr(packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct OepHeader {
     pub oep_version: u16,
     pub msg_type: u16,
     pub msg_len: u32,
+    // sequence of the message in its session, 0 for the unsequenced ones
+    pub seq: u32,
 }
 
-pub const OEP_VERSION: u16 = 1;
+// bumped on every change of a message layout, the newest version spoken. See
+// version.rs for how the version of a session is negotiated
+pub const OEP_VERSION: u16 = 7;
 pub const OEP_HEADER_SIZE: usize = std::mem::size_of::<OepHeader>();
 
 impl OepHeader {
@@ -19,28 +31,38 @@ impl OepHeader {
             oep_version: oep_version,
             msg_type: msg_type,
             msg_len: msg_len,
+            seq: 0,
         }
     }
 
+    pub fn with_seq(mut self, seq: u32) -> Self {
+        self.seq = seq;
+        self
+    }
+
     pub fn message_type(&self) -> MsgType {
-        match self.msg_type {
-            0 => MsgType::NewOrder,
-            1 => MsgType::Modify,
-            2 => MsgType::Cancel,
-            3 => MsgType::ExecutionReport,
-            4 => MsgType::Login,
-            _ => MsgType::Unknown,
-        }
+        self.msg_type.into()
     }
 }
 
 impl Decoder<OEP_HEADER_SIZE> for OepHeader {
     fn encode(self) -> [u8; OEP_HEADER_SIZE] {
-        unsafe { std::mem::transmute::<Self, [u8; OEP_HEADER_SIZE]>(self) }
+        FieldWriter::default()
+            .put(self.oep_version)
+            .put(self.msg_type)
+            .put(self.msg_len)
+            .put(self.seq)
+            .finish()
     }
 
     fn decode(buffer: [u8; OEP_HEADER_SIZE]) -> Result<Self, Box<dyn Error>> {
-        unsafe { Ok(std::mem::transmute::<[u8; OEP_HEADER_SIZE], Self>(buffer).try_into()?) }
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            oep_version: reader.get()?,
+            msg_type: reader.get()?,
+            msg_len: reader.get()?,
+            seq: reader.get()?,
+        })
     }
 }
 
@@ -52,25 +74,34 @@ mod tests {
 
     #[test]
     fn decode() {
-        let header_bytes = [1, 0, 2, 0, 20, 0, 0, 0];
+        let header_bytes = [1, 0, 2, 0, 20, 0, 0, 0, 44, 1, 0, 0];
         let boxed_target = OepHeader::decode(header_bytes);
         assert!(boxed_target.is_ok());
         let target = boxed_target.unwrap();
         let oep_version = target.oep_version;
         let msg_type = target.msg_type;
         let msg_len = target.msg_len;
+        let seq = target.seq;
 
         assert_eq!(oep_version, 1);
         assert_eq!(msg_type, 2);
         assert_eq!(msg_len, 20);
+        assert_eq!(seq, 300);
     }
 
     #[test]
     fn deduce_login() {
-        let header_bytes = [1, 0, 4, 0, 20, 0, 0, 0];
+        let header_bytes = [1, 0, 4, 0, 20, 0, 0, 0, 0, 0, 0, 0];
         let target = OepHeader::decode(header_bytes);
 
         assert!(target.is_ok());
         assert_eq!(target.unwrap().message_type(), MsgType::Login);
     }
+
+    #[test]
+    fn encode_with_seq() {
+        let target = OepHeader::new(5, 0, 72).with_seq(0x01020304);
+        assert_eq!([5, 0, 0, 0, 72, 0, 0, 0, 4, 3, 2, 1], target.encode());
+        assert_eq!(0, { OepHeader::new(5, 0, 72).seq });
+    }
 }
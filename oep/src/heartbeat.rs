@@ -0,0 +1,92 @@
+use crate::{
+    decoder::{FieldReader, FieldWriter},
+    oep_message::{MsgType, OepMessage},
+    Decoder,
+};
+use std::error::Error;
+
+/// Sent by the clients when they have nothing else to send, so that the
+/// gateway doesn't time their session out
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    pub participant: u64,
+    pub session_id: u32,
+    pub gateway_id: u8,
+}
+
+impl Heartbeat {
+    pub fn new(participant: u64, session_id: u32, gateway_id: u8) -> Self {
+        Self {
+            participant,
+            session_id,
+            gateway_id,
+        }
+    }
+}
+
+pub const HEARTBEAT_SIZE: usize = std::mem::size_of::<Heartbeat>();
+
+impl Decoder<HEARTBEAT_SIZE> for Heartbeat {
+    fn encode(self) -> [u8; HEARTBEAT_SIZE] {
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.session_id)
+            .put(self.gateway_id)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; HEARTBEAT_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            session_id: reader.get()?,
+            gateway_id: reader.get()?,
+        })
+    }
+}
+
+impl OepMessage for Heartbeat {
+    fn message_type(&self) -> MsgType {
+        MsgType::Heartbeat
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_gateway_id(&self) -> u8 {
+        self.gateway_id
+    }
+
+    fn get_session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    fn get_participant(&self) -> u64 {
+        self.participant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = Heartbeat::new(0x0102030405060708, 600, 1);
+
+        let encoded = original.encode();
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1, 88, 2, 0, 0, 1], encoded);
+        let decoded = Heartbeat::decode(encoded).unwrap();
+
+        assert_eq!({ original.participant }, { decoded.participant });
+        assert_eq!({ original.session_id }, { decoded.session_id });
+        assert_eq!({ original.gateway_id }, { decoded.gateway_id });
+    }
+
+    #[test]
+    fn test_heartbeat_size() {
+        assert_eq!(13, HEARTBEAT_SIZE);
+    }
+}
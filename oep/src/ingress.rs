@@ -0,0 +1,208 @@
+use std::{error::Error, str::FromStr};
+
+use anyhow::bail;
+
+use crate::decoder::{DecodeError, Decoder, FieldReader, FieldWriter};
+
+/// How the gateways get the client messages to the matching engines, the
+/// `ingress` key of both the gateway and the engine configurations
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum IngressMode {
+    // plain multicast, a lost datagram is a lost message
+    #[default]
+    Multicast,
+    // multicast framed with an IngressHeader, the engines asking the gateways
+    // for the lost messages with an IngressNak
+    Sequenced,
+}
+
+impl FromStr for IngressMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "multicast" => Ok(IngressMode::Multicast),
+            "sequenced" => Ok(IngressMode::Sequenced),
+            _ => bail!("Unknown ingress mode {s}"),
+        }
+    }
+}
+
+/// What an ingress frame carries
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum IngressKind {
+    // a message for the matching engines follows the header
+    Message,
+    // nothing follows, @seq is the one of the next message. Lets the engines
+    // notice the loss of the last messages sent
+    Heartbeat,
+    // nothing follows, the messages before @seq are gone for good
+    Reset,
+}
+
+impl From<IngressKind> for u8 {
+    fn from(value: IngressKind) -> Self {
+        match value {
+            IngressKind::Message => 0,
+            IngressKind::Heartbeat => 1,
+            IngressKind::Reset => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for IngressKind {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(IngressKind::Message),
+            1 => Ok(IngressKind::Heartbeat),
+            2 => Ok(IngressKind::Reset),
+            _ => Err(DecodeError),
+        }
+    }
+}
+
+/// Prefixes what a gateway sends to the matching engines when the ingress
+/// is sequenced, so that the engines can tell the lost messages and ask for
+/// them again
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct IngressHeader {
+    pub gateway_id: u8,
+    kind: u8, // see IngressKind
+    // tells the runs of a gateway apart, its sequence starting again with 1 on each
+    pub epoch: u32,
+    pub seq: u64,
+}
+
+impl IngressHeader {
+    pub fn new(gateway_id: u8, kind: IngressKind, epoch: u32, seq: u64) -> Self {
+        Self {
+            gateway_id,
+            kind: kind.into(),
+            epoch,
+            seq,
+        }
+    }
+
+    pub fn get_kind(&self) -> IngressKind {
+        // always valid, since it was checked when decoding
+        self.kind.try_into().unwrap()
+    }
+}
+
+pub const INGRESSHEADER_SIZE: usize = std::mem::size_of::<IngressHeader>();
+
+impl Decoder<INGRESSHEADER_SIZE> for IngressHeader {
+    fn encode(self) -> [u8; INGRESSHEADER_SIZE] {
+        FieldWriter::default()
+            .put(self.gateway_id)
+            .put(self.kind)
+            .put(self.epoch)
+            .put(self.seq)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; INGRESSHEADER_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        let header = Self {
+            gateway_id: reader.get()?,
+            kind: reader.get()?,
+            epoch: reader.get()?,
+            seq: reader.get()?,
+        };
+        IngressKind::try_from(header.kind)?;
+        Ok(header)
+    }
+}
+
+/// Sent by a matching engine to the gateways on the internal publisher
+/// channel, asking @gateway_id for its messages starting with @from_seq again
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct IngressNak {
+    pub engine_id: u8,
+    pub gateway_id: u8,
+    pub epoch: u32,
+    pub from_seq: u64,
+}
+
+impl IngressNak {
+    pub fn new(engine_id: u8, gateway_id: u8, epoch: u32, from_seq: u64) -> Self {
+        Self {
+            engine_id,
+            gateway_id,
+            epoch,
+            from_seq,
+        }
+    }
+}
+
+pub const INGRESSNAK_SIZE: usize = std::mem::size_of::<IngressNak>();
+
+impl Decoder<INGRESSNAK_SIZE> for IngressNak {
+    fn encode(self) -> [u8; INGRESSNAK_SIZE] {
+        FieldWriter::default()
+            .put(self.engine_id)
+            .put(self.gateway_id)
+            .put(self.epoch)
+            .put(self.from_seq)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; INGRESSNAK_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            engine_id: reader.get()?,
+            gateway_id: reader.get()?,
+            epoch: reader.get()?,
+            from_seq: reader.get()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ingress_mode() {
+        assert_eq!(IngressMode::Multicast, "multicast".parse().unwrap());
+        assert_eq!(IngressMode::Sequenced, "Sequenced".parse().unwrap());
+        assert!("tcp".parse::<IngressMode>().is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_header() {
+        let original = IngressHeader::new(3, IngressKind::Heartbeat, 0x01020304, 300);
+
+        let encoded = original.encode();
+        assert_eq!([3, 1, 4, 3, 2, 1, 44, 1, 0, 0, 0, 0, 0, 0], encoded);
+        let decoded = IngressHeader::decode(encoded).unwrap();
+
+        assert_eq!(3, decoded.gateway_id);
+        assert_eq!(IngressKind::Heartbeat, decoded.get_kind());
+        assert_eq!(0x01020304, { decoded.epoch });
+        assert_eq!(300, { decoded.seq });
+    }
+
+    #[test]
+    fn test_decode_invalid_kind() {
+        assert!(IngressHeader::decode([3, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_nak() {
+        let original = IngressNak::new(2, 3, 0x01020304, 300);
+
+        let encoded = original.encode();
+        assert_eq!([2, 3, 4, 3, 2, 1, 44, 1, 0, 0, 0, 0, 0, 0], encoded);
+        let decoded = IngressNak::decode(encoded).unwrap();
+
+        assert_eq!(2, decoded.engine_id);
+        assert_eq!(3, decoded.gateway_id);
+        assert_eq!(0x01020304, { decoded.epoch });
+        assert_eq!(300, { decoded.from_seq });
+    }
+}
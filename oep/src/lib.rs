@@ -1,55 +1,160 @@
-use cancel::{Cancel, CANCEL_SIZE};
+use cancel::{Cancel, CANCEL_SIZE, CANCEL_V5_SIZE};
 use decoder::Decoder;
-use execution_report::{ExecutionReport, EXECUTIONREPORT_SIZE};
+use execution_report::{ExecutionReport, EXECUTIONREPORT_SIZE, EXECUTIONREPORT_V6_SIZE};
 use header::{OepHeader, OEP_HEADER_SIZE};
+use heartbeat::{Heartbeat, HEARTBEAT_SIZE};
 use login::{Login, LOGIN_SIZE};
-use modify::{Modify, MODIFY_SIZE};
+use loginreject::{LoginReject, LOGINREJECT_SIZE};
+use masscancel::{MassCancel, MASSCANCEL_SIZE};
+use massquote::{MassQuote, MASSQUOTE_SIZE};
+use modify::{Modify, MODIFY_SIZE, MODIFY_V5_SIZE};
 use neworder::{NewOrder, NEWORDER_SIZE};
 use oep_message::{MsgType, OepMessage};
+use quote::{Quote, QUOTE_SIZE};
+use quotecancelall::{QuoteCancelAll, QUOTECANCELALL_SIZE};
+use replace::{Replace, REPLACE_SIZE};
+use resendrequest::{ResendRequest, RESENDREQUEST_SIZE};
+use version::{OepError, VersionReject, VERSIONREJECT_SIZE};
 
+pub mod auctioninfo;
 pub mod cancel;
 pub mod connection;
 pub mod decoder;
+pub mod delisting;
+pub mod egress;
+pub mod engine_status;
+pub mod eodsummary;
 pub mod execution_report;
 pub mod header;
+pub mod heartbeat;
+pub mod ingress;
 pub mod login;
+pub mod loginreject;
+pub mod masscancel;
+pub mod massquote;
+pub mod messagebuffer;
 pub mod modify;
 pub mod neworder;
 pub mod oep_message;
+pub mod ordertracker;
+pub mod quote;
+pub mod quotecancelall;
+pub mod registration;
+pub mod replace;
+pub mod resendrequest;
+pub mod segmentstate;
 pub mod sessioninfo;
+pub mod statechange;
+pub mod statistics;
 pub mod trade;
+pub mod tradebust;
+pub mod tradecapture;
+pub mod version;
 
+#[cfg(test)]
 mod tests;
 
 /// converts Err from std::error::Error to std::io::Error
 /// used by oep_decode, since msg::decode can return a broader range of errors
 fn convert_decode_error<T>(m: Result<T, Box<dyn std::error::Error>>) -> Result<T, std::io::Error> {
-    match m {
-        Ok(r) => Ok(r),
-        Err(e) => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                e.to_string(),
-            ))
+    m.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Decodes @body as a message M, checking its length against the one of M
+fn decode_body<M, const S: usize>(
+    header: &OepHeader,
+    body: &[u8],
+) -> Result<Box<dyn OepMessage>, std::io::Error>
+where
+    M: Decoder<S> + OepMessage + 'static,
+{
+    let Ok(inner_buffer) = <[u8; S]>::try_from(body) else {
+        return Err(OepError::InvalidLength {
+            msg_type: header.msg_type,
+            expected: S,
+            received: body.len(),
         }
-    }
+        .into());
+    };
+    Ok(Box::new(convert_decode_error(M::decode(inner_buffer))?))
 }
 
-fn convert_slicing_error<T, E>(m: Result<T, E>) -> Result<T, std::io::Error>
+/// Decodes @body as a message M of an older version, the first @size bytes of
+/// M, the fields added since taking their zero value
+fn decode_prefix<M, const S: usize>(
+    header: &OepHeader,
+    body: &[u8],
+    size: usize,
+) -> Result<Box<dyn OepMessage>, std::io::Error>
 where
-    E: std::error::Error,
+    M: Decoder<S> + OepMessage + 'static,
 {
-    match m {
-        Ok(r) => Ok(r),
-        Err(e) => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                e.to_string(),
-            ))
+    if body.len() != size {
+        return Err(OepError::InvalidLength {
+            msg_type: header.msg_type,
+            expected: size,
+            received: body.len(),
         }
+        .into());
     }
+    let mut padded = [0; S];
+    padded[..size].copy_from_slice(body);
+    decode_body::<M, S>(header, &padded)
 }
 
+/// Decodes the messages of version 5, the ones of version 6 but for the
+/// modify and the cancel, without their orig_client_order_id
+fn decode_v5(header: &OepHeader, body: &[u8]) -> Result<Box<dyn OepMessage>, std::io::Error> {
+    match header.message_type() {
+        MsgType::Modify => decode_prefix::<Modify, MODIFY_SIZE>(header, body, MODIFY_V5_SIZE),
+        MsgType::Cancel => decode_prefix::<Cancel, CANCEL_SIZE>(header, body, CANCEL_V5_SIZE),
+        _ => decode_v6(header, body),
+    }
+}
+
+/// Decodes the messages of version 6, the ones of version 7 but for the
+/// execution report, without its trade_id
+fn decode_v6(header: &OepHeader, body: &[u8]) -> Result<Box<dyn OepMessage>, std::io::Error> {
+    match header.message_type() {
+        MsgType::ExecutionReport => decode_prefix::<ExecutionReport, EXECUTIONREPORT_SIZE>(
+            header,
+            body,
+            EXECUTIONREPORT_V6_SIZE,
+        ),
+        _ => decode_v7(header, body),
+    }
+}
+
+/// Decodes the messages of version 7
+fn decode_v7(header: &OepHeader, body: &[u8]) -> Result<Box<dyn OepMessage>, std::io::Error> {
+    match header.message_type() {
+        MsgType::NewOrder => decode_body::<NewOrder, NEWORDER_SIZE>(header, body),
+        MsgType::Modify => decode_body::<Modify, MODIFY_SIZE>(header, body),
+        MsgType::Cancel => decode_body::<Cancel, CANCEL_SIZE>(header, body),
+        MsgType::Replace => decode_body::<Replace, REPLACE_SIZE>(header, body),
+        MsgType::MassCancel => decode_body::<MassCancel, MASSCANCEL_SIZE>(header, body),
+        MsgType::Quote => decode_body::<Quote, QUOTE_SIZE>(header, body),
+        MsgType::MassQuote => decode_body::<MassQuote, MASSQUOTE_SIZE>(header, body),
+        MsgType::QuoteCancelAll => decode_body::<QuoteCancelAll, QUOTECANCELALL_SIZE>(header, body),
+        MsgType::ExecutionReport => {
+            decode_body::<ExecutionReport, EXECUTIONREPORT_SIZE>(header, body)
+        }
+        MsgType::Heartbeat => decode_body::<Heartbeat, HEARTBEAT_SIZE>(header, body),
+        MsgType::ResendRequest => decode_body::<ResendRequest, RESENDREQUEST_SIZE>(header, body),
+        MsgType::Trade => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Trade cannot be sent on this message pipe",
+        )),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Unknown message type",
+        )),
+    }
+}
+
+/// Decodes the message at the start of @buffer, in the version found in its
+/// header. The errors coming from the version or the length of the message
+/// carry an OepError
 pub fn oep_decode(buffer: &[u8]) -> Result<Box<dyn OepMessage>, std::io::Error> {
     if buffer.len() < OEP_HEADER_SIZE {
         return Err(std::io::Error::new(
@@ -57,56 +162,26 @@ pub fn oep_decode(buffer: &[u8]) -> Result<Box<dyn OepMessage>, std::io::Error>
             "incomplete",
         ));
     }
-    let header_buffer: [u8; OEP_HEADER_SIZE] =
-        convert_slicing_error(buffer[..OEP_HEADER_SIZE].try_into())?;
+    let header_buffer: [u8; OEP_HEADER_SIZE] = buffer[..OEP_HEADER_SIZE].try_into().unwrap();
     let header = convert_decode_error(OepHeader::decode(header_buffer))?;
-    if buffer.len() < header.msg_len as usize + OEP_HEADER_SIZE {
+    let len = OEP_HEADER_SIZE + header.msg_len as usize;
+    if buffer.len() < len {
         return Err(std::io::Error::new(
             std::io::ErrorKind::UnexpectedEof,
             "incomplete",
         ));
     }
+    let body = &buffer[OEP_HEADER_SIZE..len];
     match header.message_type() {
-        MsgType::Login => {
-            let inner_buffer: [u8; LOGIN_SIZE] =
-                convert_slicing_error(buffer[OEP_HEADER_SIZE..].try_into())?;
-            Ok(Box::new(convert_decode_error(Login::decode(inner_buffer))?))
-        }
-        MsgType::NewOrder => {
-            let inner_buffer: [u8; NEWORDER_SIZE] =
-                convert_slicing_error(buffer[OEP_HEADER_SIZE..].try_into())?;
-            Ok(Box::new(convert_decode_error(NewOrder::decode(
-                inner_buffer,
-            ))?))
-        }
-        MsgType::Modify => {
-            let inner_buffer: [u8; MODIFY_SIZE] =
-                convert_slicing_error(buffer[OEP_HEADER_SIZE..].try_into())?;
-            Ok(Box::new(convert_decode_error(Modify::decode(
-                inner_buffer,
-            ))?))
-        }
-        MsgType::Cancel => {
-            let inner_buffer: [u8; CANCEL_SIZE] =
-                convert_slicing_error(buffer[OEP_HEADER_SIZE..].try_into())?;
-            Ok(Box::new(convert_decode_error(Cancel::decode(
-                inner_buffer,
-            ))?))
-        }
-        MsgType::ExecutionReport => {
-            let inner_buffer: [u8; EXECUTIONREPORT_SIZE] =
-                convert_slicing_error(buffer[OEP_HEADER_SIZE..].try_into())?;
-            Ok(Box::new(convert_decode_error(ExecutionReport::decode(
-                inner_buffer,
-            ))?))
-        }
-        MsgType::Trade => Err(std::io::Error::new(
-            std::io::ErrorKind::Unsupported,
-            "Trade cannot be sent on this message pipe",
-        )),
-        _ => Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Unknown message type",
-        )),
+        // the same in all the versions, so that the version can be negotiated
+        MsgType::Login => decode_body::<Login, LOGIN_SIZE>(&header, body),
+        MsgType::VersionReject => decode_body::<VersionReject, VERSIONREJECT_SIZE>(&header, body),
+        MsgType::LoginReject => decode_body::<LoginReject, LOGINREJECT_SIZE>(&header, body),
+        _ => match header.oep_version {
+            5 => decode_v5(&header, body),
+            6 => decode_v6(&header, body),
+            7 => decode_v7(&header, body),
+            version => Err(OepError::UnsupportedVersion(version).into()),
+        },
     }
 }
@@ -1,7 +1,7 @@
 use std::{error::Error, ffi::CString};
 
 use crate::{
-    decoder::Decoder,
+    decoder::{Decoder, FieldReader, FieldWriter},
     oep_message::{MsgType, OepMessage},
 };
 use sha2::{Digest, Sha512};
@@ -12,7 +12,10 @@ pub struct Login {
     pub participant: u64,
     pub session_id: u32,
     gateway_id: u8,
-    _padding: [u8; 3],
+    // 1 for the orders of the session to stay in the book when it
+    // disconnects, 0 (as the former padding) for them to be cancelled
+    pub keep_orders_on_disconnect: u8,
+    _padding: [u8; 2],
     pub user: [u8; 64],
     pub password: [u8; 64],
 }
@@ -20,10 +23,11 @@ pub struct Login {
 impl Login {
     pub fn new(participant: u64, session_id: u32, gateway_id: u8, user: &str) -> Self {
         let mut r = Self {
-            participant: participant,
-            session_id: session_id,
-            gateway_id: gateway_id,
-            _padding: [0, 0, 0],
+            participant,
+            session_id,
+            gateway_id,
+            keep_orders_on_disconnect: 0,
+            _padding: [0, 0],
             user: [0; 64],
             password: [0; 64],
         };
@@ -36,6 +40,17 @@ impl Login {
         r
     }
 
+    /// The same login, with the orders of the session kept in the book when
+    /// it disconnects unless @cancel
+    pub fn with_cancel_on_disconnect(mut self, cancel: bool) -> Self {
+        self.keep_orders_on_disconnect = (!cancel).into();
+        self
+    }
+
+    pub fn cancels_on_disconnect(&self) -> bool {
+        self.keep_orders_on_disconnect == 0
+    }
+
     pub fn hash_text_to_password(&mut self, text: &str) {
         self.password = Login::free_text_hash(text);
     }
@@ -55,11 +70,28 @@ pub const LOGIN_SIZE: usize = std::mem::size_of::<Login>();
 
 impl Decoder<LOGIN_SIZE> for Login {
     fn encode(self) -> [u8; LOGIN_SIZE] {
-        unsafe { std::mem::transmute::<Self, [u8; LOGIN_SIZE]>(self) }
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.session_id)
+            .put(self.gateway_id)
+            .put(self.keep_orders_on_disconnect)
+            .put(self._padding)
+            .put(self.user)
+            .put(self.password)
+            .finish()
     }
 
     fn decode(buffer: [u8; LOGIN_SIZE]) -> Result<Self, Box<dyn Error>> {
-        unsafe { Ok(std::mem::transmute::<[u8; LOGIN_SIZE], Self>(buffer).try_into()?) }
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            session_id: reader.get()?,
+            gateway_id: reader.get()?,
+            keep_orders_on_disconnect: reader.get()?,
+            _padding: reader.get()?,
+            user: reader.get()?,
+            password: reader.get()?,
+        })
     }
 }
 
@@ -85,7 +117,35 @@ impl OepMessage for Login {
 
 #[cfg(test)]
 mod tests {
-    use super::Login;
+    use super::{Login, LOGIN_SIZE};
+    use crate::{decoder::Decoder, oep_message::OepMessage};
+
+    #[test]
+    fn cancel_on_disconnect() {
+        let target = Login::new(1, 2, 3, "user");
+        assert!(target.cancels_on_disconnect());
+        assert_eq!([3, 0, 0, 0], target.encode()[12..16]);
+        let encoded = target.with_cancel_on_disconnect(false).encode();
+        // in the first byte of what used to be padding
+        assert_eq!([3, 1, 0, 0], encoded[12..16]);
+        assert!(!Login::decode(encoded).unwrap().cancels_on_disconnect());
+        assert!(Login::decode(target.encode())
+            .unwrap()
+            .cancels_on_disconnect());
+    }
+
+    #[test]
+    fn legacy_login_cancels_on_disconnect() {
+        // as sent before the flag, the gateway id followed by zeros
+        let mut buffer = [0; LOGIN_SIZE];
+        buffer[0..8].copy_from_slice(&7u64.to_le_bytes());
+        buffer[8..12].copy_from_slice(&2u32.to_le_bytes());
+        buffer[12..16].copy_from_slice(&3u32.to_le_bytes());
+        buffer[16..20].copy_from_slice(b"user");
+        let decoded = Login::decode(buffer).unwrap();
+        assert_eq!(3, decoded.get_gateway_id());
+        assert!(decoded.cancels_on_disconnect());
+    }
 
     #[test]
     fn hash_password() {
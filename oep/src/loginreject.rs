@@ -0,0 +1,186 @@
+//! Why a login was refused
+//!
+//! The gateway answers a login it refuses with a LoginReject, or with a
+//! VersionReject when no version is spoken by both ends, then closes the
+//! connection. Like the login, the LoginReject keeps its layout in all the
+//! versions. Connection::wait_for_login returns both as a LoginError.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+use crate::{
+    decoder::{Decoder, FieldReader, FieldWriter},
+    oep_message::{MsgType, OepMessage},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginRejectReason {
+    Unspecified = 0,
+    // unknown user, wrong password, or a session the user can't log in with
+    BadCredentials = 1,
+    // the session is logged in on another connection
+    DuplicateSession = 2,
+    // sent as a VersionReject, see oep::version
+    VersionMismatch = 3,
+    // the session went over the message rate of its gateway listener
+    Throttled = 4,
+}
+
+impl From<LoginRejectReason> for u8 {
+    fn from(reason: LoginRejectReason) -> Self {
+        reason as u8
+    }
+}
+
+impl From<u8> for LoginRejectReason {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => LoginRejectReason::BadCredentials,
+            2 => LoginRejectReason::DuplicateSession,
+            3 => LoginRejectReason::VersionMismatch,
+            4 => LoginRejectReason::Throttled,
+            _ => LoginRejectReason::Unspecified,
+        }
+    }
+}
+
+/// Sent by the gateway instead of the login reply. The connection is closed after it
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct LoginReject {
+    pub participant: u64,
+    pub session_id: u32,
+    pub gateway_id: u8,
+    pub reason: u8, // see LoginRejectReason
+}
+
+impl LoginReject {
+    pub fn new(
+        participant: u64,
+        session_id: u32,
+        gateway_id: u8,
+        reason: LoginRejectReason,
+    ) -> Self {
+        Self {
+            participant,
+            session_id,
+            gateway_id,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn get_reason(&self) -> LoginRejectReason {
+        self.reason.into()
+    }
+}
+
+pub const LOGINREJECT_SIZE: usize = std::mem::size_of::<LoginReject>();
+
+impl Decoder<LOGINREJECT_SIZE> for LoginReject {
+    fn encode(self) -> [u8; LOGINREJECT_SIZE] {
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.session_id)
+            .put(self.gateway_id)
+            .put(self.reason)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; LOGINREJECT_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            session_id: reader.get()?,
+            gateway_id: reader.get()?,
+            reason: reader.get()?,
+        })
+    }
+}
+
+impl OepMessage for LoginReject {
+    fn message_type(&self) -> MsgType {
+        MsgType::LoginReject
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_gateway_id(&self) -> u8 {
+        self.gateway_id
+    }
+
+    fn get_session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    fn get_participant(&self) -> u64 {
+        self.participant
+    }
+}
+
+/// The error of a login refused by the gateway
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoginError {
+    Rejected(LoginRejectReason),
+    // the gateway speaks min_version to max_version
+    UnsupportedVersion {
+        proposed: u16,
+        min_version: u16,
+        max_version: u16,
+    },
+}
+
+impl LoginError {
+    pub fn reason(&self) -> LoginRejectReason {
+        match self {
+            LoginError::Rejected(reason) => *reason,
+            LoginError::UnsupportedVersion { .. } => LoginRejectReason::VersionMismatch,
+        }
+    }
+}
+
+impl Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginError::Rejected(reason) => write!(f, "Login rejected: {reason:?}"),
+            LoginError::UnsupportedVersion {
+                proposed,
+                min_version,
+                max_version,
+            } => write!(
+                f,
+                "Version {proposed} rejected, the gateway speaks {min_version} to {max_version}"
+            ),
+        }
+    }
+}
+
+impl Error for LoginError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = LoginReject::new(
+            0x0102030405060708,
+            600,
+            1,
+            LoginRejectReason::DuplicateSession,
+        );
+
+        let encoded = original.encode();
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1, 88, 2, 0, 0, 1, 2], encoded);
+        let decoded = LoginReject::decode(encoded).unwrap();
+
+        assert_eq!({ original.participant }, { decoded.participant });
+        assert_eq!({ original.session_id }, { decoded.session_id });
+        assert_eq!(1, decoded.gateway_id);
+        assert_eq!(LoginRejectReason::DuplicateSession, decoded.get_reason());
+        assert_eq!(LoginRejectReason::Unspecified, LoginRejectReason::from(99));
+    }
+}
@@ -0,0 +1,131 @@
+use std::error::Error;
+
+use crate::{
+    decoder::{Decoder, FieldReader, FieldWriter},
+    oep_message::{MsgType, OepMessage},
+};
+
+/// book_id value that cancels in all the books
+pub const ANY_BOOK: u64 = 0;
+/// side value that cancels both bids and asks
+pub const ANY_SIDE: u8 = 2;
+
+/// Cancels all the orders of a participant, optionally only the ones
+/// of a certain book and/or side
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct MassCancel {
+    pub participant: u64,
+    pub book_id: u64, // or ANY_BOOK
+    pub side: u8,     // or ANY_SIDE
+    pub gateway_id: u8,
+    pub session_id: u32,
+}
+
+impl MassCancel {
+    pub fn get_book_id(&self) -> u64 {
+        self.book_id
+    }
+
+    pub fn get_side(&self) -> u8 {
+        self.side
+    }
+}
+
+pub const MASSCANCEL_SIZE: usize = std::mem::size_of::<MassCancel>();
+
+impl Decoder<MASSCANCEL_SIZE> for MassCancel {
+    fn encode(self) -> [u8; MASSCANCEL_SIZE] {
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.book_id)
+            .put(self.side)
+            .put(self.gateway_id)
+            .put(self.session_id)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; MASSCANCEL_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            book_id: reader.get()?,
+            side: reader.get()?,
+            gateway_id: reader.get()?,
+            session_id: reader.get()?,
+        })
+    }
+}
+
+impl OepMessage for MassCancel {
+    fn message_type(&self) -> MsgType {
+        MsgType::MassCancel
+    }
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_gateway_id(&self) -> u8 {
+        self.gateway_id
+    }
+
+    fn get_session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    fn get_participant(&self) -> u64 {
+        self.participant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let original = MassCancel {
+            participant: 1234567890,
+            book_id: 42,
+            side: ANY_SIDE,
+            gateway_id: 5,
+            session_id: 987654,
+        };
+
+        let encoded = original.encode();
+        assert_eq!(
+            [
+                210, 2, 150, 73, 0, 0, 0, 0, // participant (1234567890)
+                42, 0, 0, 0, 0, 0, 0, 0, // book_id (42)
+                2, // side
+                5, // gateway_id
+                6, 18, 15, 0, // session_id (987654)
+            ],
+            encoded
+        );
+        let decoded = MassCancel::decode(encoded).unwrap();
+
+        assert_eq!({ decoded.participant }, { original.participant });
+        assert_eq!(decoded.get_book_id(), original.get_book_id());
+        assert_eq!(decoded.get_side(), ANY_SIDE);
+        assert_eq!(decoded.gateway_id, original.gateway_id);
+        assert_eq!({ decoded.session_id }, { original.session_id });
+    }
+
+    #[test]
+    fn test_oep_message_traits() {
+        let mass_cancel = MassCancel {
+            participant: 1234567890,
+            book_id: ANY_BOOK,
+            side: 1,
+            gateway_id: 5,
+            session_id: 987654,
+        };
+
+        assert_eq!(mass_cancel.message_type(), MsgType::MassCancel);
+        assert_eq!(mass_cancel.message_len(), MASSCANCEL_SIZE);
+        assert_eq!(mass_cancel.get_gateway_id(), 5);
+        assert_eq!(mass_cancel.get_session_id(), 987654);
+        assert_eq!(mass_cancel.get_participant(), 1234567890);
+    }
+}
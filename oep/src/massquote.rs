@@ -0,0 +1,207 @@
+use std::error::Error;
+
+use crate::{
+    decoder::{DecodeError, Decoder, Field, FieldReader, FieldWriter},
+    oep_message::{MsgType, OepMessage},
+    quote::Quote,
+};
+
+/// The most books a mass quote can carry
+pub const MAX_MASS_QUOTE_ENTRIES: usize = 8;
+
+/// The quote of a book, within a mass quote
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuoteEntry {
+    pub book_id: u64,
+    pub bid_price: u64,
+    pub bid_quantity: u64,
+    pub ask_price: u64,
+    pub ask_quantity: u64,
+}
+
+const QUOTEENTRY_SIZE: usize = std::mem::size_of::<QuoteEntry>();
+
+impl Field for QuoteEntry {
+    const SIZE: usize = QUOTEENTRY_SIZE;
+
+    fn write(self, buffer: &mut [u8]) {
+        let encoded: [u8; QUOTEENTRY_SIZE] = FieldWriter::default()
+            .put(self.book_id)
+            .put(self.bid_price)
+            .put(self.bid_quantity)
+            .put(self.ask_price)
+            .put(self.ask_quantity)
+            .finish();
+        buffer.copy_from_slice(&encoded);
+    }
+
+    fn read(buffer: &[u8]) -> Self {
+        let mut reader = FieldReader::new(buffer);
+        // the reader is handed exactly SIZE bytes
+        Self {
+            book_id: reader.get().unwrap(),
+            bid_price: reader.get().unwrap(),
+            bid_quantity: reader.get().unwrap(),
+            ask_price: reader.get().unwrap(),
+            ask_quantity: reader.get().unwrap(),
+        }
+    }
+}
+
+/// The quotes of a market maker on several books, each of them handled as a
+/// Quote of its own, carrying the mass_quote_id. Only the first entry_count
+/// entries are taken, the others being left to 0
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct MassQuote {
+    pub mass_quote_id: u64, // set by the client, echoed in the reports
+    pub participant: u64,
+    pub gateway_id: u8,
+    pub session_id: u32,
+    pub entry_count: u8, // up to MAX_MASS_QUOTE_ENTRIES
+    pub entries: [QuoteEntry; MAX_MASS_QUOTE_ENTRIES],
+}
+
+impl MassQuote {
+    /// The quotes of the books, in the order of the entries
+    pub fn quotes(&self) -> impl Iterator<Item = Quote> {
+        let (quote_id, participant) = (self.mass_quote_id, self.participant);
+        let (gateway_id, session_id) = (self.gateway_id, self.session_id);
+        let entries = self.entries;
+        entries
+            .into_iter()
+            .take(self.entry_count as usize)
+            .map(move |e| Quote {
+                quote_id,
+                participant,
+                book_id: e.book_id,
+                bid_price: e.bid_price,
+                bid_quantity: e.bid_quantity,
+                ask_price: e.ask_price,
+                ask_quantity: e.ask_quantity,
+                gateway_id,
+                session_id,
+            })
+    }
+}
+
+pub const MASSQUOTE_SIZE: usize = std::mem::size_of::<MassQuote>();
+
+impl Decoder<MASSQUOTE_SIZE> for MassQuote {
+    fn encode(self) -> [u8; MASSQUOTE_SIZE] {
+        let entries = self.entries;
+        entries
+            .into_iter()
+            .fold(
+                FieldWriter::default()
+                    .put(self.mass_quote_id)
+                    .put(self.participant)
+                    .put(self.gateway_id)
+                    .put(self.session_id)
+                    .put(self.entry_count),
+                |writer, entry| writer.put(entry),
+            )
+            .finish()
+    }
+
+    fn decode(buffer: [u8; MASSQUOTE_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        let mut mass_quote = Self {
+            mass_quote_id: reader.get()?,
+            participant: reader.get()?,
+            gateway_id: reader.get()?,
+            session_id: reader.get()?,
+            entry_count: reader.get()?,
+            entries: [QuoteEntry::default(); MAX_MASS_QUOTE_ENTRIES],
+        };
+        if mass_quote.entry_count as usize > MAX_MASS_QUOTE_ENTRIES {
+            return Err(DecodeError.into());
+        }
+        for entry in mass_quote.entries.iter_mut() {
+            *entry = reader.get()?;
+        }
+        Ok(mass_quote)
+    }
+}
+
+impl OepMessage for MassQuote {
+    fn message_type(&self) -> MsgType {
+        MsgType::MassQuote
+    }
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_gateway_id(&self) -> u8 {
+        self.gateway_id
+    }
+
+    fn get_session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    fn get_participant(&self) -> u64 {
+        self.participant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let mut original = MassQuote {
+            mass_quote_id: 66,
+            participant: 1234567890,
+            gateway_id: 5,
+            session_id: 987654,
+            entry_count: 2,
+            entries: [QuoteEntry::default(); MAX_MASS_QUOTE_ENTRIES],
+        };
+        original.entries[0] = QuoteEntry {
+            book_id: 42,
+            bid_price: 99,
+            bid_quantity: 100,
+            ask_price: 101,
+            ask_quantity: 0,
+        };
+        original.entries[1] = QuoteEntry {
+            book_id: 43,
+            bid_price: 9,
+            bid_quantity: 10,
+            ask_price: 11,
+            ask_quantity: 12,
+        };
+
+        let encoded = original.encode();
+        assert_eq!(342, encoded.len());
+        let decoded = MassQuote::decode(encoded).unwrap();
+        assert_eq!({ decoded.mass_quote_id }, { original.mass_quote_id });
+        assert_eq!({ decoded.participant }, { original.participant });
+        assert_eq!(decoded.gateway_id, original.gateway_id);
+        assert_eq!({ decoded.session_id }, { original.session_id });
+        assert_eq!(MsgType::MassQuote, decoded.message_type());
+
+        let quotes: Vec<Quote> = decoded.quotes().collect();
+        assert_eq!(2, quotes.len());
+        assert_eq!(
+            (66, 43, 9, 10, 11, 12),
+            (
+                { quotes[1].quote_id },
+                { quotes[1].book_id },
+                { quotes[1].bid_price },
+                { quotes[1].bid_quantity },
+                { quotes[1].ask_price },
+                { quotes[1].ask_quantity }
+            )
+        );
+        assert_eq!(987654, { quotes[0].session_id });
+
+        let mut encoded = original.encode();
+        // entry_count
+        encoded[21] = MAX_MASS_QUOTE_ENTRIES as u8 + 1;
+        assert!(MassQuote::decode(encoded).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! The messages of a stream, out of the bytes read so far
+//!
+//! A read can end anywhere in a message, in its header or in its body, so
+//! the bytes are kept until the whole message is in.
+
+use crate::{
+    decoder::Decoder,
+    header::{OepHeader, OEP_HEADER_SIZE},
+    oep_decode,
+    oep_message::OepMessage,
+};
+
+// no message comes close, a longer one means the stream is garbled
+const MAX_MESSAGE_SIZE: usize = 65536;
+
+/// A message with its header, or why it didn't decode
+pub type Framed = Result<(OepHeader, Box<dyn OepMessage>), std::io::Error>;
+
+#[derive(Debug, Default)]
+pub struct MessageBuffer {
+    buffer: Vec<u8>,
+}
+
+impl MessageBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends @bytes, as read from the stream
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// The bytes of the message not complete yet
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// The next message with its header, None until one is complete. A
+    /// message not decoding is dropped and returned as an error, and so is
+    /// all that was read when the length in the header can't be right
+    pub fn next_message(&mut self) -> Option<Framed> {
+        let header: [u8; OEP_HEADER_SIZE] = self.buffer.get(..OEP_HEADER_SIZE)?.try_into().ok()?;
+        let header = OepHeader::decode(header).ok()?;
+        let len = OEP_HEADER_SIZE + header.msg_len as usize;
+        if len > MAX_MESSAGE_SIZE {
+            self.buffer.clear();
+            return Some(Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Message of {len} bytes"),
+            )));
+        }
+        if self.buffer.len() < len {
+            return None;
+        }
+        let r = oep_decode(&self.buffer[..len]).map(|m| (header, m));
+        self.buffer.drain(..len);
+        Some(r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MessageBuffer;
+    use crate::{
+        cancel::{Cancel, CANCEL_SIZE},
+        decoder::Decoder,
+        header::{OepHeader, OEP_VERSION},
+        oep_message::MsgType,
+    };
+
+    fn cancel(seq: u32) -> Vec<u8> {
+        let cancel = Cancel {
+            participant: 1,
+            order_id: 7,
+            book_id: 1000,
+            side: 0,
+            gateway_id: 1,
+            session_id: 2,
+            orig_client_order_id: 0,
+        };
+        let header = OepHeader::new(OEP_VERSION, MsgType::Cancel.into(), CANCEL_SIZE as u32);
+        [header.with_seq(seq).encode().as_slice(), &cancel.encode()].concat()
+    }
+
+    #[test]
+    fn partial_reads() {
+        let mut target = MessageBuffer::new();
+        let stream = [cancel(1), cancel(2)].concat();
+        // the header of the first one cut short
+        target.extend(&stream[..5]);
+        assert!(target.next_message().is_none());
+        // then its body
+        target.extend(&stream[5..20]);
+        assert!(target.next_message().is_none());
+        // the rest of it, with the second one but for its last byte
+        target.extend(&stream[20..stream.len() - 1]);
+        let (header, message) = target.next_message().unwrap().unwrap();
+        assert_eq!(1, { header.seq });
+        assert_eq!(MsgType::Cancel, message.message_type());
+        assert!(target.next_message().is_none());
+        target.extend(&stream[stream.len() - 1..]);
+        assert_eq!(2, { target.next_message().unwrap().unwrap().0.seq });
+        assert!(target.is_empty());
+    }
+
+    #[test]
+    fn garbled() {
+        let mut target = MessageBuffer::new();
+        let mut message = cancel(1);
+        // an unknown type
+        message[2] = 99;
+        target.extend(&[message, cancel(2)].concat());
+        assert!(target.next_message().unwrap().is_err());
+        assert!(target.next_message().unwrap().is_ok());
+
+        target.extend(&[5, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        assert!(target.next_message().unwrap().is_err());
+        assert!(target.is_empty());
+    }
+}
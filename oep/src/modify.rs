@@ -1,7 +1,7 @@
 use std::error::Error;
 
 use crate::{
-    decoder::Decoder,
+    decoder::{Decoder, FieldReader, FieldWriter},
     oep_message::{MsgType, OepMessage},
 };
 
@@ -16,9 +16,14 @@ pub struct Modify {
     pub side: u8,
     pub gateway_id: u8,
     pub session_id: u32,
+    // the client order id of the order, used instead of order_id when that is 0.
+    // Since version 6
+    pub orig_client_order_id: u64,
 }
 
 pub const MODIFY_SIZE: usize = std::mem::size_of::<Modify>();
+// without the orig_client_order_id
+pub const MODIFY_V5_SIZE: usize = MODIFY_SIZE - 8;
 
 impl Modify {
     pub fn get_side(&self) -> u8 {
@@ -28,11 +33,32 @@ impl Modify {
 
 impl Decoder<MODIFY_SIZE> for Modify {
     fn encode(self) -> [u8; MODIFY_SIZE] {
-        unsafe { std::mem::transmute::<Self, [u8; MODIFY_SIZE]>(self) }
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.order_id)
+            .put(self.book_id)
+            .put(self.quantity)
+            .put(self.price)
+            .put(self.side)
+            .put(self.gateway_id)
+            .put(self.session_id)
+            .put(self.orig_client_order_id)
+            .finish()
     }
 
     fn decode(buffer: [u8; MODIFY_SIZE]) -> Result<Self, Box<dyn Error>> {
-        unsafe { Ok(std::mem::transmute::<[u8; MODIFY_SIZE], Self>(buffer).try_into()?) }
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            order_id: reader.get()?,
+            book_id: reader.get()?,
+            quantity: reader.get()?,
+            price: reader.get()?,
+            side: reader.get()?,
+            gateway_id: reader.get()?,
+            session_id: reader.get()?,
+            orig_client_order_id: reader.get()?,
+        })
     }
 }
 
@@ -72,16 +98,17 @@ mod tests {
             side: 1,
             gateway_id: 5,
             session_id: 33333,
+            orig_client_order_id: 0,
         };
 
-        assert_eq!(modify.participant as u64, 12345);
-        assert_eq!(modify.order_id as u64, 67890);
-        assert_eq!(modify.book_id as u64, 11111);
-        assert_eq!(modify.quantity as u64, 100);
-        assert_eq!(modify.price as u64, 1000);
+        assert_eq!({ modify.participant }, 12345);
+        assert_eq!({ modify.order_id }, 67890);
+        assert_eq!({ modify.book_id }, 11111);
+        assert_eq!({ modify.quantity }, 100);
+        assert_eq!({ modify.price }, 1000);
         assert_eq!(modify.side, 1);
         assert_eq!(modify.gateway_id, 5);
-        assert_eq!(modify.session_id as u32, 33333);
+        assert_eq!({ modify.session_id }, 33333);
     }
 
     #[test]
@@ -95,6 +122,7 @@ mod tests {
             side: 1,
             gateway_id: 5,
             session_id: 33333,
+            orig_client_order_id: 0,
         };
 
         assert_eq!(modify.get_side(), 1);
@@ -111,19 +139,23 @@ mod tests {
             side: 1,
             gateway_id: 5,
             session_id: 33333,
+            orig_client_order_id: 44444,
         };
 
         let encoded = original.encode();
         let decoded = Modify::decode(encoded).unwrap();
 
-        assert_eq!(original.participant as u64, decoded.participant as u64);
-        assert_eq!(original.order_id as u64, decoded.order_id as u64);
-        assert_eq!(original.book_id as u64, decoded.book_id as u64);
-        assert_eq!(original.quantity as u64, decoded.quantity as u64);
-        assert_eq!(original.price as u64, decoded.price as u64);
+        assert_eq!({ original.participant }, { decoded.participant });
+        assert_eq!({ original.order_id }, { decoded.order_id });
+        assert_eq!({ original.book_id }, { decoded.book_id });
+        assert_eq!({ original.quantity }, { decoded.quantity });
+        assert_eq!({ original.price }, { decoded.price });
         assert_eq!(original.side, decoded.side);
         assert_eq!(original.gateway_id, decoded.gateway_id);
-        assert_eq!(original.session_id as u32, decoded.session_id as u32);
+        assert_eq!({ original.session_id }, { decoded.session_id });
+        assert_eq!({ original.orig_client_order_id }, {
+            decoded.orig_client_order_id
+        });
     }
 
     #[test]
@@ -137,6 +169,7 @@ mod tests {
             side: 1,
             gateway_id: 5,
             session_id: 33333,
+            orig_client_order_id: 0,
         };
 
         assert_eq!(modify.message_type(), MsgType::Modify);
@@ -156,12 +189,13 @@ mod tests {
             side: 1,
             gateway_id: 5,
             session_id: 33333,
+            orig_client_order_id: 0,
         };
 
         let any = modify.as_any();
         let downcast = any.downcast_ref::<Modify>();
         assert!(downcast.is_some());
-        assert_eq!(downcast.unwrap().order_id as u64, 67890);
+        assert_eq!({ downcast.unwrap().order_id }, 67890);
     }
 
     #[test]
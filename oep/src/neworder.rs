@@ -1,7 +1,7 @@
 use std::error::Error;
 
 use crate::{
-    decoder::Decoder,
+    decoder::{Decoder, FieldReader, FieldWriter},
     oep_message::{MsgType, OepMessage},
 };
 
@@ -17,17 +17,50 @@ pub struct NewOrder {
     pub side: u8,
     pub gateway_id: u8,
     pub session_id: u32,
+    // unix timestamp (seconds), only used by the GoodTillDate orders
+    pub expiry: u64,
+    // only used by the StopLoss and StopLimit orders
+    pub stop_price: u64,
+    // peak size of an iceberg order, 0 to show the whole quantity
+    pub display_quantity: u64,
 }
 
 pub const NEWORDER_SIZE: usize = std::mem::size_of::<NewOrder>();
 
 impl Decoder<NEWORDER_SIZE> for NewOrder {
     fn encode(self) -> [u8; NEWORDER_SIZE] {
-        unsafe { std::mem::transmute::<Self, [u8; NEWORDER_SIZE]>(self) }
+        FieldWriter::default()
+            .put(self.client_order_id)
+            .put(self.participant)
+            .put(self.book_id)
+            .put(self.quantity)
+            .put(self.price)
+            .put(self.order_type)
+            .put(self.side)
+            .put(self.gateway_id)
+            .put(self.session_id)
+            .put(self.expiry)
+            .put(self.stop_price)
+            .put(self.display_quantity)
+            .finish()
     }
 
     fn decode(buffer: [u8; NEWORDER_SIZE]) -> Result<Self, Box<dyn Error>> {
-        unsafe { Ok(std::mem::transmute::<[u8; NEWORDER_SIZE], Self>(buffer).try_into()?) }
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            client_order_id: reader.get()?,
+            participant: reader.get()?,
+            book_id: reader.get()?,
+            quantity: reader.get()?,
+            price: reader.get()?,
+            order_type: reader.get()?,
+            side: reader.get()?,
+            gateway_id: reader.get()?,
+            session_id: reader.get()?,
+            expiry: reader.get()?,
+            stop_price: reader.get()?,
+            display_quantity: reader.get()?,
+        })
     }
 }
 
@@ -60,20 +93,24 @@ mod tests {
     fn decode() {
         let neworder_bytes = [
             66, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0,
-            0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 66, 0, 1, 55, 66, 0, 0, 0,
+            0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 66, 0, 1, 55, 66, 0, 0, 0, 16, 14, 0, 0, 0, 0, 0,
+            0, 232, 3, 0, 0, 0, 0, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0,
         ];
         let boxed_target = NewOrder::decode(neworder_bytes);
         assert!(boxed_target.is_ok());
         let target = boxed_target.unwrap();
 
-        assert_eq!(target.client_order_id as u64, 66);
-        assert_eq!(target.participant as u64, 1);
-        assert_eq!(target.book_id as u64, 2);
-        assert_eq!(target.quantity as u64, 100);
+        assert_eq!({ target.client_order_id }, 66);
+        assert_eq!({ target.participant }, 1);
+        assert_eq!({ target.book_id }, 2);
+        assert_eq!({ target.quantity }, 100);
         assert_eq!(target.side, 1);
-        assert_eq!(target.order_type as u16, 66);
+        assert_eq!({ target.order_type }, 66);
         assert_eq!(target.gateway_id, 55);
-        assert_eq!(target.session_id as u32, 66);
+        assert_eq!({ target.session_id }, 66);
+        assert_eq!({ target.expiry }, 3600);
+        assert_eq!({ target.stop_price }, 1000);
+        assert_eq!({ target.display_quantity }, 10);
     }
 
     #[test]
@@ -88,6 +125,9 @@ mod tests {
             side: 1,
             gateway_id: 55,
             session_id: 66,
+            expiry: 3600,
+            stop_price: 990,
+            display_quantity: 10,
         };
 
         let encoded = new_order.encode();
@@ -102,23 +142,26 @@ mod tests {
             1,  // side
             55, // gateway_id
             66, 0, 0, 0, // session_id
+            16, 14, 0, 0, 0, 0, 0, 0, // expiry (3600)
+            222, 3, 0, 0, 0, 0, 0, 0, // stop_price (990)
+            10, 0, 0, 0, 0, 0, 0, 0, // display_quantity
         ];
 
         assert_eq!(encoded, expected);
 
         // Test that decoding the encoded data gives back the original struct
         let decoded = NewOrder::decode(encoded).unwrap();
-        assert_eq!(
-            decoded.client_order_id as u64,
-            new_order.client_order_id as u64
-        );
-        assert_eq!(decoded.participant as u64, new_order.participant as u64);
-        assert_eq!(decoded.book_id as u64, new_order.book_id as u64);
-        assert_eq!(decoded.quantity as u64, new_order.quantity as u64);
-        assert_eq!(decoded.price as u64, new_order.price as u64);
-        assert_eq!(decoded.order_type as u16, new_order.order_type as u16);
+        assert_eq!({ decoded.client_order_id }, { new_order.client_order_id });
+        assert_eq!({ decoded.participant }, { new_order.participant });
+        assert_eq!({ decoded.book_id }, { new_order.book_id });
+        assert_eq!({ decoded.quantity }, { new_order.quantity });
+        assert_eq!({ decoded.price }, { new_order.price });
+        assert_eq!({ decoded.order_type }, { new_order.order_type });
         assert_eq!(decoded.side, new_order.side);
         assert_eq!(decoded.gateway_id, new_order.gateway_id);
-        assert_eq!(decoded.session_id as u32, new_order.session_id as u32);
+        assert_eq!({ decoded.session_id }, { new_order.session_id });
+        assert_eq!({ decoded.expiry }, { new_order.expiry });
+        assert_eq!({ decoded.stop_price }, { new_order.stop_price });
+        assert_eq!({ decoded.display_quantity }, { new_order.display_quantity });
     }
 }
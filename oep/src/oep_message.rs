@@ -1,6 +1,24 @@
 use crate::{
-    cancel::CANCEL_SIZE, execution_report::EXECUTIONREPORT_SIZE, login::LOGIN_SIZE,
-    modify::MODIFY_SIZE, neworder::NEWORDER_SIZE, sessioninfo::SESSIONINFO_SIZE, trade::TRADE_SIZE,
+    cancel::CANCEL_SIZE,
+    egress::{EGRESSHEADER_SIZE, EGRESSNAK_SIZE},
+    engine_status::ENGINESTATUS_SIZE,
+    execution_report::EXECUTIONREPORT_SIZE,
+    heartbeat::HEARTBEAT_SIZE,
+    ingress::INGRESSNAK_SIZE,
+    login::LOGIN_SIZE,
+    loginreject::LOGINREJECT_SIZE,
+    masscancel::MASSCANCEL_SIZE,
+    massquote::MASSQUOTE_SIZE,
+    modify::MODIFY_SIZE,
+    neworder::NEWORDER_SIZE,
+    quote::QUOTE_SIZE,
+    quotecancelall::QUOTECANCELALL_SIZE,
+    registration::{GATEWAYREGISTRATIONREPLY_SIZE, GATEWAYREGISTRATION_SIZE},
+    replace::REPLACE_SIZE,
+    resendrequest::RESENDREQUEST_SIZE,
+    sessioninfo::SESSIONINFO_SIZE,
+    trade::TRADE_SIZE,
+    version::VERSIONREJECT_SIZE,
 };
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -12,6 +30,21 @@ pub enum MsgType {
     Login,
     Trade,
     SessionNotification, // sent by GW to ME, in order to inform the latter about a session exception
+    EngineStatus,        // sent by ME to GW, in order to announce if orders can be accepted
+    MassCancel,
+    Replace,
+    Heartbeat,                // sent by the clients to the GW while idle
+    ResendRequest,            // sent by the clients to the GW to recover the messages they missed
+    IngressNak,               // sent by ME to GW, in order to get again the messages it missed
+    VersionReject,            // sent by the GW to the clients proposing a version it doesn't speak
+    LoginReject,              // sent by the GW to the clients whose login it refuses
+    Quote,                    // a two-sided quote of a market maker
+    MassQuote,                // the quotes of a market maker on several books
+    QuoteCancelAll,           // pulls the quotes of a market maker
+    EgressFrame, // sent by ME to GW, an execution report with its sequence, for a sequenced egress
+    EgressNak,   // sent by GW to ME, in order to get again the execution reports it missed
+    GatewayRegistration, // sent by GW to ME, announcing the instance running with its id
+    GatewayRegistrationReply, // sent by ME to GW, accepting a registration or not
     Unknown,
 }
 
@@ -23,6 +56,21 @@ impl Into<u16> for MsgType {
             MsgType::Cancel => 2,
             MsgType::ExecutionReport => 3,
             MsgType::Login => 4,
+            MsgType::EngineStatus => 7,
+            MsgType::MassCancel => 8,
+            MsgType::Replace => 9,
+            MsgType::Heartbeat => 10,
+            MsgType::ResendRequest => 11,
+            MsgType::IngressNak => 12,
+            MsgType::VersionReject => 13,
+            MsgType::LoginReject => 14,
+            MsgType::Quote => 15,
+            MsgType::MassQuote => 16,
+            MsgType::QuoteCancelAll => 17,
+            MsgType::EgressFrame => 18,
+            MsgType::EgressNak => 19,
+            MsgType::GatewayRegistration => 20,
+            MsgType::GatewayRegistrationReply => 21,
             // MsgType::Trade intentionally left out
             // Msg::SessionInfo intentionall left out
             _ => panic!("Unknown message type"),
@@ -40,11 +88,45 @@ impl From<u16> for MsgType {
             4 => MsgType::Login,
             5 => MsgType::Trade,
             6 => MsgType::SessionNotification,
+            7 => MsgType::EngineStatus,
+            8 => MsgType::MassCancel,
+            9 => MsgType::Replace,
+            10 => MsgType::Heartbeat,
+            11 => MsgType::ResendRequest,
+            12 => MsgType::IngressNak,
+            13 => MsgType::VersionReject,
+            14 => MsgType::LoginReject,
+            15 => MsgType::Quote,
+            16 => MsgType::MassQuote,
+            17 => MsgType::QuoteCancelAll,
+            18 => MsgType::EgressFrame,
+            19 => MsgType::EgressNak,
+            20 => MsgType::GatewayRegistration,
+            21 => MsgType::GatewayRegistrationReply,
             _ => MsgType::Unknown,
         }
     }
 }
 
+impl MsgType {
+    /// Whether the message carries a sequence in its session. Login, Heartbeat
+    /// and ResendRequest are session level and go unsequenced
+    pub fn is_sequenced(&self) -> bool {
+        matches!(
+            self,
+            MsgType::NewOrder
+                | MsgType::Modify
+                | MsgType::Cancel
+                | MsgType::MassCancel
+                | MsgType::Replace
+                | MsgType::Quote
+                | MsgType::MassQuote
+                | MsgType::QuoteCancelAll
+                | MsgType::ExecutionReport
+        )
+    }
+}
+
 pub trait OepMessage {
     fn message_type(&self) -> MsgType;
     fn message_len(&self) -> usize {
@@ -56,6 +138,22 @@ pub trait OepMessage {
             MsgType::NewOrder => NEWORDER_SIZE,
             MsgType::Trade => TRADE_SIZE,
             MsgType::SessionNotification => SESSIONINFO_SIZE,
+            MsgType::EngineStatus => ENGINESTATUS_SIZE,
+            MsgType::MassCancel => MASSCANCEL_SIZE,
+            MsgType::Replace => REPLACE_SIZE,
+            MsgType::Heartbeat => HEARTBEAT_SIZE,
+            MsgType::ResendRequest => RESENDREQUEST_SIZE,
+            MsgType::IngressNak => INGRESSNAK_SIZE,
+            MsgType::VersionReject => VERSIONREJECT_SIZE,
+            MsgType::LoginReject => LOGINREJECT_SIZE,
+            MsgType::Quote => QUOTE_SIZE,
+            MsgType::MassQuote => MASSQUOTE_SIZE,
+            MsgType::QuoteCancelAll => QUOTECANCELALL_SIZE,
+            // without the execution report following it
+            MsgType::EgressFrame => EGRESSHEADER_SIZE,
+            MsgType::EgressNak => EGRESSNAK_SIZE,
+            MsgType::GatewayRegistration => GATEWAYREGISTRATION_SIZE,
+            MsgType::GatewayRegistrationReply => GATEWAYREGISTRATIONREPLY_SIZE,
             MsgType::Unknown => 1024,
         }
     }
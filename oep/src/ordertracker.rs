@@ -0,0 +1,426 @@
+//! The orders of a client session, as told by its execution reports
+//!
+//! An order is known by the client order id it was sent with, and by the
+//! exchange order id once acknowledged. A replace is a new order, with its
+//! own ids, carrying over the quantity filled by the order it replaced.
+
+use std::collections::HashMap;
+
+use order::OrderState;
+
+use crate::{
+    execution_report::{ExecutionReport, RejectReason},
+    neworder::NewOrder,
+    replace::Replace,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderStatus {
+    // sent, not acknowledged yet
+    Pending,
+    Open,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Rejected,
+}
+
+impl OrderStatus {
+    /// Whether the order can still trade
+    pub fn is_open(&self) -> bool {
+        matches!(self, Self::Pending | Self::Open | Self::PartiallyFilled)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedOrder {
+    pub client_order_id: u64,
+    // 0 until acknowledged
+    pub order_id: u64,
+    pub book_id: u64,
+    pub side: u8,
+    pub price: u64,
+    pub quantity: u64,
+    pub leaves_quantity: u64,
+    // since the order was sent, the fills of the order it replaced included
+    pub filled_quantity: u64,
+    pub status: OrderStatus,
+    // why the order was rejected, or the last modify or cancel of it
+    pub reject_reason: RejectReason,
+}
+
+/// A trade of one of the orders, at the price of its execution report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fill {
+    pub client_order_id: u64,
+    pub order_id: u64,
+    pub book_id: u64,
+    pub side: u8,
+    pub price: u64,
+    pub quantity: u64,
+}
+
+/// Correlates the orders sent with the execution reports received
+#[derive(Debug, Default)]
+pub struct OrderTracker {
+    // client order id -> order
+    orders: HashMap<u64, TrackedOrder>,
+    // exchange order id -> client order id
+    order_ids: HashMap<u64, u64>,
+    // client order id of a replace -> exchange order id of the order replaced
+    replaces: HashMap<u64, u64>,
+    fills: Vec<Fill>,
+    // sequence of the last execution report
+    last_seq: u32,
+}
+
+impl OrderTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking @order, to be called as it's sent
+    pub fn on_new_order(&mut self, order: &NewOrder) {
+        self.orders.insert(
+            order.client_order_id,
+            TrackedOrder {
+                client_order_id: order.client_order_id,
+                order_id: 0,
+                book_id: order.book_id,
+                side: order.side,
+                price: order.price,
+                quantity: order.quantity,
+                leaves_quantity: order.quantity,
+                filled_quantity: 0,
+                status: OrderStatus::Pending,
+                reject_reason: RejectReason::None,
+            },
+        );
+    }
+
+    /// Starts tracking the order replacing another, to be called as @replace is sent
+    pub fn on_replace(&mut self, replace: &Replace) {
+        self.replaces
+            .insert(replace.client_order_id, replace.orig_order_id);
+        self.orders.insert(
+            replace.client_order_id,
+            TrackedOrder {
+                client_order_id: replace.client_order_id,
+                order_id: 0,
+                book_id: replace.book_id,
+                side: replace.side,
+                price: replace.price,
+                quantity: replace.quantity,
+                leaves_quantity: replace.quantity,
+                filled_quantity: 0,
+                status: OrderStatus::Pending,
+                reject_reason: RejectReason::None,
+            },
+        );
+    }
+
+    /// Applies @ereport, received with the sequence @seq, returning the order
+    /// it's about, None for the orders not tracked
+    pub fn on_execution_report(
+        &mut self,
+        ereport: &ExecutionReport,
+        seq: u32,
+    ) -> Option<TrackedOrder> {
+        self.last_seq = self.last_seq.max(seq);
+        // the quote acks are about two orders at once
+        if ereport.state > OrderState::TradeBusted.into()
+            || ereport.state == Into::<u8>::into(OrderState::QuoteAck)
+        {
+            return None;
+        }
+        let state = OrderState::from(ereport.state);
+        let (order_id, submitted) = (ereport.order_id, ereport.submitted_order_id);
+        let pending = self
+            .orders
+            .get(&submitted)
+            .is_some_and(|o| o.status == OrderStatus::Pending);
+        // the reject of a new order or a replace carries no exchange order id
+        let client_order_id = match self.order_ids.get(&order_id) {
+            Some(id) if !(pending && state == OrderState::Rejected) => *id,
+            _ if pending => submitted,
+            _ => return None,
+        };
+        let reason = RejectReason::from(ereport.reject_reason);
+
+        if pending {
+            let replaced = self
+                .replaces
+                .remove(&client_order_id)
+                .and_then(|id| self.order_ids.get(&id).copied());
+            if let Some(old) = replaced.and_then(|id| self.orders.get_mut(&id)) {
+                if state == OrderState::Rejected {
+                    // the order replaced stays as it was
+                    old.reject_reason = reason;
+                } else {
+                    let filled = old.filled_quantity;
+                    self.orders.get_mut(&client_order_id)?.filled_quantity = filled;
+                }
+            }
+            if state != OrderState::Rejected {
+                self.order_ids.insert(order_id, client_order_id);
+            }
+        }
+
+        let order = self.orders.get_mut(&client_order_id)?;
+        match state {
+            // a modify or a cancel refused, the order stays as it was
+            OrderState::Rejected if !pending => order.reject_reason = reason,
+            OrderState::Rejected => {
+                order.status = OrderStatus::Rejected;
+                order.leaves_quantity = 0;
+                order.reject_reason = reason;
+            }
+            OrderState::Cancelled => {
+                order.status = OrderStatus::Cancelled;
+                order.leaves_quantity = 0;
+            }
+            _ => {
+                order.order_id = order_id;
+                if matches!(state, OrderState::Inserted | OrderState::Modified) {
+                    order.price = ereport.price;
+                    order.quantity = ereport.quantity;
+                }
+                if state == OrderState::TradeBusted {
+                    order.filled_quantity = order.filled_quantity.saturating_sub(ereport.quantity);
+                }
+                let filled = ereport.filled_quantity;
+                if filled > 0 {
+                    order.filled_quantity += filled;
+                    self.fills.push(Fill {
+                        client_order_id,
+                        order_id,
+                        book_id: ereport.book,
+                        side: ereport.side,
+                        price: ereport.price,
+                        quantity: filled,
+                    });
+                }
+                order.leaves_quantity = ereport.leaves_quantity;
+                order.status = match (order.leaves_quantity, order.filled_quantity) {
+                    // a fill and kill order that didn't trade
+                    (0, 0) => OrderStatus::Cancelled,
+                    (0, _) => OrderStatus::Filled,
+                    (_, 0) => OrderStatus::Open,
+                    _ => OrderStatus::PartiallyFilled,
+                };
+            }
+        }
+        Some(order.clone())
+    }
+
+    pub fn get(&self, client_order_id: u64) -> Option<&TrackedOrder> {
+        self.orders.get(&client_order_id)
+    }
+
+    /// The order acknowledged with the exchange order id @order_id
+    pub fn get_by_order_id(&self, order_id: u64) -> Option<&TrackedOrder> {
+        self.order_ids
+            .get(&order_id)
+            .and_then(|id| self.orders.get(id))
+    }
+
+    /// The orders that can still trade, by client order id
+    pub fn open_orders(&self) -> Vec<&TrackedOrder> {
+        let mut r: Vec<&TrackedOrder> = self
+            .orders
+            .values()
+            .filter(|o| o.status.is_open())
+            .collect();
+        r.sort_by_key(|o| o.client_order_id);
+        r
+    }
+
+    /// All the fills, oldest first
+    pub fn fills(&self) -> &[Fill] {
+        &self.fills
+    }
+
+    /// The sequence of the last execution report, anything after it can be
+    /// asked for again with a resend request
+    pub fn last_seq(&self) -> u32 {
+        self.last_seq
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use order::OrderState;
+
+    use super::{OrderStatus, OrderTracker};
+    use crate::{
+        execution_report::{ExecutionReport, RejectReason},
+        neworder::NewOrder,
+        replace::Replace,
+    };
+
+    fn new_order(client_order_id: u64, quantity: u64) -> NewOrder {
+        NewOrder {
+            client_order_id,
+            participant: 1,
+            book_id: 1000,
+            quantity,
+            price: 100,
+            order_type: 0,
+            side: 0,
+            gateway_id: 1,
+            session_id: 2,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        }
+    }
+
+    fn ereport(
+        order_id: u64,
+        submitted_order_id: u64,
+        state: OrderState,
+        filled_quantity: u64,
+        leaves_quantity: u64,
+    ) -> ExecutionReport {
+        ExecutionReport {
+            participant: 1,
+            order_id,
+            submitted_order_id,
+            book: 1000,
+            quantity: 10,
+            price: 100,
+            flags: 0,
+            side: 0,
+            state: state.into(),
+            session_id: 2,
+            gateway_id: 1,
+            filled_quantity,
+            leaves_quantity,
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
+        }
+    }
+
+    #[test]
+    fn fills() {
+        let mut target = OrderTracker::new();
+        target.on_new_order(&new_order(7, 10));
+        assert_eq!(OrderStatus::Pending, target.get(7).unwrap().status);
+
+        let order = target
+            .on_execution_report(&ereport(500, 7, OrderState::Inserted, 4, 6), 1)
+            .unwrap();
+        assert_eq!(
+            (500, OrderStatus::PartiallyFilled, 4, 6),
+            (
+                order.order_id,
+                order.status,
+                order.filled_quantity,
+                order.leaves_quantity
+            )
+        );
+        // passive, known by the exchange order id only
+        target.on_execution_report(&ereport(500, 500, OrderState::Traded, 6, 0), 2);
+        assert_eq!(
+            OrderStatus::Filled,
+            target.get_by_order_id(500).unwrap().status
+        );
+        assert_eq!(
+            vec![4, 6],
+            target
+                .fills()
+                .iter()
+                .map(|f| f.quantity)
+                .collect::<Vec<_>>()
+        );
+        assert!(target.open_orders().is_empty());
+        assert_eq!(2, target.last_seq());
+
+        // the first trade busted, the order stays filled by the second one
+        let bust = ExecutionReport {
+            quantity: 4,
+            ..ereport(500, 500, OrderState::TradeBusted, 0, 0)
+        };
+        let order = target.on_execution_report(&bust, 3).unwrap();
+        assert_eq!(
+            (OrderStatus::Filled, 6),
+            (order.status, order.filled_quantity)
+        );
+
+        // someone else's
+        assert!(target
+            .on_execution_report(&ereport(501, 8, OrderState::Inserted, 0, 10), 4)
+            .is_none());
+    }
+
+    #[test]
+    fn rejects() {
+        let mut target = OrderTracker::new();
+        target.on_new_order(&new_order(7, 10));
+        target.on_new_order(&new_order(8, 10));
+        let mut reject = ereport(7, 7, OrderState::Rejected, 0, 0);
+        reject.reject_reason = RejectReason::QuantityLimit.into();
+        let order = target.on_execution_report(&reject, 1).unwrap();
+        assert_eq!(OrderStatus::Rejected, order.status);
+        assert_eq!(RejectReason::QuantityLimit, order.reject_reason);
+
+        target.on_execution_report(&ereport(9, 8, OrderState::Inserted, 0, 10), 2);
+        // the rejected cancel of the order leaves it open
+        let mut reject = ereport(9, 9, OrderState::Rejected, 0, 0);
+        reject.reject_reason = RejectReason::Unspecified.into();
+        let order = target.on_execution_report(&reject, 3).unwrap();
+        assert_eq!(
+            (8, OrderStatus::Open, RejectReason::Unspecified),
+            (order.client_order_id, order.status, order.reject_reason)
+        );
+        target.on_execution_report(&ereport(9, 9, OrderState::Cancelled, 0, 0), 4);
+        assert_eq!(OrderStatus::Cancelled, target.get(8).unwrap().status);
+    }
+
+    #[test]
+    fn replaces() {
+        let mut target = OrderTracker::new();
+        target.on_new_order(&new_order(7, 10));
+        target.on_execution_report(&ereport(500, 7, OrderState::Inserted, 3, 7), 1);
+        target.on_replace(&Replace {
+            orig_order_id: 500,
+            client_order_id: 8,
+            participant: 1,
+            book_id: 1000,
+            quantity: 20,
+            price: 99,
+            order_type: 0,
+            side: 0,
+            gateway_id: 1,
+            session_id: 2,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+        });
+        assert_eq!(2, target.open_orders().len());
+
+        let mut old_half = ereport(500, 500, OrderState::Cancelled, 0, 0);
+        old_half.orig_order_id = 500;
+        target.on_execution_report(&old_half, 2);
+        let mut new_half = ereport(501, 8, OrderState::Inserted, 0, 20);
+        new_half.orig_order_id = 500;
+        (new_half.quantity, new_half.price) = (20, 99);
+        target.on_execution_report(&new_half, 3);
+
+        assert_eq!(OrderStatus::Cancelled, target.get(7).unwrap().status);
+        let open = target.open_orders();
+        assert_eq!(1, open.len());
+        assert_eq!(
+            (8, 501, 99, 20, 3),
+            (
+                open[0].client_order_id,
+                open[0].order_id,
+                open[0].price,
+                open[0].leaves_quantity,
+                open[0].filled_quantity
+            )
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::error::Error;
+
+use crate::{
+    decoder::{Decoder, FieldReader, FieldWriter},
+    oep_message::{MsgType, OepMessage},
+};
+
+/// A market maker quote: a bid and an ask on a book, taking the place of the
+/// previous quote of the participant on both sides in one step. A side with
+/// a 0 quantity is left out, the previous one being pulled
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Quote {
+    pub quote_id: u64, // set by the client, echoed in the QuoteAck
+    pub participant: u64,
+    pub book_id: u64,
+    pub bid_price: u64,
+    pub bid_quantity: u64,
+    pub ask_price: u64,
+    pub ask_quantity: u64,
+    pub gateway_id: u8,
+    pub session_id: u32,
+}
+
+pub const QUOTE_SIZE: usize = std::mem::size_of::<Quote>();
+
+impl Decoder<QUOTE_SIZE> for Quote {
+    fn encode(self) -> [u8; QUOTE_SIZE] {
+        FieldWriter::default()
+            .put(self.quote_id)
+            .put(self.participant)
+            .put(self.book_id)
+            .put(self.bid_price)
+            .put(self.bid_quantity)
+            .put(self.ask_price)
+            .put(self.ask_quantity)
+            .put(self.gateway_id)
+            .put(self.session_id)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; QUOTE_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            quote_id: reader.get()?,
+            participant: reader.get()?,
+            book_id: reader.get()?,
+            bid_price: reader.get()?,
+            bid_quantity: reader.get()?,
+            ask_price: reader.get()?,
+            ask_quantity: reader.get()?,
+            gateway_id: reader.get()?,
+            session_id: reader.get()?,
+        })
+    }
+}
+
+impl OepMessage for Quote {
+    fn message_type(&self) -> MsgType {
+        MsgType::Quote
+    }
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_gateway_id(&self) -> u8 {
+        self.gateway_id
+    }
+
+    fn get_session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    fn get_participant(&self) -> u64 {
+        self.participant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let original = Quote {
+            quote_id: 66,
+            participant: 1234567890,
+            book_id: 42,
+            bid_price: 99,
+            bid_quantity: 100,
+            ask_price: 101,
+            ask_quantity: 0,
+            gateway_id: 5,
+            session_id: 987654,
+        };
+
+        let encoded = original.encode();
+        assert_eq!(61, encoded.len());
+        let decoded = Quote::decode(encoded).unwrap();
+
+        assert_eq!({ decoded.quote_id }, { original.quote_id });
+        assert_eq!({ decoded.participant }, { original.participant });
+        assert_eq!({ decoded.book_id }, { original.book_id });
+        assert_eq!({ decoded.bid_price }, { original.bid_price });
+        assert_eq!({ decoded.bid_quantity }, { original.bid_quantity });
+        assert_eq!({ decoded.ask_price }, { original.ask_price });
+        assert_eq!({ decoded.ask_quantity }, { original.ask_quantity });
+        assert_eq!(decoded.gateway_id, original.gateway_id);
+        assert_eq!({ decoded.session_id }, { original.session_id });
+        assert_eq!(MsgType::Quote, decoded.message_type());
+    }
+}
@@ -0,0 +1,100 @@
+use std::error::Error;
+
+use crate::{
+    decoder::{Decoder, FieldReader, FieldWriter},
+    oep_message::{MsgType, OepMessage},
+};
+
+/// Pulls the quotes of a participant, in a book or in all of them
+/// (masscancel::ANY_BOOK). The other orders stay in the books
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct QuoteCancelAll {
+    pub participant: u64,
+    pub book_id: u64, // or ANY_BOOK
+    pub gateway_id: u8,
+    pub session_id: u32,
+}
+
+impl QuoteCancelAll {
+    pub fn get_book_id(&self) -> u64 {
+        self.book_id
+    }
+}
+
+pub const QUOTECANCELALL_SIZE: usize = std::mem::size_of::<QuoteCancelAll>();
+
+impl Decoder<QUOTECANCELALL_SIZE> for QuoteCancelAll {
+    fn encode(self) -> [u8; QUOTECANCELALL_SIZE] {
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.book_id)
+            .put(self.gateway_id)
+            .put(self.session_id)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; QUOTECANCELALL_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            book_id: reader.get()?,
+            gateway_id: reader.get()?,
+            session_id: reader.get()?,
+        })
+    }
+}
+
+impl OepMessage for QuoteCancelAll {
+    fn message_type(&self) -> MsgType {
+        MsgType::QuoteCancelAll
+    }
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_gateway_id(&self) -> u8 {
+        self.gateway_id
+    }
+
+    fn get_session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    fn get_participant(&self) -> u64 {
+        self.participant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let original = QuoteCancelAll {
+            participant: 1234567890,
+            book_id: 42,
+            gateway_id: 5,
+            session_id: 987654,
+        };
+
+        let encoded = original.encode();
+        assert_eq!(
+            [
+                210, 2, 150, 73, 0, 0, 0, 0, // participant (1234567890)
+                42, 0, 0, 0, 0, 0, 0, 0, // book_id (42)
+                5, // gateway_id
+                6, 18, 15, 0, // session_id (987654)
+            ],
+            encoded
+        );
+        let decoded = QuoteCancelAll::decode(encoded).unwrap();
+
+        assert_eq!({ decoded.participant }, { original.participant });
+        assert_eq!(decoded.get_book_id(), original.get_book_id());
+        assert_eq!(decoded.gateway_id, original.gateway_id);
+        assert_eq!({ decoded.session_id }, { original.session_id });
+        assert_eq!(MsgType::QuoteCancelAll, decoded.message_type());
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use crate::decoder::{DecodeError, Decoder, FieldReader, FieldWriter};
+
+/// What a gateway tells the matching engines with a registration
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum RegistrationKind {
+    // sent at startup and every second after, as long as the gateway runs
+    Register,
+    // sent when stopping, for the id to be free right away
+    Unregister,
+}
+
+impl From<RegistrationKind> for u8 {
+    fn from(value: RegistrationKind) -> Self {
+        match value {
+            RegistrationKind::Register => 0,
+            RegistrationKind::Unregister => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for RegistrationKind {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(RegistrationKind::Register),
+            1 => Ok(RegistrationKind::Unregister),
+            _ => Err(DecodeError),
+        }
+    }
+}
+
+// the gateway wants the execution reports of all the gateways, for its drop copy
+pub const FLAG_ALL_REPORTS: u8 = 1;
+
+// the address family of a registration, none for the reports by multicast
+const FAMILY_NONE: u8 = 0;
+const FAMILY_IPV4: u8 = 4;
+const FAMILY_IPV6: u8 = 6;
+
+/// Sent by a gateway to the matching engines on the order channel, so that
+/// they know which instance runs with @gateway_id and where its execution
+/// reports can be sent
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct GatewayRegistration {
+    pub gateway_id: u8,
+    kind: u8, // see RegistrationKind
+    pub flags: u8,
+    // tells the instances of a gateway apart, two running with the same id
+    // being a misconfiguration
+    pub instance: u32,
+    // the address and the port where the gateway takes its execution reports
+    // by unicast, family 0 to get them by multicast. An IPv4 address takes
+    // the first 4 bytes, the rest being 0
+    family: u8,
+    address: [u8; 16],
+    pub port: u16,
+}
+
+impl GatewayRegistration {
+    pub fn new(
+        gateway_id: u8,
+        kind: RegistrationKind,
+        flags: u8,
+        instance: u32,
+        endpoint: Option<SocketAddr>,
+    ) -> Self {
+        let mut address = [0; 16];
+        let family = match endpoint.map(|e| e.ip()) {
+            None => FAMILY_NONE,
+            Some(IpAddr::V4(ip)) => {
+                address[..4].copy_from_slice(&ip.octets());
+                FAMILY_IPV4
+            }
+            Some(IpAddr::V6(ip)) => {
+                address = ip.octets();
+                FAMILY_IPV6
+            }
+        };
+        Self {
+            gateway_id,
+            kind: kind.into(),
+            flags,
+            instance,
+            family,
+            address,
+            port: endpoint.map_or(0, |e| e.port()),
+        }
+    }
+
+    pub fn get_kind(&self) -> RegistrationKind {
+        // always valid, since it was checked when decoding
+        self.kind.try_into().unwrap()
+    }
+
+    /// Where the execution reports of the gateway can be sent by unicast
+    pub fn endpoint(&self) -> Option<SocketAddr> {
+        let address = self.address;
+        let ip = match self.family {
+            FAMILY_IPV4 => IpAddr::V4(Ipv4Addr::new(
+                address[0], address[1], address[2], address[3],
+            )),
+            FAMILY_IPV6 => IpAddr::V6(Ipv6Addr::from(address)),
+            _ => return None,
+        };
+        match (ip.is_unspecified(), self.port) {
+            (true, _) | (_, 0) => None,
+            (false, port) => Some(SocketAddr::new(ip, port)),
+        }
+    }
+
+    pub fn wants_all_reports(&self) -> bool {
+        self.flags & FLAG_ALL_REPORTS != 0
+    }
+}
+
+pub const GATEWAYREGISTRATION_SIZE: usize = std::mem::size_of::<GatewayRegistration>();
+
+impl Decoder<GATEWAYREGISTRATION_SIZE> for GatewayRegistration {
+    fn encode(self) -> [u8; GATEWAYREGISTRATION_SIZE] {
+        FieldWriter::default()
+            .put(self.gateway_id)
+            .put(self.kind)
+            .put(self.flags)
+            .put(self.instance)
+            .put(self.family)
+            .put(self.address)
+            .put(self.port)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; GATEWAYREGISTRATION_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        let registration = Self {
+            gateway_id: reader.get()?,
+            kind: reader.get()?,
+            flags: reader.get()?,
+            instance: reader.get()?,
+            family: reader.get()?,
+            address: reader.get()?,
+            port: reader.get()?,
+        };
+        RegistrationKind::try_from(registration.kind)?;
+        if ![FAMILY_NONE, FAMILY_IPV4, FAMILY_IPV6].contains(&registration.family) {
+            return Err(DecodeError.into());
+        }
+        Ok(registration)
+    }
+}
+
+/// How a matching engine took a registration
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum RegistrationStatus {
+    Accepted,
+    // another instance runs with the same gateway id
+    DuplicateId,
+}
+
+impl From<RegistrationStatus> for u8 {
+    fn from(value: RegistrationStatus) -> Self {
+        match value {
+            RegistrationStatus::Accepted => 0,
+            RegistrationStatus::DuplicateId => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for RegistrationStatus {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(RegistrationStatus::Accepted),
+            1 => Ok(RegistrationStatus::DuplicateId),
+            _ => Err(DecodeError),
+        }
+    }
+}
+
+/// Sent by a matching engine to the gateways on the internal publisher
+/// channel, answering the registration of @instance of @gateway_id
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct GatewayRegistrationReply {
+    pub engine_id: u8,
+    pub partition_id: u8,
+    pub gateway_id: u8,
+    status: u8, // see RegistrationStatus
+    pub instance: u32,
+}
+
+impl GatewayRegistrationReply {
+    pub fn new(
+        engine_id: u8,
+        partition_id: u8,
+        gateway_id: u8,
+        status: RegistrationStatus,
+        instance: u32,
+    ) -> Self {
+        Self {
+            engine_id,
+            partition_id,
+            gateway_id,
+            status: status.into(),
+            instance,
+        }
+    }
+
+    pub fn get_status(&self) -> RegistrationStatus {
+        // always valid, since it was checked when decoding
+        self.status.try_into().unwrap()
+    }
+}
+
+pub const GATEWAYREGISTRATIONREPLY_SIZE: usize = std::mem::size_of::<GatewayRegistrationReply>();
+
+impl Decoder<GATEWAYREGISTRATIONREPLY_SIZE> for GatewayRegistrationReply {
+    fn encode(self) -> [u8; GATEWAYREGISTRATIONREPLY_SIZE] {
+        FieldWriter::default()
+            .put(self.engine_id)
+            .put(self.partition_id)
+            .put(self.gateway_id)
+            .put(self.status)
+            .put(self.instance)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; GATEWAYREGISTRATIONREPLY_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        let reply = Self {
+            engine_id: reader.get()?,
+            partition_id: reader.get()?,
+            gateway_id: reader.get()?,
+            status: reader.get()?,
+            instance: reader.get()?,
+        };
+        RegistrationStatus::try_from(reply.status)?;
+        Ok(reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_registration() {
+        let endpoint = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 24001);
+        let original = GatewayRegistration::new(
+            3,
+            RegistrationKind::Register,
+            FLAG_ALL_REPORTS,
+            0x01020304,
+            Some(endpoint),
+        );
+
+        let encoded = original.encode();
+        assert_eq!(
+            [3, 0, 1, 4, 3, 2, 1, 4, 10, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xc1, 0x5d],
+            encoded
+        );
+        let decoded = GatewayRegistration::decode(encoded).unwrap();
+
+        assert_eq!(3, decoded.gateway_id);
+        assert_eq!(RegistrationKind::Register, decoded.get_kind());
+        assert!(decoded.wants_all_reports());
+        assert_eq!(0x01020304, { decoded.instance });
+        assert_eq!(Some(endpoint), decoded.endpoint());
+    }
+
+    #[test]
+    fn test_registration_without_endpoint() {
+        let original = GatewayRegistration::new(3, RegistrationKind::Unregister, 0, 7, None);
+        let decoded = GatewayRegistration::decode(original.encode()).unwrap();
+        assert_eq!(RegistrationKind::Unregister, decoded.get_kind());
+        assert!(!decoded.wants_all_reports());
+        assert_eq!(None, decoded.endpoint());
+    }
+
+    #[test]
+    fn test_registration_ipv6() {
+        let endpoint = SocketAddr::new(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1).into(), 24001);
+        let original =
+            GatewayRegistration::new(3, RegistrationKind::Register, 0, 7, Some(endpoint));
+        let encoded = original.encode();
+        assert_eq!([6, 0xfd, 0], encoded[7..10]);
+        assert_eq!(1, encoded[23]);
+        let decoded = GatewayRegistration::decode(encoded).unwrap();
+        assert_eq!(Some(endpoint), decoded.endpoint());
+    }
+
+    #[test]
+    fn test_decode_invalid_kind() {
+        let mut encoded =
+            GatewayRegistration::new(3, RegistrationKind::Register, 0, 7, None).encode();
+        encoded[1] = 2;
+        assert!(GatewayRegistration::decode(encoded).is_err());
+        // nor an unknown address family
+        encoded[1] = 0;
+        encoded[7] = 5;
+        assert!(GatewayRegistration::decode(encoded).is_err());
+    }
+
+    #[test]
+    fn test_encode_decode_reply() {
+        let original =
+            GatewayRegistrationReply::new(1, 2, 3, RegistrationStatus::DuplicateId, 0x01020304);
+
+        let encoded = original.encode();
+        assert_eq!([1, 2, 3, 1, 4, 3, 2, 1], encoded);
+        let decoded = GatewayRegistrationReply::decode(encoded).unwrap();
+
+        assert_eq!(1, decoded.engine_id);
+        assert_eq!(2, decoded.partition_id);
+        assert_eq!(3, decoded.gateway_id);
+        assert_eq!(RegistrationStatus::DuplicateId, decoded.get_status());
+        assert_eq!(0x01020304, { decoded.instance });
+        assert!(GatewayRegistrationReply::decode([1, 2, 3, 2, 0, 0, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use std::error::Error;
+
+use crate::{
+    decoder::{Decoder, FieldReader, FieldWriter},
+    oep_message::{MsgType, OepMessage},
+};
+
+/// Cancels a standing order and enters a new one in its place, in one step
+/// The new order can have a different type, but not a different side
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Replace {
+    pub orig_order_id: u64, // exchange id of the order being replaced
+    pub client_order_id: u64,
+    pub participant: u64,
+    pub book_id: u64,
+    pub quantity: u64,
+    pub price: u64,
+    pub order_type: u16,
+    pub side: u8,
+    pub gateway_id: u8,
+    pub session_id: u32,
+    // same meaning as in the new order
+    pub expiry: u64,
+    pub stop_price: u64,
+    pub display_quantity: u64,
+}
+
+pub const REPLACE_SIZE: usize = std::mem::size_of::<Replace>();
+
+impl Decoder<REPLACE_SIZE> for Replace {
+    fn encode(self) -> [u8; REPLACE_SIZE] {
+        FieldWriter::default()
+            .put(self.orig_order_id)
+            .put(self.client_order_id)
+            .put(self.participant)
+            .put(self.book_id)
+            .put(self.quantity)
+            .put(self.price)
+            .put(self.order_type)
+            .put(self.side)
+            .put(self.gateway_id)
+            .put(self.session_id)
+            .put(self.expiry)
+            .put(self.stop_price)
+            .put(self.display_quantity)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; REPLACE_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            orig_order_id: reader.get()?,
+            client_order_id: reader.get()?,
+            participant: reader.get()?,
+            book_id: reader.get()?,
+            quantity: reader.get()?,
+            price: reader.get()?,
+            order_type: reader.get()?,
+            side: reader.get()?,
+            gateway_id: reader.get()?,
+            session_id: reader.get()?,
+            expiry: reader.get()?,
+            stop_price: reader.get()?,
+            display_quantity: reader.get()?,
+        })
+    }
+}
+
+impl OepMessage for Replace {
+    fn message_type(&self) -> MsgType {
+        MsgType::Replace
+    }
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_gateway_id(&self) -> u8 {
+        self.gateway_id
+    }
+
+    fn get_session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    fn get_participant(&self) -> u64 {
+        self.participant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Replace {
+        Replace {
+            orig_order_id: 9876543210,
+            client_order_id: 66,
+            participant: 1234567890,
+            book_id: 42,
+            quantity: 100,
+            price: 1000,
+            order_type: 1,
+            side: 1,
+            gateway_id: 5,
+            session_id: 987654,
+            expiry: 3600,
+            stop_price: 0,
+            display_quantity: 10,
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let original = sample();
+
+        let encoded = original.encode();
+        assert_eq!(80, encoded.len());
+        assert_eq!([234, 22, 176, 76, 2, 0, 0, 0], encoded[0..8]);
+        let decoded = Replace::decode(encoded).unwrap();
+
+        assert_eq!({ decoded.orig_order_id }, { original.orig_order_id });
+        assert_eq!({ decoded.client_order_id }, { original.client_order_id });
+        assert_eq!({ decoded.participant }, { original.participant });
+        assert_eq!({ decoded.book_id }, { original.book_id });
+        assert_eq!({ decoded.quantity }, { original.quantity });
+        assert_eq!({ decoded.price }, { original.price });
+        assert_eq!({ decoded.order_type }, { original.order_type });
+        assert_eq!(decoded.side, original.side);
+        assert_eq!(decoded.gateway_id, original.gateway_id);
+        assert_eq!({ decoded.session_id }, { original.session_id });
+        assert_eq!({ decoded.expiry }, { original.expiry });
+        assert_eq!({ decoded.display_quantity }, { original.display_quantity });
+    }
+
+    #[test]
+    fn test_oep_message_traits() {
+        let replace = sample();
+
+        assert_eq!(replace.message_type(), MsgType::Replace);
+        assert_eq!(replace.message_len(), REPLACE_SIZE);
+        assert_eq!(replace.get_gateway_id(), 5);
+        assert_eq!(replace.get_session_id(), 987654);
+        assert_eq!(replace.get_participant(), 1234567890);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{
+    decoder::{FieldReader, FieldWriter},
+    oep_message::{MsgType, OepMessage},
+    Decoder,
+};
+use std::error::Error;
+
+/// Sent by the clients to get again the messages of their session, starting
+/// with the one sequenced @from_seq, e.g. the execution reports missed while
+/// they were disconnected
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct ResendRequest {
+    pub participant: u64,
+    pub session_id: u32,
+    pub gateway_id: u8,
+    pub from_seq: u32,
+}
+
+impl ResendRequest {
+    pub fn new(participant: u64, session_id: u32, gateway_id: u8, from_seq: u32) -> Self {
+        Self {
+            participant,
+            session_id,
+            gateway_id,
+            from_seq,
+        }
+    }
+}
+
+pub const RESENDREQUEST_SIZE: usize = std::mem::size_of::<ResendRequest>();
+
+impl Decoder<RESENDREQUEST_SIZE> for ResendRequest {
+    fn encode(self) -> [u8; RESENDREQUEST_SIZE] {
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.session_id)
+            .put(self.gateway_id)
+            .put(self.from_seq)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; RESENDREQUEST_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            session_id: reader.get()?,
+            gateway_id: reader.get()?,
+            from_seq: reader.get()?,
+        })
+    }
+}
+
+impl OepMessage for ResendRequest {
+    fn message_type(&self) -> MsgType {
+        MsgType::ResendRequest
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_gateway_id(&self) -> u8 {
+        self.gateway_id
+    }
+
+    fn get_session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    fn get_participant(&self) -> u64 {
+        self.participant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = ResendRequest::new(0x0102030405060708, 600, 1, 300);
+
+        let encoded = original.encode();
+        assert_eq!(
+            [8, 7, 6, 5, 4, 3, 2, 1, 88, 2, 0, 0, 1, 44, 1, 0, 0],
+            encoded
+        );
+        let decoded = ResendRequest::decode(encoded).unwrap();
+
+        assert_eq!({ original.participant }, { decoded.participant });
+        assert_eq!({ original.session_id }, { decoded.session_id });
+        assert_eq!({ original.gateway_id }, { decoded.gateway_id });
+        assert_eq!({ original.from_seq }, { decoded.from_seq });
+    }
+
+    #[test]
+    fn test_resend_request_size() {
+        assert_eq!(17, RESENDREQUEST_SIZE);
+    }
+}
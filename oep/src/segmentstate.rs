@@ -0,0 +1,71 @@
+use std::error::Error;
+
+use crate::decoder::{Decoder, FieldReader, FieldWriter};
+use crate::statechange::StateChangeReason;
+
+/// All the instruments of a segment, e.g. the equities, put in a new state
+/// at once, published on the feed after the state changes of the instruments
+/// Not a real OEP message either, just sharing the encoding
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentState {
+    pub segment: u16,
+    // nanoseconds since the unix epoch
+    pub timestamp: u64,
+    // as encoded in the instrument message
+    pub state: u8,
+    pub reason: u8, // see StateChangeReason
+}
+
+impl SegmentState {
+    pub fn get_reason(&self) -> StateChangeReason {
+        self.reason.into()
+    }
+}
+
+pub const SEGMENTSTATE_SIZE: usize = std::mem::size_of::<SegmentState>();
+
+impl Decoder<SEGMENTSTATE_SIZE> for SegmentState {
+    fn encode(self) -> [u8; SEGMENTSTATE_SIZE] {
+        FieldWriter::default()
+            .put(self.segment)
+            .put(self.timestamp)
+            .put(self.state)
+            .put(self.reason)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; SEGMENTSTATE_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            segment: reader.get()?,
+            timestamp: reader.get()?,
+            state: reader.get()?,
+            reason: reader.get()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = SegmentState {
+            segment: 0x0102,
+            timestamp: 1000,
+            state: 3,
+            reason: StateChangeReason::Schedule.into(),
+        };
+
+        let encoded = original.encode();
+        assert_eq!(12, encoded.len());
+        assert_eq!([2, 1], encoded[..2]);
+        let decoded = SegmentState::decode(encoded).unwrap();
+        assert_eq!(0x0102, { decoded.segment });
+        assert_eq!({ decoded.timestamp }, { original.timestamp });
+        assert_eq!(3, decoded.state);
+        assert_eq!(StateChangeReason::Schedule, decoded.get_reason());
+    }
+}
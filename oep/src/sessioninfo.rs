@@ -1,4 +1,5 @@
 use crate::{
+    decoder::{FieldReader, FieldWriter},
     oep_message::{MsgType, OepMessage},
     Decoder,
 };
@@ -6,7 +7,6 @@ use std::error::Error;
 
 /// not a real OEP message, but instead it's sent
 /// by the gateway to the matching engine when a session disconnects
-
 #[repr(packed)]
 #[derive(Clone, Copy)]
 pub struct SessionInfo {
@@ -18,9 +18,9 @@ pub struct SessionInfo {
 impl SessionInfo {
     pub fn new(participant: u64, session_id: u32, gateway_id: u8) -> Self {
         Self {
-            participant: participant,
-            session_id: session_id,
-            gateway_id: gateway_id,
+            participant,
+            session_id,
+            gateway_id,
         }
     }
 }
@@ -29,11 +29,20 @@ pub const SESSIONINFO_SIZE: usize = std::mem::size_of::<SessionInfo>();
 
 impl Decoder<SESSIONINFO_SIZE> for SessionInfo {
     fn encode(self) -> [u8; SESSIONINFO_SIZE] {
-        unsafe { std::mem::transmute::<Self, [u8; SESSIONINFO_SIZE]>(self) }
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.session_id)
+            .put(self.gateway_id)
+            .finish()
     }
 
     fn decode(buffer: [u8; SESSIONINFO_SIZE]) -> Result<Self, Box<dyn Error>> {
-        unsafe { Ok(std::mem::transmute::<[u8; SESSIONINFO_SIZE], Self>(buffer).try_into()?) }
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            session_id: reader.get()?,
+            gateway_id: reader.get()?,
+        })
     }
 }
 
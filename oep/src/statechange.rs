@@ -0,0 +1,114 @@
+use std::error::Error;
+
+use crate::decoder::{Decoder, FieldReader, FieldWriter};
+
+/// What moved an instrument to another state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateChangeReason {
+    // an instrument update of the clearing
+    Clearing = 0,
+    // the trading schedule of the engine
+    Schedule = 1,
+    // a trade too far away from the reference price, the market going into auction
+    PriceVariation = 2,
+    // the volatility interruption, see market::volatility
+    Volatility = 3,
+    // the end of the volatility halt
+    Resumed = 4,
+    // the engine stopping, closing all its markets
+    Shutdown = 5,
+    // the contract of a derivative expiring, its market closing for good
+    Expiry = 6,
+}
+
+impl From<StateChangeReason> for u8 {
+    fn from(reason: StateChangeReason) -> Self {
+        reason as u8
+    }
+}
+
+impl From<u8> for StateChangeReason {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => StateChangeReason::Schedule,
+            2 => StateChangeReason::PriceVariation,
+            3 => StateChangeReason::Volatility,
+            4 => StateChangeReason::Resumed,
+            5 => StateChangeReason::Shutdown,
+            6 => StateChangeReason::Expiry,
+            _ => StateChangeReason::Clearing,
+        }
+    }
+}
+
+/// A market opening, halting, going into auction or closing, published on
+/// the feed as it happens
+/// Not a real OEP message either, just sharing the encoding
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct InstrumentStateChange {
+    pub book_id: u64,
+    // nanoseconds since the unix epoch
+    pub timestamp: u64,
+    // both as encoded in the instrument message
+    pub previous_state: u8,
+    pub state: u8,
+    pub reason: u8, // see StateChangeReason
+}
+
+impl InstrumentStateChange {
+    pub fn get_reason(&self) -> StateChangeReason {
+        self.reason.into()
+    }
+}
+
+pub const STATECHANGE_SIZE: usize = std::mem::size_of::<InstrumentStateChange>();
+
+impl Decoder<STATECHANGE_SIZE> for InstrumentStateChange {
+    fn encode(self) -> [u8; STATECHANGE_SIZE] {
+        FieldWriter::default()
+            .put(self.book_id)
+            .put(self.timestamp)
+            .put(self.previous_state)
+            .put(self.state)
+            .put(self.reason)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; STATECHANGE_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            book_id: reader.get()?,
+            timestamp: reader.get()?,
+            previous_state: reader.get()?,
+            state: reader.get()?,
+            reason: reader.get()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = InstrumentStateChange {
+            book_id: 0x0102030405060708,
+            timestamp: 1000,
+            previous_state: 0,
+            state: 3,
+            reason: StateChangeReason::Volatility.into(),
+        };
+
+        let encoded = original.encode();
+        assert_eq!(19, encoded.len());
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1], encoded[..8]);
+        let decoded = InstrumentStateChange::decode(encoded).unwrap();
+        assert_eq!({ decoded.book_id }, { original.book_id });
+        assert_eq!({ decoded.timestamp }, { original.timestamp });
+        assert_eq!((0, 3), (decoded.previous_state, decoded.state));
+        assert_eq!(StateChangeReason::Volatility, decoded.get_reason());
+        assert_eq!(StateChangeReason::Clearing, StateChangeReason::from(99));
+    }
+}
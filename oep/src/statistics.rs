@@ -0,0 +1,95 @@
+use std::error::Error;
+
+use crate::decoder::{Decoder, FieldReader, FieldWriter};
+
+/// Trading statistics of an instrument for the current session,
+/// published periodically on the feed
+/// Not a real OEP message either, just sharing the encoding
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct Statistics {
+    pub book_id: u64,
+    // all the prices are 0 until the first trade of the session
+    pub last_price: u64,
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    // only set once the market closed
+    pub close: u64,
+    pub volume: u64,
+    // volume weighted average price, rounded down
+    pub vwap: u64,
+    pub trade_count: u64,
+}
+
+pub const STATISTICS_SIZE: usize = std::mem::size_of::<Statistics>();
+
+impl Decoder<STATISTICS_SIZE> for Statistics {
+    fn encode(self) -> [u8; STATISTICS_SIZE] {
+        FieldWriter::default()
+            .put(self.book_id)
+            .put(self.last_price)
+            .put(self.open)
+            .put(self.high)
+            .put(self.low)
+            .put(self.close)
+            .put(self.volume)
+            .put(self.vwap)
+            .put(self.trade_count)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; STATISTICS_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            book_id: reader.get()?,
+            last_price: reader.get()?,
+            open: reader.get()?,
+            high: reader.get()?,
+            low: reader.get()?,
+            close: reader.get()?,
+            volume: reader.get()?,
+            vwap: reader.get()?,
+            trade_count: reader.get()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = Statistics {
+            book_id: 0x0102030405060708,
+            last_price: 1010,
+            open: 1000,
+            high: 1020,
+            low: 990,
+            close: 0,
+            volume: 300,
+            vwap: 1005,
+            trade_count: 4,
+        };
+
+        let encoded = original.encode();
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1], encoded[0..8]);
+        let decoded = Statistics::decode(encoded).unwrap();
+
+        assert_eq!({ original.book_id }, { decoded.book_id });
+        assert_eq!({ original.last_price }, { decoded.last_price });
+        assert_eq!({ original.open }, { decoded.open });
+        assert_eq!({ original.high }, { decoded.high });
+        assert_eq!({ original.low }, { decoded.low });
+        assert_eq!({ original.close }, { decoded.close });
+        assert_eq!({ original.volume }, { decoded.volume });
+        assert_eq!({ original.vwap }, { decoded.vwap });
+        assert_eq!({ original.trade_count }, { decoded.trade_count });
+    }
+
+    #[test]
+    fn test_statistics_size() {
+        assert_eq!(72, STATISTICS_SIZE);
+    }
+}
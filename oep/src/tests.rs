@@ -1,110 +1,490 @@
-#[cfg(test)]
-mod tests {
-    use crate::{neworder::NewOrder, oep_decode, oep_message::MsgType};
-
-    #[test]
-    fn decode_new_order() {
-        let new_order_buffer = [
-            1, 0, 0, 0, 20, 0, 0, 0, 50, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0,
-            0, 0, 0, 0, 101, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 66, 0, 1, 55, 22, 0, 0,
-            0,
-        ];
-        let msg = oep_decode(&new_order_buffer);
-        if msg.is_err() {
-            let x = msg.as_ref().err().unwrap();
-            println!("Error in decoding: {}", x.to_string());
-        }
-        assert!(msg.is_ok());
-        match msg {
-            Ok(boxed_msg) => {
-                let msg = boxed_msg.as_ref();
-                assert_eq!(msg.message_type(), MsgType::NewOrder);
-                let new_order: &NewOrder = msg
-                    .as_any()
-                    .downcast_ref::<NewOrder>()
-                    .expect("Bad pointer conversion");
-                let order_id = new_order.client_order_id;
-                let participant = new_order.participant;
-                let book = new_order.book_id;
-                let quantity = new_order.quantity;
-                let flags = new_order.order_type;
-                let gateway_id = new_order.gateway_id;
-                let session_id = new_order.session_id;
-                let price = new_order.price;
-
-                assert_eq!(order_id, 50);
-                assert_eq!(participant, 1);
-                assert_eq!(book, 2);
-                assert_eq!(quantity, 101);
-                assert_eq!(price, 100);
-                assert_eq!(new_order.side, 1);
-                assert_eq!(flags, 66);
-                assert_eq!(gateway_id, 55);
-                assert_eq!(session_id, 22);
-            }
-            Err(_) => todo!(), // already matched up
+use crate::{
+    auctioninfo::{AuctionInfo, AUCTIONINFO_SIZE},
+    cancel::{Cancel, CANCEL_SIZE, CANCEL_V5_SIZE},
+    decoder::Decoder,
+    engine_status::{EngineStatus, ENGINESTATUS_SIZE},
+    eodsummary::{EodSummary, EODSUMMARY_SIZE},
+    execution_report::{
+        execution_report_size, ExecutionReport, EXECUTIONREPORT_SIZE, EXECUTIONREPORT_V6_SIZE,
+    },
+    header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+    heartbeat::{Heartbeat, HEARTBEAT_SIZE},
+    ingress::{IngressHeader, IngressKind, IngressNak, INGRESSHEADER_SIZE, INGRESSNAK_SIZE},
+    login::{Login, LOGIN_SIZE},
+    loginreject::{LoginReject, LOGINREJECT_SIZE},
+    masscancel::{MassCancel, MASSCANCEL_SIZE},
+    massquote::{MassQuote, MASSQUOTE_SIZE},
+    modify::{Modify, MODIFY_SIZE, MODIFY_V5_SIZE},
+    neworder::{NewOrder, NEWORDER_SIZE},
+    oep_decode,
+    oep_message::MsgType,
+    quote::{Quote, QUOTE_SIZE},
+    quotecancelall::{QuoteCancelAll, QUOTECANCELALL_SIZE},
+    replace::{Replace, REPLACE_SIZE},
+    resendrequest::{ResendRequest, RESENDREQUEST_SIZE},
+    sessioninfo::{SessionInfo, SESSIONINFO_SIZE},
+    statechange::{InstrumentStateChange, STATECHANGE_SIZE},
+    statistics::{Statistics, STATISTICS_SIZE},
+    trade::{Trade, TRADE_SIZE},
+    tradebust::{TradeBust, TRADEBUST_SIZE},
+    tradecapture::{TradeCapture, TRADECAPTURE_SIZE},
+    version::{OepError, VersionReject, MIN_OEP_VERSION, VERSIONREJECT_SIZE},
+};
+
+#[test]
+fn decode_new_order() {
+    let new_order_buffer = [
+        5, 0, 0, 0, 72, 0, 0, 0, 1, 0, 0, 0, 50, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0,
+        0, 0, 0, 0, 0, 0, 101, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 66, 0, 1, 55, 22, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let msg = oep_decode(&new_order_buffer);
+    if msg.is_err() {
+        let x = msg.as_ref().err().unwrap();
+        println!("Error in decoding: {}", x);
+    }
+    assert!(msg.is_ok());
+    match msg {
+        Ok(boxed_msg) => {
+            let msg = boxed_msg.as_ref();
+            assert_eq!(msg.message_type(), MsgType::NewOrder);
+            let new_order: &NewOrder = msg
+                .as_any()
+                .downcast_ref::<NewOrder>()
+                .expect("Bad pointer conversion");
+            let order_id = new_order.client_order_id;
+            let participant = new_order.participant;
+            let book = new_order.book_id;
+            let quantity = new_order.quantity;
+            let flags = new_order.order_type;
+            let gateway_id = new_order.gateway_id;
+            let session_id = new_order.session_id;
+            let price = new_order.price;
+
+            assert_eq!(order_id, 50);
+            assert_eq!(participant, 1);
+            assert_eq!(book, 2);
+            assert_eq!(quantity, 101);
+            assert_eq!(price, 100);
+            assert_eq!(new_order.side, 1);
+            assert_eq!(flags, 66);
+            assert_eq!(gateway_id, 55);
+            assert_eq!(session_id, 22);
         }
+        Err(_) => todo!(), // already matched up
     }
+}
+
+#[test]
+fn short_header() {
+    let new_order_buffer = [1, 0, 0, 0, 20];
+    let msg = oep_decode(&new_order_buffer);
+    assert!(msg.is_err());
+}
+
+#[test]
+fn short_message() {
+    let new_order_buffer = [3, 0, 0, 0, 20, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0];
+    let msg = oep_decode(&new_order_buffer);
+    assert!(msg.is_err());
+}
 
-    #[test]
-    fn short_header() {
-        let new_order_buffer = [1, 0, 0, 0, 20];
-        let msg = oep_decode(&new_order_buffer);
+#[test]
+fn too_short_until_complete() {
+    let new_order_buffer = [
+        5, 0, 0, 0, 72, 0, 0, 0, 2, 0, 0, 0, 60, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0,
+        0, 0, 0, 0, 0, 0, 101, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 66, 0, 1, 55, 22, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    for i in 0..new_order_buffer.len() {
+        let msg = oep_decode(&new_order_buffer[..i]);
         assert!(msg.is_err());
     }
+    let msg = oep_decode(&new_order_buffer);
+    if msg.is_err() {
+        let x = msg.as_ref().err().unwrap();
+        println!("Error in decoding: {}", x);
+    }
+    assert!(msg.is_ok());
+    match msg {
+        Ok(boxed_msg) => {
+            let msg = boxed_msg.as_ref();
+            assert_eq!(msg.message_type(), MsgType::NewOrder);
+            let new_order: &NewOrder = msg
+                .as_any()
+                .downcast_ref::<NewOrder>()
+                .expect("Bad pointer conversion");
+            let order_id = new_order.client_order_id;
+            let participant = new_order.participant;
+            let book = new_order.book_id;
+            let quantity = new_order.quantity;
+            let price = new_order.price;
+            let flags = new_order.order_type;
+            let gateway_id = new_order.gateway_id;
+            let session_id = new_order.session_id;
 
-    #[test]
-    fn short_message() {
-        let new_order_buffer = [1, 0, 0, 0, 20, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0];
-        let msg = oep_decode(&new_order_buffer);
-        assert!(msg.is_err());
+            assert_eq!(order_id, 60);
+            assert_eq!(participant, 1);
+            assert_eq!(book, 2);
+            assert_eq!(quantity, 101);
+            assert_eq!(price, 100);
+            assert_eq!(new_order.side, 1);
+            assert_eq!(flags, 66);
+            assert_eq!(gateway_id, 55);
+            assert_eq!(session_id, 22);
+        }
+        Err(_) => todo!(), // already matched up
+    }
+}
+
+#[test]
+fn unsupported_version() {
+    let new_order_buffer = [
+        1, 0, 0, 0, 20, 0, 0, 0, 1, 0, 0, 0, 50, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0,
+        0, 0, 0, 0, 0, 0, 101, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 66, 0, 1, 55, 22, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let msg = oep_decode(&new_order_buffer);
+    assert!(msg.is_err());
+    let e = msg.err().unwrap();
+    assert_eq!(std::io::ErrorKind::InvalidData, e.kind());
+    assert_eq!(Some(OepError::UnsupportedVersion(1)), OepError::of(&e));
+}
+
+#[test]
+fn login_in_any_version() {
+    // the version is negotiated with the login, which has to be understood
+    let login = Login::new(50, 22, 55, "user").encode();
+    for version in [1, OEP_VERSION, OEP_VERSION + 1] {
+        let header = OepHeader::new(version, MsgType::Login.into(), LOGIN_SIZE as u32);
+        let msg = oep_decode(&[header.encode().as_slice(), &login].concat()).unwrap();
+        assert_eq!(MsgType::Login, msg.message_type());
+        assert_eq!(22, msg.get_session_id());
+    }
+}
+
+#[test]
+fn length_of_the_version() {
+    let header = OepHeader::new(OEP_VERSION, MsgType::Heartbeat.into(), 14);
+    let msg = oep_decode(&[header.encode().as_slice(), &[0; 14]].concat());
+    assert_eq!(
+        Some(OepError::InvalidLength {
+            msg_type: MsgType::Heartbeat.into(),
+            expected: 13,
+            received: 14
+        }),
+        OepError::of(&msg.err().unwrap())
+    );
+}
+
+#[test]
+fn cancel_and_modify_of_v5() {
+    // version 5 has no orig_client_order_id, the rest is the same
+    let cancel = Cancel {
+        participant: 50,
+        order_id: 7,
+        book_id: 1000,
+        side: 1,
+        gateway_id: 55,
+        session_id: 22,
+        orig_client_order_id: 99,
     }
+    .encode();
+    let header = OepHeader::new(5, MsgType::Cancel.into(), CANCEL_V5_SIZE as u32);
+    let msg =
+        oep_decode(&[header.encode().as_slice(), &cancel[..CANCEL_V5_SIZE]].concat()).unwrap();
+    let decoded = msg.as_any().downcast_ref::<Cancel>().unwrap();
+    assert_eq!(7, { decoded.order_id });
+    assert_eq!(0, { decoded.orig_client_order_id });
+    // the layout of version 6 is too long for version 5
+    let header = OepHeader::new(5, MsgType::Cancel.into(), CANCEL_SIZE as u32);
+    let msg = oep_decode(&[header.encode().as_slice(), &cancel].concat());
+    assert_eq!(
+        Some(OepError::InvalidLength {
+            msg_type: MsgType::Cancel.into(),
+            expected: CANCEL_V5_SIZE,
+            received: CANCEL_SIZE
+        }),
+        OepError::of(&msg.err().unwrap())
+    );
+    let header = OepHeader::new(6, MsgType::Cancel.into(), CANCEL_SIZE as u32);
+    let msg = oep_decode(&[header.encode().as_slice(), &cancel].concat()).unwrap();
+    let decoded = msg.as_any().downcast_ref::<Cancel>().unwrap();
+    assert_eq!(99, { decoded.orig_client_order_id });
 
-    #[test]
-    fn too_short_until_complete() {
-        let new_order_buffer = [
-            1, 0, 0, 0, 20, 0, 0, 0, 60, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0,
-            0, 0, 0, 0, 101, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0, 66, 0, 1, 55, 22, 0, 0,
-            0,
-        ];
-        for i in 0..new_order_buffer.len() {
-            let msg = oep_decode(&new_order_buffer[..i]);
-            assert!(msg.is_err());
+    let modify = Modify {
+        participant: 50,
+        order_id: 7,
+        book_id: 1000,
+        quantity: 10,
+        price: 500,
+        side: 1,
+        gateway_id: 55,
+        session_id: 22,
+        orig_client_order_id: 99,
+    }
+    .encode();
+    let header = OepHeader::new(5, MsgType::Modify.into(), MODIFY_V5_SIZE as u32);
+    let msg =
+        oep_decode(&[header.encode().as_slice(), &modify[..MODIFY_V5_SIZE]].concat()).unwrap();
+    let decoded = msg.as_any().downcast_ref::<Modify>().unwrap();
+    assert_eq!(500, { decoded.price });
+    assert_eq!(22, { decoded.session_id });
+    assert_eq!(0, { decoded.orig_client_order_id });
+}
+
+#[test]
+fn execution_report_of_each_version() {
+    let mut ereport = ExecutionReport::decode([0; EXECUTIONREPORT_SIZE]).unwrap();
+    ereport.order_id = 7;
+    ereport.reject_reason = 3;
+    ereport.trade_id = 99;
+    let encoded = ereport.encode();
+    for version in MIN_OEP_VERSION..=OEP_VERSION {
+        let size = execution_report_size(version);
+        let header = OepHeader::new(version, MsgType::ExecutionReport.into(), size as u32);
+        let msg = oep_decode(&[header.encode().as_slice(), &encoded[..size]].concat()).unwrap();
+        let decoded = msg.as_any().downcast_ref::<ExecutionReport>().unwrap();
+        assert_eq!(7, { decoded.order_id });
+        assert_eq!(3, decoded.reject_reason);
+        // version 6 and before have no trade_id, the rest is the same
+        let trade_id = if version < 7 { 0 } else { 99 };
+        assert_eq!(trade_id, decoded.get_trade_id());
+    }
+    assert_eq!(EXECUTIONREPORT_V6_SIZE, execution_report_size(6));
+    assert_eq!(EXECUTIONREPORT_SIZE, execution_report_size(7));
+    // the layout of version 7 is too long for version 6
+    let header = OepHeader::new(
+        6,
+        MsgType::ExecutionReport.into(),
+        EXECUTIONREPORT_SIZE as u32,
+    );
+    let msg = oep_decode(&[header.encode().as_slice(), &encoded].concat());
+    assert_eq!(
+        Some(OepError::InvalidLength {
+            msg_type: MsgType::ExecutionReport.into(),
+            expected: EXECUTIONREPORT_V6_SIZE,
+            received: EXECUTIONREPORT_SIZE
+        }),
+        OepError::of(&msg.err().unwrap())
+    );
+}
+
+#[test]
+fn decode_heartbeat() {
+    let heartbeat_buffer = [
+        5, 0, 10, 0, 13, 0, 0, 0, 0, 0, 0, 0, 50, 0, 0, 0, 0, 0, 0, 0, 22, 0, 0, 0, 55,
+    ];
+    let msg = oep_decode(&heartbeat_buffer).unwrap();
+    assert_eq!(MsgType::Heartbeat, msg.message_type());
+    assert_eq!(50, msg.get_participant());
+    assert_eq!(22, msg.get_session_id());
+    assert_eq!(55, msg.get_gateway_id());
+    assert_eq!(13, msg.message_len());
+}
+
+#[test]
+fn decode_resend_request() {
+    let resend_request_buffer = [
+        5, 0, 11, 0, 17, 0, 0, 0, 0, 0, 0, 0, 50, 0, 0, 0, 0, 0, 0, 0, 22, 0, 0, 0, 55, 44, 1, 0, 0,
+    ];
+    let msg = oep_decode(&resend_request_buffer).unwrap();
+    assert_eq!(MsgType::ResendRequest, msg.message_type());
+    assert_eq!(50, msg.get_participant());
+    assert_eq!(22, msg.get_session_id());
+    assert_eq!(55, msg.get_gateway_id());
+    assert_eq!(17, msg.message_len());
+    let resend_request = msg.as_any().downcast_ref::<ResendRequest>().unwrap();
+    assert_eq!(300, { resend_request.from_seq });
+}
+
+// the messages with fields restricted to the values of an enum
+const INVALID_ENUMS: [&str; 3] = ["EngineStatus", "IngressHeader", "MassQuote"];
+
+// every message decodes any S bytes but the invalid enum values, and
+// encodes them back as they were. No more, no less than S bytes are taken
+macro_rules! check_codec {
+    ($($message:ty, $size:expr);* $(;)?) => {$(
+        let mut buffer = [0u8; $size];
+        for (i, b) in buffer.iter_mut().enumerate() {
+            *b = (i as u8).wrapping_mul(7).wrapping_add(1);
         }
-        let msg = oep_decode(&new_order_buffer);
-        if msg.is_err() {
-            let x = msg.as_ref().err().unwrap();
-            println!("Error in decoding: {}", x.to_string());
+        match <$message>::decode(buffer) {
+            Ok(msg) => assert_eq!(buffer, msg.encode(), "{}", stringify!($message)),
+            Err(_) => assert!(INVALID_ENUMS.contains(&stringify!($message))),
         }
-        assert!(msg.is_ok());
-        match msg {
-            Ok(boxed_msg) => {
-                let msg = boxed_msg.as_ref();
-                assert_eq!(msg.message_type(), MsgType::NewOrder);
-                let new_order: &NewOrder = msg
-                    .as_any()
-                    .downcast_ref::<NewOrder>()
-                    .expect("Bad pointer conversion");
-                let order_id = new_order.client_order_id;
-                let participant = new_order.participant;
-                let book = new_order.book_id;
-                let quantity = new_order.quantity;
-                let price = new_order.price;
-                let flags = new_order.order_type;
-                let gateway_id = new_order.gateway_id;
-                let session_id = new_order.session_id;
-
-                assert_eq!(order_id, 60);
-                assert_eq!(participant, 1);
-                assert_eq!(book, 2);
-                assert_eq!(quantity, 101);
-                assert_eq!(price, 100);
-                assert_eq!(new_order.side, 1);
-                assert_eq!(flags, 66);
-                assert_eq!(gateway_id, 55);
-                assert_eq!(session_id, 22);
+        assert!(<$message>::decode_slice(&buffer[..$size - 1]).is_err());
+        assert!(<$message>::decode_slice(&[buffer.as_slice(), &[0]].concat()).is_err());
+        assert!(<$message>::decode_slice(&[]).is_err());
+    )*};
+}
+
+#[test]
+fn round_trip_all_messages() {
+    check_codec!(
+        AuctionInfo, AUCTIONINFO_SIZE;
+        Cancel, CANCEL_SIZE;
+        EngineStatus, ENGINESTATUS_SIZE;
+        EodSummary, EODSUMMARY_SIZE;
+        ExecutionReport, EXECUTIONREPORT_SIZE;
+        OepHeader, OEP_HEADER_SIZE;
+        Heartbeat, HEARTBEAT_SIZE;
+        IngressHeader, INGRESSHEADER_SIZE;
+        IngressNak, INGRESSNAK_SIZE;
+        Login, LOGIN_SIZE;
+        LoginReject, LOGINREJECT_SIZE;
+        MassCancel, MASSCANCEL_SIZE;
+        Modify, MODIFY_SIZE;
+        NewOrder, NEWORDER_SIZE;
+        MassQuote, MASSQUOTE_SIZE;
+        Quote, QUOTE_SIZE;
+        QuoteCancelAll, QUOTECANCELALL_SIZE;
+        Replace, REPLACE_SIZE;
+        ResendRequest, RESENDREQUEST_SIZE;
+        SessionInfo, SESSIONINFO_SIZE;
+        InstrumentStateChange, STATECHANGE_SIZE;
+        Statistics, STATISTICS_SIZE;
+        Trade, TRADE_SIZE;
+        TradeBust, TRADEBUST_SIZE;
+        TradeCapture, TRADECAPTURE_SIZE;
+        VersionReject, VERSIONREJECT_SIZE;
+    );
+}
+
+#[test]
+fn little_endian_fields() {
+    // the wire layout doesn't depend on the host
+    let order = NewOrder {
+        client_order_id: 0x0102030405060708,
+        participant: 2,
+        book_id: 3,
+        quantity: 4,
+        price: 5,
+        order_type: 0x0607,
+        side: 1,
+        gateway_id: 8,
+        session_id: 0x090a0b0c,
+        expiry: 10,
+        stop_price: 11,
+        display_quantity: 0x0d0e0f1011121314,
+    };
+    let encoded = order.encode();
+    assert_eq!([8, 7, 6, 5, 4, 3, 2, 1], encoded[..8]);
+    assert_eq!([7, 6, 1, 8, 12, 11, 10, 9], encoded[40..48]);
+    assert_eq!(
+        [0x14, 0x13, 0x12, 0x11, 0x10, 0x0f, 0x0e, 0x0d],
+        encoded[64..]
+    );
+    let decoded = NewOrder::decode(encoded).unwrap();
+    assert_eq!(0x0102030405060708, { decoded.client_order_id });
+    assert_eq!(0x0607, { decoded.order_type });
+    assert_eq!(0x090a0b0c, { decoded.session_id });
+    assert_eq!(0x0d0e0f1011121314, { decoded.display_quantity });
+}
+
+#[test]
+fn invalid_enum_values() {
+    assert!(EngineStatus::decode([1, 7]).is_err());
+    let mut header = IngressHeader::new(1, IngressKind::Message, 2, 3).encode();
+    header[1] = 7;
+    assert!(IngressHeader::decode(header).is_err());
+}
+
+/// Whatever comes off the network, the decoding fails instead of panicking
+mod properties {
+    use proptest::prelude::*;
+
+    use crate::{
+        cancel::CANCEL_SIZE,
+        decoder::Decoder,
+        execution_report::EXECUTIONREPORT_SIZE,
+        header::{OepHeader, OEP_VERSION},
+        heartbeat::HEARTBEAT_SIZE,
+        login::LOGIN_SIZE,
+        loginreject::LOGINREJECT_SIZE,
+        masscancel::MASSCANCEL_SIZE,
+        massquote::MASSQUOTE_SIZE,
+        messagebuffer::MessageBuffer,
+        modify::MODIFY_SIZE,
+        neworder::NEWORDER_SIZE,
+        oep_decode,
+        oep_message::MsgType,
+        quote::QUOTE_SIZE,
+        quotecancelall::QUOTECANCELALL_SIZE,
+        replace::REPLACE_SIZE,
+        resendrequest::RESENDREQUEST_SIZE,
+        version::{MIN_OEP_VERSION, VERSIONREJECT_SIZE},
+    };
+
+    // the messages oep_decode knows, with the length of their bodies
+    const DECODED: [(MsgType, usize); 14] = [
+        (MsgType::NewOrder, NEWORDER_SIZE),
+        (MsgType::Modify, MODIFY_SIZE),
+        (MsgType::Cancel, CANCEL_SIZE),
+        (MsgType::Replace, REPLACE_SIZE),
+        (MsgType::MassCancel, MASSCANCEL_SIZE),
+        (MsgType::Quote, QUOTE_SIZE),
+        (MsgType::MassQuote, MASSQUOTE_SIZE),
+        (MsgType::QuoteCancelAll, QUOTECANCELALL_SIZE),
+        (MsgType::ExecutionReport, EXECUTIONREPORT_SIZE),
+        (MsgType::Heartbeat, HEARTBEAT_SIZE),
+        (MsgType::ResendRequest, RESENDREQUEST_SIZE),
+        (MsgType::Login, LOGIN_SIZE),
+        (MsgType::VersionReject, VERSIONREJECT_SIZE),
+        (MsgType::LoginReject, LOGINREJECT_SIZE),
+    ];
+
+    /// A known message of the current version, its body made of any bytes
+    fn sized() -> impl Strategy<Value = Vec<u8>> {
+        prop::sample::select(DECODED.to_vec()).prop_flat_map(|(msg_type, size)| {
+            prop::collection::vec(any::<u8>(), size).prop_map(move |body| {
+                let header = OepHeader::new(OEP_VERSION, msg_type.into(), size as u32).encode();
+                [header.as_slice(), &body].concat()
+            })
+        })
+    }
+
+    /// A header of any type and version, for a body of the length it announces
+    fn framed() -> impl Strategy<Value = Vec<u8>> {
+        (
+            prop_oneof![Just(MIN_OEP_VERSION), any::<u16>()],
+            0u16..24,
+            prop::collection::vec(any::<u8>(), 0..300),
+        )
+            .prop_map(|(version, msg_type, body)| {
+                let header = OepHeader::new(version, msg_type, body.len() as u32).encode();
+                [header.as_slice(), &body].concat()
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn decode_any_bytes(buffer in prop::collection::vec(any::<u8>(), 0..400)) {
+            let _ = oep_decode(&buffer);
+        }
+
+        #[test]
+        fn decode_any_body(buffer in framed()) {
+            let _ = oep_decode(&buffer);
+        }
+
+        #[test]
+        fn decode_any_fields(buffer in sized()) {
+            let _ = oep_decode(&buffer);
+        }
+
+        #[test]
+        fn frame_any_stream(
+            chunks in prop::collection::vec(
+                prop_oneof![framed(), sized(), prop::collection::vec(any::<u8>(), 0..50)],
+                0..10,
+            )
+        ) {
+            let mut target = MessageBuffer::new();
+            for chunk in chunks {
+                target.extend(&chunk);
+                while target.next_message().is_some() {}
             }
-            Err(_) => todo!(), // already matched up
         }
     }
 }
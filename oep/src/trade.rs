@@ -1,10 +1,13 @@
 use std::error::Error;
 
 use crate::{
-    decoder::Decoder,
+    decoder::{Decoder, FieldReader, FieldWriter},
     oep_message::{MsgType, OepMessage},
 };
 
+/// Side of the order that triggered an auction uncross trade, which has no aggressor
+pub const NO_AGGRESSOR: u8 = 2;
+
 /// This message is here only for the feed disseminator
 #[repr(packed)]
 #[derive(Debug, Clone, Copy)]
@@ -13,17 +16,43 @@ pub struct Trade {
     pub ask_order_id: u64,
     pub price: u64,
     pub quantity: u64,
+    pub book_id: u64,
+    // unique within the book
+    pub trade_id: u64,
+    // exchange time, nanoseconds since the unix epoch
+    pub timestamp: u64,
+    // side of the incoming order, or NO_AGGRESSOR
+    pub aggressor_side: u8,
 }
 
 pub const TRADE_SIZE: usize = std::mem::size_of::<Trade>();
 
 impl Decoder<TRADE_SIZE> for Trade {
     fn encode(self) -> [u8; TRADE_SIZE] {
-        unsafe { std::mem::transmute::<Self, [u8; TRADE_SIZE]>(self) }
+        FieldWriter::default()
+            .put(self.bid_order_id)
+            .put(self.ask_order_id)
+            .put(self.price)
+            .put(self.quantity)
+            .put(self.book_id)
+            .put(self.trade_id)
+            .put(self.timestamp)
+            .put(self.aggressor_side)
+            .finish()
     }
 
     fn decode(buffer: [u8; TRADE_SIZE]) -> Result<Self, Box<dyn Error>> {
-        unsafe { Ok(std::mem::transmute::<[u8; TRADE_SIZE], Self>(buffer).try_into()?) }
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            bid_order_id: reader.get()?,
+            ask_order_id: reader.get()?,
+            price: reader.get()?,
+            quantity: reader.get()?,
+            book_id: reader.get()?,
+            trade_id: reader.get()?,
+            timestamp: reader.get()?,
+            aggressor_side: reader.get()?,
+        })
     }
 }
 
@@ -60,12 +89,20 @@ mod tests {
             ask_order_id: 67890,
             price: 1000,
             quantity: 100,
+            book_id: 444,
+            trade_id: 7,
+            timestamp: 1_700_000_000_000_000_000,
+            aggressor_side: 1,
         };
 
-        assert_eq!(trade.bid_order_id as u64, 12345);
-        assert_eq!(trade.ask_order_id as u64, 67890);
-        assert_eq!(trade.price as u64, 1000);
-        assert_eq!(trade.quantity as u64, 100);
+        assert_eq!({ trade.bid_order_id }, 12345);
+        assert_eq!({ trade.ask_order_id }, 67890);
+        assert_eq!({ trade.price }, 1000);
+        assert_eq!({ trade.quantity }, 100);
+        assert_eq!({ trade.book_id }, 444);
+        assert_eq!({ trade.trade_id }, 7);
+        assert_eq!({ trade.timestamp }, 1_700_000_000_000_000_000);
+        assert_eq!(trade.aggressor_side, 1);
     }
 
     #[test]
@@ -75,15 +112,23 @@ mod tests {
             ask_order_id: 67890,
             price: 1000,
             quantity: 100,
+            book_id: 444,
+            trade_id: 7,
+            timestamp: 1_700_000_000_000_000_000,
+            aggressor_side: 1,
         };
 
         let encoded = original.encode();
         let decoded = Trade::decode(encoded).unwrap();
 
-        assert_eq!(original.bid_order_id as u64, decoded.bid_order_id as u64);
-        assert_eq!(original.ask_order_id as u64, decoded.ask_order_id as u64);
-        assert_eq!(original.price as u64, decoded.price as u64);
-        assert_eq!(original.quantity as u64, decoded.quantity as u64);
+        assert_eq!({ original.bid_order_id }, { decoded.bid_order_id });
+        assert_eq!({ original.ask_order_id }, { decoded.ask_order_id });
+        assert_eq!({ original.price }, { decoded.price });
+        assert_eq!({ original.quantity }, { decoded.quantity });
+        assert_eq!({ original.book_id }, { decoded.book_id });
+        assert_eq!({ original.trade_id }, { decoded.trade_id });
+        assert_eq!({ original.timestamp }, { decoded.timestamp });
+        assert_eq!(original.aggressor_side, decoded.aggressor_side);
     }
 
     #[test]
@@ -93,6 +138,10 @@ mod tests {
             ask_order_id: 67890,
             price: 1000,
             quantity: 100,
+            book_id: 444,
+            trade_id: 7,
+            timestamp: 1_700_000_000_000_000_000,
+            aggressor_side: 1,
         };
 
         assert_eq!(trade.message_type(), MsgType::Trade);
@@ -105,17 +154,22 @@ mod tests {
             ask_order_id: 67890,
             price: 1000,
             quantity: 100,
+            book_id: 444,
+            trade_id: 7,
+            timestamp: 1_700_000_000_000_000_000,
+            aggressor_side: 1,
         };
 
         let any = trade.as_any();
         let downcast = any.downcast_ref::<Trade>();
         assert!(downcast.is_some());
-        assert_eq!(downcast.unwrap().bid_order_id as u64, 12345);
+        assert_eq!({ downcast.unwrap().bid_order_id }, 12345);
     }
 
     #[test]
     fn test_trade_size() {
         assert_eq!(TRADE_SIZE, std::mem::size_of::<Trade>());
+        assert_eq!(57, TRADE_SIZE);
     }
 
     #[test]
@@ -126,6 +180,10 @@ mod tests {
             ask_order_id: 67890,
             price: 1000,
             quantity: 100,
+            book_id: 444,
+            trade_id: 7,
+            timestamp: 1_700_000_000_000_000_000,
+            aggressor_side: 1,
         };
         trade.get_gateway_id();
     }
@@ -138,6 +196,10 @@ mod tests {
             ask_order_id: 67890,
             price: 1000,
             quantity: 100,
+            book_id: 444,
+            trade_id: 7,
+            timestamp: 1_700_000_000_000_000_000,
+            aggressor_side: 1,
         };
         trade.get_session_id();
     }
@@ -150,6 +212,10 @@ mod tests {
             ask_order_id: 67890,
             price: 1000,
             quantity: 100,
+            book_id: 444,
+            trade_id: 7,
+            timestamp: 1_700_000_000_000_000_000,
+            aggressor_side: 1,
         };
         trade.get_participant();
     }
@@ -0,0 +1,69 @@
+use std::error::Error;
+
+use crate::decoder::{Decoder, FieldReader, FieldWriter};
+
+/// A trade cancelled by the exchange after the fact, published on the feed
+/// for the consumers to take it out of their statistics
+/// Not a real OEP message either, just sharing the encoding
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct TradeBust {
+    pub book_id: u64,
+    // the trade id of the trade busted
+    pub trade_id: u64,
+    // the ones of the trade busted
+    pub price: u64,
+    pub quantity: u64,
+    // when it was busted, nanoseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+pub const TRADEBUST_SIZE: usize = std::mem::size_of::<TradeBust>();
+
+impl Decoder<TRADEBUST_SIZE> for TradeBust {
+    fn encode(self) -> [u8; TRADEBUST_SIZE] {
+        FieldWriter::default()
+            .put(self.book_id)
+            .put(self.trade_id)
+            .put(self.price)
+            .put(self.quantity)
+            .put(self.timestamp)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; TRADEBUST_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            book_id: reader.get()?,
+            trade_id: reader.get()?,
+            price: reader.get()?,
+            quantity: reader.get()?,
+            timestamp: reader.get()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = TradeBust {
+            book_id: 0x0102030405060708,
+            trade_id: 7,
+            price: 1000,
+            quantity: 100,
+            timestamp: 1_700_000_000_000_000_000,
+        };
+
+        let encoded = original.encode();
+        assert_eq!(40, encoded.len());
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1], encoded[..8]);
+        let decoded = TradeBust::decode(encoded).unwrap();
+        assert_eq!({ decoded.book_id }, { original.book_id });
+        assert_eq!(7, { decoded.trade_id });
+        assert_eq!((1000, 100), ({ decoded.price }, { decoded.quantity }));
+        assert_eq!({ decoded.timestamp }, { original.timestamp });
+    }
+}
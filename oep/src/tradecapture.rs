@@ -0,0 +1,90 @@
+use std::error::Error;
+
+use crate::decoder::{Decoder, FieldReader, FieldWriter};
+
+/// A trade with its counterparties, sent by the matching engine to the clearing
+/// for the position keeping. Unlike the feed trade, it names the participants
+/// Not a real OEP message either, just sharing the encoding
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct TradeCapture {
+    // set when sent to the clearing, 1 for the first capture on a connection
+    pub seq: u64,
+    pub book_id: u64,
+    // unique within the book, same as on the feed
+    pub trade_id: u64,
+    pub price: u64,
+    pub quantity: u64,
+    pub buyer: u64,
+    pub seller: u64,
+    // nanoseconds since the epoch, same as on the feed
+    pub timestamp: u64,
+}
+
+pub const TRADECAPTURE_SIZE: usize = std::mem::size_of::<TradeCapture>();
+
+impl Decoder<TRADECAPTURE_SIZE> for TradeCapture {
+    fn encode(self) -> [u8; TRADECAPTURE_SIZE] {
+        FieldWriter::default()
+            .put(self.seq)
+            .put(self.book_id)
+            .put(self.trade_id)
+            .put(self.price)
+            .put(self.quantity)
+            .put(self.buyer)
+            .put(self.seller)
+            .put(self.timestamp)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; TRADECAPTURE_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            seq: reader.get()?,
+            book_id: reader.get()?,
+            trade_id: reader.get()?,
+            price: reader.get()?,
+            quantity: reader.get()?,
+            buyer: reader.get()?,
+            seller: reader.get()?,
+            timestamp: reader.get()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let original = TradeCapture {
+            seq: 0x0102030405060708,
+            book_id: 500,
+            trade_id: 4,
+            price: 1000,
+            quantity: 300,
+            buyer: 11,
+            seller: 12,
+            timestamp: 1700000000000000000,
+        };
+
+        let encoded = original.encode();
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1], encoded[0..8]);
+        let decoded = TradeCapture::decode(encoded).unwrap();
+
+        assert_eq!({ original.seq }, { decoded.seq });
+        assert_eq!({ original.book_id }, { decoded.book_id });
+        assert_eq!({ original.trade_id }, { decoded.trade_id });
+        assert_eq!({ original.price }, { decoded.price });
+        assert_eq!({ original.quantity }, { decoded.quantity });
+        assert_eq!({ original.buyer }, { decoded.buyer });
+        assert_eq!({ original.seller }, { decoded.seller });
+        assert_eq!({ original.timestamp }, { decoded.timestamp });
+    }
+
+    #[test]
+    fn test_tradecapture_size() {
+        assert_eq!(64, TRADECAPTURE_SIZE);
+    }
+}
@@ -0,0 +1,198 @@
+//! Negotiation of the protocol version of a session
+//!
+//! The client proposes the version it speaks in the header of its login and
+//! the gateway answers with the one accepted, in the header of the echoed
+//! login: the highest version spoken by both. The header, the login, the
+//! version reject and the login reject keep their layout in all the versions,
+//! so that they can be decoded before the version is agreed on. A client
+//! proposing a version older than all the ones spoken by the gateway gets a
+//! VersionReject and is disconnected.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    io,
+};
+
+use crate::{
+    decoder::{Decoder, FieldReader, FieldWriter},
+    header::OEP_VERSION,
+    oep_message::{MsgType, OepMessage},
+};
+
+// the oldest version still spoken
+pub const MIN_OEP_VERSION: u16 = 5;
+
+/// Why a message couldn't be decoded, carried by the io::Error of oep_decode
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum OepError {
+    // the message comes in a version without a decoder
+    UnsupportedVersion(u16),
+    // the length in the header is not the one of the message in its version
+    InvalidLength {
+        msg_type: u16,
+        expected: usize,
+        received: usize,
+    },
+}
+
+impl Display for OepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OepError::UnsupportedVersion(version) => {
+                write!(f, "unsupported OEP version {version}")
+            }
+            OepError::InvalidLength {
+                msg_type,
+                expected,
+                received,
+            } => write!(
+                f,
+                "message type {msg_type} takes {expected} bytes, not {received}"
+            ),
+        }
+    }
+}
+
+impl Error for OepError {}
+
+impl From<OepError> for io::Error {
+    fn from(value: OepError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, value)
+    }
+}
+
+impl OepError {
+    /// The OepError behind @e, if any
+    pub fn of(e: &io::Error) -> Option<OepError> {
+        e.get_ref()?.downcast_ref::<OepError>().copied()
+    }
+}
+
+/// The version of a session whose client @proposed one
+pub fn negotiate_version(proposed: u16) -> Result<u16, OepError> {
+    if proposed < MIN_OEP_VERSION {
+        return Err(OepError::UnsupportedVersion(proposed));
+    }
+    Ok(proposed.min(OEP_VERSION))
+}
+
+/// Sent by the gateway instead of the login reply, when no version is spoken
+/// by both ends. The connection is closed after it
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct VersionReject {
+    pub participant: u64,
+    pub session_id: u32,
+    pub gateway_id: u8,
+    // as found in the header of the login
+    pub proposed: u16,
+    // the versions spoken by the gateway
+    pub min_version: u16,
+    pub max_version: u16,
+}
+
+impl VersionReject {
+    pub fn new(participant: u64, session_id: u32, gateway_id: u8, proposed: u16) -> Self {
+        Self {
+            participant,
+            session_id,
+            gateway_id,
+            proposed,
+            min_version: MIN_OEP_VERSION,
+            max_version: OEP_VERSION,
+        }
+    }
+}
+
+pub const VERSIONREJECT_SIZE: usize = std::mem::size_of::<VersionReject>();
+
+impl Decoder<VERSIONREJECT_SIZE> for VersionReject {
+    fn encode(self) -> [u8; VERSIONREJECT_SIZE] {
+        FieldWriter::default()
+            .put(self.participant)
+            .put(self.session_id)
+            .put(self.gateway_id)
+            .put(self.proposed)
+            .put(self.min_version)
+            .put(self.max_version)
+            .finish()
+    }
+
+    fn decode(buffer: [u8; VERSIONREJECT_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = FieldReader::new(&buffer);
+        Ok(Self {
+            participant: reader.get()?,
+            session_id: reader.get()?,
+            gateway_id: reader.get()?,
+            proposed: reader.get()?,
+            min_version: reader.get()?,
+            max_version: reader.get()?,
+        })
+    }
+}
+
+impl OepMessage for VersionReject {
+    fn message_type(&self) -> MsgType {
+        MsgType::VersionReject
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn get_gateway_id(&self) -> u8 {
+        self.gateway_id
+    }
+
+    fn get_session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    fn get_participant(&self) -> u64 {
+        self.participant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Ok(OEP_VERSION), negotiate_version(OEP_VERSION));
+        // a newer client falls back to the version of the gateway
+        assert_eq!(Ok(OEP_VERSION), negotiate_version(OEP_VERSION + 1));
+        assert_eq!(
+            Err(OepError::UnsupportedVersion(MIN_OEP_VERSION - 1)),
+            negotiate_version(MIN_OEP_VERSION - 1)
+        );
+    }
+
+    #[test]
+    fn test_typed_error() {
+        let e: io::Error = OepError::UnsupportedVersion(1).into();
+        assert_eq!(io::ErrorKind::InvalidData, e.kind());
+        assert_eq!(Some(OepError::UnsupportedVersion(1)), OepError::of(&e));
+        assert_eq!(None, OepError::of(&io::Error::other("something else")));
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let original = VersionReject::new(0x0102030405060708, 600, 1, 4);
+
+        let encoded = original.encode();
+        assert_eq!(
+            [8, 7, 6, 5, 4, 3, 2, 1, 88, 2, 0, 0, 1, 4, 0, 5, 0, 7, 0],
+            encoded
+        );
+        let decoded = VersionReject::decode(encoded).unwrap();
+
+        assert_eq!({ original.participant }, { decoded.participant });
+        assert_eq!({ original.session_id }, { decoded.session_id });
+        assert_eq!(1, decoded.gateway_id);
+        assert_eq!(4, { decoded.proposed });
+        assert_eq!(MIN_OEP_VERSION, { decoded.min_version });
+        assert_eq!(OEP_VERSION, { decoded.max_version });
+    }
+}
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, rc::Rc};
+use std::sync::{Arc, RwLock};
 
 use instruments::instrument::Instrument;
 
@@ -82,43 +82,89 @@ impl From<u16> for OrderType {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Order {
     id: u64,
+    // arrival sequence in the book, the time part of the price-time priority
+    sequence: u64,
     pub participant: u64,
-    pub instrument: Rc<RefCell<Instrument>>,
+    pub instrument: Arc<RwLock<Instrument>>,
     pub price: u64,
     pub quantity: u64,
     pub side: Side,
     pub order_type: OrderType,
     pub gateway_id: u8,
     pub session_id: u32,
+    // unix timestamp (seconds) after which a GoodTillDate order is cancelled, 0 if none
+    pub expiry: u64,
+    // last traded price activating a StopLoss or StopLimit order, 0 if none
+    pub stop_price: u64,
+    // peak size of an iceberg order, 0 if the whole quantity is visible
+    pub display_quantity: u64,
+    // the part of an iceberg order not shown in the book yet
+    pub hidden_quantity: u64,
+    // the id given by the participant to the order, 0 if none
+    pub client_order_id: u64,
+}
+
+// same as a derived one, the instruments being compared by value
+impl PartialEq for Order {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+            && self.sequence == other.sequence
+            && self.participant == other.participant
+            && (Arc::ptr_eq(&self.instrument, &other.instrument)
+                || *self.instrument.read().unwrap() == *other.instrument.read().unwrap())
+            && self.price == other.price
+            && self.quantity == other.quantity
+            && self.side == other.side
+            && self.order_type == other.order_type
+            && self.gateway_id == other.gateway_id
+            && self.session_id == other.session_id
+            && self.expiry == other.expiry
+            && self.stop_price == other.stop_price
+            && self.display_quantity == other.display_quantity
+            && self.hidden_quantity == other.hidden_quantity
+            && self.client_order_id == other.client_order_id
+    }
 }
 
 impl Order {
     pub fn new(
         participant: u64,
-        instrument: Rc<RefCell<Instrument>>,
+        instrument: Arc<RwLock<Instrument>>,
         price: u64,
         quantity: u64,
         side: Side,
         order_type: OrderType,
-        gateway_id: u8,
-        session_id: u32,
     ) -> Self {
         Self {
             id: 0,
-            participant: participant,
-            instrument: instrument,
-            price: price,
+            sequence: 0,
+            participant,
+            instrument,
+            price,
             quantity,
-            side: side,
-            order_type: order_type,
-            gateway_id: gateway_id,
-            session_id: session_id,
+            side,
+            order_type,
+            gateway_id: 0,
+            session_id: 0,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
+            hidden_quantity: 0,
+            client_order_id: 0,
         }
     }
 
+    /// The same order, sent by the session @session_id of the gateway
+    /// @gateway_id
+    pub fn with_session(mut self, gateway_id: u8, session_id: u32) -> Self {
+        self.gateway_id = gateway_id;
+        self.session_id = session_id;
+        self
+    }
+
     pub fn set_id(&mut self, id: u64) {
         self.id = id
     }
@@ -126,6 +172,60 @@ impl Order {
     pub fn get_id(&self) -> u64 {
         self.id
     }
+
+    pub fn set_sequence(&mut self, sequence: u64) {
+        self.sequence = sequence
+    }
+
+    pub fn get_sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Whether the order survives the market close
+    pub fn is_persistent(&self) -> bool {
+        matches!(
+            self.order_type,
+            OrderType::GoodTillCancel | OrderType::GoodTillDate
+        )
+    }
+
+    /// Whether the order waits for a trade at its stop price before entering the book
+    pub fn is_stop(&self) -> bool {
+        matches!(self.order_type, OrderType::StopLoss | OrderType::StopLimit)
+    }
+
+    /// Whether a trade at @last_trade_price activates this stop order: a buy stop
+    /// is activated by a trade at or above the stop price, a sell stop at or below it
+    pub fn is_triggered(&self, last_trade_price: u64) -> bool {
+        self.is_stop()
+            && last_trade_price != 0
+            && match self.side {
+                Side::Bid => last_trade_price >= self.stop_price,
+                Side::Ask => last_trade_price <= self.stop_price,
+            }
+    }
+
+    /// Keeps only the peak of an iceberg order visible, the rest going to its hidden part
+    pub fn hide_quantity(&mut self) {
+        if self.display_quantity > 0 && self.quantity > self.display_quantity {
+            self.hidden_quantity += self.quantity - self.display_quantity;
+            self.quantity = self.display_quantity;
+        }
+    }
+
+    /// Shows the next peak of an iceberg order out of its hidden quantity
+    /// Returns false if there was nothing left to show
+    pub fn replenish(&mut self) -> bool {
+        let peak = std::cmp::min(self.display_quantity, self.hidden_quantity);
+        self.quantity += peak;
+        self.hidden_quantity -= peak;
+        peak > 0
+    }
+
+    /// Whether this is a GoodTillDate order whose expiry is at or before @now
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.order_type == OrderType::GoodTillDate && self.expiry <= now
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -136,6 +236,10 @@ pub enum OrderState {
     Rejected,
     Traded,
     PartiallyTraded,
+    // the quote of a market maker in the book, both sides in one report
+    QuoteAck,
+    // a trade of the order cancelled by the exchange
+    TradeBusted,
 }
 
 impl Into<u8> for OrderState {
@@ -147,6 +251,8 @@ impl Into<u8> for OrderState {
             OrderState::Rejected => 3,
             OrderState::Traded => 4,
             OrderState::PartiallyTraded => 5,
+            OrderState::QuoteAck => 6,
+            OrderState::TradeBusted => 7,
         }
     }
 }
@@ -160,6 +266,8 @@ impl From<u8> for OrderState {
             3 => OrderState::Rejected,
             4 => OrderState::Traded,
             5 => OrderState::PartiallyTraded,
+            6 => OrderState::QuoteAck,
+            7 => OrderState::TradeBusted,
             _ => panic!("Unknown order state"),
         }
     }
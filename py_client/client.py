@@ -48,26 +48,28 @@ def main() -> None:
             return
         
         data += socket_data
-        if len(data) < 8: # header incomplete
+        if len(data) < 12: # header incomplete
             continue
         msg_len = struct.unpack('<I', data[4:8])[0]
-        if len(data) < msg_len + 8:
+        if len(data) < msg_len + 12:
             continue # wait for the rest of the message
 
         if data[2] == int(oep.MsgType.LOGIN):
             logged_in = True
+            # carry on with the sequence expected by the gateway
+            protocol.next_seq = struct.unpack('<I', data[8:12])[0]
         elif data[2] == int(oep.MsgType.EXECUTION_REPORT):
             assert msg_len == 57, f"Invalid len: {len(data)}"
-            ereport_type = data[59]
+            ereport_type = data[63]
             assert ereport_type == 0, f"Invalid report type {ereport_type} {data}" # need OrderState::Inserted
-            order_ids.append(struct.unpack('<Q', data[16:24])[0])
+            order_ids.append(struct.unpack('<Q', data[20:28])[0])
         else:
             print(f"Unknown message type: {data[2]}", file=stderr, flush=True)
 
         if not logged_in:
             print("Could not login", file=stderr, flush=True)
             return
-        data = data[8 + msg_len:]
+        data = data[12 + msg_len:]
 
         if total_orders_sent >= MAX_ORDERS_IN_FLIGHT:
             start = time.perf_counter()
@@ -4,7 +4,7 @@ import struct
 from enum import IntEnum
 
 
-OEP_VERSION = 1
+OEP_VERSION = 5
 
 class MsgType(IntEnum):
     NEW_ORDER = 0
@@ -12,6 +12,8 @@ class MsgType(IntEnum):
     CANCEL = 2
     EXECUTION_REPORT = 3
     LOGIN = 4
+    HEARTBEAT = 10
+    RESEND_REQUEST = 11
 
 class Oep:
     def __init__(self, gateway_id: int, session_id: int, participant: int) -> None:
@@ -19,15 +21,24 @@ class Oep:
         self.session_id = session_id
         self.participant = participant
         self.client_order_id = 100
+        # sequence of the next order message, the login reply tells where to start
+        self.next_seq = 1
 
-    def build_header(self, msg_type: MsgType, msg_len: int) -> bytes:
-        return struct.pack('<HHI', OEP_VERSION, int(msg_type), msg_len)
+    def build_header(self, msg_type: MsgType, msg_len: int, seq: int = 0) -> bytes:
+        return struct.pack('<HHII', OEP_VERSION, int(msg_type), msg_len, seq)
+
+    def build_sequenced_header(self, msg_type: MsgType, msg_len: int) -> bytes:
+        self.next_seq += 1
+        return self.build_header(msg_type, msg_len, self.next_seq - 1)
 
     def build_login(self, username: str, password: str) -> bytes:
         h = hashlib.sha512()
         h.update(password.encode('utf-8'))
         hashed_password = h.digest()
-        inner = struct.pack('<QII', self.participant, self.session_id, self.gateway_id) + \
+        # 0 for the orders of the session to be cancelled when it disconnects
+        keep_orders_on_disconnect = 0
+        inner = struct.pack('<QIBB2x', self.participant, self.session_id, self.gateway_id,
+                            keep_orders_on_disconnect) + \
             username.ljust(64, '\0').encode('utf-8') + \
             hashed_password
         assert len(inner) == 144
@@ -36,17 +47,27 @@ class Oep:
     
     def build_new_day_order(self, book_id, quantity, price, side) -> bytes:
         self.client_order_id += 1
-        inner = struct.pack('<QQQQQHBBI',
+        inner = struct.pack('<QQQQQHBBIQQQ',
             self.client_order_id - 1, self.participant,
             book_id, quantity, price, 0, side,
-            self.gateway_id, self.session_id)
-        assert len(inner) == 48
-        return self.build_header(MsgType.NEW_ORDER, len(inner)) + inner
+            self.gateway_id, self.session_id, 0, 0, 0)
+        assert len(inner) == 72
+        return self.build_sequenced_header(MsgType.NEW_ORDER, len(inner)) + inner
             
     def build_cancel(self, order_id: int, book_id: int, side: int) -> bytes:
         inner = struct.pack('<QQQBBI',
             self.participant, order_id, book_id, side,
             self.gateway_id, self.session_id)
         assert len(inner) == 30
-        return self.build_header(MsgType.CANCEL, len(inner)) + inner
+        return self.build_sequenced_header(MsgType.CANCEL, len(inner)) + inner
+
+    def build_heartbeat(self) -> bytes:
+        inner = struct.pack('<QIB', self.participant, self.session_id, self.gateway_id)
+        assert len(inner) == 13
+        return self.build_header(MsgType.HEARTBEAT, len(inner)) + inner
+
+    def build_resend_request(self, from_seq: int) -> bytes:
+        inner = struct.pack('<QIBI', self.participant, self.session_id, self.gateway_id, from_seq)
+        assert len(inner) == 17
+        return self.build_header(MsgType.RESEND_REQUEST, len(inner)) + inner
             
\ No newline at end of file
@@ -0,0 +1,76 @@
+/// Keeps the audit trail of the orders: every execution report the matching
+/// engines send on the internal publisher group is stored into the database,
+/// the trades being stored by the clearing out of the trade captures
+use std::net::UdpSocket;
+
+use anyhow::Result;
+use serde::Deserialize;
+use tracing::{error, info};
+use utils::{
+    config::{self, ConfigError, ConfigMap, DatabaseConfig},
+    logging::{self, LogConfig},
+    network::{self, MulticastConfig},
+};
+
+mod recorder;
+
+/// The [recorder] section
+#[derive(Debug, Deserialize)]
+struct RecorderConfig {
+    // where the matching engines send their execution reports
+    internal_publisher_group: String,
+    internal_publisher_port: u16,
+}
+
+impl RecorderConfig {
+    fn from_config(config_map: &ConfigMap) -> Result<Self, ConfigError> {
+        config::section(config_map, "recorder")
+    }
+}
+
+fn main() -> Result<()> {
+    let config_map = config::load("recorder.ini")?;
+    let log_config = LogConfig::from_config(&config_map, "recorder")
+        .expect("Invalid log settings in the recorder section");
+    logging::init(&log_config).expect("Unable to start logging");
+    info!("Loaded the configuration file");
+
+    let RecorderConfig {
+        internal_publisher_group: group,
+        internal_publisher_port: port,
+    } = RecorderConfig::from_config(&config_map)?;
+    let multicast = MulticastConfig::from_config(&config_map, "recorder")
+        .expect("Invalid multicast settings in the recorder section");
+
+    info!("Connecting to DB");
+    let database = DatabaseConfig::from_config(&config_map)?;
+    let mut db = dbhook::factory::build(&database.db_type);
+    db.connect(
+        &database.address,
+        database.port,
+        &database.username,
+        &database.password,
+        &database.name,
+    )?;
+
+    let socket: UdpSocket =
+        network::join_multicast_group(&network::socket_address(&group, port)?, &multicast)?.into();
+    info!(group, port, "Recording the execution reports");
+    let mut buf = [0; 10000];
+    let mut recorded: u64 = 0;
+    let mut seen = recorder::EgressSeen::default();
+    loop {
+        let r = socket.recv(&mut buf)?;
+        match recorder::record(db.as_mut(), &mut seen, &buf[..r]) {
+            Ok(true) => {
+                recorded += 1;
+                if recorded.is_multiple_of(100_000) {
+                    info!(recorded, "Execution reports recorded");
+                }
+            }
+            Ok(false) => {}
+            // lost for the audit, the recording goes on
+            Err(e) => error!(error = %e, "Unable to record an execution report"),
+        }
+    }
+}
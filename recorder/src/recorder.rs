@@ -0,0 +1,172 @@
+//! The audit trail of the orders, out of the execution reports the matching
+//! engines send on the internal publisher group
+//!
+//! Every execution report is an event in the life of an order: its entry,
+//! modifies, fills, cancels, expiries, rejects and trade busts. Whatever the
+//! gateway and the session they are for, they are all stored as they come.
+//! The ones of a sequenced egress are unwrapped, their retransmissions being
+//! dropped.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use dbhook::genericdb::GenericDB;
+use oep::{
+    decoder::Decoder,
+    egress::{EgressHeader, EGRESSHEADER_SIZE},
+    execution_report::{ExecutionReport, EXECUTIONREPORT_SIZE},
+    header::{OepHeader, OEP_HEADER_SIZE},
+    ingress::IngressKind,
+    oep_message::MsgType,
+};
+
+/// The last sequences recorded of the engines with a sequenced egress
+#[derive(Debug, Default)]
+pub struct EgressSeen {
+    // (engine id, partition id, gateway id, epoch) -> the last sequence
+    last_seqs: HashMap<(u8, u8, u8, u32), u64>,
+}
+
+/// Stores the execution report in @datagram into @db, framed with its
+/// sequence or not, anything else sent on the group (engine statuses, naks,
+/// the egress heartbeats) being ignored. @seen drops the retransmissions
+///
+/// Returns: whether there was an execution report to store
+pub fn record(db: &mut dyn GenericDB, seen: &mut EgressSeen, datagram: &[u8]) -> Result<bool> {
+    if datagram.len() < OEP_HEADER_SIZE {
+        return Ok(false);
+    }
+    let header = OepHeader::decode(datagram[..OEP_HEADER_SIZE].try_into()?)
+        .map_err(|e| anyhow::anyhow!("Invalid header: {e}"))?;
+    if header.message_type() == MsgType::EgressFrame
+        && datagram.len() > OEP_HEADER_SIZE + EGRESSHEADER_SIZE
+    {
+        let frame = &datagram[OEP_HEADER_SIZE..];
+        let egress = EgressHeader::decode(frame[..EGRESSHEADER_SIZE].try_into()?)
+            .map_err(|e| anyhow::anyhow!("Invalid egress header: {e}"))?;
+        if egress.get_kind() != IngressKind::Message {
+            return Ok(false);
+        }
+        let key = (
+            egress.engine_id,
+            egress.partition_id,
+            egress.gateway_id,
+            egress.epoch,
+        );
+        let last_seq = seen.last_seqs.entry(key).or_default();
+        if egress.seq <= *last_seq {
+            return Ok(false);
+        }
+        *last_seq = egress.seq;
+        return record(db, seen, &frame[EGRESSHEADER_SIZE..]);
+    }
+    if datagram.len() != OEP_HEADER_SIZE + EXECUTIONREPORT_SIZE
+        || header.message_type() != MsgType::ExecutionReport
+    {
+        return Ok(false);
+    }
+    let ereport = ExecutionReport::decode(datagram[OEP_HEADER_SIZE..].try_into()?)
+        .map_err(|e| anyhow::anyhow!("Invalid execution report: {e}"))?;
+    db.store_order_event(&ereport)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use dbhook::mockdb::MockDB;
+    use oep::{
+        decoder::Decoder,
+        egress::{EgressHeader, EGRESSHEADER_SIZE},
+        execution_report::{ExecutionReport, EXECUTIONREPORT_SIZE},
+        header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+        ingress::IngressKind,
+        oep_message::MsgType,
+    };
+
+    use super::{record, EgressSeen};
+
+    fn datagram(order_id: u64, state: u8) -> Vec<u8> {
+        let ereport = ExecutionReport {
+            participant: 1001,
+            order_id,
+            submitted_order_id: 7,
+            book: 5,
+            quantity: 10,
+            price: 100,
+            flags: 0,
+            side: 0,
+            state,
+            gateway_id: 1,
+            session_id: 2000,
+            filled_quantity: 0,
+            leaves_quantity: 10,
+            orig_order_id: 0,
+            partition_id: 0,
+            reject_reason: 0,
+            trade_id: 0,
+        };
+        let header = OepHeader::new(
+            OEP_VERSION,
+            MsgType::ExecutionReport.into(),
+            EXECUTIONREPORT_SIZE as u32,
+        );
+        [header.encode().as_slice(), &ereport.encode()].concat()
+    }
+
+    #[test]
+    fn records_the_execution_reports() {
+        let mut db = MockDB::default();
+        let mut seen = EgressSeen::default();
+        assert!(record(&mut db, &mut seen, &datagram(500, 0)).unwrap());
+        assert!(record(&mut db, &mut seen, &datagram(500, 3)).unwrap());
+        let events = db.get_order_events();
+        assert_eq!(2, events.len());
+        assert_eq!(
+            (500, 5, 3),
+            ({ events[1].order_id }, { events[1].book }, {
+                events[1].state
+            })
+        );
+
+        // an engine status, or anything not an execution report
+        let mut other = datagram(501, 0);
+        other[..OEP_HEADER_SIZE].copy_from_slice(
+            &OepHeader::new(
+                OEP_VERSION,
+                MsgType::EngineStatus.into(),
+                EXECUTIONREPORT_SIZE as u32,
+            )
+            .encode(),
+        );
+        assert!(!record(&mut db, &mut seen, &other).unwrap());
+        assert!(!record(&mut db, &mut seen, &datagram(501, 0)[..OEP_HEADER_SIZE]).unwrap());
+        assert_eq!(2, db.get_order_events().len());
+    }
+
+    #[test]
+    fn records_the_sequenced_ones_once() {
+        let framed = |kind, seq, report: &[u8]| {
+            let header = OepHeader::new(
+                OEP_VERSION,
+                MsgType::EgressFrame.into(),
+                (EGRESSHEADER_SIZE + report.len()) as u32,
+            );
+            let egress = EgressHeader::new(0, 0, 1, kind, 100, seq);
+            [header.encode().as_slice(), &egress.encode(), report].concat()
+        };
+        let mut db = MockDB::default();
+        let mut seen = EgressSeen::default();
+        let first = framed(IngressKind::Message, 1, &datagram(500, 0));
+        assert!(record(&mut db, &mut seen, &first).unwrap());
+        assert!(record(
+            &mut db,
+            &mut seen,
+            &framed(IngressKind::Message, 2, &datagram(500, 3))
+        )
+        .unwrap());
+        // a retransmission
+        assert!(!record(&mut db, &mut seen, &first).unwrap());
+        assert!(!record(&mut db, &mut seen, &framed(IngressKind::Heartbeat, 3, &[])).unwrap());
+        assert_eq!(2, db.get_order_events().len());
+    }
+}
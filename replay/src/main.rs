@@ -0,0 +1,91 @@
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
+    str::FromStr,
+    thread,
+    time::Instant,
+};
+
+use anyhow::{bail, Result};
+use configparser::ini::Ini;
+use matching_engine::journal::{Journal, JournalEntry};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use utils::config;
+
+use pacer::Pacer;
+
+mod pacer;
+mod pcap;
+
+/// A datagram to send again, timestamped in nanoseconds since the unix epoch
+type Replayed = (u64, SocketAddrV4, Vec<u8>);
+
+/// The order messages of the journal at @path, sent to @orders, the order
+/// group of the engine replayed to
+fn from_journal(path: &Path, orders: SocketAddrV4) -> Result<Vec<Replayed>> {
+    Ok(Journal::read(path)?
+        .into_iter()
+        .filter_map(|record| match record.entry {
+            JournalEntry::Inbound(message) => Some((record.timestamp, orders, message)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// The UDP datagrams of the capture at @path, sent where they were going,
+/// or to @group if given, on the same port
+fn from_capture(path: &Path, group: Option<Ipv4Addr>) -> Result<Vec<Replayed>> {
+    Ok(pcap::read(&std::fs::read(path)?)?
+        .into_iter()
+        .map(|d| {
+            let destination = match group {
+                Some(group) => SocketAddrV4::new(group, d.destination.port()),
+                None => d.destination,
+            };
+            (d.timestamp, destination, d.payload)
+        })
+        .collect())
+}
+
+fn main() -> Result<()> {
+    println!("Loading configuration file");
+    let mut config = Ini::new();
+    let config_map = config
+        .load("replay.ini")
+        .expect("Unable to load the configuration file");
+    let optional = |key: &str| config::get_optional_config_string(&config_map, "replay", key);
+
+    let path = config::get_config_string(&config_map, "replay", "file");
+    let path = Path::new(&path);
+    // as fast as possible for 0
+    let speed = optional("speed")
+        .map(|s| s.parse::<f64>().expect("speed must be a number"))
+        .unwrap_or(1.0);
+    let replayed = match optional("format").as_deref() {
+        None | Some("journal") => {
+            let group = config::get_config_string(&config_map, "replay", "order_group");
+            let port = config::get_config_string(&config_map, "replay", "order_port")
+                .parse::<u16>()
+                .expect("Order port must be an u16");
+            from_journal(path, SocketAddrV4::new(Ipv4Addr::from_str(&group)?, port))?
+        }
+        Some("pcap") => from_capture(
+            path,
+            optional("group").map(|g| Ipv4Addr::from_str(&g).expect("Invalid group address")),
+        )?,
+        Some(other) => bail!("Unknown format {other}"),
+    };
+    println!("Replaying {} datagrams at speed {speed}", replayed.len());
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_multicast_loop_v4(true)?;
+    let mut pacer = Pacer::new(speed);
+    let start = Instant::now();
+    for (timestamp, destination, payload) in replayed.iter() {
+        let now = Instant::now();
+        thread::sleep(pacer.due(*timestamp, now).saturating_duration_since(now));
+        socket.send_to(payload, &SockAddr::from(*destination))?;
+    }
+    println!("Replayed in {:?}", start.elapsed());
+    Ok(())
+}
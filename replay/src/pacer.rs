@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+/// Spaces the messages replayed the way they were timestamped, @speed times
+/// faster, or not at all for a speed of 0
+#[derive(Debug)]
+pub struct Pacer {
+    speed: f64,
+    // the timestamp of the first message, and when it was replayed
+    first: Option<(u64, Instant)>,
+}
+
+impl Pacer {
+    pub fn new(speed: f64) -> Self {
+        Self { speed, first: None }
+    }
+
+    /// When the message timestamped @timestamp (in nanoseconds) is to be
+    /// replayed, the first one being replayed @now
+    pub fn due(&mut self, timestamp: u64, now: Instant) -> Instant {
+        if self.speed <= 0.0 {
+            return now;
+        }
+        let (first_timestamp, start) = *self.first.get_or_insert((timestamp, now));
+        let elapsed = timestamp.saturating_sub(first_timestamp) as f64 / self.speed;
+        start + Duration::from_nanos(elapsed as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::Pacer;
+
+    #[test]
+    fn speeds() {
+        let now = Instant::now();
+        let mut target = Pacer::new(1.0);
+        assert_eq!(now, target.due(5_000_000_000, now));
+        assert_eq!(
+            now + Duration::from_millis(1500),
+            target.due(6_500_000_000, now + Duration::from_millis(1))
+        );
+        // out of order, right away
+        assert_eq!(now, target.due(4_000_000_000, now));
+
+        let mut target = Pacer::new(10.0);
+        target.due(5_000_000_000, now);
+        assert_eq!(
+            now + Duration::from_millis(150),
+            target.due(6_500_000_000, now)
+        );
+
+        let mut target = Pacer::new(0.0);
+        target.due(5_000_000_000, now);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(later, target.due(6_500_000_000, later));
+    }
+}
@@ -0,0 +1,213 @@
+//! Reader of the UDP datagrams of a pcap capture
+//!
+//! Only the classic pcap format is read, in microseconds or nanoseconds and
+//! of either byte order, with Ethernet (802.1Q tags included) or raw IP
+//! link layers. The packets other than complete IPv4 UDP datagrams are
+//! skipped, and so are the fragments.
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use anyhow::{bail, Result};
+
+const MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+const GLOBAL_HEADER_SIZE: usize = 24;
+const PACKET_HEADER_SIZE: usize = 16;
+
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_UDP: u8 = 17;
+
+/// A UDP datagram of the capture
+#[derive(Debug, Clone, PartialEq)]
+pub struct Datagram {
+    // nanoseconds since the unix epoch
+    pub timestamp: u64,
+    pub destination: SocketAddrV4,
+    pub payload: Vec<u8>,
+}
+
+/// The UDP datagrams of the capture @buffer, in the order they were captured
+pub fn read(buffer: &[u8]) -> Result<Vec<Datagram>> {
+    if buffer.len() < GLOBAL_HEADER_SIZE {
+        bail!("Not a pcap file");
+    }
+    let magic = buffer[0..4].try_into().unwrap();
+    let (big_endian, nanos) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+        (MAGIC_MICROS, _) => (false, false),
+        (MAGIC_NANOS, _) => (false, true),
+        (_, MAGIC_MICROS) => (true, false),
+        (_, MAGIC_NANOS) => (true, true),
+        _ => bail!("Not a pcap file"),
+    };
+    let u32_at = |offset: usize| {
+        let bytes = buffer[offset..offset + 4].try_into().unwrap();
+        match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    };
+    let link_type = u32_at(20);
+    if link_type != LINKTYPE_ETHERNET && link_type != LINKTYPE_RAW {
+        bail!("Unsupported link type {link_type}");
+    }
+
+    let mut datagrams = vec![];
+    let mut offset = GLOBAL_HEADER_SIZE;
+    while offset + PACKET_HEADER_SIZE <= buffer.len() {
+        let (seconds, fraction) = (u32_at(offset) as u64, u32_at(offset + 4) as u64);
+        let (captured, original) = (u32_at(offset + 8) as usize, u32_at(offset + 12) as usize);
+        let start = offset + PACKET_HEADER_SIZE;
+        if start + captured > buffer.len() {
+            // cut while capturing
+            break;
+        }
+        offset = start + captured;
+        if captured < original {
+            continue;
+        }
+        let frame = &buffer[start..offset];
+        let ip = match link_type {
+            LINKTYPE_ETHERNET => ethernet_payload(frame),
+            _ => Some(frame),
+        };
+        if let Some((destination, payload)) = ip.and_then(udp_datagram) {
+            datagrams.push(Datagram {
+                timestamp: seconds * 1_000_000_000 + if nanos { fraction } else { fraction * 1000 },
+                destination,
+                payload: payload.to_vec(),
+            });
+        }
+    }
+    Ok(datagrams)
+}
+
+/// The IPv4 packet of the Ethernet @frame
+fn ethernet_payload(frame: &[u8]) -> Option<&[u8]> {
+    let mut offset = 12;
+    loop {
+        let ether_type = u16::from_be_bytes(frame.get(offset..offset + 2)?.try_into().unwrap());
+        offset += 2;
+        match ether_type {
+            ETHERTYPE_IPV4 => return frame.get(offset..),
+            ETHERTYPE_VLAN => offset += 2,
+            _ => return None,
+        }
+    }
+}
+
+/// The destination and the payload of the IPv4 @packet, if it is an
+/// unfragmented UDP datagram
+fn udp_datagram(packet: &[u8]) -> Option<(SocketAddrV4, &[u8])> {
+    if packet.len() < 20 || packet[0] >> 4 != 4 || packet[9] != IPPROTO_UDP {
+        return None;
+    }
+    let header_len = (packet[0] & 0x0f) as usize * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    // more fragments, or not the first one
+    if u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0 {
+        return None;
+    }
+    let udp = packet.get(header_len..total_len)?;
+    if udp.len() < 8 {
+        return None;
+    }
+    let destination = SocketAddrV4::new(
+        Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]),
+        u16::from_be_bytes([udp[2], udp[3]]),
+    );
+    let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    Some((destination, udp.get(8..udp_len)?))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::{read, Datagram};
+
+    fn ipv4_udp(destination: [u8; 4], port: u16, payload: &[u8], protocol: u8) -> Vec<u8> {
+        let total = (20 + 8 + payload.len()) as u16;
+        let mut r = vec![0x45, 0];
+        r.extend_from_slice(&total.to_be_bytes());
+        r.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+        r.extend_from_slice(&[10, 0, 0, 1]);
+        r.extend_from_slice(&destination);
+        r.extend_from_slice(&1234u16.to_be_bytes());
+        r.extend_from_slice(&port.to_be_bytes());
+        r.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        r.extend_from_slice(&[0, 0]);
+        r.extend_from_slice(payload);
+        r
+    }
+
+    fn ethernet(ip: Vec<u8>, vlan: bool) -> Vec<u8> {
+        let mut r = vec![0; 12];
+        if vlan {
+            r.extend_from_slice(&[0x81, 0, 0, 5]);
+        }
+        r.extend_from_slice(&[0x08, 0]);
+        r.extend(ip);
+        r
+    }
+
+    fn capture(frames: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut r = 0xa1b2c3d4u32.to_le_bytes().to_vec();
+        r.extend_from_slice(&[2, 0, 4, 0]);
+        r.extend_from_slice(&[0; 8]);
+        r.extend_from_slice(&65535u32.to_le_bytes());
+        r.extend_from_slice(&1u32.to_le_bytes());
+        for (seconds, micros, frame) in frames {
+            r.extend_from_slice(&seconds.to_le_bytes());
+            r.extend_from_slice(&micros.to_le_bytes());
+            r.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            r.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            r.extend_from_slice(frame);
+        }
+        r
+    }
+
+    #[test]
+    fn udp_datagrams() {
+        let buffer = capture(&[
+            (
+                10,
+                5,
+                ethernet(ipv4_udp([225, 225, 225, 225], 25000, b"abc", 17), false),
+            ),
+            // TCP
+            (
+                10,
+                6,
+                ethernet(ipv4_udp([10, 0, 0, 2], 25000, b"tcp", 6), false),
+            ),
+            (
+                11,
+                7,
+                ethernet(ipv4_udp([225, 225, 225, 226], 25002, b"de", 17), true),
+            ),
+        ]);
+        assert_eq!(
+            vec![
+                Datagram {
+                    timestamp: 10_000_005_000,
+                    destination: SocketAddrV4::new(Ipv4Addr::new(225, 225, 225, 225), 25000),
+                    payload: b"abc".to_vec(),
+                },
+                Datagram {
+                    timestamp: 11_000_007_000,
+                    destination: SocketAddrV4::new(Ipv4Addr::new(225, 225, 225, 226), 25002),
+                    payload: b"de".to_vec(),
+                }
+            ],
+            read(&buffer).unwrap()
+        );
+
+        // a capture cut in the middle of a packet
+        assert_eq!(1, read(&buffer[..buffer.len() - 1]).unwrap().len());
+        assert!(read(b"not a capture at all, really").is_err());
+    }
+}
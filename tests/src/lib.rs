@@ -2,11 +2,26 @@ mod test_exchange;
 
 #[cfg(test)]
 mod test {
-    use std::io::{Read, Write};
+    use std::{
+        io::{Read, Write},
+        rc::Rc,
+        time::{Duration, Instant},
+    };
+
+    use gateway::{
+        listener::{ListenerConfig, ListenerProtocol},
+        messages::{receive_and_prepare_relay_message, ConnectedSession},
+    };
 
     use oep::{
+        decoder::Decoder,
+        execution_report::{ExecutionReport, EXECUTIONREPORT_SIZE},
+        header::{OepHeader, OEP_HEADER_SIZE, OEP_VERSION},
+        heartbeat::Heartbeat,
+        login::Login,
         neworder::{NewOrder, NEWORDER_SIZE},
-        oep_message::OepMessage,
+        oep_message::{MsgType, OepMessage},
+        resendrequest::ResendRequest,
     };
     use order::{OrderState, OrderType, Side};
 
@@ -42,7 +57,7 @@ mod test {
         assert!(connection.is_ok());
         // it replies back with login
         assert_eq!(
-            8 + 144,
+            12 + 144,
             target.client_socket.borrow().read_buffer.borrow().len()
         );
         // but it doesn't publish anything towards the matching engine
@@ -57,6 +72,131 @@ mod test {
         );
     }
 
+    /// A session can only log in on a listener whose namespace contains it
+    #[test]
+    fn login_outside_listener_namespace() {
+        let target = TestExchange::new();
+        let mut mockdb = dbhook::factory::build("mock");
+        let mut connection = ConnectedSession::new(target.gateway_client_socket.clone());
+        connection.set_listener(Rc::new(ListenerConfig {
+            name: String::from("retail"),
+            address: String::from("127.0.0.1"),
+            port: 10001,
+            protocol: ListenerProtocol::Oep,
+            session_ids: 1000..=1999,
+            max_messages_per_second: 0,
+            burst_messages: 0,
+            max_throttled_per_second: 0,
+            session_timeout_ms: 0,
+            fix: None,
+        }));
+
+        let login_message = Login::new(1, 1, 1, "test");
+        let r = receive_and_prepare_relay_message(&mut mockdb, &mut connection, &login_message);
+        assert!(r.is_err());
+
+        let login_message = Login::new(1, 1000, 1, "test");
+        let r = receive_and_prepare_relay_message(&mut mockdb, &mut connection, &login_message);
+        assert!(r.is_ok());
+    }
+
+    /// Heartbeats keep the session alive, without reaching the matching engine
+    #[test]
+    fn heartbeat_is_not_relayed() {
+        let mut target = TestExchange::new();
+        let mut connection = target.login().unwrap();
+        let mut mockdb = dbhook::factory::build("mock");
+
+        let heartbeat = Heartbeat::new(111, 2, 1);
+        let r = receive_and_prepare_relay_message(&mut mockdb, &mut connection, &heartbeat);
+        assert_eq!(111, r.unwrap());
+        assert!(connection.response_buffer.is_empty());
+
+        // still has to come from the logged in participant
+        let heartbeat = Heartbeat::new(112, 2, 1);
+        let r = receive_and_prepare_relay_message(&mut mockdb, &mut connection, &heartbeat);
+        assert!(r.is_err());
+    }
+
+    /// Only the listeners with a timeout let go of the silent sessions
+    #[test]
+    fn session_timeout() {
+        let target = TestExchange::new();
+        let mut connection = ConnectedSession::new(target.gateway_client_socket.clone());
+        let later = Instant::now() + Duration::from_secs(60);
+        assert!(!connection.is_timed_out(later));
+
+        connection.set_listener(Rc::new(ListenerConfig {
+            name: String::from("retail"),
+            address: String::from("127.0.0.1"),
+            port: 10001,
+            protocol: ListenerProtocol::Oep,
+            session_ids: 1000..=1999,
+            max_messages_per_second: 0,
+            burst_messages: 0,
+            max_throttled_per_second: 0,
+            session_timeout_ms: 5000,
+            fix: None,
+        }));
+        assert!(!connection.is_timed_out(Instant::now()));
+        assert!(connection.is_timed_out(later));
+    }
+
+    /// The session gets again what it was sent, with the original sequences
+    #[test]
+    fn resend_request() {
+        let mut target = TestExchange::new();
+        let mut connection = target.login().unwrap();
+        let mut mockdb = dbhook::factory::build("mock");
+        // the login reply tells the first sequence expected from the client
+        let login_header = OepHeader::decode(
+            target.client_socket.borrow().read_buffer.borrow()[..OEP_HEADER_SIZE]
+                .try_into()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(1, { login_header.seq });
+
+        for i in 0..3u64 {
+            let header = OepHeader::new(
+                OEP_VERSION,
+                MsgType::ExecutionReport.into(),
+                EXECUTIONREPORT_SIZE as u32,
+            )
+            .encode();
+            let mut ereport = ExecutionReport::decode([0; EXECUTIONREPORT_SIZE]).unwrap();
+            ereport.order_id = i;
+            connection
+                .send_sequenced(&[header.as_slice(), &ereport.encode()].concat())
+                .unwrap();
+        }
+        target
+            .client_socket
+            .borrow()
+            .read_buffer
+            .borrow_mut()
+            .clear();
+
+        let request = ResendRequest::new(111, 2, 1, 2);
+        let r = receive_and_prepare_relay_message(&mut mockdb, &mut connection, &request);
+        assert_eq!(111, r.unwrap());
+        assert!(connection.response_buffer.is_empty());
+        let resent = target.client_socket.borrow().read_buffer.borrow().clone();
+        assert_eq!(2 * (OEP_HEADER_SIZE + EXECUTIONREPORT_SIZE), resent.len());
+        let header = OepHeader::decode(resent[..OEP_HEADER_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(2, { header.seq });
+        let ereport = ExecutionReport::decode_slice(
+            &resent[OEP_HEADER_SIZE..OEP_HEADER_SIZE + EXECUTIONREPORT_SIZE],
+        )
+        .unwrap();
+        assert_eq!(1, ereport.get_order_id());
+
+        // only for the logged in participant
+        let request = ResendRequest::new(112, 2, 1, 1);
+        let r = receive_and_prepare_relay_message(&mut mockdb, &mut connection, &request);
+        assert!(r.is_err());
+    }
+
     /// New day order in an empty market
     #[test]
     fn process_new_day_order() {
@@ -84,10 +224,14 @@ mod test {
             side: Side::Bid.into(),
             gateway_id: GATEWAY_ID,
             session_id: SESSION_ID,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
         };
         let boxed_message = Box::new(input_order.clone()) as Box<dyn OepMessage>;
         // first process the order at the gateway
-        let send_order_result = target.send_order_to_gateway(&mut connection, &boxed_message);
+        let send_order_result =
+            target.send_order_to_gateway(&mut connection, boxed_message.as_ref());
         assert!(send_order_result.is_ok());
 
         // check if the matching engine input contains header + new order
@@ -102,11 +246,20 @@ mod test {
         );
 
         // make sure there is no new order published at this time
-        assert_eq!(0, target.disseminator.borrow().new_orders.borrow().len());
+        assert_eq!(
+            0,
+            target
+                .disseminator
+                .lock()
+                .unwrap()
+                .new_orders
+                .borrow()
+                .len()
+        );
 
         // and now process the order at the matching engine
         let ereport = target.process_order_at_matching_engine();
-        assert_eq!(ereport[0].state, OrderState::Inserted.into());
+        assert_eq!(ereport[0].state, Into::<u8>::into(OrderState::Inserted));
         let client_order_id = input_order.client_order_id;
         let submitted_order_id = ereport[0].submitted_order_id;
         assert_eq!(client_order_id, submitted_order_id);
@@ -115,40 +268,55 @@ mod test {
         assert_eq!(1, target.market.generate_bids().len());
 
         // test if it published the new order on the feed
-        assert_eq!(0, target.disseminator.borrow().cancels.borrow().len());
-        assert_eq!(1, target.disseminator.borrow().new_orders.borrow().len());
+        assert_eq!(
+            0,
+            target.disseminator.lock().unwrap().cancels.borrow().len()
+        );
+        assert_eq!(
+            1,
+            target
+                .disseminator
+                .lock()
+                .unwrap()
+                .new_orders
+                .borrow()
+                .len()
+        );
 
         // check if what published on the feed matches the input
-        let disseminator = target.disseminator.borrow();
+        let disseminator = target.disseminator.lock().unwrap();
         let feed_new_orders = disseminator.new_orders.borrow();
         let feed_order = &feed_new_orders[0];
         assert_eq!(input_order.get_participant(), feed_order.participant);
         assert_eq!(
             TestExchange::INSTRUMENT_ID,
-            feed_order.instrument.borrow().get_id()
+            feed_order.instrument.read().unwrap().get_id()
         );
         let input_quantity = input_order.quantity;
         assert_eq!(input_quantity, feed_order.quantity);
         let input_price = input_order.price;
         assert_eq!(input_price, feed_order.price);
-        assert_eq!(input_order.side, feed_order.side.into());
+        assert_eq!(input_order.side, Into::<u8>::into(feed_order.side));
         let input_type = input_order.order_type;
-        assert_eq!(input_type, feed_order.order_type.into());
+        assert_eq!(input_type, Into::<u16>::into(feed_order.order_type));
         drop(feed_new_orders); // drop so we can acquire target mutable again
         drop(disseminator);
 
         //
         // disconnect the session and check if cancel on disconnect works
         //
-        target.disconnect_session(PARTICIPANT, SESSION_ID, GATEWAY_ID);
+        target.disconnect_session(&connection, GATEWAY_ID);
         // and now process it at the matching engine
         let ereport = target.process_order_at_matching_engine();
-        assert_eq!(ereport[0].state, OrderState::Cancelled.into());
+        assert_eq!(ereport[0].state, Into::<u8>::into(OrderState::Cancelled));
         // check if the order is deleted from the market
         assert_eq!(0, target.market.generate_bids().len());
 
         // test if it published the cancel on the feed
-        assert_eq!(1, target.disseminator.borrow().cancels.borrow().len());
+        assert_eq!(
+            1,
+            target.disseminator.lock().unwrap().cancels.borrow().len()
+        );
     }
 
     #[test]
@@ -165,15 +333,19 @@ mod test {
             side: Side::Bid.into(),
             gateway_id: 1,
             session_id: 2,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
         };
         let boxed_message = Box::new(passive_order.clone()) as Box<dyn OepMessage>;
         // first process the order at the gateway
-        let send_order_result = target.send_order_to_gateway(&mut connection, &boxed_message);
+        let send_order_result =
+            target.send_order_to_gateway(&mut connection, boxed_message.as_ref());
         assert!(send_order_result.is_ok());
 
         // and now process it at the matching engine
         let ereport = target.process_order_at_matching_engine();
-        assert_eq!(ereport[0].state, OrderState::Inserted.into());
+        assert_eq!(ereport[0].state, Into::<u8>::into(OrderState::Inserted));
 
         // test if the order was accepted by the market
         assert_eq!(1, target.market.generate_bids().len());
@@ -189,10 +361,14 @@ mod test {
             side: Side::Ask.into(),
             gateway_id: 1,
             session_id: 2,
+            expiry: 0,
+            stop_price: 0,
+            display_quantity: 0,
         };
         let boxed_message = Box::new(aggressive_order.clone()) as Box<dyn OepMessage>;
         // first process the order at the gateway
-        let send_order_result = target.send_order_to_gateway(&mut connection, &boxed_message);
+        let send_order_result =
+            target.send_order_to_gateway(&mut connection, boxed_message.as_ref());
         if send_order_result.is_err() {
             eprintln!("{:#?}", send_order_result);
         }
@@ -200,7 +376,11 @@ mod test {
 
         // and now process it at the matching engine
         let ereport = target.process_order_at_matching_engine();
-        assert_eq!(ereport[0].state, OrderState::Traded.into());
+        assert_eq!(2, ereport.len());
+        assert_eq!(ereport[0].state, Into::<u8>::into(OrderState::Traded));
+        // the standing order got filled too
+        assert_eq!(ereport[1].state, Into::<u8>::into(OrderState::Traded));
+        assert_eq!(Side::Bid as u8, ereport[1].side);
 
         // test if the order was executed by the market
         assert_eq!(0, target.market.generate_bids().len());
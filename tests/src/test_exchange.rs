@@ -3,31 +3,26 @@ pub(crate) mod test_exchange {
     use anyhow::Result;
     use disseminator::mockdisseminator::MockDisseminator;
     use instruments::instrument::{Instrument, InstrumentState, InstrumentType};
-    use market::Market;
+    use market::{orderid::OrderIdGenerator, Market};
     use matching_engine::processor::{self, MessageWrapper};
     use std::{
         cell::RefCell,
         io::{Read, Write},
         rc::Rc,
+        sync::{Arc, Mutex, RwLock},
     };
 
     use gateway::messages::{receive_and_prepare_relay_message, ConnectedSession};
-    use oep::{
-        decoder::Decoder,
-        execution_report::ExecutionReport,
-        login::Login,
-        oep_message::{MsgType, OepMessage},
-        sessioninfo::SessionInfo,
-    };
+    use oep::{execution_report::ExecutionReport, login::Login, oep_message::OepMessage};
     use utils::network::MockSocket;
     pub(crate) struct TestExchange {
         pub client_socket: Rc<RefCell<MockSocket>>,
         pub gateway_client_socket: Rc<RefCell<MockSocket>>,
         pub gateway_sender: Rc<RefCell<MockSocket>>,
         pub matching_engine_socket: Rc<RefCell<MockSocket>>,
-        pub disseminator: Rc<RefCell<MockDisseminator>>,
+        pub disseminator: Arc<Mutex<MockDisseminator>>,
         #[allow(unused)]
-        pub instrument: Rc<RefCell<Instrument>>,
+        pub instrument: Arc<RwLock<Instrument>>,
         pub market: Market,
     }
 
@@ -39,7 +34,7 @@ pub(crate) mod test_exchange {
         /// Connects all the infrastructure components.
         pub(crate) fn new() -> Self {
             // prepare the market for our test instrument
-            let instrument = Rc::new(RefCell::new(Instrument::new(
+            let instrument = Arc::new(RwLock::new(Instrument::new(
                 Self::INSTRUMENT_ID,
                 "TESTINST",
                 InstrumentType::Share,
@@ -48,7 +43,7 @@ pub(crate) mod test_exchange {
                 20,
             )));
 
-            let disseminator = Rc::new(RefCell::new(MockDisseminator::new()));
+            let disseminator = Arc::new(Mutex::new(MockDisseminator::new()));
 
             let r = Self {
                 client_socket: Rc::new(RefCell::new(MockSocket::new())),
@@ -57,7 +52,11 @@ pub(crate) mod test_exchange {
                 matching_engine_socket: Rc::new(RefCell::new(MockSocket::new())),
                 disseminator: disseminator.clone(),
                 instrument: instrument.clone(),
-                market: Market::new(instrument, disseminator),
+                market: Market::new(
+                    instrument,
+                    disseminator,
+                    Arc::new(Mutex::new(OrderIdGenerator::new(0))),
+                ),
             };
             // first connect the client socket to the gateway input socket
             r.client_socket
@@ -78,8 +77,13 @@ pub(crate) mod test_exchange {
         pub(crate) fn login(&mut self) -> Result<ConnectedSession<MockSocket>> {
             let mut mockdb = dbhook::factory::build("mock");
             let mut connection = ConnectedSession::new(self.gateway_client_socket.clone());
-            let login_message = Box::new(Login::new(1, 1, 1, "test")) as Box<dyn OepMessage>;
-            let r = receive_and_prepare_relay_message(&mut mockdb, &mut connection, &login_message);
+            // session 2 on gateway 1, as the messages of the tests
+            let login_message = Box::new(Login::new(1, 2, 1, "test")) as Box<dyn OepMessage>;
+            let r = receive_and_prepare_relay_message(
+                &mut mockdb,
+                &mut connection,
+                login_message.as_ref(),
+            );
             assert!(r.is_ok());
             assert_eq!(0, connection.response_buffer.len()); // nothing is sent further to the matching engine
 
@@ -91,10 +95,10 @@ pub(crate) mod test_exchange {
         pub(crate) fn send_order_to_gateway(
             &mut self,
             connection: &mut ConnectedSession<MockSocket>,
-            boxed_message: &Box<dyn OepMessage>,
+            message: &dyn OepMessage,
         ) -> Result<u64> {
             let mut mockdb = dbhook::factory::build("mock");
-            let result = receive_and_prepare_relay_message(&mut mockdb, connection, &boxed_message);
+            let result = receive_and_prepare_relay_message(&mut mockdb, connection, message);
 
             // check if we should relay anything to the matching engine
             if connection.response_buffer.len() > 0 {
@@ -107,18 +111,16 @@ pub(crate) mod test_exchange {
             result
         }
 
+        /// closes the client socket, the gateway @gateway_id relaying the
+        /// disconnect of @connection to the matching engine, if it has to
         pub(crate) fn disconnect_session(
             &mut self,
-            participant: u64,
-            session_id: u32,
+            connection: &ConnectedSession<MockSocket>,
             gateway_id: u8,
         ) {
-            let mut buffer: Vec<u8> = vec![];
-            buffer.extend_from_slice(&[MsgType::SessionNotification as u8, 0, 0, 0]);
-            buffer
-                .extend_from_slice(&SessionInfo::new(participant, session_id, gateway_id).encode());
-
-            self.gateway_sender.borrow_mut().write(&buffer).unwrap();
+            if let Some(buffer) = connection.disconnect_notification(gateway_id) {
+                self.gateway_sender.borrow_mut().write(&buffer).unwrap();
+            }
             self.client_socket.borrow_mut().close();
         }
 
@@ -130,21 +132,19 @@ pub(crate) mod test_exchange {
             assert!(r > 4);
             let (msg, book_id) =
                 processor::decode_message(&buf[0..r]).unwrap_or_else(|e| panic!("{e:#?}"));
-            let mut expect_exactly_one_execution_report = true;
             match msg {
-                MessageWrapper::KillSession(_) => {
+                MessageWrapper::KillSession(session) => {
                     assert_eq!(0, book_id);
-                    expect_exactly_one_execution_report = false;
+                    processor::process_session_kill(std::iter::once(&mut self.market), session)
+                }
+                _ => {
+                    assert_eq!(Self::INSTRUMENT_ID, book_id);
+                    // possibly followed by the reports of the passive orders it traded against
+                    let ereports = processor::process_message(&mut self.market, msg);
+                    assert!(!ereports.is_empty());
+                    ereports
                 }
-                _ => assert_eq!(Self::INSTRUMENT_ID, book_id),
-            }
-
-            let ereports = processor::process_message(&mut self.market, msg);
-            if expect_exactly_one_execution_report {
-                assert_eq!(1, ereports.len());
             }
-
-            ereports
         }
     }
 }
@@ -1,4 +1,320 @@
-use std::collections::HashMap;
+//! The configuration files
+//!
+//! The INI files are loaded by load, the environment variables named
+//! EXCHANGE__<SECTION>__<KEY> overriding their keys, e.g.
+//! EXCHANGE__GATEWAY__PUBLISHER_PORT=10001. A section is then turned into a
+//! typed struct by section, deserialized with serde: the values are parsed into
+//! the types of the fields, the lists are comma separated and the Option fields,
+//! or the ones with a serde default, can be left out.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use configparser::ini::Ini;
+use serde::{
+    de::{
+        self,
+        value::{SeqDeserializer, StrDeserializer},
+        DeserializeOwned, IntoDeserializer, MapAccess, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+
+pub type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+/// The prefix of the environment variables overriding the configuration files
+pub const ENV_PREFIX: &str = "EXCHANGE__";
+
+/// What is wrong with a configuration file, and where
+pub struct ConfigError {
+    // empty for the file itself
+    section: String,
+    key: Option<String>,
+    message: String,
+}
+
+impl ConfigError {
+    pub fn new(section: &str, key: Option<&str>, message: impl fmt::Display) -> Self {
+        Self {
+            section: section.to_string(),
+            key: key.map(str::to_string),
+            message: message.to_string(),
+        }
+    }
+
+    /// The key the error is about, if any
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    fn at_key(mut self, key: &str) -> Self {
+        self.key.get_or_insert(key.to_string());
+        self
+    }
+
+    fn in_section(mut self, section: &str) -> Self {
+        self.section = section.to_string();
+        self
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.section.as_str(), &self.key) {
+            ("", _) => write!(f, "{}", self.message),
+            (section, Some(key)) => write!(f, "{key} in the [{section}] section: {}", self.message),
+            (section, None) => write!(f, "[{section}] section: {}", self.message),
+        }
+    }
+}
+
+// shown as is by a main returning it
+impl fmt::Debug for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl de::Error for ConfigError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::new("", None, msg)
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self::new("", Some(field), "missing, please set it")
+    }
+}
+
+/// Loads the INI file at @path, overridden by the environment
+pub fn load(path: &str) -> Result<ConfigMap, ConfigError> {
+    let mut config_map = Ini::new()
+        .load(path)
+        .map_err(|e| ConfigError::new("", None, format!("Unable to load {path}: {e}")))?;
+    apply_overrides(&mut config_map, std::env::vars());
+    Ok(config_map)
+}
+
+/// Sets the keys named by the @vars starting with ENV_PREFIX, section and key
+/// separated by a double underscore. The INI sections and keys being case
+/// insensitive, they are lowercased
+pub fn apply_overrides(config_map: &mut ConfigMap, vars: impl Iterator<Item = (String, String)>) {
+    for (name, value) in vars {
+        let Some((section, key)) = name
+            .strip_prefix(ENV_PREFIX)
+            .and_then(|name| name.split_once("__"))
+        else {
+            continue;
+        };
+        config_map
+            .entry(section.to_lowercase())
+            .or_default()
+            .insert(key.to_lowercase(), Some(value));
+    }
+}
+
+/// The @section of @config_map as a T, a missing section being an empty one
+pub fn section<T: DeserializeOwned>(
+    config_map: &ConfigMap,
+    section: &str,
+) -> Result<T, ConfigError> {
+    let empty = HashMap::new();
+    let entries = config_map.get(section).unwrap_or(&empty);
+    T::deserialize(SectionDeserializer { entries }).map_err(|e| e.in_section(section))
+}
+
+/// For the fields parsed by their FromStr, with #[serde(deserialize_with)]
+pub fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+/// Same as from_str, for the optional fields
+pub fn from_str_optional<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+/// The [database] section, the same for all the components using one
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DatabaseConfig {
+    // pgsql or mock, see dbhook::factory
+    #[serde(rename = "type")]
+    pub db_type: String,
+    pub address: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    // database in the older gateway configurations
+    #[serde(alias = "database")]
+    pub name: String,
+}
+
+impl DatabaseConfig {
+    pub fn from_config(config_map: &ConfigMap) -> Result<Self, ConfigError> {
+        section(config_map, "database")
+    }
+}
+
+struct SectionDeserializer<'a> {
+    entries: &'a HashMap<String, Option<String>>,
+}
+
+impl<'de> Deserializer<'de> for SectionDeserializer<'_> {
+    type Error = ConfigError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConfigError> {
+        visitor.visit_map(SectionAccess {
+            // a key without a value is as good as missing
+            entries: self
+                .entries
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_str(), v.as_deref()?)))
+                .collect(),
+            value: None,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct SectionAccess<'a> {
+    entries: Vec<(&'a str, &'a str)>,
+    // the one of the key just given
+    value: Option<(&'a str, &'a str)>,
+}
+
+impl<'de> MapAccess<'de> for SectionAccess<'_> {
+    type Error = ConfigError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ConfigError> {
+        let Some((key, value)) = self.entries.pop() else {
+            return Ok(None);
+        };
+        self.value = Some((key, value));
+        let key: StrDeserializer<ConfigError> = key.into_deserializer();
+        seed.deserialize(key).map(Some)
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ConfigError> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| ConfigError::new("", None, "value without a key"))?;
+        seed.deserialize(Value(value)).map_err(|e| e.at_key(key))
+    }
+}
+
+/// A value of the configuration file, parsed as the field it goes into
+struct Value<'a>(&'a str);
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident: $ty:ty),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConfigError> {
+                let value = self.0.trim();
+                visitor.$visit(value.parse::<$ty>().map_err(|_| {
+                    ConfigError::new("", None, format!("{value} is not a valid {}", stringify!($ty)))
+                })?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Value<'_> {
+    type Error = ConfigError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConfigError> {
+        visitor.visit_str(self.0)
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool: bool,
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConfigError> {
+        match self.0.trim() {
+            "" => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConfigError> {
+        let items = self
+            .0
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(Value);
+        let mut seq = SeqDeserializer::new(items);
+        let r = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(r)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ConfigError> {
+        let variant: StrDeserializer<ConfigError> = self.0.trim().into_deserializer();
+        visitor.visit_enum(variant)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ConfigError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ConfigError> for Value<'_> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
 
 pub fn get_config_string(
     config_map: &HashMap<String, HashMap<String, Option<String>>>,
@@ -17,11 +333,51 @@ pub fn get_config_string(
         .clone()
 }
 
+/// same as get_config_string, but returns None instead of panicking on a missing key
+pub fn get_optional_config_string(
+    config_map: &HashMap<String, HashMap<String, Option<String>>>,
+    section: &str,
+    key: &str,
+) -> Option<String> {
+    config_map.get(section)?.get(key)?.clone()
+}
+
 #[cfg(test)]
 mod test {
     use configparser::ini::Ini;
 
-    use crate::config::get_config_string;
+    use serde::Deserialize;
+
+    use crate::config::{
+        apply_overrides, from_str_optional, get_config_string, get_optional_config_string, section,
+        DatabaseConfig,
+    };
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Mode {
+        #[serde(rename = "fast")]
+        Fast,
+        #[serde(rename = "slow")]
+        Slow,
+    }
+
+    fn slow() -> Mode {
+        Mode::Slow
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Typed {
+        port: u16,
+        address: String,
+        enabled: bool,
+        listeners: Vec<String>,
+        ids: Vec<u8>,
+        timeout_ms: Option<u64>,
+        #[serde(default = "slow")]
+        mode: Mode,
+        #[serde(default, deserialize_with = "from_str_optional")]
+        ip: Option<std::net::IpAddr>,
+    }
 
     #[test]
     fn load_config() {
@@ -87,4 +443,136 @@ mod test {
 
         let _value = get_config_string(&config_map, "database", "password");
     }
+
+    #[test]
+    fn get_optional_values() {
+        let mut f = Ini::new();
+        let config_map = f
+            .read(String::from(
+                "[database]
+        addr=192.168.1.1",
+            ))
+            .unwrap();
+
+        assert_eq!(
+            Some(String::from("192.168.1.1")),
+            get_optional_config_string(&config_map, "database", "addr")
+        );
+        assert_eq!(
+            None,
+            get_optional_config_string(&config_map, "database", "port")
+        );
+        assert_eq!(
+            None,
+            get_optional_config_string(&config_map, "notasection", "addr")
+        );
+    }
+
+    #[test]
+    fn typed_section() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[gateway]
+                port=10000
+                address=127.0.0.1
+                enabled=true
+                listeners=members, retail
+                ids=1,2,3
+                timeout_ms=
+                ip=::1
+                log_level=info",
+            ))
+            .unwrap();
+        let target: Typed = section(&config_map, "gateway").unwrap();
+        assert_eq!(
+            target,
+            Typed {
+                port: 10000,
+                address: String::from("127.0.0.1"),
+                enabled: true,
+                listeners: vec![String::from("members"), String::from("retail")],
+                ids: vec![1, 2, 3],
+                timeout_ms: None,
+                mode: Mode::Slow,
+                ip: Some("::1".parse().unwrap()),
+            }
+        );
+
+        let with = |extra: &str| {
+            Ini::new()
+                .read(format!(
+                    "[gateway]
+                    address=127.0.0.1
+                    enabled=false
+                    listeners=
+                    ids=
+                    {extra}"
+                ))
+                .unwrap()
+        };
+        let target: Typed = section(&with("port=1\nmode=fast"), "gateway").unwrap();
+        assert_eq!(target.mode, Mode::Fast);
+        assert!(target.listeners.is_empty());
+
+        let e = section::<Typed>(&with(""), "gateway").unwrap_err();
+        assert_eq!(e.key(), Some("port"));
+        assert_eq!(
+            e.to_string(),
+            "port in the [gateway] section: missing, please set it"
+        );
+        let e = section::<Typed>(&with("port=70000"), "gateway").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "port in the [gateway] section: 70000 is not a valid u16"
+        );
+        let e = section::<Typed>(&with("port=1\nmode=medium"), "gateway").unwrap_err();
+        assert_eq!(e.key(), Some("mode"));
+        let e = section::<Typed>(&with("port=1\nip=localhost"), "gateway").unwrap_err();
+        assert_eq!(e.key(), Some("ip"));
+        // no section, nothing set
+        let e = section::<Typed>(&with("port=1"), "notasection").unwrap_err();
+        assert!(e.to_string().contains("[notasection]"));
+    }
+
+    #[test]
+    fn overrides() {
+        let mut config_map = Ini::new()
+            .read(String::from(
+                "[database]
+                type=pgsql
+                address=127.0.0.1
+                port=5432
+                username=test
+                password=test
+                database=trading",
+            ))
+            .unwrap();
+        apply_overrides(
+            &mut config_map,
+            [
+                ("EXCHANGE__DATABASE__PASSWORD", "secret"),
+                ("EXCHANGE__DATABASE__PORT", "5433"),
+                ("EXCHANGE__LISTENER_MEMBERS__SESSION_ID_MAX", "999"),
+                ("EXCHANGE_DATABASE_USERNAME", "ignored"),
+                ("HOME", "/root"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        assert_eq!(
+            DatabaseConfig::from_config(&config_map).unwrap(),
+            DatabaseConfig {
+                db_type: String::from("pgsql"),
+                address: String::from("127.0.0.1"),
+                port: 5433,
+                username: String::from("test"),
+                password: String::from("secret"),
+                name: String::from("trading"),
+            }
+        );
+        assert_eq!(
+            get_optional_config_string(&config_map, "listener_members", "session_id_max"),
+            Some(String::from("999"))
+        );
+    }
 }
@@ -0,0 +1,97 @@
+//! Fault injection for the mocks, for the tests to go through the error
+//! paths of what uses them
+//!
+//! A mock asks its Faults before every call it can fail: the call is counted,
+//! delayed by the latency set, if any, and told whether it fails.
+
+use std::{cell::Cell, thread, time::Duration};
+
+/// Which calls of a mock fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailureMode {
+    #[default]
+    Never,
+    Always,
+    // the nth call from the time the mode was set, 1 being the next one,
+    // the other ones succeeding
+    Nth(u64),
+}
+
+#[derive(Debug, Default)]
+pub struct Faults {
+    mode: Cell<FailureMode>,
+    // the calls made by the time the mode was set
+    armed_at: Cell<u64>,
+    latency: Cell<Duration>,
+    calls: Cell<u64>,
+    failures: Cell<u64>,
+}
+
+impl Faults {
+    pub fn set_mode(&self, mode: FailureMode) {
+        self.mode.set(mode);
+        self.armed_at.set(self.calls.get());
+    }
+
+    /// Every call takes @latency more, failing or not
+    pub fn set_latency(&self, latency: Duration) {
+        self.latency.set(latency);
+    }
+
+    /// Counts a call, after the latency set
+    ///
+    /// Returns: true if the call has to fail
+    pub fn on_call(&self) -> bool {
+        let latency = self.latency.get();
+        if !latency.is_zero() {
+            thread::sleep(latency);
+        }
+        self.calls.set(self.calls.get() + 1);
+        let failed = match self.mode.get() {
+            FailureMode::Never => false,
+            FailureMode::Always => true,
+            FailureMode::Nth(n) => self.calls.get() - self.armed_at.get() == n,
+        };
+        if failed {
+            self.failures.set(self.failures.get() + 1);
+        }
+        failed
+    }
+
+    /// The calls made, the failed ones included
+    pub fn calls(&self) -> u64 {
+        self.calls.get()
+    }
+
+    pub fn failures(&self) -> u64 {
+        self.failures.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{FailureMode, Faults};
+
+    #[test]
+    fn failure_modes() {
+        let target = Faults::default();
+        assert!(!target.on_call());
+
+        target.set_mode(FailureMode::Nth(2));
+        let failed: Vec<bool> = (0..4).map(|_| target.on_call()).collect();
+        assert_eq!(vec![false, true, false, false], failed);
+
+        target.set_mode(FailureMode::Always);
+        assert!(target.on_call() && target.on_call());
+        target.set_mode(FailureMode::Never);
+        assert!(!target.on_call());
+        assert_eq!((8, 3), (target.calls(), target.failures()));
+
+        target.set_latency(Duration::from_millis(20));
+        let start = Instant::now();
+        assert!(!target.on_call());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,63 @@
+//! Just enough JSON for the flat objects the admin and the WebSocket clients
+//! send: no nesting, no arrays and no escapes within the strings
+//!
+//! # Example:
+//!
+//! ```
+//! # use utils::json::field;
+//! let text = r#"{"action": "subscribe", "book_id": 1000}"#;
+//! assert_eq!(Some("subscribe"), field(text, "action"));
+//! assert_eq!(Some("1000"), field(text, "book_id"));
+//! assert_eq!(None, field(text, "name"));
+//! ```
+
+use std::fmt::Write;
+
+/// The value of @key in the flat JSON object @text, unquoted
+pub fn field<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    let text = text.trim();
+    let text = text.strip_prefix('{')?.strip_suffix('}')?;
+    let (_, value) = text.split_once(&format!("\"{key}\""))?;
+    let value = value.trim_start().strip_prefix(':')?.trim_start();
+    match value.strip_prefix('"') {
+        Some(quoted) => Some(&quoted[..quoted.find('"')?]),
+        None => Some(value[..value.find(',').unwrap_or(value.len())].trim()),
+    }
+}
+
+/// @text, ready to go between the quotes of a JSON string
+pub fn escape(text: &str) -> String {
+    let mut r = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(r, "\\u{:04x}", c as u32);
+            }
+            c => r.push(c),
+        }
+    }
+    r
+}
+
+#[cfg(test)]
+mod test {
+    use super::{escape, field};
+
+    #[test]
+    fn fields() {
+        let text = r#" { "name" : "ACME" , "bands":5,"state":"halted" } "#;
+        assert_eq!(Some("ACME"), field(text, "name"));
+        assert_eq!(Some("5"), field(text, "bands"));
+        assert_eq!(Some("halted"), field(text, "state"));
+        assert_eq!(None, field(text, "type"));
+        assert_eq!(None, field("name: ACME", "name"));
+        assert_eq!(None, field(r#"{"name": "ACME}"#, "name"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(r#"A\"C\\ME\u000a"#, escape("A\"C\\ME\n"));
+    }
+}
@@ -1,2 +1,9 @@
 pub mod config;
+pub mod faults;
+pub mod json;
+pub mod logging;
+pub mod metrics;
 pub mod network;
+pub mod recovery;
+pub mod reload;
+pub mod shutdown;
@@ -0,0 +1,98 @@
+//! The logs of the binaries, written with tracing to stderr
+//!
+//! The log_level key of a section takes a level (error, warn, info, debug,
+//! trace) or a filter in the RUST_LOG syntax, e.g. `info,matching_engine=debug`,
+//! and the log_format key is `text` or `json`, one object per line with the
+//! fields of the event and of its spans. The spans are logged as they close,
+//! with the time spent in them, when their level is enabled.
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+
+/// How the logs are written, from the log_level and log_format keys of a section
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+    pub level: String,
+    pub json: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: String::from("info"),
+            json: false,
+        }
+    }
+}
+
+impl LogConfig {
+    /// The defaults for the keys missing from @section
+    pub fn from_config(
+        config_map: &HashMap<String, HashMap<String, Option<String>>>,
+        section: &str,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let optional =
+            |key: &str| crate::config::get_optional_config_string(config_map, section, key);
+        let json = match optional("log_format").as_deref() {
+            None | Some("text") => false,
+            Some("json") => true,
+            Some(other) => return Err(format!("Unknown log format {other}").into()),
+        };
+        Ok(Self {
+            level: optional("log_level").unwrap_or(Self::default().level),
+            json,
+        })
+    }
+}
+
+/// Starts writing the logs as told by @config. RUST_LOG, when set, overrides
+/// the level of the configuration file
+pub fn init(config: &LogConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(filter) => EnvFilter::try_new(filter),
+        Err(_) => EnvFilter::try_new(&config.level),
+    }?;
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr);
+    match config.json {
+        true => logs.json().with_current_span(true).try_init(),
+        false => logs.try_init(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use configparser::ini::Ini;
+
+    use super::LogConfig;
+
+    #[test]
+    fn config() {
+        let mut config = Ini::new();
+        let config_map = config
+            .read(String::from(
+                "[engine]
+                log_level=info,matching_engine=debug
+                log_format=json
+                [gateway]
+                log_format=xml",
+            ))
+            .unwrap();
+        assert_eq!(
+            LogConfig {
+                level: String::from("info,matching_engine=debug"),
+                json: true
+            },
+            LogConfig::from_config(&config_map, "engine").unwrap()
+        );
+        assert!(LogConfig::from_config(&config_map, "gateway").is_err());
+        assert_eq!(
+            LogConfig::default(),
+            LogConfig::from_config(&config_map, "clearing").unwrap()
+        );
+    }
+}
@@ -0,0 +1,470 @@
+//! Counters, gauges and histograms, rendered in the Prometheus text format
+//!
+//! The metrics are registered once, by name and labels, in the registry of
+//! the process, and updated lock free through the handles it gives back.
+//! `serve` answers the scrapes of GET /metrics on a thread of its own:
+//!
+//! ```ignore
+//! let orders = metrics::registry().counter("engine_orders_total", "Orders", &[]);
+//! orders.inc();
+//! metrics::serve("0.0.0.0", 9100)?;
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::num::ParseIntError;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use crate::config::get_optional_config_string;
+
+/// The upper bounds, in seconds, of the latency buckets: from 1us to 100ms
+pub const LATENCY_BOUNDS: &[f64] = &[
+    0.000_001, 0.000_002, 0.000_005, 0.000_01, 0.000_02, 0.000_05, 0.000_1, 0.000_2, 0.000_5,
+    0.001, 0.01, 0.1,
+];
+
+// a scrape request is tiny, anything longer is not one
+const MAX_REQUEST_SIZE: usize = 8192;
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the metrics are served, from the metrics_port and metrics_address
+/// keys of a section
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsConfig {
+    pub address: String,
+    pub port: u16,
+}
+
+impl MetricsConfig {
+    /// None without a metrics_port in @section, served on all the interfaces
+    /// without a metrics_address
+    pub fn from_config(
+        config_map: &HashMap<String, HashMap<String, Option<String>>>,
+        section: &str,
+    ) -> Result<Option<Self>, ParseIntError> {
+        let Some(port) = get_optional_config_string(config_map, section, "metrics_port") else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            address: get_optional_config_string(config_map, section, "metrics_address")
+                .unwrap_or_else(|| String::from("0.0.0.0")),
+            port: port.parse::<u16>()?,
+        }))
+    }
+
+    pub fn serve(&self) -> io::Result<()> {
+        serve(&self.address, self.port)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Durations counted in buckets of upper @bounds, in seconds
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    // not cumulative, the last one for what is above all the bounds
+    buckets: Vec<AtomicU64>,
+    sum_ns: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_ns: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|b| seconds <= *b)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_ns
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Metric::Counter(_) => "counter",
+            Metric::Gauge(_) => "gauge",
+            Metric::Histogram(_) => "histogram",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Family {
+    help: String,
+    // by the rendered labels, {a="b",c="d"} or empty
+    series: BTreeMap<String, Metric>,
+}
+
+/// The metrics of a process, by name
+#[derive(Debug, Default)]
+pub struct Registry {
+    families: Mutex<BTreeMap<String, Family>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The counter @name with @labels, registered if it wasn't yet
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Counter> {
+        match self.register(name, help, labels, || {
+            Metric::Counter(Arc::new(Counter::default()))
+        }) {
+            Metric::Counter(c) => c,
+            other => panic!("{name} is a {}, not a counter", other.kind()),
+        }
+    }
+
+    /// The gauge @name with @labels, registered if it wasn't yet
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Gauge> {
+        match self.register(name, help, labels, || {
+            Metric::Gauge(Arc::new(Gauge::default()))
+        }) {
+            Metric::Gauge(g) => g,
+            other => panic!("{name} is a {}, not a gauge", other.kind()),
+        }
+    }
+
+    /// The histogram @name with @labels, registered with @bounds if it wasn't yet
+    pub fn histogram(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        bounds: &[f64],
+    ) -> Arc<Histogram> {
+        match self.register(name, help, labels, || {
+            Metric::Histogram(Arc::new(Histogram::new(bounds)))
+        }) {
+            Metric::Histogram(h) => h,
+            other => panic!("{name} is a {}, not a histogram", other.kind()),
+        }
+    }
+
+    fn register(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        create: impl FnOnce() -> Metric,
+    ) -> Metric {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name.to_string()).or_insert_with(|| Family {
+            help: help.to_string(),
+            series: BTreeMap::new(),
+        });
+        family
+            .series
+            .entry(render_labels(labels))
+            .or_insert_with(create)
+            .clone()
+    }
+
+    /// All the metrics, in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut r = String::new();
+        for (name, family) in self.families.lock().unwrap().iter() {
+            let Some(kind) = family.series.values().next().map(Metric::kind) else {
+                continue;
+            };
+            let _ = writeln!(r, "# HELP {name} {}", family.help);
+            let _ = writeln!(r, "# TYPE {name} {kind}");
+            for (labels, metric) in &family.series {
+                match metric {
+                    Metric::Counter(c) => {
+                        let _ = writeln!(r, "{name}{labels} {}", c.get());
+                    }
+                    Metric::Gauge(g) => {
+                        let _ = writeln!(r, "{name}{labels} {}", g.get());
+                    }
+                    Metric::Histogram(h) => render_histogram(&mut r, name, labels, h),
+                }
+            }
+        }
+        r
+    }
+}
+
+/// {a="b",c="d"}, empty without labels
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{k}=\"{v}\"")
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// @labels, as rendered by render_labels, with le="@le" added
+fn with_le(labels: &str, le: &str) -> String {
+    match labels.strip_suffix('}') {
+        Some(labels) => format!("{labels},le=\"{le}\"}}"),
+        None => format!("{{le=\"{le}\"}}"),
+    }
+}
+
+fn render_histogram(r: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    let mut cumulative = 0;
+    for (i, bucket) in histogram.buckets.iter().enumerate() {
+        cumulative += bucket.load(Ordering::Relaxed);
+        let le = match histogram.bounds.get(i) {
+            Some(bound) => bound.to_string(),
+            None => "+Inf".to_string(),
+        };
+        let _ = writeln!(r, "{name}_bucket{} {cumulative}", with_le(labels, &le));
+    }
+    let sum = histogram.sum_ns.load(Ordering::Relaxed) as f64 / 1e9;
+    let _ = writeln!(r, "{name}_sum{labels} {sum}");
+    let _ = writeln!(r, "{name}_count{labels} {}", histogram.count());
+}
+
+/// The registry of the process
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::new)
+}
+
+/// Serves the metrics of the registry on http://@addr:@port/metrics, from a
+/// thread of its own, returning once listening
+pub fn serve(addr: &str, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind((addr, port))?;
+    info!(address = %listener.local_addr()?, "Serving the metrics");
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let r = stream.and_then(|stream| answer_scrape(stream, registry()));
+            if let Err(e) = r {
+                error!(error = %e, "Error serving the metrics");
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answers one HTTP request of @stream with the metrics of @registry, on
+/// GET /metrics, or with a 404
+fn answer_scrape(mut stream: TcpStream, registry: &Registry) -> io::Result<()> {
+    stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+    stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+    let mut head = vec![];
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") && !head.ends_with(b"\n\n") {
+        if head.len() >= MAX_REQUEST_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request too long",
+            ));
+        }
+        stream.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let target = (request_line.next(), request_line.next());
+    let path = target.1.map(|t| t.split('?').next().unwrap_or_default());
+    let (status, body) = match (target.0, path) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", registry.render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    use configparser::ini::Ini;
+
+    use super::{answer_scrape, MetricsConfig, Registry};
+
+    #[test]
+    fn config() {
+        let mut config = Ini::new();
+        let config_map = config
+            .read(String::from(
+                "[engine]
+                metrics_port=9100
+                [gateway]
+                metrics_port=9101
+                metrics_address=127.0.0.1
+                [clearing]
+                metrics_port=port",
+            ))
+            .unwrap();
+        assert_eq!(
+            Some(MetricsConfig {
+                address: String::from("0.0.0.0"),
+                port: 9100
+            }),
+            MetricsConfig::from_config(&config_map, "engine").unwrap()
+        );
+        assert_eq!(
+            Some(MetricsConfig {
+                address: String::from("127.0.0.1"),
+                port: 9101
+            }),
+            MetricsConfig::from_config(&config_map, "gateway").unwrap()
+        );
+        assert!(MetricsConfig::from_config(&config_map, "clearing").is_err());
+        assert_eq!(
+            None,
+            MetricsConfig::from_config(&config_map, "other").unwrap()
+        );
+    }
+
+    #[test]
+    fn render() {
+        let target = Registry::new();
+        let rejects = target.counter("rejects_total", "Rejects", &[("reason", "risk")]);
+        rejects.add(3);
+        // the same series
+        target
+            .counter("rejects_total", "Rejects", &[("reason", "risk")])
+            .inc();
+        target.counter("rejects_total", "Rejects", &[("reason", "throttled")]);
+        target.gauge("sessions", "Sessions", &[]).set(2);
+        let latency = target.histogram("latency_seconds", "Latency", &[], &[0.001, 0.01]);
+        latency.observe(Duration::from_micros(500));
+        latency.observe(Duration::from_millis(5));
+        latency.observe(Duration::from_secs(1));
+
+        assert_eq!(4, rejects.get());
+        assert_eq!(
+            "# HELP latency_seconds Latency\n\
+             # TYPE latency_seconds histogram\n\
+             latency_seconds_bucket{le=\"0.001\"} 1\n\
+             latency_seconds_bucket{le=\"0.01\"} 2\n\
+             latency_seconds_bucket{le=\"+Inf\"} 3\n\
+             latency_seconds_sum 1.0055\n\
+             latency_seconds_count 3\n\
+             # HELP rejects_total Rejects\n\
+             # TYPE rejects_total counter\n\
+             rejects_total{reason=\"risk\"} 4\n\
+             rejects_total{reason=\"throttled\"} 0\n\
+             # HELP sessions Sessions\n\
+             # TYPE sessions gauge\n\
+             sessions 2\n",
+            target.render()
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn kind_mismatch() {
+        let target = Registry::new();
+        target.counter("sessions", "Sessions", &[]);
+        target.gauge("sessions", "Sessions", &[]);
+    }
+
+    #[test]
+    fn scrape() {
+        let registry: &'static Registry = Box::leak(Box::new(Registry::new()));
+        registry.counter("orders_total", "Orders", &[]).add(7);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                answer_scrape(stream.unwrap(), registry).unwrap();
+            }
+        });
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(
+            "\r\n\r\n# HELP orders_total Orders\n# TYPE orders_total counter\norders_total 7\n"
+        ));
+        assert!(get("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
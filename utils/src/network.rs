@@ -1,26 +1,200 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::CString;
 use std::io::{self, Read, Write};
-use std::net::Ipv4Addr;
-use std::os::fd::AsFd;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::fd::{AsFd, AsRawFd};
 use std::rc::Rc;
+use std::str::FromStr;
 
-use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use socket2::{Domain, InterfaceIndexOrAddress, Protocol, SockAddr, Socket, Type};
 
-pub fn join_multicast_group(group_addr: &SockAddr) -> io::Result<Socket> {
+use crate::config::get_optional_config_string;
+
+type ConfigMap = HashMap<String, HashMap<String, Option<String>>>;
+
+/// The network interface the multicast sockets join and publish on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MulticastInterface {
+    // e.g. eth1
+    Name(String),
+    // one of the IPv4 addresses of the interface
+    Address(Ipv4Addr),
+}
+
+impl MulticastInterface {
+    fn parse(text: &str) -> Self {
+        match Ipv4Addr::from_str(text) {
+            Ok(address) => Self::Address(address),
+            Err(_) => Self::Name(text.to_string()),
+        }
+    }
+
+    /// The index of the interface, as known by the system
+    fn index(&self) -> io::Result<u32> {
+        let name = match self {
+            Self::Name(name) => name,
+            Self::Address(address) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The IPv6 groups need the interface by name, not {address}"),
+                ))
+            }
+        };
+        let unknown =
+            || io::Error::new(io::ErrorKind::NotFound, format!("Unknown interface {name}"));
+        let c_name = CString::new(name.as_str()).map_err(|_| unknown())?;
+        // SAFETY: a nul terminated string, only read
+        match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+            0 => Err(unknown()),
+            index => Ok(index),
+        }
+    }
+}
+
+/// How the multicast sockets are set up, the system defaults otherwise
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MulticastConfig {
+    // the one of the default route without it
+    pub interface: Option<MulticastInterface>,
+    // how many hops the datagrams sent can make, 1 by default
+    pub ttl: Option<u32>,
+    // whether the datagrams sent reach the listeners of the same host
+    pub loopback: bool,
+}
+
+impl Default for MulticastConfig {
+    fn default() -> Self {
+        Self {
+            interface: None,
+            ttl: None,
+            loopback: true,
+        }
+    }
+}
+
+impl MulticastConfig {
+    /// Reads the multicast_interface, multicast_ttl and multicast_loop keys
+    /// of @section
+    pub fn from_config(
+        config_map: &ConfigMap,
+        section: &str,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let optional = |key: &str| get_optional_config_string(config_map, section, key);
+        Ok(Self {
+            interface: optional("multicast_interface")
+                .filter(|interface| !interface.trim().is_empty())
+                .map(|interface| MulticastInterface::parse(interface.trim())),
+            ttl: optional("multicast_ttl")
+                .map(|ttl| ttl.parse::<u32>())
+                .transpose()?,
+            loopback: optional("multicast_loop")
+                .map(|loopback| loopback.parse::<bool>())
+                .transpose()?
+                .unwrap_or(true),
+        })
+    }
+
+    /// Makes @socket, of @domain, send its multicast datagrams as configured
+    fn set_sender(&self, socket: &Socket, domain: Domain) -> io::Result<()> {
+        if domain == Domain::IPV6 {
+            socket.set_multicast_loop_v6(self.loopback)?;
+            if let Some(ttl) = self.ttl {
+                socket.set_multicast_hops_v6(ttl)?;
+            }
+            if let Some(interface) = &self.interface {
+                socket.set_multicast_if_v6(interface.index()?)?;
+            }
+            return Ok(());
+        }
+        socket.set_multicast_loop_v4(self.loopback)?;
+        if let Some(ttl) = self.ttl {
+            socket.set_multicast_ttl_v4(ttl)?;
+        }
+        match &self.interface {
+            Some(MulticastInterface::Address(address)) => socket.set_multicast_if_v4(address),
+            Some(interface) => set_multicast_if_v4_index(socket, interface.index()?),
+            None => Ok(()),
+        }
+    }
+}
+
+/// IP_MULTICAST_IF by interface index, socket2 only taking an address
+fn set_multicast_if_v4_index(socket: &Socket, index: u32) -> io::Result<()> {
+    let request = libc::ip_mreqn {
+        imr_multiaddr: libc::in_addr { s_addr: 0 },
+        imr_address: libc::in_addr { s_addr: 0 },
+        imr_ifindex: index as libc::c_int,
+    };
+    // SAFETY: the option takes an ip_mreqn on linux, passed with its size
+    let r = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_MULTICAST_IF,
+            &request as *const libc::ip_mreqn as *const libc::c_void,
+            std::mem::size_of::<libc::ip_mreqn>() as libc::socklen_t,
+        )
+    };
+    match r {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// @address, IPv4 or IPv6 (e.g. "239.1.1.1" or "ff15::1"), with @port
+///
+/// Returns: the socket address, whose domain the sockets are created with
+pub fn socket_address(address: &str, port: u16) -> io::Result<SockAddr> {
+    let ip = IpAddr::from_str(address.trim()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid address {address}"),
+        )
+    })?;
+    Ok(SockAddr::from(SocketAddr::new(ip, port)))
+}
+
+/// A UDP socket sending to @address, a multicast group going out as told by
+/// @multicast
+pub fn udp_sender(address: &SockAddr, multicast: &MulticastConfig) -> io::Result<Socket> {
+    let socket = Socket::new(address.domain(), Type::DGRAM, Some(Protocol::UDP))?;
+    multicast.set_sender(&socket, address.domain())?;
+    socket.connect(address)?;
+    Ok(socket)
+}
+
+/// A UDP socket receiving the datagrams of @group_addr, joined on the
+/// interface of @multicast
+pub fn join_multicast_group(
+    group_addr: &SockAddr,
+    multicast: &MulticastConfig,
+) -> io::Result<Socket> {
     let socket = Socket::new(group_addr.domain(), Type::DGRAM, Some(Protocol::UDP))?;
 
     match group_addr.domain() {
         Domain::IPV4 => {
-            socket.join_multicast_v4(
-                group_addr
-                    .as_socket_ipv4()
-                    .expect("Group address cannot be a socket")
-                    .ip(),
-                &Ipv4Addr::UNSPECIFIED,
-            )?;
+            let group = group_addr
+                .as_socket_ipv4()
+                .expect("Group address cannot be a socket");
+            match &multicast.interface {
+                Some(MulticastInterface::Address(address)) => {
+                    socket.join_multicast_v4(group.ip(), address)?
+                }
+                Some(interface) => socket.join_multicast_v4_n(
+                    group.ip(),
+                    &InterfaceIndexOrAddress::Index(interface.index()?),
+                )?,
+                None => socket.join_multicast_v4(group.ip(), &Ipv4Addr::UNSPECIFIED)?,
+            }
         }
         Domain::IPV6 => {
-            socket.join_multicast_v6(group_addr.as_socket_ipv6().unwrap().ip(), 0)?;
+            let index = match &multicast.interface {
+                Some(interface) => interface.index()?,
+                None => 0,
+            };
+            socket.join_multicast_v6(group_addr.as_socket_ipv6().unwrap().ip(), index)?;
             socket.set_only_v6(true)?;
         }
         _ => return Err(std::io::ErrorKind::AddrNotAvailable.into()),
@@ -89,16 +263,13 @@ impl Write for MockSocket {
         if self.closed {
             return Err(std::io::ErrorKind::UnexpectedEof.into());
         }
-        if self.output.is_none() {
-            self.write_buffer.borrow_mut().append(&mut buf.to_vec());
-        } else {
-            self.output
-                .as_ref()
-                .unwrap()
+        match &self.output {
+            None => self.write_buffer.borrow_mut().append(&mut buf.to_vec()),
+            Some(output) => output
                 .borrow_mut()
                 .read_buffer
                 .borrow_mut()
-                .append(&mut buf.to_vec());
+                .append(&mut buf.to_vec()),
         }
         Ok(buf.len())
     }
@@ -113,3 +284,85 @@ impl AsFd for MockSocket {
         todo!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use configparser::ini::Ini;
+    use socket2::Domain;
+
+    use super::{socket_address, udp_sender, MulticastConfig, MulticastInterface};
+
+    #[test]
+    fn both_families() {
+        let v4 = socket_address("239.71.71.71", 10000).unwrap();
+        assert_eq!(Domain::IPV4, v4.domain());
+        assert_eq!("239.71.71.71:10000", v4.as_socket().unwrap().to_string());
+        let v6 = socket_address(" ff15::71 ", 10000).unwrap();
+        assert_eq!(Domain::IPV6, v6.domain());
+        assert_eq!("[ff15::71]:10000", v6.as_socket().unwrap().to_string());
+        for invalid in [
+            "",
+            "localhost",
+            "239.71.71",
+            "[ff15::71]",
+            "ff15::71:10000:",
+        ] {
+            assert!(socket_address(invalid, 1).is_err(), "{invalid}");
+        }
+
+        let sender = udp_sender(
+            &socket_address("127.0.0.1", 9).unwrap(),
+            &MulticastConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(Domain::IPV4, sender.local_addr().unwrap().domain());
+    }
+
+    #[test]
+    fn multicast_config() {
+        let config_map = Ini::new()
+            .read(String::from(
+                "[engine]
+                multicast_interface=lo
+                multicast_ttl=4
+                multicast_loop=false
+                [gateway]
+                multicast_interface=10.0.0.3
+                [client]
+                multicast_ttl=-1",
+            ))
+            .unwrap();
+        let target = MulticastConfig::from_config(&config_map, "engine").unwrap();
+        assert_eq!(
+            MulticastConfig {
+                interface: Some(MulticastInterface::Name(String::from("lo"))),
+                ttl: Some(4),
+                loopback: false,
+            },
+            target
+        );
+        let sender = udp_sender(&socket_address("239.71.71.71", 9).unwrap(), &target).unwrap();
+        assert!(!sender.multicast_loop_v4().unwrap());
+        assert_eq!(4, sender.multicast_ttl_v4().unwrap());
+
+        assert_eq!(
+            Some(MulticastInterface::Address(Ipv4Addr::new(10, 0, 0, 3))),
+            MulticastConfig::from_config(&config_map, "gateway")
+                .unwrap()
+                .interface
+        );
+        assert!(MulticastConfig::from_config(&config_map, "client").is_err());
+        assert_eq!(
+            MulticastConfig::default(),
+            MulticastConfig::from_config(&config_map, "feed").unwrap()
+        );
+
+        let unknown = MulticastConfig {
+            interface: Some(MulticastInterface::Name(String::from("nosuch0"))),
+            ..MulticastConfig::default()
+        };
+        assert!(udp_sender(&socket_address("239.71.71.71", 9).unwrap(), &unknown).is_err());
+    }
+}
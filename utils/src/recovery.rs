@@ -0,0 +1,171 @@
+//! Retransmission of the feed packets lost on the multicast
+//!
+//! A feed consumer tracks the sequence numbers with a GapDetector and asks the
+//! recovery service of the matching engine for the missing packets, over TCP:
+//!
+//! request:  | First sequence (8) | Count (4) |
+//! response: | Count (4) | then Count times: | Length (2) | Packet (Length) |
+//!
+//! The packets are replayed exactly as they were multicast, sequence included.
+//! Only the packets still cached by the service are sent back, so the response
+//! might hold less packets than requested.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+pub const RECOVERY_REQUEST_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryRequest {
+    pub from_seq: u64,
+    pub count: u32,
+}
+
+impl RecoveryRequest {
+    pub fn encode(&self) -> [u8; RECOVERY_REQUEST_SIZE] {
+        let mut r = [0; RECOVERY_REQUEST_SIZE];
+        r[0..8].copy_from_slice(&self.from_seq.to_le_bytes());
+        r[8..12].copy_from_slice(&self.count.to_le_bytes());
+        r
+    }
+
+    /// None if @buffer is too short
+    pub fn decode(buffer: &[u8]) -> Option<Self> {
+        Some(Self {
+            from_seq: u64::from_le_bytes(buffer.get(0..8)?.try_into().ok()?),
+            count: u32::from_le_bytes(buffer.get(8..12)?.try_into().ok()?),
+        })
+    }
+}
+
+pub fn encode_response<'a>(packets: impl ExactSizeIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut r = (packets.len() as u32).to_le_bytes().to_vec();
+    for packet in packets {
+        r.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+        r.extend_from_slice(packet);
+    }
+    r
+}
+
+/// Returns the packets and the number of bytes they took, None if @buffer
+/// doesn't hold a complete response yet
+pub fn decode_response(buffer: &[u8]) -> Option<(Vec<Vec<u8>>, usize)> {
+    let count = u32::from_le_bytes(buffer.get(0..4)?.try_into().ok()?);
+    let mut processed = 4;
+    let mut packets = vec![];
+    for _ in 0..count {
+        let len =
+            u16::from_le_bytes(buffer.get(processed..processed + 2)?.try_into().ok()?) as usize;
+        processed += 2;
+        packets.push(buffer.get(processed..processed + len)?.to_vec());
+        processed += len;
+    }
+    Some((packets, processed))
+}
+
+/// Detects the gaps in the sequence numbers of the feed
+#[derive(Debug, Default)]
+pub struct GapDetector {
+    // the sequence expected next, None before the first packet
+    next: Option<u64>,
+}
+
+impl GapDetector {
+    /// Accounts for a packet received with @seq
+    ///
+    /// Returns: the request for the packets missing before it, if any
+    pub fn on_packet(&mut self, seq: u64) -> Option<RecoveryRequest> {
+        let gap = match self.next {
+            Some(next) if seq > next => Some(RecoveryRequest {
+                from_seq: next,
+                count: (seq - next).try_into().unwrap_or(u32::MAX),
+            }),
+            // late or duplicated packets don't move the expected sequence back
+            Some(next) if seq < next => return None,
+            _ => None,
+        };
+        self.next = Some(seq + 1);
+        gap
+    }
+}
+
+/// Asks the recovery service at @addr (host:port) for the packets of @request
+pub fn request_retransmission(addr: &str, request: RecoveryRequest) -> io::Result<Vec<Vec<u8>>> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.write_all(&request.encode())?;
+
+    let mut buffer = vec![];
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some((packets, _)) = decode_response(&buffer) {
+            return Ok(packets);
+        }
+        match stream.read(&mut chunk)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            r => buffer.extend_from_slice(&chunk[..r]),
+        }
+    }
+}
+
+/// The sequence number of a feed packet
+pub fn packet_seq(packet: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(packet.get(0..8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn request_roundtrip() {
+        let request = RecoveryRequest {
+            from_seq: 0x0102030405060708,
+            count: 10,
+        };
+        let encoded = request.encode();
+        assert_eq!([8, 7, 6, 5, 4, 3, 2, 1, 10, 0, 0, 0], encoded);
+        assert_eq!(Some(request), RecoveryRequest::decode(&encoded));
+        assert_eq!(None, RecoveryRequest::decode(&encoded[..11]));
+    }
+
+    #[test]
+    fn response_roundtrip() {
+        let packets: [&[u8]; 2] = [&[1, 2, 3], &[4]];
+        let encoded = encode_response(packets.into_iter());
+        assert_eq!(vec![2, 0, 0, 0, 3, 0, 1, 2, 3, 1, 0, 4], encoded);
+
+        let (decoded, processed) = decode_response(&encoded).unwrap();
+        assert_eq!(vec![vec![1, 2, 3], vec![4]], decoded);
+        assert_eq!(encoded.len(), processed);
+        // incomplete
+        assert!(decode_response(&encoded[..encoded.len() - 1]).is_none());
+        // nothing cached any more
+        assert_eq!(
+            Some((vec![], 4)),
+            decode_response(&encode_response(std::iter::empty()))
+        );
+    }
+
+    #[test]
+    fn detect_gaps() {
+        let mut target = GapDetector::default();
+        assert_eq!(None, target.on_packet(5));
+        assert_eq!(None, target.on_packet(6));
+        assert_eq!(
+            Some(RecoveryRequest {
+                from_seq: 7,
+                count: 3
+            }),
+            target.on_packet(10)
+        );
+        // a late packet is not a gap
+        assert_eq!(None, target.on_packet(8));
+        assert_eq!(None, target.on_packet(11));
+    }
+
+    #[test]
+    fn seq_of_packet() {
+        assert_eq!(Some(258), packet_seq(&[2, 1, 0, 0, 0, 0, 0, 0, 4]));
+        assert_eq!(None, packet_seq(&[2, 1]));
+    }
+}
@@ -0,0 +1,63 @@
+//! Re-reading the configuration when asked to, by SIGHUP
+//!
+//! Only the settings a component can change on the fly are reloaded, the
+//! others needing a restart: see the documentation of each component.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use signal_hook::{consts::SIGHUP, iterator::Signals};
+use tracing::info;
+
+/// Watches for SIGHUP from now on, which no longer terminates the process.
+/// Each one sets the flag returned and calls @wake, for the process to reload
+/// its configuration and clear the flag
+pub fn on_reload_signal(wake: impl Fn() + Send + 'static) -> io::Result<Arc<AtomicBool>> {
+    let reloading = Arc::new(AtomicBool::new(false));
+    let mut signals = Signals::new([SIGHUP])?;
+    let flag = reloading.clone();
+    thread::Builder::new()
+        .name(String::from("reload"))
+        .spawn(move || {
+            for signal in signals.forever() {
+                info!(signal, "Asked to reload the configuration");
+                flag.store(true, Ordering::SeqCst);
+                wake();
+            }
+        })?;
+    Ok(reloading)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{atomic::Ordering, mpsc},
+        time::Duration,
+    };
+
+    use signal_hook::{consts::SIGHUP, low_level::raise};
+
+    use super::on_reload_signal;
+
+    #[test]
+    fn every_signal() {
+        let (woken, wakes) = mpsc::channel();
+        let reloading = on_reload_signal(move || {
+            let _ = woken.send(());
+        })
+        .unwrap();
+        assert!(!reloading.load(Ordering::SeqCst));
+
+        for _ in 0..2 {
+            raise(SIGHUP).unwrap();
+            wakes.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert!(reloading.swap(false, Ordering::SeqCst));
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Stopping a process on its own terms when asked to, by SIGTERM or SIGINT
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    flag,
+    iterator::Signals,
+};
+use tracing::info;
+
+const STOP_SIGNALS: [i32; 2] = [SIGTERM, SIGINT];
+
+/// Watches for SIGTERM and SIGINT from now on. The first one sets the flag
+/// returned and calls @wake, for the process to notice and stop cleanly,
+/// while a second one terminates it right away
+pub fn on_stop_signal(wake: impl Fn() + Send + 'static) -> io::Result<Arc<AtomicBool>> {
+    let stopping = Arc::new(AtomicBool::new(false));
+    // ahead of the watcher, so that it only acts on the next signals
+    for signal in STOP_SIGNALS {
+        flag::register_conditional_shutdown(signal, 1, stopping.clone())?;
+    }
+    let mut signals = Signals::new(STOP_SIGNALS)?;
+    let flag = stopping.clone();
+    thread::Builder::new()
+        .name(String::from("signals"))
+        .spawn(move || {
+            for signal in signals.forever() {
+                info!(signal, "Asked to stop");
+                flag.store(true, Ordering::SeqCst);
+                wake();
+            }
+        })?;
+    Ok(stopping)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{atomic::Ordering, mpsc},
+        time::Duration,
+    };
+
+    use signal_hook::{consts::SIGINT, low_level::raise};
+
+    use super::on_stop_signal;
+
+    #[test]
+    fn first_signal() {
+        let (woken, wakes) = mpsc::channel();
+        let stopping = on_stop_signal(move || {
+            let _ = woken.send(());
+        })
+        .unwrap();
+        assert!(!stopping.load(Ordering::SeqCst));
+
+        raise(SIGINT).unwrap();
+        wakes.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(stopping.load(Ordering::SeqCst));
+    }
+}